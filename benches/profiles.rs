@@ -0,0 +1,66 @@
+//! Compares loading a large profile directory on a single thread, like iterating over
+//! `Profiles` does, with loading it on every CPU, like `Profiles::load_all` does when the config
+//! is initialized. Run it with `cargo bench --bench profiles`.
+
+use ideapad::Profile;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tuxvantage_core::profiles::Profiles;
+use tuxvantage_core::sandbox::Sandbox;
+
+/// About as many profiles as a synced collection of community profiles has.
+const PROFILES: usize = 200;
+const RUNS: u32 = 20;
+
+fn generate(dir: &Path) {
+    let template = serde_json::to_value(Profile::IDEAPAD_15IIL05).unwrap();
+    fs::create_dir_all(dir).expect("failed to create the profile directory");
+
+    for i in 0..PROFILES {
+        let mut profile = template.clone();
+        profile["name"] = format!("profile {}", i).into();
+
+        fs::write(
+            dir.join(format!("profile-{:03}.json", i)),
+            profile.to_string(),
+        )
+        .expect("failed to write a profile");
+    }
+}
+
+/// How long `load` takes on average, after a run to warm up the page cache.
+fn time(mut load: impl FnMut() -> usize) -> Duration {
+    assert_eq!(load(), PROFILES);
+    let start = Instant::now();
+
+    for _ in 0..RUNS {
+        assert_eq!(load(), PROFILES);
+    }
+
+    start.elapsed() / RUNS
+}
+
+fn main() {
+    let sandbox = Sandbox::new().expect("failed to create the sandbox");
+    let dir = sandbox.path("profiles");
+    generate(&dir);
+
+    let sequential = time(|| Profiles::at(&dir).unwrap().filter(Result::is_ok).count());
+    let parallel = time(|| {
+        Profiles::at(&dir)
+            .unwrap()
+            .load_all()
+            .into_iter()
+            .filter(Result::is_ok)
+            .count()
+    });
+
+    println!("{} profiles, averaged over {} runs", PROFILES, RUNS);
+    println!("sequential: {:?}", sequential);
+    println!(
+        "parallel:   {:?} ({:.1}x)",
+        parallel,
+        sequential.as_secs_f64() / parallel.as_secs_f64()
+    );
+}
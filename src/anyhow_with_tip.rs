@@ -1,8 +1,21 @@
+use crate::config::Tips;
+use crate::state::State;
+use parking_lot::RwLock;
 use std::fmt;
 use std::fmt::Display;
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+static TIPS: RwLock<Tips> = parking_lot::const_rwlock(Tips::Always);
+
+pub fn tips() -> Tips {
+    *TIPS.read()
+}
+
+pub fn set_tips(tips: Tips) {
+    *TIPS.write() = tips;
+}
+
 #[derive(Copy, Clone)]
 enum NullDisplay {}
 
@@ -12,23 +25,122 @@ impl Display for NullDisplay {
     }
 }
 
+/// A tip known ahead of time, with a stable identifier which doesn't change with its wording.
+#[derive(Debug, Copy, Clone)]
+pub struct StaticTip {
+    pub id: &'static str,
+    pub message: &'static str,
+}
+
+#[derive(Debug, Clone)]
+pub struct Tip {
+    /// Only tips which come from a [`StaticTip`] have an identifier.
+    pub id: Option<&'static str>,
+    pub message: String,
+}
+
+impl Display for Tip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+pub trait IntoTip {
+    fn into_tip(self) -> Tip;
+}
+
+impl IntoTip for StaticTip {
+    fn into_tip(self) -> Tip {
+        Tip {
+            id: Some(self.id),
+            message: self.message.to_string(),
+        }
+    }
+}
+
+impl IntoTip for Tip {
+    fn into_tip(self) -> Tip {
+        self
+    }
+}
+
+impl IntoTip for &str {
+    fn into_tip(self) -> Tip {
+        Tip {
+            id: None,
+            message: self.to_string(),
+        }
+    }
+}
+
+impl IntoTip for String {
+    fn into_tip(self) -> Tip {
+        Tip {
+            id: None,
+            message: self,
+        }
+    }
+}
+
+impl IntoTip for NullDisplay {
+    fn into_tip(self) -> Tip {
+        match self {}
+    }
+}
+
 #[derive(Debug)]
 pub struct Error {
     pub source: anyhow::Error,
-    pub tip: Option<String>,
+    pub tip: Option<Tip>,
+}
+
+/// Decides whether `tip` should be shown, according to the tips setting. When tips are only
+/// shown once, tips which are shown are recorded in the state file.
+pub fn filter_tip(tip: Tip, machine: bool) -> Option<Tip> {
+    match tips() {
+        Tips::Never => None,
+        Tips::Always => Some(tip),
+        Tips::Once if machine => Some(tip),
+        Tips::Once => {
+            let id = match tip.id {
+                Some(id) => id,
+                None => return Some(tip),
+            };
+            let result = State::mutate_then_dump(|state| state.shown_tips.insert(id.into()));
+
+            match result {
+                Ok(true) => Some(tip),
+                Ok(false) => {
+                    debug!("tip '{}' was already shown", id);
+                    None
+                }
+                Err(error) => {
+                    debug!("failed to record that tip '{}' was shown: {:#}", id, error);
+                    Some(tip)
+                }
+            }
+        }
+    }
+}
+
+impl Error {
+    /// Takes the tip of this error if it should be shown. See [`filter_tip`].
+    pub fn take_tip(&mut self, machine: bool) -> Option<Tip> {
+        filter_tip(self.tip.take()?, machine)
+    }
 }
 
 pub trait TippingAnyhowResultExt<T>: Sized {
-    fn maybe_tip(self, tip: Option<impl Display>) -> Result<T>;
-    fn tip(self, tip: impl Display) -> Result<T> {
-        self.maybe_tip(Some(tip.to_string()))
+    fn maybe_tip(self, tip: Option<impl IntoTip>) -> Result<T>;
+    fn tip(self, tip: impl IntoTip) -> Result<T> {
+        self.maybe_tip(Some(tip))
     }
     fn with_tip<F, C>(self, f: F) -> Result<T>
     where
         F: FnOnce() -> C,
-        C: Display,
+        C: IntoTip,
     {
-        self.maybe_tip(Some(f().to_string()))
+        self.maybe_tip(Some(f()))
     }
     fn no_tip(self) -> Result<T> {
         let tip: Option<NullDisplay> = None;
@@ -37,10 +149,10 @@ pub trait TippingAnyhowResultExt<T>: Sized {
 }
 
 impl<T> TippingAnyhowResultExt<T> for anyhow::Result<T> {
-    fn maybe_tip(self, tip: Option<impl Display>) -> Result<T> {
+    fn maybe_tip(self, tip: Option<impl IntoTip>) -> Result<T> {
         self.map_err(|e| Error {
             source: e,
-            tip: tip.map(|tip| tip.to_string()),
+            tip: tip.map(IntoTip::into_tip),
         })
     }
 }
@@ -0,0 +1,349 @@
+//! The operations of tuxvantage for other programs, such as panel applets, which would otherwise
+//! have to run `tuxvantage --machine always` and parse its output.
+//!
+//! Unlike the command line interface, nothing here reads global state: the configuration and the
+//! [`Hardware`] to act on are passed in explicitly, and the results are the same outputs the
+//! machine mode of the command line interface serializes. Every line which would be logged is
+//! printed to standard error, unless [`set_log_sink`] says otherwise.
+//!
+//! ```
+//! use tuxvantage_core::api::{self, TuxVantage};
+//! use tuxvantage_core::hardware::Fake;
+//!
+//! let mut hardware = Fake::new();
+//! api::enable_battery_conservation(&TuxVantage::DEFAULT, &mut hardware)?;
+//!
+//! assert!(api::snapshot(&mut hardware)?.battery_conservation);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::{app, log, project_paths};
+use anyhow::Context;
+use std::path::PathBuf;
+
+pub use crate::app::battery_conservation::MachineOutput as BatteryConservationOutput;
+pub use crate::app::rapid_charge::MachineOutput as RapidChargeOutput;
+pub use crate::app::status::MachineOutput as StatusOutput;
+pub use crate::app::system_performance::MachineOutput as SystemPerformanceOutput;
+pub use crate::config::{Config, TuxVantage};
+pub use crate::hardware::{Fake, Hardware, Ideapad, Snapshot};
+pub use ideapad::{Handler, Profile, SystemPerformanceMode};
+
+/// Loads the configuration from `config_dir`, or from where the command line interface would
+/// if `None`, creating the default one if there isn't any yet.
+///
+/// The directories tuxvantage uses can only be resolved once per process, so later calls use
+/// the config directory of the first one.
+pub fn load_config(config_dir: Option<PathBuf>) -> anyhow::Result<Config> {
+    project_paths::initialize(config_dir).context("failed to initialize project paths")?;
+    let (config, errors) = Config::get(TuxVantage::get)?;
+
+    for error in errors {
+        warn!("{:#}", error);
+    }
+
+    Ok(config)
+}
+
+/// The profile the command line interface would use, which is the default profile of `config`
+/// if it has one, or the one which expects the product name of this machine otherwise.
+pub fn profile(config: &Config) -> anyhow::Result<Profile> {
+    match config.default_profile() {
+        Some(profile) => profile,
+        None => config.profiles.detect(),
+    }
+}
+
+/// Every profile which can be used, built-in or not, without the disabled built-in ones.
+pub fn profiles(config: &Config) -> Vec<Profile> {
+    config
+        .profiles
+        .with_built_ins()
+        .map(|profile| profile.get().into_owned())
+        .collect()
+}
+
+/// The real hardware, sending the acpi calls of the [`profile`] of `config`.
+pub fn ideapad(config: &Config) -> anyhow::Result<Ideapad> {
+    let profile = profile(config).context("no profile for this machine could be found")?;
+
+    Ok(Ideapad::with_profile(
+        profile,
+        config.tuxvantage.drop_fallback(),
+    ))
+}
+
+/// The battery the regulator would act on, as selected by the `battery` section of `config`.
+#[cfg(feature = "regulate")]
+pub fn battery(config: &TuxVantage) -> anyhow::Result<Option<::battery::Battery>> {
+    config.battery().map(|(battery, _)| battery)
+}
+
+/// The settings tuxvantage controls.
+///
+/// ```
+/// use tuxvantage_core::api::{self, SystemPerformanceMode};
+/// use tuxvantage_core::hardware::Fake;
+///
+/// let snapshot = api::snapshot(&mut Fake::new())?;
+///
+/// assert!(!snapshot.battery_conservation);
+/// assert!(!snapshot.rapid_charge);
+/// assert!(matches!(
+///     snapshot.system_performance,
+///     SystemPerformanceMode::IntelligentCooling
+/// ));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn snapshot(hardware: &mut impl Hardware) -> anyhow::Result<Snapshot> {
+    Snapshot::read(hardware).map_err(|error| error.source)
+}
+
+/// What `tuxvantage status` prints, which also includes the native charge thresholds of the
+/// battery if there are any.
+pub fn status(hardware: &mut impl Hardware) -> anyhow::Result<StatusOutput> {
+    StatusOutput::read(hardware).map_err(|error| error.source)
+}
+
+/// Whether battery conservation mode is enabled.
+pub fn battery_conservation(
+    hardware: &mut impl Hardware,
+) -> anyhow::Result<BatteryConservationOutput> {
+    let enabled = read_conservation(hardware)?;
+
+    Ok(BatteryConservationOutput::Enabled { enabled })
+}
+
+/// Enables battery conservation mode, handling rapid charge being enabled as `config` says.
+pub fn enable_battery_conservation(
+    config: &TuxVantage,
+    hardware: &mut impl Hardware,
+) -> anyhow::Result<BatteryConservationOutput> {
+    let handler = config.handlers().battery_conservation();
+    let changed = set_toggle(
+        hardware,
+        read_conservation,
+        |hardware, on| write_conservation(hardware, on, handler),
+        true,
+    )?;
+
+    Ok(BatteryConservationOutput::Changed {
+        changed,
+        settle: None,
+    })
+}
+
+/// Disables battery conservation mode.
+pub fn disable_battery_conservation(
+    hardware: &mut impl Hardware,
+) -> anyhow::Result<BatteryConservationOutput> {
+    let changed = set_toggle(
+        hardware,
+        read_conservation,
+        |hardware, on| write_conservation(hardware, on, Handler::Switch),
+        false,
+    )?;
+
+    Ok(BatteryConservationOutput::Changed {
+        changed,
+        settle: None,
+    })
+}
+
+/// Whether rapid charging is enabled.
+pub fn rapid_charge(hardware: &mut impl Hardware) -> anyhow::Result<RapidChargeOutput> {
+    let enabled = read_rapid_charge(hardware)?;
+
+    Ok(RapidChargeOutput::Enabled { enabled })
+}
+
+/// Enables rapid charging, handling battery conservation mode being enabled as `config` says.
+///
+/// ```
+/// use tuxvantage_core::api::{self, RapidChargeOutput, TuxVantage};
+/// use tuxvantage_core::hardware::Fake;
+///
+/// let mut hardware = Fake::new();
+/// hardware.battery_conservation = true;
+/// let output = api::enable_rapid_charge(&TuxVantage::DEFAULT, &mut hardware)?;
+///
+/// assert!(matches!(output, RapidChargeOutput::Changed { changed: true, .. }));
+/// // switched off, since the default handler is `switch`
+/// assert!(!hardware.battery_conservation);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn enable_rapid_charge(
+    config: &TuxVantage,
+    hardware: &mut impl Hardware,
+) -> anyhow::Result<RapidChargeOutput> {
+    let handler = config.handlers().rapid_charging();
+    let changed = set_toggle(
+        hardware,
+        read_rapid_charge,
+        |hardware, on| write_rapid_charge(hardware, on, handler),
+        true,
+    )?;
+
+    Ok(RapidChargeOutput::Changed {
+        changed,
+        settle: None,
+    })
+}
+
+/// Disables rapid charging.
+pub fn disable_rapid_charge(hardware: &mut impl Hardware) -> anyhow::Result<RapidChargeOutput> {
+    let changed = set_toggle(
+        hardware,
+        read_rapid_charge,
+        |hardware, on| write_rapid_charge(hardware, on, Handler::Switch),
+        false,
+    )?;
+
+    Ok(RapidChargeOutput::Changed {
+        changed,
+        settle: None,
+    })
+}
+
+/// The system performance mode.
+pub fn system_performance(hardware: &mut impl Hardware) -> anyhow::Result<SystemPerformanceOutput> {
+    let system_performance_mode = read_performance_mode(hardware)?;
+
+    Ok(SystemPerformanceOutput::Get {
+        system_performance_mode,
+        fcmo: None,
+        spmo: None,
+        matched_bits: None,
+    })
+}
+
+/// Sets the system performance mode to `mode`.
+///
+/// ```
+/// use tuxvantage_core::api::{self, SystemPerformanceMode, SystemPerformanceOutput};
+/// use tuxvantage_core::hardware::Fake;
+///
+/// let mut hardware = Fake::new();
+/// let mode = SystemPerformanceMode::BatterySaving;
+///
+/// let output = api::set_system_performance(&mut hardware, mode)?;
+/// assert!(matches!(output, SystemPerformanceOutput::Changed { changed: true, .. }));
+///
+/// let output = api::set_system_performance(&mut hardware, mode)?;
+/// assert!(matches!(output, SystemPerformanceOutput::Changed { changed: false, .. }));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn set_system_performance(
+    hardware: &mut impl Hardware,
+    mode: SystemPerformanceMode,
+) -> anyhow::Result<SystemPerformanceOutput> {
+    let changed = read_performance_mode(hardware)? != mode;
+
+    if changed {
+        write_performance_mode(hardware, mode)?;
+    }
+
+    Ok(SystemPerformanceOutput::Changed {
+        changed,
+        settle: None,
+    })
+}
+
+/// Switches a toggle of `hardware` to `on` with `set` unless `get` says it already is, returning
+/// whether it changed.
+fn set_toggle<H: Hardware>(
+    hardware: &mut H,
+    get: impl FnOnce(&mut H) -> anyhow::Result<bool>,
+    set: impl FnOnce(&mut H, bool) -> anyhow::Result<()>,
+    on: bool,
+) -> anyhow::Result<bool> {
+    let changed = get(hardware)? != on;
+
+    if changed {
+        set(hardware, on)?;
+    }
+
+    Ok(changed)
+}
+
+// The accesses to the hardware below are shared with the command line interface, so that both
+// describe a failing acpi call the same way.
+
+pub(crate) fn read_conservation(hardware: &mut impl Hardware) -> anyhow::Result<bool> {
+    hardware
+        .conservation()
+        .context("failed to get battery conservation mode value")
+}
+
+pub(crate) fn write_conservation(
+    hardware: &mut impl Hardware,
+    on: bool,
+    handler: Handler,
+) -> anyhow::Result<()> {
+    hardware.set_conservation(on, handler).with_context(|| {
+        if on {
+            "failed to enable battery conservation"
+        } else {
+            "failed to disable battery conservation"
+        }
+    })
+}
+
+pub(crate) fn read_rapid_charge(hardware: &mut impl Hardware) -> anyhow::Result<bool> {
+    hardware
+        .rapid_charge()
+        .context("failed to get rapid charge value")
+}
+
+pub(crate) fn write_rapid_charge(
+    hardware: &mut impl Hardware,
+    on: bool,
+    handler: Handler,
+) -> anyhow::Result<()> {
+    hardware.set_rapid_charge(on, handler).with_context(|| {
+        if on {
+            "failed to enable rapid charging"
+        } else {
+            "failed to disable rapid charge"
+        }
+    })
+}
+
+pub(crate) fn read_performance_mode(
+    hardware: &mut impl Hardware,
+) -> anyhow::Result<SystemPerformanceMode> {
+    hardware
+        .performance_mode()
+        .context("failed to get system performance mode")
+}
+
+pub(crate) fn write_performance_mode(
+    hardware: &mut impl Hardware,
+    mode: SystemPerformanceMode,
+) -> anyhow::Result<()> {
+    hardware.set_performance_mode(mode).with_context(|| {
+        format!(
+            "failed to set the system performance mode to {}",
+            app::format_system_performance_mode_plain(mode)
+        )
+    })
+}
+
+/// Hands every line tuxvantage would log to `sink` instead of printing it to standard error. The
+/// lines have their colors stripped.
+///
+/// ```
+/// use std::sync::{Arc, Mutex};
+///
+/// let lines = Arc::new(Mutex::new(Vec::new()));
+/// let sink = Arc::clone(&lines);
+/// tuxvantage_core::api::set_log_sink(move |line| sink.lock().unwrap().push(line.to_string()));
+/// ```
+pub fn set_log_sink(sink: impl Fn(&str) + Send + Sync + 'static) {
+    log::sink::set(sink)
+}
+
+/// Prints the lines tuxvantage logs to standard error again.
+pub fn clear_log_sink() {
+    log::sink::clear()
+}
@@ -0,0 +1,221 @@
+use crate::app::IntoOptionMachineOutput;
+use crate::config::Trigger;
+use crate::hardware::{self, Hardware};
+use crate::history::{self, Initiator};
+use crate::state::State;
+use crate::{api, config, ec_cooldown, log, utils};
+use anyhow::Context;
+use ideapad::SystemPerformanceMode;
+use owo_colors::OwoColorize;
+
+#[derive(Serialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Applied,
+    Skipped,
+    Failed,
+}
+
+#[derive(Serialize)]
+pub struct Setting {
+    setting: &'static str,
+    outcome: Outcome,
+    reason: String,
+}
+
+impl Setting {
+    fn new(setting: &'static str, outcome: Outcome, reason: impl Into<String>) -> Self {
+        Self {
+            setting,
+            outcome,
+            reason: reason.into(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct MachineOutput {
+    /// Whether any setting was applied.
+    changed: bool,
+    settings: Vec<Setting>,
+}
+
+impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
+    fn into_option_machine_output(self) -> Option<MachineOutput> {
+        Some(self)
+    }
+}
+
+fn enabled_str(enabled: bool) -> &'static str {
+    if enabled {
+        "enabled"
+    } else {
+        "disabled"
+    }
+}
+
+/// Applies a boolean setting if it's desired and differs from the current value.
+fn apply_toggle<H: Hardware>(
+    hardware: &mut H,
+    setting: &'static str,
+    desired: Option<bool>,
+    current: impl FnOnce(&mut H) -> anyhow::Result<bool>,
+    set: impl FnOnce(&mut H, bool) -> anyhow::Result<()>,
+) -> Setting {
+    let desired = match desired {
+        Some(desired) => desired,
+        None => return Setting::new(setting, Outcome::Skipped, "no desired value"),
+    };
+
+    match current(hardware) {
+        Ok(current) if current == desired => Setting::new(
+            setting,
+            Outcome::Skipped,
+            format!("already {}", enabled_str(current)),
+        ),
+        Ok(current) => {
+            ec_cooldown::guard(setting);
+
+            match set(hardware, desired) {
+                Ok(()) => {
+                    history::record(
+                        setting,
+                        enabled_str(current),
+                        enabled_str(desired),
+                        Initiator::Apply,
+                    );
+                    Setting::new(setting, Outcome::Applied, enabled_str(desired))
+                }
+                Err(error) => Setting::new(
+                    setting,
+                    Outcome::Failed,
+                    utils::dedup_error_chain_for_humans(&error),
+                ),
+            }
+        }
+        Err(error) => Setting::new(
+            setting,
+            Outcome::Failed,
+            utils::dedup_error_chain_for_humans(&error),
+        ),
+    }
+}
+
+fn apply_system_performance(
+    hardware: &mut impl Hardware,
+    desired: Option<SystemPerformanceMode>,
+) -> Setting {
+    const SETTING: &str = "system_performance";
+
+    let desired = match desired {
+        Some(desired) => desired,
+        None => return Setting::new(SETTING, Outcome::Skipped, "no desired value"),
+    };
+    let name = super::format_system_performance_mode_plain(desired);
+
+    let current = api::read_performance_mode(hardware);
+
+    match current {
+        Ok(current) if current == desired => {
+            Setting::new(SETTING, Outcome::Skipped, format!("already {}", name))
+        }
+        Ok(current) => {
+            ec_cooldown::guard(SETTING);
+
+            match api::write_performance_mode(hardware, desired) {
+                Ok(()) => {
+                    history::record(
+                        SETTING,
+                        super::format_system_performance_mode_plain(current),
+                        name,
+                        Initiator::Apply,
+                    );
+                    Setting::new(SETTING, Outcome::Applied, name)
+                }
+                Err(error) => Setting::new(
+                    SETTING,
+                    Outcome::Failed,
+                    utils::dedup_error_chain_for_humans(&error),
+                ),
+            }
+        }
+        Err(error) => Setting::new(
+            SETTING,
+            Outcome::Failed,
+            utils::dedup_error_chain_for_humans(&error),
+        ),
+    }
+}
+
+pub fn apply(trigger: Option<Trigger>) -> anyhow::Result<MachineOutput> {
+    let config = config::read();
+    let machine = config.tuxvantage.machine();
+    let handlers = config.tuxvantage.handlers();
+
+    debug!("get remembered state");
+    let remembered = State::get()
+        .context("failed to get the remembered state")?
+        .desired;
+    let desired = config.tuxvantage.desired(trigger, remembered);
+
+    let mut hardware = hardware::get();
+    let settings = vec![
+        apply_toggle(
+            &mut hardware,
+            "battery_conservation",
+            desired.battery_conservation,
+            api::read_conservation,
+            |hardware, enable| {
+                api::write_conservation(hardware, enable, handlers.battery_conservation())
+            },
+        ),
+        apply_toggle(
+            &mut hardware,
+            "rapid_charge",
+            desired.rapid_charge,
+            api::read_rapid_charge,
+            |hardware, enable| api::write_rapid_charge(hardware, enable, handlers.rapid_charging()),
+        ),
+        apply_system_performance(&mut hardware, desired.system_performance),
+    ];
+
+    let changed = settings
+        .iter()
+        .any(|setting| setting.outcome == Outcome::Applied);
+
+    if !machine {
+        if changed {
+            info!("applied the desired state:");
+        } else {
+            info!("nothing was applied:");
+        }
+
+        {
+            let _guard = log::no_prologue::guard_for(log::Level::Info);
+
+            for setting in &settings {
+                let outcome = match setting.outcome {
+                    Outcome::Applied => "applied".bold().green().to_string(),
+                    Outcome::Skipped => "skipped".bold().to_string(),
+                    Outcome::Failed => "failed".bold().red().to_string(),
+                };
+
+                info!(
+                    "{}{} {} {}",
+                    super::tab(2),
+                    setting.setting.bold(),
+                    outcome,
+                    format_args!("({})", setting.reason).italic()
+                );
+            }
+        }
+
+        let failed = settings
+            .iter()
+            .filter(|setting| setting.outcome == Outcome::Failed)
+            .count();
+        anyhow::ensure!(failed == 0, "failed to apply {} setting(s)", failed);
+    }
+
+    Ok(MachineOutput { changed, settings })
+}
@@ -2,30 +2,506 @@ mod private {
     pub trait Sealed {}
 }
 
-use crate::app::IntoOptionMachineOutput;
+use crate::app::{IntoOptionMachineOutput, Porcelain, Readings, Settle};
+#[cfg(feature = "regulate")]
 use ::log::LevelFilter;
 use anyhow::{anyhow, Context};
+#[cfg(feature = "regulate")]
+use battery::units::energy::watt_hour;
+#[cfg(feature = "regulate")]
+use battery::units::power::watt;
+#[cfg(feature = "regulate")]
+use battery::Battery;
 use ideapad::Handler;
+use itertools::Itertools;
 use owo_colors::OwoColorize;
 use parking_lot::RwLockWriteGuard;
+#[cfg(feature = "regulate")]
 use signal_hook::consts::{SIGINT, SIGTERM};
+#[cfg(feature = "regulate")]
 use signal_hook::iterator::Signals;
-use std::path::Path;
+#[cfg(feature = "service-install")]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::{env, fs, thread};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{env, fs, io, process, thread};
 
 use crate::args::FromStrHandler;
-use crate::config::{BatteryConfig, BatteryLevel, BatteryMatches, CoolDown};
-use crate::ext::AnyhowResultExt;
+use crate::config::{
+    Aggregate, BatteryConfig, BatteryMatches, HandlerMode, HandlerResolution, HandlerSource,
+    Listen, OnExit,
+};
+use crate::ext::{self, AnyhowResultExt};
+use crate::hardware::{self, Hardware};
+use crate::history::{self, Initiator};
+use crate::init_system::InitSystem;
+#[cfg(feature = "regulate")]
+use crate::log::rotating::{self, RotatingFile};
 use crate::log::Level;
-use crate::utils::{DisplaySerializer, FromStrDeserializer};
-use crate::{anyhow_with_tip, config, context, log, utils, verbose};
+use crate::regulator::{
+    Action, EvaluatedBattery, Evaluation, Iteration, LastAction, StallDetector, Stats, Status,
+};
+#[cfg(feature = "regulate")]
+use crate::sd_notify;
+#[cfg(feature = "regulate")]
+use crate::simulation::{self, Sample};
+use crate::state::OwedRestore;
+#[cfg(feature = "regulate")]
+use crate::thresholds::{self, Mechanism, Thresholds};
+use crate::types::{BatteryLevel, HumanDuration, ServiceName};
+#[cfg(feature = "regulate")]
+use crate::upower;
+use crate::{
+    anyhow_with_tip, api, config, context, daemons, ec_cooldown, format, log, machine,
+    project_paths, state, utils, verbose, TippingAnyhowResultExt,
+};
+
+pub const REGULATOR_SERVICE: &str = "bcm.service";
+pub const REGULATOR_SERVICE_PATH: &str = "/etc/systemd/system/bcm.service";
+pub const HOLD_SERVICE: &str = "bch.service";
+pub const HOLD_SERVICE_PATH: &str = "/etc/systemd/system/bch.service";
+
+/// The version of the generated service units, bumped whenever the template changes in a way
+/// installed units should pick up. Units generated before they were marked with it are version 1.
+pub const UNIT_VERSION: u32 = 3;
+const UNIT_VERSION_MARKER: &str = "# generated by tuxvantage, unit version ";
+
+/// The deadband of `battery-conservation hold` if none is given.
+#[cfg(feature = "regulate")]
+pub const DEFAULT_DEADBAND: u8 = 2;
+
+/// What the regulator keeps the battery level at.
+#[cfg(feature = "regulate")]
+#[derive(Debug, Copy, Clone)]
+pub enum Target {
+    /// Enable battery conservation mode at or above the threshold, and disable it below, or only
+    /// at or below the lower threshold if there is one.
+    Threshold(BatteryLevel),
+
+    /// Emulate a charge limit at `at` by enabling battery conservation mode at or above it, and
+    /// only disabling it again once the battery level drops more than `deadband` below it.
+    Hold { at: BatteryLevel, deadband: u8 },
+}
+
+#[cfg(feature = "regulate")]
+impl Target {
+    fn level(self) -> BatteryLevel {
+        match self {
+            Self::Threshold(threshold) => threshold,
+            Self::Hold { at, .. } => at,
+        }
+    }
+
+    fn deadband(self) -> Option<u8> {
+        match self {
+            Self::Threshold(_) => None,
+            Self::Hold { deadband, .. } => Some(deadband),
+        }
+    }
+
+    /// Whether battery conservation mode should be enabled at `battery_level`, or `None` if it
+    /// should be left as is. `lower` is the lower threshold, which the deadband of `Hold` takes
+    /// the place of.
+    fn desired(self, level: u8, lower: Option<u8>, battery_level: u8) -> Option<bool> {
+        match self.deadband() {
+            _ if battery_level >= level => Some(true),
+            None => match lower {
+                Some(lower) if battery_level > lower => None,
+                _ => Some(false),
+            },
+            Some(deadband) if battery_level < level.saturating_sub(deadband) => Some(false),
+            Some(_) => None,
+        }
+    }
+
+    /// The default name, description and arguments of the service which regulates towards this
+    /// target.
+    #[cfg(feature = "service-install")]
+    fn service(self) -> (&'static str, &'static str, String) {
+        match self {
+            Self::Threshold(_) => (
+                REGULATOR_SERVICE,
+                "Regulate the battery",
+                "battery-conservation regulate".to_string(),
+            ),
+            Self::Hold { at, deadband } => (
+                HOLD_SERVICE,
+                "Hold the battery at a charge level",
+                format!(
+                    "battery-conservation hold --at {} --deadband {}",
+                    at.inner(),
+                    deadband
+                ),
+            ),
+        }
+    }
+}
+
+/// The version of an installed service unit, according to its version marker.
+pub fn unit_version(contents: &str) -> u32 {
+    contents
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(UNIT_VERSION_MARKER))
+        .and_then(|version| version.trim().parse().ok())
+        .unwrap_or(1)
+}
+
+/// The rest of the first line of `contents` which starts with `key`.
+fn unit_line<'a>(contents: &'a str, key: &str) -> Option<&'a str> {
+    contents
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(key))
+}
+
+/// The value of the first line of `contents` which starts with `key`, without quotes.
+fn unit_value<'a>(contents: &'a str, key: &str) -> Option<&'a str> {
+    unit_line(contents, key).map(|value| value.trim_matches('"'))
+}
+
+/// The executable and the arguments of the command line of a systemd or runit service. The
+/// executable may be quoted, in case its path has spaces.
+fn unit_command(contents: &str) -> Option<(&str, &str)> {
+    let command = unit_line(contents, "ExecStart=")
+        .or_else(|| unit_line(contents, "exec "))?
+        .trim();
+
+    match command.strip_prefix('"') {
+        Some(quoted) => quoted.split_once('"'),
+        None => Some(
+            command
+                .split_once(char::is_whitespace)
+                .unwrap_or((command, "")),
+        ),
+    }
+    .map(|(exe, arguments)| (exe, arguments.trim()))
+}
+
+/// The arguments an installed service runs tuxvantage with, such as
+/// `battery-conservation hold --at 70 --deadband 2`, whichever init system it is for.
+#[cfg(feature = "service-install")]
+fn unit_arguments(contents: &str) -> Option<String> {
+    let arguments = match unit_value(contents, "command_args=") {
+        Some(arguments) => arguments.to_string(),
+        None => unit_command(contents)?.1.split_whitespace().join(" "),
+    };
+
+    Some(arguments).filter(|arguments| !arguments.is_empty())
+}
+
+/// The executable an installed service runs, whichever init system it is for.
+pub fn unit_exe(contents: &str) -> Option<&str> {
+    unit_value(contents, "command=")
+        .or_else(|| unit_command(contents).map(|(exe, _)| exe))
+        .filter(|exe| !exe.is_empty())
+}
+
+#[cfg(feature = "service-install")]
+fn unit_description(contents: &str) -> Option<&str> {
+    unit_value(contents, "Description=").or_else(|| unit_value(contents, "description="))
+}
+
+/// Where and for which init system the regulator service is installed, which `--init-system`,
+/// `--service-name` and `--unit-dir` may have moved away from [`REGULATOR_SERVICE_PATH`]. This
+/// reads `.consistency.json` by itself, as the caller may be holding the config lock.
+fn installed_regulator() -> (InitSystem, PathBuf) {
+    let (init_system, unit) = match config::Consistency::get() {
+        Ok((consistency, _)) => (
+            consistency.regulator_init_system,
+            consistency.regulator_unit,
+        ),
+        Err(error) => {
+            debug!(
+                "failed to get where the regulator service is installed: {:#}",
+                error
+            );
+            (None, None)
+        }
+    };
+
+    (
+        init_system.unwrap_or(InitSystem::Systemd),
+        unit.unwrap_or_else(|| PathBuf::from(REGULATOR_SERVICE_PATH)),
+    )
+}
+
+/// Where the regulator service is installed.
+pub fn regulator_unit() -> PathBuf {
+    installed_regulator().1
+}
+
+/// The name the init system knows the regulator service by, such as `bcm.service`.
+pub fn regulator_service() -> String {
+    let (init_system, unit) = installed_regulator();
+
+    init_system.service_name(&unit)
+}
+
+/// The installed service units which were generated from an older template.
+pub fn outdated_units() -> Vec<PathBuf> {
+    [regulator_unit(), PathBuf::from(HOLD_SERVICE_PATH)]
+        .into_iter()
+        .filter(|path| match fs::read_to_string(path) {
+            Ok(contents) => unit_version(&contents) < UNIT_VERSION,
+            Err(error) => {
+                debug!("failed to read {}: {}", path.display(), error);
+                false
+            }
+        })
+        .collect()
+}
+
+#[cfg(feature = "service-install")]
+fn warn_if_units_outdated() {
+    for path in outdated_units() {
+        warn_with_tip!(
+            format_args!(
+                "{} was generated by an older version of tuxvantage",
+                path.display().bold()
+            ),
+            ext::REGULATOR_SERVICE_OUTDATED_TIP
+        );
+    }
+}
+
+/// How long systemd waits for the regulator to check in before restarting it: twice the longest
+/// it sleeps between evaluations, and a minute for slow acpi calls and firmware cooldowns.
+#[cfg(feature = "regulate")]
+fn watchdog_interval(cooldown: Duration, cooldown_jitter: Duration) -> Duration {
+    (cooldown + cooldown_jitter) * 2 + Duration::from_secs(60)
+}
+
+/// How often the regulator tells systemd that it is alive while it waits, well within the shortest
+/// [`watchdog_interval`].
+#[cfg(feature = "regulate")]
+const WATCHDOG_PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How much longer than the wait for a battery systemd is asked to wait for the regulator to
+/// start, for the batteries to be looked for again afterwards.
+#[cfg(feature = "regulate")]
+const START_TIMEOUT_MARGIN: Duration = Duration::from_secs(60);
+
+/// Sleeps for `duration`, telling systemd that the regulator is still alive every
+/// [`WATCHDOG_PING_INTERVAL`] meanwhile so that a long wait doesn't get it restarted.
+#[cfg(feature = "regulate")]
+fn sleep_alive(duration: Duration, notifier: Option<&sd_notify::Notifier>) {
+    let notifier = match notifier {
+        Some(notifier) => notifier,
+        None => return thread::sleep(duration),
+    };
+    let started = Instant::now();
+
+    loop {
+        notifier.watchdog();
+        let remaining = duration.saturating_sub(started.elapsed());
+
+        if remaining.is_zero() {
+            break;
+        }
+
+        thread::sleep(remaining.min(WATCHDOG_PING_INTERVAL));
+    }
+}
+
+/// The watchdog interval of a service which regulates with the cooldowns of the config.
+#[cfg(feature = "service-install")]
+fn configured_watchdog_interval(config: &config::Config) -> Duration {
+    let battery_config = config.tuxvantage.battery_config();
+    let cooldown = RegulatedBattery::all(&battery_config, true)
+        .iter()
+        .map(|regulated| regulated.cooldown)
+        .min()
+        .unwrap_or_else(|| battery_config.cooldown().0);
+
+    watchdog_interval(
+        longest_sleep(&battery_config, cooldown),
+        battery_config.cooldown_jitter().0,
+    )
+}
+
+/// Writes a service of `init_system` which runs tuxvantage with `arguments` to `path`, returning
+/// the executable it runs.
+#[cfg(feature = "service-install")]
+fn write_unit(
+    init_system: InitSystem,
+    path: &Path,
+    description: &str,
+    arguments: &str,
+    watchdog: Duration,
+) -> anyhow::Result<PathBuf> {
+    let tuxvantage_exe = env::current_exe().context("failed to get current path to executable")?;
+
+    debug!("path to tuxvantage exe is: {}", tuxvantage_exe.display());
+
+    let tuxvantage_exe_str = tuxvantage_exe.to_str().with_context(|| {
+        format!(
+            "path to tuxvantage ({}) contains invalid utf-8",
+            tuxvantage_exe.display().bold()
+        )
+    })?;
+
+    let environment = project_paths::env_overrides()
+        .into_iter()
+        .map(|(key, value)| init_system.environment_line(key, value))
+        .collect::<String>();
+
+    let contents = match init_system {
+        InitSystem::Systemd => format!(
+            include_str!("../../assets/bcm.service"),
+            version = UNIT_VERSION,
+            description = description,
+            watchdog = format!("{}s", watchdog.as_secs()),
+            environment = environment,
+            tuxvantage_exe = tuxvantage_exe_str,
+            arguments = arguments,
+        ),
+        InitSystem::Openrc => format!(
+            include_str!("../../assets/bcm.openrc"),
+            version = UNIT_VERSION,
+            description = description,
+            environment = environment,
+            tuxvantage_exe = tuxvantage_exe_str,
+            arguments = arguments,
+        ),
+        InitSystem::Runit => format!(
+            include_str!("../../assets/bcm.runit"),
+            version = UNIT_VERSION,
+            description = description,
+            environment = environment,
+            tuxvantage_exe = tuxvantage_exe_str,
+            arguments = arguments,
+        ),
+    };
+
+    debug!("contents to write are:\n {}", contents);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display().bold()))?;
+    }
+
+    fs::write(path, contents).context("failed to write content into file")?;
+
+    // the other init systems run the service as a script
+    if init_system != InitSystem::Systemd {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("failed to make {} executable", path.display().bold()))?;
+    }
+
+    Ok(tuxvantage_exe)
+}
+
+/// Remembers that the services at `units` are installed and run `tuxvantage_exe`, then reloads
+/// systemd if any of them is for it.
+#[cfg(feature = "service-install")]
+fn finish_install(
+    config: &mut config::Config,
+    tuxvantage_exe: PathBuf,
+    units: &[(InitSystem, PathBuf)],
+) -> anyhow::Result<()> {
+    debug!("setting regulator service installed bit to be true");
+    let services = units
+        .iter()
+        .map(|(init_system, path)| init_system.service_name(path))
+        .collect::<Vec<_>>();
+    config
+        .consistency
+        .mutate_then_dump(move |consistency| {
+            consistency.regulator_service_installed = true;
+            consistency.last_exe = Some(tuxvantage_exe);
+
+            for service in services {
+                if !consistency.installed_services.contains(&service) {
+                    consistency.installed_services.push(service);
+                }
+            }
+        })
+        .context("failed to dump consistency configuration")?;
+
+    // only systemd needs to be told about new services
+    if !units
+        .iter()
+        .any(|(init_system, _)| *init_system == InitSystem::Systemd)
+    {
+        return Ok(());
+    }
+
+    info!("reloading the systemd daemon");
+    let daemon_reload_successful = Command::new("systemctl")
+        .arg("daemon-reload")
+        .spawn()
+        .context("failed to reload the systemd daemon (command was systemctl daemon-reload)")?
+        .wait()
+        .context(
+            "failed to wait on reloading the systemd daemon (command was systemctl daemon-reload)",
+        )?
+        .success();
+
+    anyhow::ensure!(
+        daemon_reload_successful,
+        "reloading the systemd daemon wasn't successful"
+    );
+
+    Ok(())
+}
 
 #[derive(Serialize)]
 #[serde(untagged)]
 pub enum MachineOutput {
-    Enabled { enabled: bool },
-    Disabled { disabled: bool },
+    Enabled {
+        enabled: bool,
+    },
+    Disabled {
+        disabled: bool,
+    },
+    #[cfg(feature = "regulate")]
+    Regulated {
+        regulator: Status,
+    },
+    #[cfg(feature = "regulate")]
+    RegulatorStatus {
+        installed: bool,
+        active: Option<bool>,
+        enabled: Option<bool>,
+        running: bool,
+        last_action: Option<LastAction>,
+        regulator: Option<Status>,
+    },
+    #[cfg(feature = "regulate")]
+    Daemonized {
+        pid: u32,
+    },
+    #[cfg(feature = "regulate")]
+    Stopped {
+        pid: u32,
+        stopped: bool,
+    },
+    HandlerResolution {
+        handler_resolution: HandlerResolution,
+    },
+    Changed {
+        changed: bool,
+
+        /// The setting as read back with `--settle`, if it was given and the setting changed.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        settle: Option<Readings<bool>>,
+    },
+    Reinstalled {
+        reinstalled: Vec<PathBuf>,
+    },
+    Uninstalled {
+        uninstalled: bool,
+    },
+    #[cfg(feature = "regulate")]
+    Simulated {
+        simulated: Vec<SimulatedAction>,
+    },
+    #[cfg(feature = "regulate")]
+    NativeThresholds {
+        battery: String,
+        thresholds: Thresholds,
+        mechanism: Mechanism,
+    },
 }
 
 impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
@@ -34,331 +510,2757 @@ impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
     }
 }
 
+#[cfg(feature = "regulate")]
+fn status_porcelain(status: &Status) -> Vec<String> {
+    let level = |level: Option<u8>| level.map_or_else(String::new, |level| level.to_string());
+
+    let mut lines = vec![
+        super::pair("pid", status.pid),
+        super::pair("started", status.started),
+        super::pair("ran_for", status.ran_for),
+        super::pair("evaluations", status.stats.evaluations),
+        super::pair("enables", status.stats.enables),
+        super::pair("disables", status.stats.disables),
+        super::pair("acpi_errors", status.stats.acpi_errors),
+        super::pair("min_battery_level", level(status.stats.min_battery_level)),
+        super::pair("max_battery_level", level(status.stats.max_battery_level)),
+    ];
+
+    if let Some(evaluation) = &status.evaluation {
+        lines.push(super::pair("evaluated_at", evaluation.at));
+        lines.push(super::pair("conservation", evaluation.conservation));
+
+        for (index, battery) in evaluation.batteries.iter().enumerate() {
+            lines.push(super::pair(
+                &format!("battery_{}_matches", index),
+                &battery.matches,
+            ));
+            lines.push(super::pair(
+                &format!("battery_{}_level", index),
+                battery.level,
+            ));
+            lines.push(super::pair(
+                &format!("battery_{}_threshold", index),
+                battery.threshold,
+            ));
+        }
+    }
+
+    lines
+}
+
+impl Porcelain for MachineOutput {
+    fn porcelain(&self) -> Vec<String> {
+        match self {
+            Self::Enabled { enabled: true } | Self::Disabled { disabled: false } => {
+                vec!["enabled".to_string()]
+            }
+            Self::Enabled { enabled: false } | Self::Disabled { disabled: true } => {
+                vec!["disabled".to_string()]
+            }
+            #[cfg(feature = "regulate")]
+            Self::Regulated { regulator } => status_porcelain(regulator),
+            #[cfg(feature = "regulate")]
+            Self::RegulatorStatus {
+                installed,
+                active,
+                enabled,
+                running,
+                last_action,
+                regulator,
+            } => {
+                let unknown = |value: &Option<bool>| {
+                    value.map_or_else(String::new, |value| value.to_string())
+                };
+                let mut lines = vec![
+                    super::pair("installed", installed),
+                    super::pair("active", unknown(active)),
+                    super::pair("enabled", unknown(enabled)),
+                    super::pair("running", running),
+                    super::pair(
+                        "last_action",
+                        last_action.map_or("", |last_action| {
+                            if last_action.enabled {
+                                "enabled"
+                            } else {
+                                "disabled"
+                            }
+                        }),
+                    ),
+                    super::pair(
+                        "last_action_at",
+                        last_action
+                            .map_or_else(String::new, |last_action| last_action.at.to_string()),
+                    ),
+                ];
+                lines.extend(regulator.iter().flat_map(status_porcelain));
+                lines
+            }
+            #[cfg(feature = "regulate")]
+            Self::Daemonized { pid } => vec![super::pair("pid", pid)],
+            #[cfg(feature = "regulate")]
+            Self::Stopped { pid, stopped } => {
+                vec![super::pair("pid", pid), super::pair("stopped", stopped)]
+            }
+            Self::HandlerResolution { handler_resolution } => {
+                super::handler_resolution_porcelain(handler_resolution)
+            }
+            Self::Changed { changed, .. } => vec![super::pair("changed", changed)],
+            Self::Reinstalled { reinstalled } => reinstalled
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect(),
+            Self::Uninstalled { uninstalled } => vec![super::pair("uninstalled", uninstalled)],
+            #[cfg(feature = "regulate")]
+            Self::Simulated { simulated } => simulated
+                .iter()
+                .map(|action| format!("{} {}", action.sample.timestamp, action.decision.name()))
+                .collect(),
+            #[cfg(feature = "regulate")]
+            Self::NativeThresholds {
+                battery,
+                thresholds,
+                mechanism,
+            } => super::battery_threshold::porcelain(battery, *thresholds, *mechanism),
+        }
+    }
+}
+
 pub fn enabled() -> anyhow_with_tip::Result<MachineOutput> {
     debug!("get battery conservation enabled value");
-    let enabled = ideapad::battery_conservation::enabled(context::get())
-        .context("failed to get battery conservation mode value")
-        .maybe_acpi_call_tip()?;
+    let enabled = read_enabled(&mut hardware::get())?;
     let what = if enabled {
         "enabled".bold().green().to_string()
     } else {
         "disabled".bold().red().to_string()
     };
 
-    if !config::machine() {
+    let output = MachineOutput::Enabled { enabled };
+
+    if !config::machine() && !super::print_template("battery conservation enabled", &output) {
         info!("battery conservation is {}", what);
     }
 
-    Ok(MachineOutput::Enabled { enabled })
+    Ok(output)
 }
 
 pub fn disabled() -> anyhow_with_tip::Result<MachineOutput> {
     debug!("get battery conservation disabled value");
-    let disabled = ideapad::battery_conservation::disabled(context::get())
-        .context("failed to get battery conservation mode value")
-        .maybe_acpi_call_tip()?;
+    let disabled = !read_enabled(&mut hardware::get())?;
     let what = if disabled {
         "disabled".bold().green().to_string()
     } else {
         "enabled".bold().red().to_string()
     };
 
-    if !config::machine() {
+    let output = MachineOutput::Disabled { disabled };
+
+    if !config::machine() && !super::print_template("battery conservation disabled", &output) {
         info!("battery conservation is {}", what);
     }
 
-    Ok(MachineOutput::Disabled { disabled })
+    Ok(output)
 }
 
-pub fn enable(handler: Option<FromStrHandler>) -> anyhow_with_tip::Result<()> {
+pub fn enable(
+    handler: Option<(FromStrHandler, HandlerSource)>,
+    explain: bool,
+    remember: bool,
+    force: bool,
+    settle: Option<Settle>,
+) -> anyhow_with_tip::Result<Option<MachineOutput>> {
     let mut config = config::write();
 
     debug!("setup argument override for battery conservation handler from config");
-    config.tuxvantage.overrides.handlers.battery_conservation = handler.map(|handler| handler.0);
+    let switch_back = handler
+        .as_ref()
+        .map(|(handler, _)| handler.0.switches_back())
+        .unwrap_or(config.tuxvantage.overrides.switch_back);
+    config.tuxvantage.overrides.handler_flag = matches!(handler, Some((_, HandlerSource::Flag)));
+    config.tuxvantage.overrides.handlers.battery_conservation =
+        handler.map(|(handler, _)| handler.0.handler());
 
     let config = RwLockWriteGuard::downgrade(config);
-    let handler = config.tuxvantage.handlers().battery_conservation();
+    let resolution = config
+        .tuxvantage
+        .handler_resolution(HandlerMode::BatteryConservation);
     let machine = config.tuxvantage.machine();
 
+    if explain {
+        if !machine {
+            super::print_handler_resolution("battery conservation", &resolution);
+        }
+
+        return Ok(Some(MachineOutput::HandlerResolution {
+            handler_resolution: resolution,
+        }));
+    }
+
+    let handler = resolution.handler();
+    let mut hardware = hardware::get();
+    let already_enabled = read_enabled(&mut hardware)?;
+
+    if already_enabled {
+        if !machine {
+            info!("battery conservation is already enabled, nothing to do");
+        }
+
+        if remember {
+            remember_enabled()?;
+        }
+
+        return Ok(Some(MachineOutput::Changed {
+            changed: false,
+            settle: None,
+        }));
+    }
+
     if !machine {
         info!(
             "trying to enable battery conservation with handler {}",
             super::format_handler(handler)
         );
+    }
 
-        if let Handler::Ignore = handler {
-            warn!("use this handler with care; if rapid charge is already enabled this will strain the battery")
-        }
+    if let Handler::Ignore = handler {
+        warn_with_tip!(
+            "the ignore handler won't disable rapid charge if it is already enabled, which will strain the battery",
+            ext::IGNORE_HANDLER_TIP
+        );
     }
 
+    daemons::warn_if_regulated(force);
+
+    let rapid_charge_was_enabled = if switch_back {
+        debug!("switch-back handler, check if rapid charge is enabled");
+        api::read_rapid_charge(&mut hardware).maybe_acpi_call_tip()?
+    } else {
+        false
+    };
+
+    ec_cooldown::guard("battery_conservation");
+
     debug!("enable battery conservation with handler {:?}", handler);
-    context::get()
-        .controllers()
-        .battery_conservation()
-        .enable()
-        .handler(handler)
-        .now()
-        .context("failed to enable battery conservation")
-        .maybe_acpi_call_tip()?;
+    api::write_conservation(&mut hardware, true, handler).maybe_acpi_call_tip()?;
+    history::record(
+        "battery_conservation",
+        "disabled",
+        "enabled",
+        Initiator::Cli,
+    );
 
     if !machine {
         info!("enabled battery conservation");
     }
 
-    Ok(())
-}
+    if rapid_charge_was_enabled {
+        history::record("rapid_charge", "enabled", "disabled", Initiator::Cli);
+        state::owe_restore(OwedRestore::RapidCharge)?;
+
+        if !machine {
+            info!(
+                "rapid charging was switched off, run {} to switch it back on",
+                "tuxvantage battery-conservation disable --restore".bold()
+            );
+        }
+    }
 
-pub fn disable() -> anyhow_with_tip::Result<()> {
-    debug!("disable battery conservation");
-    ideapad::battery_conservation::disable(context::get())
-        .context("failed to disable battery conservation")
-        .maybe_acpi_call_tip()?;
+    // checked last, so that a strict failure doesn't lose the restore owed above
+    let settle = settle
+        .map(|settle| {
+            super::verify_settled(
+                "battery conservation",
+                true,
+                settle,
+                super::toggle_name,
+                || read_enabled(&mut hardware),
+            )
+        })
+        .transpose()?;
 
-    if !config::machine() {
-        info!("disabled battery conservation");
+    if remember {
+        remember_enabled()?;
     }
 
-    Ok(())
+    Ok(Some(MachineOutput::Changed {
+        changed: true,
+        settle,
+    }))
 }
 
-pub fn regulate(
-    threshold: BatteryLevel,
-    cooldown: CoolDown,
-    infallible: bool,
-    matches: Option<BatteryMatches>,
-    install: bool,
-) -> anyhow_with_tip::Result<()> {
-    let mut config = config::write();
+fn read_enabled(hardware: &mut impl Hardware) -> anyhow_with_tip::Result<bool> {
+    api::read_conservation(hardware).maybe_acpi_call_tip()
+}
 
-    if install {
-        if !utils::is_systemd()? {
-            return Err(anyhow::anyhow!(
-                "you can only install this service on systems which use the systemd init system"
-            )
-            .into());
+fn remember_enabled() -> anyhow::Result<()> {
+    state::remember(|desired| {
+        desired.battery_conservation = Some(true);
+
+        // battery conservation and rapid charging can't be enabled at the same time
+        if desired.rapid_charge == Some(true) {
+            desired.rapid_charge = Some(false);
         }
+    })
+}
 
-        // todo: allow changing of the service name
-        let path = Path::new("/etc/systemd/system/bcm.service");
-        info!(
-            "installing battery conservation regulator service to {}",
-            path.display().bold()
+pub fn disable(
+    remember: bool,
+    restore: bool,
+    force: bool,
+    settle: Option<Settle>,
+) -> anyhow_with_tip::Result<MachineOutput> {
+    let machine = config::machine();
+    let mut hardware = hardware::get();
+    let mut changed = read_enabled(&mut hardware)?;
+    let switched_off = changed;
+
+    if changed {
+        daemons::warn_if_regulated(force);
+        ec_cooldown::guard("battery_conservation");
+
+        debug!("disable battery conservation");
+        api::write_conservation(&mut hardware, false, Handler::Switch).maybe_acpi_call_tip()?;
+        history::record(
+            "battery_conservation",
+            "enabled",
+            "disabled",
+            Initiator::Cli,
         );
 
-        // todo: maybe we could put a consistency check here in some hidden configuration file?
-        let tuxvantage_exe =
-            env::current_exe().context("failed to get current path to executable")?;
+        if !machine {
+            info!("disabled battery conservation");
+        }
+    } else if !machine {
+        info!("battery conservation is already disabled, nothing to do");
+    }
 
-        debug!("path to tuxvantage exe is: {}", tuxvantage_exe.display());
+    if restore {
+        if state::is_restore_owed(OwedRestore::RapidCharge)? {
+            debug!("switch rapid charge back on");
+            ec_cooldown::guard("rapid_charge");
+            let handler = config::read().tuxvantage.handlers().rapid_charging();
+            hardware
+                .set_rapid_charge(true, handler)
+                .context("failed to switch rapid charging back on")
+                .maybe_acpi_call_tip()?;
+            history::record("rapid_charge", "disabled", "enabled", Initiator::Cli);
+            state::clear_owed_restore()?;
+            changed = true;
 
-        let tuxvantage_exe_str = tuxvantage_exe.to_str().with_context(|| {
-            format!(
-                "path to tuxvantage ({}) contains invalid utf-8",
-                tuxvantage_exe.display().bold()
-            )
-        })?;
+            if !machine {
+                info!("switched rapid charging back on");
+            }
+        } else if !machine {
+            info!("rapid charging wasn't switched off by battery conservation, nothing to restore");
+        }
+    }
 
-        let contents = format!(
-            include_str!("../../assets/bcm.service"),
-            tuxvantage_exe = tuxvantage_exe_str,
-        );
+    // checked after restoring, so that a strict failure doesn't skip it
+    let readings = settle
+        .filter(|_| switched_off)
+        .map(|settle| {
+            super::verify_settled(
+                "battery conservation",
+                false,
+                settle,
+                super::toggle_name,
+                || read_enabled(&mut hardware),
+            )
+        })
+        .transpose()?;
 
-        debug!("contents to write are:\n {}", contents);
+    if remember {
+        state::remember(|desired| desired.battery_conservation = Some(false))?;
+    }
 
-        fs::write(path, contents).context("failed to write content into file")?;
+    Ok(MachineOutput::Changed {
+        changed,
+        settle: readings,
+    })
+}
 
-        debug!("setting regulator service installed bit to be true");
-        config
-            .consistency
-            .mutate_then_dump(move |consistency| {
-                consistency.regulator_service_installed = true;
-                consistency.last_exe = Some(tuxvantage_exe);
-            })
-            .context("failed to dump consistency configuration")?;
+/// Randomly deviates `duration` by up to `jitter` in either direction.
+#[cfg(feature = "regulate")]
+fn jittered(duration: Duration, jitter: Duration) -> Duration {
+    let offset = jitter.as_secs_f64() * (fastrand::f64() * 2.0 - 1.0);
 
-        info!("reloading the systemd daemon");
-        let daemon_reload_successful = Command::new("systemctl")
-            .arg("daemon-reload")
-            .spawn()
-            .context("failed to reload the systemd daemon (command was systemctl daemon-reload)")?
-            .wait()
-            .context("failed to wait on reloading the systemd daemon (command was systemctl daemon-reload)")?
-            .success();
+    Duration::from_secs_f64((duration.as_secs_f64() + offset).max(0.0))
+}
 
-        if !daemon_reload_successful {
-            return Err(anyhow::anyhow!("reloading the systemd daemon wasn't successful").into());
-        }
+/// How long until `battery` reaches `upper` while charging or drops to `lower` while discharging
+/// at its current rate, or `None` if it isn't heading towards either of them or doesn't report a
+/// rate.
+#[cfg(feature = "regulate")]
+fn time_to_crossing(battery: &Battery, upper: u8, lower: u8) -> Option<Duration> {
+    let rate = battery.energy_rate().get::<watt>();
+    let energy = battery.energy().get::<watt_hour>();
+    let energy_full = battery.energy_full().get::<watt_hour>();
 
-        return Ok(());
+    if !(rate > 0.0 && energy_full > 0.0) {
+        return None;
     }
 
-    config.tuxvantage.overrides.battery = BatteryConfig {
-        threshold: Some(FromStrDeserializer(DisplaySerializer(threshold))),
-        cooldown: Some(FromStrDeserializer(DisplaySerializer(cooldown))),
-        infallible,
-        matches,
+    let target = match battery.state() {
+        battery::State::Charging if energy < energy_full * upper as f32 / 100.0 => upper,
+        battery::State::Discharging if energy > energy_full * lower as f32 / 100.0 => lower,
+        _ => return None,
     };
-    let battery_config = config.tuxvantage.battery_config();
-    let (battery, errors) = battery_config.get().context("failed to get battery")?;
-
-    if !errors.is_empty() {
-        warn!("errors occurred while retrieving battery information, see below");
-
-        for error in errors {
-            warn!("{}", error);
-        }
-    }
-
-    let mut battery = match battery {
-        Some(battery) => battery,
-        None => {
-            info!("failed to get battery information, here are the list of batteries that you could use");
-            let r#try = || -> anyhow::Result<()> {
-                let manager =
-                    battery::Manager::new().context("failed to create battery manager")?;
-                let batteries = manager
-                    .batteries()
-                    .context("failed to get list of batteries")?;
-                let mut first = true;
-
-                {
-                    let _guard = log::no_prologue::guard_for(Level::Info);
-                    for (index, battery) in batteries.enumerate() {
-                        let battery = match battery {
-                            Ok(battery) => battery,
-                            Err(error) => {
-                                warn!("skipping battery due to an error: {}", error);
-                                continue;
-                            }
-                        };
-
-                        if first {
-                            info!(
-                                "{} {}",
-                                format_args!("#{}", index).bold(),
-                                "(first)".italic()
-                            );
-                            first = false
-                        } else {
-                            info!("{}", format_args!("#{}", index).bold())
-                        }
+    let remaining = (energy - energy_full * target as f32 / 100.0).abs();
 
-                        info!(
-                            "{}{} {}",
-                            super::tab(2),
-                            "Vendor".bold(),
-                            battery.vendor().unwrap_or("N/A")
-                        );
-                        info!(
-                            "{}{} {}",
-                            super::tab(2),
-                            "Model".bold(),
-                            battery.model().unwrap_or("N/A")
-                        );
-                        info!(
-                            "{}{} {}",
-                            super::tab(2),
-                            "Serial Number".bold(),
-                            battery.serial_number().unwrap_or("N/A")
-                        );
-                    }
-                }
+    Duration::try_from_secs_f64((remaining / rate) as f64 * 3600.0).ok()
+}
+
+/// How long to sleep with `--adaptive-cooldown`: half of `until`, the estimated time until a
+/// threshold is crossed, if that is shorter than `cooldown`, kept between `floor` and `ceiling`.
+#[cfg(feature = "regulate")]
+fn adaptive_sleep(
+    cooldown: Duration,
+    until: Option<Duration>,
+    floor: Duration,
+    ceiling: Duration,
+) -> Duration {
+    until
+        .map_or(cooldown, |until| cooldown.min(until / 2))
+        .max(floor)
+        .min(ceiling)
+}
 
-                Ok(())
+/// The longest the regulator sleeps between evaluations with `cooldown`, before jitter.
+#[cfg(feature = "regulate")]
+fn longest_sleep(battery_config: &BatteryConfig, cooldown: Duration) -> Duration {
+    if battery_config.adaptive_cooldown {
+        adaptive_sleep(
+            cooldown,
+            None,
+            battery_config.adaptive_cooldown_floor().0,
+            battery_config.adaptive_cooldown_ceiling().0,
+        )
+    } else {
+        cooldown
+    }
+}
+
+/// Lists the batteries which could be used, for when the desired one can't be found.
+#[cfg(feature = "regulate")]
+fn list_batteries() -> anyhow::Result<()> {
+    let manager = battery::Manager::new().context("failed to create battery manager")?;
+    let batteries = manager
+        .batteries()
+        .context("failed to get list of batteries")?;
+    let mut first = true;
+
+    {
+        let _guard = log::no_prologue::guard_for(Level::Info);
+        for (index, battery) in batteries.enumerate() {
+            let battery = match battery {
+                Ok(battery) => battery,
+                Err(error) => {
+                    warn!("skipping battery due to an error: {}", error);
+                    continue;
+                }
             };
 
-            if let Err(error) = r#try() {
-                let error = error.context("failed to display list of batteries");
-                warn!("{:#}", error)
+            if first && BatteryMatches::First.matches(index, &battery) {
+                info!(
+                    "{} {}",
+                    format_args!("#{}", index).bold(),
+                    "(first)".italic()
+                );
+                first = false
+            } else {
+                info!("{}", format_args!("#{}", index).bold())
             }
 
-            return Err(anyhow!("failed to get battery information").into());
+            info!(
+                "{}{} {}",
+                super::tab(2),
+                "Vendor".bold(),
+                battery.vendor().unwrap_or("N/A")
+            );
+            info!(
+                "{}{} {}",
+                super::tab(2),
+                "Model".bold(),
+                battery.model().unwrap_or("N/A")
+            );
+            info!(
+                "{}{} {}",
+                super::tab(2),
+                "Serial Number".bold(),
+                battery.serial_number().unwrap_or("N/A")
+            );
+            info!("{}{} {}", super::tab(2), "State".bold(), battery.state());
+            info!(
+                "{}{} {:.1} Wh",
+                super::tab(2),
+                "Design Capacity".bold(),
+                battery.energy_full_design().get::<watt_hour>()
+            );
         }
-    };
+    }
 
-    let level_filter = if verbose::get() {
-        LevelFilter::Debug
-    } else {
-        LevelFilter::Info
-    };
+    Ok(())
+}
 
-    env_logger::Builder::new().filter_level(level_filter).init();
+/// A battery the regulator evaluates, with what it is evaluated against.
+#[cfg(feature = "regulate")]
+struct RegulatedBattery {
+    matches: BatteryMatches,
+    threshold: u8,
+    lower_threshold: Option<u8>,
+    cooldown: Duration,
+}
 
-    let cooldown = battery_config.cooldown().0;
-    let threshold = battery_config.threshold().inner();
-    let handler = config.tuxvantage.handlers().battery_conservation();
-    let mut battery_conservation = context::get().controllers().battery_conservation();
+#[cfg(feature = "regulate")]
+impl RegulatedBattery {
+    /// The targets of `battery_config` if `uses_targets` and it has any, otherwise just the
+    /// battery it matches.
+    fn all(battery_config: &BatteryConfig, uses_targets: bool) -> Vec<Self> {
+        let threshold = battery_config.threshold().inner();
+        let lower_threshold = battery_config.lower_threshold().map(BatteryLevel::inner);
+        let cooldown = battery_config.cooldown().0;
 
-    ::log::info!(
-        "the cooldown is {} second(s)",
-        cooldown.as_secs_f64().bold()
-    );
-    ::log::info!(
-        "the threshold for the battery is {}",
-        format_args!("{}%", threshold.bold())
-    );
+        match battery_config.targets.as_deref().filter(|_| uses_targets) {
+            Some(targets) => targets
+                .iter()
+                .map(|target| Self {
+                    matches: target.matches.0 .0.clone(),
+                    threshold: target.threshold.map_or(threshold, BatteryLevel::inner),
+                    lower_threshold: target
+                        .lower_threshold
+                        .map(BatteryLevel::inner)
+                        .or(lower_threshold),
+                    cooldown: target.cooldown.map_or(cooldown, |cooldown| cooldown.0),
+                })
+                .collect(),
+            None => vec![Self {
+                matches: battery_config.matches().into_owned(),
+                threshold,
+                lower_threshold,
+                cooldown,
+            }],
+        }
+    }
+}
 
-    let (signal_sender, signal_receiver) = crossbeam::channel::bounded(1);
-    let mut signals = Signals::new([SIGTERM, SIGINT])
-        .context("failed to register handler for application exits")?;
+/// Checks that `lower_threshold` is below `threshold`. The command line checks this as it is
+/// parsed, but the config and the targets in it can still get it wrong.
+#[cfg(feature = "regulate")]
+fn ensure_lower_threshold(threshold: u8, lower_threshold: Option<u8>) -> anyhow::Result<()> {
+    if let Some(lower_threshold) = lower_threshold {
+        anyhow::ensure!(
+            lower_threshold < threshold,
+            "the lower threshold ({}) must be less than the threshold ({})",
+            format::percent(lower_threshold).bold(),
+            format::percent(threshold).bold()
+        );
+    }
 
-    thread::spawn(move || {
-        for _ in signals.forever() {
-            signal_sender.send(()).unwrap();
-        }
-    });
+    Ok(())
+}
 
+/// Finds the batteries `matches` matches, waiting for one to show up if `infallible`.
+#[cfg(feature = "regulate")]
+fn find_batteries(
+    battery_config: &BatteryConfig,
+    matches: &BatteryMatches,
+    infallible: bool,
+    machine: bool,
+    notifier: Option<&sd_notify::Notifier>,
+) -> anyhow_with_tip::Result<Vec<Battery>> {
     loop {
-        let battery_level = (battery.state_of_charge().value * 100.0).round() as u8;
-        ::log::info!(
-            "current battery level is {}",
-            format_args!("{}%", battery_level.bold())
-        );
+        let error = match battery_config.require_all_matching(matches) {
+            Ok((batteries, errors)) => {
+                if !errors.is_empty() {
+                    warn!("errors occurred while retrieving battery information, see below");
 
-        let battery_level_ge_threshold = battery_level >= threshold;
-        ::log::debug!(
-            "battery level >= threshold = {}",
-            battery_level_ge_threshold
-        );
+                    for error in errors {
+                        warn!("{}", error);
+                    }
+                }
 
-        if battery_level >= threshold {
-            ::log::info!("battery level is greater than or equal to the provided threshold, enabling battery conservation mode");
-            battery_conservation
-                .enable()
-                .handler(handler)
-                .now()
-                .context("failed to enable battery conservation")
-                .maybe_acpi_call_tip()?
-        } else {
-            ::log::info!("battery level is less than the provided threshold, disabling battery conservation mode");
-            battery_conservation
-                .disable()
-                .context("failed to disable battery conservation")
-                .maybe_acpi_call_tip()?
-        }
+                return Ok(batteries);
+            }
+            Err(error) => error,
+        };
 
-        ::log::info!("refreshing battery");
-        if let Err(error) = battery.refresh() {
-            ::log::warn!("failed to refresh battery: {}", error)
+        if !error.is::<config::NoBatteryError>() {
+            return Err(error.context("failed to get battery").into());
         }
 
-        ::log::debug!("sleeping for {} second(s)", cooldown.as_secs_f64().bold());
-        let sleep_receiver = utils::sleep(cooldown);
+        if infallible {
+            let cooldown = battery_config.cooldown().0;
+            warn!(
+                "{:#}, checking again in {}",
+                error,
+                format::duration_human(cooldown)
+            );
 
-        crossbeam::select! {
-            recv(sleep_receiver) -> _ => continue,
-            recv(signal_receiver) -> _ => {
-                ::log::info!("received signal to terminate the current program, exiting cleanly");
-                ::log::info!("enabling battery conservation mode");
+            // the regulator hasn't started until a battery is found, which may take longer than
+            // systemd waits for a service to start
+            if let Some(notifier) = notifier {
+                notifier.extend_timeout(cooldown + START_TIMEOUT_MARGIN);
+            }
+
+            sleep_alive(cooldown, notifier);
+            continue;
+        }
+
+        if !machine {
+            info!("here are the list of batteries that you could use");
 
-                battery_conservation
-                    .enable()
-                    .handler(handler)
-                    .now()
-                    .context("failed to enable battery conservation")
-                    .maybe_acpi_call_tip()?;
-                break Ok(())
+            if let Err(error) = list_batteries() {
+                let error = error.context("failed to display list of batteries");
+                warn!("{:#}", error)
             }
         }
+
+        return Err(error).tip(ext::NO_BATTERY_TIP);
+    }
+}
+
+/// Whether battery conservation mode should be enabled given what each battery wants, which is
+/// the most conservative of them: enabled if any battery reached its threshold, disabled only if
+/// every battery wants it disabled, and left as is otherwise.
+#[cfg(feature = "regulate")]
+fn combine(desired: &[Option<bool>]) -> Option<bool> {
+    if desired.contains(&Some(true)) {
+        Some(true)
+    } else if desired.iter().all(|desired| *desired == Some(false)) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// What the regulator does with battery conservation mode after evaluating the batteries.
+#[cfg(feature = "regulate")]
+#[derive(Serialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    /// It is already in the desired state, or no battery wants it changed.
+    Keep,
+
+    /// It should be toggled, but the minimum interval between toggles hasn't passed yet.
+    Wait,
+    Enable,
+    Disable,
+}
+
+#[cfg(feature = "regulate")]
+impl Decision {
+    /// The name of the decision, as it is serialized.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Keep => "keep",
+            Self::Wait => "wait",
+            Self::Enable => "enable",
+            Self::Disable => "disable",
+        }
+    }
+}
+
+/// Decides what to do with battery conservation mode given what the batteries want, whether it is
+/// enabled, and how long ago it was last toggled. This doesn't touch the hardware, so that it can
+/// be driven by both real batteries and `--simulate`.
+#[cfg(feature = "regulate")]
+fn decide(
+    desired: Option<bool>,
+    enabled: bool,
+    since_last_toggle: Option<Duration>,
+    min_toggle_interval: Duration,
+) -> Decision {
+    match desired {
+        None => Decision::Keep,
+        Some(desired) if desired == enabled => Decision::Keep,
+        Some(_)
+            if since_last_toggle.map_or(false, |since_last_toggle| {
+                since_last_toggle < min_toggle_interval
+            }) =>
+        {
+            Decision::Wait
+        }
+        Some(true) => Decision::Enable,
+        Some(false) => Decision::Disable,
+    }
+}
+
+/// Holds back enabling battery conservation mode while on battery power, for `--only-on-ac`.
+/// `on_ac` is `None` if it isn't given.
+#[cfg(feature = "regulate")]
+fn only_on_ac(desired: Option<bool>, on_ac: Option<bool>) -> Option<bool> {
+    match (desired, on_ac) {
+        (Some(true), Some(false)) => None,
+        (desired, _) => desired,
+    }
+}
+
+/// The batteries a target of the regulator matched, which is more than one with `--matches all`.
+/// They are regulated together, going by the level their aggregate combines them into.
+#[cfg(feature = "regulate")]
+struct BatteryGroup {
+    batteries: Vec<Battery>,
+    aggregate: Aggregate,
+}
+
+#[cfg(feature = "regulate")]
+impl BatteryGroup {
+    fn levels(&self) -> impl Iterator<Item = u8> + '_ {
+        self.batteries
+            .iter()
+            .map(|battery| (battery.state_of_charge().value * 100.0).round() as u8)
+    }
+
+    fn level(&self) -> u8 {
+        self.aggregate.apply(self.levels())
+    }
+
+    /// Charging if any of the batteries is, otherwise discharging if any of them is, otherwise
+    /// the state of the first one.
+    fn state(&self) -> battery::State {
+        let states = || self.batteries.iter().map(Battery::state);
+
+        [battery::State::Charging, battery::State::Discharging]
+            .into_iter()
+            .find(|wanted| states().any(|state| state == *wanted))
+            .or_else(|| states().next())
+            .unwrap_or(battery::State::Unknown)
+    }
+
+    /// The level, along with the level of every battery if there is more than one, for logs.
+    fn describe(&self) -> String {
+        let level = format::percent(self.level());
+
+        if self.batteries.len() == 1 {
+            return level;
+        }
+
+        format!(
+            "{} ({} of {})",
+            level,
+            self.aggregate.name(),
+            self.levels().map(format::percent).join(", ")
+        )
+    }
+}
+
+/// Whether the machine is plugged into AC, going by the batteries if it has no power supply which
+/// tells.
+#[cfg(feature = "regulate")]
+fn on_ac(groups: &[BatteryGroup]) -> bool {
+    utils::on_ac().unwrap_or_else(|| {
+        groups
+            .iter()
+            .flat_map(|group| &group.batteries)
+            .any(|battery| {
+                !matches!(
+                    battery.state(),
+                    battery::State::Discharging | battery::State::Empty
+                )
+            })
+    })
+}
+
+/// Refreshes the batteries of `groups`, warning about the ones which couldn't be and returning
+/// why.
+#[cfg(feature = "regulate")]
+fn refresh_batteries(regulated: &[RegulatedBattery], groups: &mut [BatteryGroup]) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    for (regulated, group) in regulated.iter().zip(groups) {
+        for battery in &mut group.batteries {
+            if let Err(error) = battery.refresh() {
+                ::log::warn!(
+                    "failed to refresh a battery matching {}: {}",
+                    regulated.matches.bold(),
+                    error
+                );
+                errors.push(format!(
+                    "failed to refresh a battery matching {}: {}",
+                    regulated.matches, error
+                ));
+            }
+        }
+    }
+
+    errors
+}
+
+/// The level and state of every battery, to tell whether anything the regulator cares about
+/// changed.
+#[cfg(feature = "regulate")]
+fn battery_snapshot(groups: &[BatteryGroup]) -> Vec<(u8, battery::State)> {
+    groups
+        .iter()
+        .flat_map(|group| &group.batteries)
+        .map(|battery| {
+            (
+                (battery.state_of_charge().value * 100.0).round() as u8,
+                battery.state(),
+            )
+        })
+        .collect()
+}
+
+/// Sends a desktop notification that battery conservation mode was toggled, for `--notify`.
+/// Failing to is only a warning, as it shouldn't stop the regulator.
+#[cfg(feature = "regulate")]
+fn notify_toggled(enabled: bool, regulated: &[RegulatedBattery], groups: &[BatteryGroup]) {
+    let summary = if enabled {
+        "Battery conservation mode enabled"
+    } else {
+        "Battery conservation mode disabled"
+    };
+    let body = regulated
+        .iter()
+        .zip(groups)
+        .map(|(regulated, group)| {
+            format!(
+                "The battery matching {} is at {}.",
+                regulated.matches,
+                group.describe()
+            )
+        })
+        .join("\n");
+
+    if let Err(error) = utils::notify(summary, &body) {
+        ::log::warn!("failed to send a desktop notification: {:#}", error)
+    }
+}
+
+/// A decision the regulator would have made for a sample of `--simulate`.
+#[cfg(feature = "regulate")]
+#[derive(Serialize)]
+pub struct SimulatedAction {
+    #[serde(flatten)]
+    sample: Sample,
+    decision: Decision,
+}
+
+/// Runs the decisions of the regulator against the samples in `path` instead of a real battery,
+/// without sleeping or touching the hardware. A sample is only evaluated once the cooldown since
+/// the last evaluated one has passed, and battery conservation mode is assumed to be disabled at
+/// the start. The cooldown jitter is ignored so that the result is the same every time.
+#[cfg(feature = "regulate")]
+fn simulate_regulator(
+    target: Target,
+    battery_config: &BatteryConfig,
+    path: &Path,
+    machine: bool,
+) -> anyhow::Result<MachineOutput> {
+    let samples = simulation::read(path)?;
+    let threshold = battery_config.threshold().inner();
+    let lower_threshold = battery_config.lower_threshold().map(BatteryLevel::inner);
+
+    if target.deadband().is_none() {
+        ensure_lower_threshold(threshold, lower_threshold)?;
+    }
+
+    let cooldown = battery_config.cooldown().0;
+    let min_toggle_interval = battery_config.min_toggle_interval().0;
+    let mut enabled = false;
+    let mut last_toggle: Option<Duration> = None;
+    let mut next_evaluation = Duration::ZERO;
+    let mut stall_detector = StallDetector::new(
+        battery_config.stall_margin(),
+        battery_config.stall_patience(),
+    );
+    let mut simulated = Vec::new();
+
+    for sample in samples {
+        let timestamp = Duration::from_secs(sample.timestamp);
+
+        if timestamp < next_evaluation {
+            continue;
+        }
+
+        next_evaluation = timestamp + cooldown;
+
+        // the samples only tell whether the battery is charging, which stands in for AC
+        let desired = only_on_ac(
+            target.desired(threshold, lower_threshold, sample.level),
+            battery_config.only_on_ac.then(|| sample.charging),
+        );
+        let since_last_toggle = last_toggle.map(|last_toggle| timestamp - last_toggle);
+        let decision = decide(desired, enabled, since_last_toggle, min_toggle_interval);
+
+        if stall_detector.observe(threshold, sample.level, sample.charging, enabled) {
+            warn_with_tip!(
+                format_args!(
+                    "at {}, the battery has stayed at {} while charging with battery conservation \
+                     mode enabled, which may be capping it below the threshold of {}",
+                    sample.timestamp.bold(),
+                    format::percent(sample.level).bold(),
+                    format::percent(threshold).bold()
+                ),
+                ext::CONSERVATION_MODE_CAP_TIP
+            );
+        }
+
+        match decision {
+            Decision::Keep => continue,
+            Decision::Wait => {}
+            Decision::Enable | Decision::Disable => {
+                enabled = decision == Decision::Enable;
+                last_toggle = Some(timestamp);
+            }
+        }
+
+        if !machine {
+            let action = match decision {
+                Decision::Enable => "enable battery conservation mode",
+                Decision::Disable => "disable battery conservation mode",
+                _ => "wait for the minimum interval between toggles to pass",
+            };
+
+            info!(
+                "at {}, the battery is {} at {}, so the regulator would {}",
+                sample.timestamp.bold(),
+                if sample.charging {
+                    "charging"
+                } else {
+                    "discharging"
+                },
+                format::percent(sample.level).bold(),
+                action
+            );
+        }
+
+        simulated.push(SimulatedAction { sample, decision });
+    }
+
+    if !machine {
+        let toggles = simulated
+            .iter()
+            .filter(|action| matches!(action.decision, Decision::Enable | Decision::Disable))
+            .count();
+        info!(
+            "the regulator would have toggled battery conservation mode {} time(s)",
+            toggles.bold()
+        );
+    }
+
+    Ok(MachineOutput::Simulated { simulated })
+}
+
+/// Installs the service which regulates towards `target` for `init_system` as `service_name` in
+/// `unit_dir`. Each of them defaults to what fits the init system this machine runs.
+#[cfg(feature = "service-install")]
+fn install_service(
+    config: &mut config::Config,
+    target: Target,
+    init_system: Option<InitSystem>,
+    service_name: Option<ServiceName>,
+    unit_dir: Option<PathBuf>,
+) -> anyhow_with_tip::Result<()> {
+    let init_system = match init_system {
+        Some(init_system) => init_system,
+        None => InitSystem::detect().no_tip()?,
+    };
+
+    if init_system == InitSystem::Systemd && !utils::is_systemd()? {
+        return Err(anyhow::anyhow!(
+            "you can only install this service on systems which use the systemd init system"
+        )
+        .into());
+    }
+
+    let (name, description, arguments) = target.service();
+    let unit_dir = unit_dir.unwrap_or_else(|| PathBuf::from(init_system.default_dir()));
+
+    if !unit_dir.is_absolute() {
+        return Err(anyhow!(
+            "the unit directory {} must be an absolute path",
+            unit_dir.display().bold()
+        )
+        .into());
+    }
+
+    let path = init_system.unit_path(
+        &unit_dir,
+        service_name
+            .as_ref()
+            .map_or(name, |service_name| service_name.as_str()),
+    );
+    info!(
+        "installing battery conservation regulator {} service to {}",
+        init_system.name(),
+        path.display().bold()
+    );
+
+    let watchdog = configured_watchdog_interval(config);
+    let tuxvantage_exe = write_unit(init_system, &path, description, &arguments, watchdog)?;
+    finish_install(config, tuxvantage_exe, &[(init_system, path.clone())])?;
+    info!(
+        "enable and start it by running {} as root",
+        init_system.enable_hint(&path).bold()
+    );
+
+    if let Target::Threshold(_) = target {
+        // remembered so that the other commands find the regulator service where it was put
+        let regulator_init_system = (init_system != InitSystem::Systemd).then(|| init_system);
+        let regulator_unit = (path != Path::new(REGULATOR_SERVICE_PATH)).then(|| path);
+        config
+            .consistency
+            .mutate_then_dump(|consistency| {
+                consistency.regulator_init_system = regulator_init_system;
+                consistency.regulator_unit = regulator_unit;
+            })
+            .context("failed to dump consistency configuration")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(all(feature = "regulate", not(feature = "service-install")))]
+fn install_service(
+    _: &mut config::Config,
+    _: Target,
+    _: Option<InitSystem>,
+    _: Option<ServiceName>,
+    _: Option<PathBuf>,
+) -> anyhow_with_tip::Result<()> {
+    Err(anyhow::Error::new(ext::FeatureDisabledError {
+        what: "install services",
+        feature: "service-install",
+    })
+    .into())
+}
+
+/// What `battery-conservation regulate` and `hold` were given on the command line, each of which
+/// is left to the config file if `None` or `false`.
+#[cfg(feature = "regulate")]
+pub struct RegulateOptions {
+    pub lower_threshold: Option<BatteryLevel>,
+    pub cooldown: HumanDuration,
+    pub cooldown_jitter: Option<HumanDuration>,
+    pub min_toggle_interval: Option<HumanDuration>,
+    pub adaptive_cooldown: bool,
+    pub adaptive_cooldown_floor: Option<HumanDuration>,
+    pub adaptive_cooldown_ceiling: Option<HumanDuration>,
+    pub infallible: bool,
+    pub only_on_ac: bool,
+    pub notify: bool,
+    pub on_exit: Option<OnExit>,
+    pub listen: Option<Listen>,
+    pub matches: Option<BatteryMatches>,
+    pub aggregate: Option<Aggregate>,
+    pub install: bool,
+    pub init_system: Option<InitSystem>,
+    pub service_name: Option<ServiceName>,
+    pub unit_dir: Option<PathBuf>,
+    pub force: bool,
+    pub daemonize: bool,
+    pub log_file: Option<PathBuf>,
+    pub log_file_size: Option<u64>,
+    pub log_file_count: Option<u32>,
+    pub simulate: Option<PathBuf>,
+    pub prefer_native_thresholds: bool,
+}
+
+#[cfg(feature = "regulate")]
+impl RegulateOptions {
+    /// Only the cooldown, which always has a value on the command line, leaving everything else
+    /// to the config file.
+    pub fn new(cooldown: HumanDuration) -> Self {
+        Self {
+            lower_threshold: None,
+            cooldown,
+            cooldown_jitter: None,
+            min_toggle_interval: None,
+            adaptive_cooldown: false,
+            adaptive_cooldown_floor: None,
+            adaptive_cooldown_ceiling: None,
+            infallible: false,
+            only_on_ac: false,
+            notify: false,
+            on_exit: None,
+            listen: None,
+            matches: None,
+            aggregate: None,
+            install: false,
+            init_system: None,
+            service_name: None,
+            unit_dir: None,
+            force: false,
+            daemonize: false,
+            log_file: None,
+            log_file_size: None,
+            log_file_count: None,
+            simulate: None,
+            prefer_native_thresholds: false,
+        }
+    }
+
+    /// Rejects the flags which can't be used together. `configured_log_file` is whether the config
+    /// file has a log file, which `--daemonize` can use instead of `--log-file`.
+    fn check(&self, configured_log_file: bool) -> anyhow::Result<()> {
+        if self.simulate.is_some() && (self.install || self.daemonize) {
+            return Err(anyhow!(
+                "{} can't be used with {} or {}",
+                "--simulate".bold(),
+                "--install".bold(),
+                "--daemonize".bold()
+            ));
+        }
+
+        if self.daemonize && self.install {
+            return Err(anyhow!(
+                "{} can't be used with {}",
+                "--daemonize".bold(),
+                "--install".bold()
+            ));
+        }
+
+        if self.daemonize && self.log_file.is_none() && !configured_log_file {
+            return Err(anyhow!(
+                "{} requires {} or {} in the config",
+                "--daemonize".bold(),
+                "--log-file".bold(),
+                "log_file".bold()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The overrides of the battery section of the config for regulating towards `target`.
+    fn battery_config(&self, target: Target) -> BatteryConfig {
+        BatteryConfig {
+            threshold: Some(target.level()),
+            lower_threshold: self.lower_threshold,
+            cooldown: Some(self.cooldown),
+            cooldown_jitter: self.cooldown_jitter,
+            min_toggle_interval: self.min_toggle_interval,
+            adaptive_cooldown: self.adaptive_cooldown,
+            adaptive_cooldown_floor: self.adaptive_cooldown_floor,
+            adaptive_cooldown_ceiling: self.adaptive_cooldown_ceiling,
+            infallible: self.infallible,
+            only_on_ac: self.only_on_ac,
+            notify: self.notify,
+            on_exit: self.on_exit,
+            listen: self.listen,
+            matches: self.matches.clone(),
+            aggregate: self.aggregate,
+            log_file: self.log_file.clone(),
+            log_file_size: self.log_file_size,
+            log_file_count: self.log_file_count,
+            stall_margin: None,
+            stall_patience: None,
+            targets: None,
+        }
+    }
+}
+
+#[cfg(feature = "regulate")]
+pub fn regulate(
+    target: Target,
+    options: RegulateOptions,
+) -> anyhow_with_tip::Result<Option<MachineOutput>> {
+    let mut config = config::write();
+    options
+        .check(config.tuxvantage.battery_config().log_file.is_some())
+        .no_tip()?;
+
+    if options.prefer_native_thresholds {
+        match thresholds::Battery::detect() {
+            Ok(battery) => {
+                let handler = config.tuxvantage.handlers().battery_conservation();
+
+                let lower_threshold = options
+                    .lower_threshold
+                    .or_else(|| config.tuxvantage.battery_config().lower_threshold());
+
+                return regulate_natively(
+                    &battery,
+                    target,
+                    lower_threshold,
+                    handler,
+                    config.tuxvantage.machine(),
+                )
+                .map(Some);
+            }
+            Err(error) => warn!(
+                "{}, so battery conservation mode will be toggled instead",
+                error
+            ),
+        }
+    }
+
+    if let Target::Hold { .. } = target {
+        warn!(
+            "holding the battery level is emulated by toggling battery conservation mode, which \
+             happens more often than when regulating"
+        );
+    }
+
+    if options.install {
+        install_service(
+            &mut config,
+            target,
+            options.init_system,
+            options.service_name,
+            options.unit_dir,
+        )?;
+
+        return Ok(None);
+    }
+
+    // batteries given on the command line replace the targets of the config
+    let uses_targets = options.matches.is_none();
+    config.tuxvantage.overrides.battery = options.battery_config(target);
+    let battery_config = config.tuxvantage.battery_config();
+
+    if battery_config.adaptive_cooldown
+        && battery_config.adaptive_cooldown_floor() > battery_config.adaptive_cooldown_ceiling()
+    {
+        return Err(anyhow!(
+            "the adaptive cooldown floor of {} is longer than its ceiling of {}",
+            battery_config.adaptive_cooldown_floor().bold(),
+            battery_config.adaptive_cooldown_ceiling().bold()
+        )
+        .into());
+    }
+
+    if let Some(simulate) = &options.simulate {
+        return simulate_regulator(
+            target,
+            &battery_config,
+            simulate,
+            config.tuxvantage.machine().get(),
+        )
+        .map(Some)
+        .no_tip();
+    }
+
+    #[cfg(feature = "service-install")]
+    warn_if_units_outdated();
+
+    if !options.force {
+        if let Some(regulator) =
+            daemons::regulator().filter(|regulator| !regulator.is_current_process())
+        {
+            return Err(anyhow!(
+                "{} is already regulating battery conservation mode",
+                regulator
+            ))
+            .tip(regulator.stop_tip());
+        }
+    }
+
+    let regulated = RegulatedBattery::all(&battery_config, uses_targets);
+
+    if target.deadband().is_none() {
+        for regulated in &regulated {
+            ensure_lower_threshold(regulated.threshold, regulated.lower_threshold).with_context(
+                || {
+                    format!(
+                        "the battery matching {} can't be regulated",
+                        regulated.matches
+                    )
+                },
+            )?;
+        }
+    }
+
+    let cooldown = regulated
+        .iter()
+        .map(|regulated| regulated.cooldown)
+        .min()
+        .unwrap_or_else(|| battery_config.cooldown().0);
+    let cooldown_jitter = battery_config.cooldown_jitter().0;
+
+    // set up before looking for the batteries, which may take a while with `infallible`
+    let notifier = sd_notify::Notifier::from_env();
+
+    if let Some(notifier) = &notifier {
+        notifier.watchdog_interval(watchdog_interval(
+            longest_sleep(&battery_config, cooldown),
+            cooldown_jitter,
+        ));
+    }
+
+    let mut batteries = Vec::new();
+
+    for regulated in &regulated {
+        batteries.push(BatteryGroup {
+            batteries: find_batteries(
+                &battery_config,
+                &regulated.matches,
+                options.infallible,
+                config.tuxvantage.machine().get(),
+                notifier.as_ref(),
+            )?,
+            aggregate: battery_config.aggregate(),
+        });
+    }
+
+    let daemonized = match &battery_config.log_file {
+        Some(log_file) if options.daemonize => {
+            match daemonize(log_file, config.tuxvantage.machine().get())? {
+                Some(pid) => return Ok(Some(MachineOutput::Daemonized { pid })),
+                None => true,
+            }
+        }
+        _ => false,
+    };
+
+    context::spawn_deferred_receiver_thread();
+    init_logger(&battery_config, daemonized)?;
+    log_settings(
+        target,
+        &battery_config,
+        &regulated,
+        cooldown,
+        cooldown_jitter,
+    );
+
+    let on_exit = battery_config.on_exit();
+    let mut hardware = hardware::get();
+    let initially_enabled = read_enabled(&mut hardware)?;
+
+    ::log::info!(
+        "battery conservation mode is {}, and on exit it will be {}",
+        if initially_enabled {
+            "enabled"
+        } else {
+            "disabled"
+        },
+        match on_exit {
+            OnExit::Keep => "left as is",
+            OnExit::Enable => "enabled",
+            OnExit::Disable => "disabled",
+            OnExit::Restore => "restored",
+        }
+    );
+
+    let mut regulator = Regulator {
+        target,
+        handler: config.tuxvantage.handlers().battery_conservation(),
+        hardware,
+        stall_detectors: vec![
+            StallDetector::new(
+                battery_config.stall_margin(),
+                battery_config.stall_patience()
+            );
+            regulated.len()
+        ],
+        signals: exit_signals()?,
+        upower: listen(battery_config.listen),
+        battery_config,
+        regulated,
+        batteries,
+        cooldown,
+        cooldown_jitter,
+        notifier,
+        on_exit,
+        initially_enabled,
+        ready: false,
+        started: SystemTime::now(),
+        stats: Stats::default(),
+        evaluation: None,
+        last_toggle: None,
+    };
+    let result = regulator.run();
+
+    let status = Status::new(regulator.started, regulator.stats, regulator.evaluation);
+    ::log::info!("regulator summary: {}", status.summary());
+
+    if let Err(error) = Status::remove() {
+        ::log::warn!("{:#}", error)
+    }
+
+    if daemonized {
+        if let Err(error) = fs::remove_file(project_paths::regulator_pid()) {
+            ::log::warn!("failed to remove {}: {}", "regulator.pid".bold(), error)
+        }
+    }
+
+    result?;
+
+    Ok(Some(MachineOutput::Regulated { regulator: status }))
+}
+
+/// Forks the regulator into the background with its output going to `log_file`, returning the pid
+/// of the child to the parent and `None` to the child.
+#[cfg(feature = "regulate")]
+fn daemonize(log_file: &Path, machine: bool) -> anyhow::Result<Option<u32>> {
+    let file = rotating::open(log_file)
+        .with_context(|| format!("failed to open {}", log_file.display().bold()))?;
+
+    if let Some(pid) = daemons::daemonize(&file)? {
+        if !machine {
+            info!(
+                "the regulator is running in the background with pid {}",
+                pid.bold()
+            );
+        }
+
+        return Ok(Some(pid));
+    }
+
+    project_paths::ensure_runtime_dir()?;
+    utils::write_atomic(project_paths::regulator_pid(), process::id().to_string())
+        .with_context(|| format!("failed to write to {}", "regulator.pid".bold()))?;
+
+    Ok(None)
+}
+
+/// Sets up the logger of the regulator, which also writes to the log file of `battery_config` if
+/// it has one. A `daemonized` regulator only writes to the log file.
+#[cfg(feature = "regulate")]
+fn init_logger(battery_config: &BatteryConfig, daemonized: bool) -> anyhow::Result<()> {
+    let level_filter = if verbose::get() {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    };
+    let mut logger = env_logger::Builder::new();
+    logger.filter_level(level_filter);
+
+    if let Some(log_file) = &battery_config.log_file {
+        let file = RotatingFile::open(
+            log_file.clone(),
+            battery_config.log_file_size(),
+            battery_config.log_file_count(),
+        )
+        .with_context(|| format!("failed to open {}", log_file.display().bold()))?;
+
+        // a daemonized regulator already writes everything else to the log file
+        let file = if daemonized {
+            file
+        } else {
+            file.echo_to_stderr()
+        };
+
+        logger.target(env_logger::Target::Pipe(Box::new(file)));
+    } else {
+        logger.target(env_logger::Target::Pipe(Box::new(log::sink::Stderr)));
+    }
+
+    logger.init();
+
+    Ok(())
+}
+
+/// Logs how the regulator is about to evaluate the batteries.
+#[cfg(feature = "regulate")]
+fn log_settings(
+    target: Target,
+    battery_config: &BatteryConfig,
+    regulated: &[RegulatedBattery],
+    cooldown: Duration,
+    cooldown_jitter: Duration,
+) {
+    ::log::info!(
+        "the cooldown is {}",
+        format::duration_human(cooldown).bold()
+    );
+    ::log::info!(
+        "the cooldown jitter is {}",
+        format::duration_human(cooldown_jitter).bold()
+    );
+    ::log::info!(
+        "the minimum interval between toggles is {}",
+        format::duration_human(battery_config.min_toggle_interval().0).bold()
+    );
+    if battery_config.adaptive_cooldown {
+        ::log::info!(
+            "the cooldown adapts to the charge rate, between {} and {}",
+            format::duration_human(battery_config.adaptive_cooldown_floor().0).bold(),
+            format::duration_human(battery_config.adaptive_cooldown_ceiling().0).bold()
+        );
+    }
+    for regulated in regulated {
+        match target.deadband() {
+            None => match regulated.lower_threshold {
+                None => ::log::info!(
+                    "the threshold for the battery matching {} is {}",
+                    regulated.matches.bold(),
+                    format::percent(regulated.threshold).bold()
+                ),
+                Some(lower_threshold) => ::log::info!(
+                    "the threshold for the battery matching {} is {}, and battery conservation \
+                     mode is disabled again at or below {}",
+                    regulated.matches.bold(),
+                    format::percent(regulated.threshold).bold(),
+                    format::percent(lower_threshold).bold()
+                ),
+            },
+            Some(deadband) => ::log::info!(
+                "holding the battery matching {} at {}, with a deadband of {}",
+                regulated.matches.bold(),
+                format::percent(regulated.threshold).bold(),
+                format::percent(deadband).bold()
+            ),
+        }
+    }
+}
+
+/// Receives once SIGTERM or SIGINT arrives.
+#[cfg(feature = "regulate")]
+fn exit_signals() -> anyhow::Result<crossbeam::channel::Receiver<()>> {
+    let (signal_sender, signal_receiver) = crossbeam::channel::bounded(1);
+    let mut signals = Signals::new([SIGTERM, SIGINT])
+        .context("failed to register handler for application exits")?;
+
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            signal_sender.send(()).unwrap();
+        }
+    });
+
+    Ok(signal_receiver)
+}
+
+/// Follows the changes UPower reports if `listen` says to, falling back to only checking the
+/// batteries after each cooldown if it can't.
+#[cfg(feature = "regulate")]
+fn listen(listen: Option<Listen>) -> Option<upower::Monitor> {
+    match listen {
+        Some(Listen::Upower) => match upower::Monitor::spawn() {
+            Ok(monitor) => {
+                ::log::info!("listening for changes to the batteries reported by UPower");
+                Some(monitor)
+            }
+            Err(error) => {
+                ::log::warn!(
+                    "{:#}, so the batteries are only checked after each cooldown",
+                    error
+                );
+                None
+            }
+        },
+        None => None,
+    }
+}
+
+/// Why the regulator stopped waiting for the next evaluation.
+#[cfg(feature = "regulate")]
+enum Wake {
+    /// The cooldown passed, or the batteries changed before it did.
+    Evaluate,
+
+    /// The regulator was told to exit.
+    Exit,
+}
+
+/// The state of a running regulator, which evaluates the batteries until it is told to exit.
+#[cfg(feature = "regulate")]
+struct Regulator {
+    target: Target,
+    battery_config: BatteryConfig,
+    regulated: Vec<RegulatedBattery>,
+    batteries: Vec<BatteryGroup>,
+    hardware: Box<dyn Hardware>,
+    handler: Handler,
+    cooldown: Duration,
+    cooldown_jitter: Duration,
+    notifier: Option<sd_notify::Notifier>,
+    upower: Option<upower::Monitor>,
+    signals: crossbeam::channel::Receiver<()>,
+    on_exit: OnExit,
+    initially_enabled: bool,
+
+    /// Whether the service manager has been told that the regulator is ready.
+    ready: bool,
+    started: SystemTime,
+    stats: Stats,
+    evaluation: Option<Evaluation>,
+    last_toggle: Option<Instant>,
+    stall_detectors: Vec<StallDetector>,
+}
+
+#[cfg(feature = "regulate")]
+impl Regulator {
+    /// Evaluates the batteries after each cooldown until the regulator is told to exit, or
+    /// battery conservation mode can't be read or toggled.
+    fn run(&mut self) -> anyhow_with_tip::Result<()> {
+        loop {
+            let seen = self.evaluate()?;
+
+            match self.wait(self.sleep(), &seen) {
+                Wake::Evaluate => continue,
+                Wake::Exit => return self.exit(),
+            }
+        }
+    }
+
+    /// Evaluates the batteries once, toggling battery conservation mode if they want it to be,
+    /// then refreshes them. Returns the level and state they were refreshed to, to tell whether
+    /// they changed while waiting.
+    fn evaluate(&mut self) -> anyhow_with_tip::Result<Vec<(u8, battery::State)>> {
+        let desired = self.desired();
+        ::log::debug!("desired battery conservation mode state = {:?}", desired);
+
+        let enabled = self.conservation()?;
+        let since_last_toggle = self.last_toggle.map(|last_toggle| last_toggle.elapsed());
+
+        if let Some(notifier) = &self.notifier {
+            // the batteries and battery conservation mode have been read, so the regulator works
+            if !self.ready {
+                notifier.ready();
+                self.ready = true;
+            }
+
+            notifier.watchdog();
+        }
+
+        self.warn_if_stalled(enabled);
+
+        let decision = decide(
+            desired,
+            enabled,
+            since_last_toggle,
+            self.battery_config.min_toggle_interval().0,
+        );
+        self.apply(decision, since_last_toggle)?;
+        self.record(decision, enabled);
+
+        ::log::info!("refreshing the batteries");
+        let refresh_errors = refresh_batteries(&self.regulated, &mut self.batteries);
+        let seen = battery_snapshot(&self.batteries);
+
+        if let (true, Some(evaluation)) = (machine::enabled(), &self.evaluation) {
+            let action = match decision {
+                Decision::Enable => Action::Enabled,
+                Decision::Disable => Action::Disabled,
+                Decision::Keep | Decision::Wait => Action::Noop,
+            };
+
+            if let Err(error) = Iteration::new(evaluation, action, refresh_errors).print() {
+                ::log::warn!("{:#}", error)
+            }
+        }
+
+        Ok(seen)
+    }
+
+    /// What battery conservation mode should be given what each battery wants, held back while
+    /// on battery power with `only_on_ac`.
+    fn desired(&mut self) -> Option<bool> {
+        let desired = self
+            .regulated
+            .iter()
+            .zip(&self.batteries)
+            .map(|(regulated, group)| {
+                let battery_level = group.level();
+                let desired = self.target.desired(
+                    regulated.threshold,
+                    regulated.lower_threshold,
+                    battery_level,
+                );
+                ::log::info!(
+                    "the battery matching {} is at {} against {}, so battery conservation mode should be {}",
+                    regulated.matches.bold(),
+                    group.describe().bold(),
+                    format::percent(regulated.threshold).bold(),
+                    match desired {
+                        Some(true) => "enabled",
+                        Some(false) => "disabled",
+                        None => "left as is",
+                    }
+                );
+                self.stats.evaluated(battery_level);
+
+                desired
+            })
+            .collect::<Vec<_>>();
+        let desired = combine(&desired);
+
+        if !self.battery_config.only_on_ac {
+            return desired;
+        }
+
+        let on_ac = on_ac(&self.batteries);
+        let gated = only_on_ac(desired, Some(on_ac));
+
+        if gated != desired {
+            ::log::info!(
+                "the machine is on battery power, so battery conservation mode won't be enabled \
+                 until AC is plugged back in"
+            );
+        } else {
+            ::log::info!(
+                "the machine is {}",
+                if on_ac { "on AC" } else { "on battery power" }
+            );
+        }
+
+        gated
+    }
+
+    /// Warns about the batteries which seem to be capped below their threshold by battery
+    /// conservation mode.
+    fn warn_if_stalled(&mut self, enabled: bool) {
+        for ((regulated, group), stall_detector) in self
+            .regulated
+            .iter()
+            .zip(&self.batteries)
+            .zip(&mut self.stall_detectors)
+        {
+            let battery_level = group.level();
+            let plugged_in = !matches!(
+                group.state(),
+                battery::State::Discharging | battery::State::Empty
+            );
+
+            if stall_detector.observe(regulated.threshold, battery_level, plugged_in, enabled) {
+                warn_with_tip!(
+                    format_args!(
+                        "the battery matching {} has stayed at {} while plugged in with battery \
+                         conservation mode enabled, which may be capping it below the threshold \
+                         of {}",
+                        regulated.matches.bold(),
+                        format::percent(battery_level).bold(),
+                        format::percent(regulated.threshold).bold()
+                    ),
+                    ext::CONSERVATION_MODE_CAP_TIP
+                );
+            }
+        }
+    }
+
+    /// Carries out `decision`, waiting for the firmware first if it is a toggle.
+    fn apply(
+        &mut self,
+        decision: Decision,
+        since_last_toggle: Option<Duration>,
+    ) -> anyhow_with_tip::Result<()> {
+        // the firmware may ignore a toggle made too soon after the last change, which could also
+        // have been made by another invocation
+        if matches!(decision, Decision::Enable | Decision::Disable) {
+            if let Some(remaining) = ec_cooldown::remaining("battery_conservation") {
+                ::log::info!(
+                    "battery conservation mode was changed recently, waiting {} for the firmware \
+                     to accept changes again",
+                    format::duration_human(remaining).bold()
+                );
+                sleep_alive(remaining, self.notifier.as_ref());
+            }
+        }
+
+        match decision {
+            Decision::Keep => {
+                ::log::debug!("battery conservation mode is already in the desired state")
+            }
+            Decision::Wait => ::log::info!(
+                "battery conservation mode was toggled {} ago, waiting until the minimum interval between toggles passes",
+                format::duration_human(since_last_toggle.unwrap_or_default()).bold()
+            ),
+            Decision::Enable => {
+                ::log::info!("a battery level is greater than or equal to its threshold, enabling battery conservation mode");
+                self.toggle(true)?;
+            }
+            Decision::Disable => {
+                ::log::info!("every battery level is less than its threshold, disabling battery conservation mode");
+                self.toggle(false)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Toggles battery conservation mode to `enable`, recording that the regulator did.
+    fn toggle(&mut self, enable: bool) -> anyhow_with_tip::Result<()> {
+        self.set_conservation(enable)?;
+        self.stats.toggled(enable);
+
+        let (old, new) = if enable {
+            ("disabled", "enabled")
+        } else {
+            ("enabled", "disabled")
+        };
+        history::record("battery_conservation", old, new, Initiator::Regulate);
+        self.last_toggle = Some(Instant::now());
+
+        if self.battery_config.notify {
+            notify_toggled(enable, &self.regulated, &self.batteries);
+        }
+
+        Ok(())
+    }
+
+    /// Whether battery conservation mode is enabled, counting the acpi call failing.
+    fn conservation(&mut self) -> anyhow_with_tip::Result<bool> {
+        let result = read_enabled(&mut self.hardware);
+
+        if result.is_err() {
+            self.stats.acpi_errors += 1;
+        }
+
+        result
+    }
+
+    /// Enables or disables battery conservation mode, counting the acpi call failing.
+    fn set_conservation(&mut self, enable: bool) -> anyhow_with_tip::Result<()> {
+        let result =
+            api::write_conservation(&mut self.hardware, enable, self.handler).maybe_acpi_call_tip();
+
+        if result.is_err() {
+            self.stats.acpi_errors += 1;
+        }
+
+        result
+    }
+
+    /// Records the latest evaluation, for `--status` and status bars.
+    fn record(&mut self, decision: Decision, enabled: bool) {
+        let evaluation = Evaluation::new(
+            match decision {
+                Decision::Enable => true,
+                Decision::Disable => false,
+                Decision::Keep | Decision::Wait => enabled,
+            },
+            self.regulated
+                .iter()
+                .zip(&self.batteries)
+                .map(|(regulated, group)| EvaluatedBattery {
+                    matches: regulated.matches.to_string(),
+                    level: group.level(),
+                    threshold: regulated.threshold,
+                })
+                .collect(),
+        );
+
+        if let Err(error) = Status::new(self.started, self.stats, Some(evaluation.clone())).dump() {
+            ::log::warn!("failed to record the status of the regulator: {:#}", error)
+        }
+
+        self.evaluation = Some(evaluation);
+    }
+
+    /// How long to wait until the next evaluation, which adapts to how soon a threshold is crossed
+    /// with `adaptive_cooldown`.
+    fn sleep(&self) -> Duration {
+        if !self.battery_config.adaptive_cooldown {
+            let sleep = jittered(self.cooldown, self.cooldown_jitter);
+            ::log::debug!("sleeping for {}", format::duration_human(sleep).bold());
+
+            return sleep;
+        }
+
+        let until = self
+            .regulated
+            .iter()
+            .zip(&self.batteries)
+            .flat_map(|(regulated, group)| {
+                let lower = match self.target.deadband() {
+                    None => regulated.lower_threshold.unwrap_or(regulated.threshold),
+                    Some(deadband) => regulated.threshold.saturating_sub(deadband),
+                };
+
+                group.batteries.iter().filter_map(move |battery| {
+                    time_to_crossing(battery, regulated.threshold, lower)
+                })
+            })
+            .min();
+        let sleep = jittered(
+            adaptive_sleep(
+                self.cooldown,
+                until,
+                self.battery_config.adaptive_cooldown_floor().0,
+                self.battery_config.adaptive_cooldown_ceiling().0,
+            ),
+            self.cooldown_jitter,
+        );
+
+        match until {
+            Some(until) => ::log::info!(
+                "a threshold is about {} away at the current rate, sleeping for {}",
+                format::duration_human(until).bold(),
+                format::duration_human(sleep).bold()
+            ),
+            None => ::log::info!(
+                "no threshold is being approached, sleeping for {}",
+                format::duration_human(sleep).bold()
+            ),
+        }
+
+        sleep
+    }
+
+    /// Waits `sleep`, or less if UPower reports that the batteries changed from `seen` or the
+    /// regulator is told to exit.
+    fn wait(&mut self, sleep: Duration, seen: &[(u8, battery::State)]) -> Wake {
+        let sleep = utils::sleep(sleep);
+        let signals = self.signals.clone();
+
+        loop {
+            let changes = self
+                .upower
+                .as_ref()
+                .map_or_else(crossbeam::channel::never, |upower| upower.changes().clone());
+
+            crossbeam::select! {
+                recv(sleep) -> _ => return Wake::Evaluate,
+                recv(changes) -> change => {
+                    if change.is_err() {
+                        ::log::warn!(
+                            "UPower stopped reporting changes, so the batteries are only checked \
+                             after each cooldown from now on"
+                        );
+                        self.upower = None;
+                        continue;
+                    }
+
+                    refresh_batteries(&self.regulated, &mut self.batteries);
+
+                    // UPower also reports changes to the charge rate and such, which don't matter
+                    if battery_snapshot(&self.batteries) == seen {
+                        ::log::debug!("UPower reported a change, but no battery level or state changed");
+                        continue;
+                    }
+
+                    ::log::info!("UPower reported a change to the batteries, checking them early");
+                    return Wake::Evaluate;
+                },
+                recv(signals) -> _ => return Wake::Exit,
+            }
+        }
+    }
+
+    /// Leaves battery conservation mode as `on_exit` says.
+    fn exit(&mut self) -> anyhow_with_tip::Result<()> {
+        ::log::info!("received signal to terminate the current program, exiting cleanly");
+
+        if let Some(notifier) = &self.notifier {
+            notifier.stopping();
+        }
+
+        let enable = match self.on_exit.conservation(self.initially_enabled) {
+            Some(enable) => enable,
+            None => {
+                ::log::info!("leaving battery conservation mode as is");
+                return Ok(());
+            }
+        };
+
+        match (self.on_exit, enable) {
+            (OnExit::Restore, true) => ::log::info!(
+                "restoring battery conservation mode, which was enabled when the regulator started"
+            ),
+            (OnExit::Restore, false) => ::log::info!(
+                "restoring battery conservation mode, which was disabled when the regulator \
+                 started"
+            ),
+            (_, true) => ::log::info!("enabling battery conservation mode"),
+            (_, false) => ::log::info!("disabling battery conservation mode"),
+        }
+
+        self.set_conservation(enable)
+    }
+}
+
+/// Stands in for regulating on models with native charge thresholds, setting them once so that
+/// the firmware holds the battery level by itself. Battery conservation mode is disabled, since it
+/// would otherwise stop charging well below the end threshold.
+#[cfg(feature = "regulate")]
+fn regulate_natively(
+    battery: &thresholds::Battery,
+    target: Target,
+    lower_threshold: Option<BatteryLevel>,
+    handler: Handler,
+    machine: config::Machine,
+) -> anyhow_with_tip::Result<MachineOutput> {
+    let end = target.level();
+    // the lower threshold is where charging starts again, like the start threshold
+    let start = match (target.deadband(), lower_threshold) {
+        (None, Some(lower_threshold)) => lower_threshold,
+        (deadband, _) => BatteryLevel::new(
+            end.inner()
+                .saturating_sub(deadband.unwrap_or(DEFAULT_DEADBAND))
+                .max(1),
+        )
+        .expect("a lower battery level is within bounds"),
+    };
+    let thresholds = Thresholds::new(start, end).no_tip()?;
+    let old = battery.get().no_tip()?;
+
+    if old != thresholds {
+        battery.set(thresholds)?;
+        history::record("charge_thresholds", old, thresholds, Initiator::Regulate);
+    }
+
+    let mut hardware = hardware::get();
+    let enabled = read_enabled(&mut hardware)?;
+
+    if enabled {
+        ec_cooldown::guard("battery_conservation");
+        api::write_conservation(&mut hardware, false, handler).maybe_acpi_call_tip()?;
+        history::record(
+            "battery_conservation",
+            "enabled",
+            "disabled",
+            Initiator::Regulate,
+        );
+    }
+
+    if !machine {
+        info!(
+            "{} now stops charging at {} and starts again below {}, so the firmware holds the \
+             battery level without a regulator",
+            battery.name.bold(),
+            end.bold(),
+            start.bold()
+        );
+
+        if enabled {
+            info!("disabled battery conservation mode, which would stop charging earlier");
+        }
+    }
+
+    Ok(MachineOutput::NativeThresholds {
+        battery: battery.name.clone(),
+        thresholds,
+        mechanism: Mechanism::NativeThresholds,
+    })
+}
+
+/// Shows the status of the regulator running in the background, if there is one.
+/// Regenerates the installed service units from the current template, keeping the arguments
+/// they were installed with.
+#[cfg(feature = "service-install")]
+pub fn reinstall() -> anyhow::Result<MachineOutput> {
+    let mut config = config::write();
+    let mut reinstalled = Vec::new();
+    let mut units = Vec::new();
+    let mut last_exe = None;
+
+    for (init_system, path) in [
+        installed_regulator(),
+        (InitSystem::Systemd, PathBuf::from(HOLD_SERVICE_PATH)),
+    ] {
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                debug!("{} isn't installed", path.display());
+                continue;
+            }
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("failed to read {}", path.display().bold()))
+            }
+        };
+        anyhow::ensure!(
+            init_system != InitSystem::Systemd || utils::is_systemd()?,
+            "you can only reinstall the systemd services on systems which use the systemd init \
+             system"
+        );
+
+        let arguments = unit_arguments(&contents).with_context(|| {
+            format!(
+                "the command of {} has no arguments to keep",
+                path.display().bold()
+            )
+        })?;
+        let description = unit_description(&contents).unwrap_or("Regulate the battery");
+
+        info!(
+            "regenerating {} from version {} to version {}, keeping the arguments {}",
+            path.display().bold(),
+            unit_version(&contents),
+            UNIT_VERSION,
+            arguments.bold()
+        );
+        last_exe = Some(write_unit(
+            init_system,
+            &path,
+            description,
+            &arguments,
+            configured_watchdog_interval(&config),
+        )?);
+        units.push((init_system, path.clone()));
+        reinstalled.push(path);
+    }
+
+    let last_exe = last_exe.with_context(|| {
+        format!(
+            "neither {} nor {} is installed",
+            regulator_service().bold(),
+            HOLD_SERVICE.bold()
+        )
+    })?;
+    finish_install(&mut config, last_exe, &units)?;
+
+    Ok(MachineOutput::Reinstalled { reinstalled })
+}
+
+#[cfg(all(feature = "regulate", not(feature = "service-install")))]
+pub fn reinstall() -> anyhow::Result<MachineOutput> {
+    Err(ext::FeatureDisabledError {
+        what: "reinstall services",
+        feature: "service-install",
+    }
+    .into())
+}
+
+/// Stops, disables and removes the regulator service, then forgets that it was installed. The
+/// executable it ran is only forgotten once no service installed by this program is left, since
+/// the hold service runs it too.
+#[cfg(feature = "service-install")]
+pub fn uninstall() -> anyhow::Result<MachineOutput> {
+    let mut config = config::write();
+    let machine = config.tuxvantage.machine();
+    let (init_system, path) = installed_regulator();
+    let service = init_system.service_name(&path);
+    let exists = path.exists();
+    let recorded = config
+        .consistency
+        .installed_services
+        .iter()
+        .any(|installed| installed == &service);
+
+    if !exists && !recorded {
+        if !machine {
+            info!(
+                "the regulator service isn't installed at {}, nothing to do",
+                path.display().bold()
+            );
+        }
+
+        return Ok(MachineOutput::Uninstalled { uninstalled: false });
+    }
+
+    if exists {
+        anyhow::ensure!(
+            utils::is_root(),
+            "uninstalling the regulator service needs to be done as root"
+        );
+        anyhow::ensure!(
+            init_system != InitSystem::Systemd || utils::is_systemd()?,
+            "you can only uninstall the service on systems which use the systemd init system"
+        );
+
+        if !machine {
+            info!(
+                "stopping, disabling and removing the {} service {}",
+                init_system.name(),
+                service.bold()
+            );
+        }
+
+        init_system.remove(&path)?;
+    } else {
+        debug!(
+            "{} was already removed, only forgetting that it was installed",
+            path.display()
+        );
+    }
+
+    config
+        .consistency
+        .mutate_then_dump(|consistency| {
+            consistency
+                .installed_services
+                .retain(|installed| installed != &service);
+            consistency.regulator_unit = None;
+            consistency.regulator_init_system = None;
+
+            if consistency.installed_services.is_empty() {
+                consistency.regulator_service_installed = false;
+                consistency.last_exe = None;
+            }
+        })
+        .context("failed to dump consistency configuration")?;
+
+    if !machine {
+        info!("uninstalled the regulator service");
+    }
+
+    Ok(MachineOutput::Uninstalled { uninstalled: true })
+}
+
+#[cfg(all(feature = "regulate", not(feature = "service-install")))]
+pub fn uninstall() -> anyhow::Result<MachineOutput> {
+    Err(ext::FeatureDisabledError {
+        what: "uninstall services",
+        feature: "service-install",
+    }
+    .into())
+}
+
+#[cfg(feature = "regulate")]
+pub fn regulator_status() -> anyhow::Result<MachineOutput> {
+    let status = Status::get()?.filter(|status| daemons::is_running(status.pid));
+    let (init_system, unit) = installed_regulator();
+    let installed = unit.exists();
+    let (active, enabled) = if installed {
+        (init_system.is_active(&unit), init_system.is_enabled(&unit))
+    } else {
+        (Some(false), Some(false))
+    };
+    let last_action = status
+        .as_ref()
+        .and_then(|status| status.stats.last_action)
+        .or_else(last_recorded_action);
+
+    if !config::machine() {
+        let state = |value: Option<bool>, yes: &'static str, no: &'static str| match value {
+            Some(true) => yes,
+            Some(false) => no,
+            None => "unknown",
+        };
+
+        if installed {
+            info!(
+                "the regulator service {} is installed for {}, {} and {}",
+                unit.display().bold(),
+                init_system.name().bold(),
+                state(active, "active", "inactive").bold(),
+                state(enabled, "enabled", "not enabled").bold()
+            );
+        } else {
+            info!("the regulator service isn't installed");
+        }
+
+        match &status {
+            Some(status) => {
+                info!("the regulator is running with pid {}", status.pid.bold());
+                let _guard = log::no_prologue::guard_for(Level::Info);
+                info!("{}{}", super::tab(2), status.summary());
+
+                if let Some(evaluation) = &status.evaluation {
+                    info!(
+                        "{}as of {} ago, battery conservation mode is {}",
+                        super::tab(2),
+                        format::duration_human(ago(evaluation.at)).bold(),
+                        if evaluation.conservation {
+                            "enabled"
+                        } else {
+                            "disabled"
+                        }
+                        .bold()
+                    );
+
+                    for battery in &evaluation.batteries {
+                        info!(
+                            "{}the battery matching {} is at {} against {}",
+                            super::tab(4),
+                            battery.matches.bold(),
+                            format::percent(battery.level).bold(),
+                            format::percent(battery.threshold).bold()
+                        );
+                    }
+                }
+            }
+            None => info!("no regulator is running, or it is running as another user"),
+        }
+
+        match last_action {
+            Some(last_action) => {
+                info!(
+                    "the regulator last {} battery conservation mode {} ago",
+                    if last_action.enabled {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    },
+                    format::duration_human(ago(last_action.at)).bold()
+                )
+            }
+            None => info!("the regulator hasn't toggled battery conservation mode yet"),
+        }
+    }
+
+    Ok(MachineOutput::RegulatorStatus {
+        installed,
+        active,
+        enabled,
+        running: status.is_some(),
+        last_action,
+        regulator: status,
+    })
+}
+
+/// How long ago `at`, in seconds since the unix epoch, was, to the second.
+#[cfg(feature = "regulate")]
+fn ago(at: u64) -> Duration {
+    let ago = SystemTime::now()
+        .duration_since(UNIX_EPOCH + Duration::from_secs(at))
+        .unwrap_or(Duration::ZERO);
+
+    Duration::from_secs(ago.as_secs())
+}
+
+/// The last toggle of battery conservation mode by the regulator recorded in the history, for when
+/// no regulator is running to tell.
+#[cfg(feature = "regulate")]
+fn last_recorded_action() -> Option<LastAction> {
+    let changes = match history::get(usize::MAX) {
+        Ok(changes) => changes,
+        Err(error) => {
+            debug!("failed to read the history: {:#}", error);
+            return None;
+        }
+    };
+
+    changes
+        .into_iter()
+        .rev()
+        .find(|change| {
+            change.initiator == Initiator::Regulate && change.setting == "battery_conservation"
+        })
+        .map(|change| LastAction {
+            enabled: change.new == "enabled",
+            at: change.time,
+        })
+}
+
+/// How long to wait for the daemonized regulator to exit after asking it to.
+#[cfg(feature = "regulate")]
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+#[cfg(feature = "regulate")]
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Stops the regulator started with `--daemonize`, waiting briefly for it to exit.
+#[cfg(feature = "regulate")]
+pub fn stop_regulator() -> anyhow::Result<MachineOutput> {
+    let pid = daemons::daemonized_pid()?.context("no daemonized regulator is running")?;
+
+    debug!("send SIGTERM to the regulator with pid {}", pid);
+    // SAFETY: `kill` is always safe to call
+    if unsafe { libc::kill(pid as libc::pid_t, SIGTERM) } == -1 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("failed to stop the regulator with pid {}", pid.bold()));
+    }
+
+    let started = Instant::now();
+
+    while daemons::is_running(pid) && started.elapsed() < STOP_TIMEOUT {
+        thread::sleep(STOP_POLL_INTERVAL);
+    }
+
+    let stopped = !daemons::is_running(pid);
+
+    if !config::machine() {
+        if stopped {
+            info!("stopped the regulator with pid {}", pid.bold());
+        } else {
+            warn!(
+                "asked the regulator with pid {} to stop, but it is still running",
+                pid.bold()
+            );
+        }
+    }
+
+    Ok(MachineOutput::Stopped { pid, stopped })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A unit generated by the baseline template, before it had a version marker.
+    const SYSTEMD_UNIT_V1: &str = "[Unit]
+Description=Regulate the battery
+
+[Service]
+ExecStart=/usr/local/bin/tuxvantage battery-conservation regulate
+
+[Install]
+WantedBy=multi-user.target";
+
+    /// A unit generated once the directories could be overridden, with the overrides passed on.
+    const SYSTEMD_UNIT_V1_ENVIRONMENT: &str = "[Unit]
+Description=Regulate the battery
+
+[Service]
+Environment=\"TUXVANTAGE_CONFIG_DIR=/etc/tuxvantage\"
+ExecStart=/usr/local/bin/tuxvantage battery-conservation regulate
+
+[Install]
+WantedBy=multi-user.target
+";
+
+    /// A hold unit generated once units had arguments of their own.
+    const SYSTEMD_UNIT_V1_HOLD: &str = "[Unit]
+Description=Hold the battery at a charge level
+
+[Service]
+ExecStart=/usr/local/bin/tuxvantage battery-conservation hold --at 70 --deadband 2
+
+[Install]
+WantedBy=multi-user.target
+";
+
+    /// A unit generated once units were marked with their version.
+    const SYSTEMD_UNIT_V2: &str = "# generated by tuxvantage, unit version 2
+[Unit]
+Description=Regulate the battery
+
+[Service]
+ExecStart=/opt/tuxvantage/tuxvantage battery-conservation regulate --threshold 80 --lower 75
+
+[Install]
+WantedBy=multi-user.target
+";
+
+    /// Formats the template of `init_system` like [`write_unit`] would.
+    #[cfg(feature = "service-install")]
+    fn current_unit(init_system: InitSystem, arguments: &str) -> String {
+        let environment = init_system
+            .environment_line(project_paths::CONFIG_DIR_ENV, Path::new("/etc/tuxvantage"));
+
+        match init_system {
+            InitSystem::Systemd => format!(
+                include_str!("../../assets/bcm.service"),
+                version = UNIT_VERSION,
+                description = "Regulate the battery",
+                watchdog = "180s",
+                environment = environment,
+                tuxvantage_exe = "/usr/bin/tuxvantage",
+                arguments = arguments,
+            ),
+            InitSystem::Openrc => format!(
+                include_str!("../../assets/bcm.openrc"),
+                version = UNIT_VERSION,
+                description = "Regulate the battery",
+                environment = environment,
+                tuxvantage_exe = "/usr/bin/tuxvantage",
+                arguments = arguments,
+            ),
+            InitSystem::Runit => format!(
+                include_str!("../../assets/bcm.runit"),
+                version = UNIT_VERSION,
+                description = "Regulate the battery",
+                environment = environment,
+                tuxvantage_exe = "/usr/bin/tuxvantage",
+                arguments = arguments,
+            ),
+        }
+    }
+
+    #[cfg(feature = "service-install")]
+    const INIT_SYSTEMS: [InitSystem; 3] =
+        [InitSystem::Systemd, InitSystem::Openrc, InitSystem::Runit];
+
+    #[test]
+    fn unmarked_units_are_version_1() {
+        for unit in [
+            SYSTEMD_UNIT_V1,
+            SYSTEMD_UNIT_V1_ENVIRONMENT,
+            SYSTEMD_UNIT_V1_HOLD,
+            "",
+        ] {
+            assert_eq!(unit_version(unit), 1);
+        }
+    }
+
+    #[test]
+    fn marked_units_have_their_version() {
+        assert_eq!(unit_version(SYSTEMD_UNIT_V2), 2);
+        assert_eq!(
+            unit_version("# generated by tuxvantage, unit version garbage\n"),
+            1
+        );
+    }
+
+    #[cfg(feature = "service-install")]
+    #[test]
+    fn current_units_have_the_current_version() {
+        for init_system in INIT_SYSTEMS {
+            assert_eq!(
+                unit_version(&current_unit(init_system, "battery-conservation regulate")),
+                UNIT_VERSION,
+                "{:?}",
+                init_system
+            );
+        }
+    }
+
+    #[test]
+    fn exe_of_historical_units() {
+        for unit in [
+            SYSTEMD_UNIT_V1,
+            SYSTEMD_UNIT_V1_ENVIRONMENT,
+            SYSTEMD_UNIT_V1_HOLD,
+        ] {
+            assert_eq!(unit_exe(unit), Some("/usr/local/bin/tuxvantage"));
+        }
+
+        assert_eq!(
+            unit_exe(SYSTEMD_UNIT_V2),
+            Some("/opt/tuxvantage/tuxvantage")
+        );
+        assert_eq!(unit_exe("[Unit]\nDescription=Something else\n"), None);
+    }
+
+    #[cfg(feature = "service-install")]
+    #[test]
+    fn exe_of_current_units() {
+        for init_system in INIT_SYSTEMS {
+            assert_eq!(
+                unit_exe(&current_unit(init_system, "battery-conservation regulate")),
+                Some("/usr/bin/tuxvantage"),
+                "{:?}",
+                init_system
+            );
+        }
+    }
+
+    #[cfg(feature = "service-install")]
+    #[test]
+    fn arguments_of_historical_units() {
+        assert_eq!(
+            unit_arguments(SYSTEMD_UNIT_V1).as_deref(),
+            Some("battery-conservation regulate")
+        );
+        assert_eq!(
+            unit_arguments(SYSTEMD_UNIT_V1_ENVIRONMENT).as_deref(),
+            Some("battery-conservation regulate")
+        );
+        assert_eq!(
+            unit_arguments(SYSTEMD_UNIT_V1_HOLD).as_deref(),
+            Some("battery-conservation hold --at 70 --deadband 2")
+        );
+        assert_eq!(
+            unit_arguments(SYSTEMD_UNIT_V2).as_deref(),
+            Some("battery-conservation regulate --threshold 80 --lower 75")
+        );
+    }
+
+    #[cfg(feature = "service-install")]
+    #[test]
+    fn arguments_of_current_units() {
+        let arguments = "battery-conservation hold --at 70 --deadband 2";
+
+        for init_system in INIT_SYSTEMS {
+            assert_eq!(
+                unit_arguments(&current_unit(init_system, arguments)).as_deref(),
+                Some(arguments),
+                "{:?}",
+                init_system
+            );
+        }
+    }
+
+    #[cfg(feature = "service-install")]
+    #[test]
+    fn units_without_arguments() {
+        assert_eq!(
+            unit_arguments("[Service]\nExecStart=/usr/bin/tuxvantage\n"),
+            None
+        );
+        assert_eq!(unit_arguments("[Unit]\nDescription=Nothing\n"), None);
+    }
+
+    #[cfg(feature = "service-install")]
+    #[test]
+    fn exe_with_spaces() {
+        let unit = format!(
+            include_str!("../../assets/bcm.runit"),
+            version = UNIT_VERSION,
+            description = "Regulate the battery",
+            environment = "",
+            tuxvantage_exe = "/opt/tux vantage/tuxvantage",
+            arguments = "battery-conservation regulate",
+        );
+
+        assert_eq!(unit_exe(&unit), Some("/opt/tux vantage/tuxvantage"));
+        assert_eq!(
+            unit_arguments(&unit).as_deref(),
+            Some("battery-conservation regulate")
+        );
+    }
+
+    /// Only systemd reads its units, the other init systems run them as scripts.
+    #[cfg(feature = "service-install")]
+    #[test]
+    fn written_units_are_executable_unless_for_systemd() {
+        crate::sandbox::Sandbox::shared();
+        let sandbox = crate::sandbox::Sandbox::new().unwrap();
+        let watchdog = Duration::from_secs(180);
+
+        for init_system in INIT_SYSTEMS {
+            let path = sandbox.path(format!("units/{:?}/bcm", init_system));
+            let exe = write_unit(init_system, &path, "Regulate the battery", "", watchdog).unwrap();
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            let contents = fs::read_to_string(&path).unwrap();
+
+            assert_eq!(
+                mode & 0o111 != 0,
+                init_system != InitSystem::Systemd,
+                "{:?}",
+                init_system
+            );
+            assert_eq!(unit_exe(&contents), exe.to_str(), "{:?}", init_system);
+        }
+    }
+
+    #[cfg(feature = "regulate")]
+    fn level(level: u8) -> BatteryLevel {
+        BatteryLevel::new(level).unwrap()
+    }
+
+    #[cfg(feature = "regulate")]
+    #[test]
+    fn threshold_without_lower_switches_at_the_threshold() {
+        let target = Target::Threshold(level(80));
+
+        assert_eq!(target.desired(80, None, 79), Some(false));
+        assert_eq!(target.desired(80, None, 80), Some(true));
+        assert_eq!(target.desired(80, None, 100), Some(true));
+        assert_eq!(target.desired(80, None, 0), Some(false));
+    }
+
+    #[cfg(feature = "regulate")]
+    #[test]
+    fn threshold_with_lower_leaves_the_band_alone() {
+        let target = Target::Threshold(level(80));
+
+        assert_eq!(target.desired(80, Some(70), 80), Some(true));
+        assert_eq!(target.desired(80, Some(70), 79), None);
+        assert_eq!(target.desired(80, Some(70), 71), None);
+        assert_eq!(target.desired(80, Some(70), 70), Some(false));
+        assert_eq!(target.desired(80, Some(70), 10), Some(false));
+    }
+
+    #[cfg(feature = "regulate")]
+    #[test]
+    fn hold_leaves_the_deadband_alone() {
+        let target = Target::Hold {
+            at: level(70),
+            deadband: 2,
+        };
+
+        assert_eq!(target.desired(70, None, 71), Some(true));
+        assert_eq!(target.desired(70, None, 70), Some(true));
+        assert_eq!(target.desired(70, None, 69), None);
+        assert_eq!(target.desired(70, None, 68), None);
+        assert_eq!(target.desired(70, None, 67), Some(false));
+        // the deadband takes the place of the lower threshold
+        assert_eq!(target.desired(70, Some(50), 60), Some(false));
+    }
+
+    #[cfg(feature = "regulate")]
+    #[test]
+    fn hold_without_deadband_switches_at_the_level() {
+        let target = Target::Hold {
+            at: level(70),
+            deadband: 0,
+        };
+
+        assert_eq!(target.desired(70, None, 70), Some(true));
+        assert_eq!(target.desired(70, None, 69), Some(false));
+    }
+
+    #[cfg(feature = "regulate")]
+    #[test]
+    fn hold_deadband_below_zero_never_disables() {
+        let target = Target::Hold {
+            at: level(3),
+            deadband: 5,
+        };
+
+        assert_eq!(target.desired(3, None, 0), None);
+        assert_eq!(target.desired(3, None, 3), Some(true));
+    }
+
+    #[cfg(feature = "regulate")]
+    #[test]
+    fn conflicting_regulate_options_are_rejected() {
+        let options = || RegulateOptions::new(HumanDuration(Duration::from_secs(30)));
+
+        assert!(options().check(false).is_ok());
+        assert!(RegulateOptions {
+            simulate: Some(PathBuf::from("samples.csv")),
+            install: true,
+            ..options()
+        }
+        .check(false)
+        .is_err());
+        assert!(RegulateOptions {
+            daemonize: true,
+            install: true,
+            log_file: Some(PathBuf::from("regulator.log")),
+            ..options()
+        }
+        .check(false)
+        .is_err());
+    }
+
+    #[cfg(feature = "regulate")]
+    #[test]
+    fn daemonizing_needs_a_log_file() {
+        let options = || RegulateOptions {
+            daemonize: true,
+            ..RegulateOptions::new(HumanDuration(Duration::from_secs(30)))
+        };
+
+        assert!(options().check(false).is_err());
+        assert!(options().check(true).is_ok());
+        assert!(RegulateOptions {
+            log_file: Some(PathBuf::from("regulator.log")),
+            ..options()
+        }
+        .check(false)
+        .is_ok());
+    }
+
+    #[cfg(feature = "regulate")]
+    #[test]
+    fn status_porcelain_lists_every_evaluated_battery() {
+        let batteries = [("index=0", 60, 80), ("index=1", 90, 70)]
+            .into_iter()
+            .map(|(matches, level, threshold)| EvaluatedBattery {
+                matches: matches.to_owned(),
+                level,
+                threshold,
+            })
+            .collect();
+        let evaluation = Evaluation::new(false, batteries);
+        let status = Status::new(SystemTime::now(), Stats::default(), Some(evaluation));
+
+        let lines = status_porcelain(&status);
+
+        assert!(lines.contains(&"conservation=false".to_owned()));
+        for line in [
+            "battery_0_matches=index=0",
+            "battery_0_level=60",
+            "battery_1_level=90",
+            "battery_1_threshold=70",
+        ] {
+            assert!(lines.contains(&line.to_owned()), "{} is missing", line);
+        }
+        assert!(
+            !status_porcelain(&Status::new(SystemTime::now(), Stats::default(), None))
+                .iter()
+                .any(|line| line.starts_with("evaluated_at="))
+        );
+    }
+
+    #[cfg(feature = "regulate")]
+    #[test]
+    fn adaptive_sleeps_are_half_the_time_to_a_crossing() {
+        let sleep = |until: Option<u64>| {
+            adaptive_sleep(
+                Duration::from_secs(60),
+                until.map(Duration::from_secs),
+                Duration::from_secs(10),
+                Duration::from_secs(3600),
+            )
+        };
+
+        assert_eq!(sleep(Some(80)), Duration::from_secs(40));
+        // a crossing far away or unknown keeps the cooldown
+        assert_eq!(sleep(Some(600)), Duration::from_secs(60));
+        assert_eq!(sleep(None), Duration::from_secs(60));
+        // an imminent crossing is still only checked as often as the floor allows
+        assert_eq!(sleep(Some(4)), Duration::from_secs(10));
+    }
+
+    #[cfg(feature = "regulate")]
+    #[test]
+    fn adaptive_sleeps_are_capped_by_the_ceiling() {
+        assert_eq!(
+            adaptive_sleep(
+                Duration::from_secs(7200),
+                None,
+                Duration::from_secs(10),
+                Duration::from_secs(3600),
+            ),
+            Duration::from_secs(3600)
+        );
     }
 }
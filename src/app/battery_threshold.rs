@@ -0,0 +1,150 @@
+use crate::app::{IntoOptionMachineOutput, Porcelain};
+use crate::ext::AnyhowResultExt;
+use crate::hardware;
+use crate::history::{self, Initiator};
+use crate::thresholds::{Battery, Mechanism, Thresholds};
+use crate::types::BatteryLevel;
+use crate::{anyhow_with_tip, api, config, TippingAnyhowResultExt};
+use owo_colors::OwoColorize;
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum MachineOutput {
+    Get {
+        battery: String,
+        thresholds: Thresholds,
+        mechanism: Mechanism,
+    },
+    Changed {
+        changed: bool,
+        battery: String,
+        thresholds: Thresholds,
+        mechanism: Mechanism,
+    },
+}
+
+impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
+    fn into_option_machine_output(self) -> Option<MachineOutput> {
+        Some(self)
+    }
+}
+
+/// The porcelain lines of native thresholds, which the regulator prints as well when it uses
+/// them instead of regulating.
+pub fn porcelain(battery: &str, thresholds: Thresholds, mechanism: Mechanism) -> Vec<String> {
+    vec![
+        super::pair("battery", battery),
+        super::pair("start", thresholds.start.inner()),
+        super::pair("end", thresholds.end.inner()),
+        super::pair("mechanism", mechanism.name()),
+    ]
+}
+
+impl Porcelain for MachineOutput {
+    fn porcelain(&self) -> Vec<String> {
+        match self {
+            Self::Get {
+                battery,
+                thresholds,
+                mechanism,
+            } => porcelain(battery, *thresholds, *mechanism),
+            Self::Changed {
+                changed,
+                battery,
+                thresholds,
+                mechanism,
+            } => {
+                let mut lines = vec![super::pair("changed", changed)];
+                lines.extend(porcelain(battery, *thresholds, *mechanism));
+                lines
+            }
+        }
+    }
+}
+
+fn conservation() -> anyhow_with_tip::Result<bool> {
+    api::read_conservation(&mut hardware::get()).maybe_acpi_call_tip()
+}
+
+fn report_mechanism(mechanism: Mechanism) {
+    match mechanism {
+        Mechanism::None => info!("nothing limits the charge of the battery"),
+        mechanism => info!(
+            "the charge of the battery is limited by {}",
+            mechanism.description().bold()
+        ),
+    }
+}
+
+pub fn get() -> anyhow_with_tip::Result<MachineOutput> {
+    let battery = Battery::require()?;
+    let thresholds = battery.get().no_tip()?;
+    let mechanism = Mechanism::in_effect(Some(thresholds), conservation()?);
+    let output = MachineOutput::Get {
+        battery: battery.name.clone(),
+        thresholds,
+        mechanism,
+    };
+
+    if !config::machine() && !super::print_template("battery threshold get", &output) {
+        info!(
+            "{} starts charging below {} and stops at {}",
+            battery.name.bold(),
+            thresholds.start.bold(),
+            thresholds.end.bold()
+        );
+        report_mechanism(mechanism);
+    }
+
+    Ok(output)
+}
+
+pub fn set(start: BatteryLevel, end: BatteryLevel) -> anyhow_with_tip::Result<MachineOutput> {
+    let thresholds = Thresholds::new(start, end).no_tip()?;
+    let battery = Battery::require()?;
+    let machine = config::machine();
+    let old = battery.get().no_tip()?;
+    let changed = old != thresholds;
+
+    if changed {
+        battery.set(thresholds)?;
+        history::record("charge_thresholds", old, thresholds, Initiator::Cli);
+
+        if !machine {
+            info!(
+                "{} now starts charging below {} and stops at {}",
+                battery.name.bold(),
+                thresholds.start.bold(),
+                thresholds.end.bold()
+            );
+        }
+    } else if !machine {
+        info!(
+            "the thresholds of {} are already {}, nothing to do",
+            battery.name.bold(),
+            thresholds.bold()
+        );
+    }
+
+    let conservation = conservation()?;
+    let mechanism = Mechanism::in_effect(Some(thresholds), conservation);
+
+    if !machine {
+        report_mechanism(mechanism);
+
+        if conservation && thresholds.limits() {
+            warn!(
+                "battery conservation mode is enabled and stops charging before the end \
+                 threshold on most machines, disable it to charge up to {}",
+                thresholds.end.bold()
+            );
+        }
+    }
+
+    Ok(MachineOutput::Changed {
+        changed,
+        battery: battery.name,
+        thresholds,
+        mechanism,
+    })
+}
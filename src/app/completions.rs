@@ -0,0 +1,188 @@
+use crate::args::TuxVantage;
+use crate::config::{BuiltInProfile, ProfilesConfig};
+use crate::project_paths;
+use anyhow::Context;
+use clap::IntoApp;
+use clap_complete::Shell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Just the name of a profile, so that listing the names doesn't need to deserialize the rest of
+/// each profile.
+#[derive(Deserialize)]
+struct ProfileName {
+    name: String,
+}
+
+/// Just the part of `tuxvantage.toml` which hides built-in profiles, so that listing the names
+/// doesn't need to deserialize the rest of the config.
+#[derive(Deserialize, Default)]
+struct DisabledBuiltIns {
+    #[serde(default)]
+    profiles: ProfilesConfig,
+}
+
+/// The names of every profile, without the disabled built-in ones. Files which can't be read are
+/// skipped, since this is used for completions which must never fail. A profile is listed as long
+/// as it has a name, even if the rest of it is broken.
+fn profile_names(tuxvantage_toml: &Path, profiles_dir: &Path) -> Vec<String> {
+    let disabled = fs::read_to_string(tuxvantage_toml)
+        .ok()
+        .and_then(|contents| toml::from_str::<DisabledBuiltIns>(&contents).ok())
+        .unwrap_or_default()
+        .profiles
+        .disabled_builtins;
+    let mut names = BuiltInProfile::enabled(&disabled)
+        .map(|profile| profile.get().name.to_string())
+        .collect::<Vec<_>>();
+    let entries = match profiles_dir.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return names,
+    };
+
+    names.extend(
+        entries
+            .flatten()
+            .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+            .filter_map(|contents| serde_json::from_str::<ProfileName>(&contents).ok())
+            .map(|profile| profile.name),
+    );
+    names
+}
+
+/// Prints the name of every profile, one per line, for the completion scripts.
+pub fn list_profile_names() {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    for name in profile_names(
+        project_paths::tuxvantage_toml(),
+        project_paths::profiles_dir(),
+    ) {
+        if writeln!(stdout, "{}", name).is_err() {
+            break;
+        }
+    }
+}
+
+/// Completes profile names for the `--profile` flag and the profile name arguments of the
+/// `profiles` subcommands, leaving everything else to the generated completions.
+const BASH: &str = r#"
+_tuxvantage_dynamic() {
+    local current="${COMP_WORDS[COMP_CWORD]}"
+    local previous="${COMP_WORDS[COMP_CWORD-1]}"
+
+    if [[ "${previous}" == "--profile" || "${previous}" == "-p" ]] \
+        || [[ "${COMP_WORDS[*]}" =~ (^|[[:space:]])(profiles|p)[[:space:]]+(set-default|sd|remove|r|rm|json|j|get|g|contribute|c)[[:space:]]+[^[:space:]]*$ ]]; then
+        COMPREPLY=($(compgen -W "$(tuxvantage __list-profile-names 2>/dev/null)" -- "${current}"))
+        return 0
+    fi
+
+    _tuxvantage "$@"
+}
+
+complete -F _tuxvantage_dynamic -o bashdefault -o default tuxvantage
+"#;
+
+const ZSH: &str = r#"
+_tuxvantage_dynamic() {
+    if [[ "${words[CURRENT-1]}" == "--profile" || "${words[CURRENT-1]}" == "-p" ]] \
+        || [[ "${words[CURRENT-1]}" =~ "^(set-default|sd|remove|r|rm|json|j|get|g|contribute|c)$" \
+            && "${words[CURRENT-2]}" =~ "^(profiles|p)$" ]]; then
+        compadd -- ${(f)"$(tuxvantage __list-profile-names 2>/dev/null)"}
+        return 0
+    fi
+
+    _tuxvantage "$@"
+}
+
+_tuxvantage_dynamic "$@"
+"#;
+
+const FISH: &str = r#"
+complete -c tuxvantage -s p -l profile -x -a "(tuxvantage __list-profile-names 2>/dev/null)"
+complete -c tuxvantage -n "__fish_seen_subcommand_from profiles p; and __fish_seen_subcommand_from set-default sd remove r rm json j get g contribute c" -f -a "(tuxvantage __list-profile-names 2>/dev/null)"
+"#;
+
+/// Prints the completion script for `shell`, including the completion of profile names where the
+/// shell supports it.
+pub fn completions(shell: Shell) -> anyhow::Result<()> {
+    let mut script = Vec::new();
+    clap_complete::generate(
+        shell,
+        &mut TuxVantage::into_app(),
+        "tuxvantage",
+        &mut script,
+    );
+    let mut script =
+        String::from_utf8(script).context("the generated completion script isn't valid utf-8")?;
+
+    match shell {
+        Shell::Bash => script.push_str(BASH),
+        Shell::Zsh => {
+            // the generated script calls the completion function itself at the end, which has to
+            // go through the dynamic completions instead
+            let call = "_tuxvantage \"$@\"";
+
+            if let Some(index) = script.rfind(call) {
+                script.replace_range(index..index + call.len(), "");
+            }
+
+            script.push_str(ZSH)
+        }
+        Shell::Fish => script.push_str(FISH),
+        _ => debug!("profile names can't be completed for {:?}", shell),
+    }
+
+    io::stdout()
+        .write_all(script.as_bytes())
+        .context("failed to write the completion script")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Profiles, TuxVantage};
+    use crate::project_paths::profiles::Profiles as ProfilesDir;
+    use crate::sandbox::Sandbox;
+    use ideapad::Profile;
+
+    #[test]
+    fn names_are_the_ones_of_the_full_config() {
+        let sandbox = Sandbox::new().unwrap();
+        let tuxvantage_toml = sandbox.path("config/tuxvantage.toml");
+        let profiles_dir = sandbox.path("config/profiles");
+        let disabled = BuiltInProfile::ALL[0].get().name.to_string();
+        let mut patched = serde_json::to_value(Profile::IDEAPAD_15IIL05).unwrap();
+        patched["name"] = "patched".into();
+
+        fs::create_dir_all(&profiles_dir).unwrap();
+        fs::write(profiles_dir.join("patched.json"), patched.to_string()).unwrap();
+        fs::write(profiles_dir.join("broken.json"), "{ not json").unwrap();
+        fs::write(
+            &tuxvantage_toml,
+            format!("[profiles]\ndisabled_builtins = [{:?}]\n", disabled),
+        )
+        .unwrap();
+
+        let tuxvantage =
+            toml::from_str::<TuxVantage>(&fs::read_to_string(&tuxvantage_toml).unwrap()).unwrap();
+        let profiles = Profiles {
+            loaded: ProfilesDir::at(&profiles_dir).unwrap().flatten().collect(),
+            failed: Vec::new(),
+            disabled_built_ins: tuxvantage.profiles.disabled_builtins,
+        };
+        let mut expected = profiles
+            .with_built_ins()
+            .map(|profile| profile.get().name.to_string())
+            .collect::<Vec<_>>();
+        let mut names = profile_names(&tuxvantage_toml, &profiles_dir);
+        expected.sort();
+        names.sort();
+
+        assert_eq!(names, expected);
+        assert!(names.contains(&"patched".to_string()));
+        assert!(!names.contains(&disabled));
+    }
+}
@@ -0,0 +1,824 @@
+use crate::anyhow_with_tip::{self, TippingAnyhowResultExt};
+use crate::app::IntoOptionMachineOutput;
+use crate::args::HandlerPolicy;
+use crate::config::{
+    self, BuiltInProfile, Consistency, Feature, HandlerMode, HandlerResolution, Handlers, Machine,
+    Tips, TuxVantage,
+};
+use crate::project_paths::{PathSource, CONFIG_DIR_ENV, PROFILES_DIR_ENV};
+use crate::state::State;
+use crate::utils::{self, Names};
+use crate::validation::{self, Finding, Location, Severity};
+use crate::{api, log, project_paths, templates};
+use anyhow::Context;
+use ideapad::Handler;
+use itertools::Itertools;
+use owo_colors::OwoColorize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{env, fs, io};
+
+/// A value one of the initialization steps took into account.
+#[derive(Serialize)]
+pub struct Input {
+    source: &'static str,
+    value: Option<String>,
+
+    /// Whether this value decided the outcome of the step.
+    used: bool,
+}
+
+impl Input {
+    fn new(source: &'static str, value: Option<String>, used: bool) -> Self {
+        Self {
+            source,
+            value,
+            used,
+        }
+    }
+
+    /// Inputs in order of precedence, where the first one which is set is used.
+    fn first_set(inputs: impl IntoIterator<Item = (&'static str, Option<String>)>) -> Vec<Self> {
+        let mut used = false;
+
+        inputs
+            .into_iter()
+            .map(|(source, value)| {
+                let input = Self::new(source, value.clone(), !used && value.is_some());
+                used |= value.is_some();
+                input
+            })
+            .collect()
+    }
+
+    /// The value of the input which was used.
+    fn used_value(inputs: &[Self]) -> Option<&str> {
+        inputs
+            .iter()
+            .find(|input| input.used)
+            .and_then(|input| input.value.as_deref())
+    }
+}
+
+/// A decision made while initializing, with what it was based on.
+#[derive(Serialize)]
+pub struct Step {
+    step: &'static str,
+    inputs: Vec<Input>,
+    outcome: String,
+}
+
+/// The handler of the config `config set-handler` sets.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HandlerTarget {
+    Default,
+    BatteryConservation,
+    RapidCharge,
+}
+
+impl HandlerTarget {
+    pub const NAMES: Names<Self> = &[
+        (Self::Default, &["default", "d"]),
+        (Self::BatteryConservation, &["battery-conservation", "bc"]),
+        (Self::RapidCharge, &["rapid-charge", "rc"]),
+    ];
+
+    /// The key of the handler in `tuxvantage.toml`.
+    fn key(self) -> &'static str {
+        match self {
+            Self::Default => "handlers.default",
+            Self::BatteryConservation => "handlers.battery_conservation",
+            Self::RapidCharge => "handlers.rapid_charging",
+        }
+    }
+
+    fn field(self, handlers: &mut Handlers) -> &mut Option<Handler> {
+        match self {
+            Self::Default => &mut handlers.default,
+            Self::BatteryConservation => &mut handlers.battery_conservation,
+            Self::RapidCharge => &mut handlers.rapid_charging,
+        }
+    }
+
+    fn feature(self) -> Option<Feature> {
+        match self {
+            Self::Default => None,
+            Self::BatteryConservation => Some(Feature::BatteryConservation),
+            Self::RapidCharge => Some(Feature::RapidCharge),
+        }
+    }
+}
+
+impl FromStr for HandlerTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        utils::parse_name(Self::NAMES, "handler target", s)
+    }
+}
+
+/// The handlers as they are in the config, and how each mode resolves them.
+#[derive(Serialize)]
+pub struct HandlerTable {
+    config: Handlers,
+    battery_conservation: HandlerResolution,
+    rapid_charging: HandlerResolution,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum MachineOutput {
+    Check {
+        valid: bool,
+        findings: Vec<Finding>,
+    },
+    Explain(Vec<Step>),
+    SetHandler {
+        changed: bool,
+        handlers: HandlerTable,
+    },
+}
+
+impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
+    fn into_option_machine_output(self) -> Option<MachineOutput> {
+        Some(self)
+    }
+}
+
+/// Reads the file at `path`, recording a finding if it couldn't be read. A missing file is only
+/// a warning since the defaults will be used in its place.
+fn read(path: &Path, findings: &mut Vec<Finding>) -> Option<String> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Some(contents),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            findings.push(
+                Finding::warning("the file doesn't exist, the defaults will be used").in_file(path),
+            );
+            None
+        }
+        Err(error) => {
+            findings
+                .push(Finding::error(format!("failed to read the file: {}", error)).in_file(path));
+            None
+        }
+    }
+}
+
+/// Finds where `key` is defined in `contents`, which is good enough to point at unknown keys.
+fn locate_key(contents: &str, key: &str) -> Option<Location> {
+    contents.lines().enumerate().find_map(|(index, line)| {
+        let trimmed = line.trim_start();
+        let rest = trimmed.strip_prefix(key)?;
+
+        if rest.trim_start().starts_with('=') {
+            Some(Location {
+                line: index + 1,
+                column: line.len() - trimmed.len() + 1,
+            })
+        } else {
+            None
+        }
+    })
+}
+
+fn check_tuxvantage_toml(findings: &mut Vec<Finding>) -> Option<TuxVantage> {
+    let path = project_paths::tuxvantage_toml();
+    let contents = read(path, findings)?;
+    let mut unknown_keys = Vec::new();
+
+    let result = serde_ignored::deserialize(&mut toml::Deserializer::new(&contents), |key| {
+        unknown_keys.push(key.to_string())
+    });
+
+    for key in unknown_keys {
+        let last_segment = key.rsplit('.').next().unwrap_or(&key);
+        let finding = Finding::warning(format!("unknown key {}", key.bold()));
+        let finding = match locate_key(&contents, last_segment) {
+            Some(location) => finding.at(&contents, location),
+            None => finding,
+        };
+
+        findings.push(finding.in_file(path));
+    }
+
+    match result {
+        Ok(tuxvantage) => Some(tuxvantage),
+        Err(error) => {
+            findings.push(Finding::toml(&contents, &error).in_file(path));
+            None
+        }
+    }
+}
+
+fn check_json<T: DeserializeOwned>(path: &Path, findings: &mut Vec<Finding>) {
+    if let Some(contents) = read(path, findings) {
+        if let Err(error) = serde_json::from_str::<T>(&contents) {
+            findings.push(Finding::json(&contents, &error).in_file(path));
+        }
+    }
+}
+
+/// Checks every profile in the profiles directory, returning the names of the valid ones.
+fn check_profiles(
+    disabled_built_ins: &[String],
+    findings: &mut Vec<Finding>,
+) -> anyhow::Result<Vec<String>> {
+    let profiles_dir = project_paths::profiles_dir();
+
+    if !profiles_dir.exists() {
+        findings
+            .push(Finding::warning("the profiles directory doesn't exist").in_file(profiles_dir));
+        return Ok(Vec::new());
+    }
+
+    let mut names = HashMap::<String, PathBuf>::new();
+
+    for entry in profiles_dir
+        .read_dir()
+        .context("failed to get entries of the profile directory")?
+    {
+        let path = entry
+            .context("failed to get the next entry of the profile directory")?
+            .path();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(error) => {
+                findings.push(
+                    Finding::error(format!("failed to read the profile: {}", error)).in_file(&path),
+                );
+                continue;
+            }
+        };
+        let validation = validation::validate(&contents, disabled_built_ins);
+
+        findings.extend(
+            validation
+                .findings
+                .iter()
+                .cloned()
+                .map(|finding| finding.in_file(&path)),
+        );
+
+        if let Some(profile) = validation.profile {
+            let name = profile.name.to_string();
+
+            match names.get(&name) {
+                Some(other) => findings.push(
+                    Finding::error(format!(
+                        "the profile name {} is also used by {}",
+                        name.bold(),
+                        other.display().bold()
+                    ))
+                    .in_file(&path),
+                ),
+                None => {
+                    names.insert(name, path);
+                }
+            }
+        }
+    }
+
+    Ok(names.into_keys().collect())
+}
+
+/// Loads the whole configuration again without installing it, returning every problem found.
+pub fn findings() -> anyhow::Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+
+    debug!("check `tuxvantage.toml`");
+    let tuxvantage = check_tuxvantage_toml(&mut findings);
+
+    debug!("check `.consistency.json`");
+    check_json::<Consistency>(project_paths::consistency_json(), &mut findings);
+
+    debug!("check `state.json`");
+    if project_paths::state_json().exists() {
+        check_json::<State>(project_paths::state_json(), &mut findings);
+    }
+
+    for (command, template) in tuxvantage
+        .iter()
+        .flat_map(|tuxvantage| &tuxvantage.templates)
+    {
+        if let Err(error) = templates::validate(command, template) {
+            findings.push(
+                Finding::error(format!(
+                    "the template of {} is invalid: {:#}",
+                    command.bold(),
+                    error
+                ))
+                .in_file(project_paths::tuxvantage_toml()),
+            );
+        }
+    }
+
+    let disabled_built_ins = tuxvantage
+        .as_ref()
+        .map(|tuxvantage| tuxvantage.profiles.disabled_builtins.as_slice())
+        .unwrap_or_default();
+
+    for name in disabled_built_ins {
+        if !BuiltInProfile::ALL
+            .iter()
+            .any(|built_in| built_in.get().name == *name)
+        {
+            findings.push(
+                Finding::warning(format!(
+                    "{} isn't a built-in profile, so disabling it does nothing",
+                    name.bold()
+                ))
+                .in_file(project_paths::tuxvantage_toml()),
+            );
+        }
+    }
+
+    debug!("check the profiles");
+    let profile_names = check_profiles(disabled_built_ins, &mut findings)?;
+
+    if let Some(default) = tuxvantage
+        .as_ref()
+        .and_then(|tuxvantage| tuxvantage.profile.as_deref())
+    {
+        let exists = profile_names.iter().any(|name| name == default)
+            || BuiltInProfile::enabled(disabled_built_ins)
+                .any(|built_in| built_in.get().name == default);
+        let disabled = BuiltInProfile::ALL.iter().any(|built_in| {
+            built_in.get().name == default && built_in.is_disabled(disabled_built_ins)
+        });
+
+        if !exists {
+            let message = if disabled {
+                format!(
+                    "the default profile {} is a built-in profile, but it is disabled by {}",
+                    default.bold(),
+                    "profiles.disabled_builtins".bold()
+                )
+            } else {
+                format!("the default profile {} does not exist", default.bold())
+            };
+
+            findings.push(Finding::error(message).in_file(project_paths::tuxvantage_toml()));
+        }
+    }
+
+    Ok(findings)
+}
+
+/// Loads the whole configuration again without installing it, reporting every problem found.
+pub fn check(machine: bool) -> anyhow::Result<MachineOutput> {
+    let findings = findings()?;
+    let errors = findings
+        .iter()
+        .filter(|finding| finding.severity == Severity::Error)
+        .count();
+
+    if !machine {
+        if findings.is_empty() {
+            info!("no problems found in the configuration");
+        }
+
+        let _guard = log::no_prologue::guard_for(log::Level::Info);
+
+        for finding in &findings {
+            match finding.severity {
+                Severity::Error => error!("{}", finding),
+                Severity::Warning => warn!("{}", finding),
+            }
+        }
+
+        anyhow::ensure!(
+            errors == 0,
+            "found {} error(s) in the configuration",
+            errors
+        );
+    }
+
+    Ok(MachineOutput::Check {
+        valid: errors == 0,
+        findings,
+    })
+}
+
+fn machine_name(machine: Machine) -> &'static str {
+    match machine {
+        Machine::Always => "always",
+        Machine::Never => "never",
+        Machine::Auto => "auto",
+    }
+}
+
+fn tips_name(tips: Tips) -> &'static str {
+    match tips {
+        Tips::Always => "always",
+        Tips::Once => "once",
+        Tips::Never => "never",
+    }
+}
+
+fn path_step(
+    step: &'static str,
+    path: &Path,
+    inputs: impl IntoIterator<Item = (&'static str, Option<String>, bool)>,
+) -> Step {
+    Step {
+        step,
+        inputs: inputs
+            .into_iter()
+            .map(|(source, value, used)| Input::new(source, value, used))
+            .collect(),
+        outcome: path.display().to_string(),
+    }
+}
+
+fn env_value(key: &str) -> Option<String> {
+    env::var_os(key)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.to_string_lossy().into_owned())
+}
+
+fn project_paths_steps() -> [Step; 2] {
+    let config_dir = project_paths::config_dir();
+    let config_dir_source = project_paths::config_dir_source();
+    let profiles_dir = project_paths::profiles_dir();
+    let profiles_dir_source = project_paths::profiles_dir_source();
+
+    [
+        path_step(
+            "config directory",
+            config_dir,
+            [
+                (
+                    "--config",
+                    Some(config_dir.display().to_string())
+                        .filter(|_| config_dir_source == PathSource::Flag),
+                    config_dir_source == PathSource::Flag,
+                ),
+                (
+                    CONFIG_DIR_ENV,
+                    env_value(CONFIG_DIR_ENV),
+                    config_dir_source == PathSource::Environment,
+                ),
+                (
+                    "platform default",
+                    Some(project_paths::get_dirs().config_dir().display().to_string()),
+                    config_dir_source == PathSource::Default,
+                ),
+            ],
+        ),
+        path_step(
+            "profiles directory",
+            profiles_dir,
+            [
+                (
+                    PROFILES_DIR_ENV,
+                    env_value(PROFILES_DIR_ENV),
+                    profiles_dir_source == PathSource::Environment,
+                ),
+                (
+                    "config directory",
+                    Some(config_dir.join("profiles").display().to_string()),
+                    profiles_dir_source == PathSource::Default,
+                ),
+            ],
+        ),
+    ]
+}
+
+fn config_step(config: &config::Config) -> Step {
+    let errors = config::recoverable_errors();
+    let mut inputs = vec![
+        Input::new(
+            "tuxvantage.toml",
+            Some(project_paths::tuxvantage_toml().display().to_string()),
+            true,
+        ),
+        Input::new(
+            "loaded profiles",
+            Some(config.profiles.loaded.len().to_string()),
+            true,
+        ),
+        Input::new(
+            "failed profiles",
+            Some(config.profiles.failed.len().to_string()),
+            false,
+        ),
+    ];
+    inputs.extend(
+        errors
+            .iter()
+            .map(|error| Input::new("recoverable error", Some(error.clone()), false)),
+    );
+
+    Step {
+        step: "config",
+        inputs,
+        outcome: format!("loaded with {} recoverable error(s)", errors.len()),
+    }
+}
+
+fn override_steps(tuxvantage: &TuxVantage) -> Vec<Step> {
+    let overrides = &tuxvantage.overrides;
+    let machine = Input::first_set([
+        (
+            "--machine",
+            overrides.machine.map(machine_name).map(str::to_string),
+        ),
+        (
+            "config",
+            tuxvantage.machine.map(machine_name).map(str::to_string),
+        ),
+        ("built-in default", Some("auto".to_string())),
+    ]);
+    let tips = Input::first_set([
+        (
+            "--no-tips",
+            Some("never".to_string()).filter(|_| overrides.no_tips),
+        ),
+        ("config", tuxvantage.tips.map(tips_name).map(str::to_string)),
+        ("built-in default", Some("always".to_string())),
+    ]);
+    let backtrace = tuxvantage.backtrace();
+    let panic = tuxvantage.panic();
+
+    let mut steps = vec![
+        Step {
+            step: "machine output",
+            outcome: format!(
+                "{}, which {} machine output",
+                machine_name(tuxvantage.machine()),
+                if tuxvantage.machine().get() {
+                    "is"
+                } else {
+                    "isn't"
+                }
+            ),
+            inputs: machine,
+        },
+        Step {
+            step: "tips",
+            outcome: Input::used_value(&tips).unwrap_or_default().to_string(),
+            inputs: tips,
+        },
+        Step {
+            step: "backtrace",
+            inputs: Input::first_set([
+                (
+                    "--backtrace",
+                    overrides.backtrace.map(|backtrace| backtrace.to_string()),
+                ),
+                ("config", Some(tuxvantage.backtrace.to_string())),
+            ]),
+            outcome: format!(
+                "backtraces on panics: {}, backtraces on errors: {}",
+                backtrace.panics, backtrace.errors
+            ),
+        },
+        Step {
+            step: "panic",
+            inputs: Input::first_set([
+                (
+                    if overrides.panic == Some(false) {
+                        "--no-panic"
+                    } else {
+                        "--panic"
+                    },
+                    overrides.panic.map(|panic| panic.to_string()),
+                ),
+                ("config", Some(tuxvantage.panic.to_string())),
+            ]),
+            outcome: format!("panic on errors: {}", panic),
+        },
+    ];
+
+    for (step, mode) in [
+        (
+            "battery conservation handler",
+            HandlerMode::BatteryConservation,
+        ),
+        ("rapid charging handler", HandlerMode::RapidCharging),
+    ] {
+        steps.push(handler_step(step, &tuxvantage.handler_resolution(mode)));
+    }
+
+    steps
+}
+
+/// The same provenance as `--explain` of the enable subcommands.
+fn handler_step(step: &'static str, resolution: &HandlerResolution) -> Step {
+    Step {
+        step,
+        inputs: resolution
+            .candidates()
+            .iter()
+            .map(|candidate| {
+                Input::new(
+                    candidate.source.name(),
+                    candidate
+                        .value
+                        .map(|handler| super::handler_name(handler).to_string()),
+                    candidate.used,
+                )
+            })
+            .collect(),
+        outcome: super::handler_name(resolution.handler()).to_string(),
+    }
+}
+
+/// The profile ideapad would be initialized with. Detecting it only reads the product name of the
+/// machine, which doesn't need the hardware to be accessed.
+fn profile_step(config: &config::Config) -> Step {
+    let mut inputs = Input::first_set([
+        ("--profile", config.tuxvantage.overrides.profile.clone()),
+        ("config", config.tuxvantage.profile.clone()),
+    ]);
+
+    let outcome = match config.tuxvantage.profile() {
+        Some(name) => match config.default_profile() {
+            Some(Ok(_)) => format!("use the default profile {}", name),
+            Some(Err(error)) => format!("{:#}", error),
+            None => unreachable!("a default profile was given"),
+        },
+        None => {
+            let search_path = config
+                .profiles
+                .with_built_ins()
+                .map(|profile| profile.get().name.to_string())
+                .join(", ");
+            inputs.push(Input::new("search path", Some(search_path), true));
+
+            // the raw product name is quoted so that padding such as NULs can be seen
+            let raw_product_name = config::raw_product_name().ok();
+            inputs.push(Input::new(
+                "product name",
+                raw_product_name.as_ref().map(|raw| format!("{:?}", raw)),
+                false,
+            ));
+            inputs.push(Input::new(
+                "normalized product name",
+                raw_product_name
+                    .as_deref()
+                    .map(|raw| config::normalize_product_name(raw).to_string()),
+                true,
+            ));
+
+            match config.profiles.detect() {
+                Ok(profile) => format!("detected the profile {}", profile.name),
+                Err(error) => format!("failed to detect a profile: {:#}", error),
+            }
+        }
+    };
+
+    Step {
+        step: "profile",
+        inputs,
+        outcome,
+    }
+}
+
+/// Walks through the decisions made while initializing, without accessing the hardware.
+pub fn explain() -> anyhow::Result<MachineOutput> {
+    let steps = {
+        let config = config::read();
+        let mut steps = Vec::new();
+
+        steps.extend(project_paths_steps());
+        steps.push(config_step(&config));
+        steps.extend(override_steps(&config.tuxvantage));
+        steps.push(profile_step(&config));
+        steps
+    };
+
+    if !config::machine() {
+        for (index, step) in steps.iter().enumerate() {
+            info!("{}. {}: {}", index + 1, step.step.bold(), step.outcome);
+
+            let _guard = log::no_prologue::guard_for(log::Level::Info);
+
+            for input in &step.inputs {
+                let value = input.value.as_deref().map_or_else(
+                    || "not set".italic().to_string(),
+                    |value| value.bold().to_string(),
+                );
+
+                if input.used {
+                    info!(
+                        "{}{}: {} {}",
+                        super::tab(2),
+                        input.source,
+                        value,
+                        "(used)".green()
+                    );
+                } else {
+                    info!("{}{}: {}", super::tab(2), input.source, value);
+                }
+            }
+        }
+    }
+
+    Ok(MachineOutput::Explain(steps))
+}
+
+/// The name of the profile which would be used, if it declares `feature` as unsupported.
+fn unsupported_by_profile(config: &config::Config, feature: Feature) -> Option<String> {
+    let profile = api::profile(config).ok()?;
+
+    config
+        .profiles
+        .with_built_ins()
+        .find(|possibly_built_in| possibly_built_in.get().name == profile.name)
+        .filter(|possibly_built_in| possibly_built_in.unsupported().contains(&feature))
+        .map(|_| profile.name.to_string())
+}
+
+/// Sets the handler of `target` in the config, or the default handler if not given, then shows
+/// how the handlers of every mode are resolved afterwards.
+pub fn set_handler(
+    target: Option<HandlerTarget>,
+    handler: HandlerPolicy,
+) -> anyhow_with_tip::Result<MachineOutput> {
+    let target = target.unwrap_or(HandlerTarget::Default);
+    let handler = match handler {
+        HandlerPolicy::Handler(handler) => handler,
+        HandlerPolicy::SwitchBack => {
+            return Err(anyhow::anyhow!(
+                "the {} handler can't be set in the config",
+                "switch-back".bold()
+            ))
+            .tip(
+                "`switch-back` remembers what it switched off, so it can only be given to the \
+                 enable subcommands with `--handler switch-back`",
+            )
+        }
+    };
+    let mut config = config::write();
+    let machine = config.tuxvantage.machine().get();
+    let current = *target.field(&mut config.tuxvantage.handlers);
+    let changed = current.map(super::handler_name) != Some(super::handler_name(handler));
+
+    if changed {
+        debug!("write the new handler to the config");
+        config
+            .tuxvantage
+            .mutate_then_dump(|tuxvantage| *target.field(&mut tuxvantage.handlers) = Some(handler))
+            .context("failed to write to `tuxvantage.toml`")
+            .no_tip()?;
+    }
+
+    if let Some(feature) = target.feature() {
+        if let Some(profile) = unsupported_by_profile(&config, feature) {
+            let message = format!(
+                "the profile {} declares {} as unsupported, so this handler won't be used with it",
+                profile.bold(),
+                feature.name().bold()
+            );
+
+            if machine {
+                crate::machine::push_warning(message, None::<&str>);
+            } else {
+                warn!("{}", message);
+            }
+        }
+    }
+
+    let handlers = &config.tuxvantage.handlers;
+    let table = HandlerTable {
+        config: Handlers {
+            default: handlers.default,
+            battery_conservation: handlers.battery_conservation,
+            rapid_charging: handlers.rapid_charging,
+        },
+        battery_conservation: config
+            .tuxvantage
+            .handler_resolution(HandlerMode::BatteryConservation),
+        rapid_charging: config
+            .tuxvantage
+            .handler_resolution(HandlerMode::RapidCharging),
+    };
+    drop(config);
+
+    if !machine {
+        if changed {
+            info!(
+                "set {} to {}",
+                target.key().bold(),
+                super::handler_name(handler).bold()
+            );
+        } else {
+            info!(
+                "{} is already {}, nothing to do",
+                target.key().bold(),
+                super::handler_name(handler).bold()
+            );
+        }
+
+        super::print_handler_resolution("battery conservation", &table.battery_conservation);
+        super::print_handler_resolution("rapid charging", &table.rapid_charging);
+    }
+
+    Ok(MachineOutput::SetHandler {
+        changed,
+        handlers: table,
+    })
+}
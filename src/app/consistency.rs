@@ -0,0 +1,132 @@
+use crate::app::{IntoOptionMachineOutput, Porcelain};
+use crate::config::Consistency;
+use crate::{config, log, project_paths};
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use std::path::PathBuf;
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum MachineOutput {
+    Show {
+        path: PathBuf,
+        version: u32,
+        last_exe: Option<PathBuf>,
+        regulator_service_installed: bool,
+        installed_services: Vec<String>,
+        regulator_unit: Option<PathBuf>,
+    },
+    Reset {
+        reset: bool,
+    },
+}
+
+impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
+    fn into_option_machine_output(self) -> Option<MachineOutput> {
+        Some(self)
+    }
+}
+
+impl Porcelain for MachineOutput {
+    fn porcelain(&self) -> Vec<String> {
+        match self {
+            Self::Show {
+                last_exe,
+                regulator_service_installed,
+                installed_services,
+                regulator_unit,
+                ..
+            } => vec![
+                super::pair(
+                    "last_exe",
+                    last_exe
+                        .as_ref()
+                        .map(|last_exe| last_exe.display().to_string())
+                        .unwrap_or_default(),
+                ),
+                super::pair("regulator_service_installed", regulator_service_installed),
+                super::pair("installed_services", installed_services.join(",")),
+                super::pair(
+                    "regulator_unit",
+                    regulator_unit
+                        .as_ref()
+                        .map(|regulator_unit| regulator_unit.display().to_string())
+                        .unwrap_or_default(),
+                ),
+            ],
+            Self::Reset { reset } => vec![super::pair("reset", reset)],
+        }
+    }
+}
+
+pub fn show() -> anyhow::Result<MachineOutput> {
+    let config = config::read();
+    let Consistency {
+        version,
+        last_exe,
+        regulator_service_installed,
+        installed_services,
+        regulator_unit,
+        ..
+    } = &config.consistency;
+    let path = project_paths::consistency_json().to_path_buf();
+
+    if !config.tuxvantage.machine() {
+        info!("{} (version {}):", path.display().bold(), version);
+
+        let _guard = log::no_prologue::guard_for(log::Level::Info);
+        let last_exe = last_exe.as_ref().map_or_else(
+            || "not set".italic().to_string(),
+            |last_exe| last_exe.display().bold().to_string(),
+        );
+        let installed_services = if installed_services.is_empty() {
+            "none".italic().to_string()
+        } else {
+            installed_services.join(", ").bold().to_string()
+        };
+
+        info!("{}last executable: {}", super::tab(2), last_exe);
+        info!(
+            "{}regulator service installed: {}",
+            super::tab(2),
+            regulator_service_installed.bold()
+        );
+        info!(
+            "{}installed services: {}",
+            super::tab(2),
+            installed_services
+        );
+
+        if let Some(regulator_unit) = regulator_unit {
+            info!(
+                "{}regulator service unit: {}",
+                super::tab(2),
+                regulator_unit.display().bold()
+            );
+        }
+    }
+
+    Ok(MachineOutput::Show {
+        path,
+        version: *version,
+        last_exe: last_exe.clone(),
+        regulator_service_installed: *regulator_service_installed,
+        installed_services: installed_services.clone(),
+        regulator_unit: regulator_unit.clone(),
+    })
+}
+
+/// Replaces `.consistency.json` with the defaults, forgetting which services were installed and
+/// with which executable.
+pub fn reset() -> anyhow::Result<MachineOutput> {
+    config::write()
+        .consistency
+        .reset()
+        .with_context(|| format!("failed to reset {}", ".consistency.json".bold()))?;
+
+    if !config::machine() {
+        info!("reset {} to the defaults", ".consistency.json".bold());
+    }
+
+    Ok(MachineOutput::Reset { reset: true })
+}
@@ -0,0 +1,370 @@
+use crate::app::{permissions, IntoOptionMachineOutput, Porcelain};
+use crate::config::Config;
+use crate::ext;
+use crate::utils::{self, Names};
+use crate::validation::Severity;
+use crate::{api, config};
+use owo_colors::OwoColorize;
+use std::str::FromStr;
+
+/// The version of [`MachineOutput`]. Monitoring agents scrape it, so fields may only be added
+/// to it. Renaming or removing one bumps this.
+const VERSION: u32 = 1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CheckName {
+    Config,
+    Profile,
+    Access,
+    Battery,
+}
+
+impl CheckName {
+    pub const NAMES: Names<Self> = &[
+        (Self::Config, &["config"]),
+        (Self::Profile, &["profile"]),
+        (Self::Access, &["access"]),
+        (Self::Battery, &["battery"]),
+    ];
+
+    fn name(self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(name, _)| *name == self)
+            .map(|(_, aliases)| aliases[0])
+            .expect("every check has a name")
+    }
+}
+
+impl FromStr for CheckName {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        utils::parse_name(Self::NAMES, "check", s)
+    }
+}
+
+/// How bad the outcome of a check is, from best to worst.
+#[derive(Serialize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Warn => "warn",
+            Self::Fail => "fail",
+        }
+    }
+
+    /// The status as a number, which is also the exit code of `--exit-by-severity`.
+    fn severity(self) -> u8 {
+        self as u8
+    }
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    name: &'static str,
+    status: Status,
+    severity: u8,
+    message: String,
+}
+
+impl Check {
+    fn new(name: CheckName, status: Status, message: impl Into<String>) -> Self {
+        Self {
+            name: name.name(),
+            status,
+            severity: status.severity(),
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct MachineOutput {
+    version: u32,
+
+    /// The worst status of the checks which ran.
+    overall: Status,
+    severity: u8,
+
+    /// The product name of this machine, unless it couldn't be read.
+    product_name: Option<String>,
+
+    /// The name of the profile which would be used, unless there is none.
+    profile: Option<String>,
+    checks: Vec<Check>,
+
+    #[serde(skip)]
+    exit_by_severity: bool,
+}
+
+impl MachineOutput {
+    /// The severity of the worst check with `--exit-by-severity`. Otherwise, only a failed check
+    /// exits with an error.
+    pub fn exit_code(&self) -> i32 {
+        match (self.exit_by_severity, self.overall) {
+            (true, overall) => overall.severity().into(),
+            (false, Status::Fail) => 1,
+            (false, _) => 0,
+        }
+    }
+}
+
+impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
+    fn into_option_machine_output(self) -> Option<MachineOutput> {
+        Some(self)
+    }
+}
+
+impl Porcelain for MachineOutput {
+    fn porcelain(&self) -> Vec<String> {
+        let mut lines = vec![super::pair("overall", self.overall.name())];
+        lines.extend(
+            self.checks
+                .iter()
+                .map(|check| super::pair(check.name, check.status.name())),
+        );
+        lines
+    }
+}
+
+fn check_config() -> Check {
+    let findings = match super::config::findings() {
+        Ok(findings) => findings,
+        Err(error) => {
+            return Check::new(
+                CheckName::Config,
+                Status::Fail,
+                utils::dedup_error_chain_for_humans(&error),
+            )
+        }
+    };
+    let errors = findings
+        .iter()
+        .filter(|finding| finding.severity == Severity::Error)
+        .count();
+    let warnings = findings.len() - errors;
+
+    match (errors, warnings) {
+        (0, 0) => Check::new(CheckName::Config, Status::Ok, "no problems found"),
+        (0, warnings) => Check::new(
+            CheckName::Config,
+            Status::Warn,
+            format!("{} warning(s), see `config check`", warnings),
+        ),
+        (errors, _) => Check::new(
+            CheckName::Config,
+            Status::Fail,
+            format!("{} error(s), see `config check`", errors),
+        ),
+    }
+}
+
+fn check_profile(config: &Config) -> (Check, Option<String>) {
+    let profile = match api::profile(config) {
+        Ok(profile) => profile,
+        Err(error) => {
+            let check = Check::new(
+                CheckName::Profile,
+                Status::Fail,
+                utils::dedup_error_chain_for_humans(&error),
+            );
+
+            return (check, None);
+        }
+    };
+    let name = profile.name.to_string();
+    let check = match config.profiles.matches_machine(&profile) {
+        Some(false) => Check::new(
+            CheckName::Profile,
+            Status::Warn,
+            format!("{} doesn't expect the product name of this machine", name),
+        ),
+        Some(true) | None => Check::new(CheckName::Profile, Status::Ok, format!("using {}", name)),
+    };
+
+    (check, Some(name))
+}
+
+fn check_access() -> Check {
+    if let Err(error) = ext::ensure_not_in_container() {
+        return Check::new(
+            CheckName::Access,
+            Status::Fail,
+            utils::dedup_error_chain_for_humans(&error.source),
+        );
+    }
+
+    let (_, paths) = permissions::detect();
+
+    match paths.iter().find(|path| !path.exists()) {
+        Some(path) => Check::new(
+            CheckName::Access,
+            Status::Fail,
+            format!("{} doesn't exist", path.display()),
+        ),
+        None if paths.iter().all(|path| utils::is_writable(path)) => {
+            Check::new(CheckName::Access, Status::Ok, "the hardware is writable")
+        }
+        None => Check::new(
+            CheckName::Access,
+            Status::Warn,
+            "the hardware isn't writable without root, see `permissions`",
+        ),
+    }
+}
+
+#[cfg(feature = "regulate")]
+fn check_battery() -> Check {
+    match config::ensure_battery() {
+        Ok(()) => Check::new(CheckName::Battery, Status::Ok, "a battery was found"),
+        Err(error) => Check::new(
+            CheckName::Battery,
+            Status::Warn,
+            utils::dedup_error_chain_for_humans(&error),
+        ),
+    }
+}
+
+/// Nothing needs a battery without the `regulate` feature, so there is nothing to check.
+#[cfg(not(feature = "regulate"))]
+fn check_battery() -> Check {
+    Check::new(
+        CheckName::Battery,
+        Status::Ok,
+        "not needed, since this build of tuxvantage can't regulate the battery",
+    )
+}
+
+/// Checks whether this program can work on this machine, as a report stable enough for
+/// monitoring agents to scrape. Only the checks in `only` are run, unless it is empty.
+pub fn doctor(only: Vec<CheckName>, exit_by_severity: bool) -> anyhow::Result<MachineOutput> {
+    let selected = |name| only.is_empty() || only.contains(&name);
+    let config = config::read();
+    let machine = config.tuxvantage.machine();
+    let (profile_check, profile) = check_profile(&config);
+    drop(config);
+
+    let mut checks = Vec::new();
+
+    if selected(CheckName::Config) {
+        debug!("check the config");
+        checks.push(check_config());
+    }
+
+    if selected(CheckName::Profile) {
+        checks.push(profile_check);
+    }
+
+    if selected(CheckName::Access) {
+        debug!("check access to the hardware");
+        checks.push(check_access());
+    }
+
+    if selected(CheckName::Battery) {
+        debug!("check the battery");
+        checks.push(check_battery());
+    }
+
+    let overall = checks
+        .iter()
+        .map(|check| check.status)
+        .max()
+        .unwrap_or(Status::Ok);
+
+    if !machine {
+        for check in &checks {
+            match check.status {
+                Status::Ok => info!("{}: {}", check.name.bold(), check.message),
+                Status::Warn => warn!("{}: {}", check.name.bold(), check.message),
+                Status::Fail => error!("{}: {}", check.name.bold(), check.message),
+            }
+        }
+
+        info!("overall: {}", overall.name().bold());
+    }
+
+    Ok(MachineOutput {
+        version: VERSION,
+        overall,
+        severity: overall.severity(),
+        product_name: config::product_name().ok(),
+        profile,
+        checks,
+        exit_by_severity,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Monitoring agents depend on these field names, so renaming one has to show up here and
+    /// come with a bump of [`VERSION`].
+    #[test]
+    fn schema() {
+        let output = MachineOutput {
+            version: VERSION,
+            overall: Status::Fail,
+            severity: Status::Fail.severity(),
+            product_name: Some("81YK".to_string()),
+            profile: Some("IDEAPAD_15IIL05".to_string()),
+            checks: vec![
+                Check::new(CheckName::Config, Status::Ok, "no problems found"),
+                Check::new(CheckName::Profile, Status::Ok, "using IDEAPAD_15IIL05"),
+                Check::new(
+                    CheckName::Access,
+                    Status::Warn,
+                    "the hardware isn't writable without root, see `permissions`",
+                ),
+                Check::new(CheckName::Battery, Status::Fail, "no battery was found"),
+            ],
+            exit_by_severity: true,
+        };
+
+        insta::assert_snapshot!(serde_json::to_string_pretty(&output).unwrap());
+    }
+
+    #[test]
+    fn schema_without_machine_identity() {
+        let output = MachineOutput {
+            version: VERSION,
+            overall: Status::Ok,
+            severity: Status::Ok.severity(),
+            product_name: None,
+            profile: None,
+            checks: Vec::new(),
+            exit_by_severity: false,
+        };
+
+        insta::assert_snapshot!(serde_json::to_string_pretty(&output).unwrap());
+    }
+
+    #[test]
+    fn exit_codes_follow_the_severity_only_when_asked() {
+        let output = |overall: Status, exit_by_severity| MachineOutput {
+            version: VERSION,
+            overall,
+            severity: overall.severity(),
+            product_name: None,
+            profile: None,
+            checks: Vec::new(),
+            exit_by_severity,
+        };
+
+        assert_eq!(output(Status::Ok, true).exit_code(), 0);
+        assert_eq!(output(Status::Warn, true).exit_code(), 1);
+        assert_eq!(output(Status::Fail, true).exit_code(), 2);
+        assert_eq!(output(Status::Ok, false).exit_code(), 0);
+        assert_eq!(output(Status::Warn, false).exit_code(), 0);
+        assert_eq!(output(Status::Fail, false).exit_code(), 1);
+    }
+}
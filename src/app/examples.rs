@@ -0,0 +1,61 @@
+use crate::app::IntoOptionMachineOutput;
+use crate::examples::{self, Example};
+use crate::{config, log};
+use itertools::Itertools;
+use owo_colors::OwoColorize;
+
+#[derive(Serialize)]
+pub struct MachineOutput {
+    examples: Vec<&'static Example>,
+}
+
+impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
+    fn into_option_machine_output(self) -> Option<MachineOutput> {
+        Some(self)
+    }
+}
+
+/// Highlights the example as a command line: subcommands in green, flags in cyan, and values as
+/// they are.
+fn highlight(example: &Example) -> String {
+    let subcommands = example.command.split(' ').count();
+    let mut seen_subcommands = 0;
+    let args = example.args.iter().map(|arg| {
+        if arg.starts_with('-') {
+            arg.cyan().to_string()
+        } else if seen_subcommands < subcommands {
+            seen_subcommands += 1;
+            arg.green().to_string()
+        } else {
+            arg.to_string()
+        }
+    });
+
+    format!("{} {}", "tuxvantage".bold(), args.format(" "))
+}
+
+pub fn examples(command: Vec<String>) -> anyhow::Result<MachineOutput> {
+    let command = command.join(" ");
+    let examples = examples::of(&command).collect::<Vec<_>>();
+
+    anyhow::ensure!(
+        !examples.is_empty(),
+        "there are no examples of {}",
+        command.bold()
+    );
+
+    if !config::machine() {
+        let _guard = log::no_prologue::guard_for(log::Level::Info);
+
+        for (index, example) in examples.iter().enumerate() {
+            if index != 0 {
+                info!("");
+            }
+
+            info!("{}", example.description);
+            info!("{}$ {}", super::tab(2), highlight(example));
+        }
+    }
+
+    Ok(MachineOutput { examples })
+}
@@ -0,0 +1,77 @@
+use crate::app::IntoOptionMachineOutput;
+use crate::history::{self, Change};
+use crate::utils::Table;
+use crate::{config, format, utils};
+
+#[derive(Serialize)]
+#[serde(transparent)]
+pub struct MachineOutput(Vec<Change>);
+
+impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
+    fn into_option_machine_output(self) -> Option<MachineOutput> {
+        Some(self)
+    }
+}
+
+pub fn history(
+    limit: usize,
+    columns: Vec<String>,
+    no_header: bool,
+    wide: bool,
+) -> anyhow::Result<MachineOutput> {
+    let changes = history::get(limit)?;
+
+    if !config::machine() {
+        let mut table = Table::new(&[
+            "time",
+            "setting",
+            "old",
+            "new",
+            "initiator",
+            "ec_cooldown",
+            "command",
+        ]);
+
+        for change in &changes {
+            table.push(vec![
+                format::utc(change.time),
+                change.setting.clone(),
+                change.old.clone(),
+                change.new.clone(),
+                change.initiator.name().to_string(),
+                if change.within_ec_cooldown {
+                    "within"
+                } else {
+                    ""
+                }
+                .to_string(),
+                format!("tuxvantage {}", change.command),
+            ]);
+        }
+
+        table.select(&columns)?;
+
+        if changes.is_empty() {
+            info!("no changes have been recorded yet");
+        } else {
+            info!("the last {} change(s) made by tuxvantage:", changes.len());
+
+            let max_width = if wide {
+                None
+            } else {
+                utils::terminal_size().map(|(width, _)| width)
+            };
+
+            for line in table.render(!no_header, max_width) {
+                utils::print_line(line);
+            }
+        }
+
+        info!(
+            "changes made outside of tuxvantage, such as by other programs or the firmware, can't be \
+             recorded"
+        );
+    }
+
+    Ok(MachineOutput(changes))
+}
@@ -1,19 +1,94 @@
+pub mod apply;
 pub mod battery_conservation;
+pub mod battery_threshold;
+pub mod completions;
+pub mod config;
+pub mod consistency;
+pub mod doctor;
+pub mod examples;
+pub mod history;
+pub mod paths;
+pub mod permissions;
 pub mod profiles;
 pub mod rapid_charge;
+pub mod self_check_service;
+#[cfg(feature = "testing")]
+pub mod selftest;
+pub mod status;
 pub mod system_performance;
+pub mod templates;
+pub mod with;
 
+use crate::anyhow_with_tip::TippingAnyhowResultExt;
+use crate::config::HandlerResolution;
+use crate::ext::{self, NotSettledError};
+use crate::types::HumanDuration;
+use crate::{anyhow_with_tip, log};
 use ideapad::{Handler, SystemPerformanceMode};
 use owo_colors::OwoColorize;
+use serde::Serialize;
+use std::time::Duration;
+use std::{fmt, thread};
 
-fn format_handler(handler: Handler) -> String {
+fn handler_name(handler: Handler) -> &'static str {
     match handler {
         Handler::Switch => "switch",
         Handler::Ignore => "ignore",
         Handler::Error => "error",
     }
-    .bold()
-    .to_string()
+}
+
+fn format_handler(handler: Handler) -> String {
+    handler_name(handler).bold().to_string()
+}
+
+fn print_handler_resolution(what: &str, resolution: &HandlerResolution) {
+    info!("the handler for {} is resolved from:", what);
+
+    let _guard = log::no_prologue::guard_for(log::Level::Info);
+
+    for candidate in resolution.candidates() {
+        let value = candidate
+            .value
+            .map_or_else(|| "not set".italic().to_string(), format_handler);
+
+        if candidate.used {
+            info!(
+                "{}{}: {} {}",
+                tab(2),
+                candidate.source.describe(),
+                value,
+                "(used)".green()
+            );
+        } else {
+            info!("{}{}: {}", tab(2), candidate.source.describe(), value);
+        }
+    }
+}
+
+/// Each source as a `source=handler` pair, empty if it wasn't set, followed by the source which
+/// was used.
+fn handler_resolution_porcelain(resolution: &HandlerResolution) -> Vec<String> {
+    let mut lines = resolution
+        .candidates()
+        .iter()
+        .map(|candidate| {
+            pair(
+                candidate.source.name(),
+                candidate.value.map_or("", handler_name),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(used) = resolution
+        .candidates()
+        .iter()
+        .find(|candidate| candidate.used)
+    {
+        lines.push(pair("used", used.source.name()));
+    }
+
+    lines
 }
 
 fn format_system_performance_mode(mode: SystemPerformanceMode) -> String {
@@ -22,7 +97,7 @@ fn format_system_performance_mode(mode: SystemPerformanceMode) -> String {
         .to_string()
 }
 
-fn format_system_performance_mode_plain(mode: SystemPerformanceMode) -> &'static str {
+pub(crate) fn format_system_performance_mode_plain(mode: SystemPerformanceMode) -> &'static str {
     match mode {
         SystemPerformanceMode::ExtremePerformance => "extreme performance",
         SystemPerformanceMode::IntelligentCooling => "intelligent cooling",
@@ -30,7 +105,101 @@ fn format_system_performance_mode_plain(mode: SystemPerformanceMode) -> &'static
     }
 }
 
-fn tab(count: usize) -> String {
+/// How long to wait for the firmware after changing a setting before reading it back, and whether
+/// to fail if it didn't stick, from `--settle` and `--strict`.
+#[derive(Debug, Copy, Clone)]
+pub struct Settle {
+    pub after: Duration,
+    pub strict: bool,
+}
+
+impl Settle {
+    /// `None` if `--settle` wasn't given, which skips reading the setting back.
+    pub fn new(after: Option<HumanDuration>, strict: bool) -> Option<Self> {
+        after.map(|after| Self {
+            after: after.0,
+            strict,
+        })
+    }
+}
+
+/// A setting as read back right after changing it, and again after waiting with `--settle`.
+#[derive(Serialize, Debug)]
+pub struct Readings<T> {
+    pub expected: T,
+    pub immediate: T,
+    pub settled: T,
+    pub stuck: bool,
+}
+
+/// Reads a setting back with `read` right after changing it to `expected`, then again after
+/// waiting for the firmware to settle, since some firmware reverts changes shortly after accepting
+/// them. Warns if the change didn't stick, or fails if `settle` is strict. `name` names the values
+/// of the setting in messages.
+fn verify_settled<T: PartialEq + Copy>(
+    setting: &'static str,
+    expected: T,
+    settle: Settle,
+    name: impl Fn(T) -> &'static str,
+    mut read: impl FnMut() -> anyhow_with_tip::Result<T>,
+) -> anyhow_with_tip::Result<Readings<T>> {
+    debug!("read {} back right after changing it", setting);
+    let immediate = read()?;
+
+    debug!(
+        "wait {} for the firmware to settle",
+        HumanDuration(settle.after)
+    );
+    thread::sleep(settle.after);
+    let settled = read()?;
+    let stuck = immediate == expected && settled == expected;
+    let readings = Readings {
+        expected,
+        immediate,
+        settled,
+        stuck,
+    };
+
+    if stuck {
+        if !crate::machine::enabled() {
+            info!(
+                "{} is still {} after {}",
+                setting,
+                name(expected).bold(),
+                HumanDuration(settle.after)
+            );
+        }
+
+        return Ok(readings);
+    }
+
+    let error = NotSettledError {
+        setting,
+        expected: name(expected),
+        immediate: name(immediate),
+        settled: name(settled),
+        after: HumanDuration(settle.after),
+    };
+
+    if settle.strict {
+        return Err(anyhow::Error::new(error)).tip(ext::NOT_SETTLED_TIP);
+    }
+
+    warn_with_tip!(error, ext::NOT_SETTLED_TIP);
+
+    Ok(readings)
+}
+
+/// The name of an enabled or disabled setting, as in messages of [`verify_settled`].
+fn toggle_name(enabled: bool) -> &'static str {
+    if enabled {
+        "enabled"
+    } else {
+        "disabled"
+    }
+}
+
+pub fn tab(count: usize) -> String {
     const TAB: &str = "    ";
 
     TAB.repeat(count - 1)
@@ -39,10 +208,91 @@ fn tab(count: usize) -> String {
 #[derive(Serialize)]
 #[serde(untagged)]
 pub enum MachineOutput {
+    Apply(apply::MachineOutput),
     BatteryConservation(battery_conservation::MachineOutput),
+    BatteryThreshold(battery_threshold::MachineOutput),
+    Config(config::MachineOutput),
+    Consistency(consistency::MachineOutput),
+    Doctor(doctor::MachineOutput),
+    Examples(examples::MachineOutput),
+    History(history::MachineOutput),
+    Paths(paths::MachineOutput),
+    Permissions(permissions::MachineOutput),
     Profiles(profiles::MachineOutput),
     RapidCharge(rapid_charge::MachineOutput),
+    SelfCheckService(self_check_service::MachineOutput),
+    #[cfg(feature = "testing")]
+    Selftest(selftest::MachineOutput),
+    Status(status::MachineOutput),
     SystemPerformance(system_performance::MachineOutput),
+    Templates(templates::MachineOutput),
+    With(with::MachineOutput),
+}
+
+/// The output of `--porcelain`, as lines for shell scripts. These formats are documented as
+/// stable, so they must not change.
+pub trait Porcelain {
+    fn porcelain(&self) -> Vec<String>;
+}
+
+/// The human output of `command` rendered with its template from `[templates]`, if it has one. An
+/// invalid template is warned about and ignored, so that the usual output is printed instead.
+fn rendered_template(command: &str, output: &impl Serialize) -> Option<String> {
+    let template = crate::config::read()
+        .tuxvantage
+        .template(command)?
+        .to_string();
+
+    match crate::templates::render(command, &template, output) {
+        Ok(rendered) => Some(rendered),
+        Err(error) => {
+            warn!("ignoring the template of {}: {:#}", command.bold(), error);
+            None
+        }
+    }
+}
+
+/// Prints the human output of `command` with its template, returning whether it has one.
+fn print_template(command: &str, output: &impl Serialize) -> bool {
+    match rendered_template(command, output) {
+        Some(rendered) => {
+            let _guard = log::no_prologue::guard_for(log::Level::Info);
+            info!("{}", rendered);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Formats a `key=value` line of porcelain output.
+fn pair(key: &str, value: impl fmt::Display) -> String {
+    format!("{}={}", key, value)
+}
+
+impl Porcelain for MachineOutput {
+    fn porcelain(&self) -> Vec<String> {
+        match self {
+            Self::BatteryConservation(output) => output.porcelain(),
+            Self::BatteryThreshold(output) => output.porcelain(),
+            Self::Consistency(output) => output.porcelain(),
+            Self::Doctor(output) => output.porcelain(),
+            Self::Paths(output) => output.porcelain(),
+            Self::Profiles(output) => output.porcelain(),
+            Self::RapidCharge(output) => output.porcelain(),
+            #[cfg(feature = "testing")]
+            Self::Selftest(output) => output.porcelain(),
+            Self::Status(output) => output.porcelain(),
+            Self::SystemPerformance(output) => output.porcelain(),
+            Self::Templates(output) => output.porcelain(),
+            Self::With(output) => output.porcelain(),
+            Self::Apply(_)
+            | Self::Config(_)
+            | Self::Examples(_)
+            | Self::History(_)
+            | Self::Permissions(_)
+            | Self::SelfCheckService(_) => Vec::new(),
+        }
+    }
 }
 
 pub trait IntoOptionMachineOutput<MO> {
@@ -55,7 +305,20 @@ impl<MO> IntoOptionMachineOutput<MO> for () {
     }
 }
 
+impl<MO> IntoOptionMachineOutput<MO> for Option<MO> {
+    fn into_option_machine_output(self) -> Option<MO> {
+        self
+    }
+}
+
 impl MachineOutput {
+    pub fn apply<T>(value: T) -> Option<Self>
+    where
+        T: IntoOptionMachineOutput<apply::MachineOutput>,
+    {
+        value.into_option_machine_output().map(Self::Apply)
+    }
+
     pub fn battery_conservation<T>(value: T) -> Option<Self>
     where
         T: IntoOptionMachineOutput<battery_conservation::MachineOutput>,
@@ -65,6 +328,64 @@ impl MachineOutput {
             .map(Self::BatteryConservation)
     }
 
+    pub fn battery_threshold<T>(value: T) -> Option<Self>
+    where
+        T: IntoOptionMachineOutput<battery_threshold::MachineOutput>,
+    {
+        value
+            .into_option_machine_output()
+            .map(Self::BatteryThreshold)
+    }
+
+    pub fn config<T>(value: T) -> Option<Self>
+    where
+        T: IntoOptionMachineOutput<config::MachineOutput>,
+    {
+        value.into_option_machine_output().map(Self::Config)
+    }
+
+    pub fn consistency<T>(value: T) -> Option<Self>
+    where
+        T: IntoOptionMachineOutput<consistency::MachineOutput>,
+    {
+        value.into_option_machine_output().map(Self::Consistency)
+    }
+
+    pub fn doctor<T>(value: T) -> Option<Self>
+    where
+        T: IntoOptionMachineOutput<doctor::MachineOutput>,
+    {
+        value.into_option_machine_output().map(Self::Doctor)
+    }
+
+    pub fn examples<T>(value: T) -> Option<Self>
+    where
+        T: IntoOptionMachineOutput<examples::MachineOutput>,
+    {
+        value.into_option_machine_output().map(Self::Examples)
+    }
+
+    pub fn history<T>(value: T) -> Option<Self>
+    where
+        T: IntoOptionMachineOutput<history::MachineOutput>,
+    {
+        value.into_option_machine_output().map(Self::History)
+    }
+
+    pub fn paths<T>(value: T) -> Option<Self>
+    where
+        T: IntoOptionMachineOutput<paths::MachineOutput>,
+    {
+        value.into_option_machine_output().map(Self::Paths)
+    }
+
+    pub fn permissions<T>(value: T) -> Option<Self>
+    where
+        T: IntoOptionMachineOutput<permissions::MachineOutput>,
+    {
+        value.into_option_machine_output().map(Self::Permissions)
+    }
+
     pub fn profiles<T>(value: T) -> Option<Self>
     where
         T: IntoOptionMachineOutput<profiles::MachineOutput>,
@@ -79,6 +400,30 @@ impl MachineOutput {
         value.into_option_machine_output().map(Self::RapidCharge)
     }
 
+    pub fn self_check_service<T>(value: T) -> Option<Self>
+    where
+        T: IntoOptionMachineOutput<self_check_service::MachineOutput>,
+    {
+        value
+            .into_option_machine_output()
+            .map(Self::SelfCheckService)
+    }
+
+    #[cfg(feature = "testing")]
+    pub fn selftest<T>(value: T) -> Option<Self>
+    where
+        T: IntoOptionMachineOutput<selftest::MachineOutput>,
+    {
+        value.into_option_machine_output().map(Self::Selftest)
+    }
+
+    pub fn status<T>(value: T) -> Option<Self>
+    where
+        T: IntoOptionMachineOutput<status::MachineOutput>,
+    {
+        value.into_option_machine_output().map(Self::Status)
+    }
+
     pub fn system_performance<T>(value: T) -> Option<Self>
     where
         T: IntoOptionMachineOutput<system_performance::MachineOutput>,
@@ -87,4 +432,97 @@ impl MachineOutput {
             .into_option_machine_output()
             .map(Self::SystemPerformance)
     }
+
+    pub fn templates<T>(value: T) -> Option<Self>
+    where
+        T: IntoOptionMachineOutput<templates::MachineOutput>,
+    {
+        value.into_option_machine_output().map(Self::Templates)
+    }
+
+    pub fn with<T>(value: T) -> Option<Self>
+    where
+        T: IntoOptionMachineOutput<with::MachineOutput>,
+    {
+        value.into_option_machine_output().map(Self::With)
+    }
+
+    /// The code this program should exit with, which is the one of the command `with` ran or
+    /// the outcome of `doctor` or `selftest`.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Doctor(output) => output.exit_code(),
+            #[cfg(feature = "testing")]
+            Self::Selftest(output) => output.exit_code(),
+            Self::With(output) => output.exit_code(),
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTLE: Settle = Settle {
+        after: Duration::ZERO,
+        strict: false,
+    };
+
+    /// Reads back `readings` one after another, like firmware which reports them in turn.
+    fn reads(readings: [bool; 2]) -> impl FnMut() -> anyhow_with_tip::Result<bool> {
+        let mut readings = readings.into_iter();
+
+        move || Ok(readings.next().expect("read back more than twice"))
+    }
+
+    #[test]
+    fn settings_which_stick_are_stuck() {
+        let readings = verify_settled(
+            "rapid charging",
+            true,
+            SETTLE,
+            toggle_name,
+            reads([true, true]),
+        )
+        .unwrap();
+
+        assert!(readings.stuck);
+    }
+
+    #[test]
+    fn reverted_settings_are_only_warned_about() {
+        for reverted in [[true, false], [false, true], [false, false]] {
+            let readings =
+                verify_settled("rapid charging", true, SETTLE, toggle_name, reads(reverted))
+                    .unwrap();
+
+            assert!(!readings.stuck, "{:?}", reverted);
+            assert_eq!(
+                [readings.immediate, readings.settled],
+                reverted,
+                "{:?}",
+                reverted
+            );
+        }
+    }
+
+    #[test]
+    fn reverted_settings_fail_when_strict() {
+        let settle = Settle {
+            strict: true,
+            ..SETTLE
+        };
+        let error = verify_settled(
+            "rapid charging",
+            true,
+            settle,
+            toggle_name,
+            reads([true, false]),
+        )
+        .unwrap_err();
+
+        assert_eq!(ext::error_code(&error.source), Some("not_settled"));
+        assert!(error.tip.is_some());
+    }
 }
@@ -0,0 +1,351 @@
+use crate::app::{IntoOptionMachineOutput, Porcelain};
+use crate::{anyhow_with_tip, config, ext, log, project_paths, utils, TippingAnyhowResultExt};
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize)]
+pub struct PathOutput {
+    path: PathBuf,
+    writable: bool,
+}
+
+impl PathOutput {
+    fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            writable: utils::is_writable(path),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Resolved {
+    config_dir: PathOutput,
+    profiles_dir: PathOutput,
+    tuxvantage_toml: PathOutput,
+    state_dir: PathOutput,
+    runtime_dir: PathOutput,
+    consistency_json: PathOutput,
+    read_only: bool,
+}
+
+/// A file or directory which the invoking user can't write or which isn't owned by the owner of
+/// the config directory.
+#[derive(Serialize)]
+pub struct Entry {
+    path: PathBuf,
+    uid: u32,
+    gid: u32,
+    writable: bool,
+    fixed: bool,
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    owner_uid: u32,
+    owner_gid: u32,
+    entries: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum MachineOutput {
+    Resolved(Resolved),
+    Check(Check),
+}
+
+impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
+    fn into_option_machine_output(self) -> Option<MachineOutput> {
+        Some(self)
+    }
+}
+
+impl Porcelain for MachineOutput {
+    fn porcelain(&self) -> Vec<String> {
+        match self {
+            Self::Resolved(resolved) => resolved.porcelain(),
+            Self::Check(check) => check.porcelain(),
+        }
+    }
+}
+
+impl Porcelain for Resolved {
+    fn porcelain(&self) -> Vec<String> {
+        vec![
+            super::pair("config_dir", self.config_dir.path.display()),
+            super::pair("profiles_dir", self.profiles_dir.path.display()),
+            super::pair("tuxvantage_toml", self.tuxvantage_toml.path.display()),
+            super::pair("state_dir", self.state_dir.path.display()),
+            super::pair("runtime_dir", self.runtime_dir.path.display()),
+            super::pair("consistency_json", self.consistency_json.path.display()),
+            super::pair("read_only", self.read_only),
+        ]
+    }
+}
+
+impl Porcelain for Check {
+    fn porcelain(&self) -> Vec<String> {
+        let mut lines = vec![super::pair(
+            "owner",
+            format_args!("{}:{}", self.owner_uid, self.owner_gid),
+        )];
+        lines.extend(self.entries.iter().map(|entry| {
+            let key = if entry.fixed { "fixed" } else { "mismatched" };
+            super::pair(key, entry.path.display())
+        }));
+        lines
+    }
+}
+
+pub fn get() -> anyhow::Result<MachineOutput> {
+    let paths: [(&str, &Path); 6] = [
+        ("Config Directory", project_paths::config_dir()),
+        ("Profiles Directory", project_paths::profiles_dir()),
+        ("tuxvantage.toml", project_paths::tuxvantage_toml()),
+        ("State Directory", project_paths::state_dir()),
+        ("Runtime Directory", project_paths::runtime_dir()),
+        (".consistency.json", project_paths::consistency_json()),
+    ];
+    let read_only = config::read_only();
+
+    if !config::machine() {
+        if read_only {
+            info!("resolved paths (configuration is {}):", "read-only".bold());
+        } else {
+            info!("resolved paths:");
+        }
+
+        let _guard = log::no_prologue::guard_for(log::Level::Info);
+
+        for (name, path) in paths {
+            let epilogue = if utils::is_writable(path) {
+                "(writable)"
+            } else {
+                "(not writable)"
+            };
+
+            info!(
+                "{}{} {} {}",
+                super::tab(2),
+                name.bold(),
+                path.display(),
+                epilogue.italic()
+            );
+        }
+    }
+
+    let [config_dir, profiles_dir, tuxvantage_toml, state_dir, runtime_dir, consistency_json] =
+        paths.map(|(_, path)| PathOutput::new(path));
+
+    Ok(MachineOutput::Resolved(Resolved {
+        config_dir,
+        profiles_dir,
+        tuxvantage_toml,
+        state_dir,
+        runtime_dir,
+        consistency_json,
+        read_only,
+    }))
+}
+
+/// Collects the entries under `path`, including itself, which `check` lists. Symbolic links aren't
+/// followed, and directories which can't be read are only reported by themselves.
+fn audit(path: &Path, owner_uid: u32, entries: &mut Vec<Entry>) {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(error) => {
+            debug!(
+                "failed to get the metadata of '{}': {}",
+                path.display(),
+                error
+            );
+            return;
+        }
+    };
+    let writable = metadata.file_type().is_symlink() || utils::is_writable(path);
+
+    if !writable || metadata.uid() != owner_uid {
+        entries.push(Entry {
+            path: path.to_path_buf(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            writable,
+            fixed: false,
+        });
+    }
+
+    if !metadata.is_dir() {
+        return;
+    }
+
+    match fs::read_dir(path) {
+        Ok(children) => {
+            let mut children = children
+                .flatten()
+                .map(|child| child.path())
+                .collect::<Vec<_>>();
+            children.sort();
+
+            for child in children {
+                audit(&child, owner_uid, entries);
+            }
+        }
+        Err(error) => debug!(
+            "failed to read the directory '{}': {}",
+            path.display(),
+            error
+        ),
+    }
+}
+
+/// Lists the files and directories in the config, profiles and state directories which can't be
+/// written or which aren't owned by the owner of the config directory, as happens after running
+/// this program with `sudo` once. With `fix`, they are given back to that owner, which needs root.
+pub fn check(fix: bool) -> anyhow_with_tip::Result<MachineOutput> {
+    if fix && !utils::is_root() {
+        return Err(anyhow::anyhow!(
+            "giving the entries back to the owner of the config directory needs root"
+        ))
+        .tip(ext::PATHS_FIX_ROOT_TIP);
+    }
+
+    let config_dir = project_paths::config_dir();
+    let metadata = fs::metadata(config_dir)
+        .with_context(|| format!("failed to get the owner of {}", config_dir.display().bold()))
+        .no_tip()?;
+    let (owner_uid, owner_gid) = (metadata.uid(), metadata.gid());
+    let mut entries = Vec::new();
+
+    for dir in project_paths::owned_dirs() {
+        debug!("audit '{}'", dir.display());
+        audit(dir, owner_uid, &mut entries);
+    }
+
+    if fix {
+        for entry in &mut entries {
+            std::os::unix::fs::lchown(&entry.path, Some(owner_uid), Some(owner_gid))
+                .with_context(|| {
+                    format!(
+                        "failed to change the owner of {}",
+                        entry.path.display().bold()
+                    )
+                })
+                .no_tip()?;
+            entry.fixed = true;
+        }
+    }
+
+    if !config::machine() {
+        if entries.is_empty() {
+            info!(
+                "everything is writable and owned by the owner of {} ({}:{})",
+                config_dir.display().bold(),
+                owner_uid,
+                owner_gid
+            );
+        } else {
+            if fix {
+                info!(
+                    "gave these back to the owner of {} ({}:{}):",
+                    config_dir.display().bold(),
+                    owner_uid,
+                    owner_gid
+                );
+            } else {
+                warn!(
+                    "these aren't writable or aren't owned by the owner of {} ({}:{}):",
+                    config_dir.display().bold(),
+                    owner_uid,
+                    owner_gid
+                );
+            }
+
+            let _guard = log::no_prologue::guard_for(log::Level::Info);
+
+            for entry in &entries {
+                let epilogue = format!(
+                    "(owned by {}:{}{})",
+                    entry.uid,
+                    entry.gid,
+                    if entry.writable { "" } else { ", not writable" }
+                );
+
+                info!(
+                    "{}{} {}",
+                    super::tab(2),
+                    entry.path.display(),
+                    epilogue.italic()
+                );
+            }
+        }
+
+        if !fix && !entries.is_empty() {
+            info!(
+                "run {} to fix them",
+                "sudo tuxvantage paths --check --fix".bold()
+            );
+        }
+    }
+
+    Ok(MachineOutput::Check(Check {
+        owner_uid,
+        owner_gid,
+        entries,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::Sandbox;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// The paths `audit` lists under the config directory of `sandbox`, relative to it.
+    fn audited(sandbox: &Sandbox, owner_uid: u32) -> Vec<(PathBuf, bool)> {
+        let mut entries = Vec::new();
+        audit(&sandbox.path("config"), owner_uid, &mut entries);
+
+        entries
+            .into_iter()
+            .map(|entry| {
+                let path = entry.path.strip_prefix(sandbox.root()).unwrap();
+                (path.to_path_buf(), entry.writable)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn read_only_entries_are_listed() {
+        let sandbox = Sandbox::new().unwrap();
+        let read_only = sandbox.path("config/tuxvantage.toml");
+        fs::write(&read_only, "").unwrap();
+        fs::write(sandbox.path("config/writable.toml"), "").unwrap();
+        fs::set_permissions(&read_only, fs::Permissions::from_mode(0o444)).unwrap();
+        let owner_uid = fs::metadata(sandbox.path("config")).unwrap().uid();
+
+        // root can write anything, so only the permission bits of other users are reported
+        let expected = match utils::is_root() {
+            true => Vec::new(),
+            false => vec![(PathBuf::from("config/tuxvantage.toml"), false)],
+        };
+
+        assert_eq!(audited(&sandbox, owner_uid), expected);
+    }
+
+    #[test]
+    fn entries_of_other_owners_are_listed() {
+        let sandbox = Sandbox::new().unwrap();
+        fs::create_dir(sandbox.path("config/profiles")).unwrap();
+        fs::write(sandbox.path("config/profiles/custom.toml"), "").unwrap();
+        let other_uid = fs::metadata(sandbox.path("config")).unwrap().uid() + 1;
+
+        assert_eq!(
+            audited(&sandbox, other_uid),
+            ["config", "config/profiles", "config/profiles/custom.toml"]
+                .map(|path| (PathBuf::from(path), true)),
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use crate::app::IntoOptionMachineOutput;
+use crate::{config, log, thresholds, utils};
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs};
+
+const GROUP: &str = "tuxvantage";
+const ACPI_CALL: &str = "/proc/acpi/call";
+const IDEAPAD_ACPI_DEVICES: &str = "/sys/bus/platform/drivers/ideapad_acpi";
+const UDEV_RULE_PATH: &str = "/etc/udev/rules.d/99-tuxvantage.rules";
+const SUDOERS_PATH: &str = "/etc/sudoers.d/tuxvantage";
+
+#[derive(Serialize, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// Talks to the firmware through the `acpi_call` kernel module. Its file is in procfs, which
+    /// udev can't change the permissions of, so running as root through sudo is the only way.
+    AcpiCall,
+
+    /// Uses the attributes of the `ideapad_acpi` kernel driver in sysfs.
+    Sysfs,
+}
+
+#[derive(Serialize)]
+pub struct RequiredPath {
+    path: PathBuf,
+    exists: bool,
+    writable: bool,
+}
+
+impl RequiredPath {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            exists: path.exists(),
+            writable: utils::is_writable(&path),
+            path,
+        }
+    }
+}
+
+#[derive(Serialize, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum SnippetKind {
+    UdevRule,
+    Sudoers,
+    Command,
+}
+
+#[derive(Serialize)]
+pub struct Snippet {
+    kind: SnippetKind,
+
+    /// Where the snippet should be installed, if it is a file.
+    path: Option<&'static str>,
+    contents: String,
+}
+
+#[derive(Serialize)]
+pub struct MachineOutput {
+    backend: Backend,
+    required_paths: Vec<RequiredPath>,
+    snippets: Vec<Snippet>,
+    installed: bool,
+}
+
+impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
+    fn into_option_machine_output(self) -> Option<MachineOutput> {
+        Some(self)
+    }
+}
+
+/// The `conservation_mode` attribute of every `ideapad_acpi` device.
+fn sysfs_attributes() -> Vec<PathBuf> {
+    let devices = match Path::new(IDEAPAD_ACPI_DEVICES).read_dir() {
+        Ok(devices) => devices,
+        Err(error) => {
+            debug!("failed to read {}: {}", IDEAPAD_ACPI_DEVICES, error);
+            return Vec::new();
+        }
+    };
+
+    devices
+        .flatten()
+        .map(|device| device.path().join("conservation_mode"))
+        .filter(|attribute| attribute.exists())
+        .collect()
+}
+
+/// How the hardware is accessed on this machine, and the paths which need to be writable for it.
+/// The native threshold attributes are included with the sysfs backend, since the same udev rule
+/// file grants access to them.
+pub fn detect() -> (Backend, Vec<PathBuf>) {
+    let mut attributes = sysfs_attributes();
+
+    if Path::new(ACPI_CALL).exists() || attributes.is_empty() {
+        (Backend::AcpiCall, vec![PathBuf::from(ACPI_CALL)])
+    } else {
+        if let Ok(battery) = thresholds::Battery::detect() {
+            attributes.extend(battery.attributes());
+        }
+
+        (Backend::Sysfs, attributes)
+    }
+}
+
+/// The user to grant access to, which is the one who ran sudo if it was used.
+fn user() -> String {
+    env::var("SUDO_USER")
+        .or_else(|_| env::var("USER"))
+        .unwrap_or_else(|_| "<user>".to_string())
+}
+
+fn snippets(backend: Backend) -> anyhow::Result<Vec<Snippet>> {
+    let mut snippets = vec![Snippet {
+        kind: SnippetKind::Command,
+        path: None,
+        contents: format!(
+            "groupadd --force --system {group} && usermod --append --groups {group} {user}",
+            group = GROUP,
+            user = user()
+        ),
+    }];
+
+    match backend {
+        Backend::AcpiCall => {
+            let exe = env::current_exe().context("failed to get current path to executable")?;
+
+            snippets.push(Snippet {
+                kind: SnippetKind::Sudoers,
+                path: Some(SUDOERS_PATH),
+                contents: format!("%{} ALL=(root) NOPASSWD: {}\n", GROUP, exe.display()),
+            })
+        }
+        Backend::Sysfs => {
+            let mut contents = format!(
+                "ACTION==\"add\", SUBSYSTEM==\"platform\", DRIVER==\"ideapad_acpi\", \
+                 RUN+=\"/bin/chgrp {group} /sys%p/conservation_mode\", \
+                 RUN+=\"/bin/chmod g+w /sys%p/conservation_mode\"\n",
+                group = GROUP
+            );
+
+            if thresholds::Battery::detect().is_ok() {
+                contents.push_str(&format!(
+                    "ACTION==\"add\", SUBSYSTEM==\"power_supply\", ATTR{{type}}==\"Battery\", \
+                     RUN+=\"/bin/chgrp {group} /sys%p/{start} /sys%p/{end}\", \
+                     RUN+=\"/bin/chmod g+w /sys%p/{start} /sys%p/{end}\"\n",
+                    group = GROUP,
+                    start = thresholds::START_ATTRIBUTE,
+                    end = thresholds::END_ATTRIBUTE
+                ));
+            }
+
+            snippets.push(Snippet {
+                kind: SnippetKind::UdevRule,
+                path: Some(UDEV_RULE_PATH),
+                contents,
+            })
+        }
+    }
+
+    Ok(snippets)
+}
+
+fn run(program: &str, args: &[&str]) -> anyhow::Result<()> {
+    let command = || format!("{} {}", program, args.join(" "));
+    let successful = Command::new(program)
+        .args(args)
+        .status()
+        .with_context(|| format!("failed to run {}", command().bold()))?
+        .success();
+
+    anyhow::ensure!(successful, "running {} wasn't successful", command().bold());
+
+    Ok(())
+}
+
+fn install(snippets: &[Snippet]) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        utils::is_root(),
+        "installing the permissions needs to be done as root"
+    );
+
+    for snippet in snippets {
+        match (snippet.kind, snippet.path) {
+            (SnippetKind::Command, _) => {
+                run("groupadd", &["--force", "--system", GROUP])?;
+                run("usermod", &["--append", "--groups", GROUP, &user()])?;
+            }
+            (SnippetKind::Sudoers, Some(path)) => {
+                fs::write(path, &snippet.contents)
+                    .with_context(|| format!("failed to write {}", path.bold()))?;
+                fs::set_permissions(path, fs::Permissions::from_mode(0o440))
+                    .with_context(|| format!("failed to set the permissions of {}", path.bold()))?;
+
+                // a broken sudoers file can lock everyone out of sudo, so never leave one behind
+                if let Err(error) = run("visudo", &["--check", "--file", path]) {
+                    let _ = fs::remove_file(path);
+                    return Err(error.context("the generated sudoers file was invalid"));
+                }
+            }
+            (SnippetKind::UdevRule, Some(path)) => {
+                fs::write(path, &snippet.contents)
+                    .with_context(|| format!("failed to write {}", path.bold()))?;
+                run("udevadm", &["control", "--reload"])?;
+                run("udevadm", &["trigger", "--subsystem-match=platform"])?;
+
+                if snippet.contents.contains("power_supply") {
+                    run("udevadm", &["trigger", "--subsystem-match=power_supply"])?;
+                }
+            }
+            (_, None) => unreachable!("file snippets always have a path"),
+        }
+    }
+
+    Ok(())
+}
+
+pub fn permissions(do_install: bool) -> anyhow::Result<MachineOutput> {
+    let (backend, paths) = detect();
+    let required_paths = paths.into_iter().map(RequiredPath::new).collect::<Vec<_>>();
+    let snippets = snippets(backend)?;
+
+    if do_install {
+        install(&snippets)?;
+    }
+
+    if !config::machine() {
+        let backend_name = match backend {
+            Backend::AcpiCall => "acpi_call",
+            Backend::Sysfs => "sysfs",
+        };
+        info!("the {} backend needs access to:", backend_name.bold());
+
+        {
+            let _guard = log::no_prologue::guard_for(log::Level::Info);
+
+            for required_path in &required_paths {
+                let epilogue = match (required_path.exists, required_path.writable) {
+                    (false, _) => "(doesn't exist)",
+                    (true, true) => "(writable)",
+                    (true, false) => "(not writable)",
+                };
+
+                info!(
+                    "{}{} {}",
+                    super::tab(2),
+                    required_path.path.display(),
+                    epilogue.italic()
+                );
+            }
+        }
+
+        if do_install {
+            info!(
+                "installed the permissions, log out and back in for the group membership to apply"
+            );
+        } else {
+            for snippet in &snippets {
+                match (snippet.kind, snippet.path) {
+                    (SnippetKind::Command, _) => info!("run the following as root:"),
+                    (_, Some(path)) => info!("install the following into {}:", path.bold()),
+                    (_, None) => unreachable!("file snippets always have a path"),
+                }
+
+                let _guard = log::no_prologue::guard_for(log::Level::Info);
+                info!("{}{}", super::tab(2), snippet.contents.trim_end());
+            }
+
+            info!(
+                "or run {} as root to do all of this",
+                "tuxvantage permissions --install".bold()
+            );
+        }
+    }
+
+    Ok(MachineOutput {
+        backend,
+        required_paths,
+        snippets,
+        installed: do_install,
+    })
+}
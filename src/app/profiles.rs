@@ -1,13 +1,21 @@
-use crate::app::IntoOptionMachineOutput;
-use crate::config::PossiblyBuiltInProfile;
-use crate::project_paths::profiles::ExternalProfile;
-use crate::{anyhow_with_tip, config, log, project_paths, TippingAnyhowResultExt};
+use crate::app::{IntoOptionMachineOutput, Porcelain};
+use crate::config::{BuiltInProfile, Config, Feature, PossiblyBuiltInProfile};
+use crate::hardware::{self, Hardware};
+use crate::project_paths::profiles::{ExternalProfile, FailedProfile};
+use crate::validation::{self, Finding, Validation};
+use crate::{
+    anyhow_with_tip, config, diff, ext, format, log, pager, project_paths, schema, utils, verbose,
+    TippingAnyhowResultExt,
+};
 use anyhow::Context;
 use ideapad::profile::BitInner;
-use ideapad::{profile::Bit, Profile};
+use ideapad::{profile::Bit, Profile, SystemPerformanceMode};
+use itertools::Itertools;
 use owo_colors::OwoColorize;
-use std::io::Read;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Read, Write};
 use std::ops::Deref;
+use std::path::PathBuf;
 use std::{fmt, fs, io};
 
 fn format_bits(name: impl fmt::Display, bit: Bit, indent: usize) {
@@ -23,7 +31,7 @@ fn format_bits(name: impl fmt::Display, bit: Bit, indent: usize) {
             super::tab(indent + 1),
             "FCMO/SPMO Bit".bold(),
             bit.fcmo(),
-            format_args!("({:#04x})", bit.spmo()).italic()
+            format_args!("({})", format::hex_u8(bit.spmo())).italic()
         );
     } else {
         info!("{}{}", super::tab(indent), name.bold());
@@ -32,23 +40,106 @@ fn format_bits(name: impl fmt::Display, bit: Bit, indent: usize) {
             super::tab(indent + 1),
             "FCMO Bit".bold(),
             bit.fcmo(),
-            format_args!("({:#04x})", bit.fcmo()).italic()
+            format_args!("({})", format::hex_u8(bit.fcmo())).italic()
         );
         info!(
             "{}{} {} {}",
             super::tab(indent + 1),
             "SPMO Bit".bold(),
             bit.spmo(),
-            format_args!("({:#04x})", bit.spmo()).italic()
+            format_args!("({})", format::hex_u8(bit.spmo())).italic()
         );
     }
 }
 
+/// A profile which couldn't be loaded.
+#[derive(Serialize)]
+pub struct Failed {
+    path: Option<PathBuf>,
+    error: String,
+}
+
+impl From<&FailedProfile> for Failed {
+    fn from(failed: &FailedProfile) -> Self {
+        Self {
+            path: failed.path.clone(),
+            error: utils::dedup_error_chain_for_humans(&failed.error),
+        }
+    }
+}
+
+/// A profile with what it declares about the model.
+#[derive(Serialize)]
+pub struct DeclaredProfile {
+    #[serde(flatten)]
+    profile: Profile,
+    unsupported: Vec<Feature>,
+}
+
+impl From<PossiblyBuiltInProfile> for DeclaredProfile {
+    fn from(profile: PossiblyBuiltInProfile) -> Self {
+        Self {
+            unsupported: profile.unsupported().to_vec(),
+            profile: profile.get().into_owned(),
+        }
+    }
+}
+
+/// The result of one entry of a bulk operation, such as `profiles validate --all`.
+#[derive(Serialize)]
+pub struct BulkEntry {
+    name: String,
+    ok: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<MachineOutput>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize, Copy, Clone)]
+pub struct BulkSummary {
+    ok: usize,
+    failed: usize,
+}
+
 #[derive(Serialize)]
 #[serde(untagged)]
 pub enum MachineOutput {
-    Get { profiles: Vec<Profile> },
-    Json { json: String },
+    Get {
+        profiles: Vec<DeclaredProfile>,
+        failed: Vec<Failed>,
+    },
+    Json {
+        json: String,
+    },
+    Validate {
+        valid: bool,
+        findings: Vec<Finding>,
+    },
+    Contribute {
+        contents: serde_json::Value,
+    },
+    SetDefault {
+        changed: bool,
+        matches_machine: Option<bool>,
+    },
+    Materialize {
+        name: String,
+        path: PathBuf,
+    },
+    Written {
+        path: PathBuf,
+        bytes: usize,
+    },
+    Schema {
+        schema: serde_json::Value,
+    },
+    Bulk {
+        entries: Vec<BulkEntry>,
+        summary: BulkSummary,
+    },
 }
 
 impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
@@ -57,7 +148,85 @@ impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
     }
 }
 
-pub fn get(name: Option<String>) -> anyhow::Result<MachineOutput> {
+impl Porcelain for MachineOutput {
+    fn porcelain(&self) -> Vec<String> {
+        match self {
+            Self::Get { profiles, .. } => profiles
+                .iter()
+                .map(|profile| profile.profile.name.to_string())
+                .collect(),
+            Self::Json { json } => vec![json.clone()],
+            Self::Validate { valid, .. } => vec![super::pair("valid", valid)],
+            Self::Contribute { contents } => vec![contents.to_string()],
+            Self::SetDefault {
+                changed,
+                matches_machine,
+            } => vec![
+                super::pair("changed", changed),
+                super::pair(
+                    "matches_machine",
+                    matches_machine
+                        .map(|matches| matches.to_string())
+                        .unwrap_or_default(),
+                ),
+            ],
+            Self::Materialize { name, path } => vec![
+                super::pair("name", name),
+                super::pair("path", path.display()),
+            ],
+            Self::Written { path, bytes } => vec![
+                super::pair("path", path.display()),
+                super::pair("bytes", bytes),
+            ],
+            Self::Schema { schema } => vec![schema.to_string()],
+            Self::Bulk { entries, .. } => entries
+                .iter()
+                .map(|entry| super::pair(&entry.name, if entry.ok { "ok" } else { "failed" }))
+                .collect(),
+        }
+    }
+}
+
+/// Writes `contents` to `path` for `--output`, only overwriting it if `force` is given. The same
+/// is done in machine and human mode, with only a confirmation line printed to humans.
+fn write_output(path: PathBuf, contents: &str, force: bool) -> anyhow::Result<MachineOutput> {
+    anyhow::ensure!(
+        force || !path.exists(),
+        "{} already exists, pass {} to overwrite it",
+        path.display().bold(),
+        "--force".bold()
+    );
+    utils::write_atomic(&path, contents)
+        .with_context(|| format!("failed to write to {}", path.display().bold()))?;
+
+    if !config::machine() {
+        info!(
+            "wrote {} byte(s) to {}",
+            contents.len(),
+            path.display().bold()
+        );
+    }
+
+    Ok(MachineOutput::Written {
+        path,
+        bytes: contents.len(),
+    })
+}
+
+pub fn get(
+    name: Option<String>,
+    brief: bool,
+    json: bool,
+    output: Option<PathBuf>,
+    force: bool,
+) -> anyhow::Result<MachineOutput> {
+    anyhow::ensure!(
+        !(json && output.is_some()),
+        "{} can't be used with {}",
+        "--json".bold(),
+        "--output".bold()
+    );
+
     let config = config::read();
     let profiles = &config.profiles;
 
@@ -82,191 +251,307 @@ pub fn get(name: Option<String>) -> anyhow::Result<MachineOutput> {
 
     let machine = config.tuxvantage.machine();
 
-    if !machine {
-        if is_singular {
-            let name = name.expect("name should always exist when singular");
-            info!("profile definition for '{}':", name)
-        } else {
-            info!("list of profiles and their contents:")
-        }
+    // failing to load a profile loses its name, so it can only be shown when listing every profile
+    let failed = if is_singular {
+        Vec::new()
+    } else {
+        config.profiles.failed.iter().collect::<Vec<_>>()
+    };
 
-        for possibly_built_in_profile in &profiles {
-            debug!("next profile");
-            let _guard = log::no_prologue::guard_for(log::Level::Info);
-            if !is_singular {
-                info!("")
-            }
-            let mut epilogue = match possibly_built_in_profile {
-                PossiblyBuiltInProfile::BuiltIn(_) => {
-                    debug!("profile is built-in");
-                    "(built-in)"
-                }
-                PossiblyBuiltInProfile::External { .. } => {
-                    debug!("profile is external");
-                    "(external)"
-                }
-            }
-            .italic()
-            .to_string();
+    if !machine && output.is_none() {
+        pager::paged(config.tuxvantage.pager(), || {
+            show(&config, name.as_deref(), &profiles, &failed, brief)
+        });
+    }
 
-            debug!("epilogue is '{}'", epilogue);
+    let get = MachineOutput::Get {
+        profiles: profiles.into_iter().map(DeclaredProfile::from).collect(),
+        failed: failed.into_iter().map(Failed::from).collect(),
+    };
 
-            let profile = possibly_built_in_profile.get();
+    match output {
+        Some(output) => {
+            let contents =
+                serde_json::to_string_pretty(&get).context("failed to serialize the profiles")?;
+            drop(config);
 
-            if let Some(default) = config.tuxvantage.profile() {
-                debug!("get default profile name");
-                if default == profile.name {
-                    debug!("profile is default, push this notice to epilogue");
-                    epilogue.push_str(" (default)".italic().to_string().as_str());
-                }
-            }
+            write_output(output, &contents, force)
+        }
+        None => Ok(get),
+    }
+}
 
-            debug!("show header");
-            info!("{}{} {}", super::tab(1), profile.name.bold(), epilogue);
+/// Shows `profiles` and the profiles which failed to load to humans. `brief` only shows the
+/// header of each profile.
+fn show(
+    config: &Config,
+    name: Option<&str>,
+    profiles: &[PossiblyBuiltInProfile],
+    failed: &[&FailedProfile],
+    brief: bool,
+) {
+    let is_singular = name.is_some();
 
-            if let PossiblyBuiltInProfile::External(ref profile) = possibly_built_in_profile {
-                debug!("show path of external profile");
-                info!(
-                    "{}{} {}",
-                    super::tab(2),
-                    "Path".bold(),
-                    profile.path.display()
-                );
+    match name {
+        Some(name) => info!("profile definition for '{}':", name),
+        None => info!("list of profiles and their contents:"),
+    }
+
+    for possibly_built_in_profile in profiles {
+        debug!("next profile");
+        let _guard = log::no_prologue::guard_for(log::Level::Info);
+        if !is_singular && !brief {
+            info!("")
+        }
+        let mut epilogue = match possibly_built_in_profile {
+            PossiblyBuiltInProfile::BuiltIn(_) => {
+                debug!("profile is built-in");
+                "(built-in)"
+            }
+            PossiblyBuiltInProfile::External { .. } => {
+                debug!("profile is external");
+                "(external)"
             }
+        }
+        .italic()
+        .to_string();
+
+        debug!("epilogue is '{}'", epilogue);
 
-            debug!("show product names header");
-            info!("{}{}", super::tab(2), "Expected Product Names".bold());
+        let profile = possibly_built_in_profile.get();
 
-            debug!("show product names");
-            for product_name in profile.expected_product_names.iter() {
-                info!("{}{}", super::tab(3), product_name);
+        if let Some(default) = config.tuxvantage.profile() {
+            debug!("get default profile name");
+            if default == profile.name {
+                debug!("profile is default, push this notice to epilogue");
+                epilogue.push_str(" (default)".italic().to_string().as_str());
             }
+        }
 
-            debug!("show system performance mode header");
-            info!("{}{}", super::tab(2), "System Performance Mode".bold());
-            debug!("show set command");
-            info!(
-                "{}{} {}",
-                super::tab(3),
-                "Set Command".bold(),
-                profile.system_performance.commands.set,
-            );
-            debug!("show fcmo bit command");
-            info!(
-                "{}{} {}",
-                super::tab(3),
-                "Get FCMO Bit Command".bold(),
-                profile.system_performance.commands.get_fcmo_bit,
-            );
-            debug!("show spmo bit command");
+        if brief {
             info!(
-                "{}{} {}",
-                super::tab(3),
-                "Get SPMO Bit Command".bold(),
-                profile.system_performance.commands.get_spmo_bit,
+                "{}{} {} {}",
+                super::tab(1),
+                profile.name.bold(),
+                epilogue,
+                profile.expected_product_names.iter().join(", ")
             );
-            info!("{}{}", super::tab(3), "Commands To Get Bits".bold());
+            continue;
+        }
 
-            let bits = profile.system_performance.bits;
-            format_bits("Intelligent Cooling", bits.intelligent_cooling, 4);
-            format_bits("Extreme Performance", bits.extreme_performance, 4);
-            format_bits("Battery Saving", bits.battery_saving, 4);
+        debug!("show header");
+        info!("{}{} {}", super::tab(1), profile.name.bold(), epilogue);
 
-            // let parameters = profile.parameters;
-            info!("{}{}", super::tab(3), "Parameters".bold());
-            info!(
-                "{}{} {} {}",
-                super::tab(4),
-                "Set To Intelligent Cooling".bold(),
-                profile.system_performance.parameters.intelligent_cooling,
-                format_args!(
-                    "({:#010x})",
-                    profile.system_performance.parameters.intelligent_cooling
-                )
-                .italic()
-            );
-            info!(
-                "{}{} {} {}",
-                super::tab(4),
-                "Set To Extreme Performance".bold(),
-                profile.system_performance.parameters.extreme_performance,
-                format_args!(
-                    "({:#010x})",
-                    profile.system_performance.parameters.extreme_performance
-                )
-                .italic()
-            );
-            info!(
-                "{}{} {} {}",
-                super::tab(4),
-                "Set To Battery Saving".bold(),
-                profile.system_performance.parameters.battery_saving,
-                format_args!(
-                    "({:#010x})",
-                    profile.system_performance.parameters.battery_saving
-                )
-                .italic()
-            );
-            info!("{}{}", super::tab(2), "Battery".bold());
-            info!(
-                "{}{} {}",
-                super::tab(3),
-                "Set Command".bold(),
-                profile.battery.set_command,
-            );
-            info!("{}{}", super::tab(3), "Battery Conservation".bold());
-            info!(
-                "{}{} {}",
-                super::tab(4),
-                "Get Command".bold(),
-                profile.battery.conservation.get_command,
-            );
-            info!("{}{}", super::tab(4), "Parameters".bold());
-            info!(
-                "{}{} {} {}",
-                super::tab(5),
-                "Enable".bold(),
-                profile.battery.conservation.parameters.enable,
-                format_args!("({:#02x})", profile.battery.conservation.parameters.enable).italic()
-            );
-            info!(
-                "{}{} {} {}",
-                super::tab(5),
-                "Disable".bold(),
-                profile.battery.conservation.parameters.disable,
-                format_args!("({:#02x})", profile.battery.conservation.parameters.disable).italic()
-            );
-            info!("{}{}", super::tab(3), "Rapid Charging".bold());
+        if let PossiblyBuiltInProfile::External(ref profile) = possibly_built_in_profile {
+            debug!("show path of external profile");
             info!(
                 "{}{} {}",
-                super::tab(4),
-                "Get Command".bold(),
-                profile.battery.rapid_charge.get_command,
-            );
-            info!("{}{}", super::tab(4), "Parameters".bold());
-            info!(
-                "{}{} {} {}",
-                super::tab(5),
-                "Enable".bold(),
-                profile.battery.rapid_charge.parameters.enable,
-                format_args!("({:#02x})", profile.battery.rapid_charge.parameters.enable).italic()
+                super::tab(2),
+                "Path".bold(),
+                profile.path.display()
             );
+        }
+
+        debug!("show product names header");
+        info!("{}{}", super::tab(2), "Expected Product Names".bold());
+
+        debug!("show product names");
+        for product_name in profile.expected_product_names.iter() {
+            info!("{}{}", super::tab(3), product_name);
+        }
+
+        let unsupported = possibly_built_in_profile.unsupported();
+
+        if !unsupported.is_empty() {
+            debug!("show unsupported features");
+            info!("{}{}", super::tab(2), "Unsupported Features".bold());
+
+            for feature in unsupported {
+                info!("{}{}", super::tab(3), feature.name());
+            }
+        }
+
+        debug!("show system performance mode header");
+        info!("{}{}", super::tab(2), "System Performance Mode".bold());
+        debug!("show set command");
+        info!(
+            "{}{} {}",
+            super::tab(3),
+            "Set Command".bold(),
+            profile.system_performance.commands.set,
+        );
+        debug!("show fcmo bit command");
+        info!(
+            "{}{} {}",
+            super::tab(3),
+            "Get FCMO Bit Command".bold(),
+            profile.system_performance.commands.get_fcmo_bit,
+        );
+        debug!("show spmo bit command");
+        info!(
+            "{}{} {}",
+            super::tab(3),
+            "Get SPMO Bit Command".bold(),
+            profile.system_performance.commands.get_spmo_bit,
+        );
+        info!("{}{}", super::tab(3), "Commands To Get Bits".bold());
+
+        let bits = profile.system_performance.bits;
+        format_bits("Intelligent Cooling", bits.intelligent_cooling, 4);
+        format_bits("Extreme Performance", bits.extreme_performance, 4);
+        format_bits("Battery Saving", bits.battery_saving, 4);
+
+        // let parameters = profile.parameters;
+        info!("{}{}", super::tab(3), "Parameters".bold());
+        info!(
+            "{}{} {} {}",
+            super::tab(4),
+            "Set To Intelligent Cooling".bold(),
+            profile.system_performance.parameters.intelligent_cooling,
+            format_args!(
+                "({})",
+                format::hex_u32(profile.system_performance.parameters.intelligent_cooling)
+            )
+            .italic()
+        );
+        info!(
+            "{}{} {} {}",
+            super::tab(4),
+            "Set To Extreme Performance".bold(),
+            profile.system_performance.parameters.extreme_performance,
+            format_args!(
+                "({})",
+                format::hex_u32(profile.system_performance.parameters.extreme_performance)
+            )
+            .italic()
+        );
+        info!(
+            "{}{} {} {}",
+            super::tab(4),
+            "Set To Battery Saving".bold(),
+            profile.system_performance.parameters.battery_saving,
+            format_args!(
+                "({})",
+                format::hex_u32(profile.system_performance.parameters.battery_saving)
+            )
+            .italic()
+        );
+        info!("{}{}", super::tab(2), "Battery".bold());
+        info!(
+            "{}{} {}",
+            super::tab(3),
+            "Set Command".bold(),
+            profile.battery.set_command,
+        );
+        info!("{}{}", super::tab(3), "Battery Conservation".bold());
+        info!(
+            "{}{} {}",
+            super::tab(4),
+            "Get Command".bold(),
+            profile.battery.conservation.get_command,
+        );
+        info!("{}{}", super::tab(4), "Parameters".bold());
+        info!(
+            "{}{} {} {}",
+            super::tab(5),
+            "Enable".bold(),
+            profile.battery.conservation.parameters.enable,
+            format_args!(
+                "({})",
+                format::hex_u32(profile.battery.conservation.parameters.enable)
+            )
+            .italic()
+        );
+        info!(
+            "{}{} {} {}",
+            super::tab(5),
+            "Disable".bold(),
+            profile.battery.conservation.parameters.disable,
+            format_args!(
+                "({})",
+                format::hex_u32(profile.battery.conservation.parameters.disable)
+            )
+            .italic()
+        );
+        info!("{}{}", super::tab(3), "Rapid Charging".bold());
+        info!(
+            "{}{} {}",
+            super::tab(4),
+            "Get Command".bold(),
+            profile.battery.rapid_charge.get_command,
+        );
+        info!("{}{}", super::tab(4), "Parameters".bold());
+        info!(
+            "{}{} {} {}",
+            super::tab(5),
+            "Enable".bold(),
+            profile.battery.rapid_charge.parameters.enable,
+            format_args!(
+                "({})",
+                format::hex_u32(profile.battery.rapid_charge.parameters.enable)
+            )
+            .italic()
+        );
+        info!(
+            "{}{} {} {}",
+            super::tab(5),
+            "Disable".bold(),
+            profile.battery.rapid_charge.parameters.disable,
+            format_args!(
+                "({})",
+                format::hex_u32(profile.battery.rapid_charge.parameters.disable)
+            )
+            .italic()
+        );
+    }
+
+    let _guard = log::no_prologue::guard_for(log::Level::Info);
+
+    for failed in failed {
+        let path = failed.path.as_ref().map_or_else(
+            || "<unknown>".to_string(),
+            |path| path.display().to_string(),
+        );
+
+        if !brief {
+            info!("");
+        }
+
+        info!(
+            "{}{} {}",
+            super::tab(1),
+            path.bold(),
+            "(failed to load)".italic()
+        );
+
+        if brief {
+            continue;
+        }
+
+        info!(
+            "{}{} {}",
+            super::tab(2),
+            "Error".bold(),
+            utils::dedup_error_chain_for_humans(&failed.error)
+        );
+
+        if verbose::enabled() {
+            info!("{}{}", super::tab(2), "Details".bold());
+
+            for error in failed.error.chain() {
+                info!("{}{}", super::tab(3), error);
+            }
+        }
+
+        if failed.path.is_some() {
             info!(
-                "{}{} {} {}",
-                super::tab(5),
-                "Disable".bold(),
-                profile.battery.rapid_charge.parameters.disable,
-                format_args!("({:#02x})", profile.battery.rapid_charge.parameters.disable).italic()
+                "{}{} run {} to see what is wrong with it",
+                super::tab(2),
+                "Tip".bold(),
+                format_args!("tuxvantage profiles validate {}", path).bold()
             );
         }
     }
-
-    Ok(MachineOutput::Get {
-        profiles: profiles
-            .into_iter()
-            .map(|profile| profile.get().deref().clone())
-            .collect(),
-    })
 }
 
 pub fn get_default() -> anyhow::Result<MachineOutput> {
@@ -274,7 +559,13 @@ pub fn get_default() -> anyhow::Result<MachineOutput> {
         Some(default_profile) => {
             debug!("default profile in config");
             let default_profile = default_profile.context("failed to get default profile")?;
-            get(Some(default_profile.name.to_string()))
+            get(
+                Some(default_profile.name.to_string()),
+                false,
+                false,
+                None,
+                false,
+            )
         }
         None => {
             debug!("no default profile found in config, bailing out");
@@ -286,107 +577,392 @@ pub fn get_default() -> anyhow::Result<MachineOutput> {
     }
 }
 
-pub fn set(name: String, contents: Option<String>, create_new: bool) -> anyhow::Result<()> {
-    enum Source {
-        Arguments,
-        Stdin,
+enum Source {
+    Arguments,
+    Stdin,
+}
+
+fn read_contents(contents: Option<String>) -> anyhow::Result<(String, Source)> {
+    debug!("get the contents and source of the contents");
+    match contents {
+        Some(contents) => {
+            debug!("contents exist, assume they're a path to something");
+            let contents = fs::read_to_string(contents)
+                .context("failed to read new profile contents from file")?;
+            debug!("...therefore, the source comes from the arguments");
+            Ok((contents, Source::Arguments))
+        }
+        None => {
+            debug!("content doesn't exist, assume they're on stdin");
+
+            let mut contents = String::new();
+            io::stdin()
+                .read_to_string(&mut contents)
+                .context("failed to read from stdin")?;
+
+            debug!("...therefore, the source comes from the stdin");
+            Ok((contents, Source::Stdin))
+        }
+    }
+}
+
+fn report_validation(validation: &Validation) -> MachineOutput {
+    if !config::machine() {
+        for warning in validation.warnings() {
+            warn!("{}", warning);
+        }
+
+        for error in validation.errors() {
+            error!("{}", error);
+        }
+    }
+
+    MachineOutput::Validate {
+        valid: validation.is_valid(),
+        findings: validation.findings.clone(),
+    }
+}
+
+fn confirm(prompt: impl fmt::Display) -> anyhow::Result<bool> {
+    // the contents of the profile may have come from stdin, so ask the terminal directly
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .context("failed to open the terminal to ask for confirmation")?;
+
+    write!(tty, "{} {} ", prompt, "[y/N]".bold()).context("failed to write to the terminal")?;
+
+    let mut answer = String::new();
+    BufReader::new(tty)
+        .read_line(&mut answer)
+        .context("failed to read from the terminal")?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn preview_changes(old: &Profile, new: &Profile) -> anyhow::Result<()> {
+    let changes = diff::diff(old, new).context("failed to compute the changes to the profile")?;
+
+    if changes.is_empty() {
+        info!("no semantic changes to the profile {}", old.name.bold());
+        return Ok(());
+    }
+
+    info!("changes to the profile {}:", old.name.bold());
+    let _guard = log::no_prologue::guard_for(log::Level::Info);
+
+    for change in changes {
+        info!("{}{}", super::tab(2), change);
     }
 
+    Ok(())
+}
+
+pub fn set(
+    name: String,
+    contents: Option<String>,
+    create_new: bool,
+    dry_run: bool,
+    confirm_write: bool,
+    quiet: bool,
+) -> anyhow::Result<Option<MachineOutput>> {
     debug!("find profile '{}' which may or may not exist", name);
     let profile = config::read()
         .profiles
         .with_built_ins()
         .find(|profile| profile.get().name == name);
 
-    let profile_path = if let Some(profile) = profile {
+    let (profile_path, existing) = if let Some(profile) = profile {
         debug!("profile '{}' found, getting the path", name);
-        profile
+        let path = profile
             .path()
             .context("you cannot set the contents of a profile that is built-in")?
-            .to_path_buf()
+            .to_path_buf();
+
+        (path, Some(profile.get().into_owned()))
     } else if create_new {
         debug!("profile '{}' not found, generating the path", name);
         let file_name = format!("{}.json", name);
-        project_paths::profiles_dir().join(file_name)
+        (project_paths::profiles_dir().join(file_name), None)
     } else {
         anyhow::bail!("profile {} not found", name.bold())
     };
 
-    debug!("get the contents and source of the contents");
-    let (contents, source) = match contents {
-        Some(contents) => {
-            debug!("contents exist, assume they're a path to something");
-            let contents = fs::read_to_string(contents)
-                .context("failed to read new profile contents from file")?;
-            debug!("...therefore, the source comes from the arguments");
-            (contents, Source::Arguments)
+    let (contents, source) = read_contents(contents)?;
+
+    debug!("make sure that `contents` is a valid profile");
+    let mut validation =
+        validation::validate(&contents, &config::read().profiles.disabled_built_ins);
+
+    if let Some(profile) = &validation.profile {
+        if profile.name != name {
+            validation.findings.push(Finding::warning(format!(
+                "the name inside of the contents, {}, differs from the name of the profile being set, {}",
+                profile.name.bold(),
+                name.bold()
+            )));
         }
-        None => {
-            debug!("content doesn't exist, assume they're on stdin");
+    }
 
-            let mut contents = String::new();
-            io::stdin()
-                .read_to_string(&mut contents)
-                .context("failed to read from stdin")?;
+    if dry_run {
+        debug!("dry run, not writing anything");
+        let output = report_validation(&validation);
 
-            debug!("...therefore, the source comes from the stdin");
-            (contents, Source::Stdin)
+        if !config::machine() {
+            anyhow::ensure!(
+                validation.is_valid(),
+                "the profile {} is invalid",
+                name.bold()
+            );
+            info!(
+                "the contents are a valid profile, {} would be written to {}",
+                name.bold(),
+                profile_path.display().bold()
+            );
         }
-    };
 
-    // make sure that `contents` is valid json
-    debug!("make sure that `contents` is valid json");
-    serde_json::from_str::<Profile>(&contents).context("contents weren't valid json")?;
+        return Ok(Some(output));
+    }
+
+    if !config::machine() {
+        for warning in validation.warnings() {
+            warn!("{}", warning);
+        }
+    }
+
+    let new = validation.into_result()?;
+
+    config::ensure_writable()?;
+
+    let machine = config::machine();
+
+    if let Some(existing) = &existing {
+        if !machine && !quiet && atty::is(atty::Stream::Stdout) {
+            debug!("preview the changes to the existing profile");
+            preview_changes(existing, &new)?;
+        }
+
+        if !machine && confirm_write && !confirm(format!("overwrite the profile {}?", name.bold()))?
+        {
+            info!("not overwriting the profile {}", name.bold());
+            return Ok(None);
+        }
+    }
 
     debug!("write the contents to the profile");
-    fs::write(&profile_path, contents).context("failed to write to profile file")?;
+    fs::write(&profile_path, contents)
+        .map_err(|error| project_paths::write_error(&profile_path, error))
+        .context("failed to write to profile file")?;
 
-    if !config::machine() {
+    if !machine {
         match source {
             Source::Arguments => info!("set profile {} to contents from arguments", name.bold()),
             Source::Stdin => info!("set profile {} to contents from stdin", name.bold()),
         }
     }
 
-    Ok(())
+    Ok(None)
 }
 
-pub fn set_default(name: String) -> anyhow::Result<()> {
-    let mut config = config::write();
+/// Runs `f` on every entry independently, continuing past the ones which fail. On a terminal, a
+/// line is printed as each entry finishes, and failures are always printed. Fails at the end if
+/// any entry failed, unless the output is for machines, which get the result of every entry
+/// instead.
+fn bulk<T>(
+    entries: impl IntoIterator<Item = (String, T)>,
+    mut f: impl FnMut(T) -> anyhow::Result<MachineOutput>,
+) -> anyhow::Result<MachineOutput> {
+    let machine = config::machine();
+    let tty = atty::is(atty::Stream::Stderr);
+    let mut results = Vec::new();
 
-    // scoped so that `profiles` will get dropped otherwise borrow checker will get mad at
-    // us
-    {
-        debug!("check if name '{}' exists as a profile", name);
-        let mut profiles = config.profiles.with_built_ins();
-        let name_exists_as_a_profile = profiles.any(|profile| profile.get().name == name);
+    for (name, entry) in entries {
+        debug!("run bulk operation on '{}'", name);
+        let result = f(entry);
+        let ok = !matches!(
+            result,
+            Ok(MachineOutput::Validate { valid: false, .. }) | Err(_)
+        );
+
+        if !machine {
+            let _guard = log::no_prologue::guard_for(log::Level::Info);
+
+            match &result {
+                Ok(_) if ok && tty => info!("{} {}", "ok".green().bold(), name),
+                Ok(_) if ok => {}
+                Ok(_) => warn!("{} {}", "failed".red().bold(), name),
+                Err(error) => warn!("{} {}: {:#}", "failed".red().bold(), name, error),
+            }
+        }
+
+        let (output, error) = match result {
+            Ok(output) => (Some(output), None),
+            Err(error) => (None, Some(utils::dedup_error_chain_for_humans(&error))),
+        };
+
+        results.push(BulkEntry {
+            name,
+            ok,
+            output,
+            error,
+        });
+    }
+
+    let failed = results.iter().filter(|entry| !entry.ok).count();
+    let summary = BulkSummary {
+        ok: results.len() - failed,
+        failed,
+    };
+
+    if !machine {
+        info!("{} ok, {} failed", summary.ok.bold(), summary.failed.bold());
         anyhow::ensure!(
-            name_exists_as_a_profile,
-            "profile {} not found",
-            name.bold()
+            failed == 0,
+            "{} of {} profile(s) failed",
+            failed,
+            results.len()
+        );
+    }
+
+    Ok(MachineOutput::Bulk {
+        entries: results,
+        summary,
+    })
+}
+
+/// The profile files in the profiles directory, sorted by path.
+fn profile_paths() -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = project_paths::profiles_dir()
+        .read_dir()
+        .context("failed to get entries of the profile directory")?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()
+        .context("failed to get the next entry of the profile directory")?;
+    paths.sort();
+
+    Ok(paths)
+}
+
+pub fn validate(
+    contents: Option<String>,
+    schema: bool,
+    all: bool,
+) -> anyhow::Result<MachineOutput> {
+    if all {
+        anyhow::ensure!(
+            contents.is_none(),
+            "{} can't be used with a path",
+            "--all".bold()
         );
+
+        let entries = profile_paths()?.into_iter().map(|path| {
+            let name = path.display().to_string();
+            (name.clone(), name)
+        });
+
+        return bulk(entries, |path| validate_one(Some(path), schema));
     }
 
-    debug!("set the default profile to '{}' in memory", name);
-    config.tuxvantage.profile = Some(name.clone());
+    validate_one(contents, schema)
+}
+
+fn validate_one(contents: Option<String>, schema: bool) -> anyhow::Result<MachineOutput> {
+    let (contents, _) = read_contents(contents)?;
+    let mut validation =
+        validation::validate(&contents, &config::read().profiles.disabled_built_ins);
+
+    if schema {
+        if let Ok(value) = serde_json::from_str(&contents) {
+            let mut findings = schema::check(&value, &schema::profile()?);
+            findings.append(&mut validation.findings);
+            validation.findings = findings;
+        }
+    }
+
+    let output = report_validation(&validation);
+
+    if !config::machine() {
+        anyhow::ensure!(validation.is_valid(), "the profile is invalid");
+        info!("the profile is valid");
+    }
+
+    Ok(output)
+}
+
+pub fn set_default(name: String, force: bool) -> anyhow_with_tip::Result<MachineOutput> {
+    let mut config = config::write();
+
+    debug!("check if name '{}' exists as a profile", name);
+    let profile = config
+        .profiles
+        .find(&name)
+        .with_context(|| format!("profile {} not found", name.bold()))
+        .no_tip()?;
+    let machine = config.tuxvantage.machine();
+    let matches_machine = config.profiles.matches_machine(&profile);
+
+    match matches_machine {
+        Some(true) => {}
+        Some(false) => {
+            let product_name = config::product_name().unwrap_or_default();
+            let message = format!(
+                "the profile {} expects the product name(s) {}, but this machine is {}",
+                name.bold(),
+                profile.expected_product_names.iter().join(", ").bold(),
+                product_name.bold()
+            );
+
+            if !force {
+                return Err(anyhow::anyhow!(message)).tip(
+                    "using a profile for another machine sends the wrong acpi calls, but pass \
+                     `--force` if it really is for this machine",
+                );
+            }
+
+            warn_with_tip!(message, ext::PROFILE_MISMATCH_TIP);
+        }
+        None if !machine => info!(
+            "couldn't read the product name of this machine, so it wasn't checked against the \
+             profile"
+        ),
+        None => {}
+    }
+
+    if config.tuxvantage.profile.as_deref() == Some(name.as_str()) {
+        if !machine {
+            info!(
+                "the default profile is already {}, nothing to do",
+                name.bold()
+            );
+        }
+
+        return Ok(MachineOutput::SetDefault {
+            changed: false,
+            matches_machine,
+        });
+    }
 
     debug!("write the new default profile to the config");
     config
         .tuxvantage
-        .dump()
+        .mutate_then_dump(|tuxvantage| tuxvantage.profile = Some(name.clone()))
         .context("failed to write to `tuxvantage.toml`")?;
 
-    if !config.tuxvantage.machine() {
+    if !machine {
         info!("set the default profile to {}", name.bold());
     }
 
-    Ok(())
+    Ok(MachineOutput::SetDefault {
+        changed: true,
+        matches_machine,
+    })
 }
 
 pub fn remove(name: String) -> anyhow_with_tip::Result<()> {
     let path = config::read()
         .profiles
-        .0
+        .loaded
         .iter()
         .find(|profile| profile.profile.name == name)
         .with_context(|| format!("profile {} not found", name.bold()))
@@ -394,6 +970,7 @@ pub fn remove(name: String) -> anyhow_with_tip::Result<()> {
         .path
         .clone();
 
+    config::ensure_writable()?;
     fs::remove_file(path).context("failed to remove profile file")?;
 
     if !config::machine() {
@@ -403,20 +980,145 @@ pub fn remove(name: String) -> anyhow_with_tip::Result<()> {
     Ok(())
 }
 
-pub fn json(name: String, generate_on_error: bool, pretty: bool) -> anyhow::Result<MachineOutput> {
+/// Writes the JSON of the built-in profile `built_in` into the profiles directory as a starting
+/// point for a patched copy, named `name` or the same as the built-in. Built-in profiles are
+/// found before the ones in the profiles directory, so keeping the name needs the built-in to be
+/// disabled first.
+pub fn materialize(
+    built_in: String,
+    name: Option<String>,
+) -> anyhow_with_tip::Result<MachineOutput> {
+    let config = config::read();
+    let profile = BuiltInProfile::ALL
+        .iter()
+        .map(BuiltInProfile::get)
+        .find(|profile| profile.name == built_in)
+        .with_context(|| format!("built-in profile {} not found", built_in.bold()))
+        .no_tip()?;
+    let name = name.unwrap_or(built_in);
+
+    if BuiltInProfile::enabled(&config.profiles.disabled_built_ins)
+        .any(|built_in| built_in.get().name == name)
+    {
+        return Err(anyhow::anyhow!(
+            "the profile {} would be hidden by the built-in profile of the same name",
+            name.bold()
+        ))
+        .tip(format!(
+            "add `{}` to `profiles.disabled_builtins` in `tuxvantage.toml`, or give the copy \
+             another name with `--name`",
+            name
+        ));
+    }
+
+    if config
+        .profiles
+        .loaded
+        .iter()
+        .any(|profile| profile.profile.name == name)
+    {
+        return Err(anyhow::anyhow!("profile {} already exists", name.bold()).into());
+    }
+
+    let path = project_paths::profiles_dir().join(format!("{}.json", name));
+
+    if path.exists() {
+        return Err(anyhow::anyhow!("{} already exists", path.display().bold()).into());
+    }
+
+    let mut json = serde_json::to_value(&profile).context("failed to serialize the profile")?;
+    json["name"] = serde_json::Value::String(name.clone());
+    let contents =
+        serde_json::to_string_pretty(&json).context("failed to serialize the profile")?;
+    let validation = validation::validate(&contents, &config.profiles.disabled_built_ins);
+    let machine = config.tuxvantage.machine();
+    drop(config);
+
+    validation.into_result()?;
+    config::ensure_writable()?;
+    fs::write(&path, contents)
+        .map_err(|error| project_paths::write_error(&path, error))
+        .with_context(|| format!("failed to write to {}", path.display().bold()))?;
+
+    if !machine {
+        info!(
+            "materialized the built-in profile {} as {} in {}",
+            profile.name.bold(),
+            name.bold(),
+            path.display().bold()
+        );
+    }
+
+    Ok(MachineOutput::Materialize { name, path })
+}
+
+pub fn json(
+    name: Option<String>,
+    all: bool,
+    generate_on_error: bool,
+    pretty: bool,
+    output: Option<PathBuf>,
+    force: bool,
+) -> anyhow::Result<MachineOutput> {
+    match (name, all, output) {
+        (Some(name), false, Some(output)) => {
+            match json_one(name, generate_on_error, pretty, false)? {
+                MachineOutput::Json { json } => write_output(output, &json, force),
+                _ => {
+                    unreachable!("the JSON contents of a profile are always `MachineOutput::Json`")
+                }
+            }
+        }
+        (_, true, Some(_)) => Err(anyhow::anyhow!(
+            "{} can't be used with {}",
+            "--output".bold(),
+            "--all".bold()
+        )),
+        (Some(name), false, None) => json_one(name, generate_on_error, pretty, true),
+        (None, true, None) => {
+            let names = config::read()
+                .profiles
+                .loaded
+                .iter()
+                .map(|profile| profile.profile.name.to_string())
+                .collect::<Vec<_>>();
+
+            bulk(names.into_iter().map(|name| (name.clone(), name)), |name| {
+                json_one(name, generate_on_error, pretty, true)
+            })
+        }
+        (Some(_), true, None) => Err(anyhow::anyhow!(
+            "{} can't be used with a profile name",
+            "--all".bold()
+        )),
+        (None, false, _) => Err(anyhow::anyhow!(
+            "either a profile name or {} must be given",
+            "--all".bold()
+        )),
+    }
+}
+
+/// The JSON contents of the profile `name`, which are only printed to humans if `print` is
+/// given.
+fn json_one(
+    name: String,
+    generate_on_error: bool,
+    pretty: bool,
+    print: bool,
+) -> anyhow::Result<MachineOutput> {
     let config = config::read();
     let profile = config
         .profiles
         .with_built_ins()
         .find(|profile| profile.get().name == name)
         .with_context(|| format!("profile {} not found", name.bold()))?;
-    let machine = config.tuxvantage.machine();
+    let print = print && !config.tuxvantage.machine();
 
     match profile {
         PossiblyBuiltInProfile::BuiltIn(profile) => {
             let profile = profile.get();
 
-            if !machine {
+            if print {
                 warn!("the profile {} is a built-in profile. note that these types of profiles don't actually exist in the filesystem\n\
                    of your computer, but is actually embedded in the binary of the program. as such, their json files don't actually exist, and\n\
                    are generated by the program itself. you may only use this as a reference.", profile.name.bold());
@@ -429,14 +1131,14 @@ pub fn json(name: String, generate_on_error: bool, pretty: bool) -> anyhow::Resu
                 serde_json::to_string(&profile).expect("failed to generate json from profile")
             };
 
-            if !machine {
-                println!("{}", json);
+            if print {
+                utils::print_line(&json);
             }
 
             Ok(MachineOutput::Json { json })
         }
         PossiblyBuiltInProfile::External(profile) => {
-            let ExternalProfile { profile, path } = profile.deref();
+            let ExternalProfile { profile, path, .. } = profile.deref();
             let clause = || -> anyhow::Result<MachineOutput> {
                 let json =
                     fs::read_to_string(path).context("failed to read contents of profile json")?;
@@ -447,7 +1149,7 @@ pub fn json(name: String, generate_on_error: bool, pretty: bool) -> anyhow::Resu
                 Ok(output) => Ok(output),
                 Err(error) => {
                     if generate_on_error {
-                        if !machine {
+                        if print {
                             warn!("{:#}", error);
                             warn!("generating from profile on memory");
                         }
@@ -459,8 +1161,8 @@ pub fn json(name: String, generate_on_error: bool, pretty: bool) -> anyhow::Resu
                                 .expect("failed to generate json from profile")
                         };
 
-                        if !machine {
-                            println!("{}", json);
+                        if print {
+                            utils::print_line(&json);
                         }
 
                         Ok(MachineOutput::Json { json })
@@ -472,3 +1174,174 @@ pub fn json(name: String, generate_on_error: bool, pretty: bool) -> anyhow::Resu
         }
     }
 }
+
+/// The result of a read-only probe of the hardware.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Probe<T> {
+    Ok(T),
+    Error(String),
+}
+
+impl<T> From<anyhow::Result<T>> for Probe<T> {
+    fn from(result: anyhow::Result<T>) -> Self {
+        match result {
+            Ok(value) => Self::Ok(value),
+            Err(error) => Self::Error(utils::dedup_error_chain_for_humans(&error)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Probes {
+    battery_conservation_enabled: Probe<bool>,
+    rapid_charge_enabled: Probe<bool>,
+    system_performance_mode: Probe<SystemPerformanceMode>,
+}
+
+impl Probes {
+    fn run() -> Self {
+        let mut hardware = hardware::get();
+
+        Self {
+            battery_conservation_enabled: hardware.conservation().into(),
+            rapid_charge_enabled: hardware.rapid_charge().into(),
+            system_performance_mode: hardware.performance_mode().into(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Contribution<'a> {
+    tuxvantage_version: &'static str,
+    product_name: Option<String>,
+    product_version: Option<String>,
+    kernel_version: Option<String>,
+    profile: &'a Profile,
+    probes: Probes,
+}
+
+pub fn contribute(name: String, out: Option<PathBuf>) -> anyhow::Result<MachineOutput> {
+    let config = config::read();
+    let machine = config.tuxvantage.machine();
+    let path = match config
+        .profiles
+        .with_built_ins()
+        .find(|profile| profile.get().name == name)
+        .with_context(|| format!("profile {} not found", name.bold()))?
+    {
+        PossiblyBuiltInProfile::BuiltIn(_) => anyhow::bail!(
+            "the profile {} is built-in, so there is nothing to contribute",
+            name.bold()
+        ),
+        PossiblyBuiltInProfile::External(profile) => profile.path,
+    };
+
+    debug!("validate the profile before collecting anything");
+    let contents = fs::read_to_string(&path).context("failed to read contents of profile json")?;
+    let validation = validation::validate(&contents, &config.profiles.disabled_built_ins);
+
+    if !validation.is_valid() {
+        report_validation(&validation);
+        anyhow::bail!(
+            "the profile {} is invalid, so nothing was collected",
+            name.bold()
+        );
+    }
+
+    let profile = validation
+        .profile
+        .expect("a valid validation should always have a profile");
+
+    debug!("run the read-only probes");
+    let contribution = Contribution {
+        tuxvantage_version: env!("CARGO_PKG_VERSION"),
+        product_name: utils::read_trimmed("/sys/class/dmi/id/product_name"),
+        product_version: utils::read_trimmed("/sys/class/dmi/id/product_version"),
+        kernel_version: utils::read_trimmed("/proc/sys/kernel/osrelease"),
+        profile: &profile,
+        probes: Probes::run(),
+    };
+    let mut contents =
+        serde_json::to_value(&contribution).context("failed to serialize the contribution")?;
+
+    debug!("redact serial numbers and hostnames");
+    let redacted = utils::redact(&mut contents, &utils::sensitive_strings());
+
+    if let Some(out) = &out {
+        let json = serde_json::to_string_pretty(&contents)
+            .context("failed to serialize the contribution")?;
+        fs::write(out, json).with_context(|| {
+            format!(
+                "failed to write the contribution to {}",
+                out.display().bold()
+            )
+        })?;
+    }
+
+    if !machine {
+        info!("collected the following for the profile {}:", name.bold());
+
+        {
+            let _guard = log::no_prologue::guard_for(log::Level::Info);
+            let unknown = || "unknown".italic().to_string();
+
+            info!(
+                "{}{} {}",
+                super::tab(2),
+                "Product Name".bold(),
+                contribution.product_name.clone().unwrap_or_else(unknown)
+            );
+            info!(
+                "{}{} {}",
+                super::tab(2),
+                "Kernel Version".bold(),
+                contribution.kernel_version.clone().unwrap_or_else(unknown)
+            );
+            info!(
+                "{}{} {}",
+                super::tab(2),
+                "Probes".bold(),
+                "battery conservation, rapid charge, and system performance mode"
+            );
+            info!(
+                "{}{} {}",
+                super::tab(2),
+                "Redacted".bold(),
+                format_args!(
+                    "{} string(s) containing serial numbers or hostnames",
+                    redacted
+                )
+            );
+        }
+
+        match &out {
+            Some(out) => info!("wrote the contribution to {}", out.display().bold()),
+            None => utils::print_line(
+                serde_json::to_string_pretty(&contents)
+                    .context("failed to serialize the contribution")?,
+            ),
+        }
+
+        info!("paste this into an issue on the tuxvantage repository to contribute the profile");
+    }
+
+    Ok(MachineOutput::Contribute { contents })
+}
+
+pub fn schema(pretty: bool) -> anyhow::Result<MachineOutput> {
+    let schema = schema::profile()?;
+
+    if !config::machine() {
+        let json = if pretty {
+            serde_json::to_string_pretty(&schema)
+        } else {
+            serde_json::to_string(&schema)
+        }
+        .context("failed to serialize the schema")?;
+
+        utils::print_line(&json);
+    }
+
+    Ok(MachineOutput::Schema { schema })
+}
@@ -1,17 +1,68 @@
-use crate::app::IntoOptionMachineOutput;
+use crate::app::{IntoOptionMachineOutput, Porcelain, Readings, Settle};
 use crate::args::FromStrHandler;
-use crate::ext::AnyhowResultExt;
-use crate::{anyhow_with_tip, config, context};
+#[cfg(feature = "regulate")]
+use crate::config::BatteryMatches;
+use crate::config::{Feature, HandlerMode, HandlerResolution, HandlerSource};
+use crate::ext::{self, AnyhowResultExt};
+use crate::hardware::{self, Hardware};
+use crate::history::{self, Initiator};
+use crate::state::OwedRestore;
+#[cfg(feature = "regulate")]
+use crate::types::{BatteryLevel, Deadline, HumanDuration};
+use crate::{anyhow_with_tip, api, config, daemons, ec_cooldown, state, TippingAnyhowResultExt};
+#[cfg(feature = "regulate")]
+use crate::{format, utils};
 use anyhow::Context;
+#[cfg(feature = "regulate")]
+use battery::units::energy::watt_hour;
+#[cfg(feature = "regulate")]
+use battery::units::power::watt;
 use ideapad::Handler;
 use owo_colors::OwoColorize;
 use parking_lot::RwLockWriteGuard;
+#[cfg(feature = "regulate")]
+use signal_hook::consts::{SIGINT, SIGTERM};
+#[cfg(feature = "regulate")]
+use signal_hook::iterator::Signals;
+#[cfg(feature = "regulate")]
+use std::io::{self, Write};
+#[cfg(feature = "regulate")]
+use std::thread;
+#[cfg(feature = "regulate")]
+use std::time::{Duration, Instant};
+
+/// How long `rapid-charge top-up` waits before estimating again if no interval is given.
+#[cfg(feature = "regulate")]
+pub const DEFAULT_TOP_UP_INTERVAL: HumanDuration = HumanDuration::from_secs(300);
+
+/// How much longer than the measured charge rate suggests charging normally is assumed to take,
+/// since the charge rate drops as the battery fills up.
+#[cfg(feature = "regulate")]
+const TOP_UP_MARGIN: f64 = 1.2;
 
 #[derive(Serialize)]
 #[serde(untagged)]
 pub enum MachineOutput {
-    Enabled { enabled: bool },
-    Disabled { disabled: bool },
+    Enabled {
+        enabled: bool,
+    },
+    Disabled {
+        disabled: bool,
+    },
+    HandlerResolution {
+        handler_resolution: HandlerResolution,
+    },
+    Changed {
+        changed: bool,
+
+        /// The setting as read back with `--settle`, if it was given and the setting changed.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        settle: Option<Readings<bool>>,
+    },
+    #[cfg(feature = "regulate")]
+    TopUp {
+        top_up: TopUp,
+    },
 }
 
 impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
@@ -20,82 +71,682 @@ impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
     }
 }
 
+impl Porcelain for MachineOutput {
+    fn porcelain(&self) -> Vec<String> {
+        match self {
+            Self::Enabled { enabled: true } | Self::Disabled { disabled: false } => {
+                vec!["enabled".to_string()]
+            }
+            Self::Enabled { enabled: false } | Self::Disabled { disabled: true } => {
+                vec!["disabled".to_string()]
+            }
+            Self::HandlerResolution { handler_resolution } => {
+                super::handler_resolution_porcelain(handler_resolution)
+            }
+            Self::Changed { changed, .. } => vec![super::pair("changed", changed)],
+            #[cfg(feature = "regulate")]
+            Self::TopUp { top_up } => vec![
+                super::pair("outcome", top_up.outcome.name()),
+                super::pair("level", top_up.level),
+            ],
+        }
+    }
+}
+
+fn ensure_supported() -> anyhow_with_tip::Result<()> {
+    config::ensure_supported(Feature::RapidCharge).no_tip()
+}
+
 pub fn enabled() -> anyhow_with_tip::Result<MachineOutput> {
-    let enabled = ideapad::rapid_charge::enabled(context::get())
-        .context("failed to get rapid charge value")
-        .maybe_acpi_call_tip()?;
+    ensure_supported()?;
+    let enabled = read_enabled(&mut hardware::get())?;
     let what = if enabled {
         "enabled".bold().green().to_string()
     } else {
         "disabled".bold().red().to_string()
     };
 
-    if !config::machine() {
-        info!("rapid charge is {}", what)
+    let output = MachineOutput::Enabled { enabled };
+
+    if !config::machine() && !super::print_template("battery rapid-charge enabled", &output) {
+        info!("rapid charge is {}", what);
     }
 
-    Ok(MachineOutput::Enabled { enabled })
+    Ok(output)
 }
 
 pub fn disabled() -> anyhow_with_tip::Result<MachineOutput> {
-    let disabled = ideapad::rapid_charge::disabled(context::get())
-        .context("failed to get rapid charge value")
-        .maybe_acpi_call_tip()?;
+    ensure_supported()?;
+    let disabled = !read_enabled(&mut hardware::get())?;
     let what = if disabled {
         "disabled".bold().green().to_string()
     } else {
         "enabled".bold().red().to_string()
     };
 
-    if !config::machine() {
+    let output = MachineOutput::Disabled { disabled };
+
+    if !config::machine() && !super::print_template("battery rapid-charge disabled", &output) {
         info!("rapid charge is {}", what);
     }
 
-    Ok(MachineOutput::Disabled { disabled })
+    Ok(output)
 }
 
-pub fn enable(handler: Option<FromStrHandler>) -> anyhow_with_tip::Result<()> {
+pub fn enable(
+    handler: Option<(FromStrHandler, HandlerSource)>,
+    explain: bool,
+    remember: bool,
+    force: bool,
+    settle: Option<Settle>,
+) -> anyhow_with_tip::Result<Option<MachineOutput>> {
+    ensure_supported()?;
     let mut config = config::write();
-    config.tuxvantage.overrides.handlers.rapid_charging = handler.map(|handler| handler.0);
+    let switch_back = handler
+        .as_ref()
+        .map(|(handler, _)| handler.0.switches_back())
+        .unwrap_or(config.tuxvantage.overrides.switch_back);
+    config.tuxvantage.overrides.handler_flag = matches!(handler, Some((_, HandlerSource::Flag)));
+    config.tuxvantage.overrides.handlers.rapid_charging =
+        handler.map(|(handler, _)| handler.0.handler());
     let config = RwLockWriteGuard::downgrade(config);
-    let handler = config.tuxvantage.handlers().rapid_charging();
+    let resolution = config
+        .tuxvantage
+        .handler_resolution(HandlerMode::RapidCharging);
     let machine = config.tuxvantage.machine();
 
+    if explain {
+        if !machine {
+            super::print_handler_resolution("rapid charging", &resolution);
+        }
+
+        return Ok(Some(MachineOutput::HandlerResolution {
+            handler_resolution: resolution,
+        }));
+    }
+
+    let handler = resolution.handler();
+    let mut hardware = hardware::get();
+    let already_enabled = read_enabled(&mut hardware)?;
+
+    if already_enabled {
+        if !machine {
+            info!("rapid charging is already enabled, nothing to do");
+        }
+
+        if remember {
+            remember_enabled()?;
+        }
+
+        return Ok(Some(MachineOutput::Changed {
+            changed: false,
+            settle: None,
+        }));
+    }
+
     if !machine {
         info!(
             "trying to enable rapid charging with handler {}",
             super::format_handler(handler)
         );
+    }
 
-        if let Handler::Ignore = handler {
-            warn!("use this handler with care; if rapid charge is already enabled this will strain the battery")
-        }
+    if let Handler::Ignore = handler {
+        warn_with_tip!(
+            "the ignore handler won't disable battery conservation if it is already enabled, which will strain the battery",
+            ext::IGNORE_HANDLER_TIP
+        );
     }
 
-    context::get()
-        .controllers()
-        .rapid_charge()
-        .enable()
-        .handler(handler)
-        .now()
-        .context("failed to enable rapid charging")
-        .maybe_acpi_call_tip()?;
+    // switching battery conservation mode off is a change the regulator may revert
+    if let Handler::Switch = handler {
+        daemons::warn_if_regulated(force);
+    }
+
+    let battery_conservation_was_enabled = if switch_back {
+        debug!("switch-back handler, check if battery conservation is enabled");
+        api::read_conservation(&mut hardware).maybe_acpi_call_tip()?
+    } else {
+        false
+    };
+
+    ec_cooldown::guard("rapid_charge");
+    api::write_rapid_charge(&mut hardware, true, handler).maybe_acpi_call_tip()?;
+    history::record("rapid_charge", "disabled", "enabled", Initiator::Cli);
 
     if !machine {
         info!("enabled rapid charging");
     }
 
-    Ok(())
+    if battery_conservation_was_enabled {
+        history::record(
+            "battery_conservation",
+            "enabled",
+            "disabled",
+            Initiator::Cli,
+        );
+        state::owe_restore(OwedRestore::BatteryConservation)?;
+
+        if !machine {
+            info!(
+                "battery conservation was switched off, run {} to switch it back on",
+                "tuxvantage rapid-charge disable --restore".bold()
+            );
+        }
+    }
+
+    // checked last, so that a strict failure doesn't lose the restore owed above
+    let settle = settle
+        .map(|settle| {
+            super::verify_settled("rapid charging", true, settle, super::toggle_name, || {
+                read_enabled(&mut hardware)
+            })
+        })
+        .transpose()?;
+
+    if remember {
+        remember_enabled()?;
+    }
+
+    Ok(Some(MachineOutput::Changed {
+        changed: true,
+        settle,
+    }))
 }
 
-pub fn disable() -> anyhow_with_tip::Result<()> {
-    ideapad::rapid_charge::disable(context::get())
-        .context("failed to disable rapid charge")
+fn read_enabled(hardware: &mut impl Hardware) -> anyhow_with_tip::Result<bool> {
+    api::read_rapid_charge(hardware).maybe_acpi_call_tip()
+}
+
+fn remember_enabled() -> anyhow::Result<()> {
+    state::remember(|desired| {
+        desired.rapid_charge = Some(true);
+
+        // battery conservation and rapid charging can't be enabled at the same time
+        if desired.battery_conservation == Some(true) {
+            desired.battery_conservation = Some(false);
+        }
+    })
+}
+
+pub fn disable(
+    remember: bool,
+    restore: bool,
+    settle: Option<Settle>,
+) -> anyhow_with_tip::Result<MachineOutput> {
+    ensure_supported()?;
+    let machine = config::machine();
+    let mut hardware = hardware::get();
+    let mut changed = read_enabled(&mut hardware)?;
+    let switched_off = changed;
+
+    if changed {
+        ec_cooldown::guard("rapid_charge");
+        api::write_rapid_charge(&mut hardware, false, Handler::Switch).maybe_acpi_call_tip()?;
+        history::record("rapid_charge", "enabled", "disabled", Initiator::Cli);
+
+        if !machine {
+            info!("disabled rapid charge")
+        }
+    } else if !machine {
+        info!("rapid charge is already disabled, nothing to do");
+    }
+
+    if restore {
+        if state::is_restore_owed(OwedRestore::BatteryConservation)? {
+            debug!("switch battery conservation back on");
+            ec_cooldown::guard("battery_conservation");
+            let handler = config::read().tuxvantage.handlers().battery_conservation();
+            hardware
+                .set_conservation(true, handler)
+                .context("failed to switch battery conservation back on")
+                .maybe_acpi_call_tip()?;
+            history::record(
+                "battery_conservation",
+                "disabled",
+                "enabled",
+                Initiator::Cli,
+            );
+            state::clear_owed_restore()?;
+            changed = true;
+
+            if !machine {
+                info!("switched battery conservation back on");
+            }
+        } else if !machine {
+            info!("battery conservation wasn't switched off by rapid charging, nothing to restore");
+        }
+    }
+
+    // checked after restoring, so that a strict failure doesn't skip it
+    let readings = settle
+        .filter(|_| switched_off)
+        .map(|settle| {
+            super::verify_settled("rapid charging", false, settle, super::toggle_name, || {
+                read_enabled(&mut hardware)
+            })
+        })
+        .transpose()?;
+
+    if remember {
+        state::remember(|desired| desired.rapid_charge = Some(false))?;
+    }
+
+    Ok(MachineOutput::Changed {
+        changed,
+        settle: readings,
+    })
+}
+
+/// What the top-up planner does with rapid charging after estimating.
+#[cfg(feature = "regulate")]
+#[derive(Serialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Plan {
+    /// Charging normally is estimated to reach the target in time, so rapid charging is disabled.
+    Normal,
+
+    /// Charging normally is estimated to fall short, or its rate isn't known yet, so rapid
+    /// charging is enabled.
+    Rapid,
+
+    /// The battery isn't charging, so rapid charging is left as is until it charges again.
+    NotCharging,
+
+    /// The target was reached, which ends the top-up.
+    Reached,
+
+    /// The deadline passed before the target was reached, which ends the top-up.
+    Missed,
+
+    /// A signal ended the top-up. Never planned, only the outcome.
+    Interrupted,
+}
+
+#[cfg(feature = "regulate")]
+impl Plan {
+    /// The name of the plan, as it is serialized.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Rapid => "rapid",
+            Self::NotCharging => "not_charging",
+            Self::Reached => "reached",
+            Self::Missed => "missed",
+            Self::Interrupted => "interrupted",
+        }
+    }
+
+    /// Whether rapid charging should be enabled, or `None` if it is left as is.
+    fn rapid_charge(self) -> Option<bool> {
+        match self {
+            Self::Rapid => Some(true),
+            Self::NotCharging => None,
+            Self::Normal | Self::Reached | Self::Missed | Self::Interrupted => Some(false),
+        }
+    }
+
+    fn ends(self) -> bool {
+        matches!(self, Self::Reached | Self::Missed | Self::Interrupted)
+    }
+}
+
+/// How long charging normally is estimated to take from `level` to `target` at `rate` percent per
+/// hour, or `None` if it never would.
+#[cfg(feature = "regulate")]
+fn estimate(level: u8, target: u8, rate: f64) -> Option<Duration> {
+    let hours = f64::from(target.saturating_sub(level)) / rate * TOP_UP_MARGIN;
+
+    Duration::try_from_secs_f64(hours * 3600.0).ok()
+}
+
+/// Decides what to do with rapid charging given the battery level, how long is left until the
+/// deadline and the charge rate last measured without rapid charging, in percent per hour. This
+/// doesn't touch the hardware or the batteries, so that it can be driven by synthetic charge rates.
+#[cfg(feature = "regulate")]
+fn plan(
+    level: u8,
+    target: u8,
+    charging: bool,
+    remaining: Duration,
+    normal_rate: Option<f64>,
+) -> Plan {
+    if level >= target {
+        Plan::Reached
+    } else if remaining.is_zero() {
+        Plan::Missed
+    } else if !charging {
+        Plan::NotCharging
+    } else {
+        match normal_rate
+            .filter(|rate| *rate > 0.0)
+            .and_then(|rate| estimate(level, target, rate))
+        {
+            Some(estimate) if estimate <= remaining => Plan::Normal,
+            _ => Plan::Rapid,
+        }
+    }
+}
+
+/// A duration to the second, for printing.
+#[cfg(feature = "regulate")]
+fn seconds(duration: Duration) -> HumanDuration {
+    HumanDuration::from_secs(duration.as_secs())
+}
+
+/// One estimate of the top-up planner, which machine mode prints as a line of JSON as it is made.
+#[cfg(feature = "regulate")]
+#[derive(Serialize)]
+pub struct Evaluation {
+    level: u8,
+    charging: bool,
+
+    /// The charge rate last measured without rapid charging, in percent per hour.
+    normal_rate: Option<f64>,
+
+    /// How long charging normally is estimated to take to reach the target.
+    estimate: Option<HumanDuration>,
+    remaining: HumanDuration,
+    plan: Plan,
+
+    /// Whether rapid charging is enabled after acting on the plan.
+    rapid_charge: bool,
+}
+
+#[cfg(feature = "regulate")]
+impl Evaluation {
+    /// Prints this as a line and flushes it right away. Failing to doesn't exit, so that the
+    /// top-up can still disable rapid charging once nothing reads its output anymore.
+    fn print(&self) -> anyhow::Result<()> {
+        let line = serde_json::to_string(self).context("failed to serialize the evaluation")?;
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+
+        writeln!(stdout, "{}", line)
+            .and_then(|()| stdout.flush())
+            .context("failed to print the evaluation")
+    }
+}
+
+#[cfg(feature = "regulate")]
+#[derive(Serialize)]
+pub struct TopUp {
+    target: BatteryLevel,
+    deadline: Deadline,
+    outcome: Plan,
+
+    /// The battery level at the last estimate.
+    level: u8,
+    evaluations: usize,
+}
+
+#[cfg(feature = "regulate")]
+fn report(evaluation: &Evaluation, target: BatteryLevel, deadline: Deadline) {
+    info!(
+        "the battery is at {} and {}, with {} left until {}",
+        format::percent(evaluation.level).bold(),
+        if evaluation.charging {
+            "charging"
+        } else {
+            "not charging"
+        },
+        evaluation.remaining.bold(),
+        deadline.bold()
+    );
+
+    match (evaluation.plan, evaluation.estimate) {
+        (Plan::Normal, Some(estimate)) => info!(
+            "charging normally reaches {} in about {}, so rapid charging isn't needed",
+            target.bold(),
+            estimate.bold()
+        ),
+        (Plan::Rapid, Some(estimate)) => info!(
+            "charging normally would take about {} to reach {}, so rapid charging is needed",
+            estimate.bold(),
+            target.bold()
+        ),
+        (Plan::Normal | Plan::Rapid, None) => {
+            info!("the normal charge rate isn't known yet, so rapid charging is used")
+        }
+        (Plan::NotCharging, _) => info!("waiting for the battery to charge"),
+        (Plan::Reached, _) => info!("reached {}", target.bold()),
+        (Plan::Missed, _) => warn!("the deadline passed before reaching {}", target.bold()),
+        (Plan::Interrupted, _) => {}
+    }
+}
+
+#[cfg(feature = "regulate")]
+fn top_up_enabled(hardware: &mut impl Hardware) -> anyhow_with_tip::Result<bool> {
+    api::read_rapid_charge(hardware).maybe_acpi_call_tip()
+}
+
+#[cfg(feature = "regulate")]
+fn set_top_up_rapid_charge(
+    hardware: &mut impl Hardware,
+    on: bool,
+    handler: Handler,
+    machine: bool,
+) -> anyhow_with_tip::Result<()> {
+    let (old, new) = if on {
+        ("disabled", "enabled")
+    } else {
+        ("enabled", "disabled")
+    };
+
+    ec_cooldown::guard("rapid_charge");
+    hardware
+        .set_rapid_charge(on, handler)
+        .with_context(|| format!("failed to set rapid charging to {}", new))
         .maybe_acpi_call_tip()?;
+    history::record("rapid_charge", old, new, Initiator::TopUp);
+
+    if !machine {
+        info!("{} rapid charging", new);
+    }
+
+    Ok(())
+}
 
-    if !config::machine() {
-        info!("disabled rapid charge")
+/// Disables rapid charging if the top-up left it enabled when it ends early.
+#[cfg(feature = "regulate")]
+fn stop_top_up(
+    hardware: &mut impl Hardware,
+    handler: Handler,
+    machine: bool,
+) -> anyhow_with_tip::Result<()> {
+    if top_up_enabled(hardware)? {
+        set_top_up_rapid_charge(hardware, false, handler, machine)?;
     }
 
     Ok(())
 }
+
+/// Charges the battery to `target` by `deadline`, estimating every `interval` whether charging
+/// normally gets there in time and enabling rapid charging only while it doesn't. Rapid charging
+/// is disabled once the target or the deadline is reached, or a signal ends the top-up.
+#[cfg(feature = "regulate")]
+pub fn top_up(
+    target: BatteryLevel,
+    deadline: Deadline,
+    interval: HumanDuration,
+    matches: Option<BatteryMatches>,
+) -> anyhow_with_tip::Result<MachineOutput> {
+    ensure_supported()?;
+    let config = config::read();
+    let machine = config.tuxvantage.machine().get();
+    let handler = config.tuxvantage.handlers().rapid_charging();
+    let battery_config = config.tuxvantage.battery_config();
+    drop(config);
+
+    let matches = matches.unwrap_or_else(|| battery_config.matches().into_owned());
+    let mut battery = match battery_config.require_matching(&matches) {
+        Ok((battery, errors)) => {
+            for error in errors {
+                warn!("{:#}", error);
+            }
+
+            battery
+        }
+        Err(error) => {
+            let tip = error
+                .is::<config::NoBatteryError>()
+                .then(|| ext::NO_BATTERY_TIP);
+
+            return Err(error.context("failed to get battery")).maybe_tip(tip);
+        }
+    };
+    let mut hardware = hardware::get();
+    let ends_at = Instant::now() + deadline.remaining();
+
+    let conservation = api::read_conservation(&mut hardware).maybe_acpi_call_tip()?;
+
+    if conservation && !machine {
+        warn!(
+            "battery conservation mode is enabled and stops charging normally well below most \
+             targets, so the top-up will likely rely on rapid charging"
+        );
+    }
+
+    let (signal_sender, signal_receiver) = crossbeam::channel::bounded(1);
+    let mut signals = Signals::new([SIGTERM, SIGINT])
+        .context("failed to register handler for application exits")
+        .no_tip()?;
+
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            let _ = signal_sender.try_send(());
+        }
+    });
+
+    let mut normal_rate = None;
+    let mut evaluations = 0;
+
+    let (outcome, level) = loop {
+        let level = (battery.state_of_charge().value * 100.0).round() as u8;
+        let charging = battery.state() == battery::State::Charging;
+        let enabled = top_up_enabled(&mut hardware)?;
+
+        // the rate while rapid charging says nothing about charging normally
+        if charging && !enabled {
+            let rate = f64::from(battery.energy_rate().get::<watt>())
+                / f64::from(battery.energy_full().get::<watt_hour>())
+                * 100.0;
+            normal_rate = Some(rate).filter(|rate| rate.is_finite());
+        }
+
+        let remaining = ends_at.saturating_duration_since(Instant::now());
+        let plan = plan(level, target.inner(), charging, remaining, normal_rate);
+        let wanted = plan.rapid_charge();
+
+        if let Some(on) = wanted.filter(|on| *on != enabled) {
+            set_top_up_rapid_charge(&mut hardware, on, handler, machine)?;
+        }
+
+        let evaluation = Evaluation {
+            level,
+            charging,
+            normal_rate,
+            estimate: normal_rate
+                .filter(|rate| *rate > 0.0)
+                .and_then(|rate| estimate(level, target.inner(), rate))
+                .map(seconds),
+            remaining: seconds(remaining),
+            plan,
+            rapid_charge: wanted.unwrap_or(enabled),
+        };
+        evaluations += 1;
+
+        if machine {
+            // such as when the reader of the evaluations exited
+            if let Err(error) = evaluation.print() {
+                stop_top_up(&mut hardware, handler, machine)?;
+
+                return Err(error).no_tip();
+            }
+        } else {
+            report(&evaluation, target, deadline);
+        }
+
+        if plan.ends() {
+            break (plan, level);
+        }
+
+        // wake up at the deadline if it comes before the next estimate
+        let sleep_receiver = utils::sleep(interval.0.min(remaining));
+
+        crossbeam::select! {
+            recv(sleep_receiver) -> _ => {}
+            recv(signal_receiver) -> _ => {
+                if !machine {
+                    info!("received signal to terminate the current program, exiting cleanly");
+                }
+
+                stop_top_up(&mut hardware, handler, machine)?;
+
+                break (Plan::Interrupted, level);
+            }
+        }
+
+        if let Err(error) = battery.refresh() {
+            warn!("failed to refresh the battery: {}", error);
+        }
+    };
+
+    Ok(MachineOutput::TopUp {
+        top_up: TopUp {
+            target,
+            deadline,
+            outcome,
+            level,
+            evaluations,
+        },
+    })
+}
+
+#[cfg(all(test, feature = "regulate"))]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn estimates_include_the_margin() {
+        // 30% at 30% per hour, with the margin on top
+        assert_eq!(estimate(50, 80, 30.0), Some(Duration::from_secs(4320)));
+        assert_eq!(estimate(80, 80, 30.0), Some(Duration::ZERO));
+        assert_eq!(estimate(90, 80, 30.0), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn rates_which_never_get_there_have_no_estimate() {
+        assert_eq!(estimate(50, 80, 0.0), None);
+        assert_eq!(estimate(50, 80, -10.0), None);
+        assert_eq!(estimate(50, 80, f64::NAN), None);
+    }
+
+    #[test]
+    fn the_target_and_the_deadline_end_the_plan() {
+        assert_eq!(plan(80, 80, true, HOUR, Some(30.0)), Plan::Reached);
+        assert_eq!(plan(80, 80, false, Duration::ZERO, None), Plan::Reached);
+        assert_eq!(plan(79, 80, true, Duration::ZERO, Some(30.0)), Plan::Missed);
+    }
+
+    #[test]
+    fn nothing_is_planned_while_not_charging() {
+        assert_eq!(plan(50, 80, false, HOUR, Some(30.0)), Plan::NotCharging);
+        assert_eq!(Plan::NotCharging.rapid_charge(), None);
+    }
+
+    #[test]
+    fn rapid_charging_is_only_planned_when_charging_normally_falls_short() {
+        // 1.2 hours normally
+        assert_eq!(plan(50, 80, true, 2 * HOUR, Some(30.0)), Plan::Normal);
+        assert_eq!(plan(50, 80, true, HOUR, Some(30.0)), Plan::Rapid);
+    }
+
+    #[test]
+    fn rapid_charging_is_planned_without_a_usable_rate() {
+        assert_eq!(plan(50, 80, true, 10 * HOUR, None), Plan::Rapid);
+        assert_eq!(plan(50, 80, true, 10 * HOUR, Some(0.0)), Plan::Rapid);
+    }
+}
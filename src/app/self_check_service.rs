@@ -0,0 +1,316 @@
+use crate::anyhow_with_tip::{self, IntoTip, StaticTip};
+use crate::app::battery_conservation;
+use crate::app::IntoOptionMachineOutput;
+use crate::{ext, log, utils};
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use std::path::Path;
+use std::process::Command;
+use std::{env, fmt, fs, io};
+
+#[derive(Serialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    pub check: &'static str,
+    pub status: Status,
+    pub message: String,
+    pub tip: Option<String>,
+}
+
+impl Check {
+    fn new(check: &'static str, status: Status, message: impl fmt::Display) -> Self {
+        Self {
+            check,
+            status,
+            message: message.to_string(),
+            tip: None,
+        }
+    }
+
+    fn pass(check: &'static str, message: impl fmt::Display) -> Self {
+        Self::new(check, Status::Pass, message)
+    }
+
+    fn warn(check: &'static str, message: impl fmt::Display) -> Self {
+        Self::new(check, Status::Warn, message)
+    }
+
+    fn fail(check: &'static str, message: impl fmt::Display) -> Self {
+        Self::new(check, Status::Fail, message)
+    }
+
+    fn tip(self, tip: StaticTip, machine: bool) -> Self {
+        Self {
+            tip: anyhow_with_tip::filter_tip(tip.into_tip(), machine).map(|tip| tip.to_string()),
+            ..self
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum MachineOutput {
+    SelfCheckService { healthy: bool, checks: Vec<Check> },
+}
+
+impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
+    fn into_option_machine_output(self) -> Option<MachineOutput> {
+        Some(self)
+    }
+}
+
+/// Runs `program` with `args`, returning its trimmed standard output regardless of the exit
+/// status, since `systemctl is-*` reports a non-zero status for perfectly valid answers.
+fn stdout_of(program: &str, args: &[&str]) -> io::Result<String> {
+    let output = Command::new(program).args(args).output()?;
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn check_exec_start(contents: &str, machine: bool) -> anyhow::Result<Check> {
+    const CHECK: &str = "exec_start";
+
+    let exe = match battery_conservation::unit_exe(contents) {
+        Some(exe) => Path::new(exe),
+        None => {
+            return Ok(Check::fail(CHECK, "the service doesn't run any executable")
+                .tip(ext::REGULATOR_SERVICE_NOT_INSTALLED_TIP, machine))
+        }
+    };
+
+    let exe = match exe.canonicalize() {
+        Ok(exe) => exe,
+        Err(error) => {
+            return Ok(Check::fail(
+                CHECK,
+                format_args!(
+                    "the executable the service runs, {}, is unusable: {}",
+                    exe.display().bold(),
+                    error
+                ),
+            )
+            .tip(ext::REGULATOR_EXE_MOVED_TIP, machine))
+        }
+    };
+    let current_exe = env::current_exe()
+        .and_then(|current_exe| current_exe.canonicalize())
+        .context("failed to get current executable location of tuxvantage")?;
+
+    Ok(if exe == current_exe {
+        Check::pass(
+            CHECK,
+            format_args!("the service runs {}", exe.display().bold()),
+        )
+    } else {
+        Check::warn(
+            CHECK,
+            format_args!(
+                "the service runs {}, which differs from the current executable, {}",
+                exe.display().bold(),
+                current_exe.display().bold()
+            ),
+        )
+        .tip(ext::REGULATOR_EXE_MOVED_TIP, machine)
+    })
+}
+
+fn check_version(contents: &str, machine: bool) -> Check {
+    const CHECK: &str = "version";
+
+    let version = battery_conservation::unit_version(contents);
+
+    if version < battery_conservation::UNIT_VERSION {
+        Check::warn(
+            CHECK,
+            format_args!(
+                "the service was generated from version {} of the template, but the current \
+                 version is {}",
+                version.bold(),
+                battery_conservation::UNIT_VERSION.bold()
+            ),
+        )
+        .tip(ext::REGULATOR_SERVICE_OUTDATED_TIP, machine)
+    } else {
+        Check::pass(
+            CHECK,
+            format_args!(
+                "the service was generated from the current version of the template, {}",
+                version.bold()
+            ),
+        )
+    }
+}
+
+fn check_enabled(service: &str, machine: bool) -> Check {
+    const CHECK: &str = "enabled";
+
+    match stdout_of("systemctl", &["is-enabled", service]) {
+        Ok(state) if state == "enabled" => Check::pass(CHECK, "the service is enabled"),
+        Ok(state) => Check::warn(
+            CHECK,
+            format_args!("the service is {}, not enabled", state.bold()),
+        )
+        .tip(ext::REGULATOR_SERVICE_NOT_ENABLED_TIP, machine),
+        Err(error) => Check::warn(
+            CHECK,
+            format_args!("failed to run `systemctl is-enabled`: {}", error),
+        ),
+    }
+}
+
+fn check_active(service: &str, machine: bool) -> Check {
+    const CHECK: &str = "active";
+
+    match stdout_of("systemctl", &["is-active", service]) {
+        Ok(state) if state == "active" => Check::pass(CHECK, "the service is active"),
+        Ok(state) if state == "failed" => Check::fail(CHECK, "the service has failed")
+            .tip(ext::REGULATOR_SERVICE_FAILED_TIP, machine),
+        Ok(state) => Check::warn(
+            CHECK,
+            format_args!("the service is {}, not active", state.bold()),
+        )
+        .tip(ext::REGULATOR_SERVICE_NOT_ACTIVE_TIP, machine),
+        Err(error) => Check::warn(
+            CHECK,
+            format_args!("failed to run `systemctl is-active`: {}", error),
+        ),
+    }
+}
+
+fn check_last_logged(service: &str) -> Check {
+    const CHECK: &str = "last_logged";
+
+    let last = match stdout_of(
+        "journalctl",
+        &[
+            "--unit",
+            service,
+            "--lines",
+            "1",
+            "--output",
+            "short-iso",
+            "--no-pager",
+            "--quiet",
+        ],
+    ) {
+        Ok(last) => last,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return Check::warn(CHECK, "journalctl isn't available")
+        }
+        Err(error) => {
+            return Check::warn(CHECK, format_args!("failed to run `journalctl`: {}", error))
+        }
+    };
+
+    // lines look like `2022-02-10T12:00:00+0000 hostname bcm[1234]: message`
+    match last.split_once(' ') {
+        Some((timestamp, rest)) => {
+            let message = rest.split_once(": ").map_or(rest, |(_, message)| message);
+
+            Check::pass(
+                CHECK,
+                format_args!(
+                    "the service last logged at {}: {}",
+                    timestamp.bold(),
+                    message
+                ),
+            )
+        }
+        None => Check::warn(
+            CHECK,
+            "the service hasn't logged anything, or the journal isn't readable by this user",
+        ),
+    }
+}
+
+/// Inspects the installed battery conservation regulator service.
+pub fn checks(machine: bool) -> anyhow::Result<Vec<Check>> {
+    let path = battery_conservation::regulator_unit();
+    let service = battery_conservation::regulator_service();
+    debug!("check {}", path.display());
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return Ok(vec![Check::fail(
+                "installed",
+                format_args!("the service isn't installed at {}", path.display().bold()),
+            )
+            .tip(ext::REGULATOR_SERVICE_NOT_INSTALLED_TIP, machine)])
+        }
+        Err(error) => {
+            return Ok(vec![Check::fail(
+                "installed",
+                format_args!("failed to read {}: {}", path.display().bold(), error),
+            )])
+        }
+    };
+    let mut checks = vec![Check::pass(
+        "installed",
+        format_args!("the service is installed at {}", path.display().bold()),
+    )];
+
+    debug!("check the executable the service runs");
+    checks.push(check_exec_start(&contents, machine)?);
+
+    debug!("check the version of the service");
+    checks.push(check_version(&contents, machine));
+
+    if !utils::is_systemd()? {
+        checks.push(Check::warn(
+            "systemd",
+            "systemd isn't running, so the state of the service can't be checked",
+        ));
+        return Ok(checks);
+    }
+
+    debug!("check the state of the service");
+    checks.push(check_enabled(&service, machine));
+    checks.push(check_active(&service, machine));
+
+    debug!("check when the service last logged");
+    checks.push(check_last_logged(&service));
+
+    Ok(checks)
+}
+
+pub fn self_check_service(machine: bool) -> anyhow::Result<MachineOutput> {
+    let checks = checks(machine)?;
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == Status::Fail)
+        .count();
+
+    if !machine {
+        let _guard = log::no_prologue::guard_for(log::Level::Info);
+
+        for check in &checks {
+            match check.status {
+                Status::Pass => info!("{} {}", "pass".bold().green(), check.message),
+                Status::Warn => info!("{} {}", "warn".bold().yellow(), check.message),
+                Status::Fail => info!("{} {}", "fail".bold().red(), check.message),
+            }
+
+            if let Some(tip) = &check.tip {
+                info!("{}{}", super::tab(2), tip.italic());
+            }
+        }
+
+        anyhow::ensure!(
+            failed == 0,
+            "{} check(s) of the regulator service failed",
+            failed
+        );
+    }
+
+    Ok(MachineOutput::SelfCheckService {
+        healthy: failed == 0,
+        checks,
+    })
+}
@@ -0,0 +1,508 @@
+use crate::app::{IntoOptionMachineOutput, Porcelain};
+use crate::args::TuxVantage;
+use crate::config::BuiltInProfile;
+use crate::sandbox::Sandbox;
+use crate::{examples, log, utils};
+use anyhow::Context;
+use clap::{App, AppSettings, IntoApp};
+use itertools::Itertools;
+use owo_colors::OwoColorize;
+use serde_json::Value;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::{fs, io};
+
+/// The name of the profile the self test creates and removes.
+const PROFILE_NAME: &str = "tuxvantage-selftest";
+
+#[derive(Serialize)]
+pub struct Step {
+    name: &'static str,
+    passed: bool,
+    message: String,
+}
+
+#[derive(Serialize)]
+pub struct MachineOutput {
+    passed: bool,
+    steps: Vec<Step>,
+}
+
+impl MachineOutput {
+    pub fn exit_code(&self) -> i32 {
+        if self.passed {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
+    fn into_option_machine_output(self) -> Option<MachineOutput> {
+        Some(self)
+    }
+}
+
+impl Porcelain for MachineOutput {
+    fn porcelain(&self) -> Vec<String> {
+        self.steps
+            .iter()
+            .map(|step| super::pair(step.name, if step.passed { "pass" } else { "fail" }))
+            .collect()
+    }
+}
+
+/// Parses the envelope of `--machine always`, checking that its status is `status`.
+fn envelope(stdout: &str, status: &str) -> anyhow::Result<Value> {
+    let envelope: Value =
+        serde_json::from_str(stdout.trim()).context("the machine output isn't valid JSON")?;
+
+    anyhow::ensure!(
+        envelope["status"] == status,
+        "the machine output has the status {}, not {}",
+        envelope["status"],
+        status
+    );
+
+    Ok(envelope)
+}
+
+fn expect_exit_code(exit_code: i32, success: bool) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        (exit_code == 0) == success,
+        "exited with {}, but was expected to {}",
+        exit_code,
+        if success { "succeed" } else { "fail" }
+    );
+
+    Ok(())
+}
+
+/// Collects the path of every visible subcommand below `app`, such as
+/// `["battery", "conservation", "enable"]`.
+fn subcommand_paths(app: &App, prefix: &[&str], paths: &mut Vec<Vec<String>>) {
+    for subcommand in app
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_set(AppSettings::Hidden))
+    {
+        let mut path = prefix.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        path.push(subcommand.get_name().to_string());
+        subcommand_paths(
+            subcommand,
+            &path.iter().map(String::as_str).collect::<Vec<_>>(),
+            paths,
+        );
+        paths.push(path);
+    }
+}
+
+/// Every step of the self test in order, each running this program inside of `sandbox` and
+/// checking its exit code and output.
+fn steps(sandbox: &Sandbox) -> anyhow::Result<Vec<(&'static str, anyhow::Result<()>)>> {
+    let built_in = BuiltInProfile::ALL
+        .first()
+        .map(|built_in| built_in.get().name.to_string())
+        .context("there are no built-in profiles")?;
+    let json_path = sandbox.path("profile.json");
+    let json_path = json_path.to_str().context("the sandbox path isn't utf-8")?;
+    let mut steps = Vec::new();
+
+    debug!("check that the sandbox is used");
+    let isolated =
+        sandbox
+            .run(&["--machine", "always", "paths"])
+            .and_then(|(exit_code, stdout)| {
+                expect_exit_code(exit_code, true)?;
+                let envelope = envelope(&stdout, "Success")?;
+
+                for dir in ["config_dir", "profiles_dir", "tuxvantage_toml"] {
+                    let path = envelope["contents"][dir]["path"]
+                        .as_str()
+                        .unwrap_or_default();
+                    anyhow::ensure!(
+                        Path::new(path).starts_with(sandbox.root()),
+                        "{} is {}, which is outside of the sandbox",
+                        dir,
+                        path
+                    );
+                }
+
+                Ok(())
+            });
+    let aborted = isolated.is_err();
+    steps.push(("paths", isolated));
+
+    // nothing else may run if the real config could be touched
+    if aborted {
+        return Ok(steps);
+    }
+
+    steps.push((
+        "config_check",
+        sandbox
+            .run(&["--machine", "always", "config", "check"])
+            .and_then(|(exit_code, stdout)| {
+                expect_exit_code(exit_code, true)?;
+                envelope(&stdout, "Success").map(drop)
+            }),
+    ));
+    steps.push((
+        "profiles_materialize",
+        sandbox
+            .run(&[
+                "--machine",
+                "always",
+                "profiles",
+                "materialize",
+                &built_in,
+                "--name",
+                PROFILE_NAME,
+            ])
+            .and_then(|(exit_code, stdout)| {
+                expect_exit_code(exit_code, true)?;
+                envelope(&stdout, "Success").map(drop)
+            }),
+    ));
+    steps.push((
+        "profiles_get",
+        sandbox
+            .run(&["--porcelain", "profiles", "get", PROFILE_NAME])
+            .and_then(|(exit_code, stdout)| {
+                expect_exit_code(exit_code, true)?;
+                anyhow::ensure!(
+                    stdout.trim() == PROFILE_NAME,
+                    "expected {}, but got {:?}",
+                    PROFILE_NAME,
+                    stdout.trim()
+                );
+
+                Ok(())
+            }),
+    ));
+    steps.push((
+        "profiles_json",
+        sandbox
+            .run(&["profiles", "json", PROFILE_NAME, "--output", json_path])
+            .and_then(|(exit_code, _)| {
+                expect_exit_code(exit_code, true)?;
+                anyhow::ensure!(
+                    Path::new(json_path).exists(),
+                    "{} wasn't written",
+                    json_path
+                );
+
+                Ok(())
+            }),
+    ));
+    steps.push((
+        "profiles_validate",
+        sandbox
+            .run(&["--porcelain", "profiles", "validate", json_path])
+            .and_then(|(exit_code, stdout)| {
+                expect_exit_code(exit_code, true)?;
+                anyhow::ensure!(
+                    stdout.trim() == "valid=true",
+                    "expected valid=true, but got {:?}",
+                    stdout.trim()
+                );
+
+                Ok(())
+            }),
+    ));
+    steps.push((
+        "profiles_remove",
+        sandbox
+            .run(&["--machine", "always", "profiles", "remove", PROFILE_NAME])
+            .and_then(|(exit_code, stdout)| {
+                expect_exit_code(exit_code, true)?;
+                envelope(&stdout, "Success").map(drop)
+            }),
+    ));
+    steps.push((
+        "profiles_get_removed",
+        sandbox
+            .run(&["--machine", "always", "profiles", "get", PROFILE_NAME])
+            .and_then(|(exit_code, stdout)| {
+                expect_exit_code(exit_code, false)?;
+                envelope(&stdout, "Failure").map(drop)
+            }),
+    ));
+    steps.push((
+        "examples",
+        sandbox
+            .run(&["--machine", "always", "examples"])
+            .and_then(|(exit_code, stdout)| {
+                expect_exit_code(exit_code, true)?;
+                envelope(&stdout, "Success").map(drop)
+            }),
+    ));
+    steps.push((
+        "machine_version",
+        sandbox
+            .run(&["--machine", "always", "--machine-version", "2", "paths"])
+            .and_then(|(exit_code, stdout)| {
+                expect_exit_code(exit_code, true)?;
+                let envelope = envelope(&stdout, "Success")?;
+                anyhow::ensure!(
+                    envelope["version"] == 2 && envelope["warnings"].is_array(),
+                    "the machine output isn't in the shape of version 2"
+                );
+
+                Ok(())
+            }),
+    ));
+    steps.push((
+        "machine_version_unsupported",
+        sandbox
+            .run(&["--machine", "always", "--machine-version", "0", "paths"])
+            .and_then(|(exit_code, _)| expect_exit_code(exit_code, false)),
+    ));
+    steps.push((
+        "battery_aliases",
+        [
+            (
+                &["bc", "enable", "--explain"][..],
+                &["battery", "conservation", "enable", "--explain"][..],
+            ),
+            (
+                &["rapid-charge", "enable", "--explain"][..],
+                &["battery", "rapid-charge", "enable", "--explain"][..],
+            ),
+        ]
+        .into_iter()
+        .try_for_each(|(old, new)| {
+            let mut contents = Vec::new();
+
+            for args in [old, new] {
+                let (exit_code, stdout) =
+                    sandbox.run(&[&["--machine", "always"][..], args].concat())?;
+                expect_exit_code(exit_code, true)?;
+                let mut envelope = envelope(&stdout, "Success")?;
+                contents.push(envelope["contents"].take());
+            }
+
+            anyhow::ensure!(
+                contents[0] == contents[1],
+                "`{}` and `{}` did different things",
+                old.join(" "),
+                new.join(" ")
+            );
+
+            Ok(())
+        }),
+    ));
+    steps.push((
+        "machine_verbose",
+        sandbox
+            .run_with_stderr(&["--machine", "always", "--verbose", "paths"])
+            .and_then(|(exit_code, stdout, stderr)| {
+                expect_exit_code(exit_code, true)?;
+                envelope(&stdout, "Success")?;
+                anyhow::ensure!(
+                    stderr.lines().any(|line| line.starts_with("[debug] ")),
+                    "standard error has no debug lines"
+                );
+                anyhow::ensure!(
+                    !stderr
+                        .lines()
+                        .filter(|line| line.starts_with("[debug] "))
+                        .any(|line| line.contains('\u{1b}')),
+                    "the debug lines on standard error aren't stripped of colors"
+                );
+
+                Ok(())
+            }),
+    ));
+    steps.push((
+        "conflicting_flags",
+        sandbox
+            .run(&["--porcelain", "--machine", "always", "paths"])
+            .and_then(|(exit_code, _)| expect_exit_code(exit_code, false)),
+    ));
+
+    let mut paths = Vec::new();
+    subcommand_paths(&TuxVantage::into_app(), &[], &mut paths);
+    steps.push((
+        "help",
+        paths.iter().try_for_each(|path| {
+            let args = path
+                .iter()
+                .map(String::as_str)
+                .chain(["--help"])
+                .collect::<Vec<_>>();
+            let (exit_code, stdout) = sandbox.run(&args)?;
+            expect_exit_code(exit_code, true).with_context(|| format!("`{}`", args.join(" ")))?;
+            anyhow::ensure!(
+                stdout.contains("USAGE:"),
+                "`{}` didn't print its usage",
+                args.join(" ")
+            );
+
+            Ok(())
+        }),
+    ));
+    steps.push((
+        "help_examples",
+        examples::EXAMPLES
+            .iter()
+            .map(|example| example.command)
+            .unique()
+            .try_for_each(|command| {
+                let args = command.split(' ').chain(["--help"]).collect::<Vec<_>>();
+                let (exit_code, stdout) = sandbox.run(&args)?;
+                expect_exit_code(exit_code, true)
+                    .with_context(|| format!("`{}`", args.join(" ")))?;
+                anyhow::ensure!(
+                    stdout.contains("EXAMPLES:"),
+                    "the help of `{}` doesn't show its examples",
+                    command
+                );
+
+                Ok(())
+            }),
+    ));
+    steps.push((
+        "usage_error",
+        sandbox
+            .run(&["--machine", "always", "tuxvantage-selftest-no-such-command"])
+            .and_then(|(exit_code, _)| {
+                anyhow::ensure!(
+                    exit_code == 2,
+                    "exited with {}, but usage errors exit with 2",
+                    exit_code
+                );
+
+                Ok(())
+            }),
+    ));
+    steps.push((
+        "templates_fields",
+        sandbox
+            .run(&["--porcelain", "templates", "fields", "status"])
+            .and_then(|(exit_code, stdout)| {
+                expect_exit_code(exit_code, true)?;
+                anyhow::ensure!(
+                    stdout.lines().any(|line| line == "status\tmechanism"),
+                    "the placeholders of `status` don't include {{mechanism}}: {:?}",
+                    stdout.trim()
+                );
+
+                Ok(())
+            }),
+    ));
+    steps.push((
+        "templates_fields_unknown",
+        sandbox
+            .run(&["--machine", "always", "templates", "fields", "paths"])
+            .and_then(|(exit_code, stdout)| {
+                expect_exit_code(exit_code, false)?;
+                envelope(&stdout, "Failure").map(drop)
+            }),
+    ));
+
+    // root can write anything, so there is nothing to report when running as root
+    let read_only_file = sandbox.path("config").join("selftest-read-only");
+    let paths_check = fs::write(&read_only_file, "")
+        .and_then(|()| fs::set_permissions(&read_only_file, fs::Permissions::from_mode(0o444)))
+        .context("failed to create a read-only file in the sandbox")
+        .and_then(|()| sandbox.run(&["--machine", "always", "paths", "--check"]))
+        .and_then(|(exit_code, stdout)| {
+            expect_exit_code(exit_code, true)?;
+            let envelope = envelope(&stdout, "Success")?;
+            let reported = envelope["contents"]["entries"]
+                .as_array()
+                .map_or(false, |entries| {
+                    entries.iter().any(|entry| {
+                        entry["path"].as_str() == read_only_file.to_str()
+                            && entry["writable"] == false
+                    })
+                });
+            anyhow::ensure!(
+                reported || utils::is_root(),
+                "{} wasn't reported as not writable",
+                read_only_file.display()
+            );
+
+            Ok(())
+        });
+    steps.push(("paths_check", paths_check));
+
+    // this breaks the config, so it has to be the last step
+    let tuxvantage_toml = sandbox.path("config").join("tuxvantage.toml");
+    let invalid_template = fs::read_to_string(&tuxvantage_toml)
+        .or_else(|error| match error.kind() {
+            io::ErrorKind::NotFound => Ok(String::new()),
+            _ => Err(error),
+        })
+        .and_then(|contents| {
+            fs::write(
+                &tuxvantage_toml,
+                format!(
+                    "{}\n[templates]\nstatus = \"{{tuxvantage_selftest}}\"\n",
+                    contents
+                ),
+            )
+        })
+        .context("failed to write a template into the config")
+        .and_then(|()| sandbox.run(&["--machine", "always", "config", "check"]))
+        .and_then(|(exit_code, stdout)| {
+            expect_exit_code(exit_code, true)?;
+            let envelope = envelope(&stdout, "Success")?;
+            anyhow::ensure!(
+                envelope["contents"]["valid"] == false,
+                "an unknown placeholder in a template wasn't reported"
+            );
+            anyhow::ensure!(
+                envelope["contents"]
+                    .to_string()
+                    .contains("tuxvantage_selftest"),
+                "the finding doesn't name the unknown placeholder"
+            );
+
+            Ok(())
+        });
+    steps.push(("templates_config_check", invalid_template));
+
+    Ok(steps)
+}
+
+/// Runs the commands which don't access the hardware inside of a [`Sandbox`], as a smoke test
+/// after installing this program. The real config is never read or written, and since no step
+/// accesses the hardware, no acpi call is ever made.
+pub fn selftest(machine: bool) -> anyhow::Result<MachineOutput> {
+    let sandbox = Sandbox::new().context("failed to create the sandbox")?;
+    debug!("created the sandbox at '{}'", sandbox.root().display());
+
+    let steps: Vec<_> = steps(&sandbox)?
+        .into_iter()
+        .map(|(name, result)| Step {
+            name,
+            passed: result.is_ok(),
+            message: match result {
+                Ok(()) => String::from("passed"),
+                Err(error) => format!("{:#}", error),
+            },
+        })
+        .collect();
+    let failed = steps.iter().filter(|step| !step.passed).count();
+
+    if !machine {
+        let _guard = log::no_prologue::guard_for(log::Level::Info);
+
+        for step in &steps {
+            if step.passed {
+                info!("{} {}", "pass".bold().green(), step.name);
+            } else {
+                info!("{} {}: {}", "fail".bold().red(), step.name, step.message);
+            }
+        }
+
+        anyhow::ensure!(failed == 0, "{} step(s) of the self test failed", failed);
+    }
+
+    Ok(MachineOutput {
+        passed: failed == 0,
+        steps,
+    })
+}
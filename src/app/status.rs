@@ -0,0 +1,248 @@
+use crate::app::{system_performance, IntoOptionMachineOutput, Porcelain};
+use crate::hardware::{self, Hardware, Snapshot};
+use crate::thresholds::{Battery, Mechanism, Thresholds};
+use crate::types::{BatteryLevel, HumanDuration};
+use crate::{anyhow_with_tip, config, ext, log, TippingAnyhowResultExt};
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+
+/// How often `--watch` redraws if `--interval` isn't given.
+pub const DEFAULT_INTERVAL: HumanDuration = HumanDuration::from_secs(2);
+
+/// Moves the cursor to the top left corner and clears the screen.
+const CLEAR: &str = "\x1b[H\x1b[2J";
+
+#[derive(Serialize)]
+pub struct MachineOutput {
+    #[serde(flatten)]
+    snapshot: Snapshot,
+
+    /// The native charge thresholds, if this model and kernel expose them.
+    thresholds: Option<Thresholds>,
+    mechanism: Mechanism,
+}
+
+impl MachineOutput {
+    pub(crate) fn read(hardware: &mut impl Hardware) -> anyhow_with_tip::Result<Self> {
+        let snapshot = Snapshot::read(hardware)?;
+        let thresholds = match Battery::detect() {
+            Ok(battery) => match battery.get() {
+                Ok(thresholds) => Some(thresholds),
+                Err(error) => {
+                    debug!("failed to read the native thresholds: {:#}", error);
+                    None
+                }
+            },
+            Err(error) => {
+                debug!("{}", error);
+                None
+            }
+        };
+
+        Ok(Self {
+            snapshot,
+            thresholds,
+            mechanism: Mechanism::in_effect(thresholds, snapshot.battery_conservation),
+        })
+    }
+
+    /// The status block, which is the same whether it is printed once or redrawn by `--watch`.
+    fn lines(&self) -> Vec<String> {
+        let thresholds = match self.thresholds {
+            Some(thresholds) => format!("{} to {}", thresholds.start.bold(), thresholds.end.bold()),
+            None => "not supported".italic().to_string(),
+        };
+
+        vec![
+            format!(
+                "battery conservation: {}",
+                format_toggle(self.snapshot.battery_conservation)
+            ),
+            format!(
+                "rapid charging:       {}",
+                format_toggle(self.snapshot.rapid_charge)
+            ),
+            format!(
+                "system performance:   {}",
+                super::format_system_performance_mode(self.snapshot.system_performance)
+            ),
+            format!("charge thresholds:    {}", thresholds),
+            format!(
+                "charge limited by:    {}",
+                self.mechanism.description().bold()
+            ),
+        ]
+    }
+}
+
+impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
+    fn into_option_machine_output(self) -> Option<MachineOutput> {
+        Some(self)
+    }
+}
+
+impl Porcelain for MachineOutput {
+    fn porcelain(&self) -> Vec<String> {
+        let threshold = |threshold: fn(Thresholds) -> BatteryLevel| {
+            self.thresholds.map_or_else(String::new, |thresholds| {
+                threshold(thresholds).inner().to_string()
+            })
+        };
+
+        vec![
+            super::pair(
+                "battery_conservation",
+                super::toggle_name(self.snapshot.battery_conservation),
+            ),
+            super::pair(
+                "rapid_charge",
+                super::toggle_name(self.snapshot.rapid_charge),
+            ),
+            super::pair(
+                "system_performance",
+                system_performance::porcelain_name(self.snapshot.system_performance),
+            ),
+            super::pair("start_threshold", threshold(|thresholds| thresholds.start)),
+            super::pair("end_threshold", threshold(|thresholds| thresholds.end)),
+            super::pair("mechanism", self.mechanism.name()),
+        ]
+    }
+}
+
+fn format_toggle(enabled: bool) -> String {
+    if enabled {
+        "enabled".green().bold().to_string()
+    } else {
+        "disabled".red().bold().to_string()
+    }
+}
+
+pub fn status() -> anyhow_with_tip::Result<MachineOutput> {
+    let output = MachineOutput::read(&mut hardware::get())?;
+
+    if !config::machine() && !super::print_template("status", &output) {
+        let _guard = log::no_prologue::guard_for(log::Level::Info);
+
+        for line in output.lines() {
+            info!("{}", line);
+        }
+    }
+
+    Ok(output)
+}
+
+/// Redraws the status block every `interval` until interrupted, reading the settings again each
+/// time. Every frame is built in full before it is written, so that the terminal never shows a
+/// half drawn one.
+pub fn watch(interval: HumanDuration) -> anyhow_with_tip::Result<()> {
+    if config::machine().get() || atty::isnt(atty::Stream::Stdout) {
+        return Err(anyhow::anyhow!(
+            "{} redraws the terminal, so it can't be used in machine mode or when standard \
+             output isn't a terminal",
+            "--watch".bold()
+        ))
+        .tip(ext::STATUS_WATCH_TIP);
+    }
+
+    if interval == HumanDuration::ZERO {
+        return Err(anyhow::anyhow!("the interval of {} can't be zero", "--watch".bold()).into());
+    }
+
+    let mut signals = Signals::new([SIGINT, SIGTERM])
+        .context("failed to register handler for application exits")
+        .no_tip()?;
+    let handle = signals.handle();
+    let (sender, receiver) = mpsc::sync_channel(1);
+
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            debug!("received signal {}, stop watching", signal);
+            let _ = sender.send(());
+        }
+    });
+
+    let mut hardware = hardware::get();
+
+    loop {
+        let output = MachineOutput::read(&mut hardware)?;
+        let mut frame = format!(
+            "{}every {}, press Ctrl-C to exit\n\n",
+            CLEAR,
+            interval.bold()
+        );
+
+        let lines = match super::rendered_template("status", &output) {
+            Some(rendered) => vec![rendered],
+            None => output.lines(),
+        };
+
+        for line in lines {
+            frame.push_str(&line);
+            frame.push('\n');
+        }
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        stdout
+            .write_all(frame.as_bytes())
+            .and_then(|()| stdout.flush())
+            .context("failed to draw the status")
+            .no_tip()?;
+        drop(stdout);
+
+        match receiver.recv_timeout(interval.0) {
+            Err(RecvTimeoutError::Timeout) => continue,
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    handle.close();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ideapad::SystemPerformanceMode;
+
+    /// Monitoring agents depend on these field names, so renaming one has to show up here.
+    #[test]
+    fn schema() {
+        let thresholds = Thresholds {
+            start: BatteryLevel::new(40).unwrap(),
+            end: BatteryLevel::new(80).unwrap(),
+        };
+        let output = MachineOutput {
+            snapshot: Snapshot {
+                battery_conservation: false,
+                rapid_charge: true,
+                system_performance: SystemPerformanceMode::ExtremePerformance,
+            },
+            thresholds: Some(thresholds),
+            mechanism: Mechanism::in_effect(Some(thresholds), false),
+        };
+
+        insta::assert_snapshot!(serde_json::to_string_pretty(&output).unwrap());
+    }
+
+    #[test]
+    fn schema_without_thresholds() {
+        let output = MachineOutput {
+            snapshot: Snapshot {
+                battery_conservation: true,
+                rapid_charge: false,
+                system_performance: SystemPerformanceMode::IntelligentCooling,
+            },
+            thresholds: None,
+            mechanism: Mechanism::in_effect(None, true),
+        };
+
+        insta::assert_snapshot!(serde_json::to_string_pretty(&output).unwrap());
+    }
+}
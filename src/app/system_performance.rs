@@ -1,15 +1,36 @@
-use crate::app::IntoOptionMachineOutput;
+use crate::app::{IntoOptionMachineOutput, Porcelain, Readings, Settle};
 use crate::args::FromStrSystemPerformanceMode;
 use crate::ext::AnyhowResultExt;
-use crate::{anyhow_with_tip, config, context};
-use anyhow::Context;
+use crate::hardware::{self, Hardware};
+use crate::history::{self, Initiator};
+use crate::{anyhow_with_tip, api, config, context, ec_cooldown, format, state};
 use ideapad::SystemPerformanceMode;
+use owo_colors::OwoColorize;
 
 #[derive(Serialize)]
 #[serde(untagged)]
 pub enum MachineOutput {
     Get {
         system_performance_mode: SystemPerformanceMode,
+
+        /// The raw value of the FCMO bit, when it was read.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fcmo: Option<u32>,
+
+        /// The raw value of the SPMO bit, when it was read.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        spmo: Option<u32>,
+
+        /// The entry of the profile's bit table the raw values matched, or `no match`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        matched_bits: Option<&'static str>,
+    },
+    Changed {
+        changed: bool,
+
+        /// The setting as read back with `--settle`, if it was given and the setting changed.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        settle: Option<Readings<SystemPerformanceMode>>,
     },
 }
 
@@ -19,41 +40,155 @@ impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
     }
 }
 
-pub fn get() -> anyhow_with_tip::Result<MachineOutput> {
-    let system_performance_mode = ideapad::system_performance::get(context::get())
-        .context("failed to get system performance mode")
-        .maybe_acpi_call_tip()?;
+impl Porcelain for MachineOutput {
+    fn porcelain(&self) -> Vec<String> {
+        match self {
+            Self::Get {
+                system_performance_mode,
+                ..
+            } => vec![porcelain_name(*system_performance_mode).to_string()],
+            Self::Changed { changed, .. } => vec![super::pair("changed", changed)],
+        }
+    }
+}
+
+/// The name of `mode` in porcelain output, which is also how it is given on the command line.
+pub fn porcelain_name(mode: SystemPerformanceMode) -> &'static str {
+    match mode {
+        SystemPerformanceMode::IntelligentCooling => "intelligent-cooling",
+        SystemPerformanceMode::ExtremePerformance => "extreme-performance",
+        SystemPerformanceMode::BatterySaving => "battery-saving",
+    }
+}
+
+/// The raw FCMO and SPMO bits as read from the firmware.
+struct RawBits {
+    fcmo: u32,
+    spmo: u32,
+}
+
+impl RawBits {
+    fn read(hardware: &mut impl Hardware) -> anyhow::Result<Self> {
+        let (fcmo, spmo) = hardware.performance_bits()?;
+
+        Ok(Self { fcmo, spmo })
+    }
+
+    /// The name of the entry of the profile's bit table these bits match.
+    fn matched(&self) -> &'static str {
+        let bits = &context::get().profile.system_performance.bits;
+
+        [
+            ("intelligent_cooling", bits.intelligent_cooling),
+            ("extreme_performance", bits.extreme_performance),
+            ("battery_saving", bits.battery_saving),
+        ]
+        .into_iter()
+        .find(|(_, bit)| u32::from(bit.fcmo()) == self.fcmo && u32::from(bit.spmo()) == self.spmo)
+        .map_or("no match", |(name, _)| name)
+    }
+}
+
+fn read_mode(hardware: &mut impl Hardware) -> anyhow_with_tip::Result<SystemPerformanceMode> {
+    api::read_performance_mode(hardware).maybe_acpi_call_tip()
+}
+
+pub fn get(raw: bool) -> anyhow_with_tip::Result<MachineOutput> {
+    let machine = config::machine().get();
+    let mut hardware = hardware::get();
+    let raw_bits = if raw || machine {
+        Some(RawBits::read(&mut hardware).maybe_acpi_call_tip()?)
+    } else {
+        None
+    };
+    let matched_bits = raw_bits.as_ref().map(RawBits::matched);
 
-    if !config::machine() {
+    if let (Some(raw_bits), Some(matched_bits), false) = (&raw_bits, matched_bits, machine) {
+        info!(
+            "the firmware reported the FCMO bit {} {} and the SPMO bit {} {}",
+            raw_bits.fcmo.bold(),
+            format_args!("({})", format::hex_u32(raw_bits.fcmo)).italic(),
+            raw_bits.spmo.bold(),
+            format_args!("({})", format::hex_u32(raw_bits.spmo)).italic()
+        );
+        info!(
+            "these bits match {} in the bit table of the profile",
+            matched_bits.bold()
+        );
+    }
+
+    let system_performance_mode = read_mode(&mut hardware)?;
+
+    let output = MachineOutput::Get {
+        system_performance_mode,
+        fcmo: raw_bits.as_ref().map(|raw_bits| raw_bits.fcmo),
+        spmo: raw_bits.as_ref().map(|raw_bits| raw_bits.spmo),
+        matched_bits,
+    };
+
+    if !machine && !super::print_template("system-performance get", &output) {
         info!(
             "the system performance mode is {}",
             super::format_system_performance_mode(system_performance_mode)
         )
     }
 
-    Ok(MachineOutput::Get {
-        system_performance_mode,
-    })
+    Ok(output)
 }
 
-pub fn set(mode: FromStrSystemPerformanceMode) -> anyhow_with_tip::Result<()> {
+pub fn set(
+    mode: FromStrSystemPerformanceMode,
+    remember: bool,
+    settle: Option<Settle>,
+) -> anyhow_with_tip::Result<MachineOutput> {
     let mode = mode.0;
+    let machine = config::machine();
+    let mut hardware = hardware::get();
+    let old = read_mode(&mut hardware)?;
+    let changed = old != mode;
+    let mut readings = None;
 
-    ideapad::system_performance::set(context::get(), mode)
-        .with_context(|| {
-            format!(
-                "failed to set the system performance mode to {}",
+    if changed {
+        ec_cooldown::guard("system_performance");
+        api::write_performance_mode(&mut hardware, mode).maybe_acpi_call_tip()?;
+        history::record(
+            "system_performance",
+            super::format_system_performance_mode_plain(old),
+            super::format_system_performance_mode_plain(mode),
+            Initiator::Cli,
+        );
+
+        if !machine {
+            info!(
+                "the system performance mode has been set to {}",
                 super::format_system_performance_mode(mode)
-            )
-        })
-        .maybe_acpi_call_tip()?;
+            );
+        }
 
-    if !config::machine() {
+        readings = settle
+            .map(|settle| {
+                super::verify_settled(
+                    "the system performance mode",
+                    mode,
+                    settle,
+                    super::format_system_performance_mode_plain,
+                    || read_mode(&mut hardware),
+                )
+            })
+            .transpose()?;
+    } else if !machine {
         info!(
-            "the system performance mode has been set to {}",
+            "the system performance mode is already {}, nothing to do",
             super::format_system_performance_mode(mode)
         );
     }
 
-    Ok(())
+    if remember {
+        state::remember(|desired| desired.system_performance = Some(mode))?;
+    }
+
+    Ok(MachineOutput::Changed {
+        changed,
+        settle: readings,
+    })
 }
@@ -0,0 +1,75 @@
+use crate::app::{IntoOptionMachineOutput, Porcelain};
+use crate::templates;
+use crate::{config, log};
+use itertools::Itertools;
+use owo_colors::OwoColorize;
+
+#[derive(Serialize)]
+pub struct CommandFields {
+    command: &'static str,
+    fields: &'static [&'static str],
+}
+
+#[derive(Serialize)]
+pub struct MachineOutput {
+    commands: Vec<CommandFields>,
+}
+
+impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
+    fn into_option_machine_output(self) -> Option<MachineOutput> {
+        Some(self)
+    }
+}
+
+impl Porcelain for MachineOutput {
+    fn porcelain(&self) -> Vec<String> {
+        self.commands
+            .iter()
+            .flat_map(|command| {
+                command
+                    .fields
+                    .iter()
+                    .map(move |field| format!("{}\t{}", command.command, field))
+            })
+            .collect()
+    }
+}
+
+/// Lists the placeholders the templates of `command` can use, or of every command which can be
+/// given a template if it is empty.
+pub fn fields(command: Vec<String>) -> anyhow::Result<MachineOutput> {
+    let command = command.join(" ");
+    let commands = templates::COMMANDS
+        .iter()
+        .filter(|(name, _)| command.is_empty() || *name == command)
+        .map(|&(command, fields)| CommandFields { command, fields })
+        .collect::<Vec<_>>();
+
+    anyhow::ensure!(
+        !commands.is_empty(),
+        "{} can't be given a template, only {} can",
+        command.bold(),
+        templates::COMMANDS
+            .iter()
+            .map(|(name, _)| name.bold())
+            .join(", ")
+    );
+
+    if !config::machine() {
+        let _guard = log::no_prologue::guard_for(log::Level::Info);
+
+        for (index, command) in commands.iter().enumerate() {
+            if index != 0 {
+                info!("");
+            }
+
+            info!("{}:", command.command.bold());
+
+            for field in command.fields {
+                info!("{}{{{}}}", super::tab(2), field);
+            }
+        }
+    }
+
+    Ok(MachineOutput { commands })
+}
@@ -0,0 +1,258 @@
+use crate::app::{IntoOptionMachineOutput, Porcelain};
+use crate::ext::AnyhowResultExt;
+use crate::hardware::{self, Hardware, Snapshot};
+use crate::history::{self, Initiator};
+use crate::{anyhow_with_tip, api, config, ec_cooldown, utils, TippingAnyhowResultExt};
+use anyhow::Context;
+use ideapad::{Handler, SystemPerformanceMode};
+use owo_colors::OwoColorize;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus};
+use std::thread;
+
+#[derive(Serialize)]
+pub struct MachineOutput {
+    before: Snapshot,
+
+    /// The state after restoring, unless it couldn't be read.
+    after: Option<Snapshot>,
+
+    /// The exit code of the command, which is 128 plus the signal if it was killed by one.
+    exit_code: i32,
+
+    /// The settings which couldn't be restored.
+    restore_errors: Vec<String>,
+}
+
+impl MachineOutput {
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code
+    }
+}
+
+impl IntoOptionMachineOutput<MachineOutput> for MachineOutput {
+    fn into_option_machine_output(self) -> Option<MachineOutput> {
+        Some(self)
+    }
+}
+
+impl Porcelain for MachineOutput {
+    fn porcelain(&self) -> Vec<String> {
+        vec![
+            super::pair("exit_code", self.exit_code),
+            super::pair("restored", self.restore_errors.is_empty()),
+        ]
+    }
+}
+
+fn enabled_str(enabled: bool) -> &'static str {
+    if enabled {
+        "enabled"
+    } else {
+        "disabled"
+    }
+}
+
+/// Changes the settings from `current` to `target`. Settings being switched off are changed
+/// first, so that the handlers never have to switch off the opposing mode themselves. Every
+/// setting is attempted, and the ones which failed are returned.
+fn change(
+    hardware: &mut impl Hardware,
+    current: Snapshot,
+    target: Snapshot,
+    handlers: (Handler, Handler),
+) -> Vec<anyhow_with_tip::Error> {
+    let (battery_conservation_handler, rapid_charging_handler) = handlers;
+    let mut errors = Vec::new();
+    let mut toggles = [
+        (
+            "battery_conservation",
+            current.battery_conservation,
+            target.battery_conservation,
+        ),
+        ("rapid_charge", current.rapid_charge, target.rapid_charge),
+    ];
+    toggles.sort_by_key(|(_, _, target)| *target);
+
+    for (setting, current, target) in toggles {
+        if current == target {
+            continue;
+        }
+
+        ec_cooldown::guard(setting);
+        let result = if setting == "battery_conservation" {
+            hardware
+                .set_conservation(target, battery_conservation_handler)
+                .with_context(|| {
+                    format!(
+                        "failed to set battery conservation mode to {}",
+                        enabled_str(target)
+                    )
+                })
+        } else {
+            hardware
+                .set_rapid_charge(target, rapid_charging_handler)
+                .with_context(|| format!("failed to set rapid charging to {}", enabled_str(target)))
+        };
+
+        match result.maybe_acpi_call_tip() {
+            Ok(()) => history::record(
+                setting,
+                enabled_str(current),
+                enabled_str(target),
+                Initiator::With,
+            ),
+            Err(error) => errors.push(error),
+        }
+    }
+
+    if current.system_performance != target.system_performance {
+        ec_cooldown::guard("system_performance");
+        let result =
+            api::write_performance_mode(hardware, target.system_performance).maybe_acpi_call_tip();
+
+        match result {
+            Ok(()) => history::record(
+                "system_performance",
+                super::format_system_performance_mode_plain(current.system_performance),
+                super::format_system_performance_mode_plain(target.system_performance),
+                Initiator::With,
+            ),
+            Err(error) => errors.push(error),
+        }
+    }
+
+    errors
+}
+
+/// The exit code a shell would report for `status`.
+fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+/// Runs `command` with the given settings changed, then restores the settings from before. The
+/// settings are also restored if this program is interrupted, which is passed on to the
+/// command.
+pub fn with(
+    battery_conservation: Option<bool>,
+    rapid_charge: Option<bool>,
+    system_performance: Option<SystemPerformanceMode>,
+    command: Vec<String>,
+) -> anyhow_with_tip::Result<MachineOutput> {
+    if battery_conservation == Some(true) && rapid_charge == Some(true) {
+        return Err(anyhow::anyhow!(
+            "battery conservation mode and rapid charging can't both be on"
+        )
+        .into());
+    }
+
+    let (program, arguments) = command
+        .split_first()
+        .context("no command to run was given")
+        .no_tip()?;
+    let config = config::read();
+    let machine = config.tuxvantage.machine();
+    let handlers = config.tuxvantage.handlers();
+    let handlers = (handlers.battery_conservation(), handlers.rapid_charging());
+    drop(config);
+
+    let mut hardware = hardware::get();
+    let before = Snapshot::read(&mut hardware)?;
+    let during = Snapshot {
+        battery_conservation: battery_conservation.unwrap_or(before.battery_conservation),
+        rapid_charge: rapid_charge.unwrap_or(before.rapid_charge),
+        system_performance: system_performance.unwrap_or(before.system_performance),
+    };
+
+    debug!("change the state from {:?} to {:?}", before, during);
+    let errors = change(&mut hardware, before, during, handlers);
+
+    if let Some(error) = errors.into_iter().next() {
+        let current = Snapshot::read(&mut hardware)?;
+        let restore_errors = change(&mut hardware, current, before, handlers);
+
+        for restore_error in restore_errors {
+            warn!(
+                "{}",
+                utils::dedup_error_chain_for_humans(&restore_error.source)
+            );
+        }
+
+        return Err(error);
+    }
+
+    // caught so that this program lives on to restore the settings. an interrupt from the
+    // terminal already reaches the command since it is in the same process group, so only
+    // termination is forwarded
+    let mut signals = Signals::new([SIGINT, SIGTERM])
+        .context("failed to register handler for application exits")
+        .no_tip()?;
+    let handle = signals.handle();
+
+    if !machine {
+        info!("running {}", command.join(" ").bold());
+    }
+
+    let result = Command::new(program)
+        .args(arguments)
+        .spawn()
+        .with_context(|| format!("failed to run {}", program.bold()));
+    let status = result.and_then(|mut child| {
+        let id = child.id() as libc::pid_t;
+
+        thread::spawn(move || {
+            for signal in signals.forever().filter(|signal| *signal == SIGTERM) {
+                debug!("forward signal {} to the command", signal);
+
+                // SAFETY: sending a signal doesn't touch any memory of this process
+                unsafe { libc::kill(id, signal) };
+            }
+        });
+
+        child
+            .wait()
+            .with_context(|| format!("failed to wait for {}", program.bold()))
+    });
+    handle.close();
+
+    debug!("restore the state to {:?}", before);
+    let restore_errors = match Snapshot::read(&mut hardware) {
+        Ok(current) => change(&mut hardware, current, before, handlers),
+        Err(error) => vec![error],
+    };
+
+    for error in &restore_errors {
+        warn!(
+            "failed to restore the settings from before the command ran: {}",
+            utils::dedup_error_chain_for_humans(&error.source)
+        );
+    }
+
+    let status = status.no_tip()?;
+    let after = Snapshot::read(&mut hardware).ok();
+
+    if !machine {
+        if restore_errors.is_empty() {
+            info!("restored the settings from before the command ran");
+        }
+
+        if !status.success() {
+            info!("{} exited with {}", program.bold(), status);
+        }
+    }
+
+    Ok(MachineOutput {
+        before,
+        after,
+        exit_code: exit_code(status),
+        restore_errors: restore_errors
+            .iter()
+            .map(|error| utils::dedup_error_chain_for_humans(&error.source))
+            .collect(),
+    })
+}
@@ -1,42 +1,159 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
 use std::str::FromStr;
 
-use crate::config::{Backtrace, BatteryLevel, BatteryMatches, CoolDown, Machine};
-use clap::Parser;
+#[cfg(feature = "regulate")]
+use crate::app::battery_conservation::DEFAULT_DEADBAND;
+use crate::app::config::HandlerTarget;
+use crate::app::doctor::CheckName;
+#[cfg(feature = "regulate")]
+use crate::app::rapid_charge::DEFAULT_TOP_UP_INTERVAL;
+#[cfg(feature = "regulate")]
+use crate::config::{Aggregate, BatteryConfig, BatteryMatches, Listen, OnExit};
+use crate::config::{Backtrace, HandlerSource, Machine, Trigger};
+use crate::init_system::InitSystem;
+#[cfg(feature = "regulate")]
+use crate::types::Deadline;
+use crate::types::{BatteryLevel, HumanDuration, ServiceName};
+use crate::utils::{self, Names};
+use crate::{config, examples, machine};
+#[cfg(feature = "regulate")]
+use clap::Args;
+use clap::{AppSettings, ErrorKind, FromArgMatches, IntoApp, Parser, PossibleValue};
+use clap_complete::Shell;
 use ideapad::{Handler, SystemPerformanceMode};
+use once_cell::sync::OnceCell;
+use owo_colors::OwoColorize;
+use std::{env, mem};
+
+/// A handler as understood by the command line, which can do more than ideapad's [`Handler`].
+#[derive(Debug, Copy, Clone)]
+pub enum HandlerPolicy {
+    Handler(Handler),
+
+    /// Like [`Handler::Switch`], but remembers that the opposing mode was switched off so that it
+    /// can be switched back on later with `--restore`.
+    SwitchBack,
+}
+
+impl HandlerPolicy {
+    pub fn handler(self) -> Handler {
+        match self {
+            Self::Handler(handler) => handler,
+            Self::SwitchBack => Handler::Switch,
+        }
+    }
+
+    pub fn switches_back(self) -> bool {
+        matches!(self, Self::SwitchBack)
+    }
+}
+
+impl PartialEq for HandlerPolicy {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            // all variants of `Handler` are unit variants, so comparing discriminants is enough
+            (Self::Handler(this), Self::Handler(other)) => {
+                mem::discriminant(this) == mem::discriminant(other)
+            }
+            (Self::SwitchBack, Self::SwitchBack) => true,
+            _ => false,
+        }
+    }
+}
 
 #[derive(Debug)]
-pub struct FromStrHandler(pub Handler);
+pub struct FromStrHandler(pub HandlerPolicy);
+
+impl FromStrHandler {
+    pub const NAMES: Names<HandlerPolicy> = &[
+        (HandlerPolicy::Handler(Handler::Switch), &["switch", "s"]),
+        (HandlerPolicy::Handler(Handler::Ignore), &["ignore", "i"]),
+        (HandlerPolicy::Handler(Handler::Error), &["error", "e"]),
+        (HandlerPolicy::SwitchBack, &["switch-back", "sb", "b"]),
+    ];
+}
 
 impl FromStr for FromStrHandler {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "ignore" | "i" => Ok(Self(Handler::Ignore)),
-            "error" | "e" => Ok(Self(Handler::Error)),
-            "switch" | "s" => Ok(Self(Handler::Switch)),
-            _ => anyhow::bail!("invalid handler '{}'", s),
+        utils::parse_name(Self::NAMES, "handler", s).map(Self)
+    }
+}
+
+/// The possible values of an argument, so that clap can list them and suggest the closest one
+/// on a typo.
+fn possible_values<T>(names: Names<T>) -> Vec<PossibleValue<'static>> {
+    names
+        .iter()
+        .map(|(_, names)| PossibleValue::new(names[0]).aliases(names[1..].iter().copied()))
+        .collect()
+}
+
+/// Resolves the handler of an enable subcommand, which can be given either positionally or with
+/// `--handler`, along with where it came from.
+pub fn enable_handler(
+    positional: Option<FromStrHandler>,
+    flag: Option<FromStrHandler>,
+) -> anyhow::Result<Option<(FromStrHandler, HandlerSource)>> {
+    match (positional, flag) {
+        (Some(positional), Some(flag)) => {
+            anyhow::ensure!(
+                positional.0 == flag.0,
+                "the handler was given both positionally and with {}, but they differ",
+                "--handler".bold()
+            );
+
+            Ok(Some((flag, HandlerSource::Flag)))
         }
+        (_, Some(flag)) => Ok(Some((flag, HandlerSource::Flag))),
+        (Some(positional), None) => Ok(Some((positional, HandlerSource::Positional))),
+        (None, None) => Ok(None),
     }
 }
 
 #[derive(Debug)]
 pub struct FromStrSystemPerformanceMode(pub SystemPerformanceMode);
 
+impl FromStrSystemPerformanceMode {
+    pub const NAMES: Names<SystemPerformanceMode> = &[
+        (
+            SystemPerformanceMode::IntelligentCooling,
+            &["intelligent-cooling", "ic", "i"],
+        ),
+        (
+            SystemPerformanceMode::ExtremePerformance,
+            &["extreme-performance", "ep", "e"],
+        ),
+        (
+            SystemPerformanceMode::BatterySaving,
+            &["battery-saving", "bs", "b"],
+        ),
+    ];
+}
+
 impl FromStr for FromStrSystemPerformanceMode {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "intelligent-cooling" | "ic" | "i" => {
-                Ok(Self(SystemPerformanceMode::IntelligentCooling))
-            }
-            "extreme-performance" | "ep" | "e" => {
-                Ok(Self(SystemPerformanceMode::ExtremePerformance))
-            }
-            "battery-saving" | "bs" | "b" => Ok(Self(SystemPerformanceMode::BatterySaving)),
-            _ => anyhow::bail!("invalid system performance mode '{}'", s),
-        }
+        utils::parse_name(Self::NAMES, "system performance mode", s).map(Self)
+    }
+}
+
+/// Whether a setting should be on or off, such as for `with`.
+#[derive(Debug, Copy, Clone)]
+pub struct FromStrToggle(pub bool);
+
+impl FromStrToggle {
+    pub const NAMES: Names<bool> = &[(true, &["on", "enabled"]), (false, &["off", "disabled"])];
+}
+
+impl FromStr for FromStrToggle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        utils::parse_name(Self::NAMES, "toggle", s).map(Self)
     }
 }
 
@@ -50,27 +167,83 @@ pub struct TuxVantage {
     pub profile: Option<String>,
 
     /// Enable machine readable output for robots. Overrides the config file.
-    #[clap(short, long)]
+    #[clap(short, long, possible_values = possible_values(Machine::NAMES))]
     pub machine: Option<Machine>,
 
+    /// The version of the shape of the machine output, so that scripts keep working when newer
+    /// versions add to it. Takes precedence over the `TUXVANTAGE_MACHINE_VERSION` environment
+    /// variable, which overrides the config file. Defaults to 1.
+    #[clap(long, value_name = "VERSION")]
+    pub machine_version: Option<machine::Version>,
+
+    /// Print plain lines for shell scripts to standard output, while everything else still goes
+    /// to standard error. Can't be used with `--machine`. These formats are stable: `enabled`
+    /// and `disabled` print `enabled` or `disabled`, `system-performance get` prints the name of
+    /// the mode such as `intelligent-cooling`, `profiles get` prints one name per line, and
+    /// everything else prints `key=value` pairs, one per line, if anything.
+    #[clap(long)]
+    pub porcelain: bool,
+
     /// Panic on error. Should be used for debugging purposes only. Overrides the config file.
     #[clap(short = 'P', long)]
     pub panic: bool,
 
+    /// Don't panic on error, even if the config file says to. Can't be used with `--panic`.
+    #[clap(long, conflicts_with = "panic")]
+    pub no_panic: bool,
+
     /// Set the backtrace configuration. In the format "[panics (0 or 1)],[errors (0 or 1)]".
-    /// Overrides the config file.
-    #[clap(short, long, default_value_t)]
-    pub backtrace: Backtrace,
+    /// Overrides the config file, including when both are 0. If not passed, the config file is
+    /// used.
+    #[clap(short, long)]
+    pub backtrace: Option<Backtrace>,
 
     /// The handler to use. If not passed, it will use the config file, and if it isn't passed
-    /// there either, it will use `switch`. Overrides the config file.
-    #[clap(short, long)]
+    /// there either, it will use `switch`. `switch-back` behaves like `switch`, but allows
+    /// switching the opposing mode back on with `--restore`. Overrides the config file.
+    #[clap(short, long, possible_values = possible_values(FromStrHandler::NAMES))]
     pub handler: Option<FromStrHandler>,
 
-    /// Enable verbose output.
+    /// Don't show tips alongside errors. Overrides the config file.
+    #[clap(long)]
+    pub no_tips: bool,
+
+    /// Don't page long output, such as the list of profiles, when it doesn't fit on the
+    /// terminal. Overrides the config file.
+    #[clap(long)]
+    pub no_pager: bool,
+
+    /// When running as root, load the `acpi_call` kernel module and retry once if it isn't
+    /// loaded. Overrides the config file.
+    #[clap(long)]
+    pub auto_modprobe: bool,
+
+    /// Wait until the firmware accepts changes again if a setting was changed too recently,
+    /// instead of only warning that the change may be ignored. How long that is comes from
+    /// `ec_cooldown` in the config file, and defaults to 5 seconds. Overrides the config file.
+    #[clap(long)]
+    pub respect_ec_cooldown: bool,
+
+    /// Enable verbose output, which goes to standard error. With `--machine`, or when standard
+    /// error isn't a terminal, every debug line is prefixed with `[debug]` and has no colors.
     #[clap(short, long)]
     pub verbose: bool,
 
+    /// The directory to use for the configuration. Takes precedence over the
+    /// `TUXVANTAGE_CONFIG_DIR` environment variable.
+    #[clap(long)]
+    pub config: Option<PathBuf>,
+
+    /// If this command fails, write a bundle for a bug report into this directory: the failure,
+    /// the configuration and profile in use, kernel module information, and the verbose log of
+    /// this run. Serial numbers and hostnames are redacted, and nothing is sent anywhere.
+    #[clap(long, value_name = "DIR")]
+    pub bug_report: Option<PathBuf>,
+
+    /// Never write to the configuration. Overrides the config file.
+    #[clap(long)]
+    pub read_only: bool,
+
     /// Skip consistency checks. Should be used for debugging purposes only.
     #[clap(long)]
     pub skip_consistency_checks: bool,
@@ -79,23 +252,485 @@ pub struct TuxVantage {
     pub action: TuxVantageAction,
 }
 
+impl TuxVantage {
+    /// Whether to panic on error as given on the command line, or `None` if neither `--panic`
+    /// nor `--no-panic` was given so that the config file decides.
+    pub fn panic_override(&self) -> Option<bool> {
+        if self.panic {
+            Some(true)
+        } else if self.no_panic {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
 #[derive(Debug, Parser)]
 pub enum TuxVantageAction {
-    /// Manage battery conservation mode.
+    /// Manage the battery: battery conservation mode, rapid charging, and holding it at a charge
+    /// level.
     #[clap(subcommand)]
+    Battery(TuxVantageBattery),
+
+    /// Manage battery conservation mode. Hidden in favor of `battery conservation`, but kept so
+    /// that scripts using it don't break.
+    #[clap(subcommand, setting = AppSettings::Hidden)]
     BatteryConservation(TuxVantageBatteryConservation),
 
     /// Manage the system performance mode.
     #[clap(subcommand)]
     SystemPerformance(TuxVantageSystemPerformance),
 
-    /// Manage rapid charging.
-    #[clap(subcommand)]
+    /// Manage rapid charging. Hidden in favor of `battery rapid-charge`, but kept so that scripts
+    /// using it don't break.
+    #[clap(subcommand, setting = AppSettings::Hidden)]
     RapidCharge(TuxVantageRapidCharge),
 
     /// Manage the profiles.
     #[clap(subcommand)]
     Profiles(TuxVantageProfiles),
+
+    /// Manage the configuration.
+    #[clap(subcommand)]
+    Config(TuxVantageConfig),
+
+    /// Inspect or reset `.consistency.json`, which tracks the installed services and the
+    /// executable they run.
+    #[clap(subcommand)]
+    Consistency(TuxVantageConsistency),
+
+    /// Check whether this program can work on this machine. The report of `--machine always` is
+    /// stable, so that monitoring agents such as Nagios or Zabbix can scrape it.
+    #[clap(after_help = examples::after_help("doctor"))]
+    Doctor {
+        /// Only run these checks, separated by commas. The checks are `config`, `profile`,
+        /// `access` and `battery`.
+        #[clap(long, use_delimiter = true, possible_values = possible_values(CheckName::NAMES))]
+        only: Vec<CheckName>,
+
+        /// Exit with 0 if every check is ok, 1 if one warns and 2 if one fails. Otherwise, only a
+        /// failed check exits with an error.
+        #[clap(long)]
+        exit_by_severity: bool,
+    },
+
+    /// Apply the desired state from the config and the remembered values.
+    #[clap(visible_alias = "a")]
+    #[clap(after_help = examples::after_help("apply"))]
+    Apply {
+        /// What caused the desired state to be applied. Selects the policy table to use, one of
+        /// `ac`, `battery`, or `boot`.
+        trigger: Option<Trigger>,
+    },
+
+    /// Run a command with battery conservation mode, rapid charging or the system performance
+    /// mode changed, restoring them once it exits or this program is interrupted.
+    #[clap(after_help = examples::after_help("with"))]
+    With {
+        /// Switch battery conservation mode `on` or `off` while the command runs.
+        #[clap(long, possible_values = possible_values(FromStrToggle::NAMES))]
+        conservation: Option<FromStrToggle>,
+
+        /// Switch rapid charging `on` or `off` while the command runs.
+        #[clap(long, possible_values = possible_values(FromStrToggle::NAMES))]
+        rapid_charge: Option<FromStrToggle>,
+
+        /// The system performance mode to use while the command runs.
+        #[clap(long, possible_values = possible_values(FromStrSystemPerformanceMode::NAMES))]
+        performance: Option<FromStrSystemPerformanceMode>,
+
+        /// The command to run and its arguments, after `--`.
+        #[clap(required = true, last = true)]
+        command: Vec<String>,
+    },
+
+    /// Print whether battery conservation mode and rapid charging are enabled, and the system
+    /// performance mode.
+    #[clap(after_help = examples::after_help("status"))]
+    Status {
+        /// Redraw the status until interrupted with Ctrl-C, like `watch`. Needs a terminal, so it
+        /// can't be used in machine mode.
+        #[clap(short, long)]
+        watch: bool,
+
+        /// How often to redraw with `--watch`. Defaults to `2s`.
+        #[clap(short, long, requires = "watch")]
+        interval: Option<HumanDuration>,
+    },
+
+    /// Show the changes this program made to the hardware, newest last.
+    #[clap(visible_alias = "hist")]
+    #[clap(after_help = examples::after_help("history"))]
+    History {
+        /// How many of the most recent changes to show.
+        #[clap(short, long, default_value_t = 20)]
+        limit: usize,
+
+        /// Only show these columns, in this order, separated by commas. The columns are `time`,
+        /// `setting`, `old`, `new`, `initiator`, `ec_cooldown` and `command`. `ec_cooldown` is
+        /// `within` for changes made so soon after the previous one that the firmware may have
+        /// ignored them.
+        #[clap(short, long, use_delimiter = true)]
+        columns: Vec<String>,
+
+        /// Don't print the header, such as for `awk`.
+        #[clap(long)]
+        no_header: bool,
+
+        /// Don't truncate long values to fit in the terminal.
+        #[clap(short, long)]
+        wide: bool,
+    },
+
+    /// Print the resolved locations of the files and directories used by this program.
+    #[clap(after_help = examples::after_help("paths"))]
+    Paths {
+        /// List the files and directories in the config, profiles and state directories which
+        /// can't be written or which aren't owned by the owner of the config directory, such as
+        /// those created by running this program with `sudo`.
+        #[clap(long)]
+        check: bool,
+
+        /// Give the listed files and directories back to the owner of the config directory. Needs
+        /// to be run as root.
+        #[clap(long, requires = "check")]
+        fix: bool,
+    },
+
+    /// Check that the installed battery conservation regulator service still works.
+    #[clap(visible_alias = "scs")]
+    #[clap(after_help = examples::after_help("self-check-service"))]
+    SelfCheckService,
+
+    /// Print what is needed to use this program without running it as root, such as a udev rule
+    /// or a sudoers line, depending on how the hardware is accessed.
+    #[clap(visible_alias = "perms")]
+    #[clap(after_help = examples::after_help("permissions"))]
+    Permissions {
+        /// Install the printed rules and group membership instead of only printing them. Needs
+        /// to be run as root.
+        #[clap(short = 'I', long)]
+        install: bool,
+    },
+
+    /// Print the completion script for a shell. Profile names are completed for bash, zsh,
+    /// and fish.
+    Completions {
+        /// The shell to print the completion script for.
+        #[clap(arg_enum)]
+        shell: Shell,
+    },
+
+    /// Print the name of every profile, one per line. Used by the completion scripts.
+    #[clap(name = "__list-profile-names", setting = AppSettings::Hidden)]
+    ListProfileNames,
+
+    /// Run the commands which don't access the hardware against a temporary configuration,
+    /// printing which of them passed. Only built with the `testing` feature.
+    #[cfg(feature = "testing")]
+    #[clap(setting = AppSettings::Hidden)]
+    Selftest,
+
+    /// Inspect the templates which replace the human output of commands, from `[templates]` in
+    /// the config.
+    #[clap(subcommand)]
+    Templates(TuxVantageTemplates),
+
+    /// Print examples of how to use this program.
+    #[clap(visible_alias = "ex")]
+    Examples {
+        /// The command to print the examples of, such as `battery conservation regulate`. If not
+        /// given, every example will be printed.
+        command: Vec<String>,
+    },
+}
+
+/// What an action needs before it can run.
+#[derive(Debug, Copy, Clone)]
+pub struct Capabilities {
+    /// Talking to the hardware, which needs ideapad to be initialized with a profile.
+    pub hardware: bool,
+
+    /// Reading the level of a battery.
+    pub battery: bool,
+
+    /// Writing to the configuration, which read-only mode forbids.
+    pub config_write: bool,
+
+    /// The values of `tuxvantage.toml`. Actions which don't need them run with the defaults if it
+    /// is broken.
+    pub config: bool,
+}
+
+impl Capabilities {
+    const NONE: Self = Self {
+        hardware: false,
+        battery: false,
+        config_write: false,
+        config: false,
+    };
+    const CONFIG: Self = Self {
+        config: true,
+        ..Self::NONE
+    };
+    const HARDWARE: Self = Self {
+        hardware: true,
+        ..Self::CONFIG
+    };
+    const CONFIG_WRITE: Self = Self {
+        config_write: true,
+        ..Self::CONFIG
+    };
+}
+
+impl TuxVantageAction {
+    /// What this action needs before it can run. Every action has an entry here, so that only
+    /// the setup it needs is done and anything missing can be reported before it runs.
+    pub fn capabilities(&self) -> Capabilities {
+        use TuxVantageBatteryConservation as Bc;
+        use TuxVantageProfiles as P;
+        use TuxVantageRapidCharge as Rc;
+        use TuxVantageSystemPerformance as Sp;
+
+        match self {
+            Self::BatteryConservation(Bc::Enabled { .. } | Bc::Disabled { .. }) => {
+                Capabilities::HARDWARE
+            }
+            Self::BatteryConservation(Bc::Enable {
+                explain, remember, ..
+            }) => Capabilities {
+                hardware: !explain,
+                config_write: *remember && !explain,
+                ..Capabilities::CONFIG
+            },
+            Self::BatteryConservation(Bc::Disable { remember, .. }) => Capabilities {
+                config_write: *remember,
+                ..Capabilities::HARDWARE
+            },
+            #[cfg(feature = "regulate")]
+            Self::BatteryConservation(Bc::Regulate {
+                simulate: Some(_), ..
+            }) => Capabilities::CONFIG,
+            #[cfg(feature = "regulate")]
+            Self::BatteryConservation(Bc::Regulate { status: true, .. })
+            | Self::BatteryConservation(Bc::Regulate { stop: true, .. }) => Capabilities::NONE,
+            #[cfg(feature = "regulate")]
+            Self::BatteryConservation(Bc::Regulate {
+                reinstall: true, ..
+            })
+            | Self::BatteryConservation(Bc::Regulate {
+                uninstall: true, ..
+            }) => Capabilities::CONFIG_WRITE,
+            #[cfg(feature = "regulate")]
+            Self::BatteryConservation(Bc::Regulate { install: true, .. })
+            | Self::BatteryConservation(Bc::Hold(TuxVantageHold { install: true, .. })) => {
+                Capabilities {
+                    config_write: true,
+                    ..Capabilities::HARDWARE
+                }
+            }
+            #[cfg(feature = "regulate")]
+            Self::BatteryConservation(Bc::Regulate { infallible, .. })
+            | Self::BatteryConservation(Bc::Hold(TuxVantageHold { infallible, .. })) => {
+                Capabilities {
+                    battery: !infallible,
+                    ..Capabilities::HARDWARE
+                }
+            }
+            Self::Battery(TuxVantageBattery::Threshold(_)) => Capabilities::HARDWARE,
+            Self::Battery(_) => unreachable!("`battery` is normalized as soon as it is parsed"),
+            Self::SystemPerformance(Sp::Get { .. }) => Capabilities::HARDWARE,
+            Self::SystemPerformance(Sp::Set { remember, .. }) => Capabilities {
+                config_write: *remember,
+                ..Capabilities::HARDWARE
+            },
+            Self::RapidCharge(Rc::Enabled { .. } | Rc::Disabled { .. }) => Capabilities::HARDWARE,
+            Self::RapidCharge(Rc::Enable {
+                explain, remember, ..
+            }) => Capabilities {
+                hardware: !explain,
+                config_write: *remember && !explain,
+                ..Capabilities::CONFIG
+            },
+            Self::RapidCharge(Rc::Disable { remember, .. }) => Capabilities {
+                config_write: *remember,
+                ..Capabilities::HARDWARE
+            },
+            #[cfg(feature = "regulate")]
+            Self::RapidCharge(Rc::TopUp { .. }) => Capabilities {
+                battery: true,
+                ..Capabilities::HARDWARE
+            },
+            Self::Profiles(P::Set { dry_run, .. }) => Capabilities {
+                config_write: !dry_run,
+                config: !dry_run,
+                ..Capabilities::NONE
+            },
+            Self::Profiles(P::SetDefault { .. } | P::Remove { .. } | P::Materialize { .. }) => {
+                Capabilities::CONFIG_WRITE
+            }
+            Self::Profiles(P::Contribute { .. }) => Capabilities::HARDWARE,
+            Self::Profiles(P::GetDefault) => Capabilities::CONFIG,
+            Self::Profiles(
+                P::Get { .. } | P::Validate { .. } | P::Json { .. } | P::Schema { .. },
+            ) => Capabilities::NONE,
+            Self::Config(TuxVantageConfig::Check) => Capabilities::NONE,
+            Self::Config(TuxVantageConfig::Explain) => Capabilities::CONFIG,
+            Self::Config(TuxVantageConfig::SetHandler { .. }) => Capabilities::CONFIG_WRITE,
+            Self::Consistency(TuxVantageConsistency::Show) => Capabilities::NONE,
+            Self::Consistency(TuxVantageConsistency::Reset) => Capabilities::CONFIG_WRITE,
+            Self::Doctor { .. } => Capabilities::NONE,
+            Self::Apply { .. } | Self::With { .. } | Self::Status { .. } => Capabilities::HARDWARE,
+            Self::History { .. }
+            | Self::Paths { .. }
+            | Self::SelfCheckService
+            | Self::Permissions { .. }
+            | Self::Completions { .. }
+            | Self::ListProfileNames
+            | Self::Templates(_)
+            | Self::Examples { .. } => Capabilities::NONE,
+            #[cfg(feature = "testing")]
+            Self::Selftest => Capabilities::NONE,
+        }
+    }
+
+    /// Whether only the JSON of the result should be printed, which read-only actions allow with
+    /// `--json`.
+    pub fn json(&self) -> bool {
+        matches!(
+            self,
+            Self::BatteryConservation(
+                TuxVantageBatteryConservation::Enabled { json: true }
+                    | TuxVantageBatteryConservation::Disabled { json: true }
+            ) | Self::RapidCharge(
+                TuxVantageRapidCharge::Enabled { json: true }
+                    | TuxVantageRapidCharge::Disabled { json: true }
+            ) | Self::SystemPerformance(TuxVantageSystemPerformance::Get { json: true, .. })
+                | Self::Profiles(TuxVantageProfiles::Get { json: true, .. })
+                | Self::Battery(TuxVantageBattery::Threshold(
+                    TuxVantageBatteryThreshold::Get { json: true }
+                ))
+        )
+    }
+
+    /// Replaces the subcommands under `battery` with the hidden top level ones they stand for, so
+    /// that both spellings run the same action. `battery threshold` has no other spelling, so it
+    /// is left as is.
+    pub fn normalize(self) -> Self {
+        match self {
+            Self::Battery(TuxVantageBattery::Conservation(battery_conservation)) => {
+                Self::BatteryConservation(battery_conservation)
+            }
+            Self::Battery(TuxVantageBattery::RapidCharge(rapid_charge)) => {
+                Self::RapidCharge(rapid_charge)
+            }
+            #[cfg(feature = "regulate")]
+            Self::Battery(TuxVantageBattery::Hold(hold)) => {
+                Self::BatteryConservation(TuxVantageBatteryConservation::Hold(hold))
+            }
+            action => action,
+        }
+    }
+
+    /// Why the arguments of this action contradict each other in a way clap can't express, if
+    /// they do.
+    pub fn conflict(&self) -> Option<String> {
+        match self {
+            #[cfg(feature = "regulate")]
+            Self::BatteryConservation(TuxVantageBatteryConservation::Regulate {
+                threshold,
+                disable_at: Some(disable_at),
+                ..
+            }) if disable_at >= threshold => Some(format!(
+                "--disable-at ({}) must be lower than --threshold ({})",
+                disable_at, threshold
+            )),
+            _ => None,
+        }
+    }
+
+    /// The profile this action needs ideapad to be initialized with instead of the default one.
+    pub fn profile(&self) -> Option<&str> {
+        match self {
+            Self::Profiles(TuxVantageProfiles::Contribute { name, .. }) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Whether this action forks into the background, which only the forking thread survives.
+    pub fn daemonizes(&self) -> bool {
+        match self {
+            #[cfg(feature = "regulate")]
+            Self::BatteryConservation(TuxVantageBatteryConservation::Regulate {
+                daemonize,
+                ..
+            }) => *daemonize,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+pub enum TuxVantageBattery {
+    /// Manage battery conservation mode.
+    #[clap(subcommand)]
+    Conservation(TuxVantageBatteryConservation),
+
+    /// Manage rapid charging.
+    #[clap(subcommand)]
+    RapidCharge(TuxVantageRapidCharge),
+
+    /// Manage the native charge thresholds of models which expose them in sysfs, which the
+    /// firmware enforces by itself instead of this program toggling battery conservation mode.
+    #[clap(subcommand)]
+    Threshold(TuxVantageBatteryThreshold),
+
+    /// Hold the battery at a charge level by toggling battery conservation mode, emulating a
+    /// charge limit.
+    #[cfg(feature = "regulate")]
+    #[clap(visible_alias = "h")]
+    #[clap(after_help = examples::after_help("battery hold"))]
+    Hold(TuxVantageHold),
+}
+
+#[derive(Debug, Parser)]
+pub enum TuxVantageTemplates {
+    /// List the placeholders a template can use, which are the fields of the machine output of
+    /// the command.
+    #[clap(after_help = examples::after_help("templates fields"))]
+    Fields {
+        /// The command to list the placeholders of, such as `status`. If not given, every
+        /// command which can be given a template is listed.
+        command: Vec<String>,
+    },
+}
+
+#[derive(Debug, Parser)]
+#[clap(visible_alias = "t")]
+pub enum TuxVantageBatteryThreshold {
+    /// Get the native charge thresholds, and what limits the charge of the battery.
+    #[clap(visible_alias = "g")]
+    #[clap(after_help = examples::after_help("battery threshold get"))]
+    Get {
+        /// Print the result as JSON to standard output, without the envelope of `--machine`.
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Set the native charge thresholds. Needs to be run as root, or with the permissions from
+    /// `tuxvantage permissions --install`.
+    #[clap(visible_alias = "s")]
+    #[clap(after_help = examples::after_help("battery threshold set"))]
+    Set {
+        /// The battery level below which charging starts again, from 1% to 99%.
+        #[clap(short, long)]
+        start: BatteryLevel,
+
+        /// The battery level at which charging stops, which must be above `--start`.
+        #[clap(short, long)]
+        end: BatteryLevel,
+    },
 }
 
 #[derive(Debug, Parser)]
@@ -103,49 +738,313 @@ pub enum TuxVantageAction {
 pub enum TuxVantageBatteryConservation {
     /// Check if battery conservation mode is enabled.
     #[clap(visible_aliases = &["ie", "g"])]
-    Enabled,
+    #[clap(after_help = examples::after_help("battery conservation enabled"))]
+    Enabled {
+        /// Print the result as JSON to standard output, without the envelope of `--machine`.
+        #[clap(long)]
+        json: bool,
+    },
 
     /// Check if battery conservation mode is disabled.
     #[clap(visible_alias = "id")]
-    Disabled,
+    Disabled {
+        /// Print the result as JSON to standard output, without the envelope of `--machine`.
+        #[clap(long)]
+        json: bool,
+    },
 
     /// Enable battery conservation mode.
     #[clap(visible_alias = "e")]
+    #[clap(after_help = examples::after_help("battery conservation enable"))]
     Enable {
-        /// What to do if rapid charging is enabled. If not specified, the default would be
-        /// chosen from the config. If there is no default specified there, the default would
-        /// be `switch`.
+        /// What to do if rapid charging is enabled. Can also be given with `--handler`. If not
+        /// specified, the global `--handler` option would be used, then the handler for battery
+        /// conservation from the config, then the default from the config. If there is no default
+        /// specified there either, the default would be `switch`. See `--explain`.
+        #[clap(possible_values = possible_values(FromStrHandler::NAMES))]
         handler: Option<FromStrHandler>,
+
+        /// Same as the positional handler, which is kept for compatibility.
+        #[clap(
+            short = 'H',
+            long = "handler",
+            possible_values = possible_values(FromStrHandler::NAMES)
+        )]
+        handler_flag: Option<FromStrHandler>,
+
+        /// Print where the handler is resolved from instead of enabling anything.
+        #[clap(long)]
+        explain: bool,
+
+        /// Remember this as the desired state for `tuxvantage apply`.
+        #[clap(short, long)]
+        remember: bool,
+
+        /// Don't warn if the battery conservation regulator is running, which may revert this
+        /// change.
+        #[clap(short, long)]
+        force: bool,
+
+        /// After changing the setting, wait this long for the firmware to settle, such as `1s`,
+        /// then read it back and warn if it didn't stick. Not done by default.
+        #[clap(long, value_name = "DURATION")]
+        settle: Option<HumanDuration>,
+
+        /// Fail instead of warning if the setting didn't stick after `--settle`.
+        #[clap(long, requires = "settle")]
+        strict: bool,
     },
 
     /// Disable battery conservation mode.
     #[clap(visible_alias = "d")]
-    Disable,
+    #[clap(after_help = examples::after_help("battery conservation disable"))]
+    Disable {
+        /// Remember this as the desired state for `tuxvantage apply`.
+        #[clap(short, long)]
+        remember: bool,
+
+        /// Switch rapid charging back on if it was switched off by the `switch-back` handler.
+        #[clap(long)]
+        restore: bool,
+
+        /// Don't warn if the battery conservation regulator is running, which may revert this
+        /// change.
+        #[clap(short, long)]
+        force: bool,
+
+        /// After changing the setting, wait this long for the firmware to settle, such as `1s`,
+        /// then read it back and warn if it didn't stick. Not done by default.
+        #[clap(long, value_name = "DURATION")]
+        settle: Option<HumanDuration>,
+
+        /// Fail instead of warning if the setting didn't stick after `--settle`.
+        #[clap(long, requires = "settle")]
+        strict: bool,
+    },
 
     /// Regulate the battery using battery conservation mode.
+    #[cfg(feature = "regulate")]
     #[clap(visible_alias = "r")]
+    #[clap(after_help = examples::after_help("battery conservation regulate"))]
     Regulate {
         /// The target battery level in which battery conservation mode will be enabled.
-        #[clap(short, long, default_value_t)]
+        #[clap(short, long, visible_alias = "enable-at", default_value_t)]
         threshold: BatteryLevel,
 
-        /// How long to wait to check the battery level again.
-        #[clap(short, long, default_value_t)]
-        cooldown: CoolDown,
+        /// The battery level at or below which battery conservation mode will be disabled again.
+        /// Must be lower than `--threshold`. Overrides the config file, and without either,
+        /// battery conservation mode is disabled as soon as the battery drops below
+        /// `--threshold`.
+        #[clap(long, visible_alias = "lower-threshold")]
+        disable_at: Option<BatteryLevel>,
+
+        /// How long to wait to check the battery level again, such as `90`, `2m` or `1h30m`.
+        /// Durations without a unit are in seconds, here and in the config file.
+        #[clap(short, long, default_value_t = BatteryConfig::DEFAULT_COOLDOWN)]
+        cooldown: HumanDuration,
+
+        /// Randomly deviate each cooldown by up to this long in either direction, so that many
+        /// machines waking up at once don't check at the same time. Overrides the config file.
+        #[clap(long)]
+        cooldown_jitter: Option<HumanDuration>,
+
+        /// The minimum time between toggles of battery conservation mode, no matter how short
+        /// the cooldown is. Overrides the config file, and defaults to 30 seconds.
+        #[clap(long)]
+        min_toggle_interval: Option<HumanDuration>,
+
+        /// Wait for half as long as the battery is estimated to take to reach the threshold at
+        /// its current charge or discharge rate, if that is shorter than the cooldown. This
+        /// checks less often when the battery level barely moves and more often under load.
+        #[clap(long)]
+        adaptive_cooldown: bool,
+
+        /// The shortest an adaptive cooldown may be. Overrides the config file, and defaults to
+        /// 10 seconds.
+        #[clap(long, requires = "adaptive-cooldown")]
+        adaptive_cooldown_floor: Option<HumanDuration>,
+
+        /// The longest an adaptive cooldown may be. Overrides the config file, and defaults to
+        /// 1 hour.
+        #[clap(long, requires = "adaptive-cooldown")]
+        adaptive_cooldown_ceiling: Option<HumanDuration>,
 
         /// Do not error if an error occurred while enumerating a battery. Instead, display a
         /// warning.
         #[clap(short, long)]
         infallible: bool,
 
-        /// How to find the desired battery.
+        /// Only enable battery conservation mode while plugged into AC. On battery power it is
+        /// left as is until AC is plugged back in, as it only matters while charging.
+        #[clap(long)]
+        only_on_ac: bool,
+
+        /// Send a desktop notification whenever battery conservation mode is toggled. Needs
+        /// `notify-send`, and the regulator to run in the session of the user to notify.
+        #[clap(long)]
+        notify: bool,
+
+        /// What to do with battery conservation mode when the regulator is told to exit, one of
+        /// `keep`, `enable`, `disable`, or `restore` to put it back to how it was when the
+        /// regulator started. Overrides the config file, and defaults to `enable`.
+        #[clap(long)]
+        on_exit: Option<OnExit>,
+
+        /// Evaluate the batteries as soon as their level or state changes instead of only after
+        /// each cooldown, going by the changes UPower reports over D-Bus. The cooldown still
+        /// applies when nothing changes, and if UPower isn't available. Overrides the config file.
+        #[clap(long, value_name = "SOURCE", possible_values = possible_values(Listen::NAMES))]
+        listen: Option<Listen>,
+
+        /// How to find the desired battery, in the format "[variant]=[value]". The variant is one
+        /// of `first`, `all`, `index`, `vendor`, `model`, `serial_number`, or `state`, which is
+        /// one of `charging`, `discharging`, or `full`. `first` skips batteries without a design
+        /// capacity, such as docks and power banks. `all` regulates every battery together, going
+        /// by the level `--aggregate` combines them into. `first` and `all` may leave out the `=`.
         #[clap(short, long)]
         matches: Option<BatteryMatches>,
 
+        /// How to combine the levels of the batteries with `--matches all`, one of `min`, `max`,
+        /// or `avg`. Overrides the config file, and defaults to `avg`.
+        #[clap(long, possible_values = possible_values(Aggregate::NAMES))]
+        aggregate: Option<Aggregate>,
+
         /// Install the battery regulation service. Assumes you're using SystemD.
         #[clap(short = 'I', long)]
         install: bool,
+
+        /// The init system to install the service for, one of `systemd`, `openrc` or `runit`.
+        /// Detected from the running system if left out.
+        #[clap(long, requires = "install")]
+        init_system: Option<InitSystem>,
+
+        /// The name to install the service as instead of `bcm.service`, such as
+        /// `tuxvantage-regulate`. The `.service` suffix is added if it is left out.
+        #[clap(long, requires = "install")]
+        service_name: Option<ServiceName>,
+
+        /// The directory to install the service into instead of `/etc/systemd/system`,
+        /// `/etc/init.d` or `/etc/sv`, such as `/usr/lib/systemd/system` when packaging.
+        #[clap(long, requires = "install", value_name = "DIR")]
+        unit_dir: Option<PathBuf>,
+
+        /// Regulate even if another regulator is already running.
+        #[clap(short, long)]
+        force: bool,
+
+        /// Show whether the regulator service is installed, active and enabled, what the regulator
+        /// running in the background has done so far, and when it last toggled battery
+        /// conservation mode, instead of regulating. What it has done so far is only shown when
+        /// run as the same user as the regulator.
+        #[clap(short = 'S', long)]
+        status: bool,
+
+        /// Detach from the terminal and regulate in the background, for systems without systemd.
+        /// Requires a log file, and can't be used with `--install`.
+        #[clap(short = 'D', long)]
+        daemonize: bool,
+
+        /// Also write the logs of the regulator to this file, or only to it when daemonized. It
+        /// is rotated once it grows too large. Overrides the config file.
+        #[clap(long)]
+        log_file: Option<PathBuf>,
+
+        /// How large the log file may grow, in megabytes, before it is moved to `<file>.1` and a
+        /// new one is started. 0 never rotates it. Overrides the config file, and defaults to 10.
+        #[clap(long, value_name = "MEGABYTES")]
+        log_file_size: Option<u64>,
+
+        /// How many rotated log files to keep. Overrides the config file, and defaults to 5.
+        #[clap(long, value_name = "COUNT")]
+        log_file_count: Option<u32>,
+
+        /// Stop the regulator started with `--daemonize` instead of regulating.
+        #[clap(long)]
+        stop: bool,
+
+        /// Regenerate the installed services from the current version of tuxvantage, keeping
+        /// the arguments they were installed with.
+        #[clap(long)]
+        reinstall: bool,
+
+        /// Stop, disable and remove the regulator service installed with `--install`. Needs to be
+        /// run as root.
+        #[clap(long, conflicts_with_all = &["install", "reinstall", "stop", "status"])]
+        uninstall: bool,
+
+        /// Print what the regulator would have done against a recorded series of battery levels
+        /// instead of regulating, without touching the hardware or waiting for the cooldown. The
+        /// file has `timestamp,level,charging` lines, or is a JSON array of objects with those
+        /// fields if it ends with `.json`. Timestamps are in seconds.
+        #[clap(long, value_name = "FILE")]
+        simulate: Option<PathBuf>,
+
+        /// If the model exposes native charge thresholds, set them once instead of regulating,
+        /// so that the firmware stops charging at the threshold by itself. Falls back to
+        /// regulating if it doesn't.
+        #[clap(long, conflicts_with_all = &["install", "daemonize", "simulate"])]
+        prefer_native_thresholds: bool,
     },
+
+    /// Hold the battery at a charge level by toggling battery conservation mode, emulating a
+    /// charge limit.
+    #[cfg(feature = "regulate")]
+    #[clap(visible_alias = "h")]
+    #[clap(after_help = examples::after_help("battery hold"))]
+    Hold(TuxVantageHold),
+}
+
+/// The arguments of holding the battery at a charge level, which is both `battery hold` and
+/// `battery conservation hold`.
+#[cfg(feature = "regulate")]
+#[derive(Debug, Args)]
+pub struct TuxVantageHold {
+    /// The battery level to hold the battery at.
+    #[clap(short, long)]
+    pub at: BatteryLevel,
+
+    /// How many percent the battery level may drop below `--at` before battery conservation
+    /// mode is disabled again.
+    #[clap(short, long, default_value_t = DEFAULT_DEADBAND)]
+    pub deadband: u8,
+
+    /// How long to wait to check the battery level again, in the same format as for
+    /// `regulate`.
+    #[clap(short, long, default_value = "30s")]
+    pub cooldown: HumanDuration,
+
+    /// Randomly deviate each cooldown by up to this long in either direction. Overrides the
+    /// config file.
+    #[clap(long)]
+    pub cooldown_jitter: Option<HumanDuration>,
+
+    /// The minimum time between toggles of battery conservation mode, no matter how short
+    /// the cooldown is. Overrides the config file, and defaults to 30 seconds.
+    #[clap(long)]
+    pub min_toggle_interval: Option<HumanDuration>,
+
+    /// Do not error if an error occurred while enumerating a battery. Instead, display a
+    /// warning.
+    #[clap(short, long)]
+    pub infallible: bool,
+
+    /// How to find the desired battery, in the same format as for `regulate`.
+    #[clap(short, long)]
+    pub matches: Option<BatteryMatches>,
+
+    /// Install the hold service, which is separate from the regulator service. Assumes you're
+    /// using SystemD.
+    #[clap(short = 'I', long)]
+    pub install: bool,
+
+    /// Hold even if another regulator is already running.
+    #[clap(short, long)]
+    pub force: bool,
+
+    /// If the model exposes native charge thresholds, set them once instead of holding, so that
+    /// the firmware stops charging at `--at` by itself. Falls back to holding if it doesn't.
+    #[clap(long, conflicts_with = "install")]
+    pub prefer_native_thresholds: bool,
 }
 
 #[derive(Debug, Parser)]
@@ -153,13 +1052,38 @@ pub enum TuxVantageBatteryConservation {
 pub enum TuxVantageSystemPerformance {
     /// Get the current system performance mode.
     #[clap(visible_alias = "g")]
-    Get,
+    #[clap(after_help = examples::after_help("system-performance get"))]
+    Get {
+        /// Also show the raw FCMO and SPMO bits read from the firmware, and which entry of the
+        /// profile's bit table they match. These are always included in machine output.
+        #[clap(long)]
+        raw: bool,
+
+        /// Print the result as JSON to standard output, without the envelope of `--machine`.
+        #[clap(long)]
+        json: bool,
+    },
 
     /// Set the system performance mode.
     #[clap(visible_alias = "s")]
+    #[clap(after_help = examples::after_help("system-performance set"))]
     Set {
         /// The system performance mode to set.
+        #[clap(possible_values = possible_values(FromStrSystemPerformanceMode::NAMES))]
         mode: FromStrSystemPerformanceMode,
+
+        /// Remember this as the desired state for `tuxvantage apply`.
+        #[clap(short, long)]
+        remember: bool,
+
+        /// After changing the setting, wait this long for the firmware to settle, such as `1s`,
+        /// then read it back and warn if it didn't stick. Not done by default.
+        #[clap(long, value_name = "DURATION")]
+        settle: Option<HumanDuration>,
+
+        /// Fail instead of warning if the setting didn't stick after `--settle`.
+        #[clap(long, requires = "settle")]
+        strict: bool,
     },
 }
 
@@ -168,24 +1092,158 @@ pub enum TuxVantageSystemPerformance {
 pub enum TuxVantageRapidCharge {
     /// Check if rapid charging is enabled.
     #[clap(visible_aliases = &["ie", "g"])]
-    Enabled,
+    Enabled {
+        /// Print the result as JSON to standard output, without the envelope of `--machine`.
+        #[clap(long)]
+        json: bool,
+    },
 
     /// Check if rapid charging is disabled.
     #[clap(visible_alias = "id")]
-    Disabled,
+    Disabled {
+        /// Print the result as JSON to standard output, without the envelope of `--machine`.
+        #[clap(long)]
+        json: bool,
+    },
 
     /// Enable rapid charging.
     #[clap(visible_alias = "e")]
+    #[clap(after_help = examples::after_help("battery rapid-charge enable"))]
     Enable {
-        /// What to do if battery conservation is enabled. If not specified, the default would
-        /// be chosen from the config. If there is no default specified there, the default would
-        /// be `switch`.
+        /// What to do if battery conservation is enabled. Can also be given with `--handler`. If
+        /// not specified, the global `--handler` option would be used, then the handler for rapid
+        /// charging from the config, then the default from the config. If there is no default
+        /// specified there either, the default would be `switch`. See `--explain`.
+        #[clap(possible_values = possible_values(FromStrHandler::NAMES))]
         handler: Option<FromStrHandler>,
+
+        /// Same as the positional handler, which is kept for compatibility.
+        #[clap(
+            short = 'H',
+            long = "handler",
+            possible_values = possible_values(FromStrHandler::NAMES)
+        )]
+        handler_flag: Option<FromStrHandler>,
+
+        /// Print where the handler is resolved from instead of enabling anything.
+        #[clap(long)]
+        explain: bool,
+
+        /// Remember this as the desired state for `tuxvantage apply`.
+        #[clap(short, long)]
+        remember: bool,
+
+        /// Don't warn if the battery conservation regulator is running, which may revert this
+        /// change.
+        #[clap(short, long)]
+        force: bool,
+
+        /// After changing the setting, wait this long for the firmware to settle, such as `1s`,
+        /// then read it back and warn if it didn't stick. Not done by default.
+        #[clap(long, value_name = "DURATION")]
+        settle: Option<HumanDuration>,
+
+        /// Fail instead of warning if the setting didn't stick after `--settle`.
+        #[clap(long, requires = "settle")]
+        strict: bool,
     },
 
     /// Disable rapid charging.
     #[clap(visible_alias = "d")]
-    Disable,
+    #[clap(after_help = examples::after_help("battery rapid-charge disable"))]
+    Disable {
+        /// Remember this as the desired state for `tuxvantage apply`.
+        #[clap(short, long)]
+        remember: bool,
+
+        /// Switch battery conservation back on if it was switched off by the `switch-back`
+        /// handler.
+        #[clap(long)]
+        restore: bool,
+
+        /// After changing the setting, wait this long for the firmware to settle, such as `1s`,
+        /// then read it back and warn if it didn't stick. Not done by default.
+        #[clap(long, value_name = "DURATION")]
+        settle: Option<HumanDuration>,
+
+        /// Fail instead of warning if the setting didn't stick after `--settle`.
+        #[clap(long, requires = "settle")]
+        strict: bool,
+    },
+
+    /// Charge the battery to a level by a deadline, enabling rapid charging only while charging
+    /// normally is estimated to fall short. Rapid charging is disabled again once the level or
+    /// the deadline is reached. In machine mode, every estimate is printed as a line of JSON as
+    /// it is made, followed by the usual result.
+    #[cfg(feature = "regulate")]
+    #[clap(visible_alias = "t")]
+    #[clap(after_help = examples::after_help("battery rapid-charge top-up"))]
+    TopUp {
+        /// The battery level to reach.
+        #[clap(long)]
+        to: BatteryLevel,
+
+        /// When to reach it by, either a time of day in local time such as `14:30`, or a duration
+        /// from now such as `45m` or `1h30m`. A time of day which already passed is tomorrow.
+        #[clap(long)]
+        by: Deadline,
+
+        /// How long to wait before estimating again, such as `90`, `2m` or `1h30m`.
+        #[clap(short, long, default_value_t = DEFAULT_TOP_UP_INTERVAL)]
+        interval: HumanDuration,
+
+        /// How to find the battery to charge, in the same format as `battery-conservation
+        /// regulate --matches`. Defaults to the battery of the config.
+        #[clap(short, long)]
+        matches: Option<BatteryMatches>,
+    },
+}
+
+#[derive(Debug, Parser)]
+#[clap(visible_alias = "c")]
+pub enum TuxVantageConfig {
+    /// Check the configuration and profiles for problems, without using them. Exits with an
+    /// error if any error-level problem was found.
+    #[clap(visible_alias = "ch")]
+    #[clap(after_help = examples::after_help("config check"))]
+    Check,
+
+    /// Walk through how the configuration, the overrides and the profile are resolved when
+    /// starting up, showing what each decision was based on. The hardware isn't accessed.
+    #[clap(visible_alias = "e")]
+    #[clap(after_help = examples::after_help("config explain"))]
+    Explain,
+
+    /// Set a handler in the configuration, then show how the handler of every mode is resolved.
+    /// The `switch-back` handler can't be set, since it is only for the enable subcommands.
+    #[clap(visible_alias = "sh")]
+    #[clap(after_help = examples::after_help("config set-handler"))]
+    SetHandler {
+        /// The handler to set. If not given, the default handler of every mode is set.
+        #[clap(
+            long = "for",
+            value_name = "TARGET",
+            possible_values = possible_values(HandlerTarget::NAMES)
+        )]
+        target: Option<HandlerTarget>,
+
+        /// The handler to use.
+        #[clap(possible_values = possible_values(FromStrHandler::NAMES))]
+        handler: FromStrHandler,
+    },
+}
+
+#[derive(Debug, Parser)]
+pub enum TuxVantageConsistency {
+    /// Show what `.consistency.json` contains.
+    #[clap(visible_alias = "s")]
+    #[clap(after_help = examples::after_help("consistency show"))]
+    Show,
+
+    /// Reset `.consistency.json` to the defaults, such as after uninstalling the services by
+    /// hand.
+    #[clap(after_help = examples::after_help("consistency reset"))]
+    Reset,
 }
 
 #[derive(Debug, Parser)]
@@ -193,9 +1251,26 @@ pub enum TuxVantageRapidCharge {
 pub enum TuxVantageProfiles {
     /// Get a profile.
     #[clap(visible_alias = "g")]
+    #[clap(after_help = examples::after_help("profiles get"))]
     Get {
         /// The profile to get. If not given, all profiles will be listed.
         name: Option<String>,
+
+        /// Only show the name, origin and expected product names of each profile.
+        #[clap(long)]
+        brief: bool,
+
+        /// Print the result as JSON to standard output, without the envelope of `--machine`.
+        #[clap(long)]
+        json: bool,
+
+        /// Write the result as JSON to this file instead, printing only where it was written.
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+
+        /// Overwrite the file given to `--output` if it already exists.
+        #[clap(short, long)]
+        force: bool,
     },
 
     /// Get the default profile from the config file. If there is no default specified there,
@@ -205,6 +1280,7 @@ pub enum TuxVantageProfiles {
 
     /// Set a profile.
     #[clap(visible_alias = "s")]
+    #[clap(after_help = examples::after_help("profiles set"))]
     Set {
         /// The name of the profile to set.
         name: String,
@@ -216,13 +1292,50 @@ pub enum TuxVantageProfiles {
         /// Create a new profile if the given name doesn't exist.
         #[clap(short, long)]
         create_new: bool,
+
+        /// Only check that the contents are a valid profile, without writing anything.
+        #[clap(short, long)]
+        dry_run: bool,
+
+        /// Ask before overwriting an existing profile.
+        #[clap(long)]
+        confirm: bool,
+
+        /// Don't preview the changes made to an existing profile.
+        #[clap(short, long)]
+        quiet: bool,
+    },
+
+    /// Check that the contents of a profile are valid without setting it.
+    #[clap(visible_alias = "v")]
+    #[clap(after_help = examples::after_help("profiles validate"))]
+    Validate {
+        /// The path to the contents of the profile in JSON. If this is not given, standard input
+        /// will be used.
+        contents: Option<String>,
+
+        /// Also check the profile against the schema from `profiles schema`, which points at the
+        /// field each problem is in.
+        #[clap(short, long)]
+        schema: bool,
+
+        /// Validate every profile in the profiles directory instead, continuing past the ones
+        /// which are invalid.
+        #[clap(short, long)]
+        all: bool,
     },
 
     /// Set the default profile.
     #[clap(visible_alias = "sd")]
+    #[clap(after_help = examples::after_help("profiles set-default"))]
     SetDefault {
         /// The name of the profile to set as the default.
         name: String,
+
+        /// Set the profile as the default even if it doesn't expect the product name of this
+        /// machine.
+        #[clap(short, long)]
+        force: bool,
     },
 
     /// Delete a profile.
@@ -233,11 +1346,45 @@ pub enum TuxVantageProfiles {
         name: String,
     },
 
+    /// Package an external profile with information about this machine into a JSON report
+    /// that can be pasted into an issue, so that the profile can be included upstream. Serial
+    /// numbers and hostnames are redacted, and nothing is sent anywhere.
+    #[clap(visible_alias = "c")]
+    #[clap(after_help = examples::after_help("profiles contribute"))]
+    Contribute {
+        /// The name of the profile to contribute.
+        name: String,
+
+        /// Write the report to this file instead of printing it.
+        #[clap(short, long)]
+        out: Option<PathBuf>,
+    },
+
+    /// Write the JSON of a built-in profile into the profiles directory, as a starting point for
+    /// a patched copy of it.
+    #[clap(visible_alias = "m")]
+    #[clap(after_help = examples::after_help("profiles materialize"))]
+    Materialize {
+        /// The name of the built-in profile to copy.
+        built_in: String,
+
+        /// The name of the copy. Defaults to the name of the built-in profile, which needs it to
+        /// be in `profiles.disabled_builtins` since the built-in would hide the copy otherwise.
+        #[clap(short, long)]
+        name: Option<String>,
+    },
+
     /// Get the JSON contents of a profile.
     #[clap(visible_alias = "j")]
+    #[clap(after_help = examples::after_help("profiles json"))]
     Json {
-        /// The name of the profile to get the JSON contents of.
-        name: String,
+        /// The name of the profile to get the JSON contents of. Required unless `--all` is given.
+        name: Option<String>,
+
+        /// Get the JSON contents of every profile in the profiles directory instead, continuing
+        /// past the ones which fail.
+        #[clap(short, long)]
+        all: bool,
 
         /// If reading the JSON contents of a profile fails, generate it instead.
         #[clap(short, long)]
@@ -247,9 +1394,300 @@ pub enum TuxVantageProfiles {
         /// generated.
         #[clap(short, long)]
         pretty: bool,
+
+        /// Write the JSON contents to this file instead, printing only where they were written.
+        /// Can't be used with `--all`.
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+
+        /// Overwrite the file given to `--output` if it already exists.
+        #[clap(short, long)]
+        force: bool,
+    },
+
+    /// Print the JSON schema of profiles, for editors to validate and complete them with.
+    #[clap(after_help = examples::after_help("profiles schema"))]
+    Schema {
+        /// Prettify the JSON schema.
+        #[clap(short, long)]
+        pretty: bool,
     },
 }
 
+/// Finds the value of `--config` by hand, since it's needed to find the default command before
+/// the arguments can be parsed.
+fn config_flag(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+
+    None
+}
+
+/// Parses the arguments, running the default command from the config file if no subcommand was
+/// given.
 pub fn parse() -> TuxVantage {
-    TuxVantage::parse()
+    static AFTER_HELP: OnceCell<String> = OnceCell::new();
+
+    let mut args = env::args_os().collect::<Vec<_>>();
+    let default_command = config::default_command(config_flag(&args));
+    let mut app = TuxVantage::into_app();
+
+    if let Ok(Some(default_command)) = &default_command {
+        let after_help = AFTER_HELP.get_or_init(|| {
+            format!(
+                "DEFAULT COMMAND:\n    Running `tuxvantage` without a subcommand runs `tuxvantage {}`, \
+                 as set by `default_command` in tuxvantage.toml.",
+                default_command
+            )
+        });
+        app = app.after_help(after_help.as_str());
+    }
+
+    // a missing subcommand is only an error once it's known that there's no default command
+    let mut relaxed = app
+        .clone()
+        .unset_setting(AppSettings::SubcommandRequiredElseHelp)
+        .unset_setting(AppSettings::SubcommandRequired)
+        .unset_setting(AppSettings::ArgRequiredElseHelp);
+    let mut matches = relaxed
+        .try_get_matches_from_mut(&args)
+        .unwrap_or_else(|error| error.exit());
+
+    if matches.subcommand().is_none() {
+        let default_command = match default_command {
+            Ok(Some(default_command)) => default_command,
+            Ok(None) => app
+                .try_get_matches_from_mut(&args)
+                .expect_err("parsing should fail without a subcommand")
+                .exit(),
+            Err(error) => {
+                warn!("failed to get the default command: {:#}", error);
+                app.try_get_matches_from_mut(&args)
+                    .expect_err("parsing should fail without a subcommand")
+                    .exit()
+            }
+        };
+
+        debug!(
+            "no subcommand given, run the default command `{}`",
+            default_command
+        );
+        args.extend(default_command.split_whitespace().map(OsString::from));
+        matches = relaxed
+            .try_get_matches_from_mut(&args)
+            .unwrap_or_else(|error| error.exit());
+
+        if matches.subcommand().is_none() {
+            relaxed
+                .error(
+                    ErrorKind::MissingSubcommand,
+                    format!(
+                        "the default command `{}` doesn't contain a subcommand, so it would run \
+                         itself forever",
+                        default_command
+                    ),
+                )
+                .exit()
+        }
+    }
+
+    let mut tuxvantage =
+        TuxVantage::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+    tuxvantage.action = tuxvantage.action.normalize();
+
+    if let Some(conflict) = tuxvantage.action.conflict() {
+        relaxed.error(ErrorKind::ArgumentConflict, conflict).exit()
+    }
+
+    tuxvantage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::iter;
+
+    fn parse(args: &[&str]) -> clap::Result<TuxVantage> {
+        TuxVantage::try_parse_from(iter::once("tuxvantage").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn omitted_panic_and_backtrace_defer_to_the_config() {
+        let tuxvantage = parse(&["status"]).unwrap();
+
+        assert_eq!(tuxvantage.panic_override(), None);
+        assert!(tuxvantage.backtrace.is_none());
+    }
+
+    #[test]
+    fn panic_flags_override_the_config() {
+        assert_eq!(
+            parse(&["--panic", "status"]).unwrap().panic_override(),
+            Some(true)
+        );
+        assert_eq!(
+            parse(&["--no-panic", "status"]).unwrap().panic_override(),
+            Some(false)
+        );
+        assert!(parse(&["--panic", "--no-panic", "status"]).is_err());
+    }
+
+    #[test]
+    fn explicit_backtrace_is_kept_even_when_off() {
+        let backtrace = parse(&["--backtrace", "0,0", "status"])
+            .unwrap()
+            .backtrace
+            .unwrap();
+
+        assert!(!backtrace.panics);
+        assert!(!backtrace.errors);
+    }
+
+    /// The action of `args` once normalized, which is how the commands are dispatched.
+    fn action(args: &[&str]) -> String {
+        let tuxvantage = parse(args).unwrap_or_else(|error| panic!("{:?}: {}", args, error));
+
+        format!("{:?}", tuxvantage.action.normalize())
+    }
+
+    fn assert_same_action(spellings: &[&[&str]]) {
+        let expected = action(spellings[0]);
+
+        for spelling in &spellings[1..] {
+            assert_eq!(action(spelling), expected, "{:?}", spelling);
+        }
+    }
+
+    #[test]
+    fn battery_conservation_spellings_are_the_same_action() {
+        for command in [
+            &["enable"][..],
+            &["disable"],
+            &["enabled"],
+            &["disabled"],
+            &["enable", "--remember"],
+        ] {
+            let spellings = [
+                ["battery-conservation"].as_slice(),
+                &["bc"],
+                &["b"],
+                &["battery", "conservation"],
+                &["battery", "bc"],
+            ]
+            .map(|prefix| [prefix, command].concat());
+
+            assert_same_action(&spellings.iter().map(Vec::as_slice).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn rapid_charge_spellings_are_the_same_action() {
+        for command in [&["enable"][..], &["disable"], &["enabled"], &["disabled"]] {
+            let spellings = [
+                ["rapid-charge"].as_slice(),
+                &["rc"],
+                &["r"],
+                &["battery", "rapid-charge"],
+                &["battery", "rc"],
+            ]
+            .map(|prefix| [prefix, command].concat());
+
+            assert_same_action(&spellings.iter().map(Vec::as_slice).collect::<Vec<_>>());
+        }
+    }
+
+    #[cfg(feature = "regulate")]
+    #[test]
+    fn regulate_spellings_are_the_same_action() {
+        assert_same_action(&[
+            &["battery-conservation", "regulate", "--threshold", "80"],
+            &["bc", "r", "-t", "80"],
+            &["battery", "conservation", "regulate", "--threshold", "80"],
+        ]);
+    }
+
+    #[cfg(feature = "regulate")]
+    #[test]
+    fn hold_spellings_are_the_same_action() {
+        assert_same_action(&[
+            &["battery-conservation", "hold", "--at", "70"],
+            &["bc", "h", "--at", "70"],
+            &["battery", "hold", "--at", "70"],
+            &["battery", "h", "--at", "70"],
+            &["battery", "conservation", "hold", "--at", "70"],
+        ]);
+    }
+
+    #[test]
+    fn battery_threshold_is_left_as_is() {
+        assert!(action(&["battery", "threshold", "get"]).starts_with("Battery(Threshold("));
+    }
+
+    /// Names every subcommand which is gated behind a feature, so that the shape of the
+    /// subcommands is type-checked with and without the features.
+    fn gated_name(action: &TuxVantageAction) -> Option<&'static str> {
+        match action {
+            #[cfg(feature = "regulate")]
+            TuxVantageAction::BatteryConservation(TuxVantageBatteryConservation::Regulate {
+                ..
+            }) => Some("regulate"),
+            #[cfg(feature = "regulate")]
+            TuxVantageAction::BatteryConservation(TuxVantageBatteryConservation::Hold(_)) => {
+                Some("hold")
+            }
+            #[cfg(feature = "regulate")]
+            TuxVantageAction::RapidCharge(TuxVantageRapidCharge::TopUp { .. }) => Some("top-up"),
+            #[cfg(feature = "testing")]
+            TuxVantageAction::Selftest => Some("selftest"),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn gated_subcommands_only_parse_with_their_feature() {
+        let regulate = cfg!(feature = "regulate");
+        let gated: [(&[&str], &str, bool); 5] = [
+            (
+                &["bc", "regulate", "--threshold", "80"],
+                "regulate",
+                regulate,
+            ),
+            (&["bc", "hold", "--at", "70"], "hold", regulate),
+            (&["battery", "hold", "--at", "70"], "hold", regulate),
+            (
+                &["rc", "top-up", "--to", "90", "--by", "1h"],
+                "top-up",
+                regulate,
+            ),
+            (&["selftest"], "selftest", cfg!(feature = "testing")),
+        ];
+
+        for (args, name, enabled) in gated {
+            let parsed = parse(args);
+
+            assert_eq!(parsed.is_ok(), enabled, "{:?}", args);
+
+            if let Ok(tuxvantage) = parsed {
+                assert_eq!(gated_name(&tuxvantage.action.normalize()), Some(name));
+            }
+        }
+    }
+
+    #[test]
+    fn ungated_subcommands_always_parse() {
+        for args in [&["bc", "enable"][..], &["rc", "disabled"], &["sp", "get"]] {
+            let tuxvantage = parse(args).unwrap_or_else(|error| panic!("{:?}: {}", args, error));
+
+            assert_eq!(gated_name(&tuxvantage.action), None);
+        }
+    }
 }
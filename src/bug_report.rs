@@ -0,0 +1,150 @@
+use crate::machine::Machine;
+use crate::{anyhow_with_tip, config, context, log, utils};
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tap::Pipe;
+
+/// The kernel modules whose lines of `/proc/modules` are included, as the hardware is accessed
+/// through them.
+const MODULES: &[&str] = &["acpi_call", "ideapad_laptop"];
+
+/// A file of the bug report bundle.
+struct Entry {
+    file_name: &'static str,
+    description: &'static str,
+    contents: Option<String>,
+}
+
+impl Entry {
+    fn new(file_name: &'static str, description: &'static str, contents: Option<String>) -> Self {
+        Self {
+            file_name,
+            description,
+            contents,
+        }
+    }
+}
+
+fn failure(error: &anyhow_with_tip::Error, sensitive: &[String]) -> Option<String> {
+    let mut value = serde_json::to_value(Machine::<()>::failure_of(error)).ok()?;
+    utils::redact(&mut value, sensitive);
+
+    serde_json::to_string_pretty(&value).ok()
+}
+
+/// The configuration as it was loaded, with serial numbers and hostnames redacted.
+fn config(sensitive: &[String]) -> Option<String> {
+    let config = config::try_read()?;
+    let mut value = match serde_json::to_value(&config.tuxvantage) {
+        Ok(value) => value,
+        Err(error) => {
+            debug!("failed to serialize the config: {}", error);
+            return None;
+        }
+    };
+    drop(config);
+    utils::redact(&mut value, sensitive);
+
+    serde_json::to_string_pretty(&value).ok()
+}
+
+/// The profile ideapad was initialized with, if the command got that far.
+fn profile() -> Option<String> {
+    serde_json::to_string_pretty(&context::try_get()?.profile).ok()
+}
+
+fn uname() -> Option<String> {
+    match Command::new("uname").arg("-a").output() {
+        Ok(output) => Some(String::from_utf8_lossy(&output.stdout).into_owned()),
+        Err(error) => {
+            debug!("failed to run `uname -a`: {}", error);
+            None
+        }
+    }
+}
+
+fn modules() -> Option<String> {
+    let modules = match fs::read_to_string("/proc/modules") {
+        Ok(modules) => modules,
+        Err(error) => {
+            debug!("failed to read '/proc/modules': {}", error);
+            return None;
+        }
+    };
+
+    modules
+        .lines()
+        .filter(|line| {
+            line.split_whitespace()
+                .next()
+                .map_or(false, |module| MODULES.contains(&module))
+        })
+        .map(|line| format!("{}\n", line))
+        .collect::<String>()
+        .pipe(Some)
+}
+
+fn log(sensitive: &[String]) -> Option<String> {
+    let mut log = log::tee::lines().join("\n");
+
+    for sensitive in sensitive {
+        log = log.replace(sensitive.as_str(), "[redacted]");
+    }
+
+    Some(log)
+}
+
+/// Writes a bundle describing the failure `error` into the directory `dir`, for the user to
+/// attach to an issue. Nothing is sent anywhere. Returns the files which were written, along
+/// with what they contain.
+pub fn write(
+    dir: &Path,
+    error: &anyhow_with_tip::Error,
+) -> anyhow::Result<Vec<(&'static str, &'static str)>> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create directory {}", dir.display().bold()))?;
+
+    let sensitive = utils::sensitive_strings();
+    let entries = [
+        Entry::new(
+            "failure.json",
+            "the failure as printed by `--machine always`, with serial numbers and hostnames redacted",
+            failure(error, &sensitive),
+        ),
+        Entry::new(
+            "config.json",
+            "the configuration, with serial numbers and hostnames redacted",
+            config(&sensitive),
+        ),
+        Entry::new("profile.json", "the profile in use", profile()),
+        Entry::new("uname.txt", "the output of `uname -a`", uname()),
+        Entry::new(
+            "modules.txt",
+            "the lines of the acpi_call and ideapad_laptop modules in /proc/modules",
+            modules(),
+        ),
+        Entry::new(
+            "log.txt",
+            "the verbose log of this run, with serial numbers and hostnames redacted",
+            log(&sensitive),
+        ),
+    ];
+    let mut written = Vec::new();
+
+    for entry in entries {
+        let contents = match entry.contents {
+            Some(contents) => contents,
+            None => continue,
+        };
+        let path = dir.join(entry.file_name);
+
+        utils::write_atomic(&path, contents)
+            .with_context(|| format!("failed to write to {}", path.display().bold()))?;
+        written.push((entry.file_name, entry.description));
+    }
+
+    Ok(written)
+}
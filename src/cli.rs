@@ -0,0 +1,929 @@
+use crate::anyhow_with_tip::{self, IntoTip, TippingAnyhowResultExt};
+#[cfg(feature = "regulate")]
+use crate::app::battery_conservation::{RegulateOptions, Target};
+use crate::args::{self, *};
+use crate::config::{self, BuiltInProfile, LastExe, PossiblyBuiltInProfile};
+use crate::context::{self, Context};
+use crate::ext::{self, AnyhowResultExt};
+use crate::machine::{self, Machine};
+use crate::utils::{self, not};
+use crate::{app, bug_report, ec_cooldown, hardware, project_paths, verbose};
+use anyhow::Context as AnyhowContext;
+use once_cell::sync::OnceCell;
+use owo_colors::OwoColorize;
+use parking_lot::RwLockWriteGuard;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{env, io, panic, process, thread};
+use tap::Pipe;
+use try_drop::drop_strategies::BroadcastDropStrategy;
+
+/// The exit code after a panic in machine mode, which is the same one Rust uses.
+const PANIC_EXIT_CODE: i32 = 101;
+
+/// Runs `action`, after the config and ideapad have been initialized.
+fn run(action: TuxVantageAction) -> anyhow_with_tip::Result<Option<app::MachineOutput>> {
+    match action {
+        TuxVantageAction::Battery(TuxVantageBattery::Threshold(threshold)) => match threshold {
+            TuxVantageBatteryThreshold::Get { .. } => {
+                app::battery_threshold::get().map(app::MachineOutput::battery_threshold)
+            }
+            TuxVantageBatteryThreshold::Set { start, end } => {
+                app::battery_threshold::set(start, end).map(app::MachineOutput::battery_threshold)
+            }
+        },
+        TuxVantageAction::Battery(_) => {
+            unreachable!("`battery` is normalized as soon as it is parsed")
+        }
+        TuxVantageAction::BatteryConservation(battery_conservation) => match battery_conservation {
+            TuxVantageBatteryConservation::Enabled { .. } => {
+                app::battery_conservation::enabled().map(app::MachineOutput::battery_conservation)
+            }
+            TuxVantageBatteryConservation::Disabled { .. } => {
+                app::battery_conservation::disabled().map(app::MachineOutput::battery_conservation)
+            }
+            TuxVantageBatteryConservation::Enable {
+                handler,
+                handler_flag,
+                explain,
+                remember,
+                force,
+                settle,
+                strict,
+            } => args::enable_handler(handler, handler_flag)
+                .no_tip()
+                .and_then(|handler| {
+                    app::battery_conservation::enable(
+                        handler,
+                        explain,
+                        remember,
+                        force,
+                        app::Settle::new(settle, strict),
+                    )
+                })
+                .map(app::MachineOutput::battery_conservation),
+            TuxVantageBatteryConservation::Disable {
+                remember,
+                restore,
+                force,
+                settle,
+                strict,
+            } => app::battery_conservation::disable(
+                remember,
+                restore,
+                force,
+                app::Settle::new(settle, strict),
+            )
+            .map(app::MachineOutput::battery_conservation),
+            #[cfg(feature = "regulate")]
+            TuxVantageBatteryConservation::Regulate { status: true, .. } => {
+                app::battery_conservation::regulator_status()
+                    .map(app::MachineOutput::battery_conservation)
+                    .no_tip()
+            }
+            #[cfg(feature = "regulate")]
+            TuxVantageBatteryConservation::Regulate { stop: true, .. } => {
+                app::battery_conservation::stop_regulator()
+                    .map(app::MachineOutput::battery_conservation)
+                    .no_tip()
+            }
+            #[cfg(feature = "regulate")]
+            TuxVantageBatteryConservation::Regulate {
+                reinstall: true, ..
+            } => app::battery_conservation::reinstall()
+                .map(app::MachineOutput::battery_conservation)
+                .no_tip(),
+            #[cfg(feature = "regulate")]
+            TuxVantageBatteryConservation::Regulate {
+                uninstall: true, ..
+            } => app::battery_conservation::uninstall()
+                .map(app::MachineOutput::battery_conservation)
+                .no_tip(),
+            #[cfg(feature = "regulate")]
+            TuxVantageBatteryConservation::Regulate {
+                threshold,
+                disable_at,
+                cooldown,
+                cooldown_jitter,
+                min_toggle_interval,
+                adaptive_cooldown,
+                adaptive_cooldown_floor,
+                adaptive_cooldown_ceiling,
+                infallible,
+                only_on_ac,
+                notify,
+                on_exit,
+                listen,
+                matches,
+                aggregate,
+                install,
+                init_system,
+                service_name,
+                unit_dir,
+                force,
+                status: false,
+                daemonize,
+                log_file,
+                log_file_size,
+                log_file_count,
+                stop: false,
+                reinstall: false,
+                uninstall: false,
+                simulate,
+                prefer_native_thresholds,
+            } => app::battery_conservation::regulate(
+                Target::Threshold(threshold),
+                RegulateOptions {
+                    lower_threshold: disable_at,
+                    cooldown,
+                    cooldown_jitter,
+                    min_toggle_interval,
+                    adaptive_cooldown,
+                    adaptive_cooldown_floor,
+                    adaptive_cooldown_ceiling,
+                    infallible,
+                    only_on_ac,
+                    notify,
+                    on_exit,
+                    listen,
+                    matches,
+                    aggregate,
+                    install,
+                    init_system,
+                    service_name,
+                    unit_dir,
+                    force,
+                    daemonize,
+                    log_file,
+                    log_file_size,
+                    log_file_count,
+                    simulate,
+                    prefer_native_thresholds,
+                },
+            )
+            .map(app::MachineOutput::battery_conservation),
+            #[cfg(feature = "regulate")]
+            TuxVantageBatteryConservation::Hold(TuxVantageHold {
+                at,
+                deadband,
+                cooldown,
+                cooldown_jitter,
+                min_toggle_interval,
+                infallible,
+                matches,
+                install,
+                force,
+                prefer_native_thresholds,
+            }) => app::battery_conservation::regulate(
+                Target::Hold { at, deadband },
+                RegulateOptions {
+                    cooldown_jitter,
+                    min_toggle_interval,
+                    infallible,
+                    matches,
+                    install,
+                    force,
+                    prefer_native_thresholds,
+                    ..RegulateOptions::new(cooldown)
+                },
+            )
+            .map(app::MachineOutput::battery_conservation),
+        },
+        TuxVantageAction::SystemPerformance(system_performance) => match system_performance {
+            TuxVantageSystemPerformance::Get { raw, .. } => {
+                app::system_performance::get(raw).map(app::MachineOutput::system_performance)
+            }
+            TuxVantageSystemPerformance::Set {
+                mode,
+                remember,
+                settle,
+                strict,
+            } => app::system_performance::set(mode, remember, app::Settle::new(settle, strict))
+                .map(app::MachineOutput::system_performance),
+        },
+        TuxVantageAction::RapidCharge(rapid_charge) => match rapid_charge {
+            TuxVantageRapidCharge::Enabled { .. } => {
+                app::rapid_charge::enabled().map(app::MachineOutput::rapid_charge)
+            }
+            TuxVantageRapidCharge::Disabled { .. } => {
+                app::rapid_charge::disabled().map(app::MachineOutput::rapid_charge)
+            }
+            TuxVantageRapidCharge::Enable {
+                handler,
+                handler_flag,
+                explain,
+                remember,
+                force,
+                settle,
+                strict,
+            } => args::enable_handler(handler, handler_flag)
+                .no_tip()
+                .and_then(|handler| {
+                    app::rapid_charge::enable(
+                        handler,
+                        explain,
+                        remember,
+                        force,
+                        app::Settle::new(settle, strict),
+                    )
+                })
+                .map(app::MachineOutput::rapid_charge),
+            TuxVantageRapidCharge::Disable {
+                remember,
+                restore,
+                settle,
+                strict,
+            } => app::rapid_charge::disable(remember, restore, app::Settle::new(settle, strict))
+                .map(app::MachineOutput::rapid_charge),
+            #[cfg(feature = "regulate")]
+            TuxVantageRapidCharge::TopUp {
+                to,
+                by,
+                interval,
+                matches,
+            } => app::rapid_charge::top_up(to, by, interval, matches)
+                .map(app::MachineOutput::rapid_charge),
+        },
+        TuxVantageAction::Profiles(profiles) => match profiles {
+            TuxVantageProfiles::Get {
+                name,
+                brief,
+                json,
+                output,
+                force,
+            } => app::profiles::get(name, brief, json, output, force)
+                .map(app::MachineOutput::profiles)
+                .no_tip(),
+            TuxVantageProfiles::GetDefault => app::profiles::get_default()
+                .map(app::MachineOutput::profiles)
+                .no_tip(),
+            TuxVantageProfiles::Set {
+                name,
+                contents,
+                create_new,
+                dry_run,
+                confirm,
+                quiet,
+            } => app::profiles::set(name, contents, create_new, dry_run, confirm, quiet)
+                .map(app::MachineOutput::profiles)
+                .no_tip(),
+            TuxVantageProfiles::Validate {
+                contents,
+                schema,
+                all,
+            } => app::profiles::validate(contents, schema, all)
+                .map(app::MachineOutput::profiles)
+                .no_tip(),
+            TuxVantageProfiles::SetDefault { name, force } => {
+                app::profiles::set_default(name, force).map(app::MachineOutput::profiles)
+            }
+            TuxVantageProfiles::Remove { name } => {
+                app::profiles::remove(name).map(app::MachineOutput::profiles)
+            }
+            TuxVantageProfiles::Materialize { built_in, name } => {
+                app::profiles::materialize(built_in, name).map(app::MachineOutput::profiles)
+            }
+            TuxVantageProfiles::Contribute { name, out } => app::profiles::contribute(name, out)
+                .map(app::MachineOutput::profiles)
+                .maybe_acpi_call_tip(),
+            TuxVantageProfiles::Json {
+                name,
+                all,
+                generate_on_error,
+                pretty,
+                output,
+                force,
+            } => app::profiles::json(name, all, generate_on_error, pretty, output, force)
+                .map(app::MachineOutput::profiles)
+                .no_tip(),
+            TuxVantageProfiles::Schema { pretty } => app::profiles::schema(pretty)
+                .map(app::MachineOutput::profiles)
+                .no_tip(),
+        },
+        TuxVantageAction::Config(TuxVantageConfig::Check) => {
+            unreachable!("the config is checked before it is loaded")
+        }
+        TuxVantageAction::Config(TuxVantageConfig::Explain) => app::config::explain()
+            .map(app::MachineOutput::config)
+            .no_tip(),
+        TuxVantageAction::Config(TuxVantageConfig::SetHandler { target, handler }) => {
+            app::config::set_handler(target, handler.0).map(app::MachineOutput::config)
+        }
+        TuxVantageAction::Consistency(consistency) => match consistency {
+            TuxVantageConsistency::Show => app::consistency::show()
+                .map(app::MachineOutput::consistency)
+                .no_tip(),
+            TuxVantageConsistency::Reset => app::consistency::reset()
+                .map(app::MachineOutput::consistency)
+                .no_tip(),
+        },
+        TuxVantageAction::Doctor {
+            only,
+            exit_by_severity,
+        } => app::doctor::doctor(only, exit_by_severity)
+            .map(app::MachineOutput::doctor)
+            .no_tip(),
+        TuxVantageAction::Apply { trigger } => app::apply::apply(trigger)
+            .map(app::MachineOutput::apply)
+            .maybe_acpi_call_tip(),
+        TuxVantageAction::History {
+            limit,
+            columns,
+            no_header,
+            wide,
+        } => app::history::history(limit, columns, no_header, wide)
+            .map(app::MachineOutput::history)
+            .no_tip(),
+        TuxVantageAction::With {
+            conservation,
+            rapid_charge,
+            performance,
+            command,
+        } => app::with::with(
+            conservation.map(|toggle| toggle.0),
+            rapid_charge.map(|toggle| toggle.0),
+            performance.map(|mode| mode.0),
+            command,
+        )
+        .map(app::MachineOutput::with),
+        TuxVantageAction::Status {
+            watch: false,
+            interval: _,
+        } => app::status::status().map(app::MachineOutput::status),
+        TuxVantageAction::Status {
+            watch: true,
+            interval,
+        } => app::status::watch(interval.unwrap_or(app::status::DEFAULT_INTERVAL)).map(|()| None),
+        TuxVantageAction::Paths { check: false, .. } => {
+            app::paths::get().map(app::MachineOutput::paths).no_tip()
+        }
+        TuxVantageAction::Paths { check: true, fix } => {
+            app::paths::check(fix).map(app::MachineOutput::paths)
+        }
+        TuxVantageAction::Permissions { install } => app::permissions::permissions(install)
+            .map(app::MachineOutput::permissions)
+            .no_tip(),
+        TuxVantageAction::Completions { shell } => {
+            app::completions::completions(shell).no_tip().map(|()| None)
+        }
+        TuxVantageAction::ListProfileNames => {
+            unreachable!("profile names are listed before the config is loaded")
+        }
+        #[cfg(feature = "testing")]
+        TuxVantageAction::Selftest => {
+            unreachable!("the self test runs before the config is loaded")
+        }
+        TuxVantageAction::Templates(TuxVantageTemplates::Fields { command }) => {
+            app::templates::fields(command)
+                .map(app::MachineOutput::templates)
+                .no_tip()
+        }
+        TuxVantageAction::Examples { command } => app::examples::examples(command)
+            .map(app::MachineOutput::examples)
+            .no_tip(),
+        TuxVantageAction::SelfCheckService => {
+            app::self_check_service::self_check_service(config::machine().get())
+                .map(app::MachineOutput::self_check_service)
+                .no_tip()
+        }
+    }
+}
+
+/// Runs the command line interface of tuxvantage, exiting the process once it is done.
+pub fn main() {
+    static MACHINE: AtomicBool = AtomicBool::new(false);
+    static BACKTRACE: AtomicBool = AtomicBool::new(false);
+    static PANIC: AtomicBool = AtomicBool::new(false);
+    static PORCELAIN: AtomicBool = AtomicBool::new(false);
+    static JSON: AtomicBool = AtomicBool::new(false);
+    static BUG_REPORT: OnceCell<PathBuf> = OnceCell::new();
+
+    color_backtrace::install();
+
+    // a panic dump on standard output would break whatever is parsing it, so report panics as
+    // failures in machine mode instead
+    let human_panic_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if !MACHINE.load(Ordering::SeqCst) {
+            return human_panic_hook(info);
+        }
+
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("Box<dyn Any>");
+        let location = info
+            .location()
+            .map_or_else(|| "<unknown>".to_string(), ToString::to_string);
+        let backtrace = BACKTRACE
+            .load(Ordering::SeqCst)
+            .then(|| anyhow::anyhow!("{}", message).backtrace().to_string());
+        let output = Machine::<()>::panic(message, location, backtrace)
+            .envelope(machine::version())
+            .pipe_ref(serde_json::to_string)
+            .expect("failed to serialize machine output");
+
+        // panicking again while panicking aborts, so a closed standard output is ignored here
+        let _ = writeln!(io::stdout(), "{}", output);
+        process::exit(PANIC_EXIT_CODE);
+    }));
+
+    fn inner() -> anyhow_with_tip::Result<Option<app::MachineOutput>> {
+        let mut args = args::parse();
+        verbose::set(args.verbose);
+
+        if let Some(bug_report) = args.bug_report.take() {
+            // the log of the whole run goes into the bug report, even without `--verbose`
+            crate::log::tee::start();
+            let _ = BUG_REPORT.set(bug_report);
+        }
+
+        debug!("hello world!");
+
+        if args.porcelain {
+            if args.machine.is_some() {
+                return Err(anyhow::anyhow!(
+                    "{} can't be used with {}",
+                    "--porcelain".bold(),
+                    "--machine".bold()
+                )
+                .into());
+            }
+
+            // human output goes to standard error, which leaves standard output to the porcelain
+            debug!("porcelain output, so never machine");
+            args.machine = Some(config::Machine::Never);
+            PORCELAIN.store(true, Ordering::SeqCst);
+        }
+
+        if args.action.json() {
+            if matches!(args.machine, Some(config::Machine::Always)) || args.porcelain {
+                return Err(anyhow::anyhow!(
+                    "{} can't be used with {} or {}",
+                    "--json".bold(),
+                    "--machine always".bold(),
+                    "--porcelain".bold()
+                )
+                .into());
+            }
+
+            // like porcelain output, the json is the only thing written to standard output
+            debug!("json output, so never machine");
+            args.machine = Some(config::Machine::Never);
+            JSON.store(true, Ordering::SeqCst);
+        }
+
+        let machine = args.machine.unwrap_or_default().get();
+        debug!("set global machine to {machine}");
+        MACHINE.store(machine, Ordering::SeqCst);
+        machine::set(machine);
+
+        // set before anything else can fail, so that even those failures are in the shape the
+        // caller asked for
+        let machine_version = match args.machine_version {
+            Some(machine_version) => Some(machine_version),
+            None => machine::version_from_env().no_tip()?,
+        };
+
+        if let Some(machine_version) = machine_version {
+            debug!("set global machine version to {machine_version}");
+            machine::set_version(machine_version);
+        }
+
+        let panic_override = args.panic_override();
+        let panic = panic_override.unwrap_or(false);
+        debug!("set global panic to {}", panic);
+        PANIC.store(panic, Ordering::SeqCst);
+
+        if let TuxVantageAction::ListProfileNames = args.action {
+            // completions must never fail, so failures only result in fewer names
+            if project_paths::initialize(args.config).is_ok() {
+                app::completions::list_profile_names();
+            }
+
+            return Ok(None);
+        }
+
+        #[cfg(feature = "testing")]
+        if let TuxVantageAction::Selftest = args.action {
+            // the self test must never touch the real config, so it is not even loaded
+            return app::selftest::selftest(machine)
+                .map(app::MachineOutput::selftest)
+                .no_tip();
+        }
+
+        debug!("initialize project paths");
+        project_paths::initialize(args.config).context("failed to initialize project paths")?;
+
+        if args.no_tips {
+            debug!("tips disabled from arguments");
+            anyhow_with_tip::set_tips(config::Tips::Never);
+        }
+
+        if args.read_only {
+            debug!("read-only mode enabled from arguments");
+            config::set_read_only(true);
+        }
+
+        if let TuxVantageAction::Config(TuxVantageConfig::Check) = args.action {
+            debug!("checking the config, which must not depend on the config being loaded");
+            return app::config::check(machine)
+                .map(app::MachineOutput::config)
+                .no_tip();
+        }
+
+        let capabilities = args.action.capabilities();
+        debug!("action needs {:?}", capabilities);
+
+        debug!("initialize config");
+        let mut invalid_config = None;
+        let result = match config::initialize() {
+            Err(error) if !capabilities.config && config::is_invalid_config(&error) => {
+                debug!("the config is invalid, but the action doesn't need it, using the defaults");
+                invalid_config = Some(error);
+                config::initialize_with_defaults()
+            }
+            result => result,
+        }
+        .context("failed to initialize config");
+        let errors = {
+            if result.is_err() {
+                debug!(
+                    "failed to initialize configuration, configuring backtrace before bailing out"
+                );
+                let backtrace = args.backtrace.unwrap_or_default();
+                backtrace.configure();
+                BACKTRACE.store(backtrace.errors, Ordering::SeqCst);
+            }
+
+            result?
+        };
+        // the overrides aren't set up yet, so `--machine` has to win over the config here too
+        let machine = args.machine.unwrap_or_else(config::machine);
+        machine::set(machine.get());
+
+        if let Some(error) = invalid_config {
+            warn_with_tip!(
+                format_args!(
+                    "{} is invalid, so the defaults are used instead: {}",
+                    "tuxvantage.toml".bold(),
+                    utils::dedup_error_chain_for_humans(&error)
+                ),
+                ext::INVALID_CONFIG_TIP
+            );
+        }
+
+        if !errors.is_empty() {
+            warn_with_tip!(
+                "recoverable errors occurred during config initialization",
+                ext::RECOVERABLE_CONFIG_ERRORS_TIP
+            );
+
+            if not(machine) {
+                for error in errors {
+                    warn!("{:#}", error);
+                }
+            }
+        }
+
+        // operating with the config needs to be in a scope so that the guard will get dropped
+        // since we're operating with an `RwLock` otherwise we'll get a deadlock
+        {
+            let mut config = config::write();
+
+            if !args.skip_consistency_checks {
+                debug!("starting consistency checks");
+
+                let current_exe = env::current_exe()
+                    .context("failed to get current executable location of tuxvantage")?;
+                debug!("current exe location is '{}'", current_exe.display());
+
+                if let LastExe::Moved(last_exe) =
+                    config.consistency.check_last_exe(current_exe.clone())
+                {
+                    let regulator_unit = config
+                        .consistency
+                        .regulator_unit
+                        .as_deref()
+                        .unwrap_or_else(|| {
+                            Path::new(app::battery_conservation::REGULATOR_SERVICE_PATH)
+                        });
+
+                    warn_with_tip!(
+                        format_args!(
+                            "the last executable used to install the battery conservation regulator service, {}, differs from the current executable location \
+running this program, {}.\n\
+{} may fail to run with a no such file or directory error.",
+                            last_exe.display().bold(),
+                            current_exe.display().bold(),
+                            regulator_unit.display().bold(),
+                        ),
+                        ext::REGULATOR_EXE_MOVED_TIP
+                    )
+                }
+            }
+
+            debug!("setup config overrides from arguments");
+            config.tuxvantage.overrides.machine = args.machine;
+            config.tuxvantage.overrides.machine_version = machine_version;
+            config.tuxvantage.overrides.profile =
+                args.action.profile().map(str::to_string).or(args.profile);
+            config.tuxvantage.overrides.handlers.default =
+                args.handler.as_ref().map(|handler| handler.0.handler());
+            config.tuxvantage.overrides.switch_back = args
+                .handler
+                .map(|handler| handler.0.switches_back())
+                .unwrap_or(false);
+            config.tuxvantage.overrides.backtrace = args.backtrace;
+            config.tuxvantage.overrides.panic = panic_override;
+            config.tuxvantage.overrides.no_tips = args.no_tips;
+            config.tuxvantage.overrides.no_pager = args.no_pager;
+            config.tuxvantage.overrides.auto_modprobe = args.auto_modprobe;
+            config.tuxvantage.overrides.respect_ec_cooldown = args.respect_ec_cooldown;
+            machine::set(config.tuxvantage.machine().get());
+            machine::set_version(config.tuxvantage.machine_version());
+
+            debug!("configure backtrace");
+            let backtrace = config.tuxvantage.backtrace();
+            backtrace.configure();
+            BACKTRACE.store(backtrace.errors, Ordering::SeqCst);
+
+            debug!("set up panic toggle");
+            PANIC.store(config.tuxvantage.panic(), Ordering::SeqCst);
+
+            debug!("set up tips");
+            anyhow_with_tip::set_tips(config.tuxvantage.tips());
+
+            debug!("set up the ec cooldown guard");
+            ec_cooldown::configure(
+                config.tuxvantage.ec_cooldown().0,
+                config.tuxvantage.respect_ec_cooldown(),
+            );
+
+            // downgrading the guard to read-only does not help with the deadlock
+            let config = RwLockWriteGuard::downgrade(config);
+
+            if capabilities.config_write {
+                config::ensure_writable()
+                    .context("this command needs to write to the configuration")
+                    .no_tip()?;
+            }
+
+            if capabilities.hardware {
+                let fake = hardware::fake_path().is_some();
+
+                if !fake {
+                    debug!("check that the hardware is visible from here");
+                    ext::ensure_not_in_container()?;
+                }
+
+                debug!("initializing ideapad");
+                let profile = match config.default_profile() {
+                    Some(profile) => {
+                        debug!("config has default profile");
+                        let profile = profile.context(
+                            "this command needs access to the hardware, but the default profile \
+                             couldn't be used",
+                        )?;
+
+                        match config.profiles.matches_machine(&profile).filter(|_| !fake) {
+                            Some(true) => {}
+                            Some(false) => warn_with_tip!(
+                                format_args!(
+                                    "the profile {} doesn't expect the product name of this \
+                                     machine, so the wrong acpi calls may be sent",
+                                    profile.name.bold()
+                                ),
+                                ext::PROFILE_MISMATCH_TIP
+                            ),
+                            None => debug!("couldn't check if the profile matches this machine"),
+                        }
+
+                        profile
+                    }
+                    None if fake => {
+                        debug!("the hardware is faked, use the first built in profile");
+                        BuiltInProfile::ALL[0].get()
+                    }
+                    None => {
+                        debug!("no default profile is used, detecting it from the profiles with built ins");
+                        match config.profiles.detect() {
+                            Ok(profile) => profile,
+                            // already a concise error naming the product, which the generic
+                            // context would only bury
+                            Err(error) if error.is::<config::NoProfileError>() => {
+                                return Err(error).tip(ext::NO_PROFILE_TIP)
+                            }
+                            Err(error) => {
+                                let permission_denied =
+                                    error.downcast_ref::<io::Error>().map_or(false, |error| {
+                                        error.kind() == io::ErrorKind::PermissionDenied
+                                    });
+                                let tip = permission_denied
+                                    .then(|| ext::PRODUCT_DETECTION_PERMISSION_DENIED_TIP);
+
+                                return Err(error)
+                                    .context(
+                                        "this command needs access to the hardware, but no \
+                                         profile for this machine could be found",
+                                    )
+                                    .maybe_tip(tip);
+                            }
+                        }
+                    }
+                };
+
+                let selection = if config.tuxvantage.overrides.profile.is_some() {
+                    context::ProfileSelection::Override
+                } else if config.tuxvantage.profile().is_some() {
+                    context::ProfileSelection::Default
+                } else {
+                    context::ProfileSelection::AutoDetected
+                };
+                let origin = match config
+                    .profiles
+                    .with_built_ins()
+                    .find(|possibly_built_in| possibly_built_in.get().name == profile.name)
+                {
+                    Some(PossiblyBuiltInProfile::BuiltIn(_)) => context::ProfileOrigin::BuiltIn,
+                    _ => context::ProfileOrigin::External,
+                };
+                let active_profile = context::ActiveProfile {
+                    name: profile.name.to_string(),
+                    origin,
+                    selection,
+                };
+
+                debug!("setup up drop strategy");
+                let (fallible_drop_strategy, receiver) = BroadcastDropStrategy::new(16);
+                let context = Context::new_with_strategies(
+                    profile,
+                    fallible_drop_strategy,
+                    context::FallbackDropStrategy(config.tuxvantage.drop_fallback()),
+                );
+                let (stop, stopped) = context::register_receiver_thread();
+
+                let spawn_receiver_thread = move || {
+                    thread::spawn(move || context::receive_drop_errors(receiver, stop, stopped));
+                };
+
+                // only the forking thread survives `--daemonize`, so the regulator spawns it once
+                // it has forked
+                if args.action.daemonizes() {
+                    context::defer_receiver_thread(spawn_receiver_thread);
+                } else {
+                    spawn_receiver_thread();
+                }
+
+                context::initialize(context, active_profile);
+
+                debug!("ideapad initialized");
+            }
+
+            #[cfg(feature = "regulate")]
+            if capabilities.battery {
+                debug!("check that there is a battery");
+                config::ensure_battery()
+                    .context("this command needs a battery")
+                    .no_tip()?;
+            }
+        }
+
+        debug!("begin to run action");
+        run(args.action)
+    }
+
+    let result = inner();
+    context::stop_receiver_thread();
+    let result = result.map_err(|mut error| {
+        if error.tip.is_none() {
+            error.tip = ext::fallback_tip(&error.source).map(IntoTip::into_tip);
+        }
+
+        error
+    });
+
+    if result.is_ok() {
+        debug!("main function was ok")
+    }
+
+    if result.is_err() {
+        debug!("main function returned an error")
+    }
+
+    let machine = MACHINE.load(Ordering::SeqCst);
+    debug!("after main function, machine is {machine}");
+    let porcelain = PORCELAIN.load(Ordering::SeqCst);
+    let json = JSON.load(Ordering::SeqCst);
+
+    let backtrace = BACKTRACE.load(Ordering::SeqCst);
+    let panic = PANIC.load(Ordering::SeqCst);
+
+    if panic {
+        debug!("was told to panic, so panicking now (if any error occurred)");
+        result.as_ref().unwrap();
+    }
+
+    match result {
+        Ok(machine_output) => {
+            let exit_code = machine_output
+                .as_ref()
+                .map_or(0, app::MachineOutput::exit_code);
+
+            if machine {
+                let output = Machine::success(machine_output)
+                    .envelope(machine::version())
+                    .pipe_ref(serde_json::to_string)
+                    .expect("failed to serialize machine output");
+
+                utils::print_line(output);
+            } else if porcelain {
+                for line in machine_output.iter().flat_map(app::Porcelain::porcelain) {
+                    utils::print_line(line);
+                }
+            } else if let (true, Some(machine_output)) = (json, machine_output) {
+                let output = if atty::is(atty::Stream::Stdout) {
+                    serde_json::to_string_pretty(&machine_output)
+                } else {
+                    serde_json::to_string(&machine_output)
+                }
+                .expect("failed to serialize the output");
+
+                utils::print_line(output);
+            }
+
+            exit_code
+        }
+        Err(mut error) => {
+            debug!("debug representation of the main error:\n {error:#?}");
+            error.tip = error.take_tip(machine);
+
+            let bug_report = BUG_REPORT
+                .get()
+                .map(|dir| (dir, bug_report::write(dir, &error)));
+
+            if let (true, Some((dir, result))) = (machine, &bug_report) {
+                match result {
+                    Ok(_) => machine::push_warning(
+                        format!("wrote a bug report to {}", dir.display()),
+                        None::<&str>,
+                    ),
+                    Err(error) => machine::push_warning(
+                        format!("failed to write a bug report: {:#}", error),
+                        None::<&str>,
+                    ),
+                }
+            }
+
+            if machine {
+                let output = Machine::<()>::failure(error)
+                    .envelope(machine::version())
+                    .pipe_ref(serde_json::to_string)
+                    .expect("failed to serialize machine output");
+
+                utils::print_line(output);
+            } else {
+                let chain = utils::dedup_error_chain(error.source.chain().map(ToString::to_string));
+                let mut message = format!("{}\n", chain[0].bold());
+
+                for error in &chain[1..] {
+                    message.push_str(&format!("    caused by {}\n", error.italic()));
+                }
+
+                error!("{}", message);
+
+                if let Some(tip) = error.tip {
+                    tip!("{}", tip);
+                }
+
+                if backtrace {
+                    info!(
+                        "a backtrace was provided alongside the error:\n{}",
+                        error.source.backtrace()
+                    );
+                }
+
+                match bug_report {
+                    Some((dir, Ok(written))) => {
+                        info!("wrote a bug report to {}:", dir.display().bold());
+                        let _guard = crate::log::no_prologue::guard_for(crate::log::Level::Info);
+
+                        for (file_name, description) in written {
+                            info!("{}{}: {}", app::tab(2), file_name.bold(), description);
+                        }
+
+                        info!("nothing was sent anywhere, attach these files to an issue yourself");
+                    }
+                    Some((_, Err(error))) => warn!("failed to write a bug report: {:#}", error),
+                    None => {}
+                }
+            }
+
+            1
+        }
+    }
+    .pipe(process::exit)
+}
@@ -1,24 +1,321 @@
 use anyhow::Context;
-use battery::{Batteries, Battery};
-use ideapad::{Handler, Profile};
+#[cfg(feature = "regulate")]
+use battery::Battery;
+use ideapad::{Handler, Profile, SystemPerformanceMode};
 use once_cell::sync::OnceCell;
 use owo_colors::OwoColorize;
 use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::borrow::Cow;
-use std::ops::{Deref, Not, RangeInclusive};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::ops::{Deref, Not};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
-use std::{env, fmt, fs};
+use std::time::{Duration, Instant};
+use std::{env, fmt, fs, io, mem, thread};
 use tap::{Pipe, Tap};
 
-use crate::project_paths;
-use crate::project_paths::profiles::ExternalProfile;
-use crate::utils::{DisplaySerializer, FromStrDeserializer};
+use crate::args::FromStrSystemPerformanceMode;
+use crate::init_system::InitSystem;
+use crate::project_paths::profiles::{ExternalProfile, FailedProfile};
+use crate::types::{BatteryLevel, HumanDuration};
+use crate::utils::{DisplaySerializer, FromStrDeserializer, Names};
+use crate::{context, project_paths, utils};
 
 static EXISTENCE_ENSURED: AtomicBool = AtomicBool::new(false);
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Returned when trying to write to the configuration while it is read-only.
+#[derive(Debug)]
+pub struct ReadOnlyError;
+
+impl fmt::Display for ReadOnlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the configuration is read-only")
+    }
+}
+
+impl std::error::Error for ReadOnlyError {}
+
+/// No battery matched the selector, which most likely means that the battery was removed.
+#[derive(Debug)]
+pub struct NoBatteryError {
+    pub matches: BatteryMatches,
+}
+
+impl fmt::Display for NoBatteryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no battery matching {} was found", self.matches.bold())
+    }
+}
+
+impl std::error::Error for NoBatteryError {}
+
+/// The active profile declares a feature as unsupported, so using it would only fail with a
+/// confusing error from the firmware.
+#[derive(Debug)]
+pub struct UnsupportedError {
+    pub feature: Feature,
+}
+
+impl fmt::Display for UnsupportedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "this profile declares {} as unsupported",
+            self.feature.name().bold()
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedError {}
+
+/// No profile expects the product name of this machine, which most likely means that it isn't a
+/// machine tuxvantage supports.
+#[derive(Debug)]
+pub struct NoProfileError {
+    pub product_name: String,
+}
+
+impl fmt::Display for NoProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "no profile matches the product {}; tuxvantage supports Lenovo IdeaPad-family machines",
+            self.product_name.bold()
+        )
+    }
+}
+
+impl std::error::Error for NoProfileError {}
+
+/// `tuxvantage.toml` exists but couldn't be deserialized, such as because of a syntax error.
+#[derive(Debug)]
+pub struct InvalidConfigError {
+    path: PathBuf,
+
+    /// Where in the file the error is, zero-based.
+    location: Option<(usize, usize)>,
+
+    /// The error of the toml crate, without its location.
+    error: Box<dyn std::error::Error + Send + Sync>,
+}
+
+impl InvalidConfigError {
+    fn new(path: &Path, contents: &str, error: toml::de::Error) -> Self {
+        // errors of values which were deserialized by hand, such as a misspelled handler, are
+        // located at the table of the key by the toml crate, so the key is looked up instead
+        let mut message = error.to_string();
+        let location = key_of(&message)
+            .and_then(|key| key_location(contents, key))
+            .or_else(|| error.line_col());
+
+        if error.line_col().is_some() {
+            if let Some(index) = message.rfind(" at line ") {
+                message.truncate(index);
+            }
+        }
+
+        Self {
+            path: path.to_path_buf(),
+            location,
+            error: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for InvalidConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to deserialize {}", self.path.display().bold())?;
+
+        if let Some((line, column)) = self.location {
+            write!(f, " at line {}, column {}", line + 1, column + 1)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvalidConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.error)
+    }
+}
+
+/// The dotted key an error of the toml crate is for, such as `handlers.default`.
+fn key_of(message: &str) -> Option<&str> {
+    let (_, key) = message.rsplit_once(" for key `")?;
+
+    key.split('`').next().filter(|key| !key.is_empty())
+}
+
+/// Where the value of the dotted `key` is in `contents`, zero-based. Only keys written out in
+/// their table are found, not dotted keys or the ones of inline tables.
+fn key_location(contents: &str, key: &str) -> Option<(usize, usize)> {
+    let (table, name) = key.rsplit_once('.').unwrap_or(("", key));
+    let mut current = String::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let trimmed = line.trim_start();
+
+        if let Some(header) = trimmed.strip_prefix('[') {
+            current = header
+                .trim_start_matches('[')
+                .split(']')
+                .next()
+                .unwrap_or_default()
+                .split('.')
+                .map(|part| part.trim().trim_matches('"'))
+                .collect::<Vec<_>>()
+                .join(".");
+            continue;
+        }
+
+        if current != table {
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once('=') {
+            if key.trim().trim_matches('"') == name {
+                let column = key.len() + 1 + value.len() - value.trim_start().len();
+                return Some((index, column));
+            }
+        }
+    }
+
+    None
+}
+
+/// Whether `error` is because `tuxvantage.toml` couldn't be deserialized.
+pub fn is_invalid_config(error: &anyhow::Error) -> bool {
+    error.chain().any(|error| error.is::<InvalidConfigError>())
+}
+
+pub fn read_only() -> bool {
+    READ_ONLY.load(Ordering::SeqCst)
+}
+
+pub fn set_read_only(read_only: bool) {
+    READ_ONLY.store(read_only, Ordering::SeqCst)
+}
+
+/// Writes the default contents of `name` to `path` if it doesn't exist yet. This is done while
+/// holding the configuration lock, so that a concurrent invocation which created and then modified
+/// it in the meantime doesn't have its changes overwritten.
+fn write_default(path: &Path, name: &str, contents: impl FnOnce() -> String) -> anyhow::Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+
+    let _lock = ConfigLock::acquire()?;
+
+    if path.exists() {
+        debug!("`{}` was created while waiting for the lock", name);
+        return Ok(());
+    }
+
+    debug!("write default `{}` to path", name);
+    utils::write_atomic(path, contents())
+        .map_err(|error| project_paths::write_error(path, error))
+        .with_context(|| format!("failed to write to {}", name.bold()))
+}
+
+/// How long to wait for another process to finish modifying the configuration.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// An advisory lock on `tuxvantage.toml.lock`, held across every load-modify-dump cycle of the
+/// configuration so that concurrent invocations don't lose each other's changes. Released when
+/// dropped.
+pub struct ConfigLock {
+    _file: File,
+}
+
+impl ConfigLock {
+    pub fn acquire() -> anyhow::Result<Self> {
+        let path = project_paths::tuxvantage_toml_lock();
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("failed to open {}", "tuxvantage.toml.lock".bold()))?;
+        let started = Instant::now();
+
+        loop {
+            let locked = utils::try_lock_exclusive(&file)
+                .with_context(|| format!("failed to lock {}", "tuxvantage.toml.lock".bold()))?;
+
+            if locked {
+                debug!("acquired the configuration lock");
+                return Ok(Self { _file: file });
+            }
+
+            anyhow::ensure!(
+                started.elapsed() < LOCK_TIMEOUT,
+                "another tuxvantage process is modifying the configuration"
+            );
+
+            debug!("configuration is locked by another process, retrying");
+            thread::sleep(LOCK_RETRY_INTERVAL);
+        }
+    }
+}
+
+pub fn ensure_writable() -> anyhow::Result<()> {
+    if read_only() {
+        Err(ReadOnlyError.into())
+    } else {
+        Ok(())
+    }
+}
+
+fn is_read_only_error(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|error| error.downcast_ref::<io::Error>())
+        .any(|error| {
+            error.raw_os_error() == Some(libc::EROFS)
+                || error.kind() == io::ErrorKind::PermissionDenied
+        })
+}
+
+/// A feature of the laptop which a profile can declare as unsupported.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    BatteryConservation,
+    RapidCharge,
+    SystemPerformance,
+}
+
+impl Feature {
+    /// The names of the features as they are written in profiles.
+    pub const NAMES: [&'static str; 3] =
+        ["battery_conservation", "rapid_charge", "system_performance"];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::BatteryConservation => "battery conservation",
+            Self::RapidCharge => "rapid charge",
+            Self::SystemPerformance => "system performance",
+        }
+    }
+}
+
+/// What a profile declares beyond what ideapad knows about, read from the same file.
+#[derive(Deserialize, Default, Clone)]
+pub struct Declarations {
+    #[serde(default)]
+    pub unsupported: Vec<Feature>,
+
+    /// Compare `expected_product_names` with the product name of the machine ignoring case.
+    #[serde(default)]
+    pub case_insensitive_product_names: bool,
+}
 
 pub enum BuiltInProfile {
     Ideapad15IIL05,
@@ -26,12 +323,35 @@ pub enum BuiltInProfile {
 }
 
 impl BuiltInProfile {
+    pub const ALL: [Self; 2] = [Self::Ideapad15IIL05, Self::Ideapad15Amd];
+
     pub fn get(&self) -> Profile {
         match self {
             Self::Ideapad15IIL05 => Profile::IDEAPAD_15IIL05,
             Self::Ideapad15Amd => Profile::IDEAPAD_AMD,
         }
     }
+
+    /// The built-in profiles whose names aren't in `disabled`.
+    pub fn enabled(disabled: &[String]) -> impl Iterator<Item = Self> + '_ {
+        Self::ALL
+            .into_iter()
+            .filter(move |built_in| !built_in.is_disabled(disabled))
+    }
+
+    pub fn is_disabled(&self, disabled: &[String]) -> bool {
+        let name = self.get().name;
+
+        disabled.iter().any(|disabled| *disabled == name)
+    }
+
+    /// The features the model of this profile doesn't have.
+    pub fn unsupported(&self) -> &'static [Feature] {
+        // both models have every feature
+        match self {
+            Self::Ideapad15IIL05 | Self::Ideapad15Amd => &[],
+        }
+    }
 }
 
 pub enum PossiblyBuiltInProfile {
@@ -57,15 +377,59 @@ impl PossiblyBuiltInProfile {
             Self::External(profile) => Some(&profile.path),
         }
     }
+
+    pub fn unsupported(&self) -> &[Feature] {
+        match self {
+            Self::BuiltIn(profile) => profile.unsupported(),
+            Self::External(profile) => &profile.declarations.unsupported,
+        }
+    }
+
+    /// Whether this profile expects `product_name`, which must already be normalized. Built-in
+    /// profiles are always compared exactly.
+    pub fn expects(&self, product_name: &str) -> bool {
+        let case_insensitive = match self {
+            Self::BuiltIn(_) => false,
+            Self::External(profile) => profile.declarations.case_insensitive_product_names,
+        };
+
+        self.get().expected_product_names.iter().any(|expected| {
+            let expected = expected.to_string();
+            let expected = normalize_product_name(&expected);
+
+            if case_insensitive {
+                expected.eq_ignore_ascii_case(product_name)
+            } else {
+                expected == product_name
+            }
+        })
+    }
 }
 
 pub struct Overrides {
     pub profile: Option<String>,
     pub handlers: Handlers,
     pub machine: Option<Machine>,
-    pub backtrace: Backtrace,
+
+    /// From `--machine-version`, or the environment variable if it isn't given.
+    pub machine_version: Option<crate::machine::Version>,
+
+    /// From `--backtrace`, which is `None` if it isn't given so that the config is used.
+    pub backtrace: Option<Backtrace>,
     pub battery: BatteryConfig,
-    pub panic: bool,
+
+    /// `Some(true)` from `--panic`, `Some(false)` from `--no-panic`, or `None` if neither is
+    /// given so that the config is used.
+    pub panic: Option<bool>,
+    pub switch_back: bool,
+
+    /// Whether the handler of the enable subcommand was given with `--handler` instead of
+    /// positionally.
+    pub handler_flag: bool,
+    pub no_tips: bool,
+    pub no_pager: bool,
+    pub auto_modprobe: bool,
+    pub respect_ec_cooldown: bool,
 }
 
 impl Overrides {
@@ -73,9 +437,16 @@ impl Overrides {
         profile: None,
         handlers: Handlers::DEFAULT,
         machine: None,
-        backtrace: Backtrace::DEFAULT,
+        machine_version: None,
+        backtrace: None,
         battery: BatteryConfig::DEFAULT,
-        panic: false,
+        panic: None,
+        switch_back: false,
+        handler_flag: false,
+        no_tips: false,
+        no_pager: false,
+        auto_modprobe: false,
+        respect_ec_cooldown: false,
     };
 }
 
@@ -87,12 +458,32 @@ impl Default for Overrides {
 
 #[derive(Serialize, Deserialize)]
 pub struct Handlers {
+    #[serde(default, deserialize_with = "deserialize_handler")]
     pub default: Option<Handler>,
+
+    #[serde(default, deserialize_with = "deserialize_handler")]
     pub battery_conservation: Option<Handler>,
+
+    #[serde(default, deserialize_with = "deserialize_handler")]
     pub rapid_charging: Option<Handler>,
 }
 
+fn deserialize_handler<'de, D>(deserializer: D) -> Result<Option<Handler>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    utils::deserialize_optional_name(deserializer, Handlers::NAMES, "handler")
+}
+
 impl Handlers {
+    /// The names of the handlers the configuration accepts, which are the same as the ones of
+    /// `--handler` without `switch-back`.
+    pub const NAMES: Names<Handler> = &[
+        (Handler::Switch, &["switch", "s"]),
+        (Handler::Ignore, &["ignore", "i"]),
+        (Handler::Error, &["error", "e"]),
+    ];
+
     pub const DEFAULT: Self = Self {
         default: None,
         battery_conservation: None,
@@ -118,7 +509,109 @@ impl Default for Handlers {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+/// The mode a handler is resolved for.
+#[derive(Debug, Copy, Clone)]
+pub enum HandlerMode {
+    BatteryConservation,
+    RapidCharging,
+}
+
+/// Where a handler can come from, in order of precedence.
+#[derive(Serialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HandlerSource {
+    /// The positional handler of the enable subcommand.
+    Positional,
+
+    /// The `--handler` flag of the enable subcommand.
+    Flag,
+
+    /// The global `--handler` flag.
+    GlobalFlag,
+
+    /// The handler of the mode in the config.
+    ModeConfig,
+
+    /// The default handler in the config.
+    DefaultConfig,
+
+    /// The `switch` handler, used when nothing else is set.
+    BuiltIn,
+}
+
+impl HandlerSource {
+    /// The name of the source, as it is serialized.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Positional => "positional",
+            Self::Flag => "flag",
+            Self::GlobalFlag => "global_flag",
+            Self::ModeConfig => "mode_config",
+            Self::DefaultConfig => "default_config",
+            Self::BuiltIn => "built_in",
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::Positional => "positional argument",
+            Self::Flag => "--handler of the subcommand",
+            Self::GlobalFlag => "global --handler",
+            Self::ModeConfig => "handler of the mode in the config",
+            Self::DefaultConfig => "default handler in the config",
+            Self::BuiltIn => "built-in default",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Copy, Clone)]
+pub struct HandlerCandidate {
+    pub source: HandlerSource,
+    pub value: Option<Handler>,
+
+    /// Whether this is the handler which was used, which is the first one that is set.
+    pub used: bool,
+}
+
+/// Every source a handler was resolved from, in order of precedence.
+#[derive(Serialize, Debug, Clone)]
+#[serde(transparent)]
+pub struct HandlerResolution(Vec<HandlerCandidate>);
+
+impl HandlerResolution {
+    fn new(candidates: impl IntoIterator<Item = (HandlerSource, Option<Handler>)>) -> Self {
+        let mut used = false;
+
+        candidates
+            .into_iter()
+            .map(|(source, value)| {
+                let candidate = HandlerCandidate {
+                    source,
+                    value,
+                    used: !used && value.is_some(),
+                };
+                used |= value.is_some();
+                candidate
+            })
+            .collect::<Vec<_>>()
+            .pipe(Self)
+    }
+
+    pub fn candidates(&self) -> &[HandlerCandidate] {
+        &self.0
+    }
+
+    /// The handler which won.
+    pub fn handler(&self) -> Handler {
+        self.0
+            .iter()
+            .find(|candidate| candidate.used)
+            .and_then(|candidate| candidate.value)
+            .unwrap_or(Handler::Switch)
+    }
+}
+
+#[derive(Serialize, Debug, Copy, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum Machine {
     Always,
@@ -126,6 +619,15 @@ pub enum Machine {
     Auto,
 }
 
+impl<'de> Deserialize<'de> for Machine {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        utils::deserialize_name(deserializer, Self::NAMES, "machine choice")
+    }
+}
+
 impl Default for Machine {
     fn default() -> Self {
         Machine::Auto
@@ -133,6 +635,12 @@ impl Default for Machine {
 }
 
 impl Machine {
+    pub const NAMES: Names<Self> = &[
+        (Self::Always, &["always", "true", "t"]),
+        (Self::Never, &["never", "false", "f"]),
+        (Self::Auto, &["auto", "a"]),
+    ];
+
     pub fn get(self) -> bool {
         match self {
             Self::Always => {
@@ -158,7 +666,40 @@ impl Machine {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// How often tips should be shown alongside errors.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Tips {
+    Always,
+
+    /// Only show each tip the first time it comes up.
+    Once,
+    Never,
+}
+
+impl Default for Tips {
+    fn default() -> Self {
+        Self::Always
+    }
+}
+
+/// What to do with an error from dropping something which couldn't be reported as usual, such as
+/// when the thread reporting them has stopped.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DropFallback {
+    Panic,
+    Log,
+    Abort,
+}
+
+impl Default for DropFallback {
+    fn default() -> Self {
+        Self::Panic
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
 pub struct Backtrace {
     pub panics: bool,
     pub errors: bool,
@@ -245,145 +786,214 @@ impl FromStr for Machine {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(match s {
-            "always" | "true" | "t" => Self::Always,
-            "never" | "false" | "f" => Self::Never,
-            "auto" | "a" => Self::Auto,
-            _ => anyhow::bail!("invalid machine choice {}", s.bold()),
-        })
+        utils::parse_name(Self::NAMES, "machine choice", s)
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct BatteryLevel(u8);
-
-impl BatteryLevel {
-    pub const DEFAULT: Self = Self(80);
-    const RANGE: RangeInclusive<u8> = 0..=100;
+/// The charging state of a battery, as matched by [`BatteryMatches::State`].
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChargeState {
+    Charging,
+    Discharging,
+    Full,
+}
 
-    pub fn new(level: u8) -> Option<Self> {
-        if Self::RANGE.contains(&level) {
-            Some(Self(level))
-        } else {
-            None
-        }
+impl ChargeState {
+    pub const NAMES: Names<Self> = &[
+        (Self::Charging, &["charging", "c"]),
+        (Self::Discharging, &["discharging", "d"]),
+        (Self::Full, &["full", "f"]),
+    ];
+
+    #[cfg(feature = "regulate")]
+    fn matches(self, state: battery::State) -> bool {
+        matches!(
+            (self, state),
+            (Self::Charging, battery::State::Charging)
+                | (Self::Discharging, battery::State::Discharging)
+                | (Self::Full, battery::State::Full)
+        )
     }
+}
 
-    pub const fn inner(self) -> u8 {
-        self.0
+/// What the regulator does with battery conservation mode when it is told to exit.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnExit {
+    /// Leave battery conservation mode as it is.
+    Keep,
+    Enable,
+    Disable,
+
+    /// Put battery conservation mode back to how it was when the regulator started.
+    Restore,
+}
+
+impl OnExit {
+    pub const NAMES: Names<Self> = &[
+        (Self::Keep, &["keep"]),
+        (Self::Enable, &["enable"]),
+        (Self::Disable, &["disable"]),
+        (Self::Restore, &["restore"]),
+    ];
+
+    /// What battery conservation mode should be set to on exit, given whether it was enabled when
+    /// the regulator started, or `None` if it should be left as is.
+    pub fn conservation(self, initially_enabled: bool) -> Option<bool> {
+        match self {
+            Self::Keep => None,
+            Self::Enable => Some(true),
+            Self::Disable => Some(false),
+            Self::Restore => Some(initially_enabled),
+        }
     }
 }
 
-impl FromStr for BatteryLevel {
+impl FromStr for OnExit {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        s.trim_end_matches('%')
-            .parse::<u8>()
-            .context("number given wasn't valid")?
-            .pipe(Self::new)
-            .with_context(|| {
-                format!(
-                    "{} is out of bounds (must be within 0 and 100 inclusive)",
-                    format_args!("{}%", s.bold())
-                )
-            })
+        utils::parse_name(Self::NAMES, "exit action", s)
     }
 }
 
-impl Default for BatteryLevel {
-    fn default() -> Self {
-        Self(80)
-    }
+/// What tells the regulator that the batteries changed, so that it doesn't have to wait for the
+/// cooldown.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Listen {
+    Upower,
 }
 
-impl fmt::Display for BatteryLevel {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}%", self.0)
-    }
+impl Listen {
+    pub const NAMES: Names<Self> = &[(Self::Upower, &["upower"])];
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct CoolDown(pub Duration);
+impl FromStr for Listen {
+    type Err = anyhow::Error;
 
-impl CoolDown {
-    pub const DEFAULT: Self = Self(Duration::from_secs(60));
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        utils::parse_name(Self::NAMES, "event source", s)
+    }
 }
 
-impl FromStr for CoolDown {
-    type Err = anyhow::Error;
+/// How the levels of the batteries matched by [`BatteryMatches::All`] are combined into one.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregate {
+    Min,
+    Max,
+    Avg,
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        pub enum F64OrU64 {
-            F64(f64),
-            U64(u64),
+impl Aggregate {
+    pub const NAMES: Names<Self> = &[
+        (Self::Min, &["min"]),
+        (Self::Max, &["max"]),
+        (Self::Avg, &["avg", "average"]),
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Avg => "avg",
         }
+    }
+
+    /// Combines `levels` into one, which is 0 if there are none.
+    pub fn apply(self, levels: impl IntoIterator<Item = u8>) -> u8 {
+        let levels = levels.into_iter();
 
-        impl F64OrU64 {
-            pub fn into_duration(self) -> Duration {
-                match self {
-                    Self::F64(value) => Duration::from_secs_f64(value),
-                    Self::U64(value) => Duration::from_secs(value),
+        match self {
+            Self::Min => levels.min().unwrap_or(0),
+            Self::Max => levels.max().unwrap_or(0),
+            Self::Avg => {
+                let (sum, count) = levels.fold((0u32, 0u32), |(sum, count), level| {
+                    (sum + level as u32, count + 1)
+                });
+
+                if count == 0 {
+                    0
+                } else {
+                    (sum as f32 / count as f32).round() as u8
                 }
             }
         }
-
-        let s = s.trim_end_matches('s');
-
-        s.parse::<f64>()
-            .map(F64OrU64::F64)
-            .or_else(|_| s.parse::<u64>().map(F64OrU64::U64))
-            .map(F64OrU64::into_duration)
-            .map(Self)
-            .context("value wasn't a valid double or number")
     }
 }
 
-impl Default for CoolDown {
-    fn default() -> Self {
-        Self(Duration::from_secs(60))
-    }
-}
-
-impl fmt::Display for CoolDown {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let duration = self.0.as_secs_f64();
+impl FromStr for Aggregate {
+    type Err = anyhow::Error;
 
-        if duration.fract() == 0.0 {
-            (duration as u64).fmt(f)
-        } else {
-            duration.fmt(f)
-        }
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        utils::parse_name(Self::NAMES, "aggregate", s)
     }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum BatteryMatches {
+    /// The first battery which reports a design capacity, which skips docks and power banks that
+    /// show up as batteries.
     First,
+
+    /// Every battery, whose levels are combined with an [`Aggregate`].
+    All,
     Index(usize),
     Vendor(String),
     Model(String),
     SerialNumber(String),
+    State(ChargeState),
 }
 
 impl BatteryMatches {
-    pub fn find(&self, batteries: &mut Batteries) -> anyhow::Result<Option<Battery>> {
+    /// The names of the variants, which come before the `=` when parsing.
+    pub const VARIANTS: Names<&'static str> = &[
+        ("first", &["first", "f"]),
+        ("all", &["all", "a"]),
+        ("index", &["index", "i"]),
+        ("vendor", &["vendor", "v"]),
+        ("model", &["model", "m"]),
+        ("serial_number", &["serial_number", "sn", "s"]),
+        ("state", &["state", "st"]),
+    ];
+
+    /// The batteries this matches, which is at most one unless this is [`Self::All`].
+    #[cfg(feature = "regulate")]
+    pub fn find<B, E>(
+        &self,
+        batteries: impl Iterator<Item = Result<B, E>>,
+    ) -> anyhow::Result<Vec<B>>
+    where
+        B: BatteryFields,
+        E: std::error::Error + Send + Sync + 'static,
+    {
         batteries
             .collect::<Result<Vec<_>, _>>()
             .context("failed to get list of batteries")?
             .into_iter()
             .enumerate()
-            .find(|(index, battery)| self.matches(*index, battery))
+            .filter(|(index, battery)| self.matches(*index, battery))
             .map(|(_, battery)| battery)
+            .take(self.limit())
+            .collect::<Vec<_>>()
             .pipe(Ok)
     }
 
-    pub fn find_infallible(
+    /// Like [`Self::find`], but batteries which couldn't be enumerated are skipped and returned
+    /// as errors instead.
+    #[cfg(feature = "regulate")]
+    pub fn find_infallible<B, E>(
         &self,
-        batteries: &mut Batteries,
-    ) -> (Option<Battery>, Vec<anyhow::Error>) {
+        batteries: impl Iterator<Item = Result<B, E>>,
+    ) -> (Vec<B>, Vec<anyhow::Error>)
+    where
+        B: BatteryFields,
+        E: std::error::Error + Send + Sync + 'static,
+    {
         let mut errors = Vec::new();
-        let mut battery = None;
+        let mut found = Vec::new();
 
         for (index, enumerated_battery) in batteries.enumerate() {
             let enumerated_battery = match enumerated_battery.context("failed to get battery") {
@@ -395,18 +1005,44 @@ impl BatteryMatches {
             };
 
             if self.matches(index, &enumerated_battery) {
-                battery = Some(enumerated_battery);
-                break;
+                found.push(enumerated_battery);
+
+                if found.len() == self.limit() {
+                    break;
+                }
             }
         }
 
-        (battery, errors)
+        (found, errors)
     }
 
-    fn matches(&self, index: usize, battery: &Battery) -> bool {
+    /// Fails with a [`NoBatteryError`] if `found`, the batteries this matched, is empty.
+    #[cfg(feature = "regulate")]
+    pub fn require<B>(&self, found: Vec<B>) -> anyhow::Result<Vec<B>> {
+        if found.is_empty() {
+            return Err(NoBatteryError {
+                matches: self.clone(),
+            }
+            .into());
+        }
+
+        Ok(found)
+    }
+
+    /// How many batteries this may match.
+    #[cfg(feature = "regulate")]
+    fn limit(&self) -> usize {
+        match self {
+            Self::All => usize::MAX,
+            _ => 1,
+        }
+    }
+
+    #[cfg(feature = "regulate")]
+    pub fn matches(&self, index: usize, battery: &impl BatteryFields) -> bool {
         match self {
-            BatteryMatches::First if index == 0 => true,
-            BatteryMatches::First => false,
+            BatteryMatches::First => battery.has_design_capacity(),
+            BatteryMatches::All => true,
             BatteryMatches::Index(this_index) => *this_index == index,
             BatteryMatches::Vendor(vendor) => {
                 battery.vendor().map(|v| v == vendor).unwrap_or(false)
@@ -416,6 +1052,66 @@ impl BatteryMatches {
                 .serial_number()
                 .map(|s| s == serial_number)
                 .unwrap_or(false),
+            BatteryMatches::State(state) => state.matches(battery.state()),
+        }
+    }
+}
+
+/// What [`BatteryMatches`] matches a battery on, so that fake batteries can stand in for real ones
+/// in the tests.
+#[cfg(feature = "regulate")]
+pub trait BatteryFields {
+    fn vendor(&self) -> Option<&str>;
+    fn model(&self) -> Option<&str>;
+    fn serial_number(&self) -> Option<&str>;
+    fn state(&self) -> battery::State;
+
+    /// Whether the battery reports the capacity it was designed for, which docks and power banks
+    /// don't.
+    fn has_design_capacity(&self) -> bool;
+}
+
+#[cfg(feature = "regulate")]
+impl BatteryFields for Battery {
+    fn vendor(&self) -> Option<&str> {
+        Battery::vendor(self)
+    }
+
+    fn model(&self) -> Option<&str> {
+        Battery::model(self)
+    }
+
+    fn serial_number(&self) -> Option<&str> {
+        Battery::serial_number(self)
+    }
+
+    fn state(&self) -> battery::State {
+        Battery::state(self)
+    }
+
+    fn has_design_capacity(&self) -> bool {
+        self.energy_full_design().value > 0.0
+    }
+}
+
+impl fmt::Display for BatteryMatches {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::First => f.write_str("first="),
+            Self::All => f.write_str("all="),
+            Self::Index(index) => write!(f, "index={}", index),
+            Self::Vendor(vendor) => write!(f, "vendor={}", vendor),
+            Self::Model(model) => write!(f, "model={}", model),
+            Self::SerialNumber(serial_number) => write!(f, "serial_number={}", serial_number),
+            Self::State(state) => {
+                let name = ChargeState::NAMES
+                    .iter()
+                    .find(|(this, _)| this == state)
+                    .map(|(_, names)| names[0])
+                    .unwrap_or_default();
+
+                write!(f, "state={}", name)
+            }
         }
     }
 }
@@ -424,44 +1120,216 @@ impl FromStr for BatteryMatches {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s
-            .split_once('=')
-            .with_context(|| format!("delimit the variant and value with {}", '='.bold()))?
-        {
-            ("first" | "f", _) => Ok(BatteryMatches::First),
-            ("index" | "i", value) => value
+        // the variants without a value may leave out the `=`
+        let (variant, value) = match s.split_once('=') {
+            Some(split) => split,
+            None if matches!(
+                utils::parse_name(Self::VARIANTS, "variant", s),
+                Ok("first" | "all")
+            ) =>
+            {
+                (s, "")
+            }
+            None => {
+                return Err(anyhow::anyhow!(
+                    "delimit the variant and value with {}",
+                    '='.bold()
+                ))
+            }
+        };
+
+        match utils::parse_name(Self::VARIANTS, "variant", variant)? {
+            "first" => Ok(BatteryMatches::First),
+            "all" => Ok(BatteryMatches::All),
+            "index" => value
                 .parse()
                 .context("value wasn't a valid integer")
                 .map(BatteryMatches::Index),
-            ("vendor" | "v", value) => Ok(BatteryMatches::Vendor(value.to_string())),
-            ("model" | "m", value) => Ok(BatteryMatches::Model(value.to_string())),
-            ("serial_number" | "sn" | "s", value) => {
-                Ok(BatteryMatches::SerialNumber(value.to_string()))
+            "vendor" => Ok(BatteryMatches::Vendor(value.to_string())),
+            "model" => Ok(BatteryMatches::Model(value.to_string())),
+            "serial_number" => Ok(BatteryMatches::SerialNumber(value.to_string())),
+            "state" => utils::parse_name(ChargeState::NAMES, "battery state", value)
+                .map(BatteryMatches::State),
+            variant => unreachable!("variant {} is in the table but wasn't handled", variant),
+        }
+    }
+}
+
+/// A battery which the regulator evaluates on its own, as an entry of `[[battery.targets]]`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BatteryTarget {
+    pub matches: FromStrDeserializer<DisplaySerializer<BatteryMatches>>,
+
+    /// The threshold of this battery, instead of the one of the regulator.
+    pub threshold: Option<BatteryLevel>,
+
+    /// The lower threshold of this battery, instead of the one of the regulator.
+    pub lower_threshold: Option<BatteryLevel>,
+
+    /// How often this battery needs to be checked. The regulator checks as often as the target
+    /// with the shortest cooldown needs.
+    pub cooldown: Option<HumanDuration>,
+}
+
+fn deserialize_targets<'de, D>(deserializer: D) -> Result<Option<Vec<BatteryTarget>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let targets = Vec::<BatteryTarget>::deserialize(deserializer)?;
+
+    if targets.is_empty() {
+        return Err(serde::de::Error::custom(
+            "there must be at least one target if `targets` is given",
+        ));
+    }
+
+    Ok(Some(targets))
+}
+
+/// Deserializes `battery.matches` from the same spellings as `--matches`, such as `"index=1"`,
+/// or from the tables it was written as before, such as `{ Index = 1 }`.
+fn deserialize_battery_matches<'de, D>(deserializer: D) -> Result<Option<BatteryMatches>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct BatteryMatchesVisitor;
+
+    impl<'de> Visitor<'de> for BatteryMatchesVisitor {
+        type Value = BatteryMatches;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a battery such as `\"first\"` or `\"index=1\"`")
+        }
+
+        fn visit_str<E: de::Error>(self, matches: &str) -> Result<Self::Value, E> {
+            match matches {
+                "First" => Ok(BatteryMatches::First),
+                "All" => Ok(BatteryMatches::All),
+                _ => matches
+                    .parse()
+                    .map_err(|error: anyhow::Error| E::custom(format!("{:#}", error))),
             }
-            (variant, value) => anyhow::bail!(
-                "unknown variant {} with value {} passed",
-                variant.bold(),
-                value.bold()
-            ),
+        }
+
+        fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+            BatteryMatches::deserialize(de::value::MapAccessDeserializer::new(map))
         }
     }
+
+    deserializer
+        .deserialize_any(BatteryMatchesVisitor)
+        .map(Some)
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct BatteryConfig {
+    #[serde(default, deserialize_with = "deserialize_battery_matches")]
     pub matches: Option<BatteryMatches>,
     pub infallible: bool,
-    pub threshold: Option<FromStrDeserializer<DisplaySerializer<BatteryLevel>>>,
-    pub cooldown: Option<FromStrDeserializer<DisplaySerializer<CoolDown>>>,
+
+    /// Only enable battery conservation mode while plugged into AC.
+    #[serde(default)]
+    pub only_on_ac: bool,
+
+    /// Send a desktop notification whenever battery conservation mode is toggled.
+    #[serde(default)]
+    pub notify: bool,
+
+    /// What to do with battery conservation mode when the regulator exits.
+    pub on_exit: Option<OnExit>,
+
+    /// Where to listen for changes to the batteries, which are evaluated right away instead of
+    /// after the cooldown.
+    pub listen: Option<Listen>,
+
+    /// How the levels of the batteries are combined when `matches` is `all`.
+    pub aggregate: Option<Aggregate>,
+    pub threshold: Option<BatteryLevel>,
+
+    /// The battery level at or below which battery conservation mode is disabled again, so that
+    /// it doesn't flip-flop around the threshold. Without it, battery conservation mode is
+    /// disabled as soon as the battery level drops below the threshold.
+    pub lower_threshold: Option<BatteryLevel>,
+    pub cooldown: Option<HumanDuration>,
+
+    /// How much each cooldown may randomly deviate by, in either direction.
+    pub cooldown_jitter: Option<HumanDuration>,
+
+    /// The minimum time between toggles of battery conservation mode, regardless of the
+    /// cooldown.
+    pub min_toggle_interval: Option<HumanDuration>,
+
+    /// Wait for half as long as the battery is estimated to take to reach the threshold instead
+    /// of the whole cooldown, if that is shorter.
+    #[serde(default)]
+    pub adaptive_cooldown: bool,
+
+    /// The shortest an adaptive cooldown may be.
+    pub adaptive_cooldown_floor: Option<HumanDuration>,
+
+    /// The longest an adaptive cooldown may be.
+    pub adaptive_cooldown_ceiling: Option<HumanDuration>,
+
+    /// A file the regulator also writes its logs to.
+    pub log_file: Option<PathBuf>,
+
+    /// How large the log file may grow, in megabytes, before it is rotated. 0 never rotates it.
+    pub log_file_size: Option<u64>,
+
+    /// How many rotated log files are kept.
+    pub log_file_count: Option<u32>,
+
+    /// How far below the threshold, in percent, the battery level has to stall before the
+    /// regulator warns that battery conservation mode may be capping the charge.
+    pub stall_margin: Option<u8>,
+
+    /// How many evaluations in a row the battery level has to stall for before the regulator
+    /// warns about it.
+    pub stall_patience: Option<u32>,
+
+    /// Batteries which are regulated together, each with their own threshold and cooldown.
+    /// Replaces `matches` when given.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_targets",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub targets: Option<Vec<BatteryTarget>>,
 }
 
 impl BatteryConfig {
     pub const DEFAULT: Self = Self {
         matches: None,
         infallible: false,
+        only_on_ac: false,
+        notify: false,
+        on_exit: None,
+        listen: None,
+        aggregate: None,
         threshold: None,
+        lower_threshold: None,
         cooldown: None,
+        cooldown_jitter: None,
+        min_toggle_interval: None,
+        adaptive_cooldown: false,
+        adaptive_cooldown_floor: None,
+        adaptive_cooldown_ceiling: None,
+        log_file: None,
+        log_file_size: None,
+        log_file_count: None,
+        stall_margin: None,
+        stall_patience: None,
+        targets: None,
     };
+    pub const DEFAULT_COOLDOWN: HumanDuration = HumanDuration::from_secs(60);
+    pub const DEFAULT_COOLDOWN_JITTER: HumanDuration = HumanDuration::ZERO;
+    pub const DEFAULT_MIN_TOGGLE_INTERVAL: HumanDuration = HumanDuration::from_secs(30);
+    pub const DEFAULT_ADAPTIVE_COOLDOWN_FLOOR: HumanDuration = HumanDuration::from_secs(10);
+    pub const DEFAULT_ADAPTIVE_COOLDOWN_CEILING: HumanDuration = HumanDuration::from_secs(3600);
+    pub const DEFAULT_LOG_FILE_SIZE: u64 = 10;
+    pub const DEFAULT_LOG_FILE_COUNT: u32 = 5;
+    pub const DEFAULT_STALL_MARGIN: u8 = 5;
+    pub const DEFAULT_STALL_PATIENCE: u32 = 10;
 
     pub fn matches(&self) -> Cow<BatteryMatches> {
         self.matches
@@ -470,54 +1338,263 @@ impl BatteryConfig {
             .unwrap_or_else(|| Cow::Owned(BatteryMatches::First))
     }
 
+    pub fn aggregate(&self) -> Aggregate {
+        self.aggregate.unwrap_or(Aggregate::Avg)
+    }
+
+    pub fn on_exit(&self) -> OnExit {
+        self.on_exit.unwrap_or(OnExit::Enable)
+    }
+
     pub fn threshold(&self) -> BatteryLevel {
-        self.threshold
-            .map(|threshold| threshold.0 .0)
-            .unwrap_or(BatteryLevel::DEFAULT)
+        self.threshold.unwrap_or(BatteryLevel::DEFAULT)
+    }
+
+    pub fn lower_threshold(&self) -> Option<BatteryLevel> {
+        self.lower_threshold
+    }
+
+    pub fn cooldown(&self) -> HumanDuration {
+        self.cooldown.unwrap_or(Self::DEFAULT_COOLDOWN)
+    }
+
+    pub fn cooldown_jitter(&self) -> HumanDuration {
+        self.cooldown_jitter
+            .unwrap_or(Self::DEFAULT_COOLDOWN_JITTER)
+    }
+
+    pub fn min_toggle_interval(&self) -> HumanDuration {
+        self.min_toggle_interval
+            .unwrap_or(Self::DEFAULT_MIN_TOGGLE_INTERVAL)
+    }
+
+    pub fn adaptive_cooldown_floor(&self) -> HumanDuration {
+        self.adaptive_cooldown_floor
+            .unwrap_or(Self::DEFAULT_ADAPTIVE_COOLDOWN_FLOOR)
+    }
+
+    pub fn adaptive_cooldown_ceiling(&self) -> HumanDuration {
+        self.adaptive_cooldown_ceiling
+            .unwrap_or(Self::DEFAULT_ADAPTIVE_COOLDOWN_CEILING)
+    }
+
+    /// The size the log file is rotated at, in bytes.
+    pub fn log_file_size(&self) -> u64 {
+        self.log_file_size.unwrap_or(Self::DEFAULT_LOG_FILE_SIZE) * 1024 * 1024
+    }
+
+    pub fn log_file_count(&self) -> u32 {
+        self.log_file_count.unwrap_or(Self::DEFAULT_LOG_FILE_COUNT)
+    }
+
+    pub fn stall_margin(&self) -> u8 {
+        self.stall_margin.unwrap_or(Self::DEFAULT_STALL_MARGIN)
+    }
+
+    pub fn stall_patience(&self) -> u32 {
+        self.stall_patience.unwrap_or(Self::DEFAULT_STALL_PATIENCE)
     }
 
-    pub fn cooldown(&self) -> CoolDown {
-        self.cooldown
-            .map(|cooldown| cooldown.0 .0)
-            .unwrap_or(CoolDown::DEFAULT)
+    /// Like [`Self::get`], but fails with a [`NoBatteryError`] if no battery matched.
+    #[cfg(feature = "regulate")]
+    pub fn require(&self) -> anyhow::Result<(Battery, Vec<anyhow::Error>)> {
+        self.require_matching(&self.matches())
     }
 
+    #[cfg(feature = "regulate")]
     pub fn get(&self) -> anyhow::Result<(Option<Battery>, Vec<anyhow::Error>)> {
+        let (batteries, errors) = self.find(&self.matches())?;
+
+        Ok((batteries.into_iter().next(), errors))
+    }
+
+    /// Like [`Self::require`], but finds the battery `matches` matches instead, which is the
+    /// first one for [`BatteryMatches::All`].
+    #[cfg(feature = "regulate")]
+    pub fn require_matching(
+        &self,
+        matches: &BatteryMatches,
+    ) -> anyhow::Result<(Battery, Vec<anyhow::Error>)> {
+        let (batteries, errors) = self.require_all_matching(matches)?;
+        let battery = batteries
+            .into_iter()
+            .next()
+            .expect("at least one battery is required");
+
+        Ok((battery, errors))
+    }
+
+    /// Like [`Self::require_matching`], but returns every battery `matches` matches.
+    #[cfg(feature = "regulate")]
+    pub fn require_all_matching(
+        &self,
+        matches: &BatteryMatches,
+    ) -> anyhow::Result<(Vec<Battery>, Vec<anyhow::Error>)> {
+        let (batteries, errors) = self.find(matches)?;
+
+        Ok((matches.require(batteries)?, errors))
+    }
+
+    #[cfg(feature = "regulate")]
+    fn find(&self, matches: &BatteryMatches) -> anyhow::Result<(Vec<Battery>, Vec<anyhow::Error>)> {
         debug!("create battery manager");
         let manager = battery::Manager::new().context("failed to create battery manager")?;
 
         debug!("create battery iterator");
-        let mut batteries = manager.batteries().context("failed to get batteries")?;
-        let matches = self.matches();
+        let batteries = manager.batteries().context("failed to get batteries")?;
 
-        let (battery, errors) = if self.infallible {
-            matches.find_infallible(&mut batteries)
+        let (found, errors) = if self.infallible {
+            matches.find_infallible(batteries)
         } else {
-            let battery = matches
-                .find(&mut batteries)
-                .context("failed to find battery")?;
+            let found = matches.find(batteries).context("failed to find battery")?;
 
-            (battery, Vec::new())
+            (found, Vec::new())
         };
 
-        Ok((battery, errors))
+        Ok((found, errors))
     }
 }
 
+/// Fails if there isn't any battery, for actions which can't do anything without one.
+#[cfg(feature = "regulate")]
+pub fn ensure_battery() -> anyhow::Result<()> {
+    let manager = battery::Manager::new().context("failed to create battery manager")?;
+    let mut batteries = manager.batteries().context("failed to get batteries")?;
+
+    anyhow::ensure!(batteries.next().is_some(), "no battery was found");
+
+    Ok(())
+}
+
 impl Default for BatteryConfig {
     fn default() -> Self {
         Self::DEFAULT
     }
 }
 
+#[derive(Serialize, Deserialize, Default, Copy, Clone)]
+pub struct DesiredState {
+    pub battery_conservation: Option<bool>,
+    pub rapid_charge: Option<bool>,
+
+    #[serde(default, deserialize_with = "deserialize_system_performance_mode")]
+    pub system_performance: Option<SystemPerformanceMode>,
+}
+
+fn deserialize_system_performance_mode<'de, D>(
+    deserializer: D,
+) -> Result<Option<SystemPerformanceMode>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    utils::deserialize_optional_name(
+        deserializer,
+        FromStrSystemPerformanceMode::NAMES,
+        "system performance mode",
+    )
+}
+
+impl DesiredState {
+    pub const DEFAULT: Self = Self {
+        battery_conservation: None,
+        rapid_charge: None,
+        system_performance: None,
+    };
+
+    /// Fills in the values missing from `self` with the ones from `other`.
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            battery_conservation: self.battery_conservation.or(other.battery_conservation),
+            rapid_charge: self.rapid_charge.or(other.rapid_charge),
+            system_performance: self.system_performance.or(other.system_performance),
+        }
+    }
+}
+
+/// What caused the desired state to be applied, which selects the policy table to use.
+#[derive(Debug, Copy, Clone)]
+pub enum Trigger {
+    Ac,
+    Battery,
+    Boot,
+}
+
+impl FromStr for Trigger {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ac" | "on-ac" | "on_ac" => Ok(Self::Ac),
+            "battery" | "on-battery" | "on_battery" => Ok(Self::Battery),
+            "boot" | "on-boot" | "on_boot" => Ok(Self::Boot),
+            _ => anyhow::bail!("invalid trigger {}", s.bold()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProfilesConfig {
+    /// The names of the built-in profiles to hide everywhere, such as when a patched copy of one
+    /// is used instead.
+    #[serde(default)]
+    pub disabled_builtins: Vec<String>,
+}
+
+impl ProfilesConfig {
+    pub const DEFAULT: Self = Self {
+        disabled_builtins: Vec::new(),
+    };
+}
+
+impl Default for ProfilesConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct TuxVantage {
     pub profile: Option<String>,
     pub machine: Option<Machine>,
 
+    /// The version of the shape of the machine output, see `--machine-version`.
+    pub machine_version: Option<crate::machine::Version>,
+    pub tips: Option<Tips>,
+
+    /// What to do with errors from dropping something which couldn't be reported as usual.
+    pub drop_fallback: Option<DropFallback>,
+
+    /// The arguments to run when `tuxvantage` is run without a subcommand, such as `bc enabled`.
+    pub default_command: Option<String>,
+
     #[serde(default)]
     pub panic: bool,
 
+    #[serde(default)]
+    pub read_only: bool,
+
+    /// Never page long output, such as the list of profiles.
+    #[serde(default)]
+    pub no_pager: bool,
+
+    /// Load the `acpi_call` kernel module and retry once if it isn't loaded, when running as
+    /// root.
+    #[serde(default)]
+    pub auto_modprobe: bool,
+
+    /// How long after changing a setting the firmware may ignore another change of it. Changes
+    /// within it are warned about, or waited out with `respect_ec_cooldown`.
+    pub ec_cooldown: Option<HumanDuration>,
+
+    /// Wait out `ec_cooldown` instead of only warning about it.
+    #[serde(default)]
+    pub respect_ec_cooldown: bool,
+
+    /// Templates which replace the human output of commands, by the name of the command such as
+    /// `status`. See `tuxvantage templates fields` for the placeholders of each command.
+    #[serde(default)]
+    pub templates: BTreeMap<String, String>,
+
     #[serde(default)]
     pub handlers: Handlers,
 
@@ -527,6 +1604,21 @@ pub struct TuxVantage {
     #[serde(default)]
     pub battery: BatteryConfig,
 
+    #[serde(default)]
+    pub profiles: ProfilesConfig,
+
+    #[serde(default)]
+    pub desired: DesiredState,
+
+    #[serde(default)]
+    pub on_ac: DesiredState,
+
+    #[serde(default)]
+    pub on_battery: DesiredState,
+
+    #[serde(default)]
+    pub on_boot: DesiredState,
+
     #[serde(skip)]
     pub overrides: Overrides,
 }
@@ -534,25 +1626,49 @@ pub struct TuxVantage {
 impl TuxVantage {
     pub const DEFAULT: Self = Self {
         profile: None,
+        tips: None,
+        drop_fallback: None,
+        default_command: None,
         handlers: Handlers::DEFAULT,
         panic: false,
+        read_only: false,
+        no_pager: false,
+        auto_modprobe: false,
+        ec_cooldown: None,
+        respect_ec_cooldown: false,
+        templates: BTreeMap::new(),
         machine: None,
+        machine_version: None,
         backtrace: Backtrace::DEFAULT,
         battery: BatteryConfig::DEFAULT,
+        profiles: ProfilesConfig::DEFAULT,
+        desired: DesiredState::DEFAULT,
+        on_ac: DesiredState::DEFAULT,
+        on_battery: DesiredState::DEFAULT,
+        on_boot: DesiredState::DEFAULT,
         overrides: Overrides::DEFAULT,
     };
 
     pub fn get() -> anyhow::Result<Self> {
-        project_paths::tuxvantage_toml()
-            .pipe(fs::read_to_string)
-            .with_context(|| format!("failed to read {}", "tuxvantage.toml".bold()))?
-            .pipe_deref(toml::from_str)
-            .with_context(|| {
-                format!(
-                    "failed to deserialize contents of {}",
-                    "tuxvantage.toml".bold()
-                )
-            })
+        let tuxvantage_toml = project_paths::tuxvantage_toml();
+
+        if read_only() && !tuxvantage_toml.exists() {
+            debug!(
+                "configuration is read-only and `tuxvantage.toml` doesn't exist, using defaults"
+            );
+            return Ok(Self::DEFAULT);
+        }
+
+        let contents = fs::read_to_string(tuxvantage_toml)
+            .with_context(|| format!("failed to read {}", "tuxvantage.toml".bold()))?;
+
+        Self::parse(tuxvantage_toml, &contents)
+    }
+
+    /// Deserializes `contents`, the contents of the `tuxvantage.toml` at `path`.
+    fn parse(path: &Path, contents: &str) -> anyhow::Result<Self> {
+        toml::from_str(contents)
+            .map_err(|error| InvalidConfigError::new(path, contents, error).into())
     }
 
     pub fn profile(&self) -> Option<&str> {
@@ -562,6 +1678,13 @@ impl TuxVantage {
             .or_else(|| self.profile.as_deref())
     }
 
+    pub fn machine_version(&self) -> crate::machine::Version {
+        self.overrides
+            .machine_version
+            .or(self.machine_version)
+            .unwrap_or_default()
+    }
+
     pub fn machine(&self) -> Machine {
         self.overrides.machine.unwrap_or_else(|| {
             debug!("no override for machine given, using config");
@@ -581,75 +1704,254 @@ impl TuxVantage {
                 .clone()
                 .or_else(|| self.battery.matches.clone()),
             infallible: self.overrides.battery.infallible || self.battery.infallible,
+            only_on_ac: self.overrides.battery.only_on_ac || self.battery.only_on_ac,
+            notify: self.overrides.battery.notify || self.battery.notify,
+            on_exit: self.overrides.battery.on_exit.or(self.battery.on_exit),
+            listen: self.overrides.battery.listen.or(self.battery.listen),
+            aggregate: self.overrides.battery.aggregate.or(self.battery.aggregate),
             threshold: self.overrides.battery.threshold.or(self.battery.threshold),
+            lower_threshold: self
+                .overrides
+                .battery
+                .lower_threshold
+                .or(self.battery.lower_threshold),
             cooldown: self.overrides.battery.cooldown.or(self.battery.cooldown),
+            cooldown_jitter: self
+                .overrides
+                .battery
+                .cooldown_jitter
+                .or(self.battery.cooldown_jitter),
+            min_toggle_interval: self
+                .overrides
+                .battery
+                .min_toggle_interval
+                .or(self.battery.min_toggle_interval),
+            adaptive_cooldown: self.overrides.battery.adaptive_cooldown
+                || self.battery.adaptive_cooldown,
+            adaptive_cooldown_floor: self
+                .overrides
+                .battery
+                .adaptive_cooldown_floor
+                .or(self.battery.adaptive_cooldown_floor),
+            adaptive_cooldown_ceiling: self
+                .overrides
+                .battery
+                .adaptive_cooldown_ceiling
+                .or(self.battery.adaptive_cooldown_ceiling),
+            log_file: self
+                .overrides
+                .battery
+                .log_file
+                .clone()
+                .or_else(|| self.battery.log_file.clone()),
+            log_file_size: self
+                .overrides
+                .battery
+                .log_file_size
+                .or(self.battery.log_file_size),
+            log_file_count: self
+                .overrides
+                .battery
+                .log_file_count
+                .or(self.battery.log_file_count),
+            stall_margin: self
+                .overrides
+                .battery
+                .stall_margin
+                .or(self.battery.stall_margin),
+            stall_patience: self
+                .overrides
+                .battery
+                .stall_patience
+                .or(self.battery.stall_patience),
+            targets: self
+                .overrides
+                .battery
+                .targets
+                .clone()
+                .or_else(|| self.battery.targets.clone()),
         }
     }
 
+    #[cfg(feature = "regulate")]
     pub fn battery(&self) -> anyhow::Result<(Option<Battery>, Vec<anyhow::Error>)> {
         self.battery_config().get()
     }
 
     pub fn panic(&self) -> bool {
-        self.overrides.panic || self.panic
+        self.overrides.panic.unwrap_or(self.panic)
+    }
+
+    /// Whether long output may be paged.
+    pub fn pager(&self) -> bool {
+        !(self.overrides.no_pager || self.no_pager)
+    }
+
+    pub fn auto_modprobe(&self) -> bool {
+        self.overrides.auto_modprobe || self.auto_modprobe
+    }
+
+    pub fn ec_cooldown(&self) -> HumanDuration {
+        self.ec_cooldown
+            .unwrap_or(crate::ec_cooldown::DEFAULT_GUARD)
+    }
+
+    pub fn respect_ec_cooldown(&self) -> bool {
+        self.overrides.respect_ec_cooldown || self.respect_ec_cooldown
+    }
+
+    pub fn template(&self, command: &str) -> Option<&str> {
+        self.templates.get(command).map(String::as_str)
+    }
+
+    pub fn tips(&self) -> Tips {
+        if self.overrides.no_tips {
+            Tips::Never
+        } else {
+            self.tips.unwrap_or_default()
+        }
+    }
+
+    pub fn drop_fallback(&self) -> DropFallback {
+        self.drop_fallback.unwrap_or_default()
     }
 
+    /// `--backtrace` if it was given, even if it turns everything off, or the config otherwise.
     pub fn backtrace(&self) -> Backtrace {
-        let panics = self.overrides.backtrace.panics || self.backtrace.panics;
-        let errors = self.overrides.backtrace.errors || self.backtrace.errors;
+        self.overrides.backtrace.unwrap_or(self.backtrace)
+    }
 
-        Backtrace { panics, errors }
+    /// Resolves the handler of `mode`, keeping track of every source it could have come from.
+    pub fn handler_resolution(&self, mode: HandlerMode) -> HandlerResolution {
+        let (overridden, configured) = match mode {
+            HandlerMode::BatteryConservation => (
+                self.overrides.handlers.battery_conservation,
+                self.handlers.battery_conservation,
+            ),
+            HandlerMode::RapidCharging => (
+                self.overrides.handlers.rapid_charging,
+                self.handlers.rapid_charging,
+            ),
+        };
+        let (positional, flag) = if self.overrides.handler_flag {
+            (None, overridden)
+        } else {
+            (overridden, None)
+        };
+
+        HandlerResolution::new([
+            (HandlerSource::Positional, positional),
+            (HandlerSource::Flag, flag),
+            (HandlerSource::GlobalFlag, self.overrides.handlers.default),
+            (HandlerSource::ModeConfig, configured),
+            (HandlerSource::DefaultConfig, self.handlers.default),
+            (HandlerSource::BuiltIn, Some(Handler::Switch)),
+        ])
     }
 
     pub fn handlers(&self) -> Handlers {
         let default = self.overrides.handlers.default.or(self.handlers.default);
         let battery_conservation = self
-            .overrides
-            .handlers
-            .battery_conservation
-            .or(self.handlers.battery_conservation);
+            .handler_resolution(HandlerMode::BatteryConservation)
+            .handler();
         let rapid_charging = self
-            .overrides
-            .handlers
-            .rapid_charging
-            .or(self.handlers.rapid_charging);
+            .handler_resolution(HandlerMode::RapidCharging)
+            .handler();
 
         Handlers {
             default,
-            battery_conservation,
-            rapid_charging,
+            battery_conservation: Some(battery_conservation),
+            rapid_charging: Some(rapid_charging),
         }
     }
 
+    /// The desired state to apply. Values from the policy table of `trigger` take precedence,
+    /// then the `remembered` values, then the `[desired]` table.
+    pub fn desired(&self, trigger: Option<Trigger>, remembered: DesiredState) -> DesiredState {
+        let policy = match trigger {
+            Some(Trigger::Ac) => self.on_ac,
+            Some(Trigger::Battery) => self.on_battery,
+            Some(Trigger::Boot) => self.on_boot,
+            None => DesiredState::DEFAULT,
+        };
+
+        policy.or(remembered).or(self.desired)
+    }
+
     pub fn dump(&self) -> anyhow::Result<()> {
+        ensure_writable()?;
+        let _lock = ConfigLock::acquire()?;
+
+        self.dump_locked()
+    }
+
+    fn dump_locked(&self) -> anyhow::Result<()> {
         let tuxvantage_toml = project_paths::tuxvantage_toml();
 
         let contents = self
             .pipe_ref(toml::to_string)
             .context("failed to serialize the config")?;
 
-        fs::write(tuxvantage_toml, contents)
+        utils::write_atomic(tuxvantage_toml, contents)
+            .map_err(|error| project_paths::write_error(tuxvantage_toml, error))
             .with_context(|| format!("failed to write to {}", "tuxvantage.toml".bold()))
     }
+
+    /// Applies `f` to the config as it currently is on disk then dumps it, all while holding the
+    /// configuration lock. The config in memory is replaced with the result, keeping the overrides.
+    pub fn mutate_then_dump<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> anyhow::Result<T> {
+        ensure_writable()?;
+        let _lock = ConfigLock::acquire()?;
+
+        let mut this = Self::get()?;
+        let result = f(&mut this);
+        this.dump_locked()?;
+
+        this.overrides = mem::take(&mut self.overrides);
+        *self = this;
+
+        Ok(result)
+    }
 }
 
-pub struct Profiles(pub Vec<ExternalProfile>);
+pub struct Profiles {
+    pub loaded: Vec<ExternalProfile>,
+
+    /// The profiles which couldn't be loaded, kept so that they can still be listed.
+    pub failed: Vec<FailedProfile>,
+
+    /// The names of the built-in profiles hidden by `profiles.disabled_builtins`.
+    pub disabled_built_ins: Vec<String>,
+}
 
 impl Profiles {
     pub fn get() -> anyhow::Result<(Self, Vec<anyhow::Error>)> {
         let mut errors = Vec::new();
-        let mut profiles = Vec::new();
+        let mut this = Self {
+            loaded: Vec::new(),
+            failed: Vec::new(),
+            disabled_built_ins: Vec::new(),
+        };
+
+        if read_only() && !project_paths::profiles_dir().exists() {
+            debug!("configuration is read-only and the profiles directory doesn't exist");
+            return Ok((this, errors));
+        }
 
-        for profile in
-            project_paths::profiles().context("failed to get handle to profiles directory")?
+        for profile in project_paths::profiles()
+            .context("failed to get handle to profiles directory")?
+            .load_all()
         {
             match profile {
-                Ok(profile) => profiles.push(profile),
-                Err(err) => errors.push(err),
+                Ok(profile) => this.loaded.push(profile),
+                Err(failed) => {
+                    errors.push(anyhow::anyhow!("{:#}", failed.error));
+                    this.failed.push(failed);
+                }
             }
         }
 
-        Ok((Self(profiles), errors))
+        Ok((this, errors))
     }
 
     pub fn find(&self, name: &str) -> Option<Profile> {
@@ -658,11 +1960,65 @@ impl Profiles {
             .find(|profile| profile.name == name)
     }
 
+    /// Finds the first profile which expects the product name of this machine, searching the
+    /// built-in profiles before the ones in the profiles directory. Unlike ideapad's detection,
+    /// the product name is normalized first.
+    pub fn detect(&self) -> anyhow::Result<Profile> {
+        let product_name =
+            product_name().context("failed to read the product name of this machine")?;
+
+        self.detect_for(product_name)
+    }
+
+    /// Like [`Profiles::detect`], but for a machine whose normalized product name is
+    /// `product_name`.
+    fn detect_for(&self, product_name: String) -> anyhow::Result<Profile> {
+        match self
+            .with_built_ins()
+            .find(|profile| profile.expects(&product_name))
+        {
+            Some(profile) => Ok(profile.get().deref().clone()),
+            None => Err(NoProfileError { product_name }.into()),
+        }
+    }
+
+    /// Whether `profile` expects the product name of this machine, or `None` if the product name
+    /// couldn't be read.
+    pub fn matches_machine(&self, profile: &Profile) -> Option<bool> {
+        match product_name() {
+            Ok(product_name) => Some(self.matches_product_name(profile, &product_name)),
+            Err(error) => {
+                debug!("failed to read the product name: {}", error);
+                None
+            }
+        }
+    }
+
+    /// Whether `profile` expects `product_name`, which is already normalized.
+    fn matches_product_name(&self, profile: &Profile, product_name: &str) -> bool {
+        // the declarations of the profile are only known if it was loaded, otherwise it is
+        // compared exactly
+        match self
+            .with_built_ins()
+            .find(|possibly_built_in| possibly_built_in.get().name == profile.name)
+        {
+            Some(possibly_built_in) => possibly_built_in.expects(product_name),
+            None => profile
+                .expected_product_names
+                .iter()
+                .any(|expected| normalize_product_name(expected) == product_name),
+        }
+    }
+
     pub fn with_built_ins(&self) -> impl Iterator<Item = PossiblyBuiltInProfile> + '_ {
-        [BuiltInProfile::Ideapad15IIL05, BuiltInProfile::Ideapad15Amd]
-            .into_iter()
+        BuiltInProfile::enabled(&self.disabled_built_ins)
             .map(PossiblyBuiltInProfile::BuiltIn)
-            .chain(self.0.iter().cloned().map(PossiblyBuiltInProfile::external))
+            .chain(
+                self.loaded
+                    .iter()
+                    .cloned()
+                    .map(PossiblyBuiltInProfile::external),
+            )
     }
 }
 
@@ -701,55 +2057,185 @@ possible modification from the end-user perhaps?", Self::COMMENT)
     }
 }
 
+fn consistency_version_1() -> u32 {
+    1
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Consistency {
     _comment: Comment,
+
+    /// The version of the format of `.consistency.json`. Files from before it was versioned are
+    /// version 1.
+    #[serde(default = "consistency_version_1")]
+    pub version: u32,
     pub last_exe: Option<PathBuf>,
 
     #[serde(default)]
     pub regulator_service_installed: bool,
+
+    /// The names of the services installed by this program, such as `bcm.service`.
+    #[serde(default)]
+    pub installed_services: Vec<String>,
+
+    /// Where the regulator service was installed, if `--service-name` or `--unit-dir` put it
+    /// somewhere other than `/etc/systemd/system/bcm.service`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regulator_unit: Option<PathBuf>,
+
+    /// The init system the regulator service was installed for, if it isn't systemd.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regulator_init_system: Option<InitSystem>,
 }
 
 impl Consistency {
+    pub const VERSION: u32 = 1;
     pub const DEFAULT: Self = Self {
         _comment: Comment { _priv: () },
+        version: Self::VERSION,
         last_exe: None,
         regulator_service_installed: false,
+        installed_services: Vec::new(),
+        regulator_unit: None,
+        regulator_init_system: None,
     };
 
-    pub fn get() -> anyhow::Result<Self> {
-        project_paths::consistency_json()
+    /// Reads `.consistency.json`. If it is corrupted or from a newer version of this program, the
+    /// defaults are used instead and the reason is returned alongside them, so that a broken file
+    /// doesn't stop this program from starting.
+    pub fn get() -> anyhow::Result<(Self, Option<anyhow::Error>)> {
+        let consistency_json = project_paths::consistency_json();
+
+        if read_only() && !consistency_json.exists() {
+            debug!(
+                "configuration is read-only and `.consistency.json` doesn't exist, using defaults"
+            );
+            return Ok((Self::DEFAULT, None));
+        }
+
+        let contents = consistency_json
             .pipe(fs::read_to_string)
             .with_context(|| format!("failed to read {}", ".consistency.json".bold()))?
-            .tap(|s| debug!("contents of `.consistency.json`: \n{}", s))
-            .pipe_deref(serde_json::from_str)
-            .with_context(|| {
-                format!(
-                    "failed to deserialize contents of {}",
-                    ".consistency.json".bold()
-                )
-            })
+            .tap(|s| debug!("contents of `.consistency.json`: \n{}", s));
+        let error = match serde_json::from_str::<Self>(&contents) {
+            Ok(this) if this.version <= Self::VERSION => return Ok((this, None)),
+            Ok(this) => anyhow::anyhow!(
+                "{} is version {}, but only up to version {} is understood",
+                ".consistency.json".bold(),
+                this.version,
+                Self::VERSION
+            ),
+            Err(error) => anyhow::Error::new(error).context(format!(
+                "failed to deserialize contents of {}",
+                ".consistency.json".bold()
+            )),
+        };
+
+        Ok((
+            Self::DEFAULT,
+            Some(error.context(format!(
+                "{} was reset to the defaults",
+                ".consistency.json".bold()
+            ))),
+        ))
     }
 
     pub fn dump(&self) -> anyhow::Result<()> {
+        ensure_writable()?;
+        let _lock = ConfigLock::acquire()?;
+
+        self.dump_locked()
+    }
+
+    fn dump_locked(&self) -> anyhow::Result<()> {
         let contents = self
             .pipe_ref(serde_json::to_string)
             .context("failed to serialize the consistency config")?;
 
-        fs::write(project_paths::consistency_json(), contents)
+        let consistency_json = project_paths::consistency_json();
+
+        utils::write_atomic(consistency_json, contents)
+            .map_err(|error| project_paths::write_error(consistency_json, error))
             .with_context(|| format!("failed to write to {}", ".consistency.json".bold()))
     }
 
+    /// Replaces `.consistency.json` with the defaults.
+    pub fn reset(&mut self) -> anyhow::Result<()> {
+        self.mutate_then_dump(|this| *this = Self::DEFAULT)
+    }
+
+    /// Applies `f` to the consistency config as it currently is on disk then dumps it, all while
+    /// holding the configuration lock. The consistency config in memory is replaced with the
+    /// result.
     pub fn mutate_then_dump<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> anyhow::Result<T> {
-        let result = f(self);
+        ensure_writable()?;
+        let _lock = ConfigLock::acquire()?;
+
+        let (mut this, error) = Self::get()?;
+
+        if let Some(error) = error {
+            debug!("overwriting the corrupted consistency config: {:#}", error);
+        }
 
-        self.dump()?;
+        let result = f(&mut this);
+        this.dump_locked()?;
+        *self = this;
 
         Ok(result)
     }
+
+    /// Compares the executable which installed the regulator service with `current_exe`,
+    /// remembering `current_exe` if there is none yet.
+    pub fn check_last_exe(&mut self, current_exe: PathBuf) -> LastExe {
+        match &self.last_exe {
+            Some(last_exe) if self.regulator_service_installed && last_exe != &current_exe => {
+                LastExe::Moved(last_exe.clone())
+            }
+            Some(_) => LastExe::Matches,
+            None if read_only() => {
+                debug!("no last exe found, but the configuration is read-only");
+                LastExe::Unknown
+            }
+            None => {
+                debug!("no last exe found, remembering the current exe");
+                let result = self.mutate_then_dump(|consistency| {
+                    // another run may have remembered it since this one read it
+                    consistency.last_exe.get_or_insert(current_exe);
+                });
+
+                // only used to warn about a moved executable later, so not worth failing the
+                // command over
+                match result {
+                    Ok(()) => LastExe::Remembered,
+                    Err(error) => {
+                        debug!("failed to remember the current exe: {:#}", error);
+                        LastExe::Unknown
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// What [`Consistency::check_last_exe`] found.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LastExe {
+    /// There was no last executable, so the current one was remembered.
+    Remembered,
+
+    /// There was no last executable, and the current one couldn't be remembered.
+    Unknown,
+
+    /// The last executable is the current one, or it doesn't matter since the regulator service
+    /// isn't installed.
+    Matches,
+
+    /// The regulator service was installed by this executable, which isn't the current one.
+    Moved(PathBuf),
 }
 
 static CONFIG: OnceCell<RwLock<Config>> = OnceCell::new();
+static RECOVERABLE_ERRORS: OnceCell<Vec<String>> = OnceCell::new();
 
 pub struct Config {
     pub tuxvantage: TuxVantage,
@@ -758,7 +2244,29 @@ pub struct Config {
 }
 
 impl Config {
-    pub fn ensure_exists() -> anyhow::Result<()> {
+    pub fn ensure_exists() -> anyhow::Result<Vec<anyhow::Error>> {
+        if read_only() {
+            debug!("configuration is read-only, not ensuring that the config exists");
+            EXISTENCE_ENSURED.store(true, Ordering::SeqCst);
+            return Ok(Vec::new());
+        }
+
+        match Self::try_ensure_exists() {
+            Ok(()) => Ok(Vec::new()),
+            Err(error) if is_read_only_error(&error) => {
+                debug!("configuration is not writable, degrading to read-only mode");
+                set_read_only(true);
+                EXISTENCE_ENSURED.store(true, Ordering::SeqCst);
+
+                Ok(vec![error.context(
+                    "the configuration is not writable, continuing in read-only mode",
+                )])
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    fn try_ensure_exists() -> anyhow::Result<()> {
         debug!("ensure that the config exists");
 
         debug!("try create config directory");
@@ -779,33 +2287,42 @@ impl Config {
             tuxvantage_toml.display()
         );
 
-        if !tuxvantage_toml.exists() {
-            debug!("serialize default `tuxvantage.toml`");
-            let contents = TuxVantage::DEFAULT
+        write_default(tuxvantage_toml, "tuxvantage.toml", || {
+            TuxVantage::DEFAULT
                 .pipe_ref(toml::to_string)
-                .expect("failed to serialize the default config");
+                .expect("failed to serialize the default config")
+        })?;
 
-            debug!("write default `tuxvantage.toml` to path");
-            fs::write(tuxvantage_toml, contents)
-                .with_context(|| format!("failed to write to {}", "tuxvantage.toml".bold()))?;
-        }
+        debug!("try create state directory");
+        project_paths::ensure_state_dir()?
+            .pipe(|path| debug!("state directory is in path '{}'", path.display()));
 
         let consistency_json = project_paths::consistency_json();
+        let legacy_consistency_json = project_paths::legacy_consistency_json();
+
+        if !consistency_json.exists() && legacy_consistency_json.exists() {
+            debug!(
+                "migrate legacy `.consistency.json` from '{}'",
+                legacy_consistency_json.display()
+            );
+            utils::move_file(legacy_consistency_json, consistency_json).with_context(|| {
+                format!(
+                    "failed to move {} into the state directory",
+                    ".consistency.json".bold()
+                )
+            })?;
+        }
+
         debug!(
             "`.consistency.json` exists in '{}'",
             consistency_json.display()
         );
 
-        if !consistency_json.exists() {
-            debug!("serialize default `.consistency.json`");
-            let contents = Consistency::DEFAULT
+        write_default(consistency_json, ".consistency.json", || {
+            Consistency::DEFAULT
                 .pipe_ref(serde_json::to_string)
-                .expect("failed to serialize the default consistency configuration");
-
-            debug!("write default `.consistency.json` to path");
-            fs::write(consistency_json, contents)
-                .with_context(|| format!("failed to write to {}", ".consistency.json".bold()))?;
-        }
+                .expect("failed to serialize the default consistency configuration")
+        })?;
 
         EXISTENCE_ENSURED.store(true, Ordering::SeqCst);
         debug!("existence was ensured");
@@ -813,28 +2330,50 @@ impl Config {
         Ok(())
     }
 
-    pub fn get() -> anyhow::Result<(Self, Vec<anyhow::Error>)> {
+    /// Gets the configuration, with `tuxvantage` giving the contents of `tuxvantage.toml`.
+    pub fn get(
+        tuxvantage: impl FnOnce() -> anyhow::Result<TuxVantage>,
+    ) -> anyhow::Result<(Self, Vec<anyhow::Error>)> {
+        let mut errors = Vec::new();
+
         if !EXISTENCE_ENSURED.load(Ordering::SeqCst) {
-            Self::ensure_exists()?;
+            errors.extend(Self::ensure_exists()?);
         }
-        let (profiles, errors) = Profiles::get().context("failed to get profiles")?;
+        let (mut profiles, profile_errors) = Profiles::get().context("failed to get profiles")?;
+        errors.extend(profile_errors);
+
+        let tuxvantage =
+            tuxvantage().with_context(|| format!("failed to get {}", "tuxvantage.toml".bold()))?;
+
+        if tuxvantage.read_only {
+            debug!("read-only mode enabled from the config");
+            set_read_only(true);
+        }
+
+        profiles.disabled_built_ins = tuxvantage.profiles.disabled_builtins.clone();
+
+        let (consistency, consistency_error) = Consistency::get()
+            .with_context(|| format!("failed to get {}", ".consistency.json".bold()))?;
+        errors.extend(consistency_error);
 
         Ok((
             Self {
-                tuxvantage: TuxVantage::get()
-                    .with_context(|| format!("failed to get {}", "tuxvantage.toml".bold()))?,
-                consistency: Consistency::get()
-                    .with_context(|| format!("failed to get {}", ".consistency.json".bold()))?,
+                tuxvantage,
+                consistency,
                 profiles,
             },
             errors,
         ))
     }
 
-    pub fn initialize() -> anyhow::Result<Vec<anyhow::Error>> {
+    pub fn initialize(
+        tuxvantage: impl FnOnce() -> anyhow::Result<TuxVantage>,
+    ) -> anyhow::Result<Vec<anyhow::Error>> {
         if CONFIG.get().is_none() {
-            let (this, errors) = Self::get()?;
+            let (this, errors) = Self::get(tuxvantage)?;
             let _ = CONFIG.set(RwLock::new(this));
+            let _ =
+                RECOVERABLE_ERRORS.set(errors.iter().map(|error| format!("{:#}", error)).collect());
             Ok(errors)
         } else {
             Ok(Vec::new())
@@ -855,27 +2394,650 @@ impl Config {
 
     pub fn default_profile(&self) -> Option<anyhow::Result<Profile>> {
         fn inner(this: &Config, profile: &str) -> anyhow::Result<Profile> {
-            this.profiles
-                .find(profile)
-                .with_context(|| format!("the default profile {} does not exist", profile.bold()))
+            if let Some(profile) = this.profiles.find(profile) {
+                return Ok(profile);
+            }
+
+            let disabled = BuiltInProfile::ALL.iter().any(|built_in| {
+                built_in.get().name == profile
+                    && built_in.is_disabled(&this.profiles.disabled_built_ins)
+            });
+
+            anyhow::ensure!(
+                !disabled,
+                "the default profile {} is a built-in profile, but it is disabled by {}",
+                profile.bold(),
+                "profiles.disabled_builtins".bold()
+            );
+            anyhow::bail!("the default profile {} does not exist", profile.bold())
         }
 
         Some(inner(self, self.tuxvantage.profile()?))
     }
 }
 
+/// The product name of this machine exactly as the firmware reports it.
+pub fn raw_product_name() -> io::Result<String> {
+    fs::read_to_string("/sys/class/dmi/id/product_name")
+}
+
+/// The product name of this machine, normalized with [`normalize_product_name`], as used to
+/// detect its profile.
+pub fn product_name() -> io::Result<String> {
+    raw_product_name().map(|name| normalize_product_name(&name).to_string())
+}
+
+/// Strips the whitespace and control characters around a product name, since some firmwares pad
+/// it with spaces or NULs.
+pub fn normalize_product_name(product_name: &str) -> &str {
+    product_name.trim_matches(|c: char| c.is_whitespace() || c.is_control())
+}
+
+/// Fails if the profile ideapad was initialized with declares `feature` as unsupported.
+pub fn ensure_supported(feature: Feature) -> anyhow::Result<()> {
+    let name = &context::get().profile.name;
+    let unsupported = read()
+        .profiles
+        .with_built_ins()
+        .find(|profile| profile.get().name == *name)
+        .map_or(false, |profile| profile.unsupported().contains(&feature));
+
+    if unsupported {
+        Err(UnsupportedError { feature }.into())
+    } else {
+        Ok(())
+    }
+}
+
 pub fn read() -> RwLockReadGuard<'static, Config> {
     Config::read()
 }
 
+/// Like [`read`], but `None` if the configuration hasn't been initialized yet.
+pub fn try_read() -> Option<RwLockReadGuard<'static, Config>> {
+    CONFIG.get().map(RwLock::read)
+}
+
 pub fn write() -> RwLockWriteGuard<'static, Config> {
     Config::write()
 }
 
 pub fn initialize() -> anyhow::Result<Vec<anyhow::Error>> {
-    Config::initialize()
+    Config::initialize(TuxVantage::get)
+}
+
+/// Initializes the configuration like `initialize`, but with the defaults instead of the contents
+/// of `tuxvantage.toml`, for actions which can run without it when it is broken.
+pub fn initialize_with_defaults() -> anyhow::Result<Vec<anyhow::Error>> {
+    Config::initialize(|| Ok(TuxVantage::DEFAULT))
 }
 
 pub fn machine() -> Machine {
     read().tuxvantage.machine()
 }
+
+/// The recoverable errors from initializing the configuration, which were only warned about.
+pub fn recoverable_errors() -> &'static [String] {
+    RECOVERABLE_ERRORS.get().map_or(&[], Vec::as_slice)
+}
+
+/// The `default_command` key of `tuxvantage.toml`. This is needed to parse the arguments, so it is
+/// read on its own before the configuration is initialized.
+pub fn default_command(config_dir_override: Option<PathBuf>) -> anyhow::Result<Option<String>> {
+    #[derive(Deserialize)]
+    struct DefaultCommand {
+        default_command: Option<String>,
+    }
+
+    let tuxvantage_toml = project_paths::tuxvantage_toml_before_initialize(config_dir_override)?;
+    let contents = match fs::read_to_string(tuxvantage_toml) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("failed to read {}", "tuxvantage.toml".bold()))
+        }
+    };
+
+    toml::from_str::<DefaultCommand>(&contents)
+        .map(|default_command| default_command.default_command)
+        .with_context(|| {
+            format!(
+                "failed to deserialize contents of {}",
+                "tuxvantage.toml".bold()
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "regulate")]
+    use crate::hardware::FakeBattery;
+    use crate::sandbox::Sandbox;
+
+    #[test]
+    fn panic_override_wins_over_the_config() {
+        let cases = [
+            (false, None, false),
+            (true, None, true),
+            (true, Some(false), false),
+            (false, Some(true), true),
+            (true, Some(true), true),
+            (false, Some(false), false),
+        ];
+
+        for (configured, overridden, expected) in cases {
+            let mut tuxvantage = TuxVantage {
+                panic: configured,
+                ..TuxVantage::DEFAULT
+            };
+            tuxvantage.overrides.panic = overridden;
+
+            assert_eq!(
+                tuxvantage.panic(),
+                expected,
+                "panic = {} in the config, {:?} from the command line",
+                configured,
+                overridden
+            );
+        }
+    }
+
+    #[test]
+    fn backtrace_override_wins_over_the_config() {
+        let on = Backtrace {
+            panics: true,
+            errors: true,
+        };
+        let off = Backtrace {
+            panics: false,
+            errors: false,
+        };
+        let mut tuxvantage = TuxVantage {
+            backtrace: on,
+            ..TuxVantage::DEFAULT
+        };
+
+        assert!(tuxvantage.backtrace().panics && tuxvantage.backtrace().errors);
+
+        tuxvantage.overrides.backtrace = Some(off);
+        assert!(!tuxvantage.backtrace().panics && !tuxvantage.backtrace().errors);
+
+        tuxvantage.backtrace = off;
+        tuxvantage.overrides.backtrace = Some(on);
+        assert!(tuxvantage.backtrace().panics && tuxvantage.backtrace().errors);
+    }
+
+    #[test]
+    fn backtrace_parses_both_toggles() {
+        let backtrace = "1,0".parse::<Backtrace>().unwrap();
+
+        assert!(backtrace.panics);
+        assert!(!backtrace.errors);
+        assert!("1".parse::<Backtrace>().is_err());
+        assert!("yes,no".parse::<Backtrace>().is_err());
+    }
+
+    /// An external profile expecting only the product name `expected`.
+    fn external_profile(expected: &str, case_insensitive: bool) -> PossiblyBuiltInProfile {
+        let mut profile = serde_json::to_value(Profile::IDEAPAD_15IIL05).unwrap();
+        profile["expected_product_names"] = serde_json::json!([expected]);
+
+        PossiblyBuiltInProfile::external(ExternalProfile {
+            profile: serde_json::from_value(profile).unwrap(),
+            declarations: Declarations {
+                case_insensitive_product_names: case_insensitive,
+                ..Declarations::default()
+            },
+            path: PathBuf::from("/profiles/test.json"),
+        })
+    }
+
+    #[test]
+    fn product_names_are_trimmed_of_padding_and_nuls() {
+        assert_eq!(normalize_product_name("  81YK  "), "81YK");
+        assert_eq!(normalize_product_name("81YK\0"), "81YK");
+        assert_eq!(normalize_product_name("\t81YK\n\0\0"), "81YK");
+        assert_eq!(normalize_product_name("\0 \n"), "");
+    }
+
+    #[test]
+    fn product_names_keep_their_case_and_inner_spaces() {
+        assert_eq!(normalize_product_name("81yk"), "81yk");
+        assert_eq!(
+            normalize_product_name(" IdeaPad 5 15IIL05\0"),
+            "IdeaPad 5 15IIL05"
+        );
+    }
+
+    #[test]
+    fn padded_expected_product_names_match() {
+        assert!(external_profile(" 81YK\0", false).expects("81YK"));
+        assert!(!external_profile(" 81YK\0", false).expects("81YM"));
+    }
+
+    #[test]
+    fn case_is_only_ignored_when_declared() {
+        assert!(!external_profile("81yk", false).expects("81YK"));
+        assert!(external_profile("81yk", true).expects("81YK"));
+    }
+
+    #[test]
+    fn built_in_profiles_match_exactly() {
+        let built_in = PossiblyBuiltInProfile::BuiltIn(BuiltInProfile::Ideapad15IIL05);
+        let expected = built_in.get().expected_product_names[0].to_string();
+
+        assert!(built_in.expects(&expected));
+        assert!(!built_in.expects(&expected.to_lowercase()));
+    }
+
+    #[test]
+    fn unknown_machines_have_no_profile() {
+        let mut profiles = Profiles {
+            loaded: Vec::new(),
+            failed: Vec::new(),
+            disabled_built_ins: Vec::new(),
+        };
+        let expected = BuiltInProfile::Ideapad15IIL05.get();
+        let product_name = expected.expected_product_names[0].to_string();
+
+        assert_eq!(
+            profiles.detect_for(product_name.clone()).unwrap().name,
+            expected.name
+        );
+
+        let error = profiles.detect_for("ThinkPad X1".to_string()).unwrap_err();
+        assert_eq!(crate::ext::error_code(&error), Some("no_profile"));
+        assert!(error.to_string().contains("ThinkPad X1"), "{}", error);
+
+        // a disabled built-in profile isn't detected either
+        profiles.disabled_built_ins = vec![expected.name.to_string()];
+        assert!(profiles
+            .detect_for(product_name)
+            .unwrap_err()
+            .is::<NoProfileError>());
+    }
+
+    #[test]
+    fn profiles_are_checked_with_their_declarations_when_loaded() {
+        let mut profiles = Profiles {
+            loaded: Vec::new(),
+            failed: Vec::new(),
+            disabled_built_ins: Vec::new(),
+        };
+        let built_in = BuiltInProfile::Ideapad15IIL05.get();
+        let product_name = normalize_product_name(&built_in.expected_product_names[0]);
+
+        assert!(profiles.matches_product_name(&built_in, product_name));
+        assert!(!profiles.matches_product_name(&built_in, "ThinkPad X1"));
+
+        let mut profile = serde_json::to_value(Profile::IDEAPAD_15IIL05).unwrap();
+        profile["name"] = "CUSTOM".into();
+        profile["expected_product_names"] = serde_json::json!(["81yk"]);
+        let profile = serde_json::from_value::<Profile>(profile).unwrap();
+        // unless it is loaded, the profile doesn't ignore case
+        assert!(!profiles.matches_product_name(&profile, "81YK"));
+        assert!(profiles.matches_product_name(&profile, "81yk"));
+
+        profiles.loaded.push(ExternalProfile {
+            profile: profile.clone(),
+            declarations: Declarations {
+                case_insensitive_product_names: true,
+                ..Declarations::default()
+            },
+            path: PathBuf::from("/profiles/custom.json"),
+        });
+        assert!(profiles.matches_product_name(&profile, "81YK"));
+    }
+
+    #[test]
+    fn concurrent_dumps_keep_each_others_changes() {
+        Sandbox::shared();
+        Config::ensure_exists().unwrap();
+
+        let threads = ["first", "second"].map(|name| {
+            thread::spawn(move || {
+                let mut tuxvantage = TuxVantage::get().unwrap();
+
+                for index in 0..20 {
+                    tuxvantage
+                        .mutate_then_dump(|tuxvantage| {
+                            tuxvantage
+                                .templates
+                                .insert(format!("{}-{}", name, index), String::new())
+                        })
+                        .unwrap();
+                }
+            })
+        });
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let templates = TuxVantage::get().unwrap().templates;
+
+        for name in ["first", "second"] {
+            for index in 0..20 {
+                assert!(templates.contains_key(&format!("{}-{}", name, index)));
+            }
+        }
+    }
+
+    #[test]
+    fn last_exe_is_remembered_once_and_compared_afterwards() {
+        Sandbox::shared();
+        Config::ensure_exists().unwrap();
+
+        let consistency_json = project_paths::consistency_json();
+        let current_exe = PathBuf::from("/usr/bin/tuxvantage");
+        let (mut consistency, _) = Consistency::get().unwrap();
+        consistency
+            .mutate_then_dump(|consistency| {
+                consistency.last_exe = None;
+                consistency.regulator_service_installed = false;
+            })
+            .unwrap();
+
+        // fresh state
+        assert_eq!(
+            consistency.check_last_exe(current_exe.clone()),
+            LastExe::Remembered
+        );
+        assert_eq!(
+            Consistency::get().unwrap().0.last_exe.as_ref(),
+            Some(&current_exe)
+        );
+
+        // matching state, which doesn't touch the file. the lock keeps the other tests from
+        // writing a default one in the meantime
+        let lock = ConfigLock::acquire().unwrap();
+        let moved_away = consistency_json.with_extension("json.moved");
+        fs::rename(consistency_json, &moved_away).unwrap();
+        let matches = consistency.check_last_exe(current_exe.clone());
+        let touched = consistency_json.exists();
+        fs::rename(&moved_away, consistency_json).unwrap();
+        drop(lock);
+
+        assert_eq!(matches, LastExe::Matches);
+        assert!(!touched);
+
+        // a different executable only matters once the regulator service is installed
+        let other_exe = PathBuf::from("/opt/tuxvantage/tuxvantage");
+        assert_eq!(
+            consistency.check_last_exe(other_exe.clone()),
+            LastExe::Matches
+        );
+
+        consistency.regulator_service_installed = true;
+        assert_eq!(
+            consistency.check_last_exe(other_exe),
+            LastExe::Moved(current_exe.clone())
+        );
+        assert_eq!(
+            consistency.check_last_exe(current_exe.clone()),
+            LastExe::Matches
+        );
+    }
+
+    /// The error of deserializing `contents` as `tuxvantage.toml`, without colors.
+    fn invalid_config(contents: &str) -> String {
+        let error = TuxVantage::parse(Path::new("/etc/tuxvantage/tuxvantage.toml"), contents)
+            .map(|_| ())
+            .expect_err("the config was deserialized");
+
+        crate::machine::strip_ansi(format!("{:#}", error))
+    }
+
+    #[test]
+    fn misspelled_handlers_point_at_their_location() {
+        let error = invalid_config("panic = false\n\n[handlers]\ndefault = \"swich\"\n");
+
+        assert!(
+            error.contains("/etc/tuxvantage/tuxvantage.toml at line 4, column 11:"),
+            "{}",
+            error
+        );
+        assert!(error.contains("handlers.default"), "{}", error);
+        assert!(error.contains("swich"), "{}", error);
+        assert!(error.contains("possible values are"), "{}", error);
+    }
+
+    #[test]
+    fn misspelled_modes_point_at_their_location() {
+        let error = invalid_config("[desired]\nsystem_performance = \"turbo\"\n");
+
+        assert!(error.contains("at line 2, column 22:"), "{}", error);
+        assert!(error.contains("desired.system_performance"), "{}", error);
+        assert!(error.contains("possible values are"), "{}", error);
+    }
+
+    #[test]
+    fn misspelled_machine_points_at_its_location() {
+        let error = invalid_config("\n\nmachine = \"sometimes\"\n");
+
+        assert!(error.contains("at line 3, column 11:"), "{}", error);
+        assert!(error.contains("key `machine`"), "{}", error);
+        assert!(error.contains("possible values are"), "{}", error);
+    }
+
+    #[test]
+    fn invalid_battery_matches_point_at_their_location() {
+        let error = invalid_config("[battery]\ninfallible = false\nmatches = \"serial_number\"\n");
+
+        assert!(error.contains("at line 3, column 11:"), "{}", error);
+        assert!(error.contains("battery.matches"), "{}", error);
+    }
+
+    #[test]
+    fn battery_matches_accept_both_spellings() {
+        for (matches, index) in [
+            ("\"index=1\"", Some(1)),
+            ("\"i=1\"", Some(1)),
+            ("{ Index = 1 }", Some(1)),
+            ("\"first\"", None),
+            ("\"First\"", None),
+        ] {
+            let config = toml::from_str::<TuxVantage>(&format!(
+                "[battery]\ninfallible = false\nmatches = {}\n",
+                matches
+            ))
+            .unwrap();
+
+            match (config.battery.matches, index) {
+                (Some(BatteryMatches::Index(found)), Some(index)) => assert_eq!(found, index),
+                (Some(BatteryMatches::First), None) => {}
+                (found, _) => panic!("{} was deserialized as {:?}", matches, found),
+            }
+        }
+    }
+
+    #[test]
+    fn errors_of_the_toml_crate_keep_their_location() {
+        let error = invalid_config("[battery]\ninfallible = \"no\"\n");
+
+        assert!(error.contains("at line 2, column"), "{}", error);
+        assert!(!error.contains("at line 2 column"), "{}", error);
+    }
+
+    #[test]
+    fn syntax_errors_point_at_their_location() {
+        let error = invalid_config("panic = false\n[handlers\n");
+
+        assert!(error.contains("at line 2, column"), "{}", error);
+    }
+
+    #[test]
+    fn command_line_spellings_are_accepted() {
+        let config = toml::from_str::<TuxVantage>(
+            "[handlers]\ndefault = \"ignore\"\nrapid_charging = \"Switch\"\n\n\
+             [desired]\nsystem_performance = \"bs\"\n",
+        )
+        .unwrap();
+
+        assert!(matches!(config.handlers.default, Some(Handler::Ignore)));
+        assert!(matches!(
+            config.handlers.rapid_charging,
+            Some(Handler::Switch)
+        ));
+        assert!(matches!(
+            config.desired.system_performance,
+            Some(SystemPerformanceMode::BatterySaving)
+        ));
+    }
+
+    #[test]
+    fn the_exit_action_defaults_to_enabling_conservation() {
+        let config = toml::from_str::<TuxVantage>("[battery]\ninfallible = false\n").unwrap();
+        assert_eq!(config.battery.on_exit(), OnExit::Enable);
+
+        let config =
+            toml::from_str::<TuxVantage>("[battery]\ninfallible = false\non_exit = \"restore\"\n")
+                .unwrap();
+        assert_eq!(config.battery.on_exit(), OnExit::Restore);
+    }
+
+    #[test]
+    fn only_restoring_depends_on_the_initial_state() {
+        for initially_enabled in [false, true] {
+            assert_eq!(OnExit::Keep.conservation(initially_enabled), None);
+            assert_eq!(OnExit::Enable.conservation(initially_enabled), Some(true));
+            assert_eq!(OnExit::Disable.conservation(initially_enabled), Some(false));
+            assert_eq!(
+                OnExit::Restore.conservation(initially_enabled),
+                Some(initially_enabled)
+            );
+        }
+    }
+
+    #[test]
+    fn aggregates_pick_the_lowest_and_highest_level() {
+        assert_eq!(Aggregate::Min.apply([80, 35, 60]), 35);
+        assert_eq!(Aggregate::Max.apply([80, 35, 60]), 80);
+    }
+
+    #[test]
+    fn averages_round_to_the_nearest_level() {
+        assert_eq!(Aggregate::Avg.apply([80, 35, 60]), 58);
+        assert_eq!(Aggregate::Avg.apply([60, 61, 61]), 61);
+        // halfway rounds up
+        assert_eq!(Aggregate::Avg.apply([50, 51]), 51);
+        assert_eq!(Aggregate::Avg.apply([100, 100]), 100);
+    }
+
+    #[test]
+    fn aggregates_of_no_levels_are_zero() {
+        for aggregate in [Aggregate::Min, Aggregate::Max, Aggregate::Avg] {
+            assert_eq!(aggregate.apply([]), 0, "{:?}", aggregate);
+        }
+    }
+
+    #[cfg(feature = "regulate")]
+    fn batteries(
+        fakes: &[FakeBattery],
+    ) -> impl Iterator<Item = Result<FakeBattery, fmt::Error>> + '_ {
+        fakes.iter().cloned().map(Ok)
+    }
+
+    /// A dock which shows up as a battery, followed by the two batteries of a laptop.
+    #[cfg(feature = "regulate")]
+    fn laptop() -> Vec<FakeBattery> {
+        vec![
+            FakeBattery {
+                vendor: Some("Dock".to_string()),
+                state: battery::State::Discharging,
+                design_capacity: 0.0,
+                ..FakeBattery::new()
+            },
+            FakeBattery {
+                vendor: Some("SMP".to_string()),
+                model: Some("L19M4PF0".to_string()),
+                serial_number: Some("1234".to_string()),
+                state: battery::State::Charging,
+                ..FakeBattery::new()
+            },
+            FakeBattery {
+                vendor: Some("LGC".to_string()),
+                model: Some("L19L4PF0".to_string()),
+                serial_number: Some("5678".to_string()),
+                state: battery::State::Full,
+                ..FakeBattery::new()
+            },
+        ]
+    }
+
+    #[cfg(feature = "regulate")]
+    #[test]
+    fn selectors_pick_the_matching_batteries() {
+        let laptop = laptop();
+        let cases: &[(&str, &[usize])] = &[
+            ("first", &[1]),
+            ("all", &[0, 1, 2]),
+            ("index=2", &[2]),
+            ("vendor=LGC", &[2]),
+            ("model=L19M4PF0", &[1]),
+            ("serial_number=5678", &[2]),
+            ("state=discharging", &[0]),
+            ("state=charging", &[1]),
+            ("state=full", &[2]),
+            ("index=3", &[]),
+            ("vendor=Nobody", &[]),
+        ];
+
+        for (selector, expected) in cases {
+            let matches = selector.parse::<BatteryMatches>().unwrap();
+            let expected = expected
+                .iter()
+                .map(|index| laptop[*index].clone())
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                matches.find(batteries(&laptop)).unwrap(),
+                expected,
+                "{}",
+                selector
+            );
+            assert_eq!(
+                matches.find_infallible(batteries(&laptop)).0,
+                expected,
+                "{}",
+                selector
+            );
+        }
+    }
+
+    #[cfg(feature = "regulate")]
+    #[test]
+    fn batteries_which_fail_are_only_skipped_when_infallible() {
+        let laptop = laptop();
+        let failing = || [Err(fmt::Error)].into_iter().chain(batteries(&laptop));
+
+        assert!(BatteryMatches::First.find(failing()).is_err());
+
+        let (found, errors) = BatteryMatches::First.find_infallible(failing());
+        assert_eq!(found, vec![laptop[1].clone()]);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[cfg(feature = "regulate")]
+    #[test]
+    fn no_battery_is_found_without_batteries() {
+        let none = std::iter::empty::<Result<FakeBattery, fmt::Error>>;
+
+        for matches in [
+            BatteryMatches::First,
+            BatteryMatches::All,
+            BatteryMatches::State(ChargeState::Full),
+        ] {
+            assert_eq!(matches.find(none()).unwrap(), Vec::new());
+
+            let (found, errors) = matches.find_infallible(none());
+            assert!(errors.is_empty());
+
+            let error = matches.require(found).unwrap_err();
+            assert!(error.is::<NoBatteryError>());
+            assert_eq!(crate::ext::error_code(&error), Some("no_battery"));
+        }
+    }
+}
@@ -1,18 +1,242 @@
+use crate::config::DropFallback;
+use crate::machine;
 use ideapad::context::Context as IdeapadContext;
 use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use std::fmt;
+use std::process;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::TryRecvError;
 use try_drop::drop_strategies::broadcast::NeedsReceivers;
 use try_drop::drop_strategies::{BroadcastDropStrategy, PanicDropStrategy};
+use try_drop::FallbackTryDropStrategy;
 
-pub type Context = IdeapadContext<BroadcastDropStrategy<NeedsReceivers>, PanicDropStrategy>;
+pub type Context = IdeapadContext<BroadcastDropStrategy<NeedsReceivers>, FallbackDropStrategy>;
 
 static CONTEXT: OnceCell<Context> = OnceCell::new();
+static ACTIVE_PROFILE: OnceCell<ActiveProfile> = OnceCell::new();
 
-pub fn initialize(context: Context) {
-    if CONTEXT.set(context).is_err() {
+/// Tells the drop error receiver thread to stop, and is disconnected once it has.
+static RECEIVER_THREAD: Mutex<Option<(SyncSender<()>, Receiver<()>)>> =
+    parking_lot::const_mutex(None);
+
+/// Spawns the drop error receiver thread, if that was held back until the regulator has forked.
+static DEFERRED_RECEIVER_THREAD: Mutex<Option<Box<dyn FnOnce() + Send>>> =
+    parking_lot::const_mutex(None);
+
+/// How often the drop error receiver thread checks for drop errors and whether to stop.
+const RECEIVER_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long to wait for the drop error receiver thread to report the errors it hasn't yet when
+/// this program exits.
+const RECEIVER_THREAD_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Where the profile ideapad was initialized with comes from.
+#[derive(Serialize, Debug, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileOrigin {
+    BuiltIn,
+    External,
+}
+
+/// How the profile ideapad was initialized with was picked.
+#[derive(Serialize, Debug, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileSelection {
+    /// Given on the command line, with `--profile` or by the command itself.
+    Override,
+
+    /// The default profile of `tuxvantage.toml`.
+    Default,
+
+    /// Detected from the product name of this machine.
+    AutoDetected,
+}
+
+/// The profile ideapad was initialized with, which is included in the machine output of commands
+/// which access the hardware.
+#[derive(Serialize, Debug, Clone)]
+pub struct ActiveProfile {
+    pub name: String,
+    pub origin: ProfileOrigin,
+    pub selection: ProfileSelection,
+}
+
+pub fn initialize(context: Context, active_profile: ActiveProfile) {
+    debug!(
+        "using the profile {} ({:?}, {:?})",
+        active_profile.name, active_profile.origin, active_profile.selection
+    );
+
+    if CONTEXT.set(context).is_err() || ACTIVE_PROFILE.set(active_profile).is_err() {
         panic!("context is already initialized")
     }
 }
 
+/// The profile ideapad was initialized with, if it was.
+pub fn active_profile() -> Option<&'static ActiveProfile> {
+    ACTIVE_PROFILE.get()
+}
+
 pub fn get() -> &'static Context {
     CONTEXT.get().expect("context is not initialized")
 }
+
+/// Like [`get`], but `None` if ideapad hasn't been initialized.
+pub fn try_get() -> Option<&'static Context> {
+    CONTEXT.get()
+}
+
+/// Reports an error which happened while dropping something. In machine mode, it is recorded
+/// into the warnings of the machine output instead of being printed.
+pub fn report_drop_error(error: impl fmt::Display + fmt::Debug) {
+    if machine::enabled() {
+        machine::push_warning(format!("failed to drop something: {}", error), None::<&str>);
+    } else {
+        error!("failed to drop something: {}", error);
+    }
+
+    debug!("debug representation of the drop error:\n {:#?}", error)
+}
+
+/// Reports that the drop error receiver thread fell behind and `count` drop errors were lost.
+pub fn report_lag(count: u64) {
+    let message = format!(
+        "drop strategy receiver thread lagged too far behind: {} skipped messages",
+        count
+    );
+
+    if machine::enabled() {
+        machine::push_warning(message, None::<&str>);
+    } else {
+        warn!("{}", message);
+    }
+}
+
+/// Handles the errors which couldn't be broadcast to the receiver thread, as chosen by
+/// `drop_fallback` in the config.
+pub struct FallbackDropStrategy(pub DropFallback);
+
+impl FallbackTryDropStrategy for FallbackDropStrategy {
+    fn handle_error_in_strategy(&self, error: anyhow::Error) {
+        match self.0 {
+            DropFallback::Panic => PanicDropStrategy::default().handle_error_in_strategy(error),
+            DropFallback::Log => report_drop_error(error),
+            DropFallback::Abort => {
+                report_drop_error(error);
+                process::abort()
+            }
+        }
+    }
+}
+
+/// Registers the drop error receiver thread, returning the receiver which tells it to stop and
+/// the sender it drops once it has.
+pub fn register_receiver_thread() -> (Receiver<()>, SyncSender<()>) {
+    let (stop_sender, stop_receiver) = mpsc::sync_channel(0);
+    let (stopped_sender, stopped_receiver) = mpsc::sync_channel(0);
+    *RECEIVER_THREAD.lock() = Some((stop_sender, stopped_receiver));
+
+    (stop_receiver, stopped_sender)
+}
+
+/// Reports the drop errors `receiver` receives until `stop` tells it to, and then the ones which
+/// were sent before that. `stopped` is dropped once it returns. This is what the drop error
+/// receiver thread runs, with the halves [`register_receiver_thread`] returned.
+pub fn receive_drop_errors<E>(
+    mut receiver: broadcast::Receiver<E>,
+    stop: Receiver<()>,
+    stopped: SyncSender<()>,
+) where
+    E: Clone + fmt::Display + fmt::Debug,
+{
+    debug!("start drop strategy receiver thread");
+
+    // dropped once this returns, which the main thread waits for
+    let _stopped = stopped;
+    let mut stopping = false;
+
+    loop {
+        match receiver.try_recv() {
+            Ok(error) => report_drop_error(error),
+            Err(TryRecvError::Lagged(count)) => report_lag(count),
+            // every drop error sent before stopping has been reported by now
+            Err(TryRecvError::Empty) if stopping => break,
+            Err(TryRecvError::Empty) => {
+                stopping = !matches!(
+                    stop.recv_timeout(RECEIVER_POLL_INTERVAL),
+                    Err(RecvTimeoutError::Timeout)
+                );
+            }
+            Err(TryRecvError::Closed) => break,
+        }
+    }
+}
+
+/// Holds back spawning the drop error receiver thread with `spawn` until
+/// [`spawn_deferred_receiver_thread`]. Only the forking thread carries over into a daemon, so a
+/// regulator started with `--daemonize` spawns it once it has forked.
+pub fn defer_receiver_thread(spawn: impl FnOnce() + Send + 'static) {
+    *DEFERRED_RECEIVER_THREAD.lock() = Some(Box::new(spawn));
+}
+
+/// Spawns the drop error receiver thread if it was held back.
+pub fn spawn_deferred_receiver_thread() {
+    let spawn = DEFERRED_RECEIVER_THREAD.lock().take();
+
+    if let Some(spawn) = spawn {
+        spawn()
+    }
+}
+
+/// Stops the drop error receiver thread if it was started, waiting a bounded time for it to
+/// report the errors it hasn't yet.
+pub fn stop_receiver_thread() {
+    // spawned anyway if the action returned before forking, so that its errors are reported
+    spawn_deferred_receiver_thread();
+
+    let (stop_sender, stopped_receiver) = match RECEIVER_THREAD.lock().take() {
+        Some(receiver_thread) => receiver_thread,
+        None => return,
+    };
+    drop(stop_sender);
+
+    match stopped_receiver.recv_timeout(RECEIVER_THREAD_TIMEOUT) {
+        Err(RecvTimeoutError::Timeout) => {
+            debug!("drop strategy receiver thread didn't stop in time")
+        }
+        Ok(()) | Err(RecvTimeoutError::Disconnected) => {
+            debug!("drop strategy receiver thread stopped")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::{Machine, Version};
+    use std::thread;
+
+    #[test]
+    fn drop_errors_end_up_in_the_machine_warnings() {
+        let (sender, receiver) = broadcast::channel(16);
+        let (stop, stopped) = register_receiver_thread();
+        machine::set(true);
+        sender
+            .send("the fake hardware is gone".to_string())
+            .unwrap();
+        thread::spawn(move || receive_drop_errors(receiver, stop, stopped));
+        stop_receiver_thread();
+        machine::set(false);
+
+        let envelope = Machine::Success(()).envelope(Version::DEFAULT);
+        let json = serde_json::to_value(&envelope).unwrap();
+
+        assert_eq!(
+            json["warnings"][0]["message"],
+            "failed to drop something: the fake hardware is gone"
+        );
+    }
+}
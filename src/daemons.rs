@@ -0,0 +1,410 @@
+use crate::app::battery_conservation::{self, HOLD_SERVICE};
+use crate::regulator::Status;
+use crate::{project_paths, utils};
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+use std::process::Command;
+use std::{env, fmt, fs, io, process, ptr};
+
+/// How a running regulator was detected.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Source {
+    /// The status file the regulator keeps in the runtime directory.
+    StatusFile,
+
+    /// The pid file of a regulator started with `--daemonize`.
+    PidFile,
+
+    /// The regulator service with this name is active according to systemd.
+    Service(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Regulator {
+    /// The process id of the regulator, if it is known.
+    pub pid: Option<u32>,
+    pub source: Source,
+}
+
+impl Regulator {
+    /// Whether this regulator is the current process.
+    pub fn is_current_process(&self) -> bool {
+        self.pid == Some(process::id())
+    }
+
+    /// How to stop this regulator.
+    pub fn stop_tip(&self) -> String {
+        match (&self.source, self.pid) {
+            (Source::Service(service), _) => format!(
+                "stop it by running `systemctl stop {}` as root, or pass `--force` to do this anyway",
+                service
+            ),
+            (Source::PidFile, _) => {
+                "stop it by running `tuxvantage battery-conservation regulate --stop`, or pass \
+                 `--force` to do this anyway"
+                    .to_string()
+            }
+            (Source::StatusFile, Some(pid)) => format!(
+                "stop it by running `kill {}`, or pass `--force` to do this anyway",
+                pid
+            ),
+            (Source::StatusFile, None) => {
+                "stop it, or pass `--force` to do this anyway".to_string()
+            }
+        }
+    }
+}
+
+impl fmt::Display for Regulator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Source::Service(service) => write!(f, "the regulator service {}", service.bold())?,
+            Source::StatusFile => write!(f, "a regulator")?,
+            Source::PidFile => write!(f, "a daemonized regulator")?,
+        }
+
+        if let Some(pid) = self.pid {
+            write!(f, " (pid {})", pid.bold())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether a process with the id `pid` exists.
+pub fn is_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Whether the process with the id `pid` runs this program, rather than something else which was
+/// given the pid after the regulator that had it exited.
+pub fn is_tuxvantage(pid: u32) -> bool {
+    let exe = env::current_exe().ok();
+    let name = exe
+        .as_deref()
+        .and_then(Path::file_name)
+        .unwrap_or_else(|| OsStr::new("tuxvantage"));
+
+    runs(pid, name)
+}
+
+/// Whether the executable of the process with the id `pid` is named `name`. The link to the
+/// executable can only be read for the processes of the same user, so the name the process was
+/// started with is used for the others.
+fn runs(pid: u32, name: &OsStr) -> bool {
+    let proc = Path::new("/proc").join(pid.to_string());
+
+    if let Ok(exe) = fs::read_link(proc.join("exe")) {
+        // the executable may have been replaced by an upgrade since the process started
+        let exe = exe.to_string_lossy();
+        let exe = exe.strip_suffix(" (deleted)").unwrap_or(&exe);
+
+        return Path::new(exe).file_name() == Some(name);
+    }
+
+    match fs::read(proc.join("cmdline")) {
+        Ok(cmdline) => {
+            let arg0 = cmdline.split(|byte| *byte == 0).next().unwrap_or_default();
+
+            Path::new(OsStr::from_bytes(arg0)).file_name() == Some(name)
+        }
+        Err(_) => false,
+    }
+}
+
+fn systemctl(args: &[&str]) -> Option<String> {
+    let output = Command::new("systemctl").args(args).output().ok()?;
+
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn from_status_file() -> Option<Regulator> {
+    let status = match Status::get() {
+        Ok(status) => status?,
+        Err(error) => {
+            debug!("failed to get the status of the regulator: {:#}", error);
+            return None;
+        }
+    };
+
+    if !is_running(status.pid) {
+        debug!(
+            "the regulator with pid {} isn't running anymore",
+            status.pid
+        );
+        return None;
+    }
+
+    Some(Regulator {
+        pid: Some(status.pid),
+        source: Source::StatusFile,
+    })
+}
+
+/// The pid of the regulator started with `--daemonize`, if it is still running. A pid file left
+/// behind by one which didn't exit cleanly is removed.
+pub fn daemonized_pid() -> anyhow::Result<Option<u32>> {
+    let pid = match fs::read_to_string(project_paths::regulator_pid()) {
+        Ok(pid) => pid,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => {
+            return Err(error).with_context(|| format!("failed to read {}", "regulator.pid".bold()))
+        }
+    };
+    let pid = pid
+        .trim()
+        .parse()
+        .with_context(|| format!("the contents of {} aren't a pid", "regulator.pid".bold()))?;
+
+    if is_tuxvantage(pid) {
+        return Ok(Some(pid));
+    }
+
+    debug!(
+        "the daemonized regulator with pid {} isn't running anymore",
+        pid
+    );
+
+    if let Err(error) = fs::remove_file(project_paths::regulator_pid()) {
+        debug!("failed to remove the stale pid file: {}", error);
+    }
+
+    Ok(None)
+}
+
+fn from_pid_file() -> Option<Regulator> {
+    match daemonized_pid() {
+        Ok(pid) => pid.map(|pid| Regulator {
+            pid: Some(pid),
+            source: Source::PidFile,
+        }),
+        Err(error) => {
+            debug!(
+                "failed to get the pid of the daemonized regulator: {:#}",
+                error
+            );
+            None
+        }
+    }
+}
+
+fn from_service() -> Option<Regulator> {
+    if !utils::is_systemd().unwrap_or(false) {
+        return None;
+    }
+
+    let service = [
+        battery_conservation::regulator_service(),
+        HOLD_SERVICE.to_string(),
+    ]
+    .into_iter()
+    .find(|service| systemctl(&["is-active", service.as_str()]).as_deref() == Some("active"))?;
+    let pid = systemctl(&["show", "--property", "MainPID", "--value", &service])
+        .and_then(|pid| pid.parse().ok())
+        .filter(|pid| *pid != 0);
+
+    Some(Regulator {
+        pid,
+        source: Source::Service(service),
+    })
+}
+
+/// Finds a running battery conservation regulator, first through its pid file, then through its
+/// status file, then through the regulator and hold services. Any failure along the way is treated as the
+/// regulator not running.
+pub fn regulator() -> Option<Regulator> {
+    from_pid_file()
+        .or_else(from_status_file)
+        .or_else(from_service)
+}
+
+/// Warns that a running regulator may revert a manual change to battery conservation mode,
+/// unless `force` is given.
+pub fn warn_if_regulated(force: bool) {
+    if force {
+        return;
+    }
+
+    if let Some(regulator) = regulator().filter(|regulator| !regulator.is_current_process()) {
+        warn_with_tip!(
+            format_args!("{} is running and may revert this change", regulator),
+            regulator.stop_tip()
+        );
+    }
+}
+
+fn last_os_error(what: &str) -> anyhow::Error {
+    anyhow::Error::new(io::Error::last_os_error()).context(format!("failed to {}", what))
+}
+
+/// Points standard input at `/dev/null`, and standard output and error at `log_file`.
+fn redirect(log_file: &File) -> anyhow::Result<()> {
+    let dev_null = OpenOptions::new()
+        .read(true)
+        .open("/dev/null")
+        .context("failed to open /dev/null")?;
+
+    for (from, to) in [
+        (dev_null.as_raw_fd(), libc::STDIN_FILENO),
+        (log_file.as_raw_fd(), libc::STDOUT_FILENO),
+        (log_file.as_raw_fd(), libc::STDERR_FILENO),
+    ] {
+        // SAFETY: both file descriptors are valid, and the standard ones are meant to be replaced
+        if unsafe { libc::dup2(from, to) } == -1 {
+            return Err(last_os_error("redirect the standard streams"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Detaches from the terminal by forking twice, with standard output and error going to
+/// `log_file` afterwards. Returns the pid of the daemon in the original process, which should
+/// exit, and `None` in the daemon.
+///
+/// Only the calling thread survives a fork, so this must be called before spawning any threads
+/// the daemon needs.
+pub fn daemonize(log_file: &File) -> anyhow::Result<Option<u32>> {
+    let mut fds = [0; 2];
+
+    // SAFETY: `fds` has room for both ends of the pipe
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(last_os_error("create a pipe to the daemon"));
+    }
+
+    // SAFETY: both ends of the pipe were just created and aren't owned by anything else
+    let (mut reader, mut writer) =
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    // anything still buffered would be written by both processes
+    let _ = io::stdout().flush();
+
+    // SAFETY: the child only calls async-signal-safe functions before forking again
+    match unsafe { libc::fork() } {
+        -1 => return Err(last_os_error("fork")),
+        0 => {}
+        child => {
+            drop(writer);
+
+            let mut pid = String::new();
+            reader
+                .read_to_string(&mut pid)
+                .context("failed to read the pid of the daemon")?;
+
+            // SAFETY: `child` is our own child, which exits right after forking the daemon
+            unsafe { libc::waitpid(child, ptr::null_mut(), 0) };
+
+            return pid
+                .trim()
+                .parse()
+                .map(Some)
+                .context("the daemon failed to start, see the log file for why");
+        }
+    }
+
+    drop(reader);
+
+    // becoming the leader of a new session detaches from the controlling terminal, and forking
+    // again makes sure a terminal can never be acquired again. `_exit` skips destructors, which
+    // belong to the daemon now.
+    // SAFETY: both are always safe to call
+    unsafe {
+        if libc::setsid() == -1 {
+            libc::_exit(1);
+        }
+
+        match libc::fork() {
+            -1 => libc::_exit(1),
+            0 => {}
+            _ => libc::_exit(0),
+        }
+    }
+
+    env::set_current_dir("/").context("failed to change directory to /")?;
+    redirect(log_file)?;
+
+    writeln!(writer, "{}", process::id()).context("failed to send the pid of the daemon")?;
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regulator::Stats;
+    use crate::sandbox::Sandbox;
+    use std::time::SystemTime;
+
+    /// Above the largest pid linux hands out, so no process ever has it.
+    const UNUSED_PID: u32 = 1 << 23;
+
+    #[test]
+    fn a_process_is_recognized_by_its_executable() {
+        let exe = env::current_exe().unwrap();
+        let name = exe.file_name().unwrap();
+
+        assert!(runs(process::id(), name));
+        assert!(!runs(process::id(), OsStr::new("not-tuxvantage")));
+    }
+
+    #[test]
+    fn a_process_which_doesnt_exist_runs_nothing() {
+        assert!(is_running(process::id()));
+        assert!(!is_running(UNUSED_PID));
+        assert!(!runs(UNUSED_PID, OsStr::new("tuxvantage")));
+    }
+
+    fn detected() -> Option<Source> {
+        regulator()
+            .map(|regulator| regulator.source)
+            .filter(|source| !matches!(source, Source::Service(_)))
+    }
+
+    /// The files are in the runtime directory of the shared sandbox, so every case runs in turn.
+    /// This test stands in for the regulator, since it is the same program as far as the pid file
+    /// is concerned.
+    #[test]
+    fn regulators_are_detected_through_their_files() {
+        Sandbox::shared();
+        project_paths::ensure_runtime_dir().unwrap();
+        let pid_file = project_paths::regulator_pid();
+        let _ = fs::remove_file(pid_file);
+        Status::remove().unwrap();
+
+        assert_eq!(daemonized_pid().unwrap(), None);
+        assert_eq!(detected(), None);
+
+        fs::write(pid_file, process::id().to_string()).unwrap();
+        assert_eq!(daemonized_pid().unwrap(), Some(process::id()));
+        assert_eq!(detected(), Some(Source::PidFile));
+        assert!(regulator().unwrap().is_current_process());
+
+        // a pid which was handed to another program since, or to nothing at all
+        for stale in [1, UNUSED_PID] {
+            fs::write(pid_file, stale.to_string()).unwrap();
+            assert_eq!(daemonized_pid().unwrap(), None);
+            assert!(!pid_file.exists());
+        }
+
+        fs::write(pid_file, "not a pid").unwrap();
+        assert!(daemonized_pid().is_err());
+        assert_eq!(detected(), None);
+        fs::remove_file(pid_file).unwrap();
+
+        let mut status = Status::new(SystemTime::now(), Stats::default(), None);
+        status.dump().unwrap();
+        assert_eq!(detected(), Some(Source::StatusFile));
+        assert_eq!(regulator().unwrap().pid, Some(process::id()));
+
+        status.pid = UNUSED_PID;
+        status.dump().unwrap();
+        assert_eq!(detected(), None);
+        Status::remove().unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+use owo_colors::OwoColorize;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+
+/// A single field-level difference between two values.
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+    Added {
+        path: String,
+        new: Value,
+    },
+    Removed {
+        path: String,
+        old: Value,
+    },
+    Changed {
+        path: String,
+        old: Value,
+        new: Value,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Added { path, new } => {
+                write!(f, "{} {}: {}", "+".green(), path.bold(), new.green())
+            }
+            Self::Removed { path, old } => {
+                write!(f, "{} {}: {}", "-".red(), path.bold(), old.red())
+            }
+            Self::Changed { path, old, new } => write!(
+                f,
+                "{} {}: {} -> {}",
+                "~".yellow(),
+                path.bold(),
+                old.red(),
+                new.green()
+            ),
+        }
+    }
+}
+
+/// Computes the field-level differences between `old` and `new` after serializing them. Since
+/// the comparison is done on the parsed values, differences in formatting aren't reported.
+pub fn diff<T: Serialize>(old: &T, new: &T) -> serde_json::Result<Vec<Change>> {
+    let old = serde_json::to_value(old)?;
+    let new = serde_json::to_value(new)?;
+    let mut changes = Vec::new();
+
+    diff_values(String::new(), &old, &new, &mut changes);
+
+    Ok(changes)
+}
+
+fn join(path: &str, key: impl fmt::Display) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn diff_values(path: String, old: &Value, new: &Value, changes: &mut Vec<Change>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, old_value) in old {
+                let path = join(&path, key);
+
+                match new.get(key) {
+                    Some(new_value) => diff_values(path, old_value, new_value, changes),
+                    None => changes.push(Change::Removed {
+                        path,
+                        old: old_value.clone(),
+                    }),
+                }
+            }
+
+            for (key, new_value) in new {
+                if !old.contains_key(key) {
+                    changes.push(Change::Added {
+                        path: join(&path, key),
+                        new: new_value.clone(),
+                    })
+                }
+            }
+        }
+        (Value::Array(old), Value::Array(new)) => {
+            for index in 0..old.len().max(new.len()) {
+                let path = join(&path, index);
+
+                match (old.get(index), new.get(index)) {
+                    (Some(old_value), Some(new_value)) => {
+                        diff_values(path, old_value, new_value, changes)
+                    }
+                    (Some(old_value), None) => changes.push(Change::Removed {
+                        path,
+                        old: old_value.clone(),
+                    }),
+                    (None, Some(new_value)) => changes.push(Change::Added {
+                        path,
+                        new: new_value.clone(),
+                    }),
+                    (None, None) => unreachable!("index is within the bounds of either array"),
+                }
+            }
+        }
+        (old, new) if old != new => changes.push(Change::Changed {
+            path,
+            old: old.clone(),
+            new: new.clone(),
+        }),
+        _ => {}
+    }
+}
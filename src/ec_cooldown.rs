@@ -0,0 +1,110 @@
+use crate::state::State;
+use crate::types::HumanDuration;
+use crate::{ext, format};
+use once_cell::sync::Lazy;
+use owo_colors::OwoColorize;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long after a write the embedded controller may ignore another write of the same setting,
+/// if the config doesn't say.
+pub const DEFAULT_GUARD: HumanDuration = HumanDuration::from_secs(5);
+
+static GUARD_MILLIS: AtomicU64 = AtomicU64::new(DEFAULT_GUARD.0.as_millis() as u64);
+static RESPECT: AtomicBool = AtomicBool::new(false);
+
+/// The last successful write of each setting made by this process, in milliseconds since the unix
+/// epoch, which is still known if `state.json` can't be written.
+static LAST_WRITES: Lazy<Mutex<HashMap<String, u64>>> = Lazy::new(Default::default);
+
+/// Sets the guard interval, and whether writes within it are waited out instead of warned about.
+pub fn configure(guard: Duration, respect: bool) {
+    GUARD_MILLIS.store(guard.as_millis() as u64, Ordering::SeqCst);
+    RESPECT.store(respect, Ordering::SeqCst);
+}
+
+fn guard_interval() -> Duration {
+    Duration::from_millis(GUARD_MILLIS.load(Ordering::SeqCst))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_millis() as u64
+}
+
+/// The last write of `setting` by this process or any other one, whichever is later.
+fn last_write(setting: &str) -> Option<u64> {
+    let in_process = LAST_WRITES.lock().get(setting).copied();
+    let persisted = match State::get() {
+        Ok(state) => state.last_writes.get(setting).copied(),
+        Err(error) => {
+            debug!("failed to get the last write of {}: {:#}", setting, error);
+            None
+        }
+    };
+
+    in_process.max(persisted)
+}
+
+/// How much of the guard interval after the last write of `setting` is left, if any.
+pub fn remaining(setting: &str) -> Option<Duration> {
+    let since = Duration::from_millis(now().saturating_sub(last_write(setting)?));
+
+    guard_interval()
+        .checked_sub(since)
+        .filter(|remaining| !remaining.is_zero())
+}
+
+/// Called before writing `setting`. If it was written within the guard interval, the firmware
+/// may ignore this write, so the rest of the interval is waited out with `--respect-ec-cooldown`
+/// and warned about otherwise.
+pub fn guard(setting: &str) {
+    let remaining = match remaining(setting) {
+        Some(remaining) => remaining,
+        None => return,
+    };
+
+    if RESPECT.load(Ordering::SeqCst) {
+        debug!(
+            "{} was written recently, wait {} for the firmware to accept writes again",
+            setting,
+            format::duration_human(remaining)
+        );
+        thread::sleep(remaining);
+    } else {
+        warn_with_tip!(
+            format_args!(
+                "{} was changed less than {} ago, so the firmware may ignore this change",
+                setting.bold(),
+                format::duration_human(guard_interval()).bold()
+            ),
+            ext::EC_COOLDOWN_TIP
+        );
+    }
+}
+
+/// Records a successful write of `setting`, returning whether it was made within the guard
+/// interval of the previous one. This is best-effort like the history it feeds.
+pub fn record(setting: &str) -> bool {
+    let within = remaining(setting).is_some();
+    let now = now();
+    LAST_WRITES.lock().insert(setting.to_string(), now);
+
+    let result = State::mutate_then_dump(|state| {
+        state.last_writes.insert(setting.to_string(), now);
+    });
+
+    if let Err(error) = result {
+        debug!(
+            "failed to record the last write of {}: {:#}",
+            setting, error
+        );
+    }
+
+    within
+}
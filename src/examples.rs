@@ -0,0 +1,735 @@
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+
+#[derive(Serialize, Debug)]
+pub struct Example {
+    /// The subcommand this example belongs to, such as `battery conservation regulate`.
+    pub command: &'static str,
+
+    /// What the example does.
+    pub description: &'static str,
+
+    /// The arguments passed to `tuxvantage`.
+    pub args: &'static [&'static str],
+}
+
+impl Example {
+    const fn new(
+        command: &'static str,
+        description: &'static str,
+        args: &'static [&'static str],
+    ) -> Self {
+        Self {
+            command,
+            description,
+            args,
+        }
+    }
+
+    /// The example as a command line.
+    pub fn command_line(&self) -> String {
+        let mut command_line = String::from("tuxvantage");
+
+        for arg in self.args {
+            command_line.push(' ');
+            command_line.push_str(arg);
+        }
+
+        command_line
+    }
+}
+
+pub const EXAMPLES: &[Example] = &[
+    Example::new(
+        "battery conservation enabled",
+        "check if battery conservation mode is enabled",
+        &["battery", "conservation", "enabled"],
+    ),
+    Example::new(
+        "battery conservation enabled",
+        "print only `enabled` or `disabled`, for use in shell scripts",
+        &["--porcelain", "battery", "conservation", "enabled"],
+    ),
+    Example::new(
+        "battery conservation enable",
+        "enable battery conservation mode, switching rapid charging off if it is on",
+        &["battery", "conservation", "enable"],
+    ),
+    Example::new(
+        "battery conservation enable",
+        "enable battery conservation mode, but fail if rapid charging is on",
+        &["battery", "conservation", "enable", "--handler", "error"],
+    ),
+    Example::new(
+        "battery conservation enable",
+        "enable battery conservation mode and reapply it on `tuxvantage apply`",
+        &["battery", "conservation", "enable", "--remember"],
+    ),
+    Example::new(
+        "battery conservation enable",
+        "show which handler would be used and where it comes from",
+        &["battery", "conservation", "enable", "--explain"],
+    ),
+    Example::new(
+        "battery conservation enable",
+        "fail if the firmware switches battery conservation mode back off within 2 seconds",
+        &[
+            "battery",
+            "conservation",
+            "enable",
+            "--settle",
+            "2s",
+            "--strict",
+        ],
+    ),
+    Example::new(
+        "battery conservation disable",
+        "disable battery conservation mode",
+        &["battery", "conservation", "disable"],
+    ),
+    Example::new(
+        "battery conservation disable",
+        "disable battery conservation mode and switch rapid charging back on if the \
+         `switch-back` handler switched it off",
+        &["battery", "conservation", "disable", "--restore"],
+    ),
+    #[cfg(feature = "regulate")]
+    Example::new(
+        "battery conservation regulate",
+        "keep the battery at around 60%",
+        &["battery", "conservation", "regulate", "--threshold", "60"],
+    ),
+    #[cfg(feature = "regulate")]
+    Example::new(
+        "battery conservation regulate",
+        "stop charging at 80% and only charge again once the battery drops to 75%",
+        &[
+            "battery",
+            "conservation",
+            "regulate",
+            "--enable-at",
+            "80",
+            "--disable-at",
+            "75",
+        ],
+    ),
+    #[cfg(feature = "regulate")]
+    Example::new(
+        "battery conservation regulate",
+        "leave battery conservation mode alone while on battery power",
+        &[
+            "battery",
+            "conservation",
+            "regulate",
+            "--threshold",
+            "80",
+            "--only-on-ac",
+        ],
+    ),
+    #[cfg(feature = "regulate")]
+    Example::new(
+        "battery conservation regulate",
+        "check more often as the battery level nears the threshold, but at most every 30 seconds",
+        &[
+            "battery",
+            "conservation",
+            "regulate",
+            "--threshold",
+            "80",
+            "--adaptive-cooldown",
+            "--adaptive-cooldown-floor",
+            "30s",
+        ],
+    ),
+    #[cfg(feature = "regulate")]
+    Example::new(
+        "battery conservation regulate",
+        "put battery conservation mode back to how it was once the regulator is stopped",
+        &[
+            "battery",
+            "conservation",
+            "regulate",
+            "--threshold",
+            "80",
+            "--on-exit",
+            "restore",
+        ],
+    ),
+    #[cfg(feature = "regulate")]
+    Example::new(
+        "battery conservation regulate",
+        "react as soon as UPower sees the battery level change, checking every 10 minutes otherwise",
+        &[
+            "battery",
+            "conservation",
+            "regulate",
+            "--threshold",
+            "80",
+            "--listen",
+            "upower",
+            "--cooldown",
+            "10m",
+        ],
+    ),
+    #[cfg(feature = "regulate")]
+    Example::new(
+        "battery conservation regulate",
+        "regulate both batteries of a dual battery laptop until the emptier one reaches 80%",
+        &[
+            "battery",
+            "conservation",
+            "regulate",
+            "--threshold",
+            "80",
+            "--matches",
+            "all",
+            "--aggregate",
+            "min",
+        ],
+    ),
+    #[cfg(feature = "regulate")]
+    Example::new(
+        "battery conservation regulate",
+        "regulate the second battery instead of the first one",
+        &[
+            "battery",
+            "conservation",
+            "regulate",
+            "--matches",
+            "index=1",
+        ],
+    ),
+    #[cfg(feature = "service-install")]
+    Example::new(
+        "battery conservation regulate",
+        "install the regulator as a systemd service, which needs root",
+        &["battery", "conservation", "regulate", "--install"],
+    ),
+    #[cfg(feature = "service-install")]
+    Example::new(
+        "battery conservation regulate",
+        "install the regulator under another name for packaging",
+        &[
+            "battery",
+            "conservation",
+            "regulate",
+            "--install",
+            "--service-name",
+            "tuxvantage-regulate",
+            "--unit-dir",
+            "/usr/lib/systemd/system",
+        ],
+    ),
+    #[cfg(feature = "service-install")]
+    Example::new(
+        "battery conservation regulate",
+        "install the regulator as an OpenRC service, such as on Alpine",
+        &[
+            "battery",
+            "conservation",
+            "regulate",
+            "--install",
+            "--init-system",
+            "openrc",
+        ],
+    ),
+    #[cfg(feature = "service-install")]
+    Example::new(
+        "battery conservation regulate",
+        "regenerate the installed services after updating tuxvantage, which needs root",
+        &["battery", "conservation", "regulate", "--reinstall"],
+    ),
+    #[cfg(feature = "service-install")]
+    Example::new(
+        "battery conservation regulate",
+        "stop and remove the installed regulator service, which needs root",
+        &["battery", "conservation", "regulate", "--uninstall"],
+    ),
+    #[cfg(feature = "regulate")]
+    Example::new(
+        "battery conservation regulate",
+        "show what the regulator running in the background has done so far",
+        &["battery", "conservation", "regulate", "--status"],
+    ),
+    #[cfg(feature = "regulate")]
+    Example::new(
+        "battery conservation regulate",
+        "regulate in the background without systemd, such as from a `@reboot` cron job",
+        &[
+            "battery",
+            "conservation",
+            "regulate",
+            "--daemonize",
+            "--log-file",
+            "/var/log/tuxvantage.log",
+        ],
+    ),
+    #[cfg(feature = "regulate")]
+    Example::new(
+        "battery conservation regulate",
+        "also log to a file, keeping 3 rotated files of at most 5 megabytes",
+        &[
+            "battery",
+            "conservation",
+            "regulate",
+            "--log-file",
+            "/var/log/tuxvantage.log",
+            "--log-file-size",
+            "5",
+            "--log-file-count",
+            "3",
+        ],
+    ),
+    #[cfg(feature = "regulate")]
+    Example::new(
+        "battery conservation regulate",
+        "try out a threshold against battery levels recorded in a CSV file",
+        &[
+            "battery",
+            "conservation",
+            "regulate",
+            "--threshold",
+            "60",
+            "--simulate",
+            "levels.csv",
+        ],
+    ),
+    #[cfg(feature = "regulate")]
+    Example::new(
+        "battery conservation regulate",
+        "stop the regulator started with `--daemonize`",
+        &["battery", "conservation", "regulate", "--stop"],
+    ),
+    #[cfg(feature = "regulate")]
+    Example::new(
+        "battery hold",
+        "keep the battery at 60%, like a charge limit",
+        &["battery", "hold", "--at", "60"],
+    ),
+    #[cfg(feature = "service-install")]
+    Example::new(
+        "battery hold",
+        "install holding the battery at 70% as a systemd service, which needs root",
+        &["battery", "hold", "--at", "70", "--install"],
+    ),
+    #[cfg(feature = "regulate")]
+    Example::new(
+        "battery hold",
+        "hold the battery at 80% with the native thresholds if the model has them, toggling \
+         battery conservation mode otherwise",
+        &[
+            "battery",
+            "hold",
+            "--at",
+            "80",
+            "--prefer-native-thresholds",
+        ],
+    ),
+    Example::new(
+        "battery threshold get",
+        "show the native charge thresholds and what limits the charge",
+        &["battery", "threshold", "get"],
+    ),
+    Example::new(
+        "battery threshold set",
+        "charge only once the battery drops below 40%, and stop at 80%",
+        &[
+            "battery",
+            "threshold",
+            "set",
+            "--start",
+            "40",
+            "--end",
+            "80",
+        ],
+    ),
+    Example::new(
+        "system-performance get",
+        "get the current system performance mode",
+        &["system-performance", "get"],
+    ),
+    Example::new(
+        "system-performance get",
+        "show the raw bits behind the mode, such as when it is wrong on a new machine",
+        &["sp", "get", "--raw"],
+    ),
+    Example::new(
+        "system-performance get",
+        "print only the JSON of the mode, such as for `jq`",
+        &["sp", "get", "--json"],
+    ),
+    Example::new(
+        "system-performance set",
+        "switch to the battery saving system performance mode",
+        &["system-performance", "set", "battery-saving"],
+    ),
+    Example::new(
+        "system-performance set",
+        "switch to extreme performance and reapply it on `tuxvantage apply`",
+        &["sp", "set", "ep", "--remember"],
+    ),
+    Example::new(
+        "battery rapid-charge enable",
+        "enable rapid charging for now, switching battery conservation mode off until \
+         rapid charging is disabled with `--restore`",
+        &[
+            "battery",
+            "rapid-charge",
+            "enable",
+            "--handler",
+            "switch-back",
+        ],
+    ),
+    Example::new(
+        "battery rapid-charge disable",
+        "disable rapid charging and switch battery conservation mode back on if the \
+         `switch-back` handler switched it off",
+        &["battery", "rapid-charge", "disable", "--restore"],
+    ),
+    #[cfg(feature = "regulate")]
+    Example::new(
+        "battery rapid-charge top-up",
+        "charge to 80% by 14:30, rapid charging only if charging normally wouldn't make it",
+        &[
+            "battery",
+            "rapid-charge",
+            "top-up",
+            "--to",
+            "80%",
+            "--by",
+            "14:30",
+        ],
+    ),
+    #[cfg(feature = "regulate")]
+    Example::new(
+        "battery rapid-charge top-up",
+        "charge to 90% within the next 45 minutes, estimating again every 2 minutes",
+        &[
+            "battery",
+            "rapid-charge",
+            "top-up",
+            "--to",
+            "90",
+            "--by",
+            "45m",
+            "--interval",
+            "2m",
+        ],
+    ),
+    Example::new("profiles get", "list every profile", &["profiles", "get"]),
+    Example::new(
+        "profiles get",
+        "list only the names and product names of every profile",
+        &["profiles", "get", "--brief"],
+    ),
+    Example::new(
+        "profiles set",
+        "check a new profile read from standard input without writing it",
+        &["profiles", "set", "my-laptop", "--create-new", "--dry-run"],
+    ),
+    Example::new(
+        "profiles validate",
+        "check that a profile in a file is valid",
+        &["profiles", "validate", "my-laptop.json"],
+    ),
+    Example::new(
+        "profiles validate",
+        "check a profile against the schema, pointing at the field each problem is in",
+        &["profiles", "validate", "my-laptop.json", "--schema"],
+    ),
+    Example::new(
+        "profiles validate",
+        "check every profile in the profiles directory, listing the ones which are invalid",
+        &["profiles", "validate", "--all"],
+    ),
+    Example::new(
+        "profiles json",
+        "get the JSON contents of every profile in the profiles directory",
+        &["--machine", "always", "profiles", "json", "--all"],
+    ),
+    Example::new(
+        "profiles json",
+        "save the JSON contents of a profile to a file, replacing it if it exists",
+        &[
+            "profiles",
+            "json",
+            "my-laptop",
+            "--output",
+            "my-laptop.json",
+            "--force",
+        ],
+    ),
+    Example::new(
+        "profiles schema",
+        "write the schema of profiles for an editor to use",
+        &["profiles", "schema", "--pretty"],
+    ),
+    Example::new(
+        "profiles set-default",
+        "use a profile by default",
+        &["profiles", "set-default", "my-laptop"],
+    ),
+    Example::new(
+        "profiles set-default",
+        "use a profile by default even though it doesn't expect the product name of this machine",
+        &["profiles", "set-default", "my-laptop", "--force"],
+    ),
+    Example::new(
+        "profiles materialize",
+        "copy a built-in profile into the profiles directory to patch it under a new name",
+        &[
+            "profiles",
+            "materialize",
+            "IDEAPAD_AMD",
+            "--name",
+            "my-ideapad-amd",
+        ],
+    ),
+    Example::new(
+        "profiles contribute",
+        "package a working profile into a report for an issue",
+        &[
+            "profiles",
+            "contribute",
+            "my-laptop",
+            "--out",
+            "report.json",
+        ],
+    ),
+    Example::new(
+        "config check",
+        "check the configuration and profiles for problems",
+        &["config", "check"],
+    ),
+    Example::new(
+        "config explain",
+        "show why a profile was picked and where every setting came from",
+        &["config", "explain"],
+    ),
+    Example::new(
+        "config set-handler",
+        "fail instead of switching rapid charging off when enabling battery conservation mode",
+        &[
+            "config",
+            "set-handler",
+            "--for",
+            "battery-conservation",
+            "error",
+        ],
+    ),
+    Example::new(
+        "config set-handler",
+        "switch the opposing mode off by default, which is also the built-in default",
+        &["config", "set-handler", "switch"],
+    ),
+    Example::new(
+        "consistency show",
+        "show which services tuxvantage remembers installing",
+        &["consistency", "show"],
+    ),
+    Example::new(
+        "consistency reset",
+        "forget the installed services after removing them by hand",
+        &["consistency", "reset"],
+    ),
+    Example::new(
+        "doctor",
+        "check whether tuxvantage can work on this machine",
+        &["doctor"],
+    ),
+    Example::new(
+        "doctor",
+        "report only the config and profile checks to a monitoring agent, exiting with their \
+         severity",
+        &[
+            "--machine",
+            "always",
+            "doctor",
+            "--only",
+            "config,profile",
+            "--exit-by-severity",
+        ],
+    ),
+    Example::new(
+        "apply",
+        "apply the desired state for when the charger is plugged in",
+        &["apply", "ac"],
+    ),
+    Example::new(
+        "with",
+        "build at full speed with rapid charging on, restoring the previous settings after",
+        &[
+            "with",
+            "--conservation",
+            "off",
+            "--rapid-charge",
+            "on",
+            "--performance",
+            "ep",
+            "--",
+            "cargo",
+            "build",
+        ],
+    ),
+    Example::new("status", "print every setting at once", &["status"]),
+    Example::new(
+        "status",
+        "keep the settings on screen while testing chargers, redrawing every second",
+        &["status", "--watch", "--interval", "1s"],
+    ),
+    Example::new(
+        "history",
+        "show why battery conservation mode is in its current state",
+        &["history", "--limit", "5"],
+    ),
+    Example::new(
+        "history",
+        "print when each setting was changed, without the header, for `awk`",
+        &["history", "--columns", "time,setting,new", "--no-header"],
+    ),
+    Example::new(
+        "templates fields",
+        "list the placeholders a `status` template in `[templates]` can use",
+        &["templates", "fields", "status"],
+    ),
+    Example::new(
+        "paths",
+        "print the paths used by tuxvantage as JSON",
+        &["--machine", "always", "paths"],
+    ),
+    Example::new(
+        "paths",
+        "print the paths as JSON in the shape of version 2 of the machine output",
+        &["--machine", "always", "--machine-version", "2", "paths"],
+    ),
+    Example::new(
+        "paths",
+        "list the files of tuxvantage which are owned by another user or aren't writable",
+        &["paths", "--check"],
+    ),
+    Example::new(
+        "paths",
+        "give those files back to the owner of the config directory",
+        &["paths", "--check", "--fix"],
+    ),
+    Example::new(
+        "permissions",
+        "print what is needed to use tuxvantage without being root",
+        &["permissions"],
+    ),
+    Example::new(
+        "permissions",
+        "set up using tuxvantage without being root",
+        &["permissions", "--install"],
+    ),
+    Example::new(
+        "self-check-service",
+        "check that the installed regulator service still works",
+        &["self-check-service"],
+    ),
+];
+
+/// The hidden top level subcommands which moved under `battery` and where they are now, so that
+/// their examples can still be looked up by the old spelling. The first match is used.
+const MOVED: &[(&str, &str)] = &[
+    ("battery-conservation hold", "battery hold"),
+    ("bc hold", "battery hold"),
+    ("battery-conservation", "battery conservation"),
+    ("bc", "battery conservation"),
+    ("rapid-charge", "battery rapid-charge"),
+    ("rc", "battery rapid-charge"),
+];
+
+/// `command` as it is spelled under `battery`, if it is spelled the old way.
+fn moved(command: &str) -> String {
+    for (old, new) in MOVED {
+        if let Some(rest) = command.strip_prefix(old) {
+            if rest.is_empty() || rest.starts_with(' ') {
+                return format!("{}{}", new, rest);
+            }
+        }
+    }
+
+    command.to_string()
+}
+
+/// The examples of `command` and every subcommand of it, or all of them if `command` is empty.
+/// Subcommands which moved under `battery` can be given by their old spelling.
+pub fn of(command: &str) -> impl Iterator<Item = &'static Example> {
+    let command = moved(command);
+
+    EXAMPLES.iter().filter(move |example| {
+        command.is_empty()
+            || example.command == command
+            || example
+                .command
+                .strip_prefix(command.as_str())
+                .map_or(false, |rest| rest.starts_with(' '))
+    })
+}
+
+/// The examples section of the help of `command`, as used by `after_help`.
+pub fn after_help(command: &str) -> &'static str {
+    static AFTER_HELP: Lazy<HashMap<&str, String>> = Lazy::new(|| {
+        let mut after_help = HashMap::<&str, String>::new();
+
+        for example in EXAMPLES {
+            let section = after_help
+                .entry(example.command)
+                .or_insert_with(|| String::from("EXAMPLES:"));
+
+            section.push_str(&format!(
+                "\n    {}\n        $ {}\n",
+                example.description,
+                example.command_line()
+            ));
+        }
+
+        after_help
+    });
+
+    AFTER_HELP
+        .get(command)
+        .map(String::as_str)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::TuxVantage;
+    use clap::Parser;
+    use std::iter;
+
+    #[test]
+    fn examples_parse() {
+        for example in EXAMPLES {
+            let tuxvantage = TuxVantage::try_parse_from(
+                iter::once("tuxvantage").chain(example.args.iter().copied()),
+            )
+            .unwrap_or_else(|error| {
+                panic!("`{}` doesn't parse: {}", example.command_line(), error)
+            });
+
+            if let Some(conflict) = tuxvantage.action.normalize().conflict() {
+                panic!("`{}` conflicts: {}", example.command_line(), conflict)
+            }
+        }
+    }
+
+    #[test]
+    fn moved_commands_have_the_same_examples() {
+        for (old, new) in MOVED {
+            let old = of(old).map(Example::command_line).collect::<Vec<_>>();
+            let new = of(new).map(Example::command_line).collect::<Vec<_>>();
+
+            assert_eq!(old, new);
+        }
+    }
+
+    #[test]
+    fn unknown_commands_have_no_examples() {
+        assert_eq!(of("battery conservation enab").count(), 0);
+        assert_eq!(after_help("battery conservation enab"), "");
+    }
+}
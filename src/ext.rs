@@ -1,13 +1,310 @@
-use crate::{anyhow_with_tip, TippingAnyhowResultExt};
+use crate::anyhow_with_tip::StaticTip;
+use crate::types::HumanDuration;
+use crate::{
+    anyhow_with_tip, config, machine, project_paths, thresholds, utils, TippingAnyhowResultExt,
+};
+use anyhow::Context;
 use ideapad::acpi_call;
 use ideapad::{battery_conservation, rapid_charge, system_performance};
+use itertools::Itertools;
+use owo_colors::OwoColorize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use std::{fmt, thread};
 
-const ACPI_CALL_METHOD_NOT_FOUND_TIP: &str =
-    "the currently used profile may not be supported for your system. use another then try again";
-const ACPI_CALL_KERNEL_MODULE_NOT_LOADED_TIP: &str = "try running the following command as root to enable `acpi_call` (exclude the '#'!):\n\
+/// How long to wait for `/proc/acpi/call` to appear after loading `acpi_call`.
+const MODPROBE_TIMEOUT: Duration = Duration::from_secs(2);
+const MODPROBE_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+const ACPI_CALL_METHOD_NOT_FOUND_TIP: StaticTip = StaticTip {
+    id: "acpi-call-method-not-found",
+    message:
+        "the currently used profile may not be supported for your system. use another then try again",
+};
+const ACPI_CALL_KERNEL_MODULE_NOT_LOADED_TIP: StaticTip = StaticTip {
+    id: "acpi-call-kernel-module-not-loaded",
+    message: "try running the following command as root to enable `acpi_call` (exclude the '#'!):\n\
 # modprobe acpi_call\n\
 if it says something about the module not being found in some directory, install it in your package repositories,\n\
-reboot (although rebooting may not be necessary depending on your system, try it!), then perform this step again";
+reboot (although rebooting may not be necessary depending on your system, try it!), then perform this step again.\n\
+when running as root, pass `--auto-modprobe` to have this done for you",
+};
+const ACPI_CALL_NOT_INSTALLED_TIP: StaticTip = StaticTip {
+    id: "acpi-call-not-installed",
+    message: "install `acpi_call` from your package repositories, which is usually called `acpi_call-dkms` or \
+`acpi-call-dkms`, then reboot and try again",
+};
+
+/// `modprobe acpi_call` failed, which most likely means that the module isn't installed.
+#[derive(Debug)]
+pub struct ModprobeError;
+
+impl fmt::Display for ModprobeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to load the {} kernel module", "acpi_call".bold())
+    }
+}
+
+impl std::error::Error for ModprobeError {}
+
+/// This program runs inside of a container which can't see the interfaces the hardware is
+/// accessed through.
+#[derive(Debug)]
+pub struct ContainerError {
+    pub missing: Vec<PathBuf>,
+}
+
+impl fmt::Display for ContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the acpi interfaces aren't visible in this container ({} missing)",
+            self.missing
+                .iter()
+                .map(|path| path.display().bold().to_string())
+                .join(", ")
+        )
+    }
+}
+
+impl std::error::Error for ContainerError {}
+
+/// Something was asked for which this build of tuxvantage left out, such as installing services
+/// without the `service-install` cargo feature.
+#[derive(Debug)]
+pub struct FeatureDisabledError {
+    pub what: &'static str,
+    pub feature: &'static str,
+}
+
+impl fmt::Display for FeatureDisabledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "this build of tuxvantage can't {}, since it was built without the {} feature",
+            self.what,
+            self.feature.bold()
+        )
+    }
+}
+
+impl std::error::Error for FeatureDisabledError {}
+
+/// A setting was changed, but read back as something else while waiting for the firmware to
+/// settle with `--settle`.
+#[derive(Debug)]
+pub struct NotSettledError {
+    pub setting: &'static str,
+    pub expected: &'static str,
+    pub immediate: &'static str,
+    pub settled: &'static str,
+    pub after: HumanDuration,
+}
+
+impl fmt::Display for NotSettledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} didn't stick: it was set to {}, but read back as {} right after and as {} after {}",
+            self.setting,
+            self.expected.bold(),
+            self.immediate.bold(),
+            self.settled.bold(),
+            self.after
+        )
+    }
+}
+
+impl std::error::Error for NotSettledError {}
+
+/// Fails if this program runs inside of a container which can't access the hardware.
+pub fn ensure_not_in_container() -> anyhow_with_tip::Result<()> {
+    if !utils::is_container() {
+        return Ok(());
+    }
+
+    let missing = utils::missing_acpi_interfaces_at(Path::new("/"));
+
+    if missing.is_empty() {
+        debug!("running in a container, but the acpi interfaces are visible");
+        return Ok(());
+    }
+
+    Err(anyhow::Error::new(ContainerError { missing })).tip(CONTAINER_TIP)
+}
+
+pub const CONTAINER_TIP: StaticTip = StaticTip {
+    id: "container",
+    message: "tuxvantage needs to run on the host to access the hardware, such as with `distrobox-host-exec tuxvantage` or `flatpak-spawn --host tuxvantage`. commands which don't access the hardware still work in the container",
+};
+
+pub const NOT_SETTLED_TIP: StaticTip = StaticTip {
+    id: "not-settled",
+    message: "the firmware reverted the change, which has been seen after BIOS updates. check that nothing else changes it, such as the regulator or another power manager, and try a longer `--settle`",
+};
+
+pub const STATUS_WATCH_TIP: StaticTip = StaticTip {
+    id: "status-watch",
+    message: "to follow the settings from a script, run `tuxvantage --porcelain status` in a loop instead, such as `while sleep 2; do tuxvantage --porcelain status; done`",
+};
+
+pub const THRESHOLDS_UNSUPPORTED_TIP: StaticTip = StaticTip {
+    id: "thresholds-unsupported",
+    message: "native thresholds need a model which exposes them and a recent enough kernel. battery conservation mode and `tuxvantage battery hold` work without them",
+};
+
+pub const THRESHOLDS_PERMISSION_DENIED_TIP: StaticTip = StaticTip {
+    id: "thresholds-permission-denied",
+    message: "run this as root, or run `tuxvantage permissions --install` as root to let the `tuxvantage` group write the thresholds",
+};
+
+pub const EC_COOLDOWN_TIP: StaticTip = StaticTip {
+    id: "ec-cooldown",
+    message: "pass `--respect-ec-cooldown` to wait until the firmware accepts changes again, or check the setting again in a few seconds",
+};
+
+pub const INVALID_CONFIG_TIP: StaticTip = StaticTip {
+    id: "invalid-config",
+    message: "run `tuxvantage config check` to see what is wrong with `tuxvantage.toml`, or move it away to go back to the defaults",
+};
+
+const READ_ONLY_TIP: StaticTip = StaticTip {
+    id: "read-only-config",
+    message: "the configuration is either on a read-only filesystem or read-only mode was enabled.\n\
+remount the configuration directory as writable, point `TUXVANTAGE_CONFIG_DIR` at a writable directory, or disable read-only mode",
+};
+
+const FOREIGN_OWNER_TIP: StaticTip = StaticTip {
+    id: "foreign-owner",
+    message: "a file or directory of tuxvantage is owned by another user, likely from running it with `sudo` once.\n\
+run `tuxvantage paths --check` to list them, then `sudo tuxvantage paths --check --fix` to give them back to the owner of the config directory",
+};
+
+pub const PATHS_FIX_ROOT_TIP: StaticTip = StaticTip {
+    id: "paths-fix-root",
+    message: "changing the owner of files needs root, so run this again with `sudo`, keeping `HOME` or passing `--config` so that the same config directory is used",
+};
+
+pub const PRODUCT_DETECTION_PERMISSION_DENIED_TIP: StaticTip = StaticTip {
+    id: "product-detection-permission-denied",
+    message: "this program tries to identify the product of your machine which requires root privileges, so try running this program as root",
+};
+
+pub const PROFILE_MISMATCH_TIP: StaticTip = StaticTip {
+    id: "profile-mismatch",
+    message: "if the profile really is for this machine, add the product name to its `expected_product_names`, otherwise use another profile",
+};
+
+pub const RECOVERABLE_CONFIG_ERRORS_TIP: StaticTip = StaticTip {
+    id: "recoverable-config-errors",
+    message: "run `tuxvantage config check` to see every problem with the configuration",
+};
+
+pub const CONSERVATION_MODE_CAP_TIP: StaticTip = StaticTip {
+    id: "conservation-mode-cap",
+    message: "battery conservation mode usually stops charging at around 60%, so lower the threshold below that, or use `tuxvantage battery-conservation hold` to emulate a higher charge limit",
+};
+
+pub const REGULATOR_EXE_MOVED_TIP: StaticTip = StaticTip {
+    id: "regulator-exe-moved",
+    message: "if the service fails to run, try running `tuxvantage battery-conservation regulate -I` again",
+};
+
+pub const REGULATOR_SERVICE_OUTDATED_TIP: StaticTip = StaticTip {
+    id: "regulator-service-outdated",
+    message:
+        "regenerate it by running `tuxvantage battery-conservation regulate --reinstall` as root",
+};
+
+pub const REGULATOR_SERVICE_NOT_INSTALLED_TIP: StaticTip = StaticTip {
+    id: "regulator-service-not-installed",
+    message: "install it by running `tuxvantage battery-conservation regulate -I` as root",
+};
+
+pub const REGULATOR_SERVICE_NOT_ENABLED_TIP: StaticTip = StaticTip {
+    id: "regulator-service-not-enabled",
+    message: "enable it by running `systemctl enable bcm.service` as root",
+};
+
+pub const REGULATOR_SERVICE_NOT_ACTIVE_TIP: StaticTip = StaticTip {
+    id: "regulator-service-not-active",
+    message: "start it by running `systemctl start bcm.service` as root",
+};
+
+pub const REGULATOR_SERVICE_FAILED_TIP: StaticTip = StaticTip {
+    id: "regulator-service-failed",
+    message: "see why by running `journalctl -u bcm.service`",
+};
+
+pub const NO_BATTERY_TIP: StaticTip = StaticTip {
+    id: "no-battery",
+    message: "check that the battery is plugged in, or pick another one with `--matches` from the batteries listed above",
+};
+
+pub const NO_PROFILE_TIP: StaticTip = StaticTip {
+    id: "no-profile",
+    message: "if this is an IdeaPad, see which profiles were tried with `tuxvantage config explain`, then add one for it with `tuxvantage profiles set <name> --create-new` or pick one with `--profile`. commands which don't access the hardware still work",
+};
+
+pub const IGNORE_HANDLER_TIP: StaticTip = StaticTip {
+    id: "ignore-handler",
+    message: "use the `switch` handler instead to disable the opposing mode first",
+};
+
+/// Finds a tip for errors which weren't given one where they happened.
+pub fn fallback_tip(error: &anyhow::Error) -> Option<StaticTip> {
+    if error
+        .chain()
+        .any(|error| error.is::<config::ReadOnlyError>())
+    {
+        Some(READ_ONLY_TIP)
+    } else if error
+        .chain()
+        .any(|error| error.is::<project_paths::ForeignOwnerError>())
+    {
+        Some(FOREIGN_OWNER_TIP)
+    } else if config::is_invalid_config(error) {
+        Some(INVALID_CONFIG_TIP)
+    } else if error.chain().any(|error| error.is::<ModprobeError>()) {
+        Some(ACPI_CALL_NOT_INSTALLED_TIP)
+    } else {
+        None
+    }
+}
+
+/// A stable identifier of the kind of error, for machine output.
+pub fn error_code(error: &anyhow::Error) -> Option<&'static str> {
+    error.chain().find_map(|error| {
+        if error.is::<config::ReadOnlyError>() {
+            Some("read_only")
+        } else if error.is::<config::NoBatteryError>() {
+            Some("no_battery")
+        } else if error.is::<config::UnsupportedError>() {
+            Some("unsupported")
+        } else if error.is::<config::InvalidConfigError>() {
+            Some("invalid_config")
+        } else if error.is::<config::NoProfileError>() {
+            Some("no_profile")
+        } else if error.is::<ModprobeError>() {
+            Some("modprobe_failed")
+        } else if error.is::<ContainerError>() {
+            Some("container")
+        } else if error.is::<FeatureDisabledError>() {
+            Some("feature_disabled")
+        } else if error.is::<NotSettledError>() {
+            Some("not_settled")
+        } else if error.is::<project_paths::ForeignOwnerError>() {
+            Some("foreign_owner")
+        } else if error.is::<thresholds::UnsupportedError>() {
+            Some("thresholds_unsupported")
+        } else if error.is::<machine::UnsupportedVersionError>() {
+            Some("unsupported_machine_version")
+        } else {
+            None
+        }
+    })
+}
 
 pub trait AcpiCallResultExt<T> {
     fn resolve_tip(self) -> anyhow_with_tip::Result<T>;
@@ -29,41 +326,66 @@ pub trait AnyhowResultExt<T> {
     fn maybe_acpi_call_tip(self) -> anyhow_with_tip::Result<T>;
 }
 
+/// The error of `acpi_call` behind `error`, even if it is wrapped by one of ideapad's errors.
+fn acpi_call_error(error: &anyhow::Error) -> Option<&acpi_call::Error> {
+    if let Some(error) = error.downcast_ref::<acpi_call::Error>() {
+        Some(error)
+    } else if let Some(battery_conservation::Error::AcpiCall { error }) =
+        error.downcast_ref::<battery_conservation::Error>()
+    {
+        Some(error)
+    } else if let Some(rapid_charge::Error::AcpiCall { error }) =
+        error.downcast_ref::<rapid_charge::Error>()
+    {
+        Some(error)
+    } else if let Some(system_performance::Error::AcpiCall { error }) =
+        error.downcast_ref::<system_performance::Error>()
+    {
+        Some(error)
+    } else {
+        None
+    }
+}
+
+/// Whether `error` happened because the `acpi_call` kernel module isn't loaded.
+pub fn is_kernel_module_not_loaded(error: &anyhow::Error) -> bool {
+    matches!(
+        acpi_call_error(error),
+        Some(acpi_call::Error::KernelModuleNotLoaded { .. })
+    )
+}
+
+/// Loads the `acpi_call` kernel module, waiting a little for it to become usable.
+pub fn modprobe_acpi_call() -> anyhow_with_tip::Result<()> {
+    let successful = Command::new("modprobe")
+        .arg("acpi_call")
+        .status()
+        .context("failed to run `modprobe acpi_call`")
+        .no_tip()?
+        .success();
+
+    if !successful {
+        return Err(anyhow::Error::new(ModprobeError)).tip(ACPI_CALL_NOT_INSTALLED_TIP);
+    }
+
+    let mut waited = Duration::ZERO;
+
+    while !Path::new("/proc/acpi/call").exists() && waited < MODPROBE_TIMEOUT {
+        thread::sleep(MODPROBE_RETRY_INTERVAL);
+        waited += MODPROBE_RETRY_INTERVAL;
+    }
+
+    Ok(())
+}
+
 impl<T> AnyhowResultExt<T> for anyhow::Result<T> {
     fn maybe_acpi_call_tip(self) -> anyhow_with_tip::Result<T> {
-        let error = self.as_ref().err();
-        let tip = if let Some(error) = error {
-            let error = if let Some(error) = error.downcast_ref::<acpi_call::Error>() {
-                Some(error)
-            } else if let Some(battery_conservation::Error::AcpiCall { error }) =
-                error.downcast_ref::<battery_conservation::Error>()
-            {
-                Some(error)
-            } else if let Some(rapid_charge::Error::AcpiCall { error }) =
-                error.downcast_ref::<rapid_charge::Error>()
-            {
-                Some(error)
-            } else if let Some(system_performance::Error::AcpiCall { error }) =
-                error.downcast_ref::<system_performance::Error>()
-            {
-                Some(error)
-            } else {
-                None
-            };
-
-            if let Some(error) = error {
-                match error {
-                    acpi_call::Error::MethodNotFound { .. } => Some(ACPI_CALL_METHOD_NOT_FOUND_TIP),
-                    acpi_call::Error::KernelModuleNotLoaded { .. } => {
-                        Some(ACPI_CALL_KERNEL_MODULE_NOT_LOADED_TIP)
-                    }
-                    _ => None,
-                }
-            } else {
-                None
+        let tip = match self.as_ref().err().and_then(acpi_call_error) {
+            Some(acpi_call::Error::MethodNotFound { .. }) => Some(ACPI_CALL_METHOD_NOT_FOUND_TIP),
+            Some(acpi_call::Error::KernelModuleNotLoaded { .. }) => {
+                Some(ACPI_CALL_KERNEL_MODULE_NOT_LOADED_TIP)
             }
-        } else {
-            None
+            _ => None,
         };
 
         self.maybe_tip(tip)
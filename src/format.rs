@@ -0,0 +1,129 @@
+use std::time::Duration;
+
+/// A percentage, such as a battery level.
+pub fn percent(value: u8) -> String {
+    format!("{}%", value)
+}
+
+/// A duration in seconds, without a fractional part if it is a whole number of seconds.
+pub fn duration_human(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64();
+
+    if seconds.fract() == 0.0 {
+        format!("{} second(s)", seconds as u64)
+    } else {
+        format!("{:.2} second(s)", seconds)
+    }
+}
+
+/// A byte in hexadecimal, padded to two digits.
+pub fn hex_u8(value: u8) -> String {
+    format!("{:#04x}", value)
+}
+
+/// A 32-bit value in hexadecimal, padded to eight digits.
+pub fn hex_u32(value: u32) -> String {
+    format!("{:#010x}", value)
+}
+
+/// Seconds since the unix epoch as a date and time in UTC, such as `2022-02-10 12:00:00 UTC`.
+pub fn utc(seconds: u64) -> String {
+    // the days since the epoch as a date in the proleptic gregorian calendar, from
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = seconds / 86_400 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    let time = seconds % 86_400;
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 86_400;
+
+    #[test]
+    fn percents() {
+        assert_eq!(percent(0), "0%");
+        assert_eq!(percent(80), "80%");
+        assert_eq!(percent(100), "100%");
+    }
+
+    #[test]
+    fn durations_only_have_a_fraction_when_needed() {
+        assert_eq!(duration_human(Duration::ZERO), "0 second(s)");
+        assert_eq!(duration_human(Duration::from_secs(90)), "90 second(s)");
+        assert_eq!(
+            duration_human(Duration::from_millis(1500)),
+            "1.50 second(s)"
+        );
+        assert_eq!(duration_human(Duration::from_millis(1)), "0.00 second(s)");
+    }
+
+    #[test]
+    fn hex_is_padded() {
+        assert_eq!(hex_u8(0), "0x00");
+        assert_eq!(hex_u8(0xa), "0x0a");
+        assert_eq!(hex_u8(0xff), "0xff");
+        assert_eq!(hex_u32(0), "0x00000000");
+        assert_eq!(hex_u32(0x1f), "0x0000001f");
+        assert_eq!(hex_u32(u32::MAX), "0xffffffff");
+    }
+
+    #[test]
+    fn the_epoch() {
+        assert_eq!(utc(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(utc(DAY - 1), "1970-01-01 23:59:59 UTC");
+    }
+
+    #[test]
+    fn times_of_day() {
+        assert_eq!(utc(1_644_494_400), "2022-02-10 12:00:00 UTC");
+        assert_eq!(utc(1_644_494_400 + 3_723), "2022-02-10 13:02:03 UTC");
+    }
+
+    #[test]
+    fn leap_days() {
+        // 2000 is a leap year despite being divisible by 100, since it is divisible by 400
+        assert_eq!(utc(951_782_400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(utc(1_709_164_800), "2024-02-29 00:00:00 UTC");
+        assert_eq!(utc(1_709_164_800 + DAY), "2024-03-01 00:00:00 UTC");
+        // 2100 isn't, since it is divisible by 100 but not by 400
+        assert_eq!(utc(4_107_456_000), "2100-02-28 00:00:00 UTC");
+        assert_eq!(utc(4_107_456_000 + DAY), "2100-03-01 00:00:00 UTC");
+    }
+
+    #[test]
+    fn the_start_of_the_era_the_algorithm_counts_from() {
+        // the algorithm counts years from march, and 2000-03-01 starts one of its 400 year eras
+        assert_eq!(utc(951_868_800 - 1), "2000-02-29 23:59:59 UTC");
+        assert_eq!(utc(951_868_800), "2000-03-01 00:00:00 UTC");
+    }
+
+    #[test]
+    fn the_end_of_a_year() {
+        assert_eq!(utc(1_704_067_199), "2023-12-31 23:59:59 UTC");
+        assert_eq!(utc(1_704_067_200), "2024-01-01 00:00:00 UTC");
+    }
+}
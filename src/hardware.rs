@@ -0,0 +1,750 @@
+use crate::args::FromStrSystemPerformanceMode;
+use crate::config::{BuiltInProfile, DropFallback};
+use crate::context::{self, Context};
+use crate::ext::{self, AnyhowResultExt};
+use crate::{anyhow_with_tip, config, machine, utils};
+use anyhow::Context as AnyhowContext;
+use ideapad::{acpi_call, Handler, Profile, SystemPerformanceMode};
+use owo_colors::OwoColorize;
+use serde::{Deserializer, Serializer};
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::{env, fs, io};
+use try_drop::drop_strategies::BroadcastDropStrategy;
+
+/// Makes every command act on [`Fake`] hardware instead of the real one, keeping its state in the
+/// file this points to, so that the commands can be tested without an ideapad. Only read with the
+/// `testing` feature.
+pub const FAKE_ENV: &str = "TUXVANTAGE_FAKE_HARDWARE";
+
+/// The settings of the laptop which tuxvantage controls.
+///
+/// Loops such as the regulator and `apply` go through this instead of ideapad directly, so that
+/// something other than the real hardware can stand in for it.
+pub trait Hardware {
+    fn conservation(&mut self) -> anyhow::Result<bool>;
+    fn set_conservation(&mut self, on: bool, handler: Handler) -> anyhow::Result<()>;
+    fn rapid_charge(&mut self) -> anyhow::Result<bool>;
+    fn set_rapid_charge(&mut self, on: bool, handler: Handler) -> anyhow::Result<()>;
+    fn performance_mode(&mut self) -> anyhow::Result<SystemPerformanceMode>;
+    fn set_performance_mode(&mut self, mode: SystemPerformanceMode) -> anyhow::Result<()>;
+
+    /// The raw FCMO and SPMO bits behind the system performance mode.
+    fn performance_bits(&mut self) -> anyhow::Result<(u32, u32)>;
+}
+
+impl<H: Hardware + ?Sized> Hardware for Box<H> {
+    fn conservation(&mut self) -> anyhow::Result<bool> {
+        (**self).conservation()
+    }
+
+    fn set_conservation(&mut self, on: bool, handler: Handler) -> anyhow::Result<()> {
+        (**self).set_conservation(on, handler)
+    }
+
+    fn rapid_charge(&mut self) -> anyhow::Result<bool> {
+        (**self).rapid_charge()
+    }
+
+    fn set_rapid_charge(&mut self, on: bool, handler: Handler) -> anyhow::Result<()> {
+        (**self).set_rapid_charge(on, handler)
+    }
+
+    fn performance_mode(&mut self) -> anyhow::Result<SystemPerformanceMode> {
+        (**self).performance_mode()
+    }
+
+    fn set_performance_mode(&mut self, mode: SystemPerformanceMode) -> anyhow::Result<()> {
+        (**self).set_performance_mode(mode)
+    }
+
+    fn performance_bits(&mut self) -> anyhow::Result<(u32, u32)> {
+        (**self).performance_bits()
+    }
+}
+
+/// The file the state of the fake hardware is kept in, if [`FAKE_ENV`] is set and this is a build
+/// with the `testing` feature.
+pub fn fake_path() -> Option<PathBuf> {
+    if !cfg!(feature = "testing") {
+        return None;
+    }
+
+    env::var_os(FAKE_ENV)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+/// The hardware the commands act on, which is the real one unless [`FAKE_ENV`] is set.
+pub fn get() -> Box<dyn Hardware> {
+    match fake_path() {
+        Some(path) => {
+            debug!("using the fake hardware kept in '{}'", path.display());
+            Box::new(FakeFile::new(path))
+        }
+        None => Box::new(Ideapad::new()),
+    }
+}
+
+/// The settings tuxvantage controls at one point in time, which `status` prints and `with`
+/// changes and restores.
+#[derive(Serialize, Debug, Copy, Clone, PartialEq)]
+pub struct Snapshot {
+    pub battery_conservation: bool,
+    pub rapid_charge: bool,
+    pub system_performance: SystemPerformanceMode,
+}
+
+impl Snapshot {
+    pub fn read(hardware: &mut impl Hardware) -> anyhow_with_tip::Result<Self> {
+        Ok(Self {
+            battery_conservation: hardware
+                .conservation()
+                .context("failed to get battery conservation mode value")
+                .maybe_acpi_call_tip()?,
+            rapid_charge: hardware
+                .rapid_charge()
+                .context("failed to get rapid charge value")
+                .maybe_acpi_call_tip()?,
+            system_performance: hardware
+                .performance_mode()
+                .context("failed to get system performance mode")
+                .maybe_acpi_call_tip()?,
+        })
+    }
+}
+
+/// The real hardware, through the context ideapad was initialized with or one of its own.
+pub struct Ideapad {
+    context: IdeapadContext,
+    auto_modprobe: bool,
+}
+
+enum IdeapadContext {
+    Global(&'static Context),
+    Owned(Box<Context>),
+}
+
+impl Deref for IdeapadContext {
+    type Target = Context;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Global(context) => context,
+            Self::Owned(context) => context,
+        }
+    }
+}
+
+impl Ideapad {
+    /// The hardware through the context the command line interface initialized ideapad with,
+    /// loading the `acpi_call` kernel module if the config says to.
+    pub fn new() -> Self {
+        Self {
+            context: IdeapadContext::Global(context::get()),
+            auto_modprobe: config::try_read()
+                .map_or(false, |config| config.tuxvantage.auto_modprobe()),
+        }
+    }
+
+    /// The hardware through a context of its own, which sends the acpi calls of `profile`. Errors
+    /// while dropping are handled as `drop_fallback` says, since nothing receives them.
+    pub fn with_profile(profile: Profile, drop_fallback: DropFallback) -> Self {
+        let (fallible_drop_strategy, _) = BroadcastDropStrategy::new(1);
+        let context = Context::new_with_strategies(
+            profile,
+            fallible_drop_strategy,
+            context::FallbackDropStrategy(drop_fallback),
+        );
+
+        Self {
+            context: IdeapadContext::Owned(Box::new(context)),
+            auto_modprobe: false,
+        }
+    }
+
+    /// Sends the acpi calls of `call`. If they failed because the `acpi_call` kernel module isn't
+    /// loaded, the module is loaded and only `call` is retried, once, as long as `--auto-modprobe`
+    /// was given and this program runs as root. Nothing else is ran again, and `call` itself had
+    /// no effect since its first acpi call failed.
+    fn call<T>(&self, call: impl Fn(&Context) -> anyhow::Result<T>) -> anyhow::Result<T> {
+        match call(&self.context) {
+            Err(error)
+                if self.auto_modprobe
+                    && ext::is_kernel_module_not_loaded(&error)
+                    && utils::is_root() =>
+            {
+                debug!("the acpi_call kernel module isn't loaded, load it and retry once");
+                ext::modprobe_acpi_call().map_err(|error| error.source)?;
+
+                if machine::enabled() {
+                    machine::push_warning(
+                        "loaded the acpi_call kernel module and retried",
+                        None::<&str>,
+                    );
+                } else {
+                    info!("loaded the {} kernel module, retrying", "acpi_call".bold());
+                }
+
+                call(&self.context)
+            }
+            result => result,
+        }
+    }
+}
+
+impl Default for Ideapad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hardware for Ideapad {
+    fn conservation(&mut self) -> anyhow::Result<bool> {
+        self.call(|context| Ok(ideapad::battery_conservation::enabled(context)?))
+    }
+
+    fn set_conservation(&mut self, on: bool, handler: Handler) -> anyhow::Result<()> {
+        self.call(|context| {
+            if on {
+                context
+                    .controllers()
+                    .battery_conservation()
+                    .enable()
+                    .handler(handler)
+                    .now()?;
+            } else {
+                context.controllers().battery_conservation().disable()?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn rapid_charge(&mut self) -> anyhow::Result<bool> {
+        self.call(|context| Ok(ideapad::rapid_charge::enabled(context)?))
+    }
+
+    fn set_rapid_charge(&mut self, on: bool, handler: Handler) -> anyhow::Result<()> {
+        self.call(|context| {
+            if on {
+                context
+                    .controllers()
+                    .rapid_charge()
+                    .enable()
+                    .handler(handler)
+                    .now()?;
+            } else {
+                ideapad::rapid_charge::disable(context)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn performance_mode(&mut self) -> anyhow::Result<SystemPerformanceMode> {
+        self.call(|context| Ok(ideapad::system_performance::get(context)?))
+    }
+
+    fn set_performance_mode(&mut self, mode: SystemPerformanceMode) -> anyhow::Result<()> {
+        self.call(|context| Ok(ideapad::system_performance::set(context, mode)?))
+    }
+
+    fn performance_bits(&mut self) -> anyhow::Result<(u32, u32)> {
+        let read = |name: &str, command: String| {
+            acpi_call::acpi_call_expect_valid(command, [])
+                .with_context(|| format!("failed to read the {} bit", name.bold()))
+        };
+
+        self.call(|context| {
+            let commands = &context.profile.system_performance.commands;
+
+            Ok((
+                read("FCMO", commands.get_fcmo_bit.to_string())?,
+                read("SPMO", commands.get_spmo_bit.to_string())?,
+            ))
+        })
+    }
+}
+
+/// Hardware which only exists in memory, for the tests. Enabling battery conservation mode or
+/// rapid charge while the other one is enabled is handled like the firmware and ideapad would.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(default)]
+pub struct Fake {
+    pub battery_conservation: bool,
+    pub rapid_charge: bool,
+
+    #[serde(
+        serialize_with = "serialize_system_performance_mode",
+        deserialize_with = "deserialize_system_performance_mode"
+    )]
+    pub system_performance: SystemPerformanceMode,
+
+    /// Fails every read and write, like an unloaded `acpi_call` module would.
+    pub broken: bool,
+
+    /// Panics on every read and write, standing in for a bug.
+    pub panicking: bool,
+}
+
+fn serialize_system_performance_mode<S>(
+    mode: &SystemPerformanceMode,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let name = FromStrSystemPerformanceMode::NAMES
+        .iter()
+        .find(|(named, _)| named == mode)
+        .map_or("", |(_, names)| names[0]);
+
+    serializer.serialize_str(name)
+}
+
+fn deserialize_system_performance_mode<'de, D>(
+    deserializer: D,
+) -> Result<SystemPerformanceMode, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    utils::deserialize_name(
+        deserializer,
+        FromStrSystemPerformanceMode::NAMES,
+        "system performance mode",
+    )
+}
+
+impl Fake {
+    pub fn new() -> Self {
+        Self {
+            battery_conservation: false,
+            rapid_charge: false,
+            system_performance: SystemPerformanceMode::IntelligentCooling,
+            broken: false,
+            panicking: false,
+        }
+    }
+
+    fn ensure_working(&self) -> anyhow::Result<()> {
+        assert!(!self.panicking, "the fake hardware panicked");
+        anyhow::ensure!(!self.broken, "the fake hardware is broken");
+        Ok(())
+    }
+}
+
+impl Default for Fake {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Switches `setting` to `on`, handling `other` being enabled at the same time with `handler`.
+fn set_exclusive(
+    setting: &mut bool,
+    other: &mut bool,
+    on: bool,
+    handler: Handler,
+    other_name: &str,
+) -> anyhow::Result<()> {
+    if on && *other {
+        match handler {
+            Handler::Switch => *other = false,
+            Handler::Error => anyhow::bail!("{} is enabled", other_name),
+            Handler::Ignore => {}
+        }
+    }
+
+    *setting = on;
+    Ok(())
+}
+
+impl Hardware for Fake {
+    fn conservation(&mut self) -> anyhow::Result<bool> {
+        self.ensure_working()?;
+        Ok(self.battery_conservation)
+    }
+
+    fn set_conservation(&mut self, on: bool, handler: Handler) -> anyhow::Result<()> {
+        self.ensure_working()?;
+        set_exclusive(
+            &mut self.battery_conservation,
+            &mut self.rapid_charge,
+            on,
+            handler,
+            "rapid charge",
+        )
+    }
+
+    fn rapid_charge(&mut self) -> anyhow::Result<bool> {
+        self.ensure_working()?;
+        Ok(self.rapid_charge)
+    }
+
+    fn set_rapid_charge(&mut self, on: bool, handler: Handler) -> anyhow::Result<()> {
+        self.ensure_working()?;
+        set_exclusive(
+            &mut self.rapid_charge,
+            &mut self.battery_conservation,
+            on,
+            handler,
+            "battery conservation",
+        )
+    }
+
+    fn performance_mode(&mut self) -> anyhow::Result<SystemPerformanceMode> {
+        self.ensure_working()?;
+        Ok(self.system_performance)
+    }
+
+    fn set_performance_mode(&mut self, mode: SystemPerformanceMode) -> anyhow::Result<()> {
+        self.ensure_working()?;
+        self.system_performance = mode;
+        Ok(())
+    }
+
+    /// The bits the profile ideapad was initialized with has for the current mode, or the first
+    /// built-in profile if it wasn't.
+    fn performance_bits(&mut self) -> anyhow::Result<(u32, u32)> {
+        self.ensure_working()?;
+        let profile = context::try_get().map_or_else(
+            || BuiltInProfile::ALL[0].get(),
+            |context| context.profile.clone(),
+        );
+
+        Ok(bits_of(&profile, self.system_performance))
+    }
+}
+
+/// The FCMO and SPMO bits `profile` has for `mode`.
+fn bits_of(profile: &Profile, mode: SystemPerformanceMode) -> (u32, u32) {
+    let bits = &profile.system_performance.bits;
+    let bit = match mode {
+        SystemPerformanceMode::IntelligentCooling => bits.intelligent_cooling,
+        SystemPerformanceMode::ExtremePerformance => bits.extreme_performance,
+        SystemPerformanceMode::BatterySaving => bits.battery_saving,
+    };
+
+    (u32::from(bit.fcmo()), u32::from(bit.spmo()))
+}
+
+/// [`Fake`] hardware whose state is kept in a file, so that it lasts across the commands run by
+/// the tests. A missing file is hardware with everything switched off.
+pub struct FakeFile {
+    path: PathBuf,
+}
+
+impl FakeFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn load(&self) -> anyhow::Result<Fake> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => serde_json::from_str(&contents).with_context(|| {
+                format!(
+                    "failed to deserialize the fake hardware in {}",
+                    self.path.display().bold()
+                )
+            }),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Fake::new()),
+            Err(error) => Err(error).with_context(|| {
+                format!(
+                    "failed to read the fake hardware in {}",
+                    self.path.display().bold()
+                )
+            }),
+        }
+    }
+
+    pub fn store(&self, fake: &Fake) -> anyhow::Result<()> {
+        let contents =
+            serde_json::to_string(fake).context("failed to serialize the fake hardware")?;
+
+        utils::write_atomic(&self.path, contents).with_context(|| {
+            format!(
+                "failed to write the fake hardware to {}",
+                self.path.display().bold()
+            )
+        })
+    }
+
+    /// Runs `f` on the stored fake hardware, storing it again afterwards even if `f` failed.
+    fn with<T>(&self, f: impl FnOnce(&mut Fake) -> anyhow::Result<T>) -> anyhow::Result<T> {
+        let mut fake = self.load()?;
+        let result = f(&mut fake);
+        self.store(&fake)?;
+
+        result
+    }
+}
+
+impl Hardware for FakeFile {
+    fn conservation(&mut self) -> anyhow::Result<bool> {
+        self.load()?.conservation()
+    }
+
+    fn set_conservation(&mut self, on: bool, handler: Handler) -> anyhow::Result<()> {
+        self.with(|fake| fake.set_conservation(on, handler))
+    }
+
+    fn rapid_charge(&mut self) -> anyhow::Result<bool> {
+        self.load()?.rapid_charge()
+    }
+
+    fn set_rapid_charge(&mut self, on: bool, handler: Handler) -> anyhow::Result<()> {
+        self.with(|fake| fake.set_rapid_charge(on, handler))
+    }
+
+    fn performance_mode(&mut self) -> anyhow::Result<SystemPerformanceMode> {
+        self.load()?.performance_mode()
+    }
+
+    fn set_performance_mode(&mut self, mode: SystemPerformanceMode) -> anyhow::Result<()> {
+        self.with(|fake| fake.set_performance_mode(mode))
+    }
+
+    fn performance_bits(&mut self) -> anyhow::Result<(u32, u32)> {
+        self.load()?.performance_bits()
+    }
+}
+
+/// A battery which only exists in memory, for testing which batteries are matched without the
+/// ones of the machine running the tests.
+#[cfg(feature = "regulate")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeBattery {
+    pub vendor: Option<String>,
+    pub model: Option<String>,
+    pub serial_number: Option<String>,
+    pub state: battery::State,
+
+    /// In watt-hours, which is 0 for docks and power banks.
+    pub design_capacity: f32,
+}
+
+#[cfg(feature = "regulate")]
+impl FakeBattery {
+    pub fn new() -> Self {
+        Self {
+            vendor: None,
+            model: None,
+            serial_number: None,
+            state: battery::State::Unknown,
+            design_capacity: 45.0,
+        }
+    }
+}
+
+#[cfg(feature = "regulate")]
+impl Default for FakeBattery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "regulate")]
+impl config::BatteryFields for FakeBattery {
+    fn vendor(&self) -> Option<&str> {
+        self.vendor.as_deref()
+    }
+
+    fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    fn serial_number(&self) -> Option<&str> {
+        self.serial_number.as_deref()
+    }
+
+    fn state(&self) -> battery::State {
+        self.state
+    }
+
+    fn has_design_capacity(&self) -> bool {
+        self.design_capacity > 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_reads_every_setting() {
+        let mut hardware = Fake {
+            battery_conservation: true,
+            system_performance: SystemPerformanceMode::BatterySaving,
+            ..Fake::new()
+        };
+
+        assert_eq!(
+            Snapshot::read(&mut hardware).unwrap(),
+            Snapshot {
+                battery_conservation: true,
+                rapid_charge: false,
+                system_performance: SystemPerformanceMode::BatterySaving,
+            }
+        );
+    }
+
+    #[test]
+    fn snapshot_fails_with_the_setting_it_failed_on() {
+        let mut hardware = Fake {
+            broken: true,
+            ..Fake::new()
+        };
+        let error = Snapshot::read(&mut hardware).unwrap_err();
+
+        assert_eq!(
+            error.source.to_string(),
+            "failed to get battery conservation mode value"
+        );
+    }
+
+    #[test]
+    fn switch_handler_switches_the_other_setting_off() {
+        let mut hardware = Fake {
+            rapid_charge: true,
+            ..Fake::new()
+        };
+        hardware.set_conservation(true, Handler::Switch).unwrap();
+
+        assert!(hardware.conservation().unwrap());
+        assert!(!hardware.rapid_charge().unwrap());
+
+        hardware.set_rapid_charge(true, Handler::Switch).unwrap();
+
+        assert!(!hardware.conservation().unwrap());
+        assert!(hardware.rapid_charge().unwrap());
+    }
+
+    #[test]
+    fn error_handler_changes_nothing() {
+        let mut hardware = Fake {
+            rapid_charge: true,
+            ..Fake::new()
+        };
+
+        assert!(hardware.set_conservation(true, Handler::Error).is_err());
+        assert_eq!(
+            hardware,
+            Fake {
+                rapid_charge: true,
+                ..Fake::new()
+            }
+        );
+    }
+
+    #[test]
+    fn ignore_handler_leaves_the_other_setting_on() {
+        let mut hardware = Fake {
+            battery_conservation: true,
+            ..Fake::new()
+        };
+        hardware.set_rapid_charge(true, Handler::Ignore).unwrap();
+
+        assert!(hardware.conservation().unwrap());
+        assert!(hardware.rapid_charge().unwrap());
+    }
+
+    #[test]
+    fn disabling_ignores_the_handler() {
+        let mut hardware = Fake {
+            battery_conservation: true,
+            rapid_charge: true,
+            ..Fake::new()
+        };
+        hardware.set_conservation(false, Handler::Error).unwrap();
+
+        assert!(!hardware.conservation().unwrap());
+        assert!(hardware.rapid_charge().unwrap());
+    }
+
+    #[test]
+    fn performance_mode_round_trips() {
+        let mut hardware = Fake::new();
+        hardware
+            .set_performance_mode(SystemPerformanceMode::ExtremePerformance)
+            .unwrap();
+
+        assert_eq!(
+            hardware.performance_mode().unwrap(),
+            SystemPerformanceMode::ExtremePerformance
+        );
+    }
+
+    #[test]
+    fn broken_hardware_changes_nothing() {
+        let mut hardware = Fake {
+            broken: true,
+            ..Fake::new()
+        };
+
+        assert!(hardware.set_conservation(true, Handler::Switch).is_err());
+        assert!(!hardware.battery_conservation);
+    }
+
+    #[test]
+    fn fake_file_keeps_the_state() {
+        let path = env::temp_dir().join(format!(
+            "tuxvantage-fake-hardware-{}-{:016x}.json",
+            std::process::id(),
+            fastrand::u64(..)
+        ));
+        let mut hardware = FakeFile::new(path.clone());
+
+        assert_eq!(hardware.load().unwrap(), Fake::new());
+
+        hardware.set_rapid_charge(true, Handler::Switch).unwrap();
+        hardware
+            .set_performance_mode(SystemPerformanceMode::BatterySaving)
+            .unwrap();
+
+        assert_eq!(
+            FakeFile::new(path.clone()).load().unwrap(),
+            Fake {
+                rapid_charge: true,
+                system_performance: SystemPerformanceMode::BatterySaving,
+                ..Fake::new()
+            }
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn fake_file_accepts_command_line_spellings() {
+        let fake: Fake = serde_json::from_str(r#"{"system_performance": "ep"}"#).unwrap();
+
+        assert_eq!(
+            fake,
+            Fake {
+                system_performance: SystemPerformanceMode::ExtremePerformance,
+                ..Fake::new()
+            }
+        );
+    }
+
+    #[test]
+    fn fake_bits_match_the_profile() {
+        let profile = BuiltInProfile::ALL[0].get();
+
+        for mode in [
+            SystemPerformanceMode::IntelligentCooling,
+            SystemPerformanceMode::ExtremePerformance,
+            SystemPerformanceMode::BatterySaving,
+        ] {
+            let mut hardware = Fake {
+                system_performance: mode,
+                ..Fake::new()
+            };
+
+            assert_eq!(
+                hardware.performance_bits().unwrap(),
+                bits_of(&profile, mode)
+            );
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{ec_cooldown, project_paths, utils};
+use anyhow::Context;
+use itertools::Itertools;
+use owo_colors::OwoColorize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{env, fs, io};
+
+/// How large the journal may grow before the older half of it is dropped.
+const MAX_SIZE: u64 = 256 * 1024;
+
+/// What made a change.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Initiator {
+    /// A command run by the user, such as `battery conservation enable`.
+    Cli,
+
+    /// The battery conservation regulator.
+    Regulate,
+
+    /// `tuxvantage apply`.
+    Apply,
+
+    /// `tuxvantage with`, changing the settings for a command and restoring them after.
+    With,
+
+    /// `rapid-charge top-up`, enabling rapid charging only while it is needed to meet a deadline.
+    TopUp,
+}
+
+impl Initiator {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Cli => "cli",
+            Self::Regulate => "regulate",
+            Self::Apply => "apply",
+            Self::With => "with",
+            Self::TopUp => "top_up",
+        }
+    }
+}
+
+/// A change to a setting this program made, as a line of `history.jsonl` in the state directory.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Change {
+    /// When the change was made, in seconds since the unix epoch.
+    pub time: u64,
+
+    /// The setting which was changed, such as `battery_conservation`.
+    pub setting: String,
+    pub old: String,
+    pub new: String,
+    pub initiator: Initiator,
+
+    /// The arguments the change was made with.
+    pub command: String,
+
+    /// Whether the change was made so soon after the previous change of the same setting that
+    /// the firmware may have ignored it.
+    #[serde(default)]
+    pub within_ec_cooldown: bool,
+}
+
+fn append(change: &Change) -> anyhow::Result<()> {
+    project_paths::ensure_state_dir()?;
+
+    let path = project_paths::history_jsonl();
+    let line = serde_json::to_string(change).context("failed to serialize the change")?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|error| project_paths::write_error(path, error))
+        .with_context(|| format!("failed to open {}", "history.jsonl".bold()))?;
+
+    writeln!(file, "{}", line)
+        .with_context(|| format!("failed to write to {}", "history.jsonl".bold()))?;
+
+    if file.metadata().map_or(0, |metadata| metadata.len()) > MAX_SIZE {
+        debug!("the history is too large, drop the older half");
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", "history.jsonl".bold()))?;
+        let lines = contents.lines().collect::<Vec<_>>();
+        let kept = lines[lines.len() / 2..].iter().join("\n") + "\n";
+
+        utils::write_atomic(path, kept)
+            .map_err(|error| project_paths::write_error(path, error))
+            .with_context(|| format!("failed to write to {}", "history.jsonl".bold()))?;
+    }
+
+    Ok(())
+}
+
+/// Records that `initiator` changed `setting` from `old` to `new`, along with when it was written
+/// for the ec cooldown guard. This is best-effort, so that being unable to record a change never
+/// stops it from being made.
+pub fn record(setting: &str, old: impl ToString, new: impl ToString, initiator: Initiator) {
+    let within_ec_cooldown = ec_cooldown::record(setting);
+    let change = Change {
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_secs(),
+        setting: setting.to_string(),
+        old: old.to_string(),
+        new: new.to_string(),
+        initiator,
+        command: env::args().skip(1).join(" "),
+        within_ec_cooldown,
+    };
+
+    if let Err(error) = append(&change) {
+        debug!("failed to record the change to the history: {:#}", error);
+    }
+}
+
+/// The last `limit` changes, oldest first. Lines which can't be read are skipped.
+pub fn get(limit: usize) -> anyhow::Result<Vec<Change>> {
+    let contents = match fs::read_to_string(project_paths::history_jsonl()) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => {
+            return Err(error).with_context(|| format!("failed to read {}", "history.jsonl".bold()))
+        }
+    };
+    let changes = contents
+        .lines()
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(change) => Some(change),
+            Err(error) => {
+                debug!("skipping an unreadable line of the history: {}", error);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Ok(changes[changes.len().saturating_sub(limit)..].to_vec())
+}
@@ -0,0 +1,281 @@
+use crate::utils::{self, Names};
+#[cfg(feature = "service-install")]
+use anyhow::Context;
+#[cfg(feature = "service-install")]
+use itertools::Itertools;
+#[cfg(feature = "service-install")]
+use owo_colors::OwoColorize;
+use std::fmt;
+#[cfg(feature = "regulate")]
+use std::fs;
+#[cfg(feature = "service-install")]
+use std::io;
+use std::path::Path;
+#[cfg(feature = "service-install")]
+use std::path::PathBuf;
+#[cfg(feature = "regulate")]
+use std::process::{Command, Output};
+use std::str::FromStr;
+
+/// Where runit services are linked to enable them, which differs between distributions.
+#[cfg(feature = "regulate")]
+const RUNIT_SERVICE_DIRS: &[&str] = &["/var/service", "/etc/runit/runsvdir/default"];
+
+/// An init system which the regulator can be installed as a service of.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InitSystem {
+    Systemd,
+    Openrc,
+    Runit,
+}
+
+impl InitSystem {
+    pub const NAMES: Names<Self> = &[
+        (Self::Systemd, &["systemd"]),
+        (Self::Openrc, &["openrc"]),
+        (Self::Runit, &["runit"]),
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Systemd => "systemd",
+            Self::Openrc => "openrc",
+            Self::Runit => "runit",
+        }
+    }
+
+    /// The init system this machine was booted with, going by the directories each of them
+    /// creates in `/run`.
+    #[cfg(feature = "service-install")]
+    pub fn detect() -> anyhow::Result<Self> {
+        if utils::is_systemd()? {
+            return Ok(Self::Systemd);
+        }
+
+        let detected = [(Self::Openrc, "/run/openrc"), (Self::Runit, "/run/runit")]
+            .into_iter()
+            .find(|(_, marker)| Path::new(marker).exists())
+            .map(|(init_system, _)| init_system);
+
+        detected.with_context(|| {
+            format!(
+                "failed to detect the init system, pass one of {} with {}",
+                Self::NAMES
+                    .iter()
+                    .map(|(value, _)| value.name().bold().to_string())
+                    .join(", "),
+                "--init-system".bold()
+            )
+        })
+    }
+
+    /// Where the services of this init system are installed unless `--unit-dir` says otherwise.
+    #[cfg(feature = "service-install")]
+    pub fn default_dir(self) -> &'static str {
+        match self {
+            Self::Systemd => "/etc/systemd/system",
+            Self::Openrc => "/etc/init.d",
+            Self::Runit => "/etc/sv",
+        }
+    }
+
+    /// The file a service named `name`, such as `bcm.service`, is written to in `dir`. The other
+    /// init systems name their services without the `.service` suffix, and runit wants a
+    /// directory with a `run` script in it.
+    #[cfg(feature = "service-install")]
+    pub fn unit_path(self, dir: &Path, name: &str) -> PathBuf {
+        let stem = name.strip_suffix(".service").unwrap_or(name);
+
+        match self {
+            Self::Systemd => dir.join(name),
+            Self::Openrc => dir.join(stem),
+            Self::Runit => dir.join(stem).join("run"),
+        }
+    }
+
+    /// The name the init system knows the service at `unit` by, which is how it is referred to
+    /// when starting or stopping it.
+    pub fn service_name(self, unit: &Path) -> String {
+        let name = match self {
+            Self::Systemd | Self::Openrc => unit.file_name(),
+            Self::Runit => unit.parent().and_then(Path::file_name),
+        };
+
+        name.map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// A line of the service which sets the environment variable `key` to `value`.
+    #[cfg(feature = "service-install")]
+    pub fn environment_line(self, key: &str, value: &Path) -> String {
+        match self {
+            Self::Systemd => format!("Environment=\"{}={}\"\n", key, value.display()),
+            Self::Openrc | Self::Runit => format!("export {}=\"{}\"\n", key, value.display()),
+        }
+    }
+
+    /// How to enable and start the service at `unit` once it is installed.
+    #[cfg(feature = "service-install")]
+    pub fn enable_hint(self, unit: &Path) -> String {
+        let service = self.service_name(unit);
+
+        match self {
+            Self::Systemd => format!("systemctl enable --now {}", service),
+            Self::Openrc => format!("rc-update add {0} default && rc-service {0} start", service),
+            Self::Runit => format!(
+                "ln -s {} /var/service/",
+                unit.parent().unwrap_or(unit).display()
+            ),
+        }
+    }
+
+    /// Whether the service at `unit` is running, or `None` if the init system couldn't be asked.
+    #[cfg(feature = "regulate")]
+    pub fn is_active(self, unit: &Path) -> Option<bool> {
+        let service = self.service_name(unit);
+
+        match self {
+            Self::Systemd => output("systemctl", &["is-active", &service])
+                .map(|output| stdout(&output) == "active"),
+            Self::Openrc => {
+                output("rc-service", &[&service, "status"]).map(|output| output.status.success())
+            }
+            Self::Runit => output("sv", &["status", &unit.parent()?.to_string_lossy()])
+                .map(|output| stdout(&output).starts_with("run:")),
+        }
+    }
+
+    /// Whether the service at `unit` is started on boot, or `None` if that couldn't be found out.
+    #[cfg(feature = "regulate")]
+    pub fn is_enabled(self, unit: &Path) -> Option<bool> {
+        let service = self.service_name(unit);
+
+        match self {
+            Self::Systemd => output("systemctl", &["is-enabled", &service])
+                .map(|output| stdout(&output) == "enabled"),
+            Self::Openrc => {
+                let mut runlevels = fs::read_dir("/etc/runlevels").ok()?.flatten();
+
+                Some(runlevels.any(|runlevel| runlevel.path().join(&service).exists()))
+            }
+            Self::Runit => Some(
+                RUNIT_SERVICE_DIRS
+                    .iter()
+                    .any(|dir| Path::new(dir).join(&service).symlink_metadata().is_ok()),
+            ),
+        }
+    }
+
+    /// Stops the service at `unit` and removes it, along with everything which enables it.
+    #[cfg(feature = "service-install")]
+    pub fn remove(self, unit: &Path) -> anyhow::Result<()> {
+        let service = self.service_name(unit);
+
+        match self {
+            Self::Systemd => {
+                utils::run("systemctl", &["disable", "--now", &service])?;
+                remove_file(unit)?;
+                utils::run("systemctl", &["daemon-reload"])?;
+            }
+            Self::Openrc => {
+                utils::run("rc-service", &["--ifstarted", &service, "stop"])?;
+
+                // fails if the service was never added to a runlevel, which is fine
+                if let Err(error) = utils::run("rc-update", &["del", &service]) {
+                    debug!(
+                        "failed to remove {} from its runlevels: {:#}",
+                        service, error
+                    );
+                }
+
+                remove_file(unit)?;
+            }
+            Self::Runit => {
+                // runsvdir stops the service once its link is gone
+                for link in RUNIT_SERVICE_DIRS {
+                    let link = Path::new(link).join(&service);
+
+                    if link.symlink_metadata().is_ok() {
+                        remove_file(&link)?;
+                    }
+                }
+
+                if let Some(dir) = unit.parent().filter(|dir| dir.exists()) {
+                    fs::remove_dir_all(dir)
+                        .with_context(|| format!("failed to remove {}", dir.display().bold()))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The output of running `program` with `args`, or `None` if it couldn't be run, such as because
+/// it isn't installed.
+#[cfg(feature = "regulate")]
+fn output(program: &str, args: &[&str]) -> Option<Output> {
+    match Command::new(program).args(args).output() {
+        Ok(output) => Some(output),
+        Err(error) => {
+            debug!("failed to run {}: {}", program, error);
+            None
+        }
+    }
+}
+
+#[cfg(feature = "regulate")]
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+#[cfg(feature = "service-install")]
+fn remove_file(path: &Path) -> anyhow::Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(error) => {
+            Err(error).with_context(|| format!("failed to remove {}", path.display().bold()))
+        }
+    }
+}
+
+impl FromStr for InitSystem {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        utils::parse_name(Self::NAMES, "init system", s)
+    }
+}
+
+impl fmt::Display for InitSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(all(test, feature = "service-install"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn services_are_named_after_their_unit() {
+        let dir = Path::new("/usr/lib/services");
+
+        for (init_system, unit) in [
+            (InitSystem::Systemd, "/usr/lib/services/tuxvantage.service"),
+            (InitSystem::Openrc, "/usr/lib/services/tuxvantage"),
+            (InitSystem::Runit, "/usr/lib/services/tuxvantage/run"),
+        ] {
+            let path = init_system.unit_path(dir, "tuxvantage.service");
+
+            assert_eq!(path, Path::new(unit));
+            assert_eq!(
+                init_system.service_name(&path),
+                unit.trim_start_matches("/usr/lib/services/")
+                    .trim_end_matches("/run")
+            );
+        }
+    }
+}
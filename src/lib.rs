@@ -0,0 +1,68 @@
+//! Controls the battery conservation mode, rapid charging and system performance mode of Lenovo
+//! IdeaPad laptops.
+//!
+//! Besides the `tuxvantage` command line interface, the operations are available to other
+//! programs through [`api`], and the hardware they act on through [`hardware`]. Every other
+//! module is an implementation detail of the command line interface, and may change at any time.
+
+// leaving out the regulator leaves behind helpers and imports which only it uses
+#![cfg_attr(not(feature = "regulate"), allow(dead_code, unused_imports))]
+
+#[macro_use]
+extern crate serde;
+
+#[macro_use]
+mod macros;
+
+mod anyhow_with_tip;
+pub mod api;
+mod app;
+mod args;
+mod bug_report;
+mod cli;
+mod config;
+mod context;
+mod daemons;
+mod diff;
+mod ec_cooldown;
+mod examples;
+mod ext;
+mod format;
+pub mod hardware;
+mod history;
+mod init_system;
+mod log;
+mod machine;
+mod pager;
+mod project_paths;
+mod regulator;
+#[cfg(feature = "testing")]
+#[doc(hidden)]
+pub mod sandbox;
+mod schema;
+#[cfg(feature = "regulate")]
+mod sd_notify;
+#[cfg(feature = "regulate")]
+mod simulation;
+mod state;
+mod templates;
+mod thresholds;
+mod types;
+#[cfg(feature = "regulate")]
+mod upower;
+mod utils;
+mod validation;
+mod verbose;
+
+use crate::anyhow_with_tip::TippingAnyhowResultExt;
+
+// loading the profile directory, for `benches/profiles.rs`
+#[cfg(feature = "testing")]
+#[doc(hidden)]
+pub use crate::project_paths::profiles;
+
+/// Runs the `tuxvantage` command line interface with the arguments of this process, exiting with
+/// its exit code. This is all the `tuxvantage` binary does.
+pub fn run() {
+    cli::main()
+}
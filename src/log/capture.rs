@@ -0,0 +1,38 @@
+use parking_lot::Mutex;
+use std::fmt;
+
+static CAPTURED: Mutex<Option<Vec<String>>> = parking_lot::const_mutex(None);
+
+/// Records `line` if logs are being captured, returning whether it was.
+pub fn push(line: impl fmt::Display) -> bool {
+    match CAPTURED.lock().as_mut() {
+        Some(captured) => {
+            captured.push(line.to_string());
+            true
+        }
+        None => false,
+    }
+}
+
+/// Captures every logged line instead of printing it, until the guard is finished or dropped.
+pub struct Guard {
+    _priv: (),
+}
+
+impl Guard {
+    /// Stops capturing, returning the captured lines.
+    pub fn finish(self) -> Vec<String> {
+        CAPTURED.lock().take().unwrap_or_default()
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        CAPTURED.lock().take();
+    }
+}
+
+pub fn start() -> Guard {
+    *CAPTURED.lock() = Some(Vec::new());
+    Guard { _priv: () }
+}
@@ -1,10 +1,24 @@
+pub mod capture;
 pub mod no_prologue;
+#[cfg(feature = "regulate")]
+pub mod rotating;
+pub mod sink;
+pub mod tee;
 
-use crate::verbose;
+use crate::anyhow_with_tip::IntoTip;
+use crate::{anyhow_with_tip, machine, utils, verbose};
 use owo_colors::colors::*;
 use owo_colors::{Color, OwoColorize};
 use std::fmt;
 
+fn emit(line: impl fmt::Display) {
+    tee::push(&line);
+
+    if !capture::push(&line) && !sink::push(&line) {
+        utils::eprint_line(line)
+    }
+}
+
 fn log<C, P, M>(prologue: Option<P>, message: M)
 where
     C: Color,
@@ -14,7 +28,7 @@ where
     let prologue = match prologue {
         Some(prologue) => prologue.to_string(),
         None => {
-            eprintln!("{}", message);
+            emit(message);
             return;
         }
     };
@@ -25,17 +39,62 @@ where
     } else {
         return;
     };
-    eprintln!("{}{} {}", prologue.fg::<C>().bold(), ":".bold(), first_line);
+    emit(format_args!(
+        "{}{} {}",
+        prologue.fg::<C>().bold(),
+        ":".bold(),
+        first_line
+    ));
     let new_prologue = format!("{}{}", " ".repeat(prologue.len()), "|".bold());
 
     for line in lines {
-        eprintln!("{} {}", new_prologue, line);
+        emit(format_args!("{} {}", new_prologue, line));
+    }
+}
+
+/// Prints a warning followed by a tip on what to do about it. In machine mode, the warning is
+/// recorded into the machine output instead.
+pub fn warn_with_tip(message: impl fmt::Display, tip: impl IntoTip) {
+    let machine = machine::enabled();
+    let tip = anyhow_with_tip::filter_tip(tip.into_tip(), machine);
+
+    if machine {
+        machine::push_warning(message, tip);
+        return;
+    }
+
+    warn(message, true);
+
+    if let Some(tip) = tip {
+        let prologue = if matches!(no_prologue::for_what(), Some(Level::Warn)) {
+            None
+        } else {
+            Some("  tip")
+        };
+
+        log::<Cyan, _, _>(prologue, tip)
     }
 }
 
+/// Prints a debug line if verbose output is enabled. Like every other line, it goes to standard
+/// error. When it is read by something other than a person, such as in machine mode or when
+/// standard error isn't a terminal, every line is prefixed with `[debug]` and has its colors
+/// stripped, so that log collectors can tell the lines apart and standard output stays clean.
 pub fn debug(message: impl fmt::Display, prologue: bool) {
     if verbose::enabled() {
-        __debug(message, prologue)
+        if machine::enabled() || atty::isnt(atty::Stream::Stderr) {
+            plain_debug(message)
+        } else {
+            __debug(message, prologue)
+        }
+    } else if tee::enabled() {
+        tee::push(format_args!("debug: {}", message))
+    }
+}
+
+fn plain_debug(message: impl fmt::Display) {
+    for line in machine::strip_ansi(message.to_string()).lines() {
+        emit(format_args!("[debug] {}", line))
     }
 }
 
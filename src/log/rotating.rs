@@ -0,0 +1,208 @@
+//! The log file of the regulator, which is rotated once it grows too large so that a regulator
+//! running for weeks doesn't fill the disk.
+
+use super::sink;
+use owo_colors::OwoColorize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Opens the log file at `path` for appending, creating it readable only by its owner and group
+/// if it doesn't exist.
+pub fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o640)
+        .open(path)
+}
+
+/// A log file which moves to `<path>.1` once it would grow past its maximum size, shifting the
+/// older ones along and dropping the oldest.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: u32,
+    file: Option<File>,
+    size: u64,
+    echo: bool,
+    failing: bool,
+}
+
+impl RotatingFile {
+    /// Opens the log file at `path`, keeping `keep` rotated files of at most `max_size` bytes.
+    /// A `max_size` of 0 never rotates it.
+    pub fn open(path: PathBuf, max_size: u64, keep: u32) -> io::Result<Self> {
+        let file = open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            max_size,
+            keep,
+            file: Some(file),
+            size,
+            echo: false,
+            failing: false,
+        })
+    }
+
+    /// Also writes everything to standard error, or the log sink if there is one, for when the
+    /// regulator isn't daemonized and should keep logging to the terminal or the journal.
+    pub fn echo_to_stderr(mut self) -> Self {
+        self.echo = true;
+        self
+    }
+
+    /// The path of the `n`th rotated log file.
+    fn rotated(&self, n: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+
+        for n in (1..self.keep).rev() {
+            let from = self.rotated(n);
+
+            if from.exists() {
+                fs::rename(from, self.rotated(n + 1))?;
+            }
+        }
+
+        let result = if self.keep == 0 {
+            fs::remove_file(&self.path)
+        } else {
+            fs::rename(&self.path, self.rotated(1))
+        };
+
+        match result {
+            // someone else already moved it away, such as logrotate
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => {
+                self.size = 0;
+                Ok(())
+            }
+        }
+    }
+
+    fn write_record(&mut self, buf: &[u8]) -> io::Result<()> {
+        let record = strip_ansi_escapes::strip(buf)?;
+
+        if self.max_size != 0 && self.size != 0 && self.size + record.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let file = open(&self.path)?;
+                self.size = file.metadata()?.len();
+                self.file.insert(file)
+            }
+        };
+
+        file.write_all(&record)?;
+        self.size += record.len() as u64;
+
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    /// Never fails, so that a full disk or a removed directory doesn't stop the regulator. Failures
+    /// are warned about once until writing works again, and the file is reopened on the next write.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.echo {
+            let _ = sink::Stderr.write_all(buf);
+        }
+
+        match self.write_record(buf) {
+            Ok(()) => self.failing = false,
+            Err(error) => {
+                if !self.failing {
+                    warn!(
+                        "failed to write to the log file {}: {}",
+                        self.path.display().bold(),
+                        error
+                    );
+                }
+
+                self.failing = true;
+                self.file = None;
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(file) = &mut self.file {
+            let _ = file.flush();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::Sandbox;
+
+    fn read(path: PathBuf) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn full_files_are_rotated_and_the_oldest_dropped() {
+        let sandbox = Sandbox::new().unwrap();
+        let path = sandbox.path("regulator.log");
+        let mut log = RotatingFile::open(path.clone(), 8, 2).unwrap();
+
+        for record in ["first\n", "second\n", "third\n", "fourth\n"] {
+            log.write_all(record.as_bytes()).unwrap();
+        }
+
+        assert_eq!(read(path.clone()), "fourth\n");
+        assert_eq!(read(log.rotated(1)), "third\n");
+        assert_eq!(read(log.rotated(2)), "second\n");
+        assert!(!log.rotated(3).exists());
+    }
+
+    #[test]
+    fn records_are_written_without_colors() {
+        let sandbox = Sandbox::new().unwrap();
+        let path = sandbox.path("regulator.log");
+        let mut log = RotatingFile::open(path.clone(), 0, 2).unwrap();
+
+        writeln!(log, "{} was enabled", "rapid charge".bold()).unwrap();
+
+        assert_eq!(read(path), "rapid charge was enabled\n");
+    }
+
+    #[test]
+    fn writing_resumes_once_the_directory_is_back() {
+        let sandbox = Sandbox::new().unwrap();
+        let directory = sandbox.path("log");
+        let path = directory.join("regulator.log");
+        fs::create_dir(&directory).unwrap();
+        let mut log = RotatingFile::open(path.clone(), 8, 1).unwrap();
+
+        log.write_all(b"first\n").unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        // rotating finds the file gone, and reopening it fails without the directory
+        log.write_all(b"second\n").unwrap();
+        assert!(log.failing);
+
+        fs::create_dir(&directory).unwrap();
+        log.write_all(b"third\n").unwrap();
+
+        assert!(!log.failing);
+        assert_eq!(read(path), "third\n");
+    }
+}
@@ -0,0 +1,50 @@
+use crate::machine;
+use parking_lot::RwLock;
+use std::fmt;
+use std::io::{self, Write};
+
+type Sink = Box<dyn Fn(&str) + Send + Sync>;
+
+static SINK: RwLock<Option<Sink>> = parking_lot::const_rwlock(None);
+
+/// Hands every logged line to `sink` instead of printing it to standard error, for programs which
+/// use tuxvantage as a library. The lines have their colors stripped.
+pub fn set(sink: impl Fn(&str) + Send + Sync + 'static) {
+    *SINK.write() = Some(Box::new(sink));
+}
+
+/// Prints the logged lines to standard error again.
+pub fn clear() {
+    SINK.write().take();
+}
+
+/// Hands `line` to the sink if there is one, returning whether there was.
+pub fn push(line: impl fmt::Display) -> bool {
+    match SINK.read().as_ref() {
+        Some(sink) => {
+            sink(&machine::strip_ansi(line.to_string()));
+            true
+        }
+        None => false,
+    }
+}
+
+/// Standard error for loggers which format their records themselves, such as the one of the
+/// regulator. Each record written is handed to the sink instead if there is one.
+pub struct Stderr;
+
+impl Write for Stderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let record = String::from_utf8_lossy(buf);
+
+        if !push(record.trim_end_matches('\n')) {
+            io::stderr().write_all(buf)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
@@ -0,0 +1,34 @@
+use parking_lot::Mutex;
+use std::fmt;
+
+static TEED: Mutex<Option<Vec<String>>> = parking_lot::const_mutex(None);
+
+/// Starts keeping a copy of every logged line, including debug lines which aren't printed since
+/// verbose output is disabled.
+pub fn start() {
+    *TEED.lock() = Some(Vec::new());
+}
+
+pub fn enabled() -> bool {
+    TEED.lock().is_some()
+}
+
+/// Keeps a copy of `line` if lines are being teed.
+pub fn push(line: impl fmt::Display) {
+    if let Some(teed) = TEED.lock().as_mut() {
+        teed.push(line.to_string());
+    }
+}
+
+/// The lines teed so far, with their colors stripped.
+pub fn lines() -> Vec<String> {
+    TEED.lock()
+        .iter()
+        .flatten()
+        .map(|line| {
+            strip_ansi_escapes::strip(line)
+                .map(|line| String::from_utf8_lossy(&line).into_owned())
+                .unwrap_or_else(|_| line.clone())
+        })
+        .collect()
+}
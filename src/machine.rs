@@ -1,6 +1,207 @@
-use crate::anyhow_with_tip;
-use itertools::Itertools;
+use crate::context::{self, ActiveProfile};
+use crate::{anyhow_with_tip, ext, utils};
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use parking_lot::Mutex;
 use serde::Serialize;
+use std::env;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// The environment variable which picks the version of the machine output, if `--machine-version`
+/// isn't given.
+pub const VERSION_ENV: &str = "TUXVANTAGE_MACHINE_VERSION";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static VERSION: AtomicU32 = AtomicU32::new(Version::DEFAULT.0);
+static WARNINGS: Mutex<Vec<Warning>> = parking_lot::const_mutex(Vec::new());
+
+/// How many messages of an error chain a failure has at most, so that a runaway chain doesn't
+/// bury the useful ones.
+const MAX_CHAIN_LEN: usize = 32;
+
+/// How many characters a message of a failure has at most.
+const MAX_MESSAGE_LEN: usize = 4096;
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+pub fn set(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst)
+}
+
+/// The version of the machine output to print.
+pub fn version() -> Version {
+    Version(VERSION.load(Ordering::SeqCst))
+}
+
+pub fn set_version(version: Version) {
+    VERSION.store(version.0, Ordering::SeqCst)
+}
+
+/// The version given with [`VERSION_ENV`], if it is set.
+pub fn version_from_env() -> anyhow::Result<Option<Version>> {
+    env::var(VERSION_ENV)
+        .ok()
+        .map(|version| version.parse())
+        .transpose()
+        .with_context(|| format!("{} is invalid", VERSION_ENV.bold()))
+}
+
+/// A version of the shape of the machine output. Version 1 is the shape machine output has always
+/// had, and every later version only adds to it, so that scripts written against one version keep
+/// working until they ask for a newer one.
+///
+/// - `1`: the status, the contents, and the warnings and profile if there are any.
+/// - `2`: like `1`, but the envelope also states its `version`, and `warnings` and `profile` are
+///   always present, being empty or `null` if there aren't any.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(try_from = "u32", into = "u32")]
+pub struct Version(u32);
+
+impl Version {
+    pub const DEFAULT: Self = Self(1);
+    pub const SUPPORTED: RangeInclusive<u32> = 1..=2;
+
+    pub fn new(version: u32) -> Result<Self, UnsupportedVersionError> {
+        if Self::SUPPORTED.contains(&version) {
+            Ok(Self(version))
+        } else {
+            Err(UnsupportedVersionError { version })
+        }
+    }
+
+    pub const fn get(self) -> u32 {
+        self.0
+    }
+}
+
+impl Default for Version {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for Version {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let version = s
+            .trim()
+            .parse::<u32>()
+            .with_context(|| format!("{} isn't a machine output version", s.bold()))?;
+
+        Ok(Self::new(version)?)
+    }
+}
+
+impl TryFrom<u32> for Version {
+    type Error = UnsupportedVersionError;
+
+    fn try_from(version: u32) -> Result<Self, Self::Error> {
+        Self::new(version)
+    }
+}
+
+impl From<Version> for u32 {
+    fn from(version: Version) -> Self {
+        version.0
+    }
+}
+
+/// A version of the machine output which this version of tuxvantage can't print.
+#[derive(Debug)]
+pub struct UnsupportedVersionError {
+    pub version: u32,
+}
+
+impl fmt::Display for UnsupportedVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "machine output version {} isn't supported, the supported versions are {} to {}",
+            self.version.bold(),
+            Version::SUPPORTED.start(),
+            Version::SUPPORTED.end()
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedVersionError {}
+
+/// `message` without its ansi escapes. Anything which isn't valid utf-8 afterwards is replaced,
+/// and `message` is kept as is if it can't be stripped, so that a failure can always be printed.
+pub fn strip_ansi(message: String) -> String {
+    match strip_ansi_escapes::strip(&message) {
+        Ok(stripped) => String::from_utf8_lossy(&stripped).into_owned(),
+        Err(_) => message,
+    }
+}
+
+/// Cuts `message` off after [`MAX_MESSAGE_LEN`] characters, saying how much was cut.
+fn truncate(message: String) -> String {
+    match message.char_indices().nth(MAX_MESSAGE_LEN) {
+        Some((end, _)) => format!("{}… ({} more bytes)", &message[..end], message.len() - end),
+        None => message,
+    }
+}
+
+/// `chain` with its messages truncated and at most [`MAX_CHAIN_LEN`] of them, the last of which
+/// says how many were left out. The full chain is logged as a debug line if anything was cut.
+fn truncate_chain(chain: Vec<String>) -> Vec<String> {
+    let too_long = chain.len() > MAX_CHAIN_LEN
+        || chain
+            .iter()
+            .any(|message| message.chars().count() > MAX_MESSAGE_LEN);
+
+    if !too_long {
+        return chain;
+    }
+
+    debug!("the full error chain of the failure:\n{:#?}", chain);
+
+    let len = chain.len();
+    let kept = if len > MAX_CHAIN_LEN {
+        MAX_CHAIN_LEN - 1
+    } else {
+        len
+    };
+    let mut truncated = chain
+        .into_iter()
+        .take(kept)
+        .map(truncate)
+        .collect::<Vec<_>>();
+
+    if kept < len {
+        truncated.push(format!("… {} more", len - kept));
+    }
+
+    truncated
+}
+
+#[derive(Serialize)]
+pub struct Warning {
+    pub message: String,
+    pub tip: Option<String>,
+}
+
+/// Records a warning to be included in the machine output, since warnings aren't printed in
+/// machine mode.
+pub fn push_warning(message: impl ToString, tip: Option<impl ToString>) {
+    WARNINGS.lock().push(Warning {
+        message: strip_ansi(message.to_string()),
+        tip: tip.map(|tip| strip_ansi(tip.to_string())),
+    })
+}
 
 #[derive(Serialize)]
 #[serde(tag = "status", content = "contents")]
@@ -8,34 +209,172 @@ pub enum Machine<S: Serialize> {
     Success(S),
     Failure {
         chain: Vec<String>,
+
+        /// A stable identifier of the kind of error, if it is a known one.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        code: Option<&'static str>,
         tip: Option<String>,
+
+        /// The backtrace of a panic, if backtraces are enabled.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        backtrace: Option<String>,
     },
 }
 
+/// The envelope of version 1.
+#[derive(Serialize)]
+pub struct Output<S: Serialize> {
+    #[serde(flatten)]
+    pub machine: Machine<S>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<Warning>,
+
+    /// The profile used to access the hardware, if the command did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<&'static ActiveProfile>,
+}
+
+/// The envelope of version 2 and later, which states its version and always has every field.
+#[derive(Serialize)]
+pub struct OutputV2<S: Serialize> {
+    pub version: Version,
+
+    #[serde(flatten)]
+    pub machine: Machine<S>,
+    pub warnings: Vec<Warning>,
+    pub profile: Option<&'static ActiveProfile>,
+}
+
+/// The machine output in the shape of one [`Version`].
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Envelope<S: Serialize> {
+    V1(Output<S>),
+    V2(OutputV2<S>),
+}
+
 impl<S: Serialize> Machine<S> {
     pub fn success(value: S) -> Self {
         Self::Success(value)
     }
 
     pub fn failure(error: impl Into<anyhow_with_tip::Error>) -> Self {
-        let error = error.into();
-        let chain = error
-            .source
-            .chain()
-            .map(|error| error.to_string())
-            .map(strip_ansi_escapes::strip)
-            .map(|error| error.expect("failed to strip ansi escapes from error message"))
-            .map(String::from_utf8)
-            .map(|error| {
-                error.expect(
-                    "error message contains invalid utf-8 after stripping it of ansi escapes",
-                )
-            })
-            .unique()
-            .collect();
+        Self::failure_of(&error.into())
+    }
+
+    /// Like [`Self::failure`], but without consuming `error`.
+    pub fn failure_of(error: &anyhow_with_tip::Error) -> Self {
+        let chain = truncate_chain(utils::dedup_error_chain(
+            error
+                .source
+                .chain()
+                .map(|error| error.to_string())
+                .map(strip_ansi),
+        ));
         Self::Failure {
             chain,
-            tip: error.tip,
+            code: ext::error_code(&error.source),
+            tip: error.tip.as_ref().map(|tip| tip.message.clone()),
+            backtrace: None,
+        }
+    }
+
+    /// The failure of a panic with `message`, which happened at `location`.
+    pub fn panic(message: &str, location: impl fmt::Display, backtrace: Option<String>) -> Self {
+        Self::Failure {
+            chain: truncate_chain(vec![strip_ansi(format!(
+                "panicked at {}: {}",
+                location, message
+            ))]),
+            code: Some("panic"),
+            tip: Some("this is a bug, please report it".to_string()),
+            backtrace,
+        }
+    }
+
+    /// Wraps this output into the envelope of `version`, along with the warnings recorded so far.
+    /// Every machine output is printed through this, so that its shape only depends on
+    /// `version`.
+    pub fn envelope(self, version: Version) -> Envelope<S> {
+        let warnings = WARNINGS.lock().drain(..).collect();
+        let profile = context::active_profile();
+
+        match version.0 {
+            1 => Envelope::V1(Output {
+                machine: self,
+                warnings,
+                profile,
+            }),
+            _ => Envelope::V2(OutputV2 {
+                version,
+                machine: self,
+                warnings,
+                profile,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    /// The chain of `machine` as it is read back from its JSON.
+    fn chain_of(machine: Machine<()>) -> Vec<String> {
+        let json = serde_json::to_string(&machine).unwrap();
+        let value = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+
+        value["contents"]["chain"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| message.as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn failure_with_non_utf8_paths_is_valid_json() {
+        let path = Path::new(OsStr::from_bytes(b"/tmp/caf\xe9/\xff\xfe"));
+        let error = anyhow::anyhow!("\x1b[1mno such file\x1b[0m")
+            .context(format!("failed to read {}", path.display()));
+        let chain = chain_of(Machine::failure(error));
+
+        assert_eq!(chain.len(), 2);
+        assert!(chain[0].starts_with("failed to read /tmp/caf"));
+        assert_eq!(chain[1], "no such file");
+    }
+
+    #[test]
+    fn huge_chains_are_capped() {
+        let mut error = anyhow::anyhow!("root cause");
+
+        for layer in 0..500 {
+            error = error.context(format!("layer {}", layer));
         }
+
+        let chain = chain_of(Machine::failure(error));
+
+        assert_eq!(chain.len(), MAX_CHAIN_LEN);
+        assert_eq!(chain[0], "layer 499");
+        assert_eq!(
+            chain.last().unwrap(),
+            &format!("… {} more", 501 - (MAX_CHAIN_LEN - 1))
+        );
+    }
+
+    #[test]
+    fn long_messages_are_truncated() {
+        let chain = chain_of(Machine::failure(anyhow::anyhow!(
+            "{}",
+            "é".repeat(MAX_MESSAGE_LEN + 10)
+        )));
+
+        assert_eq!(chain.len(), 1);
+        assert!(chain[0].starts_with(&"é".repeat(MAX_MESSAGE_LEN)));
+        assert!(chain[0].ends_with("… (20 more bytes)"));
     }
 }
@@ -4,7 +4,6 @@ macro_rules! log {
         $($fn_name:ident,)*
     ) => {
         $(
-        #[macro_export]
         macro_rules! $fn_name {
             (@prologue $d ($d arg:tt)*) => {
                  $crate::log::$fn_name(::std::format_args!($d ($d arg)*), true)
@@ -34,3 +33,9 @@ log! {
     tip,
     debug,
 }
+
+macro_rules! warn_with_tip {
+    ($message:expr, $tip:expr $(,)?) => {
+        $crate::log::warn_with_tip($message, $tip)
+    };
+}
@@ -0,0 +1,64 @@
+use crate::{log, machine, utils};
+use anyhow::Context;
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// The pager used if `$PAGER` isn't set.
+const DEFAULT_PAGER: &str = "less -R";
+
+fn page(lines: &[String]) -> anyhow::Result<()> {
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_PAGER.to_string());
+
+    debug!("page the output through `{}`", pager);
+    let mut child = Command::new("sh")
+        .args(["-c", &pager])
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run the pager `{}`", pager))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        for line in lines {
+            // the pager exiting early, such as when quitting `less`, closes the pipe
+            if writeln!(stdin, "{}", line).is_err() {
+                break;
+            }
+        }
+    }
+
+    child.wait().context("failed to wait on the pager")?;
+
+    Ok(())
+}
+
+/// Runs `f`, showing everything it logs through the pager if `enabled` is true, standard output
+/// is a terminal, and the output is taller than it. Otherwise, everything is logged as usual.
+pub fn paged<T>(enabled: bool, f: impl FnOnce() -> T) -> T {
+    if !enabled || machine::enabled() || atty::isnt(atty::Stream::Stdout) {
+        return f();
+    }
+
+    let height = match utils::terminal_size() {
+        Some((_, height)) => height,
+        None => return f(),
+    };
+
+    let capture = log::capture::start();
+    let value = f();
+    let lines = capture.finish();
+
+    if lines.len() < height {
+        lines.iter().for_each(utils::eprint_line);
+    } else if let Err(error) = page(&lines) {
+        debug!(
+            "failed to page the output, printing it instead: {:#}",
+            error
+        );
+        lines.iter().for_each(utils::eprint_line);
+    }
+
+    value
+}
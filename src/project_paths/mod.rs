@@ -1,32 +1,301 @@
 pub mod profiles;
 
+use crate::utils;
 use anyhow::Context;
 use directories::ProjectDirs;
 use once_cell::sync::{Lazy, OnceCell};
+use owo_colors::OwoColorize;
 use profiles::Profiles;
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
+use std::{env, fmt, fs, io};
+use tap::Pipe;
 
 static PROJECT_DIRS: OnceCell<ProjectDirs> = OnceCell::new();
-static PROFILES_DIR: Lazy<PathBuf> = Lazy::new(|| config_dir().join("profiles"));
+static CONFIG_DIR: OnceCell<PathBuf> = OnceCell::new();
+static PROFILES_DIR: OnceCell<PathBuf> = OnceCell::new();
+static CONFIG_DIR_SOURCE: OnceCell<PathSource> = OnceCell::new();
+static PROFILES_DIR_SOURCE: OnceCell<PathSource> = OnceCell::new();
+static STATE_DIR_OVERRIDE: OnceCell<PathBuf> = OnceCell::new();
+static RUNTIME_DIR_OVERRIDE: OnceCell<PathBuf> = OnceCell::new();
 static TUXVANTAGE_TOML: Lazy<PathBuf> = Lazy::new(|| config_dir().join("tuxvantage.toml"));
-static CONSISTENCY_JSON: Lazy<PathBuf> = Lazy::new(|| config_dir().join(".consistency.json"));
+static TUXVANTAGE_TOML_LOCK: Lazy<PathBuf> =
+    Lazy::new(|| config_dir().join("tuxvantage.toml.lock"));
+static STATE_DIR: Lazy<PathBuf> = Lazy::new(resolve_state_dir);
+static RUNTIME_DIR: Lazy<PathBuf> = Lazy::new(resolve_runtime_dir);
+static CONSISTENCY_JSON: Lazy<PathBuf> = Lazy::new(|| state_dir().join(".consistency.json"));
+static STATE_JSON: Lazy<PathBuf> = Lazy::new(|| state_dir().join("state.json"));
+static HISTORY_JSONL: Lazy<PathBuf> = Lazy::new(|| state_dir().join("history.jsonl"));
+static REGULATOR_JSON: Lazy<PathBuf> = Lazy::new(|| runtime_dir().join("regulator.json"));
+static REGULATOR_PID: Lazy<PathBuf> = Lazy::new(|| runtime_dir().join("regulator.pid"));
+static LEGACY_CONSISTENCY_JSON: Lazy<PathBuf> =
+    Lazy::new(|| config_dir().join(".consistency.json"));
 const QUALIFIER: &str = "com";
 const ORGANIZATION: &str = "ALinuxPerson";
 const APPLICATION: &str = "tuxvantage";
+const ROOT_STATE_DIR: &str = "/var/lib";
+const ROOT_RUNTIME_DIR: &str = "/run";
+pub const CONFIG_DIR_ENV: &str = "TUXVANTAGE_CONFIG_DIR";
+pub const PROFILES_DIR_ENV: &str = "TUXVANTAGE_PROFILES_DIR";
+pub const STATE_DIR_ENV: &str = "TUXVANTAGE_STATE_DIR";
+pub const RUNTIME_DIR_ENV: &str = "TUXVANTAGE_RUNTIME_DIR";
+const STATE_DIR_MODE: u32 = 0o755;
+const RUNTIME_DIR_MODE: u32 = 0o700;
 
-pub fn initialize() -> anyhow::Result<()> {
+/// Where a project path was taken from.
+#[derive(Serialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PathSource {
+    /// The `--config` flag.
+    Flag,
+
+    /// An environment variable, such as [`CONFIG_DIR_ENV`].
+    Environment,
+
+    /// The platform default, or the config directory for the profiles directory.
+    Default,
+}
+
+/// Initializes the project paths.
+///
+/// The config directory is taken from `config_dir_override` (the `--config` flag) if given, then
+/// from the [`CONFIG_DIR_ENV`] environment variable, then from the platform default. The profiles
+/// directory is taken from the [`PROFILES_DIR_ENV`] environment variable if set, otherwise it is
+/// the `profiles` directory inside of the config directory. The state and runtime directories
+/// depend on whether this program runs as root, unless [`STATE_DIR_ENV`] and [`RUNTIME_DIR_ENV`]
+/// override them in a build with the `testing` feature.
+pub fn initialize(config_dir_override: Option<PathBuf>) -> anyhow::Result<()> {
     debug!(
         "initialize project directories, qualifier = '{}', organization = '{}', application = '{}'",
         QUALIFIER, ORGANIZATION, APPLICATION
     );
-    let project_dirs = ProjectDirs::from(QUALIFIER, ORGANIZATION, APPLICATION)
-        .context("failed to get project directories")?;
+    let project_dirs = project_dirs()?;
+    let (config_dir, config_dir_source) = resolve_config_dir(&project_dirs, config_dir_override)?;
+    let (profiles_dir, profiles_dir_source) = match env_path(PROFILES_DIR_ENV)? {
+        Some(profiles_dir) => {
+            debug!("profiles directory overridden by `{}`", PROFILES_DIR_ENV);
+            (profiles_dir, PathSource::Environment)
+        }
+        None => (config_dir.join("profiles"), PathSource::Default),
+    };
+
+    let overridable = if cfg!(feature = "testing") {
+        &[
+            (STATE_DIR_ENV, &STATE_DIR_OVERRIDE),
+            (RUNTIME_DIR_ENV, &RUNTIME_DIR_OVERRIDE),
+        ][..]
+    } else {
+        &[]
+    };
+
+    for &(key, cell) in overridable {
+        if let Some(dir) = env_path(key)? {
+            debug!("directory overridden by `{}`", key);
+            let _ = cell.set(dir);
+        }
+    }
 
     let _ = PROJECT_DIRS.set(project_dirs);
+    let _ = CONFIG_DIR.set(config_dir);
+    let _ = PROFILES_DIR.set(profiles_dir);
+    let _ = CONFIG_DIR_SOURCE.set(config_dir_source);
+    let _ = PROFILES_DIR_SOURCE.set(profiles_dir_source);
 
     Ok(())
 }
 
+/// Initializes the project paths so that every one of them is inside of `root`, regardless of the
+/// environment and of whether this program runs as root. Used by the sandbox of the tests.
+#[cfg(test)]
+pub(crate) fn initialize_in(root: &Path) -> anyhow::Result<()> {
+    let root = ensure_absolute("the sandbox", root.to_path_buf())?;
+    let config_dir = root.join("config");
+
+    let _ = PROJECT_DIRS.set(project_dirs()?);
+    let _ = PROFILES_DIR.set(config_dir.join("profiles"));
+    let _ = CONFIG_DIR.set(config_dir);
+    let _ = CONFIG_DIR_SOURCE.set(PathSource::Flag);
+    let _ = PROFILES_DIR_SOURCE.set(PathSource::Default);
+    let _ = STATE_DIR_OVERRIDE.set(root.join("state"));
+    let _ = RUNTIME_DIR_OVERRIDE.set(root.join("runtime"));
+
+    Ok(())
+}
+
+fn project_dirs() -> anyhow::Result<ProjectDirs> {
+    ProjectDirs::from(QUALIFIER, ORGANIZATION, APPLICATION)
+        .context("failed to get project directories")
+}
+
+fn resolve_config_dir(
+    project_dirs: &ProjectDirs,
+    config_dir_override: Option<PathBuf>,
+) -> anyhow::Result<(PathBuf, PathSource)> {
+    match config_dir_override {
+        Some(config_dir) => {
+            debug!("config directory overridden by the `--config` flag");
+            ensure_absolute("--config", config_dir).map(|config_dir| (config_dir, PathSource::Flag))
+        }
+        None => match env_path(CONFIG_DIR_ENV)? {
+            Some(config_dir) => {
+                debug!("config directory overridden by `{}`", CONFIG_DIR_ENV);
+                Ok((config_dir, PathSource::Environment))
+            }
+            None => Ok((project_dirs.config_dir().to_path_buf(), PathSource::Default)),
+        },
+    }
+}
+
+/// The path to `tuxvantage.toml` the same way [`initialize`] would resolve it, for when it's
+/// needed before the project paths can be initialized.
+pub fn tuxvantage_toml_before_initialize(
+    config_dir_override: Option<PathBuf>,
+) -> anyhow::Result<PathBuf> {
+    resolve_config_dir(&project_dirs()?, config_dir_override)
+        .map(|(config_dir, _)| config_dir.join("tuxvantage.toml"))
+}
+
+fn ensure_absolute(source: &str, path: PathBuf) -> anyhow::Result<PathBuf> {
+    anyhow::ensure!(
+        path.is_absolute(),
+        "the path given by {}, {}, must be absolute",
+        source.bold(),
+        path.display().bold()
+    );
+
+    Ok(path)
+}
+
+fn env_path(key: &str) -> anyhow::Result<Option<PathBuf>> {
+    match env::var_os(key) {
+        Some(value) if !value.is_empty() => ensure_absolute(key, PathBuf::from(value)).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// The environment variables which need to be passed on to other invocations of this program
+/// (such as the regulator service) so that they resolve the same paths as this one.
+pub fn env_overrides() -> Vec<(&'static str, &'static Path)> {
+    let mut overrides = Vec::new();
+
+    if config_dir() != get_dirs().config_dir() {
+        overrides.push((CONFIG_DIR_ENV, config_dir()));
+    }
+
+    if profiles_dir() != config_dir().join("profiles") {
+        overrides.push((PROFILES_DIR_ENV, profiles_dir()));
+    }
+
+    if let Some(state_dir) = STATE_DIR_OVERRIDE.get() {
+        overrides.push((STATE_DIR_ENV, state_dir));
+    }
+
+    if let Some(runtime_dir) = RUNTIME_DIR_OVERRIDE.get() {
+        overrides.push((RUNTIME_DIR_ENV, runtime_dir));
+    }
+
+    overrides
+}
+
+fn resolve_state_dir() -> PathBuf {
+    if let Some(state_dir) = STATE_DIR_OVERRIDE.get() {
+        return state_dir.clone();
+    }
+
+    let project_dirs = get_dirs();
+
+    state_dir_for(
+        utils::is_root(),
+        project_dirs.state_dir(),
+        project_dirs.data_local_dir(),
+    )
+}
+
+/// The state directory of root if `root`, otherwise the one of the user with the given XDG state
+/// and local data directories.
+fn state_dir_for(root: bool, xdg_state_dir: Option<&Path>, data_local_dir: &Path) -> PathBuf {
+    if root {
+        debug!(
+            "running as root, state directory is under '{}'",
+            ROOT_STATE_DIR
+        );
+        return Path::new(ROOT_STATE_DIR).join(APPLICATION);
+    }
+
+    match xdg_state_dir {
+        Some(state_dir) => state_dir.to_path_buf(),
+        None => {
+            debug!(
+                "no state directory for this platform, falling back to the local data directory"
+            );
+            data_local_dir.join("state")
+        }
+    }
+}
+
+fn resolve_runtime_dir() -> PathBuf {
+    if let Some(runtime_dir) = RUNTIME_DIR_OVERRIDE.get() {
+        return runtime_dir.clone();
+    }
+
+    runtime_dir_for(
+        utils::is_root(),
+        get_dirs().runtime_dir(),
+        &Path::new("/run/user").join(utils::euid().to_string()),
+        state_dir(),
+    )
+}
+
+/// The runtime directory of root if `root`, otherwise the one of the user with the given XDG
+/// runtime directory, per-user runtime directory such as `/run/user/1000`, and state directory.
+fn runtime_dir_for(
+    root: bool,
+    xdg_runtime_dir: Option<&Path>,
+    user_runtime_dir: &Path,
+    state_dir: &Path,
+) -> PathBuf {
+    if root {
+        debug!(
+            "running as root, runtime directory is under '{}'",
+            ROOT_RUNTIME_DIR
+        );
+        return Path::new(ROOT_RUNTIME_DIR).join(APPLICATION);
+    }
+
+    if let Some(runtime_dir) = xdg_runtime_dir {
+        return runtime_dir.to_path_buf();
+    }
+
+    if user_runtime_dir.is_dir() {
+        debug!(
+            "no XDG_RUNTIME_DIR set, using '{}'",
+            user_runtime_dir.display()
+        );
+        user_runtime_dir.join(APPLICATION)
+    } else {
+        debug!("no XDG_RUNTIME_DIR set and no per-user runtime directory, falling back to the state directory");
+        state_dir.join("run")
+    }
+}
+
+fn ensure_dir(path: &'static Path, mode: u32) -> anyhow::Result<&'static Path> {
+    if !path.exists() {
+        debug!("create directory '{}' with mode {:o}", path.display(), mode);
+        fs::create_dir_all(path)
+            .with_context(|| format!("failed to create directory {}", path.display().bold()))?;
+        fs::Permissions::from_mode(mode)
+            .pipe(|permissions| fs::set_permissions(path, permissions))
+            .with_context(|| {
+                format!(
+                    "failed to set permissions of directory {}",
+                    path.display().bold()
+                )
+            })?;
+    }
+
+    Ok(path)
+}
+
 pub fn get_dirs() -> &'static ProjectDirs {
     PROJECT_DIRS
         .get()
@@ -34,21 +303,266 @@ pub fn get_dirs() -> &'static ProjectDirs {
 }
 
 pub fn config_dir() -> &'static Path {
-    get_dirs().config_dir()
+    CONFIG_DIR
+        .get()
+        .expect("project directories not initialized")
 }
 
 pub fn profiles_dir() -> &'static Path {
-    PROFILES_DIR.as_ref()
+    PROFILES_DIR
+        .get()
+        .expect("project directories not initialized")
+}
+
+/// Where the config directory was taken from.
+pub fn config_dir_source() -> PathSource {
+    *CONFIG_DIR_SOURCE
+        .get()
+        .expect("project directories not initialized")
+}
+
+/// Where the profiles directory was taken from.
+pub fn profiles_dir_source() -> PathSource {
+    *PROFILES_DIR_SOURCE
+        .get()
+        .expect("project directories not initialized")
 }
 
 pub fn tuxvantage_toml() -> &'static Path {
     TUXVANTAGE_TOML.as_ref()
 }
 
+/// The file locked while the configuration is being modified.
+pub fn tuxvantage_toml_lock() -> &'static Path {
+    TUXVANTAGE_TOML_LOCK.as_ref()
+}
+
+/// Directory for data which should persist across reboots but isn't configuration, such as the
+/// consistency state.
+pub fn state_dir() -> &'static Path {
+    STATE_DIR.as_ref()
+}
+
+/// Directory for data which only makes sense while the system is running, such as locks and
+/// status files.
+pub fn runtime_dir() -> &'static Path {
+    RUNTIME_DIR.as_ref()
+}
+
+/// Like [`state_dir`], but creates the directory if it doesn't exist yet.
+pub fn ensure_state_dir() -> anyhow::Result<&'static Path> {
+    ensure_dir(state_dir(), STATE_DIR_MODE).context("failed to ensure the state directory exists")
+}
+
+/// Like [`runtime_dir`], but creates the directory if it doesn't exist yet.
+pub fn ensure_runtime_dir() -> anyhow::Result<&'static Path> {
+    ensure_dir(runtime_dir(), RUNTIME_DIR_MODE)
+        .context("failed to ensure the runtime directory exists")
+}
+
 pub fn consistency_json() -> &'static Path {
     CONSISTENCY_JSON.as_ref()
 }
 
+pub fn state_json() -> &'static Path {
+    STATE_JSON.as_ref()
+}
+
+/// The journal of the changes this program made.
+pub fn history_jsonl() -> &'static Path {
+    HISTORY_JSONL.as_ref()
+}
+
+/// The status of the running battery conservation regulator.
+pub fn regulator_json() -> &'static Path {
+    REGULATOR_JSON.as_ref()
+}
+
+/// The pid of the battery conservation regulator started with `--daemonize`.
+pub fn regulator_pid() -> &'static Path {
+    REGULATOR_PID.as_ref()
+}
+
+/// Where `.consistency.json` used to live before it was moved into the state directory.
+pub fn legacy_consistency_json() -> &'static Path {
+    LEGACY_CONSISTENCY_JSON.as_ref()
+}
+
+/// The directories which are expected to be owned by the same user as the config directory,
+/// without those which are inside of another. The state directory of root is its own, so it isn't
+/// one of them when running as root.
+pub fn owned_dirs() -> Vec<&'static Path> {
+    let mut dirs = vec![config_dir(), profiles_dir()];
+
+    if !utils::is_root() {
+        dirs.push(state_dir());
+    }
+
+    dirs.iter()
+        .copied()
+        .filter(|dir| {
+            !dirs
+                .iter()
+                .any(|other| other != dir && dir.starts_with(other))
+        })
+        .collect()
+}
+
+/// A path inside of the configuration or state directories couldn't be written even though a
+/// directory above it can be, which usually means it was created by another user, such as by
+/// running this program with `sudo` once.
+#[derive(Debug)]
+pub struct ForeignOwnerError {
+    pub path: PathBuf,
+    source: io::Error,
+}
+
+impl fmt::Display for ForeignOwnerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} isn't writable by this user, although the directory it's in is",
+            self.path.display().bold()
+        )
+    }
+}
+
+impl std::error::Error for ForeignOwnerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Turns an error from writing `path` into a [`ForeignOwnerError`] if it was denied permission
+/// while a directory between it and the top of its tree is writable, so that it can be pointed at
+/// `tuxvantage paths --check`.
+pub fn write_error(path: &Path, error: io::Error) -> anyhow::Error {
+    let foreign = error.kind() == io::ErrorKind::PermissionDenied
+        && owned_dirs()
+            .into_iter()
+            .filter(|dir| path.starts_with(dir))
+            .any(|dir| {
+                path.ancestors()
+                    .skip(1)
+                    .take_while(|ancestor| ancestor.starts_with(dir))
+                    .any(utils::is_writable)
+            });
+
+    if foreign {
+        ForeignOwnerError {
+            path: path.to_path_buf(),
+            source: error,
+        }
+        .into()
+    } else {
+        error.into()
+    }
+}
+
 pub fn profiles() -> anyhow::Result<Profiles> {
     Profiles::new()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn root_state_dir_ignores_the_user_dirs() {
+        assert_eq!(
+            state_dir_for(
+                true,
+                Some(Path::new("/home/user/.local/state/tuxvantage")),
+                Path::new("/home/user/.local/share/tuxvantage"),
+            ),
+            Path::new("/var/lib/tuxvantage"),
+        );
+    }
+
+    #[test]
+    fn user_state_dir_is_the_xdg_state_dir() {
+        assert_eq!(
+            state_dir_for(
+                false,
+                Some(Path::new("/home/user/.local/state/tuxvantage")),
+                Path::new("/home/user/.local/share/tuxvantage"),
+            ),
+            Path::new("/home/user/.local/state/tuxvantage"),
+        );
+    }
+
+    #[test]
+    fn user_state_dir_falls_back_to_the_local_data_dir() {
+        assert_eq!(
+            state_dir_for(false, None, Path::new("/home/user/.local/share/tuxvantage")),
+            Path::new("/home/user/.local/share/tuxvantage/state"),
+        );
+    }
+
+    #[test]
+    fn root_runtime_dir_ignores_the_user_dirs() {
+        assert_eq!(
+            runtime_dir_for(
+                true,
+                Some(Path::new("/run/user/0")),
+                &env::temp_dir(),
+                Path::new("/var/lib/tuxvantage"),
+            ),
+            Path::new("/run/tuxvantage"),
+        );
+    }
+
+    #[test]
+    fn user_runtime_dir_is_the_xdg_runtime_dir() {
+        assert_eq!(
+            runtime_dir_for(
+                false,
+                Some(Path::new("/run/user/1000")),
+                &env::temp_dir(),
+                Path::new("/home/user/.local/state/tuxvantage"),
+            ),
+            Path::new("/run/user/1000"),
+        );
+    }
+
+    #[test]
+    fn user_runtime_dir_falls_back_to_the_per_user_runtime_dir() {
+        let user_runtime_dir = env::temp_dir();
+
+        assert_eq!(
+            runtime_dir_for(
+                false,
+                None,
+                &user_runtime_dir,
+                Path::new("/home/user/.local/state/tuxvantage"),
+            ),
+            user_runtime_dir.join("tuxvantage"),
+        );
+    }
+
+    #[test]
+    fn user_runtime_dir_falls_back_to_the_state_dir() {
+        assert_eq!(
+            runtime_dir_for(
+                false,
+                None,
+                &env::temp_dir().join("tuxvantage-no-such-runtime-dir"),
+                Path::new("/home/user/.local/state/tuxvantage"),
+            ),
+            Path::new("/home/user/.local/state/tuxvantage/run"),
+        );
+    }
+
+    #[test]
+    fn denied_writes_inside_a_writable_tree_point_at_the_audit() {
+        crate::sandbox::Sandbox::shared();
+        let denied = || io::Error::from(io::ErrorKind::PermissionDenied);
+
+        assert!(write_error(tuxvantage_toml(), denied()).is::<ForeignOwnerError>());
+        assert!(
+            !write_error(tuxvantage_toml(), io::ErrorKind::NotFound.into())
+                .is::<ForeignOwnerError>()
+        );
+        assert!(!write_error(Path::new("/proc/tuxvantage"), denied()).is::<ForeignOwnerError>());
+    }
+}
@@ -1,11 +1,13 @@
 use std::fs::{DirEntry, ReadDir};
-use std::path::PathBuf;
-use std::{fs, io};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::{fs, io, thread};
 
 use anyhow::Context;
 use ideapad::Profile;
 use owo_colors::OwoColorize;
 
+use crate::config::Declarations;
 use crate::project_paths;
 
 pub struct Profiles {
@@ -14,8 +16,13 @@ pub struct Profiles {
 
 impl Profiles {
     pub fn new() -> anyhow::Result<Self> {
+        Self::at(project_paths::profiles_dir())
+    }
+
+    /// The profiles in `dir` instead of the profile directory.
+    pub fn at(dir: &Path) -> anyhow::Result<Self> {
         Ok(Self {
-            entries: project_paths::profiles_dir()
+            entries: dir
                 .read_dir()
                 .context("failed to get entries of the profile directory")?,
         })
@@ -25,34 +32,197 @@ impl Profiles {
 #[derive(Clone)]
 pub struct ExternalProfile {
     pub profile: Profile,
+    pub declarations: Declarations,
     pub path: PathBuf,
 }
 
+/// A profile in the profile directory which couldn't be loaded.
+pub struct FailedProfile {
+    /// The path of the profile, unless the entry of the profile directory couldn't be read.
+    pub path: Option<PathBuf>,
+    pub error: anyhow::Error,
+}
+
+impl Profiles {
+    /// Loads every profile, reading and deserializing them on as many threads as there are CPUs.
+    /// The profiles are in the same order as when iterating.
+    pub fn load_all(self) -> Vec<Result<ExternalProfile, FailedProfile>> {
+        let mut entries = self.entries.collect::<Vec<_>>();
+        let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let chunk_size = (entries.len() / threads).max(1);
+        let mut handles = Vec::new();
+
+        debug!(
+            "load {} profile(s) in chunks of {}",
+            entries.len(),
+            chunk_size
+        );
+
+        while !entries.is_empty() {
+            let chunk = entries
+                .drain(..chunk_size.min(entries.len()))
+                .collect::<Vec<_>>();
+
+            handles.push(thread::spawn(move || {
+                chunk.into_iter().map(load_entry).collect::<Vec<_>>()
+            }));
+        }
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("loading profiles panicked"))
+            .collect()
+    }
+}
+
+fn load_entry(entry: io::Result<DirEntry>) -> Result<ExternalProfile, FailedProfile> {
+    let path = entry
+        .context("failed to get the next entry of the profile directory")
+        .map_err(|error| FailedProfile { path: None, error })?
+        .path();
+
+    load(path)
+}
+
+fn load(path: PathBuf) -> Result<ExternalProfile, FailedProfile> {
+    let failed = |error| FailedProfile {
+        path: Some(path.clone()),
+        error,
+    };
+    let contents = fs::read_to_string(&path)
+        .with_context(|| {
+            format!(
+                "failed to read contents of profile {}",
+                path.display().bold()
+            )
+        })
+        .map_err(failed)?;
+
+    let profile = serde_json::from_str(&contents)
+        .with_context(|| {
+            format!(
+                "failed to deserialize contents of profile {}",
+                path.display().bold()
+            )
+        })
+        .map_err(failed)?;
+
+    let declarations = serde_json::from_str(&contents)
+        .with_context(|| {
+            format!(
+                "failed to deserialize the declarations of profile {}",
+                path.display().bold()
+            )
+        })
+        .map_err(failed)?;
+
+    Ok(ExternalProfile {
+        profile,
+        declarations,
+        path,
+    })
+}
+
 impl Iterator for Profiles {
-    type Item = anyhow::Result<ExternalProfile>;
+    type Item = Result<ExternalProfile, FailedProfile>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        fn inner(entry: io::Result<DirEntry>) -> anyhow::Result<ExternalProfile> {
-            let path = entry
-                .context("failed to get the next entry of the profile directory")?
-                .path();
-            let contents = fs::read_to_string(&path).with_context(|| {
-                format!(
-                    "failed to read contents of profile {}",
-                    path.display().bold()
-                )
-            })?;
-
-            let profile = serde_json::from_str(&contents).with_context(|| {
-                format!(
-                    "failed to deserialize contents of profile {}",
-                    path.display().bold()
-                )
-            })?;
-
-            Ok(ExternalProfile { profile, path })
+        Some(load_entry(self.entries.next()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::Sandbox;
+
+    /// Fills `dir` with `count` profiles, every seventh of which is broken in some way.
+    fn generate(dir: &Path, count: usize) {
+        let template = serde_json::to_value(Profile::IDEAPAD_15IIL05).unwrap();
+        fs::create_dir_all(dir).unwrap();
+
+        for i in 0..count {
+            let path = dir.join(format!("profile-{:03}.json", i));
+            let mut profile = template.clone();
+            profile["name"] = format!("profile {}", i).into();
+
+            let contents = match i % 7 {
+                0 => "{ not json".to_string(),
+                3 => serde_json::json!({ "name": "missing everything" }).to_string(),
+                5 => {
+                    profile["case_insensitive_product_names"] = "yes".into();
+                    profile.to_string()
+                }
+                _ => profile.to_string(),
+            };
+
+            fs::write(path, contents).unwrap();
         }
 
-        Some(inner(self.entries.next()?))
+        // a directory can't be read as a profile
+        fs::create_dir(dir.join("profile-dir.json")).unwrap();
+    }
+
+    /// What a loaded profile came down to, comparable between both ways of loading them.
+    fn summarize(profile: Result<ExternalProfile, FailedProfile>) -> String {
+        match profile {
+            Ok(profile) => format!(
+                "loaded {} from {} ({:?})",
+                profile.profile.name,
+                profile.path.display(),
+                profile.declarations.case_insensitive_product_names
+            ),
+            Err(failed) => format!("failed {:?}: {:#}", failed.path, failed.error),
+        }
+    }
+
+    #[test]
+    fn loading_all_is_the_same_as_iterating() {
+        let sandbox = Sandbox::new().unwrap();
+        let dir = sandbox.path("profiles");
+        generate(&dir, 200);
+
+        let sequential = Profiles::at(&dir)
+            .unwrap()
+            .map(summarize)
+            .collect::<Vec<_>>();
+        let parallel = Profiles::at(&dir)
+            .unwrap()
+            .load_all()
+            .into_iter()
+            .map(summarize)
+            .collect::<Vec<_>>();
+
+        assert_eq!(sequential.len(), 201);
+        assert_eq!(parallel, sequential);
+        assert_eq!(
+            parallel
+                .iter()
+                .filter(|profile| profile.starts_with("failed"))
+                .count(),
+            29 + 29 + 28 + 1
+        );
+    }
+
+    #[test]
+    fn loading_all_of_few_profiles() {
+        for count in [0, 1, 2] {
+            let sandbox = Sandbox::new().unwrap();
+            let dir = sandbox.path("profiles");
+            generate(&dir, count);
+
+            let sequential = Profiles::at(&dir)
+                .unwrap()
+                .map(summarize)
+                .collect::<Vec<_>>();
+            let parallel = Profiles::at(&dir)
+                .unwrap()
+                .load_all()
+                .into_iter()
+                .map(summarize)
+                .collect::<Vec<_>>();
+
+            assert_eq!(parallel, sequential, "{} profile(s)", count);
+        }
     }
 }
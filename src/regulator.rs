@@ -0,0 +1,474 @@
+use crate::{format, project_paths, utils};
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fs, io, process};
+use tap::Pipe;
+
+/// What the battery conservation regulator did over its lifetime.
+#[derive(Serialize, Deserialize, Default, Debug, Copy, Clone)]
+pub struct Stats {
+    /// How many times the battery level was evaluated.
+    pub evaluations: u64,
+
+    /// How many times battery conservation mode was enabled.
+    pub enables: u64,
+
+    /// How many times battery conservation mode was disabled.
+    pub disables: u64,
+
+    /// How many times talking to acpi failed.
+    pub acpi_errors: u64,
+
+    pub min_battery_level: Option<u8>,
+    pub max_battery_level: Option<u8>,
+
+    #[serde(default)]
+    pub last_action: Option<LastAction>,
+}
+
+/// When the regulator last toggled battery conservation mode, and which way.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct LastAction {
+    /// Whether battery conservation mode was enabled, rather than disabled.
+    pub enabled: bool,
+
+    /// When it was toggled, in seconds since the unix epoch.
+    pub at: u64,
+}
+
+impl Stats {
+    pub fn toggled(&mut self, enabled: bool) {
+        if enabled {
+            self.enables += 1;
+        } else {
+            self.disables += 1;
+        }
+
+        self.last_action = Some(LastAction {
+            enabled,
+            at: since_epoch(SystemTime::now()),
+        });
+    }
+
+    pub fn evaluated(&mut self, battery_level: u8) {
+        self.evaluations += 1;
+        self.min_battery_level = Some(
+            self.min_battery_level
+                .map_or(battery_level, |min| min.min(battery_level)),
+        );
+        self.max_battery_level = Some(
+            self.max_battery_level
+                .map_or(battery_level, |max| max.max(battery_level)),
+        );
+    }
+}
+
+/// Notices the battery level not rising while plugged in with battery conservation mode enabled
+/// and the level well below the threshold. On most machines battery conservation mode caps the
+/// charge at around 60%, so a threshold above that can never be reached once it is enabled.
+///
+/// Slow chargers also look like this for a while, so the level has to stay put for `patience`
+/// evaluations in a row before it counts, and it is only reported once.
+#[derive(Debug, Copy, Clone)]
+pub struct StallDetector {
+    margin: u8,
+    patience: u32,
+    highest: Option<u8>,
+    stalled_for: u32,
+    reported: bool,
+}
+
+impl StallDetector {
+    pub fn new(margin: u8, patience: u32) -> Self {
+        Self {
+            margin,
+            patience,
+            highest: None,
+            stalled_for: 0,
+            reported: false,
+        }
+    }
+
+    /// Records an evaluation of the battery, returning true the first time the level is found to
+    /// have stalled below `threshold`.
+    pub fn observe(&mut self, threshold: u8, level: u8, plugged_in: bool, enabled: bool) -> bool {
+        if self.reported {
+            return false;
+        }
+
+        if !(enabled && plugged_in && level < threshold.saturating_sub(self.margin)) {
+            self.highest = None;
+            self.stalled_for = 0;
+            return false;
+        }
+
+        match self.highest {
+            Some(highest) if level <= highest => self.stalled_for += 1,
+            _ => {
+                self.highest = Some(level);
+                self.stalled_for = 0;
+            }
+        }
+
+        self.reported = self.stalled_for >= self.patience;
+        self.reported
+    }
+}
+
+fn since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+/// What the regulator found on its latest evaluation, for status bars and scripts.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Evaluation {
+    /// When the evaluation was made, in seconds since the unix epoch.
+    pub at: u64,
+
+    /// Whether battery conservation mode was enabled once the regulator acted on it.
+    pub conservation: bool,
+    pub batteries: Vec<EvaluatedBattery>,
+}
+
+impl Evaluation {
+    pub fn new(conservation: bool, batteries: Vec<EvaluatedBattery>) -> Self {
+        Self {
+            at: since_epoch(SystemTime::now()),
+            conservation,
+            batteries,
+        }
+    }
+}
+
+/// A regulated battery as of the latest evaluation.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EvaluatedBattery {
+    /// How the battery was found, such as `first` or `index=1`.
+    pub matches: String,
+    pub level: u8,
+    pub threshold: u8,
+}
+
+/// What the regulator did with battery conservation mode on an evaluation.
+#[derive(Serialize, Debug, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Enabled,
+    Disabled,
+    Noop,
+}
+
+/// A line of the stream of JSON objects the regulator prints to standard output in machine mode,
+/// one per evaluation, so that automation can follow it while it runs. The envelope still
+/// follows on a line of its own once the regulator exits.
+#[derive(Serialize, Debug)]
+pub struct Iteration<'a> {
+    /// When the evaluation was made, in seconds since the unix epoch.
+    pub timestamp: u64,
+
+    /// The level and threshold of the regulated battery if exactly one was evaluated, and `None`
+    /// otherwise, such as with targets, which are only told apart in `batteries`.
+    pub battery_level: Option<u8>,
+    pub threshold: Option<u8>,
+    pub action: Action,
+
+    /// Every evaluated battery, with its own level and threshold.
+    pub batteries: &'a [EvaluatedBattery],
+
+    /// The batteries which couldn't be refreshed for the next evaluation.
+    pub refresh_errors: Vec<String>,
+}
+
+impl<'a> Iteration<'a> {
+    pub fn new(evaluation: &'a Evaluation, action: Action, refresh_errors: Vec<String>) -> Self {
+        let only = match evaluation.batteries.as_slice() {
+            [only] => Some(only),
+            _ => None,
+        };
+
+        Self {
+            timestamp: evaluation.at,
+            battery_level: only.map(|battery| battery.level),
+            threshold: only.map(|battery| battery.threshold),
+            action,
+            batteries: &evaluation.batteries,
+            refresh_errors,
+        }
+    }
+
+    /// Prints this as a line and flushes it right away. Unlike the rest of the machine output,
+    /// failing to doesn't exit, since the regulator should keep going without a reader.
+    pub fn print(&self) -> anyhow::Result<()> {
+        let line = serde_json::to_string(self).context("failed to serialize the iteration")?;
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+
+        writeln!(stdout, "{}", line)
+            .and_then(|()| stdout.flush())
+            .context("failed to print the iteration")
+    }
+}
+
+/// The status of a running regulator, stored in `regulator.json` inside of the runtime directory
+/// so that other invocations can see it. It is rewritten after every evaluation.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Status {
+    pub pid: u32,
+
+    /// When the regulator started, in seconds since the unix epoch.
+    pub started: u64,
+
+    /// How long the regulator ran for, in seconds.
+    pub ran_for: u64,
+
+    pub stats: Stats,
+
+    #[serde(default)]
+    pub evaluation: Option<Evaluation>,
+}
+
+impl Status {
+    pub fn new(started: SystemTime, stats: Stats, evaluation: Option<Evaluation>) -> Self {
+        Self {
+            pid: process::id(),
+            started: since_epoch(started),
+            ran_for: started.elapsed().unwrap_or(Duration::ZERO).as_secs(),
+            stats,
+            evaluation,
+        }
+    }
+
+    /// Reads the status of the running regulator, if there is one.
+    pub fn get() -> anyhow::Result<Option<Self>> {
+        match fs::read_to_string(project_paths::regulator_json()) {
+            Ok(contents) => contents
+                .pipe_deref(serde_json::from_str)
+                .with_context(|| {
+                    format!(
+                        "failed to deserialize contents of {}",
+                        "regulator.json".bold()
+                    )
+                })
+                .map(Some),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => {
+                Err(error).with_context(|| format!("failed to read {}", "regulator.json".bold()))
+            }
+        }
+    }
+
+    pub fn dump(&self) -> anyhow::Result<()> {
+        project_paths::ensure_runtime_dir()?;
+
+        let contents = self
+            .pipe_ref(serde_json::to_string)
+            .context("failed to serialize the regulator status")?;
+
+        utils::write_atomic(project_paths::regulator_json(), contents)
+            .with_context(|| format!("failed to write to {}", "regulator.json".bold()))
+    }
+
+    pub fn remove() -> anyhow::Result<()> {
+        match fs::remove_file(project_paths::regulator_json()) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                Err(error).with_context(|| format!("failed to remove {}", "regulator.json".bold()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// A one line summary of the status, for logs.
+    pub fn summary(&self) -> String {
+        let level = |level: Option<u8>| level.map_or_else(|| "N/A".to_string(), format::percent);
+
+        format!(
+            "ran for {}, {} evaluation(s), {} enable(s), {} disable(s), {} acpi error(s), battery level between {} and {}",
+            format::duration_human(Duration::from_secs(self.ran_for)),
+            self.stats.evaluations,
+            self.stats.enables,
+            self.stats.disables,
+            self.stats.acpi_errors,
+            level(self.stats.min_battery_level),
+            level(self.stats.max_battery_level),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `levels` to a detector while plugged in with battery conservation mode enabled,
+    /// returning the evaluation it first reported a stall on.
+    fn stalls_at(detector: &mut StallDetector, threshold: u8, levels: &[u8]) -> Option<usize> {
+        levels
+            .iter()
+            .position(|&level| detector.observe(threshold, level, true, true))
+    }
+
+    #[test]
+    fn capped_charge_is_reported_after_the_patience_runs_out() {
+        let mut detector = StallDetector::new(5, 3);
+
+        assert_eq!(
+            stalls_at(&mut detector, 80, &[55, 58, 60, 60, 60, 60, 60]),
+            Some(5)
+        );
+    }
+
+    #[test]
+    fn stalls_are_only_reported_once() {
+        let mut detector = StallDetector::new(5, 2);
+
+        assert_eq!(stalls_at(&mut detector, 80, &[60, 60, 60]), Some(2));
+        assert_eq!(stalls_at(&mut detector, 80, &[60; 20]), None);
+    }
+
+    #[test]
+    fn slow_but_rising_charge_isnt_a_stall() {
+        let mut detector = StallDetector::new(5, 3);
+
+        assert_eq!(
+            stalls_at(&mut detector, 80, &[50, 50, 50, 51, 51, 51, 52, 52, 52, 53]),
+            None
+        );
+    }
+
+    #[test]
+    fn dropping_charge_counts_as_stalled() {
+        let mut detector = StallDetector::new(5, 3);
+
+        assert_eq!(stalls_at(&mut detector, 80, &[60, 59, 59, 58]), Some(3));
+    }
+
+    #[test]
+    fn levels_within_the_margin_arent_stalls() {
+        let mut detector = StallDetector::new(5, 2);
+
+        assert_eq!(stalls_at(&mut detector, 80, &[75; 10]), None);
+        assert_eq!(stalls_at(&mut detector, 80, &[74, 74, 74]), Some(2));
+    }
+
+    #[test]
+    fn unplugging_or_disabling_starts_over() {
+        let mut detector = StallDetector::new(5, 3);
+
+        for _ in 0..3 {
+            assert!(!detector.observe(80, 60, true, true));
+        }
+
+        assert!(!detector.observe(80, 60, false, true));
+
+        for _ in 0..3 {
+            assert!(!detector.observe(80, 60, true, true));
+        }
+
+        assert!(!detector.observe(80, 60, true, false));
+        assert_eq!(stalls_at(&mut detector, 80, &[60, 60, 60, 60]), Some(3));
+    }
+
+    #[test]
+    fn low_thresholds_never_stall() {
+        let mut detector = StallDetector::new(5, 0);
+
+        assert_eq!(stalls_at(&mut detector, 3, &[0, 0, 0]), None);
+    }
+
+    fn evaluated(matches: &str, level: u8, threshold: u8) -> EvaluatedBattery {
+        EvaluatedBattery {
+            matches: matches.to_string(),
+            level,
+            threshold,
+        }
+    }
+
+    #[test]
+    fn iteration_of_a_single_battery() {
+        let evaluation = Evaluation::new(true, vec![evaluated("first", 82, 80)]);
+        let iteration = serde_json::to_value(Iteration::new(&evaluation, Action::Enabled, vec![]))
+            .expect("an iteration serializes");
+
+        assert_eq!(iteration["battery_level"], 82);
+        assert_eq!(iteration["threshold"], 80);
+        assert_eq!(iteration["batteries"].as_array().map(Vec::len), Some(1));
+    }
+
+    #[test]
+    fn iteration_of_several_batteries_has_an_entry_for_each() {
+        let evaluation = Evaluation::new(
+            false,
+            vec![evaluated("index=0", 60, 80), evaluated("index=1", 90, 70)],
+        );
+        let iteration = serde_json::to_value(Iteration::new(&evaluation, Action::Noop, vec![]))
+            .expect("an iteration serializes");
+
+        assert!(iteration["battery_level"].is_null());
+        assert!(iteration["threshold"].is_null());
+        assert_eq!(iteration["batteries"][0]["level"], 60);
+        assert_eq!(iteration["batteries"][1]["level"], 90);
+        assert_eq!(iteration["batteries"][1]["threshold"], 70);
+    }
+
+    #[test]
+    fn iteration_without_batteries_has_no_level() {
+        let evaluation = Evaluation::new(false, vec![]);
+        let iteration = serde_json::to_value(Iteration::new(&evaluation, Action::Noop, vec![]))
+            .expect("an iteration serializes");
+
+        assert!(iteration["battery_level"].is_null());
+        assert!(iteration["threshold"].is_null());
+    }
+
+    #[test]
+    fn status_files_of_older_versions_have_no_evaluation() {
+        let status = Status::new(SystemTime::now(), Stats::default(), None);
+        let mut contents = serde_json::to_value(&status).expect("a status serializes");
+        contents
+            .as_object_mut()
+            .expect("a status is an object")
+            .remove("evaluation");
+
+        let status: Status = serde_json::from_value(contents).expect("older statuses deserialize");
+
+        assert!(status.evaluation.is_none());
+    }
+
+    #[test]
+    fn status_files_keep_the_evaluation() {
+        let evaluation = Evaluation::new(true, vec![evaluated("first", 81, 80)]);
+        let status = Status::new(SystemTime::now(), Stats::default(), Some(evaluation));
+        let contents = serde_json::to_string(&status).expect("a status serializes");
+
+        let evaluation = serde_json::from_str::<Status>(&contents)
+            .expect("a status deserializes")
+            .evaluation
+            .expect("the evaluation is kept");
+
+        assert!(evaluation.conservation);
+        assert_eq!(evaluation.batteries[0].matches, "first");
+        assert_eq!(evaluation.batteries[0].level, 81);
+        assert_eq!(evaluation.batteries[0].threshold, 80);
+    }
+
+    #[test]
+    fn the_last_toggle_is_kept() {
+        let mut stats = Stats::default();
+        stats.toggled(true);
+        stats.toggled(false);
+
+        assert_eq!((stats.enables, stats.disables), (1, 1));
+        assert!(!stats.last_action.expect("a toggle is recorded").enabled);
+
+        let mut contents = serde_json::to_value(stats).expect("stats serialize");
+        contents
+            .as_object_mut()
+            .expect("stats are an object")
+            .remove("last_action");
+        let stats: Stats = serde_json::from_value(contents).expect("older stats deserialize");
+        assert!(stats.last_action.is_none());
+    }
+}
@@ -0,0 +1,155 @@
+use crate::hardware::{self, Fake, FakeFile};
+use crate::{machine, project_paths};
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::{env, fs};
+
+/// A temporary directory which stands in for the config, state and runtime directories and the
+/// hardware of the commands ran inside of it, so that they never touch the ones of the user. It is
+/// removed once dropped.
+pub struct Sandbox {
+    root: PathBuf,
+    exe: PathBuf,
+}
+
+impl Sandbox {
+    /// A sandbox running this program.
+    pub fn new() -> anyhow::Result<Self> {
+        let exe = env::current_exe().context("failed to get the location of tuxvantage")?;
+
+        Self::with_exe(exe)
+    }
+
+    /// A sandbox running the tuxvantage executable at `exe`, for when this program isn't it, such
+    /// as in the tests.
+    pub fn with_exe(exe: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let root = env::temp_dir().join(format!(
+            "tuxvantage-sandbox-{}-{:016x}",
+            process::id(),
+            fastrand::u64(..)
+        ));
+
+        for dir in ["config", "state", "data", "runtime"] {
+            fs::create_dir_all(root.join(dir)).with_context(|| {
+                format!(
+                    "failed to create directory {}",
+                    root.join(dir).display().bold()
+                )
+            })?;
+        }
+
+        fs::set_permissions(root.join("runtime"), fs::Permissions::from_mode(0o700))
+            .context("failed to set permissions of the runtime directory")?;
+
+        Ok(Self {
+            root,
+            exe: exe.into(),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.root.join(path)
+    }
+
+    /// The fake hardware the commands ran inside of the sandbox act on.
+    pub fn hardware(&self) -> FakeFile {
+        FakeFile::new(self.path("hardware.json"))
+    }
+
+    /// Replaces the state of the fake hardware with `fake`.
+    pub fn set_hardware(&self, fake: &Fake) -> anyhow::Result<()> {
+        self.hardware().store(fake)
+    }
+
+    /// A command running this program with `args` inside of the sandbox. Standard input is
+    /// closed, so that nothing waits for it.
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(&self.exe);
+        command
+            .arg("--config")
+            .arg(self.path("config"))
+            .args(args)
+            .env_remove(project_paths::CONFIG_DIR_ENV)
+            .env_remove(project_paths::PROFILES_DIR_ENV)
+            .env_remove(machine::VERSION_ENV)
+            .env(project_paths::STATE_DIR_ENV, self.path("state"))
+            .env(project_paths::RUNTIME_DIR_ENV, self.path("runtime"))
+            .env(hardware::FAKE_ENV, self.path("hardware.json"))
+            .env("HOME", &self.root)
+            .env("XDG_CONFIG_HOME", self.path("config"))
+            .env("XDG_STATE_HOME", self.path("state"))
+            .env("XDG_DATA_HOME", self.path("data"))
+            .env("XDG_RUNTIME_DIR", self.path("runtime"))
+            .stdin(Stdio::null());
+
+        command
+    }
+
+    /// Runs this program with `args` inside of the sandbox, returning its exit code and
+    /// standard output.
+    pub fn run(&self, args: &[&str]) -> anyhow::Result<(i32, String)> {
+        let (exit_code, stdout, _) = self.run_inner(args, Stdio::null())?;
+
+        Ok((exit_code, stdout))
+    }
+
+    /// Like [`Sandbox::run`], but also returns standard error, captured separately.
+    pub fn run_with_stderr(&self, args: &[&str]) -> anyhow::Result<(i32, String, String)> {
+        self.run_inner(args, Stdio::piped())
+    }
+
+    fn run_inner(&self, args: &[&str], stderr: Stdio) -> anyhow::Result<(i32, String, String)> {
+        debug!("run tuxvantage {}", args.join(" "));
+        let output = self
+            .command(args)
+            .stderr(stderr)
+            .output()
+            .context("failed to run tuxvantage")?;
+
+        Ok((
+            output.status.code().unwrap_or(-1),
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ))
+    }
+
+    /// Points the project paths and the hardware of this process at the sandbox, for the tests
+    /// which call into the commands directly instead of running this program.
+    ///
+    /// The project paths can only be initialized once, so every test of a process shares the
+    /// same sandbox, which is never removed.
+    #[cfg(test)]
+    pub(crate) fn shared() -> &'static Self {
+        use once_cell::sync::OnceCell;
+
+        static SHARED: OnceCell<Sandbox> = OnceCell::new();
+
+        SHARED.get_or_init(|| {
+            let sandbox = Self::new().expect("failed to create the shared sandbox");
+            project_paths::initialize_in(&sandbox.root)
+                .expect("failed to initialize the project paths inside of the sandbox");
+            env::set_var(hardware::FAKE_ENV, sandbox.path("hardware.json"));
+
+            sandbox
+        })
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        if let Err(error) = fs::remove_dir_all(&self.root) {
+            debug!(
+                "failed to remove the sandbox at '{}': {}",
+                self.root.display(),
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use crate::config::{BuiltInProfile, Feature};
+use crate::validation::{Finding, Severity};
+use anyhow::Context;
+use itertools::Itertools;
+use owo_colors::OwoColorize;
+use serde_json::{json, Map, Value};
+use std::fmt;
+
+/// The version of the profile schema, which is bumped whenever its shape changes.
+pub const VERSION: u32 = 1;
+const ID: &str = "urn:tuxvantage:profile";
+const DRAFT: &str = "http://json-schema.org/draft-07/schema#";
+
+/// The JSON schema of a profile.
+///
+/// ideapad's profile types can't be annotated from here, so the schema is inferred from the
+/// built-in profiles as they are serialized. This keeps it in sync with the version of ideapad
+/// tuxvantage is built with, but only knows about the shapes the built-in profiles use.
+pub fn profile() -> anyhow::Result<Value> {
+    let mut schema = BuiltInProfile::ALL
+        .iter()
+        .map(|profile| serde_json::to_value(profile.get()).map(|profile| infer(&profile)))
+        .collect::<serde_json::Result<Vec<_>>>()
+        .context("failed to serialize the built-in profiles")?
+        .into_iter()
+        .reduce(merge)
+        .context("there are no built-in profiles to infer the schema from")?;
+
+    let object = schema
+        .as_object_mut()
+        .expect("profiles are always serialized as objects");
+
+    // the declarations aren't part of ideapad's profiles, so they can't be inferred
+    if let Some(properties) = object.get_mut("properties").and_then(Value::as_object_mut) {
+        properties.insert(
+            "unsupported".to_string(),
+            json!({
+                "type": "array",
+                "items": { "type": "string", "enum": Feature::NAMES },
+            }),
+        );
+        properties.insert(
+            "case_insensitive_product_names".to_string(),
+            json!({ "type": "boolean" }),
+        );
+    }
+    object.insert("$schema".to_string(), json!(DRAFT));
+    object.insert("$id".to_string(), json!(ID));
+    object.insert("title".to_string(), json!("tuxvantage profile"));
+    object.insert("version".to_string(), json!(VERSION));
+
+    Ok(schema)
+}
+
+/// The schema of `value`, where every field it has is required.
+fn infer(value: &Value) -> Value {
+    match value {
+        Value::Null => json!({ "type": "null" }),
+        Value::Bool(_) => json!({ "type": "boolean" }),
+        Value::Number(number) if number.is_u64() => json!({ "type": "integer", "minimum": 0 }),
+        Value::Number(number) if number.is_i64() => json!({ "type": "integer" }),
+        Value::Number(_) => json!({ "type": "number" }),
+        Value::String(_) => json!({ "type": "string" }),
+        Value::Array(items) => {
+            let mut schema = json!({ "type": "array" });
+
+            if let Some(items) = items.iter().map(infer).reduce(merge) {
+                schema["items"] = items;
+            }
+
+            schema
+        }
+        Value::Object(object) => json!({
+            "type": "object",
+            "properties": object
+                .iter()
+                .map(|(key, value)| (key.clone(), infer(value)))
+                .collect::<Map<_, _>>(),
+            "required": object.keys().collect::<Vec<_>>(),
+            "additionalProperties": false,
+        }),
+    }
+}
+
+/// Merges the schemas of two examples of the same value, such as the same field of two profiles.
+/// Fields of objects are only required if both require them, and anything else which differs
+/// becomes a choice between the two.
+fn merge(a: Value, b: Value) -> Value {
+    if a == b {
+        return a;
+    }
+
+    match (a["type"].as_str(), b["type"].as_str()) {
+        (Some("object"), Some("object")) => {
+            let mut properties = a["properties"].as_object().cloned().unwrap_or_default();
+
+            for (key, schema) in b["properties"].as_object().into_iter().flatten() {
+                let merged = match properties.remove(key) {
+                    Some(existing) => merge(existing, schema.clone()),
+                    None => schema.clone(),
+                };
+                properties.insert(key.clone(), merged);
+            }
+
+            let b_required = b["required"].as_array().cloned().unwrap_or_default();
+            let required = a["required"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|key| b_required.contains(key))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            json!({
+                "type": "object",
+                "properties": properties,
+                "required": required,
+                "additionalProperties": false,
+            })
+        }
+        (Some("array"), Some("array")) => match (a.get("items"), b.get("items")) {
+            (Some(a), Some(b)) => json!({ "type": "array", "items": merge(a.clone(), b.clone()) }),
+            (Some(items), None) | (None, Some(items)) => {
+                json!({ "type": "array", "items": items })
+            }
+            (None, None) => json!({ "type": "array" }),
+        },
+        _ => {
+            let mut any_of = Vec::new();
+
+            for schema in [a, b] {
+                match schema.get("anyOf").and_then(Value::as_array) {
+                    Some(schemas) => any_of.extend(schemas.iter().cloned()),
+                    None => any_of.push(schema),
+                }
+            }
+
+            any_of.dedup();
+            json!({ "anyOf": any_of })
+        }
+    }
+}
+
+/// Where a value is within a profile, such as `battery.conservation`.
+struct FieldPath<'a>(&'a str);
+
+impl fmt::Display for FieldPath<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            write!(f, "{}", "the profile".bold())
+        } else {
+            write!(f, "{}", self.0.bold())
+        }
+    }
+}
+
+fn join(path: &str, key: impl fmt::Display) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+fn expected_name(expected: &str) -> &str {
+    match expected {
+        "boolean" => "a boolean",
+        "integer" => "an integer",
+        "number" => "a number",
+        "string" => "a string",
+        "array" => "an array",
+        "object" => "an object",
+        expected => expected,
+    }
+}
+
+fn is_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+/// Checks `value` against `schema`, pointing each problem at the field it was found in.
+pub fn check(value: &Value, schema: &Value) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    check_value("", value, schema, &mut findings);
+    findings
+}
+
+fn check_value(path: &str, value: &Value, schema: &Value, findings: &mut Vec<Finding>) {
+    if let Some(any_of) = schema.get("anyOf").and_then(Value::as_array) {
+        let matches_any = any_of.iter().any(|schema| {
+            let mut findings = Vec::new();
+            check_value(path, value, schema, &mut findings);
+            findings
+                .iter()
+                .all(|finding| finding.severity != Severity::Error)
+        });
+
+        if !matches_any {
+            findings.push(Finding::error(format!(
+                "{} doesn't match any of the shapes it can have",
+                FieldPath(path)
+            )));
+        }
+
+        return;
+    }
+
+    let expected = match schema.get("type").and_then(Value::as_str) {
+        Some(expected) => expected,
+        None => return,
+    };
+
+    if !is_type(value, expected) {
+        findings.push(Finding::error(format!(
+            "{} must be {}, but it is {}",
+            FieldPath(path),
+            expected_name(expected),
+            type_name(value)
+        )));
+        return;
+    }
+
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            findings.push(Finding::error(format!(
+                "{} must be one of {}, but it is {}",
+                FieldPath(path),
+                allowed.iter().join(", "),
+                value
+            )));
+        }
+    }
+
+    if let (Some(minimum), Some(number)) = (
+        schema.get("minimum").and_then(Value::as_i64),
+        value.as_i64(),
+    ) {
+        if number < minimum {
+            findings.push(Finding::error(format!(
+                "{} must be at least {}, but it is {}",
+                FieldPath(path),
+                minimum,
+                number
+            )));
+        }
+    }
+
+    if let (Some(items), Some(values)) = (schema.get("items"), value.as_array()) {
+        for (index, value) in values.iter().enumerate() {
+            check_value(&join(path, index), value, items, findings);
+        }
+    }
+
+    if let Some(object) = value.as_object() {
+        let properties = schema.get("properties").and_then(Value::as_object);
+
+        for key in schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if !object.contains_key(key) {
+                findings.push(Finding::error(format!(
+                    "{} is missing the field {}",
+                    FieldPath(path),
+                    key.bold()
+                )));
+            }
+        }
+
+        for (key, value) in object {
+            match properties.and_then(|properties| properties.get(key)) {
+                Some(schema) => check_value(&join(path, key), value, schema, findings),
+                None => findings.push(Finding::warning(format!(
+                    "{} has the unknown field {}",
+                    FieldPath(path),
+                    key.bold()
+                ))),
+            }
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Tells systemd how the regulator is doing with the `sd_notify` protocol, when a service with
+//! `Type=notify` runs it.
+
+use std::ffi::OsStr;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+use std::{env, io};
+
+/// The socket systemd listens on for the notifications of the service, which it passes in
+/// `NOTIFY_SOCKET`.
+pub struct Notifier {
+    address: SocketAddr,
+}
+
+impl Notifier {
+    /// The notifier of the service running this process, or `None` if systemd isn't listening,
+    /// such as when run from a terminal or with `--daemonize`.
+    pub fn from_env() -> Option<Self> {
+        Self::at(&env::var_os("NOTIFY_SOCKET")?)
+    }
+
+    /// The notifier of the socket at `path`, given like in `NOTIFY_SOCKET`.
+    fn at(path: &OsStr) -> Option<Self> {
+        // abstract sockets are given with a leading `@` in place of the nul byte they start with
+        let address = match path.as_bytes().strip_prefix(b"@") {
+            Some(name) => SocketAddr::from_abstract_name(name),
+            None => SocketAddr::from_pathname(path),
+        };
+
+        match address {
+            Ok(address) => Some(Self { address }),
+            Err(error) => {
+                debug!("ignoring the invalid notify socket {:?}: {}", path, error);
+                None
+            }
+        }
+    }
+
+    /// Tells systemd that the service has started.
+    pub fn ready(&self) {
+        self.notify("READY=1")
+    }
+
+    /// Tells systemd that the service is still alive, so that the watchdog doesn't restart it.
+    pub fn watchdog(&self) {
+        self.notify("WATCHDOG=1")
+    }
+
+    /// Tells systemd how often the service checks in from now on, which replaces `WatchdogSec`
+    /// of the unit in case the cooldown changed since it was generated.
+    pub fn watchdog_interval(&self, interval: Duration) {
+        self.notify(&format!("WATCHDOG_USEC={}", interval.as_micros()))
+    }
+
+    /// Tells systemd to wait at least `timeout` from now for the service to start, instead of
+    /// `TimeoutStartSec` of the unit.
+    pub fn extend_timeout(&self, timeout: Duration) {
+        self.notify(&format!("EXTEND_TIMEOUT_USEC={}", timeout.as_micros()))
+    }
+
+    /// Tells systemd that the service is shutting down.
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1")
+    }
+
+    /// Sends `state`. Failing to is only a warning, as systemd copes with a service which doesn't
+    /// check in by itself.
+    fn notify(&self, state: &str) {
+        if let Err(error) = self.send(state) {
+            ::log::warn!("failed to send {} to systemd: {}", state, error)
+        }
+    }
+
+    fn send(&self, state: &str) -> io::Result<()> {
+        UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &self.address)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::Sandbox;
+
+    fn received(socket: &UnixDatagram) -> String {
+        let mut buffer = [0; 64];
+        let len = socket.recv(&mut buffer).unwrap();
+
+        String::from_utf8_lossy(&buffer[..len]).into_owned()
+    }
+
+    #[test]
+    fn notifies_path_sockets() {
+        let sandbox = Sandbox::new().unwrap();
+        let path = sandbox.path("notify");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        let notifier = Notifier::at(path.as_os_str()).unwrap();
+
+        notifier.extend_timeout(Duration::from_secs(90));
+        notifier.ready();
+        notifier.watchdog_interval(Duration::from_secs(3));
+
+        assert_eq!(received(&socket), "EXTEND_TIMEOUT_USEC=90000000");
+        assert_eq!(received(&socket), "READY=1");
+        assert_eq!(received(&socket), "WATCHDOG_USEC=3000000");
+    }
+
+    #[test]
+    fn notifies_abstract_sockets() {
+        let name = format!("tuxvantage-test-{}", fastrand::u64(..));
+        let address = SocketAddr::from_abstract_name(&name).unwrap();
+        let socket = UnixDatagram::bind_addr(&address).unwrap();
+        let notifier = Notifier::at(OsStr::new(&format!("@{}", name))).unwrap();
+
+        notifier.watchdog();
+
+        assert_eq!(received(&socket), "WATCHDOG=1");
+    }
+
+    #[test]
+    fn invalid_sockets_are_ignored() {
+        assert!(Notifier::at(OsStr::new(&"a".repeat(200))).is_none());
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use std::fs;
+use std::path::Path;
+
+/// A battery level at a point in time, read from the file given to `regulate --simulate`.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct Sample {
+    /// When the level was read, in seconds. Only the differences between timestamps matter, so
+    /// they can start at zero or be seconds since the unix epoch.
+    pub timestamp: u64,
+    pub level: u8,
+    pub charging: bool,
+}
+
+fn parse_charging(value: &str) -> Option<bool> {
+    match value {
+        "true" | "1" | "charging" => Some(true),
+        "false" | "0" | "discharging" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses `timestamp,level,charging` lines. Empty lines, lines starting with `#`, and a header
+/// are skipped.
+fn parse_csv(contents: &str) -> anyhow::Result<Vec<Sample>> {
+    let mut samples = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+        let sample = match fields.as_slice() {
+            [timestamp, ..] if samples.is_empty() && timestamp.parse::<u64>().is_err() => continue,
+            [timestamp, level, charging] => timestamp.parse().ok().and_then(|timestamp| {
+                Some(Sample {
+                    timestamp,
+                    level: level.parse().ok()?,
+                    charging: parse_charging(charging)?,
+                })
+            }),
+            _ => None,
+        };
+
+        samples.push(sample.with_context(|| {
+            format!(
+                "line {} isn't in the format {}",
+                index + 1,
+                "timestamp,level,charging".bold()
+            )
+        })?);
+    }
+
+    Ok(samples)
+}
+
+/// Reads a time series of battery levels, which is a JSON array of samples if `path` ends with
+/// `.json`, and CSV otherwise.
+pub fn read(path: &Path) -> anyhow::Result<Vec<Sample>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display().bold()))?;
+    let samples = if path
+        .extension()
+        .map_or(false, |extension| extension == "json")
+    {
+        serde_json::from_str(&contents).context("failed to parse the samples")?
+    } else {
+        parse_csv(&contents)?
+    };
+
+    anyhow::ensure!(
+        !samples.is_empty(),
+        "{} doesn't have any samples",
+        path.display().bold()
+    );
+
+    for (previous, sample) in samples.iter().zip(samples.iter().skip(1)) {
+        anyhow::ensure!(
+            sample.timestamp >= previous.timestamp,
+            "the samples must be in order, but {} comes after {}",
+            sample.timestamp.bold(),
+            previous.timestamp.bold()
+        );
+    }
+
+    if let Some(sample) = samples.iter().find(|sample| sample.level > 100) {
+        anyhow::bail!(
+            "the battery level at {} is {}, which is above 100",
+            sample.timestamp.bold(),
+            sample.level.bold()
+        );
+    }
+
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::Sandbox;
+
+    fn levels(samples: &[Sample]) -> Vec<(u64, u8, bool)> {
+        samples
+            .iter()
+            .map(|sample| (sample.timestamp, sample.level, sample.charging))
+            .collect()
+    }
+
+    #[test]
+    fn csv_skips_comments_blank_lines_and_a_header() {
+        let samples = parse_csv(
+            "# recorded on battery\n\ntimestamp, level, charging\n0,80,false\n 60 , 79 , discharging \n120,79,1\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            levels(&samples),
+            [(0, 80, false), (60, 79, false), (120, 79, true)]
+        );
+    }
+
+    #[test]
+    fn csv_lines_which_arent_samples_are_errors() {
+        for contents in [
+            "0,80,false\ntimestamp,level,charging\n",
+            "0,80\n",
+            "0,80,false,extra\n",
+            "0,eighty,false\n",
+            "0,80,maybe\n",
+            "0,256,false\n",
+        ] {
+            assert!(parse_csv(contents).is_err(), "{:?}", contents);
+        }
+    }
+
+    #[test]
+    fn the_example_curves_can_be_read() {
+        for curve in ["unplugged.csv", "plugged_in.json"] {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/curves")
+                .join(curve);
+
+            assert!(read(&path).unwrap().len() > 1, "{}", curve);
+        }
+    }
+
+    #[test]
+    fn samples_must_be_in_order_and_at_most_100() {
+        let sandbox = Sandbox::new().unwrap();
+        let cases = [
+            ("empty.csv", "timestamp,level,charging\n"),
+            ("empty.json", "[]"),
+            ("backwards.csv", "60,80,false\n0,81,false\n"),
+            ("overfull.csv", "0,101,true\n"),
+            (
+                "overfull.json",
+                r#"[{"timestamp": 0, "level": 101, "charging": true}]"#,
+            ),
+        ];
+
+        for (name, contents) in cases {
+            let path = sandbox.path(name);
+            fs::write(&path, contents).unwrap();
+
+            assert!(read(&path).is_err(), "{}", name);
+        }
+    }
+}
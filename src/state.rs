@@ -0,0 +1,102 @@
+use crate::config::DesiredState;
+use crate::{project_paths, utils};
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use tap::Pipe;
+
+/// Runtime data which persists across invocations but isn't configuration, stored in
+/// `state.json` inside of the state directory.
+#[derive(Serialize, Deserialize, Default)]
+pub struct State {
+    /// The values the user asked to be remembered.
+    #[serde(default)]
+    pub desired: DesiredState,
+
+    /// The mode which was switched off by the `switch-back` handler and should be switched back
+    /// on when its opposing mode is disabled with `--restore`.
+    #[serde(default)]
+    pub owed_restore: Option<OwedRestore>,
+
+    /// The identifiers of the tips which were already shown, used when tips are only shown once.
+    #[serde(default)]
+    pub shown_tips: BTreeSet<String>,
+
+    /// When each setting, such as `battery_conservation`, was last written successfully, in
+    /// milliseconds since the unix epoch. Used to warn about writes the firmware may ignore.
+    #[serde(default)]
+    pub last_writes: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OwedRestore {
+    BatteryConservation,
+    RapidCharge,
+}
+
+impl State {
+    pub fn get() -> anyhow::Result<Self> {
+        let state_json = project_paths::state_json();
+
+        if !state_json.exists() {
+            debug!("`state.json` doesn't exist, using defaults");
+            return Ok(Self::default());
+        }
+
+        state_json
+            .pipe(fs::read_to_string)
+            .with_context(|| format!("failed to read {}", "state.json".bold()))?
+            .pipe_deref(serde_json::from_str)
+            .with_context(|| format!("failed to deserialize contents of {}", "state.json".bold()))
+    }
+
+    pub fn dump(&self) -> anyhow::Result<()> {
+        project_paths::ensure_state_dir()?;
+
+        let contents = self
+            .pipe_ref(serde_json::to_string_pretty)
+            .context("failed to serialize the state")?;
+
+        let state_json = project_paths::state_json();
+
+        utils::write_atomic(state_json, contents)
+            .map_err(|error| project_paths::write_error(state_json, error))
+            .with_context(|| format!("failed to write to {}", "state.json".bold()))
+    }
+
+    pub fn mutate_then_dump<T>(f: impl FnOnce(&mut Self) -> T) -> anyhow::Result<T> {
+        let mut this = Self::get()?;
+        let result = f(&mut this);
+
+        this.dump()?;
+
+        Ok(result)
+    }
+}
+
+/// Records `f`'s changes to the desired state so that they can be reapplied later.
+pub fn remember(f: impl FnOnce(&mut DesiredState)) -> anyhow::Result<()> {
+    debug!("remember the desired state");
+    State::mutate_then_dump(|state| f(&mut state.desired))
+        .context("failed to remember the desired state")
+}
+
+/// Records that `mode` was switched off and should be switched back on later.
+pub fn owe_restore(mode: OwedRestore) -> anyhow::Result<()> {
+    debug!("owe a restore of {:?}", mode);
+    State::mutate_then_dump(|state| state.owed_restore = Some(mode))
+        .context("failed to record the mode to switch back on")
+}
+
+/// Whether `mode` is owed a restore.
+pub fn is_restore_owed(mode: OwedRestore) -> anyhow::Result<bool> {
+    Ok(State::get()?.owed_restore == Some(mode))
+}
+
+pub fn clear_owed_restore() -> anyhow::Result<()> {
+    debug!("clear the owed restore");
+    State::mutate_then_dump(|state| state.owed_restore = None)
+        .context("failed to clear the mode to switch back on")
+}
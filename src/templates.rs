@@ -0,0 +1,198 @@
+use anyhow::Context;
+use itertools::Itertools;
+use owo_colors::OwoColorize;
+use serde::Serialize;
+use serde_json::Value;
+
+/// The commands which can be given a template in `[templates]`, along with the placeholders each
+/// of them fills in. The placeholders are the fields of the machine output of the command, with
+/// nested fields joined by dots, so these have to be kept in sync with it.
+pub const COMMANDS: &[(&str, &[&str])] = &[
+    (
+        "status",
+        &[
+            "battery_conservation",
+            "rapid_charge",
+            "system_performance",
+            "thresholds.start",
+            "thresholds.end",
+            "mechanism",
+        ],
+    ),
+    ("battery conservation enabled", &["enabled"]),
+    ("battery conservation disabled", &["disabled"]),
+    ("battery rapid-charge enabled", &["enabled"]),
+    ("battery rapid-charge disabled", &["disabled"]),
+    (
+        "system-performance get",
+        &["system_performance_mode", "fcmo", "spmo", "matched_bits"],
+    ),
+    (
+        "battery threshold get",
+        &["battery", "thresholds.start", "thresholds.end", "mechanism"],
+    ),
+];
+
+enum Segment<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Splits a template into its text and `{placeholder}`s. Braces are written as `{{` and `}}`.
+fn parse(template: &str) -> anyhow::Result<Vec<Segment<'_>>> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+
+    while let Some(index) = rest.find(['{', '}']) {
+        let (text, after) = rest.split_at(index);
+        segments.push(Segment::Text(text));
+
+        if let Some(after) = after.strip_prefix("{{") {
+            segments.push(Segment::Text("{"));
+            rest = after;
+        } else if let Some(after) = after.strip_prefix("}}") {
+            segments.push(Segment::Text("}"));
+            rest = after;
+        } else if let Some(after) = after.strip_prefix('{') {
+            let end = after.find('}').with_context(|| {
+                format!(
+                    "the placeholder at column {} is never closed",
+                    template.len() - after.len()
+                )
+            })?;
+            segments.push(Segment::Placeholder(after[..end].trim()));
+            rest = &after[end + 1..];
+        } else {
+            anyhow::bail!(
+                "the {} at column {} doesn't close a placeholder, write {} for a literal one",
+                "}".bold(),
+                template.len() - after.len() + 1,
+                "}}".bold()
+            );
+        }
+    }
+
+    segments.push(Segment::Text(rest));
+
+    Ok(segments)
+}
+
+/// The placeholders `command` fills in, if it can be given a template.
+pub fn fields(command: &str) -> Option<&'static [&'static str]> {
+    COMMANDS
+        .iter()
+        .find(|(name, _)| *name == command)
+        .map(|(_, fields)| *fields)
+}
+
+/// Checks that `template` parses and only uses placeholders `command` fills in.
+pub fn validate(command: &str, template: &str) -> anyhow::Result<()> {
+    let fields = fields(command).with_context(|| {
+        format!(
+            "{} can't be given a template, only {} can",
+            command.bold(),
+            COMMANDS.iter().map(|(name, _)| name.bold()).join(", ")
+        )
+    })?;
+
+    for segment in parse(template)? {
+        if let Segment::Placeholder(placeholder) = segment {
+            anyhow::ensure!(
+                fields.contains(&placeholder),
+                "{} has no placeholder {}, see {}",
+                command.bold(),
+                format_args!("{{{}}}", placeholder).bold(),
+                format_args!("tuxvantage templates fields {}", command).bold()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Renders `template` with the fields of `output` as it would be serialized in machine output.
+/// Fields which are missing, such as optional ones, are left empty.
+pub fn render(command: &str, template: &str, output: &impl Serialize) -> anyhow::Result<String> {
+    validate(command, template)?;
+    let value =
+        serde_json::to_value(output).context("failed to serialize the output for the template")?;
+    let mut rendered = String::new();
+
+    for segment in parse(template)? {
+        match segment {
+            Segment::Text(text) => rendered.push_str(text),
+            Segment::Placeholder(placeholder) => {
+                let pointer = format!("/{}", placeholder.replace('.', "/"));
+
+                match value.pointer(&pointer) {
+                    None | Some(Value::Null) => {}
+                    Some(Value::String(string)) => rendered.push_str(string),
+                    Some(value) => rendered.push_str(&value.to_string()),
+                }
+            }
+        }
+    }
+
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn fields_are_filled_in() {
+        let output = json!({
+            "battery": "BAT0",
+            "thresholds": { "start": 40, "end": 80 },
+            "mechanism": "native_thresholds",
+        });
+
+        assert_eq!(
+            render(
+                "battery threshold get",
+                "{battery}: { thresholds.start }-{thresholds.end} by {mechanism}",
+                &output
+            )
+            .unwrap(),
+            "BAT0: 40-80 by native_thresholds"
+        );
+    }
+
+    #[test]
+    fn missing_and_null_fields_are_empty() {
+        let output = json!({ "battery": null });
+
+        assert_eq!(
+            render(
+                "battery threshold get",
+                "[{battery}][{thresholds.end}]",
+                &output
+            )
+            .unwrap(),
+            "[][]"
+        );
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        let output = json!({ "enabled": true });
+
+        assert_eq!(
+            render("battery rapid-charge enabled", "{{{enabled}}}", &output).unwrap(),
+            "{true}"
+        );
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        let command = "battery rapid-charge enabled";
+
+        assert!(validate(command, "{enabled").is_err());
+        assert!(validate(command, "enabled}").is_err());
+        assert!(validate(command, "{disabled}").is_err());
+        assert!(validate("battery hold", "{enabled}").is_err());
+        assert!(validate(command, "{ enabled }").is_ok());
+    }
+}
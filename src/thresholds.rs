@@ -0,0 +1,375 @@
+use crate::anyhow_with_tip;
+use crate::ext;
+use crate::types::BatteryLevel;
+use crate::TippingAnyhowResultExt;
+use anyhow::Context;
+use itertools::Itertools;
+use owo_colors::OwoColorize;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+
+/// Where the kernel lists the batteries, which the native thresholds are attributes of.
+const POWER_SUPPLY: &str = "/sys/class/power_supply";
+pub const START_ATTRIBUTE: &str = "charge_control_start_threshold";
+pub const END_ATTRIBUTE: &str = "charge_control_end_threshold";
+const ATTRIBUTES: [&str; 2] = [START_ATTRIBUTE, END_ATTRIBUTE];
+
+/// The charge thresholds which the firmware enforces by itself on models which support them:
+/// charging only starts once the battery level drops below `start`, and stops at `end`.
+#[derive(Serialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Thresholds {
+    pub start: BatteryLevel,
+    pub end: BatteryLevel,
+}
+
+impl Thresholds {
+    /// Validates thresholds to be written, which the kernel would otherwise reject with a less
+    /// helpful error.
+    pub fn new(start: BatteryLevel, end: BatteryLevel) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            start.inner() >= 1 && end.inner() >= 1,
+            "the thresholds must be within {} and {} inclusive",
+            "1%".bold(),
+            "100%".bold()
+        );
+        anyhow::ensure!(
+            start < end,
+            "the start threshold ({}) must be less than the end threshold ({})",
+            start.bold(),
+            end.bold()
+        );
+
+        Ok(Self { start, end })
+    }
+
+    /// Whether these thresholds stop charging before the battery is full.
+    pub fn limits(self) -> bool {
+        self.end.inner() < 100
+    }
+}
+
+impl fmt::Display for Thresholds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+/// What keeps the battery from charging to full.
+#[derive(Serialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Mechanism {
+    NativeThresholds,
+    ConservationMode,
+    None,
+}
+
+impl Mechanism {
+    /// The mechanism in effect, preferring native thresholds which limit the charge over battery
+    /// conservation mode, as the firmware enforces them by itself.
+    pub fn in_effect(thresholds: Option<Thresholds>, conservation: bool) -> Self {
+        match thresholds {
+            Some(thresholds) if thresholds.limits() => Self::NativeThresholds,
+            _ if conservation => Self::ConservationMode,
+            _ => Self::None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::NativeThresholds => "native_thresholds",
+            Self::ConservationMode => "conservation_mode",
+            Self::None => "none",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::NativeThresholds => "the native thresholds",
+            Self::ConservationMode => "battery conservation mode",
+            Self::None => "nothing",
+        }
+    }
+}
+
+/// No battery has both native threshold attributes, because either the model or the kernel
+/// doesn't support them.
+#[derive(Debug)]
+pub struct UnsupportedError {
+    /// The batteries which were found, along with the attributes each of them lacks.
+    pub batteries: Vec<(String, Vec<&'static str>)>,
+}
+
+impl fmt::Display for UnsupportedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "native charge thresholds aren't supported by this kernel or model: "
+        )?;
+
+        if self.batteries.is_empty() {
+            return write!(f, "there are no batteries in {}", POWER_SUPPLY.bold());
+        }
+
+        let batteries = self
+            .batteries
+            .iter()
+            .map(|(name, missing)| {
+                format!(
+                    "{} has no {}",
+                    name.bold(),
+                    missing
+                        .iter()
+                        .map(|attribute| attribute.bold())
+                        .join(" or ")
+                )
+            })
+            .join(", ");
+
+        f.write_str(&batteries)
+    }
+}
+
+impl std::error::Error for UnsupportedError {}
+
+/// A battery which exposes the native threshold attributes.
+#[derive(Debug, Clone)]
+pub struct Battery {
+    pub name: String,
+    path: PathBuf,
+}
+
+impl Battery {
+    /// Finds the first battery with both threshold attributes, by name.
+    pub fn detect() -> Result<Self, UnsupportedError> {
+        Self::detect_at(Path::new(POWER_SUPPLY))
+    }
+
+    /// [`Battery::detect`], with the power supplies in `dir` instead of `/sys/class/power_supply`.
+    fn detect_at(dir: &Path) -> Result<Self, UnsupportedError> {
+        let mut batteries = match dir.read_dir() {
+            Ok(entries) => entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    fs::read_to_string(path.join("type"))
+                        .map_or(false, |kind| kind.trim() == "Battery")
+                })
+                .collect::<Vec<_>>(),
+            Err(error) => {
+                debug!("failed to read {}: {}", dir.display(), error);
+                Vec::new()
+            }
+        };
+        batteries.sort();
+        let mut unsupported = Vec::new();
+
+        for path in batteries {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let missing = ATTRIBUTES
+                .into_iter()
+                .filter(|attribute| !path.join(attribute).exists())
+                .collect::<Vec<_>>();
+
+            if missing.is_empty() {
+                debug!("using the native thresholds of {}", name);
+                return Ok(Self { name, path });
+            }
+
+            unsupported.push((name, missing));
+        }
+
+        Err(UnsupportedError {
+            batteries: unsupported,
+        })
+    }
+
+    /// Like [`Battery::detect`], but with a tip for when native thresholds aren't supported.
+    pub fn require() -> anyhow_with_tip::Result<Self> {
+        Self::detect()
+            .map_err(anyhow::Error::new)
+            .tip(ext::THRESHOLDS_UNSUPPORTED_TIP)
+    }
+
+    /// The paths of the threshold attributes, which need to be writable to set them.
+    pub fn attributes(&self) -> Vec<PathBuf> {
+        ATTRIBUTES
+            .into_iter()
+            .map(|attribute| self.path.join(attribute))
+            .collect()
+    }
+
+    fn read(&self, attribute: &str) -> anyhow::Result<BatteryLevel> {
+        let path = self.path.join(attribute);
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display().bold()))?;
+
+        contents
+            .trim()
+            .parse()
+            .with_context(|| format!("{} has an invalid threshold", path.display().bold()))
+    }
+
+    pub fn get(&self) -> anyhow::Result<Thresholds> {
+        Ok(Thresholds {
+            start: self.read(START_ATTRIBUTE)?,
+            end: self.read(END_ATTRIBUTE)?,
+        })
+    }
+
+    pub fn set(&self, thresholds: Thresholds) -> anyhow_with_tip::Result<()> {
+        // the kernel rejects a start threshold which isn't below the current end threshold, so
+        // raising both at once has to move the end threshold first
+        let current_end = self.read(END_ATTRIBUTE).no_tip()?;
+        let order = if thresholds.start >= current_end {
+            [
+                (END_ATTRIBUTE, thresholds.end),
+                (START_ATTRIBUTE, thresholds.start),
+            ]
+        } else {
+            [
+                (START_ATTRIBUTE, thresholds.start),
+                (END_ATTRIBUTE, thresholds.end),
+            ]
+        };
+
+        for (attribute, level) in order {
+            let path = self.path.join(attribute);
+            let result = fs::write(&path, level.inner().to_string());
+            let permission_denied =
+                matches!(&result, Err(error) if error.kind() == io::ErrorKind::PermissionDenied);
+
+            result
+                .with_context(|| {
+                    format!(
+                        "failed to write {} to {}",
+                        level.bold(),
+                        path.display().bold()
+                    )
+                })
+                .maybe_tip(permission_denied.then(|| ext::THRESHOLDS_PERMISSION_DENIED_TIP))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::Sandbox;
+
+    fn level(level: u8) -> BatteryLevel {
+        BatteryLevel::new(level).unwrap()
+    }
+
+    /// Creates a power supply called `name` of `kind` inside of `sandbox`, with `attributes` set
+    /// to their values.
+    fn supply(sandbox: &Sandbox, name: &str, kind: &str, attributes: &[(&str, u8)]) {
+        let path = sandbox.path(name);
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("type"), format!("{}\n", kind)).unwrap();
+
+        for (attribute, value) in attributes {
+            fs::write(path.join(attribute), format!("{}\n", value)).unwrap();
+        }
+    }
+
+    #[test]
+    fn thresholds_are_validated() {
+        assert!(Thresholds::new(level(40), level(80)).is_ok());
+        assert!(Thresholds::new(level(0), level(80)).is_err());
+        assert!(Thresholds::new(level(80), level(80)).is_err());
+        assert!(Thresholds::new(level(80), level(40)).is_err());
+    }
+
+    #[test]
+    fn limiting_thresholds_take_precedence_over_conservation_mode() {
+        let limiting = Thresholds::new(level(40), level(80)).unwrap();
+        let full = Thresholds::new(level(95), level(100)).unwrap();
+
+        assert_eq!(
+            Mechanism::in_effect(Some(limiting), true),
+            Mechanism::NativeThresholds
+        );
+        assert_eq!(
+            Mechanism::in_effect(Some(full), true),
+            Mechanism::ConservationMode
+        );
+        assert_eq!(Mechanism::in_effect(Some(full), false), Mechanism::None);
+        assert_eq!(Mechanism::in_effect(None, false), Mechanism::None);
+    }
+
+    #[test]
+    fn the_first_supporting_battery_is_used() {
+        let sandbox = Sandbox::new().unwrap();
+        supply(&sandbox, "AC", "Mains", &[]);
+        supply(&sandbox, "BAT0", "Battery", &[(END_ATTRIBUTE, 80)]);
+        supply(
+            &sandbox,
+            "BAT1",
+            "Battery",
+            &[(START_ATTRIBUTE, 40), (END_ATTRIBUTE, 80)],
+        );
+
+        let battery = Battery::detect_at(sandbox.root()).unwrap();
+
+        assert_eq!(battery.name, "BAT1");
+        assert_eq!(
+            battery.get().unwrap(),
+            Thresholds::new(level(40), level(80)).unwrap()
+        );
+    }
+
+    #[test]
+    fn unsupported_batteries_list_their_missing_attributes() {
+        let sandbox = Sandbox::new().unwrap();
+        supply(&sandbox, "BAT0", "Battery", &[(END_ATTRIBUTE, 80)]);
+        supply(&sandbox, "BAT1", "Battery", &[]);
+
+        let error = Battery::detect_at(sandbox.root()).unwrap_err();
+
+        assert_eq!(
+            error.batteries,
+            [
+                ("BAT0".to_owned(), vec![START_ATTRIBUTE]),
+                ("BAT1".to_owned(), vec![START_ATTRIBUTE, END_ATTRIBUTE]),
+            ]
+        );
+    }
+
+    #[test]
+    fn raised_thresholds_are_written_end_first() {
+        let sandbox = Sandbox::new().unwrap();
+        supply(&sandbox, "BAT0", "Battery", &[(END_ATTRIBUTE, 60)]);
+        // a directory can't be written to, so the write of the start threshold fails after the
+        // end threshold was written
+        fs::create_dir(sandbox.path("BAT0").join(START_ATTRIBUTE)).unwrap();
+        let battery = Battery::detect_at(sandbox.root()).unwrap();
+
+        assert!(battery
+            .set(Thresholds::new(level(70), level(90)).unwrap())
+            .is_err());
+        assert_eq!(battery.read(END_ATTRIBUTE).unwrap(), level(90));
+    }
+
+    #[test]
+    fn written_thresholds_are_read_back() {
+        let sandbox = Sandbox::new().unwrap();
+        supply(
+            &sandbox,
+            "BAT0",
+            "Battery",
+            &[(START_ATTRIBUTE, 40), (END_ATTRIBUTE, 60)],
+        );
+        let battery = Battery::detect_at(sandbox.root()).unwrap();
+
+        for thresholds in [(70, 90), (20, 50)] {
+            let thresholds = Thresholds::new(level(thresholds.0), level(thresholds.1)).unwrap();
+            battery.set(thresholds).unwrap();
+            assert_eq!(battery.get().unwrap(), thresholds);
+        }
+    }
+}
@@ -0,0 +1,567 @@
+use crate::utils;
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::time::Duration;
+use tap::Pipe;
+
+/// A battery level in percent, such as `80` or `80%`. Parsed the same way on the command line
+/// and in the config.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BatteryLevel(u8);
+
+impl BatteryLevel {
+    pub const DEFAULT: Self = Self(80);
+    const RANGE: RangeInclusive<u8> = 0..=100;
+
+    pub fn new(level: u8) -> Option<Self> {
+        if Self::RANGE.contains(&level) {
+            Some(Self(level))
+        } else {
+            None
+        }
+    }
+
+    pub const fn inner(self) -> u8 {
+        self.0
+    }
+
+    fn out_of_bounds(level: impl fmt::Display) -> String {
+        format!(
+            "{} is out of bounds (must be within 0 and 100 inclusive)",
+            format_args!("{}%", level).bold()
+        )
+    }
+}
+
+impl FromStr for BatteryLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim_end_matches('%')
+            .parse::<u8>()
+            .context("number given wasn't valid")?
+            .pipe(Self::new)
+            .with_context(|| Self::out_of_bounds(s.trim_end_matches('%')))
+    }
+}
+
+impl Default for BatteryLevel {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl fmt::Display for BatteryLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}%", self.0)
+    }
+}
+
+impl Serialize for BatteryLevel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for BatteryLevel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BatteryLevelVisitor;
+
+        impl<'de> Visitor<'de> for BatteryLevelVisitor {
+            type Value = BatteryLevel;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a battery level such as `80` or `\"80%\"`")
+            }
+
+            fn visit_u64<E: de::Error>(self, level: u64) -> Result<Self::Value, E> {
+                u8::try_from(level)
+                    .ok()
+                    .and_then(BatteryLevel::new)
+                    .ok_or_else(|| E::custom(BatteryLevel::out_of_bounds(level)))
+            }
+
+            fn visit_i64<E: de::Error>(self, level: i64) -> Result<Self::Value, E> {
+                u64::try_from(level)
+                    .map_err(|_| E::custom(BatteryLevel::out_of_bounds(level)))
+                    .and_then(|level| self.visit_u64(level))
+            }
+
+            fn visit_str<E: de::Error>(self, level: &str) -> Result<Self::Value, E> {
+                level
+                    .parse()
+                    .map_err(|error: anyhow::Error| E::custom(format!("{:#}", error)))
+            }
+        }
+
+        deserializer.deserialize_any(BatteryLevelVisitor)
+    }
+}
+
+/// A duration such as `90`, `1.5s`, `500ms`, `2m` or `1h30m`, in seconds if there is no unit.
+/// Parsed the same way on the command line and in the config, and displayed in a form which
+/// parses back to the same duration.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct HumanDuration(pub Duration);
+
+impl HumanDuration {
+    pub const ZERO: Self = Self(Duration::ZERO);
+    const SYNTAX: &'static str =
+        "durations are a number of seconds such as `90` or `1.5`, or numbers with the units \
+         `ms`, `s`, `m` or `h` such as `500ms`, `2m` or `1h30m`";
+
+    pub const fn from_secs(secs: u64) -> Self {
+        Self(Duration::from_secs(secs))
+    }
+
+    fn from_secs_f64(secs: f64) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            secs.is_finite() && secs >= 0.0 && secs < u64::MAX as f64,
+            "{} isn't a duration which can be waited for",
+            secs.bold()
+        );
+
+        Ok(Self(Duration::from_secs_f64(secs)))
+    }
+
+    fn unit(unit: &str) -> Option<f64> {
+        match unit {
+            "ms" => Some(0.001),
+            "s" => Some(1.0),
+            "m" => Some(60.0),
+            "h" => Some(3600.0),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for HumanDuration {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn inner(s: &str) -> anyhow::Result<HumanDuration> {
+            let s = s.trim();
+
+            if let Ok(secs) = s.parse::<f64>() {
+                return HumanDuration::from_secs_f64(secs);
+            }
+
+            anyhow::ensure!(!s.is_empty(), "the duration is empty");
+
+            let mut secs = 0.0;
+            let mut rest = s;
+
+            while !rest.is_empty() {
+                let number_end = rest
+                    .find(|c: char| !c.is_ascii_digit() && c != '.')
+                    .unwrap_or(rest.len());
+                let (number, after) = rest.split_at(number_end);
+                let unit_end = after
+                    .find(|c: char| c.is_ascii_digit() || c == '.')
+                    .unwrap_or(after.len());
+                let (unit, after) = after.split_at(unit_end);
+                let number = number
+                    .parse::<f64>()
+                    .with_context(|| format!("{} isn't a number", number.bold()))?;
+                let unit = HumanDuration::unit(unit)
+                    .with_context(|| format!("{} isn't a unit", unit.bold()))?;
+
+                secs += number * unit;
+                rest = after;
+            }
+
+            HumanDuration::from_secs_f64(secs)
+        }
+
+        inner(s).map_err(|error| {
+            anyhow::anyhow!(
+                "{} isn't a valid duration: {:#} ({})",
+                s.bold(),
+                error,
+                Self::SYNTAX
+            )
+        })
+    }
+}
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.subsec_nanos() % 1_000_000 != 0 {
+            return write!(f, "{}s", self.0.as_secs_f64());
+        }
+
+        let secs = self.0.as_secs();
+        let millis = self.0.subsec_millis();
+
+        if secs == 0 && millis == 0 {
+            return f.write_str("0s");
+        }
+
+        let parts = [
+            (secs / 3600, "h"),
+            (secs % 3600 / 60, "m"),
+            (secs % 60, "s"),
+            (u64::from(millis), "ms"),
+        ];
+
+        for (value, unit) in parts {
+            if value != 0 {
+                write!(f, "{}{}", value, unit)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Serialize for HumanDuration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for HumanDuration {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct HumanDurationVisitor;
+
+        impl<'de> Visitor<'de> for HumanDurationVisitor {
+            type Value = HumanDuration;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a duration such as `90` or `\"1h30m\"`")
+            }
+
+            fn visit_u64<E: de::Error>(self, secs: u64) -> Result<Self::Value, E> {
+                Ok(HumanDuration::from_secs(secs))
+            }
+
+            fn visit_i64<E: de::Error>(self, secs: i64) -> Result<Self::Value, E> {
+                self.visit_f64(secs as f64)
+            }
+
+            fn visit_f64<E: de::Error>(self, secs: f64) -> Result<Self::Value, E> {
+                HumanDuration::from_secs_f64(secs)
+                    .map_err(|error| E::custom(format!("{:#}", error)))
+            }
+
+            fn visit_str<E: de::Error>(self, duration: &str) -> Result<Self::Value, E> {
+                duration
+                    .parse()
+                    .map_err(|error: anyhow::Error| E::custom(format!("{:#}", error)))
+            }
+        }
+
+        deserializer.deserialize_any(HumanDurationVisitor)
+    }
+}
+
+/// When something has to be done by, either a time of day in local time such as `14:30`, or a
+/// duration from now such as `1h30m`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Deadline {
+    At { hour: u8, minute: u8 },
+    In(HumanDuration),
+}
+
+impl Deadline {
+    /// How long from now until the deadline. A time of day which already passed today is taken to
+    /// be tomorrow.
+    pub fn remaining(self) -> Duration {
+        match self {
+            Self::At { hour, minute } => {
+                let now = utils::local_seconds_of_day();
+                let at = u64::from(hour) * 3600 + u64::from(minute) * 60;
+
+                if at > now {
+                    Duration::from_secs(at - now)
+                } else {
+                    Duration::from_secs(at + 86_400 - now)
+                }
+            }
+            Self::In(duration) => duration.0,
+        }
+    }
+}
+
+impl FromStr for Deadline {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hour, minute) = match s.trim().split_once(':') {
+            Some(time) => time,
+            None => return s.parse().map(Self::In),
+        };
+        let hour = hour
+            .parse::<u8>()
+            .ok()
+            .filter(|hour| *hour < 24)
+            .with_context(|| format!("{} isn't an hour from 0 to 23", hour.bold()))?;
+        let minute = minute
+            .parse::<u8>()
+            .ok()
+            .filter(|minute| *minute < 60)
+            .with_context(|| format!("{} isn't a minute from 0 to 59", minute.bold()))?;
+
+        Ok(Self::At { hour, minute })
+    }
+}
+
+impl fmt::Display for Deadline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::At { hour, minute } => write!(f, "{:02}:{:02}", hour, minute),
+            Self::In(duration) => write!(f, "{}", duration),
+        }
+    }
+}
+
+impl Serialize for Deadline {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// The name of a systemd service unit, such as `tuxvantage-regulate.service`. The `.service`
+/// suffix is added if it is left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceName(String);
+
+impl ServiceName {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for ServiceName {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = if s.ends_with(".service") {
+            s.to_string()
+        } else {
+            format!("{}.service", s)
+        };
+        let stem = &name[..name.len() - ".service".len()];
+
+        anyhow::ensure!(!stem.is_empty(), "the service name can't be empty");
+        anyhow::ensure!(
+            !name.contains('/'),
+            "the service name {} can't contain a path separator, use {} to choose the directory",
+            s.bold(),
+            "--unit-dir".bold()
+        );
+        anyhow::ensure!(
+            !name.contains('@'),
+            "the service name {} can't contain {}, which would make it a template unit",
+            s.bold(),
+            "@".bold()
+        );
+
+        // the characters systemd allows in unit names, other than `@` for templates
+        if let Some(invalid) = stem
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, ':' | '-' | '_' | '.' | '\\')))
+        {
+            anyhow::bail!(
+                "the service name {} contains {}, but only letters, digits, {} are allowed",
+                s.bold(),
+                format_args!("{:?}", invalid).bold(),
+                "`:`, `-`, `_`, `.` and `\\`"
+            );
+        }
+
+        anyhow::ensure!(
+            name.len() <= 255,
+            "the service name {} is longer than 255 characters",
+            s.bold()
+        );
+
+        Ok(Self(name))
+    }
+}
+
+impl fmt::Display for ServiceName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `value` like the config would, as the value of a key in `tuxvantage.toml`.
+    fn from_toml<T: for<'de> Deserialize<'de>>(value: &str) -> Result<T, toml::de::Error> {
+        #[derive(Deserialize)]
+        struct Table<T> {
+            value: T,
+        }
+
+        toml::from_str::<Table<T>>(&format!("value = {}", value)).map(|table| table.value)
+    }
+
+    #[test]
+    fn battery_levels_parse_with_and_without_percent() {
+        assert_eq!("80".parse::<BatteryLevel>().unwrap().inner(), 80);
+        assert_eq!("80%".parse::<BatteryLevel>().unwrap().inner(), 80);
+        assert_eq!("0".parse::<BatteryLevel>().unwrap().inner(), 0);
+        assert_eq!("100%".parse::<BatteryLevel>().unwrap().inner(), 100);
+    }
+
+    #[test]
+    fn battery_levels_out_of_bounds_are_rejected() {
+        for level in ["101", "255%", "256", "-1", "", "%", "eighty"] {
+            assert!(level.parse::<BatteryLevel>().is_err(), "{:?} parsed", level);
+        }
+    }
+
+    #[test]
+    fn every_battery_level_round_trips() {
+        for level in 0..=100 {
+            let level = BatteryLevel::new(level).unwrap();
+
+            assert_eq!(level.to_string().parse::<BatteryLevel>().unwrap(), level);
+            assert_eq!(
+                from_toml::<BatteryLevel>(&format!("{:?}", level.to_string())).unwrap(),
+                level
+            );
+            assert_eq!(
+                from_toml::<BatteryLevel>(&level.inner().to_string()).unwrap(),
+                level
+            );
+        }
+    }
+
+    #[test]
+    fn battery_levels_out_of_bounds_are_rejected_in_the_config() {
+        assert!(from_toml::<BatteryLevel>("101").is_err());
+        assert!(from_toml::<BatteryLevel>("-5").is_err());
+        assert!(from_toml::<BatteryLevel>("\"101%\"").is_err());
+    }
+
+    #[test]
+    fn durations_parse_every_syntax() {
+        let cases = [
+            ("90", Duration::from_secs(90)),
+            ("1.5", Duration::from_millis(1500)),
+            ("1.5s", Duration::from_millis(1500)),
+            ("500ms", Duration::from_millis(500)),
+            ("2m", Duration::from_secs(120)),
+            ("1h30m", Duration::from_secs(5400)),
+            ("1h30m15s", Duration::from_secs(5415)),
+            (" 10s ", Duration::from_secs(10)),
+            ("0", Duration::ZERO),
+        ];
+
+        for (duration, expected) in cases {
+            assert_eq!(
+                duration.parse::<HumanDuration>().unwrap(),
+                HumanDuration(expected),
+                "{:?}",
+                duration
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_durations_are_rejected() {
+        for duration in ["", "-1", "10x", "h", "1.2.3s", "inf", "NaN"] {
+            assert!(
+                duration.parse::<HumanDuration>().is_err(),
+                "{:?} parsed",
+                duration
+            );
+        }
+    }
+
+    #[test]
+    fn durations_round_trip() {
+        let durations = (0..5000)
+            .step_by(7)
+            .map(Duration::from_millis)
+            .chain((0..200_000).step_by(997).map(Duration::from_secs))
+            .chain([Duration::from_micros(1500), Duration::from_nanos(1)]);
+
+        for duration in durations {
+            let duration = HumanDuration(duration);
+
+            assert_eq!(
+                duration.to_string().parse::<HumanDuration>().unwrap(),
+                duration,
+                "{} doesn't parse back",
+                duration
+            );
+            assert_eq!(
+                from_toml::<HumanDuration>(&format!("{:?}", duration.to_string())).unwrap(),
+                duration
+            );
+        }
+    }
+
+    #[test]
+    fn durations_in_the_config_accept_numbers_of_seconds() {
+        assert_eq!(
+            from_toml::<HumanDuration>("90").unwrap(),
+            HumanDuration::from_secs(90)
+        );
+        assert_eq!(
+            from_toml::<HumanDuration>("1.5").unwrap(),
+            HumanDuration(Duration::from_millis(1500))
+        );
+        assert!(from_toml::<HumanDuration>("-1").is_err());
+    }
+
+    #[test]
+    fn deadlines_are_times_of_day_or_durations() {
+        assert_eq!(
+            "07:30".parse::<Deadline>().unwrap(),
+            Deadline::At {
+                hour: 7,
+                minute: 30
+            }
+        );
+        assert_eq!(
+            "1h".parse::<Deadline>().unwrap(),
+            Deadline::In(HumanDuration::from_secs(3600))
+        );
+        assert!("24:00".parse::<Deadline>().is_err());
+        assert!("12:60".parse::<Deadline>().is_err());
+        assert_eq!(
+            Deadline::In(HumanDuration::from_secs(5)).remaining(),
+            Duration::from_secs(5)
+        );
+    }
+
+    #[test]
+    fn service_names_get_the_service_suffix() {
+        for name in ["tuxvantage-regulate", "tuxvantage-regulate.service"] {
+            assert_eq!(
+                name.parse::<ServiceName>().unwrap().as_str(),
+                "tuxvantage-regulate.service"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_service_names_are_rejected() {
+        for name in [
+            "",
+            ".service",
+            "system/bcm",
+            "bcm@battery",
+            "bcm regulator",
+            &"a".repeat(255),
+        ] {
+            assert!(
+                name.parse::<ServiceName>().is_err(),
+                "{:?} is accepted",
+                name
+            );
+        }
+    }
+}
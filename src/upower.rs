@@ -0,0 +1,113 @@
+//! Tells the regulator when UPower reports a change to a power device, for `--listen upower`, by
+//! following the signals UPower sends on the system bus.
+
+use anyhow::Context;
+use crossbeam::channel::{Receiver, TrySendError};
+use std::thread;
+use zbus::blocking::fdo::DBusProxy;
+use zbus::blocking::{Connection, MessageIterator};
+use zbus::names::{BusName, UniqueName};
+use zbus::{MatchRule, MessageType};
+
+const UPOWER: &str = "org.freedesktop.UPower";
+
+/// The signals of UPower the regulator listens for.
+struct Rules {
+    /// A property of a device, such as its level or state, changed.
+    changed: MatchRule<'static>,
+
+    /// A device, such as a newly plugged in battery, appeared.
+    added: MatchRule<'static>,
+
+    /// UPower exited or was replaced, so nothing more will come from the one being followed.
+    gone: MatchRule<'static>,
+}
+
+impl Rules {
+    fn new(owner: UniqueName<'static>) -> zbus::Result<Self> {
+        Ok(Self {
+            changed: MatchRule::builder()
+                .msg_type(MessageType::Signal)
+                .sender(owner.clone())?
+                .interface("org.freedesktop.DBus.Properties")?
+                .member("PropertiesChanged")?
+                .path_namespace("/org/freedesktop/UPower/devices")?
+                .build(),
+            added: MatchRule::builder()
+                .msg_type(MessageType::Signal)
+                .sender(owner)?
+                .interface(UPOWER)?
+                .member("DeviceAdded")?
+                .build(),
+            gone: MatchRule::builder()
+                .msg_type(MessageType::Signal)
+                .sender("org.freedesktop.DBus")?
+                .interface("org.freedesktop.DBus")?
+                .member("NameOwnerChanged")?
+                .arg(0, UPOWER)?
+                .build(),
+        })
+    }
+}
+
+/// Follows the devices of UPower on a thread of its own, which stops with the first signal after
+/// this is dropped.
+pub struct Monitor {
+    changes: Receiver<()>,
+}
+
+impl Monitor {
+    /// Starts following the devices of UPower, failing if the system bus can't be reached or
+    /// UPower isn't running.
+    pub fn spawn() -> anyhow::Result<Self> {
+        let connection = Connection::system().context("failed to connect to the system bus")?;
+        let dbus = DBusProxy::new(&connection).context("failed to talk to the system bus")?;
+        let owner = dbus
+            .get_name_owner(BusName::try_from(UPOWER)?)
+            .context("UPower isn't running")?;
+        let rules = Rules::new(owner.into_inner())?;
+
+        // created before the rules are added, so that no signal is missed in between
+        let messages = MessageIterator::from(&connection);
+
+        for rule in [&rules.changed, &rules.added, &rules.gone] {
+            dbus.add_match_rule(rule.clone())
+                .context("failed to subscribe to the signals of UPower")?;
+        }
+
+        let (sender, changes) = crossbeam::channel::bounded(1);
+
+        thread::spawn(move || {
+            for message in messages {
+                let message = match message {
+                    Ok(message) => message,
+                    Err(_) => break,
+                };
+
+                if rules.gone.matches(&message).unwrap_or(false) {
+                    break;
+                }
+
+                let relevant = rules.changed.matches(&message).unwrap_or(false)
+                    || rules.added.matches(&message).unwrap_or(false);
+
+                if !relevant {
+                    continue;
+                }
+
+                // changes which arrive before the regulator gets to the last one are merged
+                if let Err(TrySendError::Disconnected(_)) = sender.try_send(()) {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self { changes })
+    }
+
+    /// Receives whenever a device changed or was added, and disconnects once UPower exits or the
+    /// connection to the system bus is lost.
+    pub fn changes(&self) -> &Receiver<()> {
+        &self.changes
+    }
+}
@@ -1,16 +1,198 @@
 use anyhow::Context;
+#[cfg(feature = "regulate")]
 use crossbeam::channel::Receiver;
 use itertools::Itertools;
 use owo_colors::OwoColorize;
 use serde::{de, Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::ffi::{CString, OsString};
+use std::fs::File;
+use std::io::Write;
 use std::ops::Not;
-use std::path::Path;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::Duration;
-use std::{fmt, io, thread};
+use std::{env, fmt, fs, io, mem, process, thread};
 
-pub fn dedup_error_chain_for_humans(error: anyhow::Error) -> String {
-    error.chain().map(ToString::to_string).unique().join(": ")
+/// Prints a line to standard output like `println!`, but exits successfully instead of panicking
+/// if the reader has gone away, such as when piping into `head`.
+pub fn print_line(line: impl fmt::Display) {
+    if let Err(error) = writeln!(io::stdout().lock(), "{}", line) {
+        if error.kind() == io::ErrorKind::BrokenPipe {
+            process::exit(0);
+        }
+
+        panic!("failed printing to stdout: {}", error);
+    }
+}
+
+/// Prints a line to standard error like `eprintln!`, but ignores failures since there is nowhere
+/// left to report them to.
+pub fn eprint_line(line: impl fmt::Display) {
+    let _ = writeln!(io::stderr().lock(), "{}", line);
+}
+
+/// Removes what each message of an error chain repeats from its cause, so that every message
+/// adds something new. Contexts often include the message of their cause, such as `failed to
+/// enable battery conservation mode: acpi_call failed` caused by `acpi_call failed`, so the
+/// cause is stripped from the end of its parent before duplicates are removed.
+pub fn dedup_error_chain(messages: impl IntoIterator<Item = String>) -> Vec<String> {
+    let messages = messages.into_iter().collect::<Vec<_>>();
+    let mut stripped = Vec::with_capacity(messages.len());
+
+    for (message, cause) in messages
+        .iter()
+        .zip(messages.iter().skip(1).map(Some).chain([None]))
+    {
+        let message = cause
+            .filter(|cause| *cause != message)
+            .and_then(|cause| message.strip_suffix(cause.as_str()))
+            .map(|rest| rest.trim_end_matches(|c: char| c == ':' || c.is_whitespace()))
+            .filter(|rest| !rest.is_empty())
+            .unwrap_or(message);
+
+        stripped.push(message.to_string());
+    }
+
+    stripped.into_iter().unique().collect()
+}
+
+pub fn dedup_error_chain_for_humans(error: &anyhow::Error) -> String {
+    dedup_error_chain(error.chain().map(ToString::to_string)).join(": ")
+}
+
+/// The width and height of the terminal standard output is connected to, in columns and rows, if
+/// it is one.
+pub fn terminal_size() -> Option<(usize, usize)> {
+    // SAFETY: a zeroed `winsize` is valid, and `TIOCGWINSZ` only writes one into it
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+
+    (result == 0 && size.ws_col > 0 && size.ws_row > 0)
+        .then(|| (usize::from(size.ws_col), usize::from(size.ws_row)))
+}
+
+/// Text aligned into columns, for commands which list things.
+pub struct Table {
+    columns: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    const GAP: &'static str = "  ";
+
+    /// Columns are never truncated to be narrower than this, even if the table doesn't fit.
+    const MIN_WIDTH: usize = 8;
+
+    pub fn new(columns: &[&'static str]) -> Self {
+        Self {
+            columns: columns.to_vec(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<String>) {
+        debug_assert_eq!(row.len(), self.columns.len(), "row doesn't fit the columns");
+        self.rows.push(row)
+    }
+
+    /// Keeps only `columns`, in the order they are given. Keeps every column if it is empty.
+    pub fn select(&mut self, columns: &[String]) -> anyhow::Result<()> {
+        if columns.is_empty() {
+            return Ok(());
+        }
+
+        let indices = columns
+            .iter()
+            .map(|column| {
+                self.columns
+                    .iter()
+                    .position(|known| known == column)
+                    .with_context(|| {
+                        format!(
+                            "there is no column {}, the columns are {}",
+                            column.bold(),
+                            self.columns.join(", ")
+                        )
+                    })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        self.columns = indices.iter().map(|&index| self.columns[index]).collect();
+
+        for row in &mut self.rows {
+            *row = indices.iter().map(|&index| row[index].clone()).collect();
+        }
+
+        Ok(())
+    }
+
+    /// The width of every column, narrowing the widest ones until the table fits in `max_width`.
+    fn widths(&self, header: bool, max_width: Option<usize>) -> Vec<usize> {
+        let mut widths = self
+            .columns
+            .iter()
+            .map(|column| if header { column.len() } else { 0 })
+            .collect::<Vec<_>>();
+
+        for row in &self.rows {
+            for (width, value) in widths.iter_mut().zip(row) {
+                *width = (*width).max(value.chars().count());
+            }
+        }
+
+        if let Some(max_width) = max_width {
+            let gaps = Self::GAP.len() * widths.len().saturating_sub(1);
+
+            while widths.iter().sum::<usize>() + gaps > max_width {
+                match widths.iter_mut().max() {
+                    Some(widest) if *widest > Self::MIN_WIDTH => *widest -= 1,
+                    _ => break,
+                }
+            }
+        }
+
+        widths
+    }
+
+    /// The lines of the table. Values which are too long to fit in `max_width` are truncated with
+    /// an ellipsis, and the header is left out unless `header` is set.
+    pub fn render(&self, header: bool, max_width: Option<usize>) -> Vec<String> {
+        let widths = self.widths(header, max_width);
+        let header = header.then(|| {
+            self.columns
+                .iter()
+                .map(|column| column.to_uppercase())
+                .collect::<Vec<_>>()
+        });
+
+        header
+            .iter()
+            .chain(&self.rows)
+            .map(|row| {
+                row.iter()
+                    .zip(&widths)
+                    .map(|(value, &width)| format!("{:<width$}", truncate(value, width)))
+                    .join(Self::GAP)
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    }
+}
+
+/// `value` cut down to `width` characters, ending with an ellipsis if it was cut.
+fn truncate(value: &str, width: usize) -> String {
+    if value.chars().count() <= width {
+        value.to_string()
+    } else {
+        value
+            .chars()
+            .take(width.saturating_sub(1))
+            .chain(['…'])
+            .collect()
+    }
 }
 
 pub fn not<T: Not>(value: T) -> T::Output {
@@ -93,12 +275,103 @@ where
     }
 }
 
+/// The names a value can be parsed from, with the canonical name of each value first. Shared
+/// between the [`FromStr`] implementations and the possible values of command line arguments.
+pub type Names<T> = &'static [(T, &'static [&'static str])];
+
+pub fn parse_name<T: Copy>(names: Names<T>, what: &str, s: &str) -> anyhow::Result<T> {
+    names
+        .iter()
+        .find(|(_, aliases)| aliases.contains(&s))
+        .map(|(value, _)| *value)
+        .ok_or_else(|| unknown_name(names, what, s))
+}
+
+/// Like [`parse_name`], but ignoring case, dashes and underscores, which also accepts the names
+/// values were serialized with before they were deserialized from their names.
+fn parse_name_loosely<T: Copy>(names: Names<T>, what: &str, s: &str) -> anyhow::Result<T> {
+    fn normalize(name: &str) -> String {
+        name.chars()
+            .filter(|c| !matches!(c, '-' | '_'))
+            .flat_map(char::to_lowercase)
+            .collect()
+    }
+
+    let normalized = normalize(s);
+
+    names
+        .iter()
+        .find(|(_, aliases)| aliases.iter().any(|alias| normalize(alias) == normalized))
+        .map(|(value, _)| *value)
+        .ok_or_else(|| unknown_name(names, what, s))
+}
+
+/// Deserializes a value of the configuration from any of its `names`, so that it accepts the same
+/// spellings as the command line.
+pub fn deserialize_name<'de, D, T>(
+    deserializer: D,
+    names: Names<T>,
+    what: &str,
+) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Copy,
+{
+    parse_name_loosely(names, what, &String::deserialize(deserializer)?).map_err(de::Error::custom)
+}
+
+/// Like [`deserialize_name`], but for optional values.
+pub fn deserialize_optional_name<'de, D, T>(
+    deserializer: D,
+    names: Names<T>,
+    what: &str,
+) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Copy,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|name| parse_name_loosely(names, what, &name))
+        .transpose()
+        .map_err(de::Error::custom)
+}
+
+/// An error for a name which isn't in `names`, listing the possible values and suggesting the
+/// closest one, if any is close enough.
+pub fn unknown_name<T>(names: Names<T>, what: &str, s: &str) -> anyhow::Error {
+    let canonical = names.iter().map(|(_, aliases)| aliases[0]);
+    let mut message = format!(
+        "invalid {} {}, possible values are {}",
+        what,
+        s.bold(),
+        canonical
+            .clone()
+            .map(|name| name.bold().to_string())
+            .join(", ")
+    );
+
+    // the same threshold clap uses for its own suggestions
+    let suggestion = canonical
+        .map(|name| (name, strsim::jaro_winkler(s, name)))
+        .filter(|(_, confidence)| *confidence > 0.8)
+        .max_by(|(_, left), (_, right)| left.partial_cmp(right).unwrap_or(Ordering::Equal));
+
+    if let Some((suggestion, _)) = suggestion {
+        message.push_str(&format!(". did you mean {}?", suggestion.bold()));
+    }
+
+    anyhow::anyhow!(message)
+}
+
+#[cfg(feature = "regulate")]
 pub fn sleep(duration: Duration) -> Receiver<()> {
     let (sender, receiver) = crossbeam::channel::bounded(1);
 
     thread::spawn(move || {
         thread::sleep(duration);
-        sender.send(()).expect("failed to send sleep event");
+
+        // the sleep may have been cut short by something else, which drops the receiver
+        let _ = sender.send(());
     });
 
     receiver
@@ -116,3 +389,555 @@ pub fn is_systemd() -> anyhow::Result<bool> {
         }),
     }
 }
+
+/// Runs `program` with `args`, failing if it doesn't succeed. Its output goes to the terminal.
+#[cfg(feature = "service-install")]
+pub fn run(program: &str, args: &[&str]) -> anyhow::Result<()> {
+    let command = || format!("{} {}", program, args.join(" "));
+    let successful = process::Command::new(program)
+        .args(args)
+        .status()
+        .with_context(|| format!("failed to run {}", command().bold()))?
+        .success();
+
+    anyhow::ensure!(successful, "running {} wasn't successful", command().bold());
+
+    Ok(())
+}
+
+/// The files which container runtimes such as podman, docker and toolbox leave behind, relative to
+/// the root of the filesystem.
+const CONTAINER_MARKERS: &[&str] = &["run/.containerenv", "run/.toolboxenv", ".dockerenv"];
+
+/// The interfaces the hardware is accessed through, relative to the root of the filesystem.
+const ACPI_INTERFACES: &[&str] = &["sys/class/dmi/id", "proc/acpi/call"];
+
+/// Whether the filesystem at `root` is the one of a container, judging by the files container
+/// runtimes leave behind.
+pub fn is_container_at(root: &Path) -> bool {
+    CONTAINER_MARKERS
+        .iter()
+        .any(|marker| root.join(marker).exists())
+}
+
+/// Whether this program runs inside of a container, such as a toolbox or distrobox.
+pub fn is_container() -> bool {
+    env::var_os("container").is_some() || is_container_at(Path::new("/"))
+}
+
+/// The interfaces the hardware is accessed through which are missing from the filesystem at
+/// `root`.
+pub fn missing_acpi_interfaces_at(root: &Path) -> Vec<PathBuf> {
+    ACPI_INTERFACES
+        .iter()
+        .map(|interface| root.join(interface))
+        .filter(|path| !path.exists())
+        .collect()
+}
+
+pub fn euid() -> u32 {
+    // SAFETY: `geteuid` is always successful and has no side effects
+    unsafe { libc::geteuid() }
+}
+
+pub fn is_root() -> bool {
+    euid() == 0
+}
+
+/// The seconds since midnight in the local time zone, or in UTC if it can't be determined.
+pub fn local_seconds_of_day() -> u64 {
+    // SAFETY: `time` accepts a null pointer, and `tm` is only read after `localtime_r` filled it
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = mem::zeroed();
+
+        if libc::localtime_r(&now, &mut tm).is_null() {
+            return now as u64 % 86_400;
+        }
+
+        tm.tm_hour as u64 * 3600 + tm.tm_min as u64 * 60 + tm.tm_sec as u64
+    }
+}
+
+/// Checks if `path` can be written to by the current user. If `path` doesn't exist yet, checks
+/// if its nearest existing ancestor can be written to instead, since that's where it would be
+/// created.
+pub fn is_writable(path: &Path) -> bool {
+    let existing = match path.ancestors().find(|path| path.exists()) {
+        Some(existing) => existing,
+        None => return false,
+    };
+    let existing = match CString::new(existing.as_os_str().as_bytes()) {
+        Ok(existing) => existing,
+        Err(_) => return false,
+    };
+
+    // SAFETY: `existing` is a valid nul-terminated string which outlives the call
+    unsafe { libc::access(existing.as_ptr(), libc::W_OK) == 0 }
+}
+
+/// Tries to take an exclusive advisory lock on `file` without blocking. Returns `false` if another
+/// open file already holds the lock. The lock is released when `file` is closed.
+pub fn try_lock_exclusive(file: &File) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: the file descriptor is valid for as long as `file` is borrowed
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+
+    match io::Error::last_os_error() {
+        error if error.raw_os_error() == Some(libc::EWOULDBLOCK) => Ok(false),
+        error => Err(error),
+    }
+}
+
+/// Writes `contents` to `path` by writing to a temporary file in the same directory then renaming
+/// it over `path`, so that readers never see a partially written file.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut temporary_name = OsString::from(".");
+    temporary_name.push(file_name);
+    temporary_name.push(".tmp");
+    let temporary_path = path.with_file_name(temporary_name);
+
+    let result = (|| {
+        let mut file = File::create(&temporary_path)?;
+        file.write_all(contents.as_ref())?;
+        file.sync_all()?;
+        fs::rename(&temporary_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary_path);
+    }
+
+    result
+}
+
+/// Moves the file at `from` to `to` by renaming it, so that it is never in both places at once.
+/// Only across filesystems, where that can't be done, it is copied and then removed instead.
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(error) if error.raw_os_error() == Some(libc::EXDEV) => {
+            debug!(
+                "'{}' and '{}' are on different filesystems, copying instead",
+                from.display(),
+                to.display()
+            );
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+/// Reads the file at `path` with the whitespace around it trimmed, or `None` if it can't be read.
+pub fn read_trimmed(path: &str) -> Option<String> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Some(contents.trim().to_string()),
+        Err(error) => {
+            debug!("failed to read '{}': {}", path, error);
+            None
+        }
+    }
+}
+
+#[cfg(feature = "regulate")]
+fn battery_serial_numbers() -> Vec<String> {
+    let battery_serial_numbers = battery::Manager::new()
+        .and_then(|manager| manager.batteries())
+        .map(|batteries| {
+            batteries
+                .flatten()
+                .filter_map(|battery| {
+                    battery
+                        .serial_number()
+                        .map(|serial| serial.trim().to_string())
+                })
+                .collect::<Vec<_>>()
+        });
+
+    battery_serial_numbers.unwrap_or_else(|error| {
+        debug!("failed to get battery serial numbers: {}", error);
+        Vec::new()
+    })
+}
+
+/// Without the `battery` crate, the serial numbers are read from sysfs instead.
+#[cfg(not(feature = "regulate"))]
+fn battery_serial_numbers() -> Vec<String> {
+    match fs::read_dir("/sys/class/power_supply") {
+        Ok(entries) => entries
+            .flatten()
+            .filter_map(|entry| {
+                entry
+                    .path()
+                    .join("serial_number")
+                    .to_str()
+                    .and_then(read_trimmed)
+            })
+            .collect(),
+        Err(error) => {
+            debug!("failed to get battery serial numbers: {}", error);
+            Vec::new()
+        }
+    }
+}
+
+/// Whether the machine is plugged into AC, going by the `online` attribute of its mains and USB
+/// power supplies, or `None` if it has none, such as in some containers.
+#[cfg(feature = "regulate")]
+pub fn on_ac() -> Option<bool> {
+    on_ac_at(Path::new("/sys/class/power_supply"))
+}
+
+/// [`on_ac`], with the power supplies in `dir` instead of `/sys/class/power_supply`.
+#[cfg(feature = "regulate")]
+fn on_ac_at(dir: &Path) -> Option<bool> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) => {
+            debug!("failed to read the power supplies: {}", error);
+            return None;
+        }
+    };
+    let mut on_ac = None;
+
+    for path in entries.flatten().map(|entry| entry.path()) {
+        let adapter = fs::read_to_string(path.join("type"))
+            .map_or(false, |kind| matches!(kind.trim(), "Mains" | "USB"));
+
+        if !adapter {
+            continue;
+        }
+
+        match fs::read_to_string(path.join("online")) {
+            Ok(online) => on_ac = Some(on_ac.unwrap_or(false) || online.trim() == "1"),
+            Err(error) => debug!(
+                "failed to read whether {} is online: {}",
+                path.display(),
+                error
+            ),
+        }
+    }
+
+    on_ac
+}
+
+/// Sends a desktop notification through `notify-send`, which hands it to whichever daemon
+/// implements `org.freedesktop.Notifications` on the session bus of the current user.
+#[cfg(feature = "regulate")]
+pub fn notify(summary: &str, body: &str) -> anyhow::Result<()> {
+    let output = process::Command::new("notify-send")
+        .args(["--app-name", "tuxvantage", summary, body])
+        .output()
+        .with_context(|| format!("failed to run {}", "notify-send".bold()))?;
+
+    anyhow::ensure!(
+        output.status.success(),
+        "{} failed: {}",
+        "notify-send".bold(),
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    Ok(())
+}
+
+/// Strings which identify this machine or its owner, which must not end up in anything meant to
+/// be shared, such as a contribution or a bug report.
+pub fn sensitive_strings() -> Vec<String> {
+    let mut sensitive = [
+        "/proc/sys/kernel/hostname",
+        "/sys/class/dmi/id/product_serial",
+        "/sys/class/dmi/id/board_serial",
+        "/sys/class/dmi/id/chassis_serial",
+    ]
+    .into_iter()
+    .filter_map(read_trimmed)
+    .collect::<Vec<_>>();
+
+    sensitive.extend(battery_serial_numbers());
+
+    // very short strings would redact unrelated parts of the contribution
+    sensitive.retain(|string| string.len() >= 4);
+    sensitive
+}
+
+/// Replaces every occurrence of the sensitive strings in the strings of `value`, returning how
+/// many strings were redacted.
+pub fn redact(value: &mut serde_json::Value, sensitive: &[String]) -> usize {
+    match value {
+        serde_json::Value::String(string) => {
+            let mut redacted = 0;
+
+            for sensitive in sensitive {
+                if string.contains(sensitive.as_str()) {
+                    *string = string.replace(sensitive.as_str(), "[redacted]");
+                    redacted += 1;
+                }
+            }
+
+            redacted
+        }
+        serde_json::Value::Array(values) => values
+            .iter_mut()
+            .map(|value| redact(value, sensitive))
+            .sum(),
+        serde_json::Value::Object(map) => {
+            map.values_mut().map(|value| redact(value, sensitive)).sum()
+        }
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The shape of a failure to enable battery conservation, where a context repeats the message
+    /// of its cause.
+    fn acpi_failure() -> anyhow::Error {
+        anyhow::anyhow!("acpi_call failed")
+            .context("failed to enable battery conservation: acpi_call failed")
+            .context("failed to enable battery conservation")
+    }
+
+    #[test]
+    fn causes_repeated_as_a_suffix_are_stripped() {
+        let chain = dedup_error_chain(acpi_failure().chain().map(ToString::to_string));
+
+        assert_eq!(
+            chain,
+            ["failed to enable battery conservation", "acpi_call failed"]
+        );
+    }
+
+    #[test]
+    fn identical_messages_are_only_kept_once() {
+        let error = anyhow::anyhow!("acpi_call failed")
+            .context("acpi_call failed")
+            .context("failed to get rapid charge value");
+
+        assert_eq!(
+            dedup_error_chain_for_humans(&error),
+            "failed to get rapid charge value: acpi_call failed"
+        );
+    }
+
+    #[test]
+    fn messages_only_made_of_their_cause_are_kept() {
+        let error = anyhow::anyhow!("the kernel module isn't loaded")
+            .context(": the kernel module isn't loaded");
+
+        assert_eq!(
+            dedup_error_chain(error.chain().map(ToString::to_string)),
+            [
+                ": the kernel module isn't loaded",
+                "the kernel module isn't loaded"
+            ]
+        );
+    }
+
+    #[test]
+    fn unrelated_messages_are_untouched() {
+        let error = anyhow::anyhow!("permission denied (os error 13)")
+            .context("failed to open /proc/acpi/call")
+            .context("failed to get system performance mode");
+
+        assert_eq!(
+            dedup_error_chain_for_humans(&error),
+            "failed to get system performance mode: failed to open /proc/acpi/call: permission \
+             denied (os error 13)"
+        );
+    }
+
+    #[test]
+    fn debug_representation_keeps_the_raw_chain() {
+        assert!(format!("{:?}", acpi_failure())
+            .contains("failed to enable battery conservation: acpi_call failed"));
+    }
+
+    mod container {
+        use super::*;
+        use crate::sandbox::Sandbox;
+
+        /// Creates `path` inside of `sandbox` as an empty file, along with its parents.
+        fn touch(sandbox: &Sandbox, path: &str) {
+            let path = sandbox.path(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        #[test]
+        fn hosts_arent_containers() {
+            let sandbox = Sandbox::new().unwrap();
+            touch(&sandbox, "run/systemd/system/.keep");
+
+            assert!(!is_container_at(sandbox.root()));
+        }
+
+        #[test]
+        fn every_marker_is_a_container() {
+            for marker in CONTAINER_MARKERS {
+                let sandbox = Sandbox::new().unwrap();
+                touch(&sandbox, marker);
+
+                assert!(is_container_at(sandbox.root()), "{} isn't detected", marker);
+            }
+        }
+
+        #[test]
+        fn every_interface_is_missing_from_an_empty_root() {
+            let sandbox = Sandbox::new().unwrap();
+
+            assert_eq!(
+                missing_acpi_interfaces_at(sandbox.root()),
+                vec![
+                    sandbox.path("sys/class/dmi/id"),
+                    sandbox.path("proc/acpi/call")
+                ]
+            );
+        }
+
+        #[test]
+        fn only_the_missing_interfaces_are_listed() {
+            let sandbox = Sandbox::new().unwrap();
+            fs::create_dir_all(sandbox.path("sys/class/dmi/id")).unwrap();
+
+            assert_eq!(
+                missing_acpi_interfaces_at(sandbox.root()),
+                vec![sandbox.path("proc/acpi/call")]
+            );
+
+            touch(&sandbox, "proc/acpi/call");
+            assert!(missing_acpi_interfaces_at(sandbox.root()).is_empty());
+        }
+    }
+
+    #[cfg(feature = "regulate")]
+    mod on_ac {
+        use super::*;
+        use crate::sandbox::Sandbox;
+
+        /// Adds a power supply named `name` to `sandbox`, with its `online` attribute if any.
+        fn supply(sandbox: &Sandbox, name: &str, kind: &str, online: Option<&str>) {
+            let dir = sandbox.path("power_supply").join(name);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("type"), format!("{}\n", kind)).unwrap();
+
+            if let Some(online) = online {
+                fs::write(dir.join("online"), format!("{}\n", online)).unwrap();
+            }
+        }
+
+        fn on_ac(sandbox: &Sandbox) -> Option<bool> {
+            on_ac_at(&sandbox.path("power_supply"))
+        }
+
+        #[test]
+        fn plugged_in_adapter() {
+            let sandbox = Sandbox::new().unwrap();
+            supply(&sandbox, "BAT0", "Battery", None);
+            supply(&sandbox, "ADP0", "Mains", Some("1"));
+
+            assert_eq!(on_ac(&sandbox), Some(true));
+        }
+
+        #[test]
+        fn unplugged_adapter() {
+            let sandbox = Sandbox::new().unwrap();
+            supply(&sandbox, "BAT0", "Battery", None);
+            supply(&sandbox, "ADP0", "Mains", Some("0"));
+
+            assert_eq!(on_ac(&sandbox), Some(false));
+        }
+
+        #[test]
+        fn any_online_adapter_counts() {
+            let sandbox = Sandbox::new().unwrap();
+            supply(&sandbox, "ADP0", "Mains", Some("0"));
+            supply(&sandbox, "ucsi-source-psy-USBC000:001", "USB", Some("1"));
+
+            assert_eq!(on_ac(&sandbox), Some(true));
+        }
+
+        #[test]
+        fn batteries_alone_are_unknown() {
+            let sandbox = Sandbox::new().unwrap();
+            supply(&sandbox, "BAT0", "Battery", Some("1"));
+
+            assert_eq!(on_ac(&sandbox), None);
+        }
+
+        #[test]
+        fn adapters_without_online_are_unknown() {
+            let sandbox = Sandbox::new().unwrap();
+            supply(&sandbox, "ADP0", "Mains", None);
+
+            assert_eq!(on_ac(&sandbox), None);
+        }
+
+        #[test]
+        fn missing_directory_is_unknown() {
+            let sandbox = Sandbox::new().unwrap();
+
+            assert_eq!(on_ac(&sandbox), None);
+        }
+    }
+
+    /// The batteries of a laptop on a dock, with values of very different lengths.
+    fn batteries() -> Table {
+        let mut table = Table::new(&["index", "vendor", "model", "state", "level"]);
+
+        for row in [
+            ["0", "SMP", "L19M4PF0", "charging", "64%"],
+            ["1", "LGC", "L19L4PF0", "full", "100%"],
+            [
+                "2",
+                "Lenovo ThinkPad USB-C Dock Gen 2 Power Delivery",
+                "40AS0090",
+                "discharging",
+                "0%",
+            ],
+        ] {
+            table.push(row.iter().map(ToString::to_string).collect());
+        }
+
+        table
+    }
+
+    #[test]
+    fn tables_are_aligned() {
+        let table = batteries();
+
+        insta::assert_snapshot!("table", table.render(true, None).join("\n"));
+        insta::assert_snapshot!("table_without_header", table.render(false, None).join("\n"));
+    }
+
+    #[test]
+    fn tables_are_truncated_to_fit() {
+        let table = batteries();
+
+        insta::assert_snapshot!("table_truncated", table.render(true, Some(50)).join("\n"));
+
+        // no column is narrowed below the minimum, even if the table still doesn't fit
+        insta::assert_snapshot!(
+            "table_truncated_to_the_minimum",
+            table.render(true, Some(10)).join("\n")
+        );
+    }
+
+    #[test]
+    fn table_columns_are_selected_in_order() {
+        let mut table = batteries();
+        table
+            .select(&["level".to_string(), "vendor".to_string()])
+            .unwrap();
+
+        insta::assert_snapshot!("table_selected", table.render(true, None).join("\n"));
+        assert!(batteries().select(&["capacity".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use crate::config::{BuiltInProfile, Declarations};
+use anyhow::anyhow;
+use ideapad::Profile;
+use itertools::Itertools;
+use owo_colors::OwoColorize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Serialize, Copy, Clone)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+
+    /// The human readable rendering of this finding, with a caret-annotated snippet of the
+    /// offending region if there is one.
+    #[serde(skip)]
+    pub rendered: String,
+}
+
+impl Finding {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        let message = message.into();
+
+        Self {
+            severity,
+            rendered: message.clone(),
+            message,
+            file: None,
+            location: None,
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    /// Points this finding at `location` within `contents`, adding a snippet of the offending
+    /// region to its rendering.
+    pub fn at(mut self, contents: &str, location: Location) -> Self {
+        if let Some(snippet) = render_snippet(contents, location) {
+            self.rendered.push('\n');
+            self.rendered.push_str(&snippet);
+        }
+
+        self.location = Some(location);
+        self
+    }
+
+    /// Marks this finding as coming from the file at `path`.
+    pub fn in_file(mut self, path: &Path) -> Self {
+        self.rendered = format!("{}: {}", path.display().bold(), self.rendered);
+        self.file = Some(path.to_path_buf());
+        self
+    }
+
+    pub fn json(contents: &str, error: &serde_json::Error) -> Self {
+        let this = Self::error(error.to_string());
+
+        if error.line() != 0 {
+            this.at(
+                contents,
+                Location {
+                    line: error.line(),
+                    column: error.column(),
+                },
+            )
+        } else {
+            this
+        }
+    }
+
+    pub fn toml(contents: &str, error: &toml::de::Error) -> Self {
+        let this = Self::error(error.to_string());
+
+        match error.line_col() {
+            // toml's line and columns are zero based
+            Some((line, column)) => this.at(
+                contents,
+                Location {
+                    line: line + 1,
+                    column: column + 1,
+                },
+            ),
+            None => this,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.rendered)
+    }
+}
+
+/// The result of validating the contents of a profile.
+pub struct Validation {
+    pub profile: Option<Profile>,
+    pub findings: Vec<Finding>,
+}
+
+impl Validation {
+    pub fn is_valid(&self) -> bool {
+        self.profile.is_some()
+            && self
+                .findings
+                .iter()
+                .all(|finding| finding.severity != Severity::Error)
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Finding> {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Finding> {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == Severity::Warning)
+    }
+
+    /// Converts this validation into the profile if it is valid, otherwise into an error which
+    /// lists every problem found.
+    pub fn into_result(self) -> anyhow::Result<Profile> {
+        if self.is_valid() {
+            Ok(self.profile.expect("valid profiles must have been parsed"))
+        } else {
+            let errors = self.errors().join("\n");
+            Err(anyhow!(errors).context("contents weren't a valid profile"))
+        }
+    }
+}
+
+/// Renders the line of `contents` at `location` with a caret pointing at the column.
+pub fn render_snippet(contents: &str, location: Location) -> Option<String> {
+    let source_line = contents.lines().nth(location.line.checked_sub(1)?)?;
+    let gutter = location.line.to_string();
+    let caret_offset = source_line
+        .chars()
+        .take(location.column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+
+    Some(format!(
+        "{} {} {}\n{} {} {}{}",
+        gutter.bold(),
+        "|".bold(),
+        source_line,
+        " ".repeat(gutter.len()),
+        "|".bold(),
+        caret_offset,
+        "^".bold().red()
+    ))
+}
+
+/// Parses and semantically checks the contents of a profile. The names of the built-in profiles
+/// in `disabled_built_ins` are free to be used.
+pub fn validate(contents: &str, disabled_built_ins: &[String]) -> Validation {
+    match serde_json::from_str::<Profile>(contents) {
+        Ok(profile) => {
+            let mut findings = check(&profile, disabled_built_ins);
+
+            if let Err(error) = serde_json::from_str::<Declarations>(contents) {
+                findings.push(Finding::json(contents, &error));
+            }
+
+            Validation {
+                profile: Some(profile),
+                findings,
+            }
+        }
+        Err(error) => Validation {
+            profile: None,
+            findings: vec![Finding::json(contents, &error)],
+        },
+    }
+}
+
+/// Semantic checks for a profile that has already been parsed.
+pub fn check(profile: &Profile, disabled_built_ins: &[String]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    if profile.name.trim().is_empty() {
+        findings.push(Finding::error("the profile name must not be empty"));
+    }
+
+    if profile.name.contains('/') {
+        findings.push(Finding::error(format!(
+            "the profile name {} must not contain a {}",
+            profile.name.bold(),
+            '/'.bold()
+        )));
+    }
+
+    if BuiltInProfile::enabled(disabled_built_ins)
+        .any(|built_in| built_in.get().name == profile.name)
+    {
+        findings.push(Finding::error(format!(
+            "the profile name {} is already used by a built-in profile",
+            profile.name.bold()
+        )));
+    }
+
+    if profile.expected_product_names.is_empty() {
+        findings.push(Finding::warning(
+            "the profile has no expected product names, so it will never be detected automatically",
+        ));
+    }
+
+    let parameters = &profile.system_performance.parameters;
+    let modes = [
+        parameters.intelligent_cooling,
+        parameters.extreme_performance,
+        parameters.battery_saving,
+    ];
+
+    if !modes.iter().all_unique() {
+        findings.push(Finding::error(
+            "the system performance mode parameters must all be different from each other",
+        ));
+    }
+
+    let conservation = &profile.battery.conservation.parameters;
+
+    if conservation.enable == conservation.disable {
+        findings.push(Finding::error(
+            "the battery conservation enable and disable parameters must be different",
+        ));
+    }
+
+    let rapid_charge = &profile.battery.rapid_charge.parameters;
+
+    if rapid_charge.enable == rapid_charge.disable {
+        findings.push(Finding::error(
+            "the rapid charge enable and disable parameters must be different",
+        ));
+    }
+
+    findings
+}
@@ -0,0 +1,850 @@
+//! Runs the tuxvantage binary against fake hardware and a temporary configuration, comparing what
+//! it prints with the snapshots in `tests/snapshots`. After changing the output on purpose, review
+//! the new snapshots with `cargo insta review`.
+
+use ideapad::SystemPerformanceMode;
+use std::os::unix::io::FromRawFd;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tuxvantage_core::hardware::{self, Fake, FakeFile};
+use tuxvantage_core::sandbox::Sandbox;
+
+/// The ways a command can print its output.
+const MODES: &[(&str, &[&str])] = &[
+    ("human", &["--machine", "never"]),
+    ("porcelain", &["--porcelain"]),
+    ("machine", &["--machine", "always"]),
+];
+
+fn sandbox() -> Sandbox {
+    Sandbox::with_exe(env!("CARGO_BIN_EXE_tuxvantage")).expect("failed to create the sandbox")
+}
+
+/// A sandbox whose fake hardware fails every read and write.
+fn broken_sandbox() -> Sandbox {
+    let sandbox = sandbox();
+    sandbox
+        .set_hardware(&Fake {
+            broken: true,
+            ..Fake::new()
+        })
+        .expect("failed to break the fake hardware");
+
+    sandbox
+}
+
+/// A sandbox whose fake hardware panics on every read and write.
+fn panicking_sandbox() -> Sandbox {
+    let sandbox = sandbox();
+    sandbox
+        .set_hardware(&Fake {
+            panicking: true,
+            ..Fake::new()
+        })
+        .expect("failed to make the fake hardware panic");
+
+    sandbox
+}
+
+/// A sandbox whose config sets what `config explain` walks through, so that every step has to
+/// weigh it against the command line.
+fn configured_sandbox() -> Sandbox {
+    let sandbox = sandbox();
+    std::fs::write(
+        sandbox.path("config/tuxvantage.toml"),
+        "panic = true\nprofile = \"IDEAPAD_AMD\"\n\n[handlers]\ndefault = \"error\"\nrapid_charging = \"switch\"\n",
+    )
+    .expect("failed to write the config");
+
+    sandbox
+}
+
+/// A sandbox whose config can't be parsed.
+fn invalid_config_sandbox() -> Sandbox {
+    let sandbox = sandbox();
+    std::fs::write(
+        sandbox.path("config/tuxvantage.toml"),
+        "[handlers]\ndefault = \"swich\"\n",
+    )
+    .expect("failed to write the config");
+
+    sandbox
+}
+
+/// A sandbox whose profile directory has a valid profile and a profile which isn't JSON.
+fn profiles_sandbox() -> Sandbox {
+    let sandbox = sandbox();
+    let (_, stdout) = sandbox
+        .run(&["--machine", "always", "profiles", "json", "IDEAPAD_15IIL05"])
+        .expect("failed to run tuxvantage");
+    let json = serde_json::from_str::<serde_json::Value>(&stdout).expect("invalid machine output");
+    let mut profile = serde_json::from_str::<serde_json::Value>(
+        json["contents"]["json"].as_str().expect("no profile JSON"),
+    )
+    .expect("invalid profile JSON");
+    profile["name"] = "CUSTOM".into();
+
+    let dir = sandbox.path("config/profiles");
+    std::fs::create_dir_all(&dir).expect("failed to create the profile directory");
+    std::fs::write(dir.join("broken.json"), "{").expect("failed to write a profile");
+    std::fs::write(dir.join("custom.json"), profile.to_string())
+        .expect("failed to write a profile");
+
+    sandbox
+}
+
+/// Runs tuxvantage with `args` inside of `sandbox`, returning its exit code along with the exit
+/// code, standard output and standard error rendered for a snapshot. Colors are stripped and the
+/// sandbox is replaced with a placeholder, since where it is changes with every run.
+fn run(sandbox: &Sandbox, args: &[&str]) -> (i32, String) {
+    let (exit_code, stdout, stderr) = sandbox
+        .run_with_stderr(args)
+        .expect("failed to run tuxvantage");
+    let rendered = strip_ansi_escapes::strip(format!(
+        "exit code: {}\n--- stdout\n{}--- stderr\n{}",
+        exit_code, stdout, stderr
+    ))
+    .expect("failed to strip the colors");
+    let rendered = String::from_utf8_lossy(&rendered)
+        .replace(&sandbox.root().display().to_string(), "[sandbox]");
+
+    (exit_code, rendered)
+}
+
+/// Runs `args` in every mode, once in a fresh sandbox from `sandbox` and expecting `exit_code`,
+/// snapshotting each run as `name` followed by the mode.
+fn snapshot_modes(name: &str, sandbox: fn() -> Sandbox, args: &[&str], exit_code: i32) {
+    for (mode, mode_args) in MODES {
+        let args = mode_args.iter().chain(args).copied().collect::<Vec<_>>();
+        let (actual, rendered) = run(&sandbox(), &args);
+
+        assert_eq!(actual, exit_code, "`tuxvantage {}`", args.join(" "));
+        insta::assert_snapshot!(format!("{}_{}", name, mode), rendered);
+    }
+}
+
+#[test]
+fn help() {
+    let sandbox = sandbox();
+    let commands: &[&[&str]] = &[
+        &[],
+        &["bc"],
+        &["bc", "enabled"],
+        &["bc", "disabled"],
+        &["bc", "enable"],
+        &["bc", "disable"],
+        &["bc", "regulate"],
+        &["bc", "hold"],
+        &["rc"],
+        &["rc", "enabled"],
+        &["rc", "disabled"],
+        &["rc", "enable"],
+        &["rc", "disable"],
+        &["rc", "top-up"],
+        &["sp"],
+        &["sp", "get"],
+        &["sp", "set"],
+    ];
+
+    for command in commands {
+        let args = command
+            .iter()
+            .chain(&["--help"])
+            .copied()
+            .collect::<Vec<_>>();
+        let (exit_code, rendered) = run(&sandbox, &args);
+        let name = ["help"].iter().chain(*command).copied().collect::<Vec<_>>();
+
+        assert_eq!(exit_code, 0, "`tuxvantage {}`", args.join(" "));
+        insta::assert_snapshot!(name.join("_").replace('-', "_"), rendered);
+    }
+}
+
+#[test]
+fn battery_conservation() {
+    snapshot_modes("bc_enable", sandbox, &["bc", "enable"], 0);
+    snapshot_modes("bc_enabled_broken", broken_sandbox, &["bc", "enabled"], 1);
+}
+
+#[test]
+fn rapid_charge() {
+    snapshot_modes("rc_enable", sandbox, &["rc", "enable"], 0);
+    snapshot_modes("rc_disable_broken", broken_sandbox, &["rc", "disable"], 1);
+}
+
+#[test]
+fn system_performance() {
+    snapshot_modes("sp_set", sandbox, &["sp", "set", "battery-saving"], 0);
+    snapshot_modes("sp_get_broken", broken_sandbox, &["sp", "get"], 1);
+}
+
+/// The steps of `config explain` show the precedence of the command line, the environment and the
+/// config, so a change to them has to show up here. The config sets the profile, since detecting
+/// one depends on the machine running the tests.
+#[test]
+fn config_explain() {
+    snapshot_modes(
+        "config_explain",
+        configured_sandbox,
+        &["config", "explain"],
+        0,
+    );
+    snapshot_modes(
+        "config_explain_overridden",
+        configured_sandbox,
+        &[
+            "--no-panic",
+            "--handler",
+            "ignore",
+            "--profile",
+            "IDEAPAD_15IIL05",
+            "config",
+            "explain",
+        ],
+        0,
+    );
+}
+
+/// Replays the example curves in `tests/curves`, with and without a lower threshold, snapshotting
+/// the decisions the regulator would make for each.
+#[cfg(feature = "regulate")]
+#[test]
+fn simulated_curves() {
+    for curve in ["unplugged.csv", "plugged_in.json"] {
+        let path = format!("{}/tests/curves/{}", env!("CARGO_MANIFEST_DIR"), curve);
+
+        for (variant, variant_args) in [("", &[][..]), ("_disable_at", &["--disable-at", "60"])] {
+            // porcelain output is the same as human output here
+            for (mode, mode_args) in [MODES[0], MODES[2]] {
+                let args = mode_args
+                    .iter()
+                    .chain(&["bc", "regulate", "--simulate", &path])
+                    .chain(variant_args)
+                    .copied()
+                    .collect::<Vec<_>>();
+                let (exit_code, rendered) = run(&sandbox(), &args);
+                let name = curve.replace('.', "_");
+
+                assert_eq!(exit_code, 0, "`tuxvantage {}`", args.join(" "));
+                insta::assert_snapshot!(format!("simulate_{}{}_{}", name, variant, mode), rendered);
+            }
+        }
+    }
+}
+
+/// `--all` goes on past the profiles which fail, which only fail the whole run for humans.
+#[test]
+fn profiles_validate_all() {
+    for ((mode, mode_args), exit_code) in MODES.iter().zip([1, 1, 0]) {
+        let args = mode_args
+            .iter()
+            .chain(&["profiles", "validate", "--all"])
+            .copied()
+            .collect::<Vec<_>>();
+        let (actual, rendered) = run(&profiles_sandbox(), &args);
+
+        assert_eq!(actual, exit_code, "`tuxvantage {}`", args.join(" "));
+        insta::assert_snapshot!(format!("profiles_validate_all_{}", mode), rendered);
+    }
+}
+
+/// A copy of a built-in profile can only keep its name once the built-in is disabled, after which
+/// the copy is the profile found by that name.
+#[test]
+fn materialized_profiles_replace_disabled_built_ins() {
+    snapshot_modes(
+        "profiles_materialize_hidden",
+        sandbox,
+        &["profiles", "materialize", "IDEAPAD_15IIL05"],
+        1,
+    );
+
+    let sandbox = sandbox();
+    std::fs::write(
+        sandbox.path("config/tuxvantage.toml"),
+        "[profiles]\ndisabled_builtins = [\"IDEAPAD_15IIL05\"]\n",
+    )
+    .expect("failed to write the config");
+    let (exit_code, rendered) = run(&sandbox, &["profiles", "materialize", "IDEAPAD_15IIL05"]);
+    assert_eq!(exit_code, 0, "{}", rendered);
+
+    let written = std::fs::read_to_string(sandbox.path("config/profiles/IDEAPAD_15IIL05.json"))
+        .expect("the profile wasn't written into the profile directory");
+    let (exit_code, stdout) = sandbox
+        .run(&["--machine", "always", "profiles", "json", "IDEAPAD_15IIL05"])
+        .expect("failed to run tuxvantage");
+    let json = serde_json::from_str::<serde_json::Value>(&stdout).expect("invalid machine output");
+
+    assert_eq!(exit_code, 0, "{}", stdout);
+    assert_eq!(json["contents"]["json"], written.as_str());
+}
+
+#[test]
+fn config_set_handler() {
+    // the machine output has the handlers the way ideapad serializes them, so it is only checked
+    // for whether anything changed below
+    for (mode, mode_args) in &MODES[..2] {
+        let args = mode_args
+            .iter()
+            .chain(&["config", "set-handler", "--for", "rapid-charge", "ignore"])
+            .copied()
+            .collect::<Vec<_>>();
+        let (exit_code, rendered) = run(&sandbox(), &args);
+
+        assert_eq!(exit_code, 0, "`tuxvantage {}`", args.join(" "));
+        insta::assert_snapshot!(format!("config_set_handler_{}", mode), rendered);
+    }
+
+    snapshot_modes(
+        "config_set_handler_switch_back",
+        sandbox,
+        &["config", "set-handler", "switch-back"],
+        1,
+    );
+
+    // the handler is read back from the config, so setting it again changes nothing
+    let sandbox = sandbox();
+    let args = ["--machine", "always", "config", "set-handler", "error"];
+
+    for changed in [true, false] {
+        let (exit_code, stdout) = sandbox.run(&args).expect("failed to run tuxvantage");
+        let json =
+            serde_json::from_str::<serde_json::Value>(&stdout).expect("invalid machine output");
+
+        assert_eq!(exit_code, 0, "{}", stdout);
+        assert_eq!(json["contents"]["changed"], changed, "{}", stdout);
+    }
+}
+
+/// Commands which need nothing from the config go on with the defaults and a warning, while the
+/// ones which need it fail.
+#[test]
+fn invalid_configs_only_fail_commands_which_need_them() {
+    snapshot_modes(
+        "history_invalid_config",
+        invalid_config_sandbox,
+        &["history"],
+        0,
+    );
+    snapshot_modes(
+        "sp_get_invalid_config",
+        invalid_config_sandbox,
+        &["sp", "get"],
+        1,
+    );
+
+    let cases: &[(&[&str], bool)] = &[
+        (&["profiles", "json", "IDEAPAD_15IIL05"], false),
+        (&["paths"], false),
+        (&["consistency", "show"], false),
+        (&["bc", "enabled"], true),
+        (&["rc", "enable"], true),
+        (&["config", "explain"], true),
+    ];
+
+    for (args, needs_config) in cases {
+        let args = ["--machine", "always"]
+            .iter()
+            .chain(*args)
+            .copied()
+            .collect::<Vec<_>>();
+        let (exit_code, stdout) = invalid_config_sandbox()
+            .run(&args)
+            .expect("failed to run tuxvantage");
+        let json = serde_json::from_str::<serde_json::Value>(&stdout)
+            .unwrap_or_else(|error| panic!("`tuxvantage {}`: {}", args.join(" "), error));
+
+        if *needs_config {
+            assert_eq!(exit_code, 1, "`tuxvantage {}`", args.join(" "));
+            assert_eq!(json["contents"]["code"], "invalid_config", "{}", stdout);
+        } else {
+            assert_eq!(exit_code, 0, "`tuxvantage {}`", args.join(" "));
+            assert!(
+                json["warnings"][0]["message"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .starts_with("tuxvantage.toml is invalid"),
+                "{}",
+                stdout
+            );
+        }
+    }
+}
+
+#[test]
+fn changes_reach_the_hardware() {
+    let sandbox = sandbox();
+
+    for args in [&["bc", "enable"][..], &["sp", "set", "extreme-performance"]] {
+        assert_eq!(run(&sandbox, args).0, 0, "`tuxvantage {}`", args.join(" "));
+    }
+
+    let fake = sandbox
+        .hardware()
+        .load()
+        .expect("failed to load the fake hardware");
+    assert!(fake.battery_conservation);
+    assert!(!fake.rapid_charge);
+    assert_eq!(
+        sandbox.run(&["--porcelain", "sp", "get"]).unwrap(),
+        (0, "extreme-performance\n".to_string())
+    );
+}
+
+/// The `profile` of the machine output of `bc enabled`, run in `sandbox` with `args` before it.
+fn active_profile(sandbox: Sandbox, args: &[&str]) -> serde_json::Value {
+    let args = ["--machine", "always"]
+        .iter()
+        .chain(args)
+        .chain(&["bc", "enabled"])
+        .copied()
+        .collect::<Vec<_>>();
+    let (exit_code, stdout) = sandbox.run(&args).expect("failed to run tuxvantage");
+    let json = serde_json::from_str::<serde_json::Value>(&stdout).expect("invalid machine output");
+
+    assert_eq!(exit_code, 0, "`tuxvantage {}`: {}", args.join(" "), stdout);
+    json["profile"].clone()
+}
+
+/// The machine output of commands which access the hardware says which profile was used and why.
+#[test]
+fn machine_output_names_the_active_profile() {
+    let profile = |name: &str, origin: &str, selection: &str| serde_json::json!({ "name": name, "origin": origin, "selection": selection });
+
+    assert_eq!(
+        active_profile(sandbox(), &[]),
+        profile("IDEAPAD_15IIL05", "built_in", "auto_detected")
+    );
+    assert_eq!(
+        active_profile(configured_sandbox(), &[]),
+        profile("IDEAPAD_AMD", "built_in", "default")
+    );
+    assert_eq!(
+        active_profile(configured_sandbox(), &["--profile", "IDEAPAD_15IIL05"]),
+        profile("IDEAPAD_15IIL05", "built_in", "override")
+    );
+    assert_eq!(
+        active_profile(profiles_sandbox(), &["--profile", "CUSTOM"]),
+        profile("CUSTOM", "external", "override")
+    );
+
+    let (_, stdout) = sandbox()
+        .run(&["--machine", "always", "paths"])
+        .expect("failed to run tuxvantage");
+    let json = serde_json::from_str::<serde_json::Value>(&stdout).expect("invalid machine output");
+    assert!(json.get("profile").is_none(), "{}", stdout);
+}
+
+/// A failed run with `--bug-report` leaves a bundle behind, and lists it in the warnings of the
+/// machine output.
+#[test]
+fn failed_runs_write_a_bug_report() {
+    let sandbox = broken_sandbox();
+    let bundle = sandbox.path("bug-report");
+    let bundle_arg = bundle.display().to_string();
+    let (exit_code, stdout) = sandbox
+        .run(&[
+            "--machine",
+            "always",
+            "--bug-report",
+            &bundle_arg,
+            "bc",
+            "enabled",
+        ])
+        .expect("failed to run tuxvantage");
+    let json = serde_json::from_str::<serde_json::Value>(&stdout).expect("invalid machine output");
+
+    assert_eq!(exit_code, 1, "{}", stdout);
+    assert!(
+        json["warnings"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .any(|warning| warning["message"]
+                .as_str()
+                .unwrap_or_default()
+                .contains(&bundle_arg)),
+        "{}",
+        stdout
+    );
+
+    // uname.txt and modules.txt depend on the machine running the tests, so they may be missing
+    for file in ["failure.json", "config.json", "profile.json", "log.txt"] {
+        assert!(bundle.join(file).exists(), "{} is missing", file);
+    }
+
+    let failure = std::fs::read_to_string(bundle.join("failure.json")).unwrap();
+    let failure = serde_json::from_str::<serde_json::Value>(&failure).expect("invalid failure");
+    assert_eq!(failure["status"], "Failure");
+    assert_eq!(failure["contents"], json["contents"]);
+
+    // nothing is written for a run which succeeds
+    let sandbox = self::sandbox();
+    let bundle = sandbox.path("bug-report");
+    let bundle_arg = bundle.display().to_string();
+    assert_eq!(
+        sandbox
+            .run(&["--bug-report", &bundle_arg, "bc", "enabled"])
+            .expect("failed to run tuxvantage")
+            .0,
+        0
+    );
+    assert!(!bundle.exists());
+}
+
+/// A regulator service which was removed by hand is only forgotten, which needs neither root nor
+/// the init system.
+#[cfg(feature = "service-install")]
+#[test]
+fn uninstalling_a_removed_service_forgets_it() {
+    let sandbox = sandbox();
+    let unit = sandbox.path("units/bcm.service");
+    let consistency = sandbox.path("state/.consistency.json");
+    // any command which reads the config writes the default consistency config first
+    sandbox
+        .run(&["consistency", "show"])
+        .expect("failed to run tuxvantage");
+    let contents = std::fs::read_to_string(&consistency).expect("no consistency config");
+    let mut contents = serde_json::from_str::<serde_json::Value>(&contents).unwrap();
+    contents["regulator_service_installed"] = true.into();
+    contents["installed_services"] = serde_json::json!(["bcm.service"]);
+    contents["regulator_unit"] = unit.display().to_string().into();
+    contents["regulator_init_system"] = "systemd".into();
+    std::fs::write(&consistency, contents.to_string())
+        .expect("failed to write the consistency config");
+    // once it is forgotten, the service is looked for at the default path, which depends on the
+    // machine running the tests, so it is only uninstalled once
+    let (exit_code, stdout) = sandbox
+        .run(&["--machine", "always", "bc", "regulate", "--uninstall"])
+        .expect("failed to run tuxvantage");
+    let json = serde_json::from_str::<serde_json::Value>(&stdout).expect("invalid machine output");
+
+    assert_eq!(exit_code, 0, "{}", stdout);
+    assert_eq!(json["contents"]["uninstalled"], true, "{}", stdout);
+
+    let consistency = std::fs::read_to_string(consistency).unwrap();
+    let consistency = serde_json::from_str::<serde_json::Value>(&consistency).unwrap();
+    assert_eq!(consistency["installed_services"], serde_json::json!([]));
+    assert_eq!(consistency["regulator_service_installed"], false);
+    assert!(consistency.get("regulator_unit").is_none());
+}
+
+/// `with` only changes the settings while the command runs, and exits with its exit code.
+#[test]
+fn with_restores_the_settings_afterwards() {
+    let sandbox = sandbox();
+    let during = sandbox.path("during.json");
+    let script = format!(
+        "cp \"${}\" '{}' && exit 3",
+        hardware::FAKE_ENV,
+        during.display()
+    );
+    let (exit_code, rendered) = run(
+        &sandbox,
+        &[
+            "with",
+            "--conservation",
+            "on",
+            "--performance",
+            "extreme-performance",
+            "--",
+            "sh",
+            "-c",
+            &script,
+        ],
+    );
+
+    assert_eq!(exit_code, 3, "{}", rendered);
+    assert_eq!(
+        FakeFile::new(during).load().unwrap(),
+        Fake {
+            battery_conservation: true,
+            system_performance: SystemPerformanceMode::ExtremePerformance,
+            ..Fake::new()
+        }
+    );
+    assert_eq!(sandbox.hardware().load().unwrap(), Fake::new());
+}
+
+/// Writing a setting again right after it was written is warned about, or waited out when asked
+/// to.
+#[test]
+fn writes_within_the_ec_cooldown() {
+    let sandbox = sandbox();
+    std::fs::write(
+        sandbox.path("config/tuxvantage.toml"),
+        "ec_cooldown = \"1s\"\n",
+    )
+    .expect("failed to write the config");
+    let warnings = |stdout: &str| {
+        let json =
+            serde_json::from_str::<serde_json::Value>(stdout).expect("invalid machine output");
+        json["warnings"].as_array().cloned().unwrap_or_default()
+    };
+
+    let (_, stdout) = sandbox
+        .run(&["--machine", "always", "rc", "enable"])
+        .expect("failed to run tuxvantage");
+    assert!(warnings(&stdout).is_empty(), "{}", stdout);
+
+    let (_, stdout) = sandbox
+        .run(&["--machine", "always", "rc", "disable"])
+        .expect("failed to run tuxvantage");
+    assert!(
+        warnings(&stdout)[0]["message"]
+            .as_str()
+            .unwrap_or_default()
+            .starts_with("rapid_charge was changed less than 1 second(s) ago"),
+        "{}",
+        stdout
+    );
+
+    // most of the second is left, which is waited out instead
+    let start = Instant::now();
+    let (_, stdout) = sandbox
+        .run(&[
+            "--respect-ec-cooldown",
+            "--machine",
+            "always",
+            "rc",
+            "enable",
+        ])
+        .expect("failed to run tuxvantage");
+    assert!(warnings(&stdout).is_empty(), "{}", stdout);
+    assert!(start.elapsed() > Duration::from_millis(500));
+}
+
+#[test]
+fn usage_errors_exit_with_2() {
+    let sandbox = sandbox();
+
+    for args in [&["bc", "bogus"][..], &["sp", "set", "bogus"], &["--bogus"]] {
+        assert_eq!(run(&sandbox, args).0, 2, "`tuxvantage {}`", args.join(" "));
+    }
+}
+
+/// Runs `args` with machine output of every supported version, once in a fresh sandbox from
+/// `sandbox` and expecting `exit_code`, snapshotting the JSON of each as `name` followed by the
+/// version. Scripts pin a version, so these must only change along with a new version.
+fn snapshot_versions(name: &str, sandbox: fn() -> Sandbox, args: &[&str], exit_code: i32) {
+    for version in ["1", "2"] {
+        let args = ["--machine", "always", "--machine-version", version]
+            .iter()
+            .chain(args)
+            .copied()
+            .collect::<Vec<_>>();
+        let sandbox = sandbox();
+        let (actual, stdout) = sandbox.run(&args).expect("failed to run tuxvantage");
+        let json = serde_json::from_str::<serde_json::Value>(&stdout)
+            .unwrap_or_else(|error| panic!("`tuxvantage {}`: {}", args.join(" "), error));
+
+        assert_eq!(actual, exit_code, "`tuxvantage {}`", args.join(" "));
+        insta::assert_snapshot!(
+            format!("{}_v{}", name, version),
+            serde_json::to_string_pretty(&json).unwrap()
+        );
+    }
+}
+
+#[test]
+fn machine_versions() {
+    snapshot_versions("v_bc_enabled", sandbox, &["bc", "enabled"], 0);
+    snapshot_versions("v_bc_enable_broken", broken_sandbox, &["bc", "enable"], 1);
+    snapshot_versions("v_rc_disable", sandbox, &["rc", "disable"], 0);
+    snapshot_versions("v_rc_enabled_broken", broken_sandbox, &["rc", "enabled"], 1);
+    snapshot_versions("v_sp_get", sandbox, &["sp", "get"], 0);
+    snapshot_versions(
+        "v_sp_set_broken",
+        broken_sandbox,
+        &["sp", "set", "battery-saving"],
+        1,
+    );
+    // the native thresholds `status` reads come from the machine running the tests
+    snapshot_versions("v_status_broken", broken_sandbox, &["status"], 1);
+}
+
+/// Scripts read the warnings of machine output as objects of a message and an optional tip, so
+/// their shape must only change along with a new version.
+#[test]
+fn machine_warnings() {
+    for version in ["1", "2"] {
+        let args = [
+            "--machine",
+            "always",
+            "--machine-version",
+            version,
+            "rc",
+            "enable",
+            "ignore",
+        ];
+        let (exit_code, stdout) = sandbox().run(&args).expect("failed to run tuxvantage");
+        let json = serde_json::from_str::<serde_json::Value>(&stdout)
+            .unwrap_or_else(|error| panic!("`tuxvantage {}`: {}", args.join(" "), error));
+
+        assert_eq!(exit_code, 0, "`tuxvantage {}`", args.join(" "));
+        insta::assert_snapshot!(
+            format!("warnings_v{}", version),
+            serde_json::to_string_pretty(&json["warnings"]).unwrap()
+        );
+    }
+}
+
+#[test]
+fn unsupported_machine_versions_are_usage_errors() {
+    let sandbox = sandbox();
+    let (exit_code, rendered) = run(
+        &sandbox,
+        &[
+            "--machine",
+            "always",
+            "--machine-version",
+            "3",
+            "bc",
+            "enabled",
+        ],
+    );
+
+    assert_eq!(exit_code, 2);
+    assert!(
+        rendered.contains("the supported versions are 1 to 2"),
+        "{}",
+        rendered
+    );
+}
+
+#[test]
+fn verbose_machine_output_keeps_the_streams_apart() {
+    for args in [&["bc", "enable"][..], &["sp", "get"]] {
+        let sandbox = sandbox();
+        let args = ["--machine", "always", "--verbose"]
+            .iter()
+            .chain(args)
+            .copied()
+            .collect::<Vec<_>>();
+        let (exit_code, stdout, stderr) = sandbox
+            .run_with_stderr(&args)
+            .expect("failed to run tuxvantage");
+
+        assert_eq!(exit_code, 0, "`tuxvantage {}`", args.join(" "));
+
+        // a single document, with nothing logged before or after it
+        let mut documents = serde_json::Deserializer::from_str(&stdout)
+            .into_iter::<serde_json::Value>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_else(|error| panic!("{}\n{}", error, stdout));
+        assert_eq!(documents.len(), 1, "{}", stdout);
+        assert_eq!(documents.remove(0)["status"], "Success");
+        assert!(!stdout.contains("[debug]"), "{}", stdout);
+
+        // standard error isn't a terminal, so the debug lines are prefixed and have no colors
+        assert!(
+            stderr.lines().any(|line| line.starts_with("[debug] ")),
+            "{}",
+            stderr
+        );
+        assert!(!stderr.contains('\x1b'), "{}", stderr);
+    }
+}
+
+/// The write end of a pipe whose read end is already closed, so that writing to it fails like
+/// writing into `head` after it has exited.
+fn closed_pipe() -> Stdio {
+    let mut fds = [0; 2];
+    assert_eq!(
+        unsafe { libc::pipe(fds.as_mut_ptr()) },
+        0,
+        "failed to create a pipe"
+    );
+
+    unsafe {
+        libc::close(fds[0]);
+        Stdio::from_raw_fd(fds[1])
+    }
+}
+
+#[test]
+fn closed_output_streams_exit_quietly() {
+    for args in [
+        &["--porcelain", "sp", "get"][..],
+        &["--machine", "always", "bc", "enabled"],
+        &["profiles", "get"],
+    ] {
+        let sandbox = sandbox();
+        let output = sandbox
+            .command(args)
+            .stdout(closed_pipe())
+            .stderr(Stdio::piped())
+            .output()
+            .expect("failed to run tuxvantage");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        assert_eq!(
+            output.status.code(),
+            Some(0),
+            "`tuxvantage {}`: {}",
+            args.join(" "),
+            stderr
+        );
+        assert!(!stderr.contains("panicked"), "{}", stderr);
+
+        let args = ["--verbose"]
+            .iter()
+            .chain(args)
+            .copied()
+            .collect::<Vec<_>>();
+        let status = sandbox
+            .command(&args)
+            .stdout(Stdio::null())
+            .stderr(closed_pipe())
+            .status()
+            .expect("failed to run tuxvantage");
+
+        assert_eq!(status.code(), Some(0), "`tuxvantage {}`", args.join(" "));
+    }
+}
+
+#[test]
+fn panics_are_machine_failures_in_machine_mode() {
+    for version in ["1", "2"] {
+        let args = [
+            "--machine",
+            "always",
+            "--machine-version",
+            version,
+            "bc",
+            "enabled",
+        ];
+        let (exit_code, stdout) = panicking_sandbox()
+            .run(&args)
+            .expect("failed to run tuxvantage");
+        let json = serde_json::from_str::<serde_json::Value>(&stdout)
+            .unwrap_or_else(|error| panic!("`tuxvantage {}`: {}", args.join(" "), error));
+        let contents = &json["contents"];
+
+        assert_eq!(exit_code, 101, "`tuxvantage {}`", args.join(" "));
+        assert_eq!(json["status"], "Failure", "{}", stdout);
+        assert_eq!(contents["code"], "panic", "{}", stdout);
+        assert!(
+            contents["chain"][0]
+                .as_str()
+                .unwrap_or_default()
+                .ends_with("the fake hardware panicked"),
+            "{}",
+            stdout
+        );
+        assert_eq!(
+            contents["tip"], "this is a bug, please report it",
+            "{}",
+            stdout
+        );
+    }
+
+    // without machine output, the panic is left to the usual hook
+    let (exit_code, stdout, stderr) = panicking_sandbox()
+        .run_with_stderr(&["--machine", "never", "bc", "enabled"])
+        .expect("failed to run tuxvantage");
+
+    assert_eq!(exit_code, 101);
+    assert_eq!(stdout, "");
+    assert!(stderr.contains("the fake hardware panicked"), "{}", stderr);
+}